@@ -1,8 +1,14 @@
 //! Hashers (feature-gated) and utilities for implementing them.
 
-use crate::trie::{InternalData, LeafData, Node, NodeKind, TERMINATOR};
+use crate::trie::{
+    InternalData, LeafData, Node, NodeKind, ValueHash, DEFAULT_VALUE_HASH_LEN, TERMINATOR,
+};
 
-/// A trie node hash function specialized for 64 bytes of data.
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+/// A trie node hash function specialized for 64 bytes of data. Leaves with value hashes of a width
+/// other than 32 bytes have preimages of a different size.
 ///
 /// Note that it is illegal for the produced hash to equal [0; 32], as this value is reserved
 /// for the terminator node.
@@ -14,7 +20,9 @@ use crate::trie::{InternalData, LeafData, Node, NodeKind, TERMINATOR};
 pub trait NodeHasher {
     /// Hash a leaf. This should domain-separate the hash
     /// according to the node kind.
-    fn hash_leaf(data: &LeafData) -> [u8; 32];
+    ///
+    /// Leaves may carry value hashes of any width `N`.
+    fn hash_leaf<const N: usize>(data: &LeafData<N>) -> [u8; 32];
 
     /// Hash an internal node. This should domain-separate
     /// the hash according to the node kind.
@@ -25,9 +33,11 @@ pub trait NodeHasher {
 }
 
 /// A hasher for arbitrary-length values.
-pub trait ValueHasher {
+///
+/// Value hashes are `N` bytes wide, 32 by default. See [`ValueHash`].
+pub trait ValueHasher<const N: usize = DEFAULT_VALUE_HASH_LEN> {
     /// Hash an arbitrary-length value.
-    fn hash_value(value: &[u8]) -> [u8; 32];
+    fn hash_value(value: &[u8]) -> ValueHash<N>;
 }
 
 /// Get the node kind, according to a most-significant bit labeling scheme.
@@ -70,7 +80,9 @@ pub trait BinaryHash {
 /// A node and value hasher constructed from a simple binary hasher.
 ///
 /// This implements a [`ValueHasher`] and [`NodeHasher`] where the node kind is tagged by setting
-/// or unsetting the MSB of the hash value.
+/// or unsetting the MSB of the hash value. Leaves are hashed over the concatenation of the key path
+/// and the value hash, whatever the width of the value hash. Value hashes produced by this hasher
+/// are 32 bytes.
 ///
 /// The binary hash wrapped by this structure must behave approximately like a random oracle over
 /// the space 2^256, i.e. all 256 bit outputs are valid and inputs are uniformly distributed.
@@ -85,8 +97,16 @@ impl<H: BinaryHash> ValueHasher for BinaryHasher<H> {
 }
 
 impl<H: BinaryHash> NodeHasher for BinaryHasher<H> {
-    fn hash_leaf(data: &LeafData) -> [u8; 32] {
-        let mut h = H::hash2_32_concat(&data.key_path, &data.value_hash);
+    fn hash_leaf<const N: usize>(data: &LeafData<N>) -> [u8; 32] {
+        let mut h = match <&[u8; 32]>::try_from(&data.value_hash[..]) {
+            Ok(value_hash) => H::hash2_32_concat(&data.key_path, value_hash),
+            Err(_) => {
+                let mut preimage = Vec::with_capacity(32 + N);
+                preimage.extend_from_slice(&data.key_path);
+                preimage.extend_from_slice(&data.value_hash);
+                H::hash(&preimage)
+            }
+        };
         set_msb(&mut h);
         h
     }
@@ -103,12 +123,156 @@ impl<H: BinaryHash> NodeHasher for BinaryHasher<H> {
 }
 
 /// Blanket implementation for all implementations of `Digest`
+#[cfg(feature = "digest")]
 impl<H: digest::Digest<OutputSize = digest::typenum::U32> + Send + Sync> BinaryHash for H {
     fn hash(input: &[u8]) -> [u8; 32] {
         H::digest(input).into()
     }
 }
 
+/// An element of a prime field, as used by algebraic hash functions.
+pub type FieldElement = ruint::aliases::U256;
+
+/// A simple trait for representing algebraic hash functions over a prime field, the counterpart of
+/// [`BinaryHash`] for hashes which are cheap to prove in a SNARK.
+pub trait FieldHash {
+    /// The modulus of the field. This must be below 2^254, so that the two most-significant bits
+    /// of every element are free.
+    const MODULUS: FieldElement;
+
+    /// Apply the permutation to a state of three elements.
+    fn permute(state: &mut [FieldElement; 3]);
+}
+
+/// A node and value hasher constructed from an algebraic hash function.
+///
+/// Nodes and value hashes are the 32-byte big-endian encodings of field elements, so that a
+/// circuit verifying a witness works with a single element per node. The node kind is tagged by
+/// setting the MSB for leaves, which no element has. Hashing works as follows:
+///   - Internal nodes compress their children: the first element of `permute([left, right, 0])`.
+///   - Leaves absorb the two 128-bit halves of the key path, then the value hash, into a sponge
+///     with a capacity of 1.
+///   - Values are split into 31-byte chunks absorbed two at a time into a sponge whose capacity
+///     is 2 plus the length of the value.
+///
+/// Value hashes of a width other than 32 bytes are hashed like values before being absorbed into
+/// leaves.
+///
+/// Encodings which are not those of a field element are never reduced, as that would let several
+/// encodings hash alike. Instead, a node with such a value hash or child hashes to an encoding
+/// which is itself not that of an element, and so can't match any root built from canonical
+/// encodings.
+pub struct FieldHasher<H>(core::marker::PhantomData<H>);
+
+// The hash of an internal node with a child which is not the canonical encoding of an element.
+// This is above every modulus, so it propagates to the root. Tagged, it is the hash of such a leaf.
+pub(crate) const NON_CANONICAL: [u8; 32] = {
+    let mut bytes = [0xff; 32];
+    bytes[0] = 0x7f;
+    bytes
+};
+
+impl<H: FieldHash> FieldHasher<H> {
+    /// Get the field element a value hash or untagged node encodes, or `None` if the bytes are not
+    /// the canonical encoding of an element.
+    pub fn to_element(bytes: &[u8; 32]) -> Option<FieldElement> {
+        let element = FieldElement::from_be_bytes(*bytes);
+        (element < H::MODULUS).then_some(element)
+    }
+
+    // Get the field element a node encodes, ignoring the node kind tag.
+    pub(crate) fn node_to_element(node: &Node) -> Option<FieldElement> {
+        let mut bytes = *node;
+        unset_msb(&mut bytes);
+        Self::to_element(&bytes)
+    }
+
+    /// Encode a field element as a node or value hash, without a node kind tag.
+    pub fn from_element(element: FieldElement) -> [u8; 32] {
+        element.to_be_bytes()
+    }
+
+    fn hash_bytes(bytes: &[u8]) -> FieldElement {
+        let mut state = [
+            FieldElement::ZERO,
+            FieldElement::ZERO,
+            FieldElement::from(bytes.len() + 2),
+        ];
+        let mut chunks = bytes.chunks(31).map(|chunk| {
+            let mut buf = [0u8; 32];
+            buf[32 - chunk.len()..].copy_from_slice(chunk);
+            FieldElement::from_be_bytes(buf)
+        });
+        loop {
+            let (first, second) = (chunks.next(), chunks.next());
+            for (lane, element) in state.iter_mut().zip([first, second]) {
+                *lane = lane.add_mod(element.unwrap_or_default(), H::MODULUS);
+            }
+            H::permute(&mut state);
+            if second.is_none() {
+                break;
+            }
+        }
+        state[0]
+    }
+}
+
+impl<H: FieldHash> ValueHasher for FieldHasher<H> {
+    fn hash_value(value: &[u8]) -> [u8; 32] {
+        Self::from_element(Self::hash_bytes(value))
+    }
+}
+
+impl<H: FieldHash> NodeHasher for FieldHasher<H> {
+    fn hash_leaf<const N: usize>(data: &LeafData<N>) -> [u8; 32] {
+        let half = |bytes: &[u8]| {
+            let mut buf = [0u8; 32];
+            buf[16..].copy_from_slice(bytes);
+            FieldElement::from_be_bytes(buf)
+        };
+        let value = match <&[u8; 32]>::try_from(&data.value_hash[..]) {
+            Ok(value_hash) => match Self::to_element(value_hash) {
+                Some(element) => element,
+                None => {
+                    let mut h = NON_CANONICAL;
+                    set_msb(&mut h);
+                    return h;
+                }
+            },
+            Err(_) => Self::hash_bytes(&data.value_hash),
+        };
+
+        let mut state = [
+            half(&data.key_path[..16]),
+            half(&data.key_path[16..]),
+            FieldElement::from(1),
+        ];
+        H::permute(&mut state);
+        state[0] = state[0].add_mod(value, H::MODULUS);
+        H::permute(&mut state);
+
+        let mut h = Self::from_element(state[0]);
+        set_msb(&mut h);
+        h
+    }
+
+    fn hash_internal(data: &InternalData) -> [u8; 32] {
+        let (Some(left), Some(right)) = (
+            Self::node_to_element(&data.left),
+            Self::node_to_element(&data.right),
+        ) else {
+            return NON_CANONICAL;
+        };
+        let mut state = [left, right, FieldElement::ZERO];
+        H::permute(&mut state);
+        Self::from_element(state[0])
+    }
+
+    fn node_kind(node: &Node) -> NodeKind {
+        node_kind_by_msb(node)
+    }
+}
+
 #[cfg(any(feature = "blake3-hasher", test))]
 pub use blake3::Blake3Hasher;
 
@@ -138,7 +302,7 @@ pub mod blake3 {
 }
 
 #[cfg(feature = "sha2-hasher")]
-pub use sha2::Sha2Hasher;
+pub use sha2::{Sha256Hasher, Sha2Hasher};
 
 /// A node and value hasher making use of sha2-256.
 #[cfg(feature = "sha2-hasher")]
@@ -152,6 +316,9 @@ pub mod sha2 {
     /// A wrapper around sha2-256 for use in NOMT.
     pub type Sha2Hasher = BinaryHasher<Sha2BinaryHasher>;
 
+    /// Another name for [`Sha2Hasher`], naming the digest size.
+    pub type Sha256Hasher = Sha2Hasher;
+
     impl BinaryHash for Sha2BinaryHasher {
         fn hash(value: &[u8]) -> [u8; 32] {
             let mut hasher = Sha256::new();
@@ -167,3 +334,294 @@ pub mod sha2 {
         }
     }
 }
+
+#[cfg(feature = "keccak-hasher")]
+pub use keccak::Keccak256Hasher;
+
+#[cfg(feature = "poseidon2-hasher")]
+pub use poseidon2::Poseidon2Hasher;
+
+/// A node and value hasher making use of keccak-256, as used by Ethereum.
+///
+/// This is the original Keccak padding, not the one standardized as SHA3-256.
+#[cfg(feature = "keccak-hasher")]
+pub mod keccak {
+    use super::BinaryHasher;
+
+    /// A [`BinaryHash`](super::BinaryHash) implementation for keccak-256.
+    pub type Keccak256BinaryHasher = sha3::Keccak256;
+
+    /// A wrapper around keccak-256 for use in NOMT.
+    pub type Keccak256Hasher = BinaryHasher<Keccak256BinaryHasher>;
+
+    #[cfg(test)]
+    mod tests {
+        use super::Keccak256BinaryHasher;
+        use crate::hasher::BinaryHash;
+
+        fn hex(s: &str) -> [u8; 32] {
+            let mut out = [0u8; 32];
+            for (i, byte) in out.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+            }
+            out
+        }
+
+        #[test]
+        fn original_keccak_padding() {
+            // keccak-256, not SHA3-256, of the empty string.
+            assert_eq!(
+                <Keccak256BinaryHasher as BinaryHash>::hash(b""),
+                hex("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470")
+            );
+        }
+    }
+}
+
+/// A node and value hasher making use of Poseidon2 over the scalar field of BN254, for witnesses
+/// which are verified in a SNARK.
+///
+/// This is the instance with a state of 3 elements, the `x^5` S-box, 8 full and 56 partial
+/// rounds, and the round constants of the reference implementation by HorizenLabs.
+#[cfg(feature = "poseidon2-hasher")]
+pub mod poseidon2 {
+    use super::{FieldElement, FieldHash, FieldHasher};
+    use ruint::uint;
+
+    /// A [`FieldHash`] implementation for Poseidon2 over BN254.
+    pub struct Poseidon2Bn254;
+
+    /// A wrapper around Poseidon2 for use in NOMT.
+    pub type Poseidon2Hasher = FieldHasher<Poseidon2Bn254>;
+
+    impl FieldHash for Poseidon2Bn254 {
+        const MODULUS: FieldElement =
+            uint!(0x30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000001_U256);
+
+        fn permute(state: &mut [FieldElement; 3]) {
+            permute(state)
+        }
+    }
+
+    const P: FieldElement = Poseidon2Bn254::MODULUS;
+
+    const FULL_ROUND_CONSTANTS: [[FieldElement; 3]; 8] = [
+        [
+            uint!(0x1d066a255517b7fd8bddd3a93f7804ef7f8fcde48bb4c37a59a09a1a97052816_U256),
+            uint!(0x29daefb55f6f2dc6ac3f089cebcc6120b7c6fef31367b68eb7238547d32c1610_U256),
+            uint!(0x1f2cb1624a78ee001ecbd88ad959d7012572d76f08ec5c4f9e8b7ad7b0b4e1d1_U256),
+        ],
+        [
+            uint!(0x0aad2e79f15735f2bd77c0ed3d14aa27b11f092a53bbc6e1db0672ded84f31e5_U256),
+            uint!(0x2252624f8617738cd6f661dd4094375f37028a98f1dece66091ccf1595b43f28_U256),
+            uint!(0x1a24913a928b38485a65a84a291da1ff91c20626524b2b87d49f4f2c9018d735_U256),
+        ],
+        [
+            uint!(0x22fc468f1759b74d7bfc427b5f11ebb10a41515ddff497b14fd6dae1508fc47a_U256),
+            uint!(0x1059ca787f1f89ed9cd026e9c9ca107ae61956ff0b4121d5efd65515617f6e4d_U256),
+            uint!(0x02be9473358461d8f61f3536d877de982123011f0bf6f155a45cbbfae8b981ce_U256),
+        ],
+        [
+            uint!(0x0ec96c8e32962d462778a749c82ed623aba9b669ac5b8736a1ff3a441a5084a4_U256),
+            uint!(0x292f906e073677405442d9553c45fa3f5a47a7cdb8c99f9648fb2e4d814df57e_U256),
+            uint!(0x274982444157b86726c11b9a0f5e39a5cc611160a394ea460c63f0b2ffe5657e_U256),
+        ],
+        [
+            uint!(0x1acd63c67fbc9ab1626ed93491bda32e5da18ea9d8e4f10178d04aa6f8747ad0_U256),
+            uint!(0x19f8a5d670e8ab66c4e3144be58ef6901bf93375e2323ec3ca8c86cd2a28b5a5_U256),
+            uint!(0x1c0dc443519ad7a86efa40d2df10a011068193ea51f6c92ae1cfbb5f7b9b6893_U256),
+        ],
+        [
+            uint!(0x14b39e7aa4068dbe50fe7190e421dc19fbeab33cb4f6a2c4180e4c3224987d3d_U256),
+            uint!(0x1d449b71bd826ec58f28c63ea6c561b7b820fc519f01f021afb1e35e28b0795e_U256),
+            uint!(0x1ea2c9a89baaddbb60fa97fe60fe9d8e89de141689d1252276524dc0a9e987fc_U256),
+        ],
+        [
+            uint!(0x0478d66d43535a8cb57e9c1c3d6a2bd7591f9a46a0e9c058134d5cefdb3c7ff1_U256),
+            uint!(0x19272db71eece6a6f608f3b2717f9cd2662e26ad86c400b21cde5e4a7b00bebe_U256),
+            uint!(0x14226537335cab33c749c746f09208abb2dd1bd66a87ef75039be846af134166_U256),
+        ],
+        [
+            uint!(0x01fd6af15956294f9dfe38c0d976a088b21c21e4a1c2e823f912f44961f9a9ce_U256),
+            uint!(0x18e5abedd626ec307bca190b8b2cab1aaee2e62ed229ba5a5ad8518d4e5f2a57_U256),
+            uint!(0x0fc1bbceba0590f5abbdffa6d3b35e3297c021a3a409926d0e2d54dc1c84fda6_U256),
+        ],
+    ];
+
+    const PARTIAL_ROUND_CONSTANTS: [FieldElement; 56] = [
+        uint!(0x1a1d063e54b1e764b63e1855bff015b8cedd192f47308731499573f23597d4b5_U256),
+        uint!(0x26abc66f3fdf8e68839d10956259063708235dccc1aa3793b91b002c5b257c37_U256),
+        uint!(0x0c7c64a9d887385381a578cfed5aed370754427aabca92a70b3c2b12ff4d7be8_U256),
+        uint!(0x1cf5998769e9fab79e17f0b6d08b2d1eba2ebac30dc386b0edd383831354b495_U256),
+        uint!(0x0f5e3a8566be31b7564ca60461e9e08b19828764a9669bc17aba0b97e66b0109_U256),
+        uint!(0x18df6a9d19ea90d895e60e4db0794a01f359a53a180b7d4b42bf3d7a531c976e_U256),
+        uint!(0x04f7bf2c5c0538ac6e4b782c3c6e601ad0ea1d3a3b9d25ef4e324055fa3123dc_U256),
+        uint!(0x29c76ce22255206e3c40058523748531e770c0584aa2328ce55d54628b89ebe6_U256),
+        uint!(0x198d425a45b78e85c053659ab4347f5d65b1b8e9c6108dbe00e0e945dbc5ff15_U256),
+        uint!(0x25ee27ab6296cd5e6af3cc79c598a1daa7ff7f6878b3c49d49d3a9a90c3fdf74_U256),
+        uint!(0x138ea8e0af41a1e024561001c0b6eb1505845d7d0c55b1b2c0f88687a96d1381_U256),
+        uint!(0x306197fb3fab671ef6e7c2cba2eefd0e42851b5b9811f2ca4013370a01d95687_U256),
+        uint!(0x1a0c7d52dc32a4432b66f0b4894d4f1a21db7565e5b4250486419eaf00e8f620_U256),
+        uint!(0x2b46b418de80915f3ff86a8e5c8bdfccebfbe5f55163cd6caa52997da2c54a9f_U256),
+        uint!(0x12d3e0dc0085873701f8b777b9673af9613a1af5db48e05bfb46e312b5829f64_U256),
+        uint!(0x263390cf74dc3a8870f5002ed21d089ffb2bf768230f648dba338a5cb19b3a1f_U256),
+        uint!(0x0a14f33a5fe668a60ac884b4ca607ad0f8abb5af40f96f1d7d543db52b003dcd_U256),
+        uint!(0x28ead9c586513eab1a5e86509d68b2da27be3a4f01171a1dd847df829bc683b9_U256),
+        uint!(0x1c6ab1c328c3c6430972031f1bdb2ac9888f0ea1abe71cffea16cda6e1a7416c_U256),
+        uint!(0x1fc7e71bc0b819792b2500239f7f8de04f6decd608cb98a932346015c5b42c94_U256),
+        uint!(0x03e107eb3a42b2ece380e0d860298f17c0c1e197c952650ee6dd85b93a0ddaa8_U256),
+        uint!(0x2d354a251f381a4669c0d52bf88b772c46452ca57c08697f454505f6941d78cd_U256),
+        uint!(0x094af88ab05d94baf687ef14bc566d1c522551d61606eda3d14b4606826f794b_U256),
+        uint!(0x19705b783bf3d2dc19bcaeabf02f8ca5e1ab5b6f2e3195a9d52b2d249d1396f7_U256),
+        uint!(0x09bf4acc3a8bce3f1fcc33fee54fc5b28723b16b7d740a3e60cef6852271200e_U256),
+        uint!(0x1803f8200db6013c50f83c0c8fab62843413732f301f7058543a073f3f3b5e4e_U256),
+        uint!(0x0f80afb5046244de30595b160b8d1f38bf6fb02d4454c0add41f7fef2faf3e5c_U256),
+        uint!(0x126ee1f8504f15c3d77f0088c1cfc964abcfcf643f4a6fea7dc3f98219529d78_U256),
+        uint!(0x23c203d10cfcc60f69bfb3d919552ca10ffb4ee63175ddf8ef86f991d7d0a591_U256),
+        uint!(0x2a2ae15d8b143709ec0d09705fa3a6303dec1ee4eec2cf747c5a339f7744fb94_U256),
+        uint!(0x07b60dee586ed6ef47e5c381ab6343ecc3d3b3006cb461bbb6b5d89081970b2b_U256),
+        uint!(0x27316b559be3edfd885d95c494c1ae3d8a98a320baa7d152132cfe583c9311bd_U256),
+        uint!(0x1d5c49ba157c32b8d8937cb2d3f84311ef834cc2a743ed662f5f9af0c0342e76_U256),
+        uint!(0x2f8b124e78163b2f332774e0b850b5ec09c01bf6979938f67c24bd5940968488_U256),
+        uint!(0x1e6843a5457416b6dc5b7aa09a9ce21b1d4cba6554e51d84665f75260113b3d5_U256),
+        uint!(0x11cdf00a35f650c55fca25c9929c8ad9a68daf9ac6a189ab1f5bc79f21641d4b_U256),
+        uint!(0x21632de3d3bbc5e42ef36e588158d6d4608b2815c77355b7e82b5b9b7eb560bc_U256),
+        uint!(0x0de625758452efbd97b27025fbd245e0255ae48ef2a329e449d7b5c51c18498a_U256),
+        uint!(0x2ad253c053e75213e2febfd4d976cc01dd9e1e1c6f0fb6b09b09546ba0838098_U256),
+        uint!(0x1d6b169ed63872dc6ec7681ec39b3be93dd49cdd13c813b7d35702e38d60b077_U256),
+        uint!(0x1660b740a143664bb9127c4941b67fed0be3ea70a24d5568c3a54e706cfef7fe_U256),
+        uint!(0x0065a92d1de81f34114f4ca2deef76e0ceacdddb12cf879096a29f10376ccbfe_U256),
+        uint!(0x1f11f065202535987367f823da7d672c353ebe2ccbc4869bcf30d50a5871040d_U256),
+        uint!(0x26596f5c5dd5a5d1b437ce7b14a2c3dd3bd1d1a39b6759ba110852d17df0693e_U256),
+        uint!(0x16f49bc727e45a2f7bf3056efcf8b6d38539c4163a5f1e706743db15af91860f_U256),
+        uint!(0x1abe1deb45b3e3119954175efb331bf4568feaf7ea8b3dc5e1a4e7438dd39e5f_U256),
+        uint!(0x0e426ccab66984d1d8993a74ca548b779f5db92aaec5f102020d34aea15fba59_U256),
+        uint!(0x0e7c30c2e2e8957f4933bd1942053f1f0071684b902d534fa841924303f6a6c6_U256),
+        uint!(0x0812a017ca92cf0a1622708fc7edff1d6166ded6e3528ead4c76e1f31d3fc69d_U256),
+        uint!(0x21a5ade3df2bc1b5bba949d1db96040068afe5026edd7a9c2e276b47cf010d54_U256),
+        uint!(0x01f3035463816c84ad711bf1a058c6c6bd101945f50e5afe72b1a5233f8749ce_U256),
+        uint!(0x0b115572f038c0e2028c2aafc2d06a5e8bf2f9398dbd0fdf4dcaa82b0f0c1c8b_U256),
+        uint!(0x1c38ec0b99b62fd4f0ef255543f50d2e27fc24db42bc910a3460613b6ef59e2f_U256),
+        uint!(0x1c89c6d9666272e8425c3ff1f4ac737b2f5d314606a297d4b1d0b254d880c53e_U256),
+        uint!(0x03326e643580356bf6d44008ae4c042a21ad4880097a5eb38b71e2311bb88f8f_U256),
+        uint!(0x268076b0054fb73f67cee9ea0e51e3ad50f27a6434b5dceb5bdde2299910a4c9_U256),
+    ];
+
+    fn sbox(x: FieldElement) -> FieldElement {
+        let x2 = x.mul_mod(x, P);
+        x2.mul_mod(x2, P).mul_mod(x, P)
+    }
+
+    // The external matrix, circ(2, 1, 1).
+    fn external_layer(state: &mut [FieldElement; 3]) {
+        let sum = state[0].add_mod(state[1], P).add_mod(state[2], P);
+        for x in state.iter_mut() {
+            *x = x.add_mod(sum, P);
+        }
+    }
+
+    // The internal matrix, with a diagonal of (2, 2, 3).
+    fn internal_layer(state: &mut [FieldElement; 3]) {
+        let sum = state[0].add_mod(state[1], P).add_mod(state[2], P);
+        state[0] = state[0].add_mod(sum, P);
+        state[1] = state[1].add_mod(sum, P);
+        state[2] = state[2].add_mod(state[2], P).add_mod(sum, P);
+    }
+
+    fn full_round(state: &mut [FieldElement; 3], constants: &[FieldElement; 3]) {
+        for (x, c) in state.iter_mut().zip(constants) {
+            *x = sbox(x.add_mod(*c, P));
+        }
+        external_layer(state);
+    }
+
+    /// Apply the Poseidon2 permutation.
+    pub fn permute(state: &mut [FieldElement; 3]) {
+        external_layer(state);
+        for constants in &FULL_ROUND_CONSTANTS[..4] {
+            full_round(state, constants);
+        }
+        for c in PARTIAL_ROUND_CONSTANTS {
+            state[0] = sbox(state[0].add_mod(c, P));
+            internal_layer(state);
+        }
+        for constants in &FULL_ROUND_CONSTANTS[4..] {
+            full_round(state, constants);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{permute, FieldElement, Poseidon2Bn254, Poseidon2Hasher};
+        use crate::{
+            hasher::{FieldHash, NodeHasher, ValueHasher},
+            trie::{InternalData, LeafData, NodeKind},
+        };
+        use ruint::uint;
+
+        #[test]
+        fn known_vector() {
+            let mut state = [
+                FieldElement::from(0),
+                FieldElement::from(1),
+                FieldElement::from(2),
+            ];
+            permute(&mut state);
+            assert_eq!(
+                state,
+                [
+                    uint!(0x0bb61d24daca55eebcb1929a82650f328134334da98ea4f847f760054f4a3033_U256),
+                    uint!(0x303b6f7c86d043bfcbcc80214f26a30277a15d3f74ca654992defe7ff8d03570_U256),
+                    uint!(0x1ed25194542b12eef8617361c3ba7c52e660b145994427cc86296242cf766ec8_U256),
+                ]
+            );
+        }
+
+        #[test]
+        fn node_kinds_and_value_lengths() {
+            let leaf = Poseidon2Hasher::hash_leaf(&LeafData {
+                key_path: [0xff; 32],
+                value_hash: Poseidon2Hasher::hash_value(b"value"),
+            });
+            let internal = Poseidon2Hasher::hash_internal(&InternalData {
+                left: leaf,
+                right: [0; 32],
+            });
+            assert_eq!(Poseidon2Hasher::node_kind(&leaf), NodeKind::Leaf);
+            assert_eq!(Poseidon2Hasher::node_kind(&internal), NodeKind::Internal);
+
+            // Encodings out of range are not reduced.
+            let value_hash = Poseidon2Hasher::hash_value(b"value");
+            let mut shifted = FieldElement::from_be_bytes(value_hash);
+            shifted += Poseidon2Bn254::MODULUS;
+            let shifted = shifted.to_be_bytes::<32>();
+            assert_eq!(Poseidon2Hasher::to_element(&shifted), None);
+            let shifted_leaf = Poseidon2Hasher::hash_leaf(&LeafData {
+                key_path: [0xff; 32],
+                value_hash: shifted,
+            });
+            assert_ne!(shifted_leaf, leaf);
+            assert_eq!(Poseidon2Hasher::node_kind(&shifted_leaf), NodeKind::Leaf);
+            let shifted_internal = Poseidon2Hasher::hash_internal(&InternalData {
+                left: shifted_leaf,
+                right: [0; 32],
+            });
+            assert_eq!(Poseidon2Hasher::to_element(&shifted_internal), None);
+            assert_eq!(
+                Poseidon2Hasher::node_kind(&shifted_internal),
+                NodeKind::Internal
+            );
+
+            // Trailing zeros are not lost to the packing of bytes into elements.
+            let values: [&[u8]; 4] = [b"", b"\0", &[0; 31], &[0; 62]];
+            for (i, a) in values.iter().enumerate() {
+                for b in &values[i + 1..] {
+                    assert_ne!(
+                        Poseidon2Hasher::hash_value(a),
+                        Poseidon2Hasher::hash_value(b)
+                    );
+                }
+            }
+        }
+    }
+}
@@ -14,6 +14,8 @@ pub mod hasher;
 pub mod page;
 pub mod page_id;
 pub mod proof;
+pub mod quad;
+pub mod spec;
 pub mod trie;
 pub mod trie_pos;
 pub mod update;
@@ -5,17 +5,37 @@
 //! handling these kinds of proofs.
 //!
 //! Using the types and functions exposed from this module, you can verify the value of a single
-//! key within the trie ([`PathProof`]), the values of multiple keys ([`MultiProof`]), or the result
-//! of updating a trie with a set of changes ([`verify_update`]).
+//! key within the trie ([`PathProof`]), the values of multiple keys ([`MultiProof`]), all of the
+//! values within a range of keys ([`RangeProof`]), the whole subtree of keys under a prefix
+//! ([`PrefixProof`]), or the result of updating a trie with a set of
+//! changes ([`verify_update`], or [`verify_multi_proof_update`] for a [`MultiProof`]). Keys of a trie sharded across several instances are proven with a
+//! [`ShardedPathProof`].
+//!
+//! The [`size`] module estimates the sizes of proofs and witnesses ahead of time.
 
 pub use multi_proof::{
     verify as verify_multi_proof, verify_update as verify_multi_proof_update, MultiPathProof,
-    MultiProof, MultiProofVerificationError, VerifiedMultiProof,
+    MultiProof, MultiProofDecodeError, MultiProofVerificationError, MultiVerifyUpdateError,
+    VerifiedMultiProof,
 };
 pub use path_proof::{
-    verify_update, KeyOutOfScope, PathProof, PathProofTerminal, PathProofVerificationError,
-    PathUpdate, VerifiedPathProof, VerifyUpdateError,
+    child_direction, hash_path, order_children, sibling_directions, update_subtree, verify_update,
+    verify_update_of_width, verify_update_with_subtrees, KeyOutOfScope, PathProof,
+    PathProofTerminal, PathProofVerificationError, PathUpdate, SubtreeUpdate, VerifiedPathProof,
+    VerifyUpdateError,
+};
+pub use prefix_proof::{
+    verify_prefix, PrefixNode, PrefixProof, PrefixProofVerificationError, VerifiedPrefixProof,
+};
+pub use range_proof::{verify_range, RangeProof, RangeProofVerificationError};
+pub use shard_proof::{
+    combine_shard_roots, shard_index, shard_siblings, ShardedPathProof,
+    ShardedPathProofVerificationError, MAX_SHARD_BITS,
 };
 
 mod multi_proof;
 mod path_proof;
+mod prefix_proof;
+mod range_proof;
+mod shard_proof;
+pub mod size;
@@ -9,6 +9,7 @@ use crate::{
         KeyOutOfScope, PathProof, PathProofTerminal,
     },
     trie::{InternalData, KeyPath, LeafData, Node, NodeKind, ValueHash, TERMINATOR},
+    trie_pos::TriePosition,
 };
 
 #[cfg(not(feature = "std"))]
@@ -264,6 +265,213 @@ impl MultiProof {
 
         Self { paths, siblings }
     }
+
+    /// Encode the multi-proof compactly, for shipping to verifiers where every byte counts.
+    ///
+    /// Terminator siblings, which are common wherever the trie is sparse, take a single bit in a
+    /// bitmap instead of a full node. Each leaf key path is stored as the suffix following the
+    /// bytes it shares with the previous leaf. The encoding is canonical:
+    ///
+    /// ```text
+    /// proof:    path count: varint | paths | sibling count: varint
+    ///           | terminator bitmap: [u8; ceil(sibling count / 8)] | non-terminator siblings
+    /// path:     depth: varint | 0 | shared: u8 | key path suffix: [u8; 32 - shared]
+    ///           | value hash: [u8; 32]                                            (leaf)
+    ///           depth: varint | 1 | position depth: varint
+    ///           | position: [u8; ceil(position depth / 8)], zero-padded           (terminator)
+    /// ```
+    ///
+    /// Varints are LEB128 and the bitmap is most significant bit first, zero-padded.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_varint(&mut out, self.paths.len() as u64);
+        let mut prev_key: Option<&KeyPath> = None;
+        for path in &self.paths {
+            write_varint(&mut out, path.depth as u64);
+            match path.terminal {
+                PathProofTerminal::Leaf(ref leaf) => {
+                    let shared = prev_key.map_or(0, |prev| {
+                        prev.iter()
+                            .zip(&leaf.key_path)
+                            .take_while(|(a, b)| a == b)
+                            .count()
+                    });
+                    out.push(COMPACT_LEAF);
+                    out.push(shared as u8);
+                    out.extend_from_slice(&leaf.key_path[shared..]);
+                    out.extend_from_slice(&leaf.value_hash);
+                    prev_key = Some(&leaf.key_path);
+                }
+                PathProofTerminal::Terminator(ref pos) => {
+                    out.push(COMPACT_TERMINATOR);
+                    write_varint(&mut out, pos.depth() as u64);
+                    out.extend_from_slice(&pos.raw_path()[..(pos.depth() as usize).div_ceil(8)]);
+                }
+            }
+        }
+
+        write_varint(&mut out, self.siblings.len() as u64);
+        let bitmap = self
+            .siblings
+            .iter()
+            .map(|sibling| *sibling == TERMINATOR)
+            .collect::<BitVec<u8, Msb0>>();
+        out.extend_from_slice(bitmap.as_raw_slice());
+        for sibling in self.siblings.iter().filter(|s| **s != TERMINATOR) {
+            out.extend_from_slice(sibling);
+        }
+        out
+    }
+
+    /// Decode a multi-proof from the encoding produced by [`MultiProof::encode`].
+    ///
+    /// Only the canonical encoding is accepted, so a proof decodes from exactly one byte string.
+    /// The decoded proof still has to be verified.
+    pub fn decode(bytes: &[u8]) -> Result<Self, MultiProofDecodeError> {
+        let mut reader = CompactReader { bytes };
+
+        let path_count = reader.read_varint()?;
+        let mut paths = Vec::new();
+        let mut prev_key: Option<KeyPath> = None;
+        for _ in 0..path_count {
+            let depth = reader.read_depth()?;
+            let terminal = match reader.read_byte()? {
+                COMPACT_LEAF => {
+                    let shared = reader.read_byte()? as usize;
+                    let mut key_path = prev_key.unwrap_or_default();
+                    if shared > 32 || (prev_key.is_none() && shared != 0) {
+                        return Err(MultiProofDecodeError::Malformed);
+                    }
+                    key_path[shared..].copy_from_slice(reader.read_bytes(32 - shared)?);
+                    // The shared prefix must be the longest one, or the encoding isn't canonical.
+                    if prev_key.is_some_and(|prev| shared < 32 && prev[shared] == key_path[shared])
+                    {
+                        return Err(MultiProofDecodeError::Malformed);
+                    }
+                    let mut value_hash = ValueHash::default();
+                    value_hash.copy_from_slice(reader.read_bytes(32)?);
+                    prev_key = Some(key_path);
+                    PathProofTerminal::Leaf(LeafData {
+                        key_path,
+                        value_hash,
+                    })
+                }
+                COMPACT_TERMINATOR => {
+                    let pos_depth = reader.read_depth()?;
+                    let mut path = KeyPath::default();
+                    let len = pos_depth.div_ceil(8);
+                    path[..len].copy_from_slice(reader.read_bytes(len)?);
+                    if path.view_bits::<Msb0>()[pos_depth..].any() {
+                        return Err(MultiProofDecodeError::Malformed);
+                    }
+                    PathProofTerminal::Terminator(TriePosition::from_path_and_depth(
+                        path,
+                        pos_depth as u16,
+                    ))
+                }
+                _ => return Err(MultiProofDecodeError::Malformed),
+            };
+            paths.push(MultiPathProof { terminal, depth });
+        }
+
+        let sibling_count = reader.read_varint()? as usize;
+        // Each sibling takes at least a bit, which bounds the count before allocating for it.
+        if sibling_count.div_ceil(8) > reader.bytes.len() {
+            return Err(MultiProofDecodeError::UnexpectedEnd);
+        }
+        let bitmap = reader.read_bytes(sibling_count.div_ceil(8))?;
+        let bitmap = bitmap.view_bits::<Msb0>();
+        if bitmap[sibling_count..].any() {
+            return Err(MultiProofDecodeError::Malformed);
+        }
+        let mut siblings = Vec::with_capacity(sibling_count);
+        for is_terminator in bitmap[..sibling_count].iter().by_vals() {
+            if is_terminator {
+                siblings.push(TERMINATOR);
+                continue;
+            }
+            let mut sibling = Node::default();
+            sibling.copy_from_slice(reader.read_bytes(32)?);
+            // A terminator must be marked in the bitmap.
+            if sibling == TERMINATOR {
+                return Err(MultiProofDecodeError::Malformed);
+            }
+            siblings.push(sibling);
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(MultiProofDecodeError::TrailingBytes);
+        }
+        Ok(MultiProof { paths, siblings })
+    }
+}
+
+const COMPACT_LEAF: u8 = 0;
+const COMPACT_TERMINATOR: u8 = 1;
+
+/// Errors in decoding a compactly encoded multi-proof.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultiProofDecodeError {
+    /// The encoding ended before the proof did.
+    UnexpectedEnd,
+    /// The encoding is invalid or not canonical.
+    Malformed,
+    /// There were bytes left after the proof.
+    TrailingBytes,
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+struct CompactReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> CompactReader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], MultiProofDecodeError> {
+        if self.bytes.len() < len {
+            return Err(MultiProofDecodeError::UnexpectedEnd);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn read_byte(&mut self) -> Result<u8, MultiProofDecodeError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_varint(&mut self) -> Result<u64, MultiProofDecodeError> {
+        let mut n = 0u64;
+        for i in 0..10 {
+            let byte = self.read_byte()?;
+            n |= ((byte & 0x7f) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                // Overlong encodings end in a zero byte.
+                if i > 0 && byte == 0 {
+                    break;
+                }
+                return Ok(n);
+            }
+        }
+        Err(MultiProofDecodeError::Malformed)
+    }
+
+    fn read_depth(&mut self) -> Result<usize, MultiProofDecodeError> {
+        match self.read_varint()? {
+            depth @ 0..=256 => Ok(depth as usize),
+            _ => Err(MultiProofDecodeError::Malformed),
+        }
+    }
 }
 
 /// Errors in multi-proof verification.
@@ -683,8 +891,11 @@ impl CommonSiblings {
 ///
 /// All provided operations should have a key-path which is in scope for the multi proof.
 ///
-/// Returns the root of the trie obtained after application of the given updates in the `paths`
-/// vector. In case the `paths` is empty, `prev_root` is returned.
+/// Returns the root of the trie obtained after application of the given updates. In case `ops` is
+/// empty, the root the proof was verified against is returned.
+///
+/// This is the multi-proof equivalent of [`crate::proof::verify_update`], without the need to
+/// split the proof into a [`crate::proof::PathUpdate`] per path.
 pub fn verify_update<H: NodeHasher>(
     proof: &VerifiedMultiProof,
     ops: Vec<(KeyPath, Option<ValueHash>)>,
@@ -901,7 +1112,7 @@ fn hash_and_compact_terminal<H: NodeHasher>(
 
 #[cfg(test)]
 mod tests {
-    use super::{verify, verify_update, MultiProof};
+    use super::{verify, verify_update, MultiProof, MultiProofDecodeError};
 
     use crate::proof::multi_proof::{
         MultiVerifyUpdateError, VerifiedMultiPath, VerifiedMultiProof,
@@ -1967,4 +2178,136 @@ mod tests {
             _ => panic!(),
         }
     }
+
+    // Two leaves deep down a chain of internal nodes with terminator siblings, an empty subtree
+    // and a leaf near the root.
+    fn sparse_multiproof() -> (MultiProof, [u8; 32]) {
+        let internal_hash =
+            |left, right| Blake3Hasher::hash_internal(&InternalData { left, right });
+        let make_leaf = |key_path: [u8; 32]| {
+            let leaf_data = LeafData {
+                key_path,
+                value_hash: [key_path[31]; 32],
+            };
+            let hash = Blake3Hasher::hash_leaf(&leaf_data);
+            (leaf_data, hash)
+        };
+
+        let ka = [0; 32];
+        let mut kb = [0; 32];
+        kb[2] = 0b0000_1000;
+        let kc = [0xff; 32];
+        let (la, va) = make_leaf(ka);
+        let (lb, vb) = make_leaf(kb);
+        let (lc, vc) = make_leaf(kc);
+
+        let mut chain = vec![internal_hash(va, vb)];
+        for _ in 2..21 {
+            chain.push(internal_hash(*chain.last().unwrap(), TERMINATOR));
+        }
+        let root = internal_hash(*chain.last().unwrap(), vc);
+
+        let mut siblings_a = vec![vc];
+        siblings_a.extend(core::iter::repeat_n(TERMINATOR, 19));
+        let mut siblings_b = siblings_a.clone();
+        siblings_a.push(vb);
+        siblings_b.push(va);
+
+        let mut kt = [0; 32];
+        kt[0] = 0b0100_0000;
+        let path_proofs = vec![
+            PathProof {
+                terminal: PathProofTerminal::Leaf(la),
+                siblings: siblings_a,
+            },
+            PathProof {
+                terminal: PathProofTerminal::Leaf(lb),
+                siblings: siblings_b,
+            },
+            PathProof {
+                terminal: PathProofTerminal::Terminator(TriePosition::from_path_and_depth(kt, 2)),
+                siblings: vec![vc, chain[17]],
+            },
+            PathProof {
+                terminal: PathProofTerminal::Leaf(lc),
+                siblings: vec![*chain.last().unwrap()],
+            },
+        ];
+        (MultiProof::from_path_proofs(path_proofs), root)
+    }
+
+    #[test]
+    pub fn test_compact_encoding_round_trip() {
+        let (multi_proof, root) = sparse_multiproof();
+        let _ = verify::<Blake3Hasher>(&multi_proof, root).unwrap();
+
+        let encoded = multi_proof.encode();
+        assert_eq!(MultiProof::decode(&encoded).unwrap(), multi_proof);
+
+        // Every sibling is a terminator, which takes a bit rather than 32 bytes.
+        let terminators = multi_proof
+            .siblings
+            .iter()
+            .filter(|s| **s == TERMINATOR)
+            .count();
+        assert_eq!(terminators, 18);
+        assert_eq!(multi_proof.siblings.len(), terminators);
+        // The path count, three leaves and a terminator, the second leaf sharing two bytes of its
+        // key path with the first, the sibling count and an 18-bit bitmap.
+        assert_eq!(encoded.len(), 1 + 67 + 65 + 4 + 67 + 1 + 3);
+
+        let empty = MultiProof {
+            paths: Vec::new(),
+            siblings: Vec::new(),
+        };
+        assert_eq!(empty.encode(), vec![0, 0]);
+        assert_eq!(MultiProof::decode(&[0, 0]).unwrap(), empty);
+    }
+
+    #[test]
+    pub fn test_compact_encoding_rejects_bad_input() {
+        let (multi_proof, _) = sparse_multiproof();
+        let encoded = multi_proof.encode();
+
+        for len in 0..encoded.len() {
+            assert_eq!(
+                MultiProof::decode(&encoded[..len]),
+                Err(MultiProofDecodeError::UnexpectedEnd)
+            );
+        }
+
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert_eq!(
+            MultiProof::decode(&trailing),
+            Err(MultiProofDecodeError::TrailingBytes)
+        );
+
+        // An overlong varint for the path count.
+        let mut overlong = vec![multi_proof.paths.len() as u8 | 0x80, 0];
+        overlong.extend_from_slice(&encoded[1..]);
+        assert_eq!(
+            MultiProof::decode(&overlong),
+            Err(MultiProofDecodeError::Malformed)
+        );
+
+        // A terminator sibling spelled out in full instead of marked in the bitmap.
+        let explicit = MultiProof {
+            paths: Vec::new(),
+            siblings: vec![TERMINATOR],
+        };
+        assert_eq!(explicit.encode(), vec![0, 1, 0x80]);
+        let mut spelled_out = vec![0, 1, 0];
+        spelled_out.extend_from_slice(&TERMINATOR);
+        assert_eq!(
+            MultiProof::decode(&spelled_out),
+            Err(MultiProofDecodeError::Malformed)
+        );
+
+        // Padding bits in the bitmap.
+        assert_eq!(
+            MultiProof::decode(&[0, 1, 0xc0]),
+            Err(MultiProofDecodeError::Malformed)
+        );
+    }
 }
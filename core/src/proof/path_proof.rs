@@ -1,7 +1,9 @@
 //! Proving and verifying inclusion, non-inclusion, and updates to the trie.
 
 use crate::hasher::NodeHasher;
-use crate::trie::{self, InternalData, KeyPath, LeafData, Node, NodeKind, TERMINATOR};
+use crate::trie::{
+    self, InternalData, KeyPath, LeafData, Node, NodeKind, DEFAULT_VALUE_HASH_LEN, TERMINATOR,
+};
 use crate::trie_pos::TriePosition;
 
 use bitvec::prelude::*;
@@ -18,12 +20,12 @@ use alloc::vec::Vec;
     derive(borsh::BorshDeserialize, borsh::BorshSerialize)
 )]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-pub enum PathProofTerminal {
-    Leaf(LeafData),
+pub enum PathProofTerminal<const N: usize = DEFAULT_VALUE_HASH_LEN> {
+    Leaf(LeafData<N>),
     Terminator(TriePosition),
 }
 
-impl PathProofTerminal {
+impl<const N: usize> PathProofTerminal<N> {
     /// Return the bit-path to the Terminal Node
     pub fn path(&self) -> &BitSlice<u8, Msb0> {
         match self {
@@ -40,7 +42,7 @@ impl PathProofTerminal {
     }
 
     /// Transform this into an optional LeafData.
-    pub fn as_leaf_option(&self) -> Option<LeafData> {
+    pub fn as_leaf_option(&self) -> Option<LeafData<N>> {
         match self {
             Self::Leaf(leaf_data) => Some(leaf_data.clone()),
             Self::Terminator(_) => None,
@@ -48,22 +50,22 @@ impl PathProofTerminal {
     }
 }
 
-/// A proof of some particular path through the trie.
+/// A proof of some particular path through the trie, for leaves with value hashes of `N` bytes.
 #[derive(Debug, Clone)]
 #[cfg_attr(
     feature = "borsh",
     derive(borsh::BorshDeserialize, borsh::BorshSerialize)
 )]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-pub struct PathProof {
+pub struct PathProof<const N: usize = DEFAULT_VALUE_HASH_LEN> {
     /// The terminal node encountered when looking up a key. This is always either a terminator or
     /// leaf.
-    pub terminal: PathProofTerminal,
+    pub terminal: PathProofTerminal<N>,
     /// Sibling nodes encountered during lookup, in ascending order by depth.
     pub siblings: Vec<Node>,
 }
 
-impl PathProof {
+impl<const N: usize> PathProof<N> {
     /// Verify this path proof.
     /// This ONLY verifies the path proof. It does not verify the key path or value of the terminal
     /// node.
@@ -76,7 +78,7 @@ impl PathProof {
         &self,
         key_path: &BitSlice<u8, Msb0>,
         root: Node,
-    ) -> Result<VerifiedPathProof, PathProofVerificationError> {
+    ) -> Result<VerifiedPathProof<N>, PathProofVerificationError> {
         if self.siblings.len() > core::cmp::min(key_path.len(), 256) {
             return Err(PathProofVerificationError::TooManySiblings);
         }
@@ -105,34 +107,71 @@ impl PathProof {
 /// Given a node, a path, and a set of siblings, hash up to the root and return it.
 /// This only consumes the last `siblings.len()` bits of the path, or the whole path.
 /// Siblings are in ascending order from the last bit of `path`.
+///
+/// See [`child_direction`], [`order_children`] and [`sibling_directions`] for the individual
+/// steps of this convention.
 pub fn hash_path<H: NodeHasher>(
     mut node: Node,
     path: &BitSlice<u8, Msb0>,
     siblings: impl IntoIterator<Item = Node>,
 ) -> Node {
     for (bit, sibling) in path.iter().by_vals().rev().zip(siblings) {
-        let (left, right) = if bit {
-            (sibling, node)
-        } else {
-            (node, sibling)
-        };
-
-        let next = InternalData {
-            left: left.clone(),
-            right: right.clone(),
-        };
-        node = H::hash_internal(&next);
+        node = H::hash_internal(&order_children(node, sibling, bit));
     }
 
     node
 }
 
+/// Whether the path to `key_path` continues to the right child of the internal node at `depth`.
+///
+/// The root is at depth 0 and branches on the most significant bit of the first byte of the key
+/// path. Each following depth branches on the next bit, so the node at `depth` branches on bit
+/// `depth % 8` of byte `depth / 8`, counting from the most significant bit.
+///
+/// # Panics
+///
+/// Panics if `depth` is 256 or greater.
+pub fn child_direction(key_path: &KeyPath, depth: usize) -> bool {
+    key_path.view_bits::<Msb0>()[depth]
+}
+
+/// Place a node and its sibling as the children of their parent.
+///
+/// `bit` is the last bit of the path to `node`, as returned by [`child_direction`] for the parent:
+/// `node` is the right child if it is `true` and the left child otherwise.
+pub fn order_children(node: Node, sibling: Node, bit: bool) -> InternalData {
+    if bit {
+        InternalData {
+            left: sibling,
+            right: node,
+        }
+    } else {
+        InternalData {
+            left: node,
+            right: sibling,
+        }
+    }
+}
+
+/// The bits of `key_path` which position the node at `depth` and each of its ancestors below the
+/// root relative to their siblings, from the node at `depth` upwards.
+///
+/// This is the order in which [`hash_path`] consumes siblings, i.e. the reverse of
+/// [`PathProof::siblings`]. The n-th item pairs with the sibling at depth `depth - n`.
+///
+/// # Panics
+///
+/// Panics if `depth` is greater than 256.
+pub fn sibling_directions(key_path: &KeyPath, depth: usize) -> impl Iterator<Item = bool> + '_ {
+    key_path.view_bits::<Msb0>()[..depth].iter().by_vals().rev()
+}
+
 /// An error type indicating that a key is out of scope of a path proof.
 #[derive(Debug, Clone, Copy)]
 pub struct KeyOutOfScope;
 
 /// Errors in path proof verification.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PathProofVerificationError {
     /// Amount of provided siblings is impossible for the expected trie depth.
     TooManySiblings,
@@ -153,16 +192,16 @@ pub enum PathProofVerificationError {
 /// either not a leaf or contains a value for a different key.
 #[derive(Clone)]
 #[must_use = "VerifiedPathProof only checks the trie path, not whether it actually looks up to your expected value."]
-pub struct VerifiedPathProof {
+pub struct VerifiedPathProof<const N: usize = DEFAULT_VALUE_HASH_LEN> {
     key_path: BitVec<u8, Msb0>,
-    terminal: Option<LeafData>,
+    terminal: Option<LeafData<N>>,
     siblings: Vec<Node>,
     root: Node,
 }
 
-impl VerifiedPathProof {
+impl<const N: usize> VerifiedPathProof<N> {
     /// Get the terminal node. `None` signifies that this path concludes with a [`TERMINATOR`].
-    pub fn terminal(&self) -> Option<&LeafData> {
+    pub fn terminal(&self) -> Option<&LeafData<N>> {
         self.terminal.as_ref()
     }
 
@@ -182,7 +221,7 @@ impl VerifiedPathProof {
     /// `Ok(false)` confirms that this key has a different value or does not exist.
     ///
     /// Fails if the key is out of the scope of this path.
-    pub fn confirm_value(&self, expected_leaf: &LeafData) -> Result<bool, KeyOutOfScope> {
+    pub fn confirm_value(&self, expected_leaf: &LeafData<N>) -> Result<bool, KeyOutOfScope> {
         self.in_scope(&expected_leaf.key_path)
             .map(|_| self.terminal() == Some(expected_leaf))
     }
@@ -212,7 +251,7 @@ impl VerifiedPathProof {
     }
 }
 
-impl fmt::Debug for VerifiedPathProof {
+impl<const N: usize> fmt::Debug for VerifiedPathProof<N> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("VerifiedPathProof")
             .field("path", &self.path())
@@ -235,17 +274,19 @@ pub enum VerifyUpdateError {
     PathWithoutOps,
     /// Paths were verified against different state-roots.
     RootMismatch,
+    /// A path is not within the subtree being updated.
+    PathOutOfSubtree,
 }
 
 /// An update to the node at some path.
-pub struct PathUpdate {
+pub struct PathUpdate<const N: usize = DEFAULT_VALUE_HASH_LEN> {
     /// The proven path.
-    pub inner: VerifiedPathProof,
+    pub inner: VerifiedPathProof<N>,
     /// Update operations to perform on keys that all start with the path.
-    pub ops: Vec<(KeyPath, Option<trie::ValueHash>)>,
+    pub ops: Vec<(KeyPath, Option<trie::ValueHash<N>>)>,
 }
 
-impl fmt::Debug for PathUpdate {
+impl<const N: usize> fmt::Debug for PathUpdate<N> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("PathUpdate")
             .field("inner", &self.inner)
@@ -254,6 +295,53 @@ impl fmt::Debug for PathUpdate {
     }
 }
 
+impl<const N: usize> PathUpdate<N> {
+    // The root of the sub-trie at the terminal after applying the operations.
+    fn sub_root<H: NodeHasher>(&self) -> Node {
+        let leaf = self.inner.terminal().cloned();
+        let ops = crate::update::leaf_ops_spliced(leaf, &self.ops);
+        crate::update::build_trie_of_width::<H, N>(self.inner.path().len(), ops, |_| {})
+    }
+}
+
+/// A replacement of the root of a subtree whose contents are not revealed, such as a subtree of
+/// keys excluded from a witness.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshDeserialize, borsh::BorshSerialize)
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SubtreeUpdate {
+    /// The position of the root of the subtree. The subtree holds every key beginning with the
+    /// path of the position.
+    pub position: TriePosition,
+    /// Sibling nodes along the path to the subtree root, in ascending order by depth. There is one
+    /// for every bit of the path.
+    pub siblings: Vec<Node>,
+    /// The root of the subtree before the update.
+    pub prev_root: Node,
+    /// The root of the subtree after the update. This must be the root of a well-formed subtree:
+    /// [`TERMINATOR`] if it is empty and a leaf if it holds a single key.
+    pub new_root: Node,
+}
+
+impl SubtreeUpdate {
+    /// Whether the subtree root before the update is proven against the given root.
+    pub fn verify<H: NodeHasher>(&self, root: Node) -> bool {
+        let path = self.position.path();
+        self.siblings.len() == path.len()
+            && hash_path::<H>(self.prev_root, path, self.siblings.iter().rev().cloned()) == root
+    }
+}
+
+// The root of a sub-trie at the end of a path, along with the siblings of the path.
+struct SubRoot<'a> {
+    path: &'a BitSlice<u8, Msb0>,
+    siblings: &'a [Node],
+    node: Node,
+}
+
 /// Verify an update operation against the root node. This follows a similar algorithm to the
 /// multi-item update, but without altering any backing storage.
 ///
@@ -267,12 +355,116 @@ impl fmt::Debug for PathUpdate {
 pub fn verify_update<H: NodeHasher>(
     prev_root: Node,
     paths: &[PathUpdate],
+) -> Result<Node, VerifyUpdateError> {
+    verify_update_of_width::<H, DEFAULT_VALUE_HASH_LEN>(prev_root, paths)
+}
+
+/// Like [`verify_update`], for leaves with value hashes of `N` bytes.
+pub fn verify_update_of_width<H: NodeHasher, const N: usize>(
+    prev_root: Node,
+    paths: &[PathUpdate<N>],
 ) -> Result<Node, VerifyUpdateError> {
     if paths.is_empty() {
         return Ok(prev_root);
     }
 
-    // Verify important properties about the paths.
+    check_path_updates(prev_root, paths)?;
+    let sub_roots = paths
+        .iter()
+        .map(|path| SubRoot {
+            path: path.inner.path(),
+            siblings: &path.inner.siblings,
+            node: path.sub_root::<H>(),
+        })
+        .collect::<Vec<_>>();
+    Ok(hash_sub_roots::<H>(&sub_roots, 0))
+}
+
+/// Like [`verify_update`], but additionally replacing the roots of subtrees whose contents are not
+/// revealed.
+///
+/// The subtrees must be in ascending order by position and must not overlap with each other or
+/// with the paths. Each subtree root before the update is checked against `prev_root`.
+pub fn verify_update_with_subtrees<H: NodeHasher>(
+    prev_root: Node,
+    paths: &[PathUpdate],
+    subtrees: &[SubtreeUpdate],
+) -> Result<Node, VerifyUpdateError> {
+    check_path_updates(prev_root, paths)?;
+    for (i, subtree) in subtrees.iter().enumerate() {
+        if !subtree.verify::<H>(prev_root) {
+            return Err(VerifyUpdateError::RootMismatch);
+        }
+        if i != 0 && subtrees[i - 1].position.path() >= subtree.position.path() {
+            return Err(VerifyUpdateError::PathsOutOfOrder);
+        }
+    }
+
+    let mut sub_roots = paths
+        .iter()
+        .map(|path| SubRoot {
+            path: path.inner.path(),
+            siblings: &path.inner.siblings,
+            node: path.sub_root::<H>(),
+        })
+        .chain(subtrees.iter().map(|subtree| SubRoot {
+            path: subtree.position.path(),
+            siblings: &subtree.siblings,
+            node: subtree.new_root,
+        }))
+        .collect::<Vec<_>>();
+    if sub_roots.is_empty() {
+        return Ok(prev_root);
+    }
+    sub_roots.sort_by(|a, b| a.path.cmp(b.path));
+
+    // A subtree within another would be replaced twice.
+    for pair in sub_roots.windows(2) {
+        if pair[0].path == pair[1].path || pair[1].path.starts_with(pair[0].path) {
+            return Err(VerifyUpdateError::PathsOutOfOrder);
+        }
+    }
+    Ok(hash_sub_roots::<H>(&sub_roots, 0))
+}
+
+/// Compute the root of the subtree at `depth` along the given paths after applying their updates.
+///
+/// All paths must begin with the same `depth` bits and must be verified against the same root.
+/// Like the root of a whole trie, the result is [`TERMINATOR`] if the subtree ends up empty and a
+/// leaf if it ends up with a single key.
+pub fn update_subtree<H: NodeHasher>(
+    depth: usize,
+    paths: &[PathUpdate],
+) -> Result<Node, VerifyUpdateError> {
+    let Some(first) = paths.first() else {
+        return Err(VerifyUpdateError::PathOutOfSubtree);
+    };
+    let prefix = first.inner.path().get(..depth);
+    let prefix = prefix.ok_or(VerifyUpdateError::PathOutOfSubtree)?;
+    if paths
+        .iter()
+        .any(|path| !path.inner.path().starts_with(prefix))
+    {
+        return Err(VerifyUpdateError::PathOutOfSubtree);
+    }
+
+    check_path_updates(first.inner.root(), paths)?;
+    let sub_roots = paths
+        .iter()
+        .map(|path| SubRoot {
+            path: path.inner.path(),
+            siblings: &path.inner.siblings,
+            node: path.sub_root::<H>(),
+        })
+        .collect::<Vec<_>>();
+    Ok(hash_sub_roots::<H>(&sub_roots, depth))
+}
+
+// Verify important properties about the paths.
+fn check_path_updates<const N: usize>(
+    prev_root: Node,
+    paths: &[PathUpdate<N>],
+) -> Result<(), VerifyUpdateError> {
     for (i, path) in paths.iter().enumerate() {
         // All paths must stem from the same starting root.
         if path.inner.root() != prev_root {
@@ -299,41 +491,40 @@ pub fn verify_update<H: NodeHasher>(
             }
         }
     }
+    Ok(())
+}
 
+// Hash the sub-roots, in ascending order by path, up to the node at depth `stop` shared by all
+// of their paths.
+fn hash_sub_roots<H: NodeHasher>(sub_roots: &[SubRoot], stop: usize) -> Node {
     // left frontier
     let mut pending_siblings: Vec<(Node, usize)> = Vec::new();
-    for (i, path) in paths.iter().enumerate() {
-        let leaf = path.inner.terminal().map(|x| x.clone());
-        let ops = &path.ops;
-        let skip = path.inner.path().len();
-
-        let up_layers = match paths.get(i + 1) {
-            None => skip, // go to root
-            Some(p) => {
-                let n = shared_bits(p.inner.path(), path.inner.path());
+    for (i, sub_root) in sub_roots.iter().enumerate() {
+        let skip = sub_root.path.len();
+
+        let up_layers = match sub_roots.get(i + 1) {
+            None => skip - stop, // go to the top
+            Some(next) => {
+                let n = shared_bits(next.path, sub_root.path);
                 // n always < skip
                 // we want to end at layer n + 1
                 skip - (n + 1)
             }
         };
 
-        let ops = crate::update::leaf_ops_spliced(leaf, ops);
-        let sub_root = crate::update::build_trie::<H>(skip, ops, |_| {});
-
-        let mut cur_node = sub_root;
+        let mut cur_node = sub_root.node;
         let mut cur_layer = skip;
         let end_layer = skip - up_layers;
         // iterate siblings up to the point of collision with next path, replacing with pending
         // siblings, and compacting where possible.
         // push (node, end_layer) to pending siblings when done.
-        for (bit, sibling) in path
-            .inner
-            .path()
+        for (bit, sibling) in sub_root
+            .path
             .iter()
             .by_vals()
             .rev()
             .take(up_layers)
-            .zip(path.inner.siblings.iter().rev())
+            .zip(sub_root.siblings.iter().rev())
         {
             let sibling = if pending_siblings.last().map_or(false, |p| p.1 == cur_layer) {
                 // unwrap: checked above
@@ -370,12 +561,111 @@ pub fn verify_update<H: NodeHasher>(
         pending_siblings.push((cur_node, end_layer));
     }
 
-    // UNWRAP: If `paths` is not empty this can never be `None` since `pending_siblings` is
+    // UNWRAP: If `sub_roots` is not empty this can never be `None` since `pending_siblings` is
     // unconditionally appended to.
-    Ok(pending_siblings.pop().map(|n| n.0).unwrap())
+    pending_siblings.pop().map(|n| n.0).unwrap()
 }
 
 // TODO: dedup, this appears in `update` as well.
 pub fn shared_bits(a: &BitSlice<u8, Msb0>, b: &BitSlice<u8, Msb0>) -> usize {
     a.iter().zip(b.iter()).take_while(|(a, b)| a == b).count()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        child_direction, hash_path, order_children, sibling_directions, verify_update_of_width,
+        PathProof, PathProofTerminal, PathUpdate,
+    };
+    use crate::hasher::{Blake3Hasher, NodeHasher};
+    use crate::trie::{InternalData, LeafData};
+    use bitvec::prelude::*;
+
+    fn value_hashes_of_width<const N: usize>() {
+        let leaf = |first_byte: u8, value_byte: u8| LeafData::<N> {
+            key_path: [first_byte; 32],
+            value_hash: [value_byte; N],
+        };
+        let (a, b) = (leaf(0x00, 1), leaf(0xff, 2));
+
+        // Leaves are hashed over the key path followed by the whole value hash.
+        let mut expected = *blake3::hash(&[&a.key_path[..], &a.value_hash[..]].concat()).as_bytes();
+        expected[0] |= 0b1000_0000;
+        assert_eq!(Blake3Hasher::hash_leaf(&a), expected);
+
+        let root = Blake3Hasher::hash_internal(&InternalData {
+            left: Blake3Hasher::hash_leaf(&a),
+            right: Blake3Hasher::hash_leaf(&b),
+        });
+        let proof = PathProof::<N> {
+            terminal: PathProofTerminal::Leaf(a.clone()),
+            siblings: vec![Blake3Hasher::hash_leaf(&b)],
+        };
+        let verified = proof
+            .verify::<Blake3Hasher>(a.key_path.view_bits::<Msb0>(), root)
+            .unwrap();
+        assert!(verified.confirm_value(&a).unwrap());
+        assert!(!verified.confirm_value(&leaf(0x00, 3)).unwrap());
+
+        let new_a = leaf(0x00, 3);
+        let new_root = verify_update_of_width::<Blake3Hasher, N>(
+            root,
+            &[PathUpdate {
+                inner: verified,
+                ops: vec![(new_a.key_path, Some(new_a.value_hash))],
+            }],
+        )
+        .unwrap();
+        assert_eq!(
+            new_root,
+            Blake3Hasher::hash_internal(&InternalData {
+                left: Blake3Hasher::hash_leaf(&new_a),
+                right: Blake3Hasher::hash_leaf(&b),
+            })
+        );
+    }
+
+    #[test]
+    fn value_hashes_of_any_width() {
+        value_hashes_of_width::<16>();
+        value_hashes_of_width::<32>();
+        value_hashes_of_width::<64>();
+    }
+
+    #[test]
+    fn directions_match_hash_path() {
+        let mut key_path = [0; 32];
+        key_path[0] = 0b1010_0000;
+        key_path[1] = 0b0000_0001;
+
+        assert!(child_direction(&key_path, 0));
+        assert!(!child_direction(&key_path, 1));
+        assert!(child_direction(&key_path, 2));
+        assert!(child_direction(&key_path, 15));
+        assert!(!child_direction(&key_path, 16));
+
+        let depth = 16;
+        let leaf = [0xAA; 32];
+        let siblings = (0..depth as u8).map(|i| [i + 1; 32]).collect::<Vec<_>>();
+
+        // Hash up from the node at `depth`, with siblings ordered deepest first.
+        let mut node = leaf;
+        for (bit, sibling) in sibling_directions(&key_path, depth).zip(siblings.iter().rev()) {
+            node = Blake3Hasher::hash_internal(&order_children(node, *sibling, bit));
+        }
+
+        let expected = hash_path::<Blake3Hasher>(
+            leaf,
+            &key_path.view_bits::<Msb0>()[..depth],
+            siblings.iter().rev().cloned(),
+        );
+        assert_eq!(node, expected);
+
+        let directions = sibling_directions(&key_path, depth).collect::<Vec<_>>();
+        let expected = (0..depth)
+            .rev()
+            .map(|d| child_direction(&key_path, d))
+            .collect::<Vec<_>>();
+        assert_eq!(directions, expected);
+    }
+}
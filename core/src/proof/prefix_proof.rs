@@ -0,0 +1,308 @@
+//! Proving and verifying the complete subtree under a key-path prefix.
+//!
+//! All keys beginning with a prefix of `n` bits live in the subtree rooted at the node at depth
+//! `n` along the prefix. A [`PrefixProof`] proves that node against the root with the siblings
+//! along the way, and optionally lists every leaf of the subtree, which the verifier hashes back
+//! into the node. Proving a whole small keyspace this way takes far fewer nodes than proving each
+//! key on its own.
+//!
+//! The path to the prefix may end early in a terminal node: a terminator, meaning that no key has
+//! the prefix, or a leaf, which is then the only candidate for a key with the prefix.
+
+use crate::hasher::NodeHasher;
+use crate::proof::path_proof::{hash_path, PathProofTerminal};
+use crate::trie::{self, LeafData, Node, TERMINATOR};
+use crate::update::build_trie;
+
+use bitvec::prelude::*;
+
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+/// The node the path to a prefix ends in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshDeserialize, borsh::BorshSerialize)
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PrefixNode {
+    /// The internal node at the depth of the prefix, which is the root of the subtree of all keys
+    /// with the prefix.
+    Internal(Node),
+    /// A terminal node at or above the depth of the prefix.
+    Terminal(PathProofTerminal),
+}
+
+/// A proof of the subtree of all keys beginning with a prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshDeserialize, borsh::BorshSerialize)
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PrefixProof {
+    /// The node the path to the prefix ends in.
+    pub node: PrefixNode,
+    /// Sibling nodes along the path to `node`, in ascending order by depth.
+    pub siblings: Vec<Node>,
+    /// Every leaf of the subtree, in ascending key order. `None` if the leaves are not listed.
+    pub leaves: Option<Vec<LeafData>>,
+}
+
+/// Errors in prefix proof verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefixProofVerificationError {
+    /// The prefix is longer than a key path.
+    PrefixTooLong,
+    /// The node is not at the depth of the prefix, or an internal node is not labeled as one.
+    BadNode,
+    /// The node and siblings do not hash to the root.
+    RootMismatch,
+    /// The leaves are out of order, outside of the prefix, or do not make up the subtree.
+    LeavesMismatch,
+}
+
+/// A verified prefix proof.
+#[derive(Debug, Clone)]
+pub struct VerifiedPrefixProof {
+    subtree_root: Node,
+    leaves: Option<Vec<LeafData>>,
+}
+
+impl VerifiedPrefixProof {
+    /// The root of the subtree of all keys with the prefix. This is [`TERMINATOR`] if there are
+    /// none, and a leaf if there is only one.
+    pub fn subtree_root(&self) -> Node {
+        self.subtree_root
+    }
+
+    /// Every leaf with the prefix, in ascending key order. `None` if the proof does not list the
+    /// leaves of a subtree with more than one leaf.
+    ///
+    /// The caller is responsible for checking values against the value hashes of the leaves.
+    pub fn leaves(&self) -> Option<&[LeafData]> {
+        self.leaves.as_deref()
+    }
+}
+
+/// Verify a proof of the subtree of all keys beginning with `prefix` against the root.
+pub fn verify_prefix<H: NodeHasher>(
+    proof: &PrefixProof,
+    prefix: &BitSlice<u8, Msb0>,
+    root: Node,
+) -> Result<VerifiedPrefixProof, PrefixProofVerificationError> {
+    if prefix.len() > 256 {
+        return Err(PrefixProofVerificationError::PrefixTooLong);
+    }
+
+    let depth = proof.siblings.len();
+    let node = match proof.node {
+        PrefixNode::Internal(node) if depth == prefix.len() && trie::is_internal::<H>(&node) => {
+            node
+        }
+        PrefixNode::Terminal(ref terminal) if depth <= prefix.len() => terminal.node::<H>(),
+        _ => return Err(PrefixProofVerificationError::BadNode),
+    };
+    let path = &prefix[..depth];
+    if hash_path::<H>(node, path, proof.siblings.iter().rev().cloned()) != root {
+        return Err(PrefixProofVerificationError::RootMismatch);
+    }
+
+    let (subtree_root, known_leaves) = match proof.node {
+        PrefixNode::Internal(node) => (node, None),
+        // A leaf above the prefix may belong anywhere beneath its position, so it is only within
+        // the subtree if its key has the prefix.
+        PrefixNode::Terminal(PathProofTerminal::Leaf(ref leaf))
+            if leaf.key_path.view_bits::<Msb0>().starts_with(prefix) =>
+        {
+            (node, Some(vec![leaf.clone()]))
+        }
+        PrefixNode::Terminal(_) => (TERMINATOR, Some(Vec::new())),
+    };
+
+    let leaves = match (&proof.leaves, known_leaves) {
+        (None, known_leaves) => known_leaves,
+        (Some(leaves), Some(known_leaves)) if *leaves == known_leaves => Some(known_leaves),
+        (Some(_), Some(_)) => return Err(PrefixProofVerificationError::LeavesMismatch),
+        (Some(leaves), None) => {
+            let in_order = leaves.windows(2).all(|w| w[0].key_path < w[1].key_path);
+            let in_prefix = leaves
+                .iter()
+                .all(|leaf| leaf.key_path.view_bits::<Msb0>().starts_with(prefix));
+            if !in_order || !in_prefix {
+                return Err(PrefixProofVerificationError::LeavesMismatch);
+            }
+            let ops = leaves.iter().map(|leaf| (leaf.key_path, leaf.value_hash));
+            if build_trie::<H>(prefix.len(), ops, |_| {}) != subtree_root {
+                return Err(PrefixProofVerificationError::LeavesMismatch);
+            }
+            Some(leaves.clone())
+        }
+    };
+
+    Ok(VerifiedPrefixProof {
+        subtree_root,
+        leaves,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_prefix, PrefixNode, PrefixProof, PrefixProofVerificationError};
+    use crate::{
+        hasher::{Blake3Hasher, NodeHasher},
+        proof::PathProofTerminal,
+        trie::{InternalData, LeafData, TERMINATOR},
+        trie_pos::TriePosition,
+    };
+    use bitvec::prelude::*;
+
+    //       root
+    //      /    \
+    //     i1     v3
+    //    /  \
+    //   v0   i2
+    //       /  \
+    //      v1   v2
+    fn leaf(first_byte: u8) -> LeafData {
+        let mut key_path = [0; 32];
+        key_path[0] = first_byte;
+        LeafData {
+            key_path,
+            value_hash: [first_byte; 32],
+        }
+    }
+
+    struct Trie {
+        root: [u8; 32],
+        i1: [u8; 32],
+        i2: [u8; 32],
+        v: [([u8; 32], LeafData); 4],
+    }
+
+    fn trie() -> Trie {
+        let v = [0b0000_0000, 0b0100_0000, 0b0110_0000, 0b1000_0000].map(|b| {
+            let leaf = leaf(b);
+            (Blake3Hasher::hash_leaf(&leaf), leaf)
+        });
+        let i2 = Blake3Hasher::hash_internal(&InternalData {
+            left: v[1].0,
+            right: v[2].0,
+        });
+        let i1 = Blake3Hasher::hash_internal(&InternalData {
+            left: v[0].0,
+            right: i2,
+        });
+        let root = Blake3Hasher::hash_internal(&InternalData {
+            left: i1,
+            right: v[3].0,
+        });
+        Trie { root, i1, i2, v }
+    }
+
+    fn prefix(bits: &[bool]) -> BitVec<u8, Msb0> {
+        bits.iter().collect()
+    }
+
+    #[test]
+    fn internal_node_with_leaves() {
+        let t = trie();
+        let mut proof = PrefixProof {
+            node: PrefixNode::Internal(t.i1),
+            siblings: vec![t.v[3].0],
+            leaves: Some(vec![t.v[0].1.clone(), t.v[1].1.clone(), t.v[2].1.clone()]),
+        };
+        let verified = verify_prefix::<Blake3Hasher>(&proof, &prefix(&[false]), t.root).unwrap();
+        assert_eq!(verified.subtree_root(), t.i1);
+        assert_eq!(verified.leaves().unwrap().len(), 3);
+
+        // Without the leaves, only the subtree root is proven.
+        proof.leaves = None;
+        let verified = verify_prefix::<Blake3Hasher>(&proof, &prefix(&[false]), t.root).unwrap();
+        assert!(verified.leaves().is_none());
+
+        // A missing leaf is detected.
+        proof.leaves = Some(vec![t.v[0].1.clone(), t.v[2].1.clone()]);
+        assert_eq!(
+            verify_prefix::<Blake3Hasher>(&proof, &prefix(&[false]), t.root).unwrap_err(),
+            PrefixProofVerificationError::LeavesMismatch
+        );
+    }
+
+    #[test]
+    fn deeper_prefix() {
+        let t = trie();
+        let proof = PrefixProof {
+            node: PrefixNode::Internal(t.i2),
+            siblings: vec![t.v[3].0, t.v[0].0],
+            leaves: Some(vec![t.v[1].1.clone(), t.v[2].1.clone()]),
+        };
+        verify_prefix::<Blake3Hasher>(&proof, &prefix(&[false, true]), t.root).unwrap();
+
+        // The same node at the wrong depth.
+        assert_eq!(
+            verify_prefix::<Blake3Hasher>(&proof, &prefix(&[false, true, false]), t.root)
+                .unwrap_err(),
+            PrefixProofVerificationError::BadNode
+        );
+    }
+
+    #[test]
+    fn leaf_above_prefix() {
+        let t = trie();
+        let proof = PrefixProof {
+            node: PrefixNode::Terminal(PathProofTerminal::Leaf(t.v[3].1.clone())),
+            siblings: vec![t.i1],
+            leaves: None,
+        };
+
+        // The leaf has the prefix 10.
+        let verified =
+            verify_prefix::<Blake3Hasher>(&proof, &prefix(&[true, false]), t.root).unwrap();
+        assert_eq!(verified.leaves().unwrap(), &[t.v[3].1.clone()]);
+        assert_eq!(verified.subtree_root(), t.v[3].0);
+
+        // But not the prefix 11, under which there are no keys.
+        let verified =
+            verify_prefix::<Blake3Hasher>(&proof, &prefix(&[true, true]), t.root).unwrap();
+        assert_eq!(verified.leaves().unwrap(), &[]);
+        assert_eq!(verified.subtree_root(), TERMINATOR);
+
+        // Listing the leaf under the wrong prefix is rejected.
+        let listed = PrefixProof {
+            leaves: Some(vec![t.v[3].1.clone()]),
+            ..proof
+        };
+        assert_eq!(
+            verify_prefix::<Blake3Hasher>(&listed, &prefix(&[true, true]), t.root).unwrap_err(),
+            PrefixProofVerificationError::LeavesMismatch
+        );
+    }
+
+    #[test]
+    fn empty_trie() {
+        let proof = PrefixProof {
+            node: PrefixNode::Terminal(PathProofTerminal::Terminator(TriePosition::new())),
+            siblings: vec![],
+            leaves: Some(vec![]),
+        };
+        let verified =
+            verify_prefix::<Blake3Hasher>(&proof, &prefix(&[true, false]), TERMINATOR).unwrap();
+        assert_eq!(verified.leaves().unwrap(), &[]);
+    }
+
+    #[test]
+    fn wrong_root() {
+        let t = trie();
+        let proof = PrefixProof {
+            node: PrefixNode::Internal(t.i1),
+            siblings: vec![t.v[3].0],
+            leaves: None,
+        };
+        assert_eq!(
+            verify_prefix::<Blake3Hasher>(&proof, &prefix(&[true]), t.root).unwrap_err(),
+            PrefixProofVerificationError::RootMismatch
+        );
+    }
+}
@@ -0,0 +1,370 @@
+//! Proving and verifying the complete contents of a range of keys.
+//!
+//! Leaves in the trie are ordered by their key paths, read as bit-strings from the most
+//! significant bit of the first byte. This is the same as the lexicographic order of the key
+//! bytes, so a range of keys corresponds to a contiguous run of leaves.
+//!
+//! A [`RangeProof`] is made of the path proof for the start of the range, the path proofs of all
+//! leaves within the range, and the path proof of the first leaf after the range, if any. Two
+//! adjacent proofs in this chain show that no leaf lies between them when every sibling branching
+//! off toward the other path, below the point where the paths diverge, is empty. Because a proof
+//! covering `[a, b)` includes the leaf at `b`, a large range can be split into chunks which chain
+//! onto one another.
+
+use crate::hasher::NodeHasher;
+use crate::proof::path_proof::{shared_bits, PathProof, PathProofTerminal};
+use crate::trie::{KeyPath, LeafData, Node, TERMINATOR};
+
+use bitvec::prelude::*;
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+/// A proof that a list of leaves makes up all the leaves in a range of keys.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshDeserialize, borsh::BorshSerialize)
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RangeProof {
+    /// The proof of the start key of the range. This proves either the leaf at the start key, or
+    /// the absence of it.
+    pub start: PathProof,
+    /// The proofs of every leaf within the range, in ascending key order.
+    pub leaves: Vec<PathProof>,
+    /// The proof of the first leaf at or after the end of the range. `None` if there is no such
+    /// leaf.
+    pub upper: Option<PathProof>,
+}
+
+/// Errors in range proof verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeProofVerificationError {
+    /// One of the path proofs did not verify against the root.
+    RootMismatch,
+    /// One of the leaf proofs did not end in a leaf.
+    ExpectedLeaf,
+    /// The leaves were not in ascending order, or fell outside of their part of the range.
+    LeavesOutOfOrder,
+    /// A leaf may exist between two adjacent leaves of the proof.
+    Gap,
+    /// A leaf after the range was provided for a range without an end.
+    UnexpectedUpper,
+}
+
+/// Verify a range proof of the keys in `[start, end)` against the root. `end` is `None` for a range
+/// reaching the end of the key space.
+///
+/// On success, returns all leaves within the range, in ascending key order. The caller is
+/// responsible for checking the values against the value hashes of the leaves.
+pub fn verify_range<H: NodeHasher>(
+    proof: &RangeProof,
+    start: &KeyPath,
+    end: Option<&KeyPath>,
+    root: Node,
+) -> Result<Vec<LeafData>, RangeProofVerificationError> {
+    if end.is_none() && proof.upper.is_some() {
+        return Err(RangeProofVerificationError::UnexpectedUpper);
+    }
+
+    verify_path::<H>(&proof.start, start.view_bits(), root)?;
+
+    let mut leaves = Vec::with_capacity(proof.leaves.len());
+    for leaf_proof in &proof.leaves {
+        let leaf = verify_leaf::<H>(leaf_proof, root)?;
+        let in_order = match leaves.last() {
+            None => &leaf.key_path >= start,
+            Some(LeafData { key_path, .. }) => &leaf.key_path > key_path,
+        };
+        if !in_order || end.is_some_and(|end| &leaf.key_path >= end) {
+            return Err(RangeProofVerificationError::LeavesOutOfOrder);
+        }
+        leaves.push(leaf);
+    }
+
+    if let Some(ref upper_proof) = proof.upper {
+        let upper = verify_leaf::<H>(upper_proof, root)?;
+        // UNWRAP: `end` is checked to be `Some` above.
+        if &upper.key_path < end.unwrap() {
+            return Err(RangeProofVerificationError::LeavesOutOfOrder);
+        }
+    }
+
+    // The proven leaves following the start, in order.
+    let mut chain = proof.leaves.iter().chain(proof.upper.iter());
+
+    // The start proof may end in a leaf which is in the chain as well. Any other leaf at or after
+    // the start key would be missing from the chain.
+    let mut prev = match proof.start.terminal {
+        PathProofTerminal::Leaf(ref leaf) if &leaf.key_path >= start => {
+            match chain.next().and_then(|p| p.terminal.as_leaf_option()) {
+                Some(next) if next.key_path == leaf.key_path => {}
+                _ => return Err(RangeProofVerificationError::Gap),
+            }
+            leaf.key_path.view_bits::<Msb0>()
+        }
+        _ => start.view_bits::<Msb0>(),
+    };
+    let mut prev_siblings = &proof.start.siblings[..];
+
+    for next_proof in chain {
+        // UNWRAP: all proofs in the chain were checked to end in leaves above.
+        let next = next_proof.terminal.as_leaf_option().unwrap();
+        if !no_leaves_between(
+            &prev[..prev_siblings.len()],
+            prev_siblings,
+            &next.key_path.view_bits::<Msb0>()[..next_proof.siblings.len()],
+            &next_proof.siblings,
+        ) {
+            return Err(RangeProofVerificationError::Gap);
+        }
+        prev = next_proof.terminal.path();
+        prev_siblings = &next_proof.siblings[..];
+    }
+
+    // Without a leaf after the range, nothing may lie to the right of the last proven path.
+    if proof.upper.is_none() {
+        let path = &prev[..prev_siblings.len()];
+        if !branches_empty(path, prev_siblings, 0, false) {
+            return Err(RangeProofVerificationError::Gap);
+        }
+    }
+
+    Ok(leaves)
+}
+
+fn verify_path<H: NodeHasher>(
+    proof: &PathProof,
+    key_path: &BitSlice<u8, Msb0>,
+    root: Node,
+) -> Result<(), RangeProofVerificationError> {
+    proof
+        .verify::<H>(key_path, root)
+        .map(|_| ())
+        .map_err(|_| RangeProofVerificationError::RootMismatch)
+}
+
+fn verify_leaf<H: NodeHasher>(
+    proof: &PathProof,
+    root: Node,
+) -> Result<LeafData, RangeProofVerificationError> {
+    let leaf = proof
+        .terminal
+        .as_leaf_option()
+        .ok_or(RangeProofVerificationError::ExpectedLeaf)?;
+    verify_path::<H>(proof, leaf.key_path.view_bits(), root)?;
+    Ok(leaf)
+}
+
+// Whether no leaf may lie between the terminals of two verified paths, the left one strictly
+// before the right one.
+fn no_leaves_between(
+    left: &BitSlice<u8, Msb0>,
+    left_siblings: &[Node],
+    right: &BitSlice<u8, Msb0>,
+    right_siblings: &[Node],
+) -> bool {
+    let diverge = shared_bits(left, right);
+    if diverge >= left.len() || diverge >= right.len() {
+        // One terminal lies beneath the other, which is only possible for malformed proofs.
+        return false;
+    }
+
+    // Below the point of divergence, anything to the right of the left path or to the left of the
+    // right path lies between the two.
+    branches_empty(left, left_siblings, diverge + 1, false)
+        && branches_empty(right, right_siblings, diverge + 1, true)
+}
+
+// Whether all siblings from `from_depth` on are empty where the path takes the branch `bit`.
+fn branches_empty(
+    path: &BitSlice<u8, Msb0>,
+    siblings: &[Node],
+    from_depth: usize,
+    bit: bool,
+) -> bool {
+    path.iter()
+        .by_vals()
+        .zip(siblings)
+        .skip(from_depth)
+        .all(|(path_bit, sibling)| path_bit != bit || sibling == &TERMINATOR)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_range, RangeProof, RangeProofVerificationError};
+    use crate::{
+        hasher::{Blake3Hasher, NodeHasher},
+        proof::{PathProof, PathProofTerminal},
+        trie::{InternalData, LeafData, TERMINATOR},
+        trie_pos::TriePosition,
+    };
+
+    //       root
+    //      /    \
+    //     i1     v3
+    //    /  \
+    //   v0   i2
+    //       /  \
+    //      v1   T
+    fn key(first_byte: u8) -> [u8; 32] {
+        let mut key = [0; 32];
+        key[0] = first_byte;
+        key
+    }
+
+    fn leaf(first_byte: u8) -> LeafData {
+        LeafData {
+            key_path: key(first_byte),
+            value_hash: [first_byte; 32],
+        }
+    }
+
+    struct Trie {
+        root: [u8; 32],
+        v0: PathProof,
+        v1: PathProof,
+        v3: PathProof,
+    }
+
+    fn trie() -> Trie {
+        let [v0, v1, v3] = [0b0000_0000, 0b0100_0000, 0b1000_0000].map(|b| {
+            let leaf = leaf(b);
+            (Blake3Hasher::hash_leaf(&leaf), leaf)
+        });
+        let i2 = Blake3Hasher::hash_internal(&InternalData {
+            left: v1.0,
+            right: TERMINATOR,
+        });
+        let i1 = Blake3Hasher::hash_internal(&InternalData {
+            left: v0.0,
+            right: i2,
+        });
+        let root = Blake3Hasher::hash_internal(&InternalData {
+            left: i1,
+            right: v3.0,
+        });
+        Trie {
+            root,
+            v0: PathProof {
+                terminal: PathProofTerminal::Leaf(v0.1),
+                siblings: vec![v3.0, i2],
+            },
+            v1: PathProof {
+                terminal: PathProofTerminal::Leaf(v1.1),
+                siblings: vec![v3.0, v0.0, TERMINATOR],
+            },
+            v3: PathProof {
+                terminal: PathProofTerminal::Leaf(v3.1),
+                siblings: vec![i1],
+            },
+        }
+    }
+
+    #[test]
+    fn full_range() {
+        let t = trie();
+        let proof = RangeProof {
+            start: t.v0.clone(),
+            leaves: vec![t.v0, t.v1, t.v3],
+            upper: None,
+        };
+        let leaves = verify_range::<Blake3Hasher>(&proof, &[0; 32], None, t.root).unwrap();
+        assert_eq!(leaves, vec![leaf(0), leaf(0b0100_0000), leaf(0b1000_0000)]);
+    }
+
+    #[test]
+    fn chained_chunks() {
+        let t = trie();
+        // [0x00, 0x40) contains v0, followed by v1.
+        let first = RangeProof {
+            start: t.v0.clone(),
+            leaves: vec![t.v0.clone()],
+            upper: Some(t.v1.clone()),
+        };
+        let leaves =
+            verify_range::<Blake3Hasher>(&first, &[0; 32], Some(&key(0b0100_0000)), t.root)
+                .unwrap();
+        assert_eq!(leaves, vec![leaf(0)]);
+
+        // [0x40, end) contains v1 and v3.
+        let second = RangeProof {
+            start: t.v1.clone(),
+            leaves: vec![t.v1.clone(), t.v3.clone()],
+            upper: None,
+        };
+        let leaves =
+            verify_range::<Blake3Hasher>(&second, &key(0b0100_0000), None, t.root).unwrap();
+        assert_eq!(leaves, vec![leaf(0b0100_0000), leaf(0b1000_0000)]);
+    }
+
+    #[test]
+    fn empty_range() {
+        let t = trie();
+        // [0x60, 0x80) starts in the terminator next to v1.
+        let start = PathProof {
+            terminal: PathProofTerminal::Terminator(TriePosition::from_path_and_depth(
+                key(0b0110_0000),
+                3,
+            )),
+            siblings: vec![
+                t.v0.siblings[0],
+                t.v1.siblings[1],
+                Blake3Hasher::hash_leaf(&leaf(0b0100_0000)),
+            ],
+        };
+        let proof = RangeProof {
+            start,
+            leaves: vec![],
+            upper: Some(t.v3.clone()),
+        };
+        let leaves = verify_range::<Blake3Hasher>(
+            &proof,
+            &key(0b0110_0000),
+            Some(&key(0b1000_0000)),
+            t.root,
+        )
+        .unwrap();
+        assert!(leaves.is_empty());
+    }
+
+    #[test]
+    fn missing_leaf_detected() {
+        let t = trie();
+        let proof = RangeProof {
+            start: t.v0.clone(),
+            leaves: vec![t.v0.clone(), t.v3.clone()],
+            upper: None,
+        };
+        assert_eq!(
+            verify_range::<Blake3Hasher>(&proof, &[0; 32], None, t.root),
+            Err(RangeProofVerificationError::Gap),
+        );
+
+        // Omitting the last leaf without an upper bound is detected as well.
+        let proof = RangeProof {
+            start: t.v0.clone(),
+            leaves: vec![t.v0, t.v1],
+            upper: None,
+        };
+        assert_eq!(
+            verify_range::<Blake3Hasher>(&proof, &[0; 32], None, t.root),
+            Err(RangeProofVerificationError::Gap),
+        );
+    }
+
+    #[test]
+    fn empty_trie() {
+        let proof = RangeProof {
+            start: PathProof {
+                terminal: PathProofTerminal::Terminator(TriePosition::new()),
+                siblings: vec![],
+            },
+            leaves: vec![],
+            upper: None,
+        };
+        let leaves = verify_range::<Blake3Hasher>(&proof, &[7; 32], None, TERMINATOR).unwrap();
+        assert!(leaves.is_empty());
+    }
+}
@@ -0,0 +1,234 @@
+//! Proving keys of a trie sharded across several instances.
+//!
+//! A sharded trie splits the key space by the first `shard_bits` bits of the key path into
+//! `2^shard_bits` shards, each an independent trie with its own root. The shard roots are the
+//! leaves of a small complete binary tree of fixed depth `shard_bits`, hashed with
+//! [`NodeHasher::hash_internal`], whose root is the root of the sharded trie. Shard `i` is at the
+//! position given by the first `shard_bits` bits of its keys, so the left-most shard holds the
+//! keys starting with `0` bits.
+//!
+//! Unlike the trie itself, the tree of shard roots is never compacted: empty shards contribute a
+//! [`TERMINATOR`](crate::trie::TERMINATOR) at their position. With a single shard, the root of the
+//! sharded trie is the root of the shard.
+//!
+//! A [`ShardedPathProof`] is the [`PathProof`] of a key within its shard, along with the root of
+//! the shard and the siblings on the path from the shard root to the root of the sharded trie.
+
+use crate::hasher::NodeHasher;
+use crate::proof::path_proof::{
+    hash_path, PathProof, PathProofVerificationError, VerifiedPathProof,
+};
+use crate::trie::{InternalData, KeyPath, Node};
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use bitvec::prelude::*;
+
+/// The maximum number of bits of the key path used to select a shard.
+pub const MAX_SHARD_BITS: usize = 16;
+
+/// Get the index of the shard holding the key, given the number of bits used to select a shard.
+///
+/// # Panics
+///
+/// Panics if `shard_bits` exceeds [`MAX_SHARD_BITS`].
+pub fn shard_index(key_path: &KeyPath, shard_bits: usize) -> usize {
+    assert!(shard_bits <= MAX_SHARD_BITS);
+    if shard_bits == 0 {
+        return 0;
+    }
+    key_path.view_bits::<Msb0>()[..shard_bits].load_be::<usize>()
+}
+
+/// Combine the roots of all shards, in shard order, into the root of the sharded trie.
+///
+/// # Panics
+///
+/// Panics if the number of roots is not a power of two of at most `2^MAX_SHARD_BITS`.
+pub fn combine_shard_roots<H: NodeHasher>(shard_roots: &[Node]) -> Node {
+    let mut level = check_shard_count(shard_roots);
+    while level.len() > 1 {
+        level = hash_level::<H>(&level);
+    }
+    level[0]
+}
+
+/// Get the siblings on the path from the root of the given shard to the root of the sharded trie,
+/// starting from the root of the sharded trie.
+///
+/// # Panics
+///
+/// Panics if the number of roots is not a power of two of at most `2^MAX_SHARD_BITS`, or if the
+/// shard is out of range.
+pub fn shard_siblings<H: NodeHasher>(shard_roots: &[Node], shard: usize) -> Vec<Node> {
+    let mut level = check_shard_count(shard_roots);
+    assert!(shard < level.len());
+
+    let mut siblings = Vec::new();
+    let mut index = shard;
+    while level.len() > 1 {
+        siblings.push(level[index ^ 1]);
+        level = hash_level::<H>(&level);
+        index /= 2;
+    }
+    siblings.reverse();
+    siblings
+}
+
+fn check_shard_count(shard_roots: &[Node]) -> Vec<Node> {
+    assert!(shard_roots.len().is_power_of_two());
+    assert!(shard_roots.len() <= 1 << MAX_SHARD_BITS);
+    shard_roots.to_vec()
+}
+
+fn hash_level<H: NodeHasher>(level: &[Node]) -> Vec<Node> {
+    level
+        .chunks(2)
+        .map(|pair| {
+            H::hash_internal(&InternalData {
+                left: pair[0],
+                right: pair[1],
+            })
+        })
+        .collect()
+}
+
+/// A proof of a key in a sharded trie.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshDeserialize, borsh::BorshSerialize)
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ShardedPathProof {
+    /// The siblings on the path from the shard root to the root of the sharded trie, starting from
+    /// the root of the sharded trie. There is one sibling for each bit used to select a shard.
+    pub shard_siblings: Vec<Node>,
+    /// The root of the shard holding the key.
+    pub shard_root: Node,
+    /// The proof of the key within its shard.
+    pub proof: PathProof,
+}
+
+/// Errors in sharded path proof verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShardedPathProofVerificationError {
+    /// More siblings than [`MAX_SHARD_BITS`] were provided for the shard root.
+    TooManyShardSiblings,
+    /// The shard root did not hash up to the root of the sharded trie.
+    ShardRootMismatch,
+    /// The proof of the key did not verify against the shard root.
+    Shard(PathProofVerificationError),
+}
+
+impl ShardedPathProof {
+    /// Verify the proof of the key against the root of the sharded trie.
+    ///
+    /// On success, returns the path proof verified against the shard root.
+    pub fn verify<H: NodeHasher>(
+        &self,
+        key_path: &KeyPath,
+        root: Node,
+    ) -> Result<VerifiedPathProof, ShardedPathProofVerificationError> {
+        let shard_bits = self.shard_siblings.len();
+        if shard_bits > MAX_SHARD_BITS {
+            return Err(ShardedPathProofVerificationError::TooManyShardSiblings);
+        }
+
+        let path = &key_path.view_bits::<Msb0>()[..shard_bits];
+        let combined_root = hash_path::<H>(
+            self.shard_root,
+            path,
+            self.shard_siblings.iter().rev().cloned(),
+        );
+        if combined_root != root {
+            return Err(ShardedPathProofVerificationError::ShardRootMismatch);
+        }
+
+        self.proof
+            .verify::<H>(key_path.view_bits::<Msb0>(), self.shard_root)
+            .map_err(ShardedPathProofVerificationError::Shard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        combine_shard_roots, shard_index, shard_siblings, ShardedPathProof,
+        ShardedPathProofVerificationError,
+    };
+    use crate::{
+        hasher::{Blake3Hasher, NodeHasher},
+        proof::{PathProof, PathProofTerminal},
+        trie::{InternalData, LeafData, TERMINATOR},
+    };
+
+    fn key(first_byte: u8) -> [u8; 32] {
+        let mut key = [0; 32];
+        key[0] = first_byte;
+        key
+    }
+
+    #[test]
+    fn shard_index_uses_leading_bits() {
+        assert_eq!(shard_index(&key(0b1011_0000), 0), 0);
+        assert_eq!(shard_index(&key(0b1011_0000), 1), 1);
+        assert_eq!(shard_index(&key(0b1011_0000), 3), 0b101);
+        assert_eq!(shard_index(&[0xff; 32], 12), 0xfff);
+    }
+
+    #[test]
+    fn combined_root_matches_tree() {
+        let roots = [[1; 32], [2; 32], TERMINATOR, [4; 32]];
+        let internal = |left, right| Blake3Hasher::hash_internal(&InternalData { left, right });
+        let expected = internal(internal(roots[0], roots[1]), internal(roots[2], roots[3]));
+        assert_eq!(combine_shard_roots::<Blake3Hasher>(&roots), expected);
+        assert_eq!(combine_shard_roots::<Blake3Hasher>(&roots[..1]), roots[0]);
+
+        assert_eq!(
+            shard_siblings::<Blake3Hasher>(&roots, 2),
+            vec![internal(roots[0], roots[1]), roots[3]]
+        );
+        assert!(shard_siblings::<Blake3Hasher>(&roots[..1], 0).is_empty());
+    }
+
+    #[test]
+    fn sharded_proof_verifies() {
+        // Shard 2 of 4 holds a single leaf, which is its root.
+        let leaf = LeafData {
+            key_path: key(0b1000_0000),
+            value_hash: [9; 32],
+        };
+        let shard_root = Blake3Hasher::hash_leaf(&leaf);
+        let roots = [[1; 32], [2; 32], shard_root, TERMINATOR];
+        let root = combine_shard_roots::<Blake3Hasher>(&roots);
+
+        let mut proof = ShardedPathProof {
+            shard_siblings: shard_siblings::<Blake3Hasher>(&roots, 2),
+            shard_root,
+            proof: PathProof {
+                terminal: PathProofTerminal::Leaf(leaf.clone()),
+                siblings: Vec::new(),
+            },
+        };
+        let verified = proof.verify::<Blake3Hasher>(&leaf.key_path, root).unwrap();
+        assert!(verified.confirm_value(&leaf).unwrap());
+
+        // The key must be routed to the shard the proof is for.
+        assert_eq!(
+            proof
+                .verify::<Blake3Hasher>(&key(0b0100_0000), root)
+                .unwrap_err(),
+            ShardedPathProofVerificationError::ShardRootMismatch
+        );
+
+        proof.shard_root = [3; 32];
+        assert_eq!(
+            proof
+                .verify::<Blake3Hasher>(&leaf.key_path, root)
+                .unwrap_err(),
+            ShardedPathProofVerificationError::ShardRootMismatch
+        );
+    }
+}
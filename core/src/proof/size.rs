@@ -0,0 +1,237 @@
+//! Estimates of the sizes of proofs and witnesses, e.g. to derive gas schedules.
+//!
+//! Sizes are given in bytes of the stable encodings: [`crate::witness::Witness::encode_to`] for
+//! witnesses and their paths, and [`crate::proof::MultiProof::encode`] for multi-proofs.
+//!
+//! The worst cases hold for any trie, given the depth of the paths. Tries of uniformly distributed
+//! keys, which NOMT's are as keys are hashes, are far shallower than the maximum depth; see
+//! [`expected_depth`] for the depth to assume there.
+
+/// The size of a node, in bytes.
+pub const NODE_SIZE: usize = 32;
+
+/// The size of the data of a leaf, its key path and value hash, in bytes.
+pub const LEAF_DATA_SIZE: usize = 64;
+
+/// The maximum depth of a path: the length of a key path, in bits.
+pub const MAX_PATH_DEPTH: usize = 256;
+
+/// The depth to assume for the paths of a trie of `num_keys` uniformly distributed keys.
+///
+/// The average depth of a leaf in such a trie is about `log2(num_keys) + 1.33`. This rounds it
+/// up, and is within two of the depth of most leaves.
+pub fn expected_depth(num_keys: u64) -> usize {
+    match num_keys {
+        0 | 1 => 0,
+        n => (n.ilog2() as usize + 2).min(MAX_PATH_DEPTH),
+    }
+}
+
+/// The size of a path proof of a leaf at `depth` in a witness, at most.
+///
+/// This is the position of the path, the leaf and one sibling per level. The proof of a key
+/// without value ends at a terminator instead of a leaf, and is smaller.
+pub fn path_proof_size(depth: usize) -> usize {
+    let depth = depth.min(MAX_PATH_DEPTH);
+    position_size(depth) + 1 + LEAF_DATA_SIZE + varint_size(depth) + depth * NODE_SIZE
+}
+
+/// The largest number of siblings in a multi-proof of `num_paths` paths of at most `depth`.
+///
+/// Paths share their upper siblings, and no sibling is needed where two paths part, so this is
+/// less than one sibling per level and path.
+pub fn multi_proof_siblings(num_paths: usize, depth: usize) -> usize {
+    let depth = depth.min(MAX_PATH_DEPTH);
+    if num_paths == 0 {
+        return 0;
+    }
+    // With `a_j` nodes of the paths at depth `j`, the siblings at depth `j` are
+    // `2 * a_(j-1) - a_j`. This sums up to `2 + sum(a_j for 0 < j < depth) - num_paths`, largest
+    // when the paths part as early as possible.
+    let nodes = (1..depth)
+        .map(|j| match j {
+            j if j < usize::BITS as usize => num_paths.min(1 << j),
+            _ => num_paths,
+        })
+        .sum::<usize>();
+    (2 + nodes).saturating_sub(num_paths)
+}
+
+/// The size of a multi-proof of `num_paths` paths of at most `depth`, at most.
+///
+/// See [`multi_proof_siblings`]. All paths are taken to end at leaves sharing no key prefix, and
+/// no sibling to be a terminator.
+pub fn multi_proof_size(num_paths: usize, depth: usize) -> usize {
+    let depth = depth.min(MAX_PATH_DEPTH);
+    let siblings = multi_proof_siblings(num_paths, depth);
+    let path = varint_size(depth) + 2 + LEAF_DATA_SIZE;
+    varint_size(num_paths)
+        + num_paths * path
+        + varint_size(siblings)
+        + siblings.div_ceil(8)
+        + siblings * NODE_SIZE
+}
+
+/// The expected size of a multi-proof of `num_paths` keys out of a trie of `num_keys` uniformly
+/// distributed keys.
+///
+/// This is the worst case at the [`expected_depth`], which overestimates proofs of keys sharing
+/// paths with others.
+pub fn expected_multi_proof_size(num_paths: usize, num_keys: u64) -> usize {
+    multi_proof_size(num_paths, expected_depth(num_keys))
+}
+
+/// The size of a witness of `reads` reads and `writes` writes on paths of at most `depth`, at
+/// most.
+///
+/// Each operation is taken to be on a path of its own. Neither subtrees are excluded, nor values
+/// embedded; see [`witness_values_size`] for the latter.
+pub fn witness_size(reads: usize, writes: usize, depth: usize) -> usize {
+    let paths = reads + writes;
+    let mut size = WITNESS_HEADER_SIZE + 1;
+    if paths > 0 {
+        size += section_size(varint_size(paths) + paths * path_proof_size(depth));
+    }
+    for ops in [reads, writes] {
+        if ops > 0 {
+            let op = NODE_SIZE + 1 + NODE_SIZE + varint_size(paths);
+            size += section_size(varint_size(ops) + ops * op);
+        }
+    }
+    size
+}
+
+/// The expected size of a witness of `reads` reads and `writes` writes in a trie of `num_keys`
+/// uniformly distributed keys.
+///
+/// This is the worst case at the [`expected_depth`]. Witnesses don't share siblings between
+/// paths, so this is close for operations on distinct keys.
+pub fn expected_witness_size(reads: usize, writes: usize, num_keys: u64) -> usize {
+    witness_size(reads, writes, expected_depth(num_keys))
+}
+
+/// The size added to a witness by embedding `count` values of `total_len` bytes altogether, at
+/// most.
+pub fn witness_values_size(count: usize, total_len: usize) -> usize {
+    if count == 0 {
+        return 0;
+    }
+    let value = NODE_SIZE + 1 + varint_size(total_len);
+    section_size(varint_size(count) + count * value + total_len)
+}
+
+// The magic bytes and version opening a witness.
+const WITNESS_HEADER_SIZE: usize = 6;
+
+// A section of the witness encoding, with its tag and length.
+fn section_size(body: usize) -> usize {
+    1 + varint_size(body) + body
+}
+
+// The position of a path in the witness encoding.
+fn position_size(depth: usize) -> usize {
+    varint_size(depth) + depth.div_ceil(8)
+}
+
+// The size of a LEB128 varint.
+fn varint_size(n: usize) -> usize {
+    (usize::BITS - n.leading_zeros()).div_ceil(7).max(1) as usize
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{
+        proof::{MultiProof, PathProof, PathProofTerminal},
+        trie::LeafData,
+        trie_pos::TriePosition,
+        witness::{Witness, WitnessedOperations, WitnessedPath, WitnessedRead, WitnessedWrite},
+    };
+    use bitvec::prelude::*;
+
+    fn leaf_path(key: u8, depth: usize) -> PathProof {
+        PathProof {
+            terminal: PathProofTerminal::Leaf(LeafData {
+                key_path: [key; 32],
+                value_hash: [1; 32],
+            }),
+            siblings: vec![[2; 32]; depth],
+        }
+    }
+
+    #[test]
+    fn expected_depth_rounds_up() {
+        assert_eq!(expected_depth(0), 0);
+        assert_eq!(expected_depth(1), 0);
+        assert_eq!(expected_depth(2), 3);
+        assert_eq!(expected_depth(1 << 30), 32);
+        assert_eq!(expected_depth(u64::MAX), 65);
+    }
+
+    #[test]
+    fn varint_sizes() {
+        assert_eq!(varint_size(0), 1);
+        assert_eq!(varint_size(127), 1);
+        assert_eq!(varint_size(128), 2);
+        assert_eq!(varint_size(usize::MAX), 10);
+    }
+
+    #[test]
+    fn multi_proof_siblings_bound() {
+        assert_eq!(multi_proof_siblings(0, 10), 0);
+        assert_eq!(multi_proof_siblings(1, 10), 10);
+        // Two paths parting at the root.
+        assert_eq!(multi_proof_siblings(2, 10), 18);
+        // All paths of a complete subtree need no siblings below it.
+        assert_eq!(multi_proof_siblings(8, 3), 0);
+        assert_eq!(multi_proof_siblings(8, 5), 16);
+        assert_eq!(multi_proof_siblings(1, 1000), MAX_PATH_DEPTH);
+    }
+
+    #[test]
+    fn multi_proof_size_bounds_encoding() {
+        // Two keys parting at the root, with all the siblings of their paths.
+        let depth = 20;
+        let proof =
+            MultiProof::from_path_proofs(vec![leaf_path(0x00, depth), leaf_path(0x80, depth)]);
+        assert_eq!(proof.siblings.len(), multi_proof_siblings(2, depth));
+        assert_eq!(proof.encode().len(), multi_proof_size(2, depth));
+    }
+
+    #[test]
+    fn witness_size_bounds_encoding() {
+        let depth = 200;
+        let path = |key: u8| {
+            let mut position = TriePosition::new();
+            for bit in [key; 32].view_bits::<Msb0>()[..depth].iter() {
+                position.down(*bit);
+            }
+            WitnessedPath {
+                inner: leaf_path(key, depth),
+                path: position,
+            }
+        };
+        let witness = Witness {
+            path_proofs: vec![path(0x00), path(0x80)],
+            operations: WitnessedOperations {
+                reads: vec![WitnessedRead {
+                    key: [0x00; 32],
+                    value: Some([1; 32]),
+                    path_index: 0,
+                }],
+                writes: vec![WitnessedWrite {
+                    key: [0x80; 32],
+                    value: Some([4; 32]),
+                    path_index: 1,
+                }],
+            },
+            excluded: Vec::new(),
+            binding: None,
+            values: Vec::new(),
+        };
+        let mut encoded = Vec::new();
+        witness.encode_to(&mut encoded).unwrap();
+        assert_eq!(encoded.len(), witness_size(1, 1, depth));
+        assert_eq!(witness_size(0, 0, depth), 7);
+    }
+}
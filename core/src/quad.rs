@@ -0,0 +1,417 @@
+//! An experimental 4-ary trie, for comparing proof sizes and hashing costs with the binary trie.
+//!
+//! The 4-ary trie branches on two bits of the key path at a time. Like the binary trie, it is
+//! compact: a leaf sits at the shallowest level where no other key shares its prefix, and empty
+//! subtries are [`TERMINATOR`]s. An internal node hashes its four children, so a path carries
+//! three siblings per level, over half as many levels.
+//!
+//! The database stores the binary trie. A [`QuadTrie`] is built in memory from a set of leaves,
+//! such as all the values of a database. A witness is the same structure with the subtries no
+//! key of interest leads into replaced by their hashes: it is verified by hashing it up to the
+//! root, and updated in place to compute the new root.
+//!
+//! This is a prototype for gathering data. The commitment it produces is not stable.
+
+use crate::hasher::{BinaryHash, BinaryHasher, FieldElement, FieldHash, FieldHasher, NodeHasher};
+use crate::trie::{KeyPath, LeafData, Node, NodeKind, ValueHash, TERMINATOR};
+
+use bitvec::prelude::*;
+
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, vec::Vec};
+
+/// A node hasher which can also hash the internal nodes of a [`QuadTrie`].
+pub trait QuadHasher: NodeHasher {
+    /// Hash an internal node with four children. This should domain-separate the hash as an
+    /// internal node, like [`NodeHasher::hash_internal`].
+    fn hash_quad(children: &[Node; 4]) -> Node;
+}
+
+impl<H: BinaryHash> QuadHasher for BinaryHasher<H> {
+    fn hash_quad(children: &[Node; 4]) -> Node {
+        let mut preimage = [0u8; 128];
+        for (chunk, child) in preimage.chunks_exact_mut(32).zip(children) {
+            chunk.copy_from_slice(child);
+        }
+        let mut h = H::hash(&preimage);
+        crate::hasher::unset_msb(&mut h);
+        h
+    }
+}
+
+impl<H: FieldHash> QuadHasher for FieldHasher<H> {
+    fn hash_quad(children: &[Node; 4]) -> Node {
+        // A sponge absorbing two children at a time, with a capacity distinct from those of
+        // leaves and values.
+        let [Some(a), Some(b), Some(c), Some(d)] =
+            children.map(|child| Self::node_to_element(&child))
+        else {
+            return crate::hasher::NON_CANONICAL;
+        };
+        let mut state = [a, b, FieldElement::from(1) << 250];
+        H::permute(&mut state);
+        state[0] = state[0].add_mod(c, H::MODULUS);
+        state[1] = state[1].add_mod(d, H::MODULUS);
+        H::permute(&mut state);
+        Self::from_element(state[0])
+    }
+}
+
+/// A node of a [`QuadTrie`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshDeserialize, borsh::BorshSerialize)
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum QuadNode {
+    /// An empty subtrie.
+    Terminator,
+    /// The only key in the subtrie.
+    Leaf(LeafData),
+    /// A subtrie holding at least two keys, with the children for each value of the next two bits.
+    Internal(Box<[QuadNode; 4]>),
+    /// A subtrie left out of a witness, given by its hash.
+    Opaque(Node),
+}
+
+/// An error of reading or updating a [`QuadTrie`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuadTrieError {
+    /// The path to the key leads into a subtrie left out of the witness.
+    NotCovered(KeyPath),
+}
+
+/// The number of nodes of each kind in a [`QuadTrie`].
+///
+/// The number of internal nodes is the number of hashes of four children needed to compute the
+/// root. A witness carries 32 bytes per opaque node and 64 bytes per leaf.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QuadTrieStats {
+    /// The number of leaves.
+    pub leaves: usize,
+    /// The number of internal nodes.
+    pub internal_nodes: usize,
+    /// The number of subtries left out.
+    pub opaque_nodes: usize,
+    /// The depth of the deepest node, in levels of two bits.
+    pub depth: usize,
+}
+
+/// A 4-ary trie, or a witness of some of its keys. See the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshDeserialize, borsh::BorshSerialize)
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QuadTrie {
+    /// The root node.
+    pub root: QuadNode,
+}
+
+impl QuadTrie {
+    /// Build the trie of the given leaves. Of leaves with the same key, the last one is kept.
+    pub fn build(leaves: impl IntoIterator<Item = LeafData>) -> Self {
+        let mut leaves = leaves.into_iter().collect::<Vec<_>>();
+        // A stable sort keeps leaves with the same key in order, so the last one can be kept.
+        leaves.sort_by_key(|leaf| leaf.key_path);
+        leaves.reverse();
+        leaves.dedup_by_key(|leaf| leaf.key_path);
+        leaves.reverse();
+        QuadTrie {
+            root: build(&leaves, 0),
+        }
+    }
+
+    /// Compute the root of the trie.
+    pub fn root<H: QuadHasher>(&self) -> Node {
+        self.root.hash::<H>()
+    }
+
+    /// Count the nodes of the trie.
+    pub fn stats(&self) -> QuadTrieStats {
+        let mut stats = QuadTrieStats::default();
+        self.root.count(0, &mut stats);
+        stats
+    }
+
+    /// Create a witness of the given keys, for reading or writing them.
+    ///
+    /// The witness holds the nodes along the paths to the keys and the hashes of the subtries
+    /// next to them. It has the same root as this trie.
+    pub fn witness<H: QuadHasher>(&self, keys: impl IntoIterator<Item = KeyPath>) -> Self {
+        let mut keys = keys.into_iter().collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys.dedup();
+        QuadTrie {
+            root: self.root.prune::<H>(&keys, 0),
+        }
+    }
+
+    /// Get the value hash stored under the key, if any.
+    pub fn get(&self, key: &KeyPath) -> Result<Option<ValueHash>, QuadTrieError> {
+        let mut node = &self.root;
+        let mut level = 0;
+        loop {
+            match node {
+                QuadNode::Terminator => return Ok(None),
+                QuadNode::Leaf(leaf) => {
+                    return Ok((&leaf.key_path == key).then_some(leaf.value_hash))
+                }
+                QuadNode::Internal(children) => {
+                    node = &children[digit(key, level)];
+                    level += 1;
+                }
+                QuadNode::Opaque(_) => return Err(QuadTrieError::NotCovered(*key)),
+            }
+        }
+    }
+
+    /// Apply writes to the trie. `None` deletes the key.
+    ///
+    /// On error, the writes before the failing one have been applied.
+    pub fn update<H: QuadHasher>(
+        &mut self,
+        writes: impl IntoIterator<Item = (KeyPath, Option<ValueHash>)>,
+    ) -> Result<(), QuadTrieError> {
+        for (key, value) in writes {
+            self.root.update::<H>(0, key, value)?;
+        }
+        Ok(())
+    }
+}
+
+// The two bits of the key path which select the child at the given level.
+fn digit(key: &KeyPath, level: usize) -> usize {
+    key.view_bits::<Msb0>()[level * 2..level * 2 + 2].load_be::<usize>()
+}
+
+fn build(leaves: &[LeafData], level: usize) -> QuadNode {
+    match leaves {
+        [] => QuadNode::Terminator,
+        [leaf] => QuadNode::Leaf(leaf.clone()),
+        _ => {
+            let mut rest = leaves;
+            let children = core::array::from_fn(|d| {
+                let end = rest.partition_point(|leaf| digit(&leaf.key_path, level) == d);
+                let (these, others) = rest.split_at(end);
+                rest = others;
+                build(these, level + 1)
+            });
+            QuadNode::Internal(Box::new(children))
+        }
+    }
+}
+
+// An internal node over two leaves with different keys, which share a prefix down to the level.
+fn split(a: LeafData, b: LeafData, level: usize) -> QuadNode {
+    let (da, db) = (digit(&a.key_path, level), digit(&b.key_path, level));
+    let mut children: [QuadNode; 4] = core::array::from_fn(|_| QuadNode::Terminator);
+    if da == db {
+        children[da] = split(a, b, level + 1);
+    } else {
+        children[da] = QuadNode::Leaf(a);
+        children[db] = QuadNode::Leaf(b);
+    }
+    QuadNode::Internal(Box::new(children))
+}
+
+impl QuadNode {
+    fn hash<H: QuadHasher>(&self) -> Node {
+        match self {
+            QuadNode::Terminator => TERMINATOR,
+            QuadNode::Leaf(leaf) => H::hash_leaf(leaf),
+            QuadNode::Internal(children) => H::hash_quad(&children.each_ref().map(Self::hash::<H>)),
+            QuadNode::Opaque(node) => *node,
+        }
+    }
+
+    fn kind<H: QuadHasher>(&self) -> NodeKind {
+        match self {
+            QuadNode::Terminator => NodeKind::Terminator,
+            QuadNode::Leaf(_) => NodeKind::Leaf,
+            QuadNode::Internal(_) => NodeKind::Internal,
+            QuadNode::Opaque(node) => H::node_kind(node),
+        }
+    }
+
+    fn count(&self, level: usize, stats: &mut QuadTrieStats) {
+        stats.depth = stats.depth.max(level);
+        match self {
+            QuadNode::Terminator => {}
+            QuadNode::Leaf(_) => stats.leaves += 1,
+            QuadNode::Opaque(_) => stats.opaque_nodes += 1,
+            QuadNode::Internal(children) => {
+                stats.internal_nodes += 1;
+                for child in children.iter() {
+                    child.count(level + 1, stats);
+                }
+            }
+        }
+    }
+
+    // Keep the nodes along the paths to the sorted keys, and replace the rest by their hashes.
+    fn prune<H: QuadHasher>(&self, keys: &[KeyPath], level: usize) -> QuadNode {
+        match self {
+            QuadNode::Internal(_) if keys.is_empty() => QuadNode::Opaque(self.hash::<H>()),
+            QuadNode::Internal(children) => {
+                let mut rest = keys;
+                let pruned = core::array::from_fn(|d| {
+                    let end = rest.partition_point(|key| digit(key, level) == d);
+                    let (these, others) = rest.split_at(end);
+                    rest = others;
+                    children[d].prune::<H>(these, level + 1)
+                });
+                QuadNode::Internal(Box::new(pruned))
+            }
+            QuadNode::Leaf(_) if keys.is_empty() => QuadNode::Opaque(self.hash::<H>()),
+            node => node.clone(),
+        }
+    }
+
+    fn update<H: QuadHasher>(
+        &mut self,
+        level: usize,
+        key: KeyPath,
+        value: Option<ValueHash>,
+    ) -> Result<(), QuadTrieError> {
+        match (&mut *self, value) {
+            (QuadNode::Terminator, None) => {}
+            (QuadNode::Terminator, Some(value_hash)) => {
+                *self = QuadNode::Leaf(LeafData {
+                    key_path: key,
+                    value_hash,
+                })
+            }
+            (QuadNode::Leaf(leaf), value) if leaf.key_path == key => match value {
+                Some(value_hash) => leaf.value_hash = value_hash,
+                None => *self = QuadNode::Terminator,
+            },
+            (QuadNode::Leaf(_), None) => {}
+            (QuadNode::Leaf(leaf), Some(value_hash)) => {
+                let new_leaf = LeafData {
+                    key_path: key,
+                    value_hash,
+                };
+                *self = split(leaf.clone(), new_leaf, level);
+            }
+            (QuadNode::Internal(children), value) => {
+                children[digit(&key, level)].update::<H>(level + 1, key, value)?;
+
+                // A subtrie left with a single leaf collapses into it. Leaves hash the same at any
+                // level, so this holds for leaves left out of a witness as well.
+                let mut non_empty = children
+                    .iter()
+                    .filter(|child| child.kind::<H>() != NodeKind::Terminator);
+                match (non_empty.next(), non_empty.next()) {
+                    (None, _) => *self = QuadNode::Terminator,
+                    (Some(only), None) if only.kind::<H>() == NodeKind::Leaf => {
+                        *self = only.clone()
+                    }
+                    _ => {}
+                }
+            }
+            (QuadNode::Opaque(_), _) => return Err(QuadTrieError::NotCovered(key)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QuadTrie, QuadTrieError, QuadTrieStats};
+    use crate::{
+        hasher::{Blake3Hasher, NodeHasher},
+        trie::{KeyPath, LeafData, TERMINATOR},
+    };
+
+    fn leaf(i: u32, v: u8) -> LeafData {
+        LeafData {
+            key_path: *blake3::hash(&i.to_le_bytes()).as_bytes(),
+            value_hash: [v; 32],
+        }
+    }
+
+    #[test]
+    fn empty_and_single_leaf() {
+        assert_eq!(QuadTrie::build([]).root::<Blake3Hasher>(), TERMINATOR);
+        assert_eq!(
+            QuadTrie::build([leaf(0, 1)]).root::<Blake3Hasher>(),
+            Blake3Hasher::hash_leaf(&leaf(0, 1))
+        );
+    }
+
+    #[test]
+    fn witness_updates_match_rebuilt_trie() {
+        let leaves = (0..500).map(|i| leaf(i, 1)).collect::<Vec<_>>();
+        let trie = QuadTrie::build(leaves.clone());
+        let stats = trie.stats();
+        assert_eq!(stats.leaves, 500);
+        assert_eq!(stats.opaque_nodes, 0);
+
+        // Delete every third of the first 60 keys, overwrite the others, and insert 20 new keys.
+        let writes = (0..60)
+            .map(|i| {
+                let value = (i % 3 != 0).then_some([2; 32]);
+                (leaf(i, 0).key_path, value)
+            })
+            .chain((1000..1020).map(|i| (leaf(i, 0).key_path, Some([3; 32]))))
+            .collect::<Vec<(KeyPath, _)>>();
+
+        let mut witness =
+            trie.witness::<Blake3Hasher>(writes.iter().map(|(key, _)| *key).chain([[0; 32]]));
+        assert_eq!(witness.root::<Blake3Hasher>(), trie.root::<Blake3Hasher>());
+        assert_eq!(witness.get(&leaf(3, 0).key_path), Ok(Some([1; 32])));
+        assert_eq!(witness.get(&leaf(1000, 0).key_path), Ok(None));
+        assert_eq!(witness.get(&[0; 32]), Ok(None));
+        assert!(matches!(
+            witness.get(&leaf(400, 0).key_path),
+            Err(QuadTrieError::NotCovered(_))
+        ));
+        let QuadTrieStats { opaque_nodes, .. } = witness.stats();
+        assert!(opaque_nodes > 0);
+
+        witness.update::<Blake3Hasher>(writes.clone()).unwrap();
+
+        let mut expected = leaves;
+        expected.retain(|l| !writes.iter().any(|(key, _)| *key == l.key_path));
+        expected.extend(writes.iter().filter_map(|(key, value)| {
+            value.map(|value_hash| LeafData {
+                key_path: *key,
+                value_hash,
+            })
+        }));
+        let rebuilt = QuadTrie::build(expected);
+        assert_eq!(
+            witness.root::<Blake3Hasher>(),
+            rebuilt.root::<Blake3Hasher>()
+        );
+
+        // Deleting everything but one key collapses the trie into its leaf.
+        let mut trie = rebuilt;
+        let remaining = trie.stats().leaves;
+        let mut keys = Vec::new();
+        collect_keys(&trie.root, &mut keys);
+        assert_eq!(keys.len(), remaining);
+        trie.update::<Blake3Hasher>(keys[1..].iter().map(|key| (*key, None)))
+            .unwrap();
+        assert_eq!(
+            trie.root::<Blake3Hasher>(),
+            Blake3Hasher::hash_leaf(&LeafData {
+                key_path: keys[0],
+                value_hash: trie.get(&keys[0]).unwrap().unwrap(),
+            })
+        );
+    }
+
+    fn collect_keys(node: &super::QuadNode, keys: &mut Vec<KeyPath>) {
+        match node {
+            super::QuadNode::Leaf(leaf) => keys.push(leaf.key_path),
+            super::QuadNode::Internal(children) => {
+                children.iter().for_each(|child| collect_keys(child, keys))
+            }
+            _ => {}
+        }
+    }
+}
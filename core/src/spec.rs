@@ -0,0 +1,344 @@
+//! A machine-readable description of the trie layout.
+//!
+//! Alternative implementations and auditors need the exact node encodings, hash domain separation
+//! and page layout to reproduce NOMT roots and proofs. Rather than maintaining these by hand, this
+//! module generates a JSON descriptor from the same constants and functions the rest of the crate
+//! uses, along with test vectors computed by the given hasher, so that the description cannot
+//! drift from the code.
+//!
+//! ```
+//! # #[cfg(feature = "blake3-hasher")] {
+//! use nomt_core::{hasher::Blake3Hasher, spec};
+//!
+//! let json = spec::to_json::<Blake3Hasher>("blake3");
+//! assert!(json.starts_with("{\"spec_version\":1,"));
+//! # }
+//! ```
+
+use crate::{
+    hasher::{set_msb, NodeHasher, ValueHasher},
+    page::{DEPTH, NODES_PER_PAGE},
+    page_id::{ChildPageIndex, MAX_CHILD_INDEX, MAX_PAGE_DEPTH, NUM_CHILDREN, ROOT_PAGE_ID},
+    trie::{
+        InternalData, KeyPath, LeafData, Node, NodeKind, ValueHash, DEFAULT_VALUE_HASH_LEN,
+        TERMINATOR,
+    },
+    trie_pos::TriePosition,
+    update::build_trie,
+};
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+
+/// The version of the descriptor format. This is bumped whenever the shape of the JSON changes.
+pub const SPEC_VERSION: u32 = 1;
+
+/// Generate the JSON descriptor of the trie layout, with test vectors computed by `H`.
+///
+/// `hasher` is the name recorded for the hasher in the descriptor. The output is compact and its
+/// keys are in a fixed order, so it is byte-for-byte reproducible.
+pub fn to_json<H: NodeHasher + ValueHasher>(hasher: &str) -> String {
+    let mut out = String::new();
+    out.push('{');
+    field(&mut out, "spec_version", SPEC_VERSION);
+    out.push(',');
+    key(&mut out, "hasher");
+    string(&mut out, hasher);
+    out.push(',');
+    key(&mut out, "node");
+    node(&mut out);
+    out.push(',');
+    key(&mut out, "hashing");
+    hashing(&mut out);
+    out.push(',');
+    key(&mut out, "page");
+    page(&mut out);
+    out.push(',');
+    key(&mut out, "page_id");
+    page_id(&mut out);
+    out.push(',');
+    key(&mut out, "vectors");
+    vectors::<H>(&mut out);
+    out.push('}');
+    out
+}
+
+fn node(out: &mut String) {
+    out.push('{');
+    field(out, "size_bytes", core::mem::size_of::<Node>());
+    out.push(',');
+    field(out, "key_path_bits", core::mem::size_of::<KeyPath>() * 8);
+    out.push(',');
+    field(out, "default_value_hash_bytes", DEFAULT_VALUE_HASH_LEN);
+    out.push(',');
+    key(out, "terminator");
+    hex(out, &TERMINATOR);
+    out.push('}');
+}
+
+fn hashing(out: &mut String) {
+    let mut leaf_mask = [0u8; 32];
+    set_msb(&mut leaf_mask);
+
+    out.push('{');
+    key(out, "labeling");
+    string(out, "msb");
+    out.push(',');
+    key(out, "leaf_mask");
+    hex(out, &leaf_mask);
+    out.push(',');
+    key(out, "leaf_preimage");
+    out.push_str("[\"key_path\",\"value_hash\"]");
+    out.push(',');
+    key(out, "internal_preimage");
+    out.push_str("[\"left\",\"right\"]");
+    out.push('}');
+}
+
+fn page(out: &mut String) {
+    // The index of the leftmost node of each layer of a page, computed by the same code which
+    // places nodes in pages.
+    let layer_offsets = (1..=DEPTH as u16)
+        .map(|depth| TriePosition::from_path_and_depth([0; 32], depth).node_index())
+        .collect::<Vec<_>>();
+
+    out.push('{');
+    field(out, "depth", DEPTH);
+    out.push(',');
+    field(out, "nodes_per_page", NODES_PER_PAGE);
+    out.push(',');
+    field(out, "children_per_page", NUM_CHILDREN);
+    out.push(',');
+    key(out, "layer_offsets");
+    list(out, &layer_offsets);
+    out.push('}');
+}
+
+fn page_id(out: &mut String) {
+    let paths: [&[u8]; 4] = [&[], &[0], &[MAX_CHILD_INDEX], &[5, 17, 63, 0]];
+
+    out.push('{');
+    field(out, "max_depth", MAX_PAGE_DEPTH);
+    out.push(',');
+    field(out, "max_child_index", MAX_CHILD_INDEX);
+    out.push(',');
+    key(out, "encodings");
+    out.push('[');
+    for (i, path) in paths.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let mut page_id = ROOT_PAGE_ID;
+        for &child_index in *path {
+            // UNWRAP: the paths above are valid.
+            page_id = page_id
+                .child_page_id(ChildPageIndex::new(child_index).unwrap())
+                .unwrap();
+        }
+        out.push('{');
+        key(out, "path");
+        list(out, path);
+        out.push(',');
+        key(out, "encoded");
+        hex(out, &page_id.encode());
+        out.push('}');
+    }
+    out.push(']');
+    out.push('}');
+}
+
+fn vectors<H: NodeHasher + ValueHasher>(out: &mut String) {
+    let values: [&[u8]; 3] = [b"", &[0x01], b"nomt"];
+    let value_hash: ValueHash = H::hash_value(b"nomt");
+    let leaf = LeafData {
+        key_path: [0x11; 32],
+        value_hash,
+    };
+    let leaf_hash = H::hash_leaf(&leaf);
+    let internal = [
+        InternalData {
+            left: leaf_hash,
+            right: TERMINATOR,
+        },
+        InternalData {
+            left: TERMINATOR,
+            right: leaf_hash,
+        },
+    ];
+    let tries: [&[KeyPath]; 3] = [&[], &[[0x11; 32]], &[[0x00; 32], [0x11; 32], [0x80; 32]]];
+
+    out.push('{');
+    key(out, "values");
+    out.push('[');
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push('{');
+        key(out, "value");
+        hex(out, value);
+        out.push(',');
+        key(out, "hash");
+        hex(out, &H::hash_value(value));
+        out.push('}');
+    }
+    out.push(']');
+    out.push(',');
+
+    key(out, "leaves");
+    out.push('[');
+    out.push('{');
+    key(out, "key_path");
+    hex(out, &leaf.key_path);
+    out.push(',');
+    key(out, "value_hash");
+    hex(out, &leaf.value_hash);
+    out.push(',');
+    key(out, "hash");
+    hex(out, &leaf_hash);
+    out.push(',');
+    key(out, "kind");
+    kind::<H>(out, &leaf_hash);
+    out.push('}');
+    out.push(']');
+    out.push(',');
+
+    key(out, "internal_nodes");
+    out.push('[');
+    for (i, data) in internal.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let hash = H::hash_internal(data);
+        out.push('{');
+        key(out, "left");
+        hex(out, &data.left);
+        out.push(',');
+        key(out, "right");
+        hex(out, &data.right);
+        out.push(',');
+        key(out, "hash");
+        hex(out, &hash);
+        out.push(',');
+        key(out, "kind");
+        kind::<H>(out, &hash);
+        out.push('}');
+    }
+    out.push(']');
+    out.push(',');
+
+    // Roots of tries where every key holds the value hash of the key path itself.
+    key(out, "roots");
+    out.push('[');
+    for (i, keys) in tries.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let ops = keys
+            .iter()
+            .map(|key_path| (*key_path, H::hash_value(key_path)));
+        let root = build_trie::<H>(0, ops, |_| {});
+        out.push('{');
+        key(out, "key_paths");
+        out.push('[');
+        for (j, key_path) in keys.iter().enumerate() {
+            if j > 0 {
+                out.push(',');
+            }
+            hex(out, key_path);
+        }
+        out.push(']');
+        out.push(',');
+        key(out, "root");
+        hex(out, &root);
+        out.push('}');
+    }
+    out.push(']');
+    out.push('}');
+}
+
+fn kind<H: NodeHasher>(out: &mut String, node: &Node) {
+    string(
+        out,
+        match NodeKind::of::<H>(node) {
+            NodeKind::Terminator => "terminator",
+            NodeKind::Leaf => "leaf",
+            NodeKind::Internal => "internal",
+        },
+    );
+}
+
+fn key(out: &mut String, key: &str) {
+    string(out, key);
+    out.push(':');
+}
+
+fn field(out: &mut String, name: &str, value: impl core::fmt::Display) {
+    key(out, name);
+    // UNWRAP: writing to a string never fails.
+    write!(out, "{value}").unwrap();
+}
+
+// Only used with keys and names which need no escaping.
+fn string(out: &mut String, s: &str) {
+    debug_assert!(!s.contains(['"', '\\']));
+    out.push('"');
+    out.push_str(s);
+    out.push('"');
+}
+
+fn list(out: &mut String, items: &[impl core::fmt::Display]) {
+    out.push('[');
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        // UNWRAP: writing to a string never fails.
+        write!(out, "{item}").unwrap();
+    }
+    out.push(']');
+}
+
+fn hex(out: &mut String, bytes: &[u8]) {
+    out.push('"');
+    for byte in bytes {
+        // UNWRAP: writing to a string never fails.
+        write!(out, "{byte:02x}").unwrap();
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_json;
+    use crate::hasher::Blake3Hasher;
+
+    #[test]
+    fn layout_matches_documentation() {
+        let json = to_json::<Blake3Hasher>("blake3");
+        assert!(json.contains("\"nodes_per_page\":126"));
+        assert!(json.contains("\"layer_offsets\":[0,2,6,14,30,62]"));
+        assert!(json.contains(&format!("\"leaf_mask\":\"80{}\"", "00".repeat(31))));
+        assert!(json.contains(&format!(
+            "{{\"path\":[],\"encoded\":\"{}\"}}",
+            "00".repeat(32)
+        )));
+    }
+
+    #[test]
+    fn output_is_deterministic() {
+        assert_eq!(
+            to_json::<Blake3Hasher>("blake3"),
+            to_json::<Blake3Hasher>("blake3")
+        );
+    }
+
+    #[test]
+    fn output_is_valid_json() {
+        let json = to_json::<Blake3Hasher>("blake3");
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["spec_version"], 1);
+        assert_eq!(value["vectors"]["leaves"][0]["kind"], "leaf");
+        assert_eq!(value["vectors"]["internal_nodes"][0]["kind"], "internal");
+        assert_eq!(value["vectors"]["roots"][0]["root"], "00".repeat(32));
+    }
+}
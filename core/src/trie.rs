@@ -11,7 +11,8 @@
 //!      and serve as a stand-in for an empty sub-trie at any height. Terminator nodes enable the
 //!      trie to be tractably represented.
 //!
-//! All node preimages are 512 bits.
+//! All internal node preimages are 512 bits. Leaf node preimages are 256 bits followed by the
+//! value hash, which is 256 bits by default. See [`ValueHash`].
 
 use crate::hasher::NodeHasher;
 
@@ -25,8 +26,16 @@ pub type Node = [u8; 32];
 /// The path to a key. All paths have a 256 bit fixed length.
 pub type KeyPath = [u8; 32];
 
-/// The hash of a value. In this schema, it is always 256 bits.
-pub type ValueHash = [u8; 32];
+/// The default width of a [`ValueHash`], in bytes.
+pub const DEFAULT_VALUE_HASH_LEN: usize = 32;
+
+/// The hash of a value, of `N` bytes.
+///
+/// Value hashes are 256 bits by default. Protocols committing to values with hashes of a different
+/// width may use them in leaves, proofs and updates by choosing `N`, so that their commitments
+/// are hashed into the trie as-is rather than padded or truncated. Nodes are always 256 bits,
+/// regardless of the width of the value hashes.
+pub type ValueHash<const N: usize = DEFAULT_VALUE_HASH_LEN> = [u8; N];
 
 /// The terminator hash is a special node hash value denoting an empty sub-tree.
 /// Concretely, when this appears at a given location in the trie,
@@ -77,19 +86,79 @@ pub struct InternalData {
     pub right: Node,
 }
 
-/// The data of a leaf node.
-#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// The data of a leaf node, carrying a value hash of `N` bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(
     feature = "borsh",
     derive(borsh::BorshDeserialize, borsh::BorshSerialize)
 )]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-pub struct LeafData {
+pub struct LeafData<const N: usize = DEFAULT_VALUE_HASH_LEN> {
     /// The total path to this value within the trie.
     ///
     /// The actual location of this node may be anywhere along this path, depending on the other
     /// data within the trie.
     pub key_path: KeyPath,
     /// The hash of the value carried in this leaf.
-    pub value_hash: ValueHash,
+    #[cfg_attr(feature = "serde", serde(with = "value_hash_serde"))]
+    pub value_hash: ValueHash<N>,
+}
+
+impl<const N: usize> Default for LeafData<N> {
+    fn default() -> Self {
+        LeafData {
+            key_path: KeyPath::default(),
+            value_hash: [0; N],
+        }
+    }
+}
+
+// serde only implements its traits for arrays of up to 32 elements. This encodes value hashes of
+// any width the same way serde encodes arrays, so that the encoding of 32-byte value hashes is
+// unchanged.
+#[cfg(feature = "serde")]
+mod value_hash_serde {
+    use core::fmt;
+    use serde::{
+        de::{Error, SeqAccess, Visitor},
+        ser::SerializeTuple,
+        Deserializer, Serializer,
+    };
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        value_hash: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(N)?;
+        for byte in value_hash {
+            tuple.serialize_element(byte)?;
+        }
+        tuple.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        struct ValueHashVisitor<const N: usize>;
+
+        impl<'de, const N: usize> Visitor<'de> for ValueHashVisitor<N> {
+            type Value = [u8; N];
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a value hash of {} bytes", N)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<[u8; N], A::Error> {
+                let mut value_hash = [0; N];
+                for (i, byte) in value_hash.iter_mut().enumerate() {
+                    *byte = seq
+                        .next_element()?
+                        .ok_or_else(|| A::Error::invalid_length(i, &self))?;
+                }
+                Ok(value_hash)
+            }
+        }
+
+        deserializer.deserialize_tuple(N, ValueHashVisitor::<N>)
+    }
 }
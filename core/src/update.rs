@@ -1,7 +1,7 @@
 //! Trie update logic helpers.
 
 use crate::hasher::NodeHasher;
-use crate::trie::{self, KeyPath, LeafData, Node, ValueHash};
+use crate::trie::{self, KeyPath, LeafData, Node, ValueHash, DEFAULT_VALUE_HASH_LEN};
 
 use bitvec::prelude::*;
 
@@ -15,10 +15,10 @@ pub(crate) fn shared_bits(a: &BitSlice<u8, Msb0>, b: &BitSlice<u8, Msb0>) -> usi
 
 /// Creates an iterator of all provided operations, with the leaf value spliced in if its key
 /// does not appear in the original ops list. Then filters out all `None`s.
-pub fn leaf_ops_spliced(
-    leaf: Option<LeafData>,
-    ops: &[(KeyPath, Option<ValueHash>)],
-) -> impl Iterator<Item = (KeyPath, ValueHash)> + Clone + '_ {
+pub fn leaf_ops_spliced<const N: usize>(
+    leaf: Option<LeafData<N>>,
+    ops: &[(KeyPath, Option<ValueHash<N>>)],
+) -> impl Iterator<Item = (KeyPath, ValueHash<N>)> + Clone + '_ {
     let splice_index = leaf
         .as_ref()
         .and_then(|leaf| ops.binary_search_by_key(&leaf.key_path, |x| x.0).err());
@@ -37,11 +37,11 @@ pub fn leaf_ops_spliced(
         .filter_map(|(k, o)| o.map(move |value| (k, value)))
 }
 
-pub enum WriteNode<'a> {
+pub enum WriteNode<'a, const N: usize = DEFAULT_VALUE_HASH_LEN> {
     Leaf {
         up: bool,
         down: &'a BitSlice<u8, Msb0>,
-        leaf_data: LeafData,
+        leaf_data: LeafData<N>,
         node: Node,
     },
     Internal {
@@ -51,7 +51,7 @@ pub enum WriteNode<'a> {
     Terminator,
 }
 
-impl<'a> WriteNode<'a> {
+impl<'a, const N: usize> WriteNode<'a, N> {
     /// Whether to move up a step before writing the node.
     pub fn up(&self) -> bool {
         match self {
@@ -94,7 +94,16 @@ impl<'a> WriteNode<'a> {
 pub fn build_trie<H: NodeHasher>(
     skip: usize,
     ops: impl IntoIterator<Item = (KeyPath, ValueHash)>,
-    mut visit: impl FnMut(WriteNode),
+    visit: impl FnMut(WriteNode),
+) -> Node {
+    build_trie_of_width::<H, DEFAULT_VALUE_HASH_LEN>(skip, ops, visit)
+}
+
+// Like `build_trie`, for leaves with value hashes of `N` bytes.
+pub fn build_trie_of_width<H: NodeHasher, const N: usize>(
+    skip: usize,
+    ops: impl IntoIterator<Item = (KeyPath, ValueHash<N>)>,
+    mut visit: impl FnMut(WriteNode<N>),
 ) -> Node {
     // we build a compact addressable sub-trie in-place based on the given set of ordered keys,
     // ignoring deletions as they are implicit in a fresh sub-trie.
@@ -261,7 +270,7 @@ mod tests {
     struct DummyNodeHasher;
 
     impl NodeHasher for DummyNodeHasher {
-        fn hash_leaf(data: &trie::LeafData) -> [u8; 32] {
+        fn hash_leaf<const N: usize>(data: &trie::LeafData<N>) -> [u8; 32] {
             let mut hasher = blake3::Hasher::new();
             hasher.update(&data.key_path);
             hasher.update(&data.value_hash);
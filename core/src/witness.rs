@@ -1,17 +1,39 @@
 //! Witnesses of NOMT sessions. These types encapsulate entire sets of reads and writes.
 
 use crate::{
-    proof::PathProof,
-    trie::{KeyPath, ValueHash},
+    hasher::{NodeHasher, ValueHasher},
+    proof::{
+        hash_path, PathProof, PathProofTerminal, PathProofVerificationError, PathUpdate,
+        SubtreeUpdate, VerifyUpdateError,
+    },
+    trie::{KeyPath, LeafData, Node, ValueHash},
     trie_pos::TriePosition,
+    update::shared_bits,
 };
 
+use bitvec::prelude::*;
+
 #[cfg(not(feature = "std"))]
-use alloc::vec::Vec;
+use alloc::{vec, vec::Vec};
 
 /// A witness that can be used to prove the correctness of state trie retrievals and updates.
 ///
 /// Expected to be serializable.
+///
+/// # Ordering
+///
+/// Witnesses produced by NOMT are in canonical order:
+///   - `path_proofs` are sorted lexicographically by their path. Since the paths lead to
+///     distinct terminals, this is also the order of the keys they cover.
+///   - `operations.reads` and `operations.writes` are each sorted by `path_index`, and by key
+///     among operations on the same path.
+///   - `excluded` is sorted by the paths of the subtree positions.
+///   - `values` is sorted by key.
+///
+/// All operations on a path are therefore contiguous, which is what [`Witness::reads_for_path`]
+/// and [`Witness::writes_for_path`] rely on. Witnesses from other sources can be brought into
+/// canonical order with [`Witness::normalize`].
+#[derive(Clone)]
 #[cfg_attr(
     feature = "borsh",
     derive(borsh::BorshDeserialize, borsh::BorshSerialize)
@@ -19,13 +41,1149 @@ use alloc::vec::Vec;
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Witness {
     /// Various paths down the trie used as part of this witness.
-    /// Note that the paths are not necessarily in lexicographic order.
     pub path_proofs: Vec<WitnessedPath>,
     /// The operations witnessed by the paths.
     pub operations: WitnessedOperations,
+    /// Subtrees excluded from the witness, e.g. because they hold private state. Operations on
+    /// their keys are not witnessed. Instead, the roots of the subtrees before and after the
+    /// operations are proven. See [`Witness::exclude_subtrees`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub excluded: Vec<SubtreeUpdate>,
+    /// The commit the witness is bound to, if any. See [`Witness::bind`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub binding: Option<WitnessBinding>,
+    /// The values of witnessed keys before the operations, if embedded in the witness. See
+    /// [`Witness::prior_value`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub values: Vec<WitnessedValue>,
+}
+
+/// Metadata binding a witness to the commit it was produced for, so that a verifier does not
+/// accept it for another one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshDeserialize, borsh::BorshSerialize)
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WitnessBinding {
+    /// The root before the witnessed operations.
+    pub prev_root: Node,
+    /// The root after the witnessed operations.
+    pub new_root: Node,
+    /// Context supplied by the producer of the witness, e.g. a chain id and block number.
+    pub context: Vec<u8>,
+}
+
+impl Witness {
+    /// The reads witnessed by the path at the given index.
+    ///
+    /// The witness must be in canonical order. Returns an empty slice for unknown paths.
+    pub fn reads_for_path(&self, path_index: usize) -> &[WitnessedRead] {
+        let reads = &self.operations.reads;
+        let start = reads.partition_point(|r| r.path_index < path_index);
+        let end = start + reads[start..].partition_point(|r| r.path_index == path_index);
+        &reads[start..end]
+    }
+
+    /// The writes witnessed by the path at the given index.
+    ///
+    /// The witness must be in canonical order. Returns an empty slice for unknown paths.
+    pub fn writes_for_path(&self, path_index: usize) -> &[WitnessedWrite] {
+        let writes = &self.operations.writes;
+        let start = writes.partition_point(|w| w.path_index < path_index);
+        let end = start + writes[start..].partition_point(|w| w.path_index == path_index);
+        &writes[start..end]
+    }
+
+    /// Whether the witness is in canonical order.
+    pub fn is_canonical(&self) -> bool {
+        self.path_proofs
+            .windows(2)
+            .all(|w| w[0].path.path() <= w[1].path.path())
+            && self
+                .operations
+                .reads
+                .windows(2)
+                .all(|w| (w[0].path_index, w[0].key) <= (w[1].path_index, w[1].key))
+            && self
+                .operations
+                .writes
+                .windows(2)
+                .all(|w| (w[0].path_index, w[0].key) <= (w[1].path_index, w[1].key))
+            && self
+                .excluded
+                .windows(2)
+                .all(|w| w[0].position.path() <= w[1].position.path())
+            && self.values.windows(2).all(|w| w[0].key <= w[1].key)
+    }
+
+    /// Bring the witness into canonical order, updating the path indices of all operations.
+    pub fn normalize(&mut self) {
+        let mut paths = core::mem::take(&mut self.path_proofs)
+            .into_iter()
+            .enumerate()
+            .collect::<Vec<_>>();
+        paths.sort_by(|(_, a), (_, b)| a.path.path().cmp(b.path.path()));
+
+        // Maps the previous index of a path to its new one.
+        let mut new_index = vec![0; paths.len()];
+        for (i, (prev, _)) in paths.iter().enumerate() {
+            new_index[*prev] = i;
+        }
+        self.path_proofs = paths.into_iter().map(|(_, path)| path).collect();
+
+        for read in &mut self.operations.reads {
+            read.path_index = new_index
+                .get(read.path_index)
+                .copied()
+                .unwrap_or(read.path_index);
+        }
+        for write in &mut self.operations.writes {
+            write.path_index = new_index
+                .get(write.path_index)
+                .copied()
+                .unwrap_or(write.path_index);
+        }
+
+        self.operations.reads.sort_by_key(|r| (r.path_index, r.key));
+        self.operations
+            .writes
+            .sort_by_key(|w| (w.path_index, w.key));
+        self.excluded
+            .sort_by(|a, b| a.position.path().cmp(b.position.path()));
+        self.values.sort_by_key(|v| v.key);
+    }
+
+    /// The value of a key before the witnessed operations, if embedded in the witness.
+    ///
+    /// `Some(None)` means the key had no value. Values are embedded for the keys read and
+    /// written when requested from NOMT, and are checked with [`Witness::verify_values`]. The
+    /// witness must be in canonical order.
+    pub fn prior_value(&self, key_path: &KeyPath) -> Option<Option<&[u8]>> {
+        self.values
+            .binary_search_by_key(key_path, |v| v.key)
+            .ok()
+            .map(|i| self.values[i].value.as_deref())
+    }
+
+    /// The value of a witnessed read, if embedded in the witness. See [`Witness::prior_value`].
+    pub fn read_value(&self, read: &WitnessedRead) -> Option<Option<&[u8]>> {
+        self.prior_value(&read.key)
+    }
+
+    /// Check that the embedded values hash to the value hashes witnessed for their keys: the
+    /// hashes of the reads, or the leaves of the paths of the writes.
+    ///
+    /// This relies on the paths and reads, which [`Witness::verify`] checks against the root.
+    /// On mismatch, returns the smallest key whose value differs or is not witnessed.
+    pub fn verify_values<H: ValueHasher>(&self) -> Result<(), KeyPath> {
+        for WitnessedValue { key, value } in &self.values {
+            let witnessed = match self.operations.reads.iter().find(|r| r.key == *key) {
+                Some(read) => read.value,
+                None => {
+                    let write = self.operations.writes.iter().find(|w| w.key == *key);
+                    let path = write.and_then(|w| self.path_proofs.get(w.path_index));
+                    match path.map(|p| &p.inner.terminal) {
+                        None => return Err(*key),
+                        Some(PathProofTerminal::Leaf(leaf)) if leaf.key_path == *key => {
+                            Some(leaf.value_hash)
+                        }
+                        Some(_) => None,
+                    }
+                }
+            };
+            if value.as_deref().map(H::hash_value) != witnessed {
+                return Err(*key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Bind the witness to the commit from `prev_root` to `new_root` in the given context.
+    ///
+    /// [`Witness::verify`] then checks the roots, and [`Witness::verify_in_context`] the context
+    /// as well, so that the witness cannot be replayed against another block.
+    pub fn bind(&mut self, prev_root: Node, new_root: Node, context: Vec<u8>) {
+        self.binding = Some(WitnessBinding {
+            prev_root,
+            new_root,
+            context,
+        });
+    }
+
+    /// Exclude the keys beginning with any of the given prefixes from the witness.
+    ///
+    /// The paths and operations of the excluded keys are removed. For each prefix with operations,
+    /// the witness instead proves the root of the subtree at the position of the prefix before
+    /// the operations, and commits to its root after them. The subtree roots commit to the
+    /// excluded state, which is left for the parties privy to it to check.
+    ///
+    /// A subtree root must exist for each prefix with operations: the paths of its keys must not
+    /// end above the prefix, as they do when few keys begin with it.
+    pub fn exclude_subtrees<H: NodeHasher>(
+        &mut self,
+        prefixes: &[TriePosition],
+    ) -> Result<(), WitnessExclusionError> {
+        // Prefixes within other prefixes are covered by those.
+        let mut prefixes = prefixes.iter().collect::<Vec<_>>();
+        prefixes.sort_by(|a, b| a.path().cmp(b.path()));
+        prefixes.dedup_by(|b, a| b.path().starts_with(a.path()));
+
+        self.normalize();
+        let mut excluded_paths = vec![false; self.path_proofs.len()];
+        for prefix in prefixes {
+            let prefix_bits = prefix.path();
+            let depth = prefix_bits.len();
+            if self.excluded.iter().any(|s| {
+                s.position.path().starts_with(prefix_bits)
+                    || prefix_bits.starts_with(s.position.path())
+            }) {
+                return Err(WitnessExclusionError::AlreadyExcluded);
+            }
+
+            let keys = self
+                .operations
+                .reads
+                .iter()
+                .map(|r| (r.key, r.path_index))
+                .chain(self.operations.writes.iter().map(|w| (w.key, w.path_index)));
+            let mut has_ops = false;
+            for (key, path_index) in keys {
+                if !key.view_bits::<Msb0>().starts_with(prefix_bits) {
+                    continue;
+                }
+                has_ops = true;
+                let path = self
+                    .path_proofs
+                    .get(path_index)
+                    .ok_or(WitnessExclusionError::UnknownPath(path_index))?;
+                if path.inner.siblings.len() < depth {
+                    return Err(WitnessExclusionError::NoSubtreeRoot(key));
+                }
+            }
+            if !has_ops {
+                continue;
+            }
+
+            // Paths below the prefix are proven against the root, which is consistent with the
+            // subtree root, so any one of them proves the latter.
+            let below = (0..self.path_proofs.len())
+                .filter(|i| {
+                    let path = &self.path_proofs[*i];
+                    path.inner.siblings.len() >= depth && path.path.path().starts_with(prefix_bits)
+                })
+                .collect::<Vec<_>>();
+            // UNWRAP: every operation under the prefix is on a path below the prefix.
+            let first = &self.path_proofs[below[0]];
+            let siblings = first.inner.siblings[..depth].to_vec();
+            let prev_root = hash_path::<H>(
+                first.inner.terminal.node::<H>(),
+                &first.path.path()[depth..],
+                first.inner.siblings[depth..].iter().rev().cloned(),
+            );
+
+            let mut updates = Vec::new();
+            for &i in &below {
+                excluded_paths[i] = true;
+                let ops = self
+                    .writes_for_path(i)
+                    .iter()
+                    .map(|w| (w.key, w.value))
+                    .collect::<Vec<_>>();
+                if ops.is_empty() {
+                    continue;
+                }
+                let WitnessedPath { inner, path } = &self.path_proofs[i];
+                let root = hash_path::<H>(
+                    inner.terminal.node::<H>(),
+                    path.path(),
+                    inner.siblings.iter().rev().cloned(),
+                );
+                // UNWRAP: the path is verified against the root it hashes up to.
+                let inner = inner.verify::<H>(path.path(), root).unwrap();
+                updates.push(PathUpdate { inner, ops });
+            }
+            let new_root = if updates.is_empty() {
+                prev_root
+            } else {
+                crate::proof::update_subtree::<H>(depth, &updates)
+                    .map_err(WitnessExclusionError::Update)?
+            };
+
+            self.excluded.push(SubtreeUpdate {
+                position: prefix.clone(),
+                siblings,
+                prev_root,
+                new_root,
+            });
+        }
+
+        // Drop the excluded paths along with their operations, and renumber the rest.
+        let mut new_index = Vec::with_capacity(self.path_proofs.len());
+        let mut next = 0;
+        for excluded in &excluded_paths {
+            new_index.push((!excluded).then_some(next));
+            next += usize::from(!excluded);
+        }
+        let mut index = 0;
+        self.path_proofs.retain(|_| {
+            index += 1;
+            !excluded_paths[index - 1]
+        });
+        let renumber = |path_index: &mut usize| match new_index.get(*path_index) {
+            Some(Some(i)) => {
+                *path_index = *i;
+                true
+            }
+            Some(None) => false,
+            None => true,
+        };
+        self.operations
+            .reads
+            .retain_mut(|r| renumber(&mut r.path_index));
+        self.operations
+            .writes
+            .retain_mut(|w| renumber(&mut w.path_index));
+        let excluded = &self.excluded;
+        self.values.retain(|v| {
+            let bits = v.key.view_bits::<Msb0>();
+            !excluded.iter().any(|s| bits.starts_with(s.position.path()))
+        });
+        self.normalize();
+        Ok(())
+    }
+
+    /// Exclude the keys for which `keep` returns false from the witness, wherever they are not on
+    /// the paths of the kept keys.
+    ///
+    /// Each excluded key is replaced by the largest subtree holding it but none of the kept keys,
+    /// as with [`Witness::exclude_subtrees`]. The witness still leads from the previous root to the
+    /// new one, taking the new roots of the subtrees as given, but its size follows the number of
+    /// kept keys rather than the number of keys accessed. Keys whose paths end above such a
+    /// subtree share a path with a kept key and stay in the witness.
+    pub fn retain_keys<H: NodeHasher>(
+        &mut self,
+        mut keep: impl FnMut(&KeyPath) -> bool,
+    ) -> Result<(), WitnessExclusionError> {
+        let mut keys = self
+            .operations
+            .reads
+            .iter()
+            .map(|r| (r.key, r.path_index))
+            .chain(self.operations.writes.iter().map(|w| (w.key, w.path_index)))
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys.dedup_by_key(|(key, _)| *key);
+        let (kept, dropped): (Vec<_>, Vec<_>) = keys.into_iter().partition(|(key, _)| keep(key));
+
+        let mut prefixes = Vec::new();
+        for (key, path_index) in dropped {
+            let bits = key.view_bits::<Msb0>();
+            // The subtree must not hold the closest kept keys, nor overlap the excluded subtrees.
+            let next = kept.partition_point(|(k, _)| *k < key);
+            let neighbors = kept[next.saturating_sub(1)..]
+                .iter()
+                .take(2)
+                .map(|(k, _)| k.view_bits::<Msb0>());
+            let excluded = self.excluded.iter().map(|s| s.position.path());
+            let depth = neighbors
+                .chain(excluded)
+                .map(|other| shared_bits(bits, other) + 1)
+                .max()
+                .unwrap_or(0);
+
+            let path = self
+                .path_proofs
+                .get(path_index)
+                .ok_or(WitnessExclusionError::UnknownPath(path_index))?;
+            if path.inner.siblings.len() < depth {
+                continue;
+            }
+            prefixes.push(match depth {
+                0 => TriePosition::new(),
+                _ => TriePosition::from_bitslice(&bits[..depth]),
+            });
+        }
+        prefixes.dedup();
+
+        self.exclude_subtrees::<H>(&prefixes)
+    }
+
+    /// Verify the witness against the root of the trie before the witnessed operations, and
+    /// extract the statements it proves.
+    ///
+    /// The witness need not be in canonical order, and may contain the same path or operation
+    /// more than once. Every read must be proven by the path it refers to.
+    ///
+    /// The roots of excluded subtrees before the operations are proven against `prev_root`, but
+    /// their roots after the operations are taken as given. These are reported along with the
+    /// other statements, to be checked by whoever knows the excluded state.
+    ///
+    /// If the witness is bound to a commit, its roots must be `prev_root` and the new root. Use
+    /// [`Witness::verify_in_context`] to check its context as well.
+    pub fn verify<H: NodeHasher>(
+        &self,
+        prev_root: Node,
+    ) -> Result<WitnessStatements, WitnessVerificationError> {
+        if self
+            .binding
+            .as_ref()
+            .is_some_and(|binding| binding.prev_root != prev_root)
+        {
+            return Err(WitnessVerificationError::BoundToOtherRoots);
+        }
+
+        let mut excluded = self.excluded.iter().collect::<Vec<_>>();
+        excluded.sort_by(|a, b| a.position.path().cmp(b.position.path()));
+        for (index, subtree) in self.excluded.iter().enumerate() {
+            if !subtree.verify::<H>(prev_root) {
+                return Err(WitnessVerificationError::InvalidSubtree(index));
+            }
+        }
+        let check_not_excluded = |key: &KeyPath| {
+            let bits = key.view_bits::<Msb0>();
+            match excluded.iter().any(|s| bits.starts_with(s.position.path())) {
+                true => Err(WitnessVerificationError::OpInExcludedSubtree(*key)),
+                false => Ok(()),
+            }
+        };
+
+        let verified = self
+            .path_proofs
+            .iter()
+            .enumerate()
+            .map(|(index, path)| {
+                path.inner
+                    .verify::<H>(path.path.path(), prev_root)
+                    .map_err(|error| WitnessVerificationError::InvalidPath { index, error })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let path_of = |path_index: usize| {
+            verified
+                .get(path_index)
+                .ok_or(WitnessVerificationError::UnknownPath(path_index))
+        };
+
+        let mut reads = Vec::with_capacity(self.operations.reads.len());
+        for read in &self.operations.reads {
+            check_not_excluded(&read.key)?;
+            let path = path_of(read.path_index)?;
+            let proven = match read.value {
+                None => path.confirm_nonexistence(&read.key),
+                Some(value_hash) => path.confirm_value(&LeafData {
+                    key_path: read.key,
+                    value_hash,
+                }),
+            }
+            .map_err(|_| WitnessVerificationError::OpOutOfScope(read.key))?;
+            if !proven {
+                return Err(WitnessVerificationError::ReadNotProven(read.key));
+            }
+            reads.push((read.key, read.value));
+        }
+        // Reads proven against the same root cannot conflict.
+        reads.sort_unstable();
+        reads.dedup();
+
+        let mut writes = Vec::with_capacity(self.operations.writes.len());
+        for write in &self.operations.writes {
+            check_not_excluded(&write.key)?;
+            let path = path_of(write.path_index)?;
+            if !write.key.view_bits::<Msb0>().starts_with(path.path()) {
+                return Err(WitnessVerificationError::OpOutOfScope(write.key));
+            }
+            writes.push((write.key, write.value, write.path_index));
+        }
+        writes.sort_unstable();
+        writes.dedup();
+        if let Some(w) = writes.windows(2).find(|w| w[0].0 == w[1].0) {
+            return Err(WitnessVerificationError::ConflictingWrites(w[0].0));
+        }
+
+        // Verified paths end at terminals and so cover disjoint ranges of keys. Writes ordered by
+        // key are therefore grouped by path, and the groups are ordered by path.
+        let mut updates: Vec<PathUpdate> = Vec::new();
+        for (key, value, path_index) in &writes {
+            let path = &verified[*path_index];
+            match updates.last_mut() {
+                Some(update) if update.inner.path() == path.path() => {
+                    update.ops.push((*key, *value))
+                }
+                _ => updates.push(PathUpdate {
+                    inner: path.clone(),
+                    ops: vec![(*key, *value)],
+                }),
+            }
+        }
+        let excluded = excluded.into_iter().cloned().collect::<Vec<_>>();
+        let new_root =
+            crate::proof::verify_update_with_subtrees::<H>(prev_root, &updates, &excluded)
+                .map_err(WitnessVerificationError::Update)?;
+        if self
+            .binding
+            .as_ref()
+            .is_some_and(|binding| binding.new_root != new_root)
+        {
+            return Err(WitnessVerificationError::BoundToOtherRoots);
+        }
+
+        Ok(WitnessStatements {
+            prev_root,
+            new_root,
+            excluded: excluded
+                .into_iter()
+                .map(|subtree| ExcludedSubtree {
+                    position: subtree.position,
+                    prev_root: subtree.prev_root,
+                    new_root: subtree.new_root,
+                })
+                .collect(),
+            reads,
+            writes: writes
+                .into_iter()
+                .map(|(key, value, _)| (key, value))
+                .collect(),
+        })
+    }
+
+    /// Verify the witness like [`Witness::verify`], additionally requiring it to be bound to a
+    /// commit in the given context.
+    pub fn verify_in_context<H: NodeHasher>(
+        &self,
+        prev_root: Node,
+        context: &[u8],
+    ) -> Result<WitnessStatements, WitnessVerificationError> {
+        match self.binding {
+            None => Err(WitnessVerificationError::Unbound),
+            Some(ref binding) if binding.context != context => {
+                Err(WitnessVerificationError::BoundToOtherContext)
+            }
+            Some(_) => self.verify::<H>(prev_root),
+        }
+    }
+
+    /// Check that this and another witness of the same commit are semantically equivalent: both
+    /// are valid against `prev_root` and prove the same reads, the same writes and the same new
+    /// root.
+    ///
+    /// The witnesses may differ in encoding, e.g. in the order of paths and operations or in
+    /// duplicated entries, as witnesses produced by different versions of NOMT might.
+    ///
+    /// Returns the statements proven by both witnesses.
+    pub fn check_equivalence<H: NodeHasher>(
+        &self,
+        other: &Witness,
+        prev_root: Node,
+    ) -> Result<WitnessStatements, WitnessEquivalenceError> {
+        let a = self
+            .verify::<H>(prev_root)
+            .map_err(WitnessEquivalenceError::InvalidFirst)?;
+        let b = other
+            .verify::<H>(prev_root)
+            .map_err(WitnessEquivalenceError::InvalidSecond)?;
+
+        if let Some(key) = first_difference(&a.reads, &b.reads) {
+            return Err(WitnessEquivalenceError::ReadsDiffer(key));
+        }
+        if let Some(key) = first_difference(&a.writes, &b.writes) {
+            return Err(WitnessEquivalenceError::WritesDiffer(key));
+        }
+        if a.excluded != b.excluded {
+            return Err(WitnessEquivalenceError::ExcludedSubtreesDiffer);
+        }
+        if a.new_root != b.new_root {
+            return Err(WitnessEquivalenceError::NewRootsDiffer);
+        }
+        Ok(a)
+    }
+}
+
+/// The statements proven by a valid witness, independent of how the witness encodes them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WitnessStatements {
+    /// The root the witness was verified against.
+    pub prev_root: Node,
+    /// The root after applying the writes.
+    pub new_root: Node,
+    /// The keys read and their value hashes before the writes, sorted by key. `None` means the
+    /// key had no value.
+    pub reads: Vec<(KeyPath, Option<ValueHash>)>,
+    /// The keys written and their new value hashes, sorted by key. `None` means "delete".
+    pub writes: Vec<(KeyPath, Option<ValueHash>)>,
+    /// The subtrees excluded from the witness, sorted by position.
+    pub excluded: Vec<ExcludedSubtree>,
+}
+
+/// A subtree excluded from a witness, as proven by the witness.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExcludedSubtree {
+    /// The position of the root of the subtree.
+    pub position: TriePosition,
+    /// The root of the subtree before the operations, proven against the previous root.
+    pub prev_root: Node,
+    /// The root of the subtree after the operations. This is not checked by the witness.
+    pub new_root: Node,
+}
+
+impl WitnessStatements {
+    /// Get the proven value hash of a read key. `None` if the key was not read, `Some(None)` if
+    /// it had no value.
+    pub fn read(&self, key_path: &KeyPath) -> Option<Option<ValueHash>> {
+        self.reads
+            .binary_search_by_key(key_path, |(k, _)| *k)
+            .ok()
+            .map(|i| self.reads[i].1)
+    }
+
+    /// Check that a key was read with the given value, e.g. a value supplied alongside the
+    /// witness to re-execute the session.
+    pub fn confirm_read<H: ValueHasher>(&self, key_path: &KeyPath, value: Option<&[u8]>) -> bool {
+        self.read(key_path) == Some(value.map(H::hash_value))
+    }
+
+    /// Check that the writes proven by the witness are exactly the given writes, e.g. those
+    /// obtained by re-executing the session. The writes must be sorted by key.
+    ///
+    /// On mismatch, returns the smallest key written differently or only on one side.
+    pub fn confirm_writes<H: ValueHasher>(
+        &self,
+        writes: &[(KeyPath, Option<&[u8]>)],
+    ) -> Result<(), KeyPath> {
+        let writes = writes
+            .iter()
+            .map(|(key, value)| (*key, value.map(H::hash_value)))
+            .collect::<Vec<_>>();
+        match first_difference(&self.writes, &writes) {
+            None => Ok(()),
+            Some(key) => Err(key),
+        }
+    }
+}
+
+/// Errors that can occur when verifying a witness.
+#[derive(Debug, Clone, Copy)]
+pub enum WitnessVerificationError {
+    /// The path proof at the given index does not verify against the root.
+    InvalidPath {
+        /// The index of the path.
+        index: usize,
+        /// The error verifying the path proof.
+        error: PathProofVerificationError,
+    },
+    /// An operation refers to a path index beyond the paths of the witness.
+    UnknownPath(usize),
+    /// The key of an operation is out of scope for the path it refers to.
+    OpOutOfScope(KeyPath),
+    /// The value of a read is not the one proven by its path.
+    ReadNotProven(KeyPath),
+    /// A key is written more than once, with different values.
+    ConflictingWrites(KeyPath),
+    /// The writes could not be applied to the paths.
+    Update(VerifyUpdateError),
+    /// The excluded subtree at the given index does not verify against the root.
+    InvalidSubtree(usize),
+    /// The key of an operation is within an excluded subtree.
+    OpInExcludedSubtree(KeyPath),
+    /// The witness is bound to a commit with other roots than the ones verified.
+    BoundToOtherRoots,
+    /// The witness is bound to a commit in another context than the expected one.
+    BoundToOtherContext,
+    /// The witness is not bound to a commit, but a context was expected.
+    Unbound,
+}
+
+/// Errors that can occur when excluding subtrees from a witness.
+#[derive(Debug, Clone, Copy)]
+pub enum WitnessExclusionError {
+    /// The path of a key beginning with an excluded prefix ends above the prefix, so there is no
+    /// subtree root to commit to.
+    NoSubtreeRoot(KeyPath),
+    /// A prefix overlaps a subtree already excluded from the witness.
+    AlreadyExcluded,
+    /// An operation refers to a path index beyond the paths of the witness.
+    UnknownPath(usize),
+    /// The writes under a prefix could not be applied to the subtree.
+    Update(VerifyUpdateError),
+}
+
+/// Errors that can occur when checking two witnesses for equivalence.
+#[derive(Debug, Clone, Copy)]
+pub enum WitnessEquivalenceError {
+    /// The witness checked is not valid.
+    InvalidFirst(WitnessVerificationError),
+    /// The witness compared against is not valid.
+    InvalidSecond(WitnessVerificationError),
+    /// The witnesses prove different reads. Carries the smallest key read differently or by only
+    /// one of the witnesses.
+    ReadsDiffer(KeyPath),
+    /// The witnesses prove different writes. Carries the smallest key written differently or by
+    /// only one of the witnesses.
+    WritesDiffer(KeyPath),
+    /// The witnesses exclude different subtrees, or prove different roots for them.
+    ExcludedSubtreesDiffer,
+    /// The witnesses lead to different new roots.
+    NewRootsDiffer,
+}
+
+// The smallest key with a different entry in two lists sorted by key.
+fn first_difference(
+    a: &[(KeyPath, Option<ValueHash>)],
+    b: &[(KeyPath, Option<ValueHash>)],
+) -> Option<KeyPath> {
+    let mismatch = a.iter().zip(b).find(|(a, b)| a != b);
+    match mismatch {
+        Some((a, b)) => Some(core::cmp::min(a.0, b.0)),
+        None => a.get(b.len()).or_else(|| b.get(a.len())).map(|e| e.0),
+    }
+}
+
+/// The magic bytes opening an encoded witness.
+#[cfg(feature = "std")]
+pub const WITNESS_MAGIC: [u8; 4] = *b"NWIT";
+
+/// The latest version of the witness encoding. Decoders accept every version up to their own.
+#[cfg(feature = "std")]
+pub const WITNESS_FORMAT_VERSION: u16 = 1;
+
+#[cfg(feature = "std")]
+const SECTION_END: u64 = 0;
+#[cfg(feature = "std")]
+const SECTION_PATHS: u64 = 1;
+#[cfg(feature = "std")]
+const SECTION_READS: u64 = 2;
+#[cfg(feature = "std")]
+const SECTION_WRITES: u64 = 3;
+#[cfg(feature = "std")]
+const SECTION_EXCLUDED: u64 = 4;
+#[cfg(feature = "std")]
+const SECTION_BINDING: u64 = 5;
+#[cfg(feature = "std")]
+const SECTION_VALUES: u64 = 6;
+
+#[cfg(feature = "std")]
+impl Witness {
+    /// Write the witness in its versioned binary encoding.
+    ///
+    /// Unlike the borsh and serde encodings, which change with the types, this encoding is
+    /// stable across crate versions:
+    ///
+    /// ```text
+    /// witness:   magic: b"NWIT" | version: u16 LE | section* | 0: varint
+    /// section:   tag: varint | length: varint | body: [u8; length]
+    /// paths:     count: varint | (position | terminal | sibling count: varint | [u8; 32]*)*
+    /// reads:     count: varint | (key: [u8; 32] | value | path index: varint)*
+    /// writes:    as reads
+    /// excluded:  count: varint | (position | sibling count: varint | [u8; 32]*
+    ///            | prev root: [u8; 32] | new root: [u8; 32])*
+    /// binding:   prev root: [u8; 32] | new root: [u8; 32] | context length: varint | context
+    /// values:    count: varint | (key: [u8; 32] | 0 | 1 | length: varint | [u8; length])*
+    /// position:  depth: varint | path: [u8; ceil(depth / 8)], zero-padded
+    /// terminal:  0 | key: [u8; 32] | value hash: [u8; 32]  (leaf)
+    ///            1 | position                            (terminator)
+    /// value:     0 | 1 | value hash: [u8; 32]
+    /// ```
+    ///
+    /// The sections are tagged 1 to 6 in the order above and written in ascending order, leaving
+    /// out the empty ones. Varints are LEB128.
+    ///
+    /// Decoders skip sections with tags they don't know, so new sections can be added without
+    /// breaking older decoders. The version is bumped only for changes an older decoder would
+    /// misread, and decoders keep parsing every earlier version.
+    pub fn encode_to(&self, writer: &mut impl std::io::Write) -> std::io::Result<()> {
+        writer.write_all(&WITNESS_MAGIC)?;
+        writer.write_all(&WITNESS_FORMAT_VERSION.to_le_bytes())?;
+
+        let mut write_section = |tag: u64, body: Vec<u8>| -> std::io::Result<()> {
+            let mut header = Vec::new();
+            write_varint(&mut header, tag);
+            write_varint(&mut header, body.len() as u64);
+            writer.write_all(&header)?;
+            writer.write_all(&body)
+        };
+
+        if !self.path_proofs.is_empty() {
+            let mut body = Vec::new();
+            write_varint(&mut body, self.path_proofs.len() as u64);
+            for path in &self.path_proofs {
+                write_position(&mut body, &path.path);
+                match path.inner.terminal {
+                    PathProofTerminal::Leaf(ref leaf) => {
+                        body.push(0);
+                        body.extend_from_slice(&leaf.key_path);
+                        body.extend_from_slice(&leaf.value_hash);
+                    }
+                    PathProofTerminal::Terminator(ref pos) => {
+                        body.push(1);
+                        write_position(&mut body, pos);
+                    }
+                }
+                write_nodes(&mut body, &path.inner.siblings);
+            }
+            write_section(SECTION_PATHS, body)?;
+        }
+
+        let operations = [
+            (
+                SECTION_READS,
+                self.operations
+                    .reads
+                    .iter()
+                    .map(|r| (&r.key, &r.value, r.path_index))
+                    .collect::<Vec<_>>(),
+            ),
+            (
+                SECTION_WRITES,
+                self.operations
+                    .writes
+                    .iter()
+                    .map(|w| (&w.key, &w.value, w.path_index))
+                    .collect(),
+            ),
+        ];
+        for (tag, ops) in operations {
+            if ops.is_empty() {
+                continue;
+            }
+            let mut body = Vec::new();
+            write_varint(&mut body, ops.len() as u64);
+            for (key, value, path_index) in ops {
+                body.extend_from_slice(key);
+                match value {
+                    None => body.push(0),
+                    Some(value) => {
+                        body.push(1);
+                        body.extend_from_slice(value);
+                    }
+                }
+                write_varint(&mut body, path_index as u64);
+            }
+            write_section(tag, body)?;
+        }
+
+        if !self.excluded.is_empty() {
+            let mut body = Vec::new();
+            write_varint(&mut body, self.excluded.len() as u64);
+            for subtree in &self.excluded {
+                write_position(&mut body, &subtree.position);
+                write_nodes(&mut body, &subtree.siblings);
+                body.extend_from_slice(&subtree.prev_root);
+                body.extend_from_slice(&subtree.new_root);
+            }
+            write_section(SECTION_EXCLUDED, body)?;
+        }
+
+        if let Some(ref binding) = self.binding {
+            let mut body = Vec::new();
+            body.extend_from_slice(&binding.prev_root);
+            body.extend_from_slice(&binding.new_root);
+            write_varint(&mut body, binding.context.len() as u64);
+            body.extend_from_slice(&binding.context);
+            write_section(SECTION_BINDING, body)?;
+        }
+
+        if !self.values.is_empty() {
+            let mut body = Vec::new();
+            write_varint(&mut body, self.values.len() as u64);
+            for WitnessedValue { key, value } in &self.values {
+                body.extend_from_slice(key);
+                match value {
+                    None => body.push(0),
+                    Some(value) => {
+                        body.push(1);
+                        write_varint(&mut body, value.len() as u64);
+                        body.extend_from_slice(value);
+                    }
+                }
+            }
+            write_section(SECTION_VALUES, body)?;
+        }
+
+        let mut end = Vec::new();
+        write_varint(&mut end, SECTION_END);
+        writer.write_all(&end)
+    }
+
+    /// Read a witness in the encoding written by [`Witness::encode_to`], of this version or an
+    /// earlier one.
+    ///
+    /// Reading stops at the end of the witness, so witnesses can be read one after another from
+    /// a stream. The decoded witness still has to be verified.
+    pub fn decode_from(reader: &mut impl std::io::Read) -> Result<Self, WitnessDecodeError> {
+        use std::io::Read as _;
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != WITNESS_MAGIC {
+            return Err(WitnessDecodeError::BadMagic);
+        }
+        let mut version = [0; 2];
+        reader.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if version == 0 || version > WITNESS_FORMAT_VERSION {
+            return Err(WitnessDecodeError::UnsupportedVersion(version));
+        }
+
+        let mut witness = Witness {
+            path_proofs: Vec::new(),
+            operations: WitnessedOperations {
+                reads: Vec::new(),
+                writes: Vec::new(),
+            },
+            excluded: Vec::new(),
+            binding: None,
+            values: Vec::new(),
+        };
+
+        let mut last_tag = SECTION_END;
+        loop {
+            let tag = read_stream_varint(reader)?;
+            if tag == SECTION_END {
+                break;
+            }
+            // Sections come in ascending order, each at most once.
+            if tag <= last_tag {
+                return Err(WitnessDecodeError::Malformed);
+            }
+            last_tag = tag;
+
+            let len = read_stream_varint(reader)?;
+            let mut section = reader.by_ref().take(len);
+            if tag > SECTION_VALUES {
+                // A section added after this version.
+                let skipped = std::io::copy(&mut section, &mut std::io::sink())?;
+                if skipped != len {
+                    return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+                }
+                continue;
+            }
+
+            // `take` bounds the allocation by what the reader actually holds.
+            let mut body = Vec::new();
+            section.read_to_end(&mut body)?;
+            if body.len() as u64 != len {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+            let mut body = SectionReader { bytes: &body };
+
+            match tag {
+                SECTION_PATHS => {
+                    for _ in 0..body.read_count()? {
+                        let path = body.read_position()?;
+                        let terminal = match body.read_byte()? {
+                            0 => PathProofTerminal::Leaf(LeafData {
+                                key_path: body.read_node()?,
+                                value_hash: body.read_node()?,
+                            }),
+                            1 => PathProofTerminal::Terminator(body.read_position()?),
+                            _ => return Err(WitnessDecodeError::Malformed),
+                        };
+                        let siblings = body.read_nodes()?;
+                        witness.path_proofs.push(WitnessedPath {
+                            inner: PathProof { terminal, siblings },
+                            path,
+                        });
+                    }
+                }
+                SECTION_READS | SECTION_WRITES => {
+                    for _ in 0..body.read_count()? {
+                        let key = body.read_node()?;
+                        let value = match body.read_byte()? {
+                            0 => None,
+                            1 => Some(body.read_node()?),
+                            _ => return Err(WitnessDecodeError::Malformed),
+                        };
+                        let path_index = usize::try_from(body.read_varint()?)
+                            .map_err(|_| WitnessDecodeError::Malformed)?;
+                        if tag == SECTION_READS {
+                            witness.operations.reads.push(WitnessedRead {
+                                key,
+                                value,
+                                path_index,
+                            });
+                        } else {
+                            witness.operations.writes.push(WitnessedWrite {
+                                key,
+                                value,
+                                path_index,
+                            });
+                        }
+                    }
+                }
+                SECTION_EXCLUDED => {
+                    for _ in 0..body.read_count()? {
+                        witness.excluded.push(SubtreeUpdate {
+                            position: body.read_position()?,
+                            siblings: body.read_nodes()?,
+                            prev_root: body.read_node()?,
+                            new_root: body.read_node()?,
+                        });
+                    }
+                }
+                SECTION_BINDING => {
+                    let prev_root = body.read_node()?;
+                    let new_root = body.read_node()?;
+                    let len = body.read_count()?;
+                    let context = body.read_bytes(len)?.to_vec();
+                    witness.binding = Some(WitnessBinding {
+                        prev_root,
+                        new_root,
+                        context,
+                    });
+                }
+                _ => {
+                    for _ in 0..body.read_count()? {
+                        let key = body.read_node()?;
+                        let value = match body.read_byte()? {
+                            0 => None,
+                            1 => {
+                                let len = body.read_count()?;
+                                Some(body.read_bytes(len)?.to_vec())
+                            }
+                            _ => return Err(WitnessDecodeError::Malformed),
+                        };
+                        witness.values.push(WitnessedValue { key, value });
+                    }
+                }
+            }
+
+            if !body.bytes.is_empty() {
+                return Err(WitnessDecodeError::Malformed);
+            }
+        }
+
+        Ok(witness)
+    }
+}
+
+/// Errors in decoding a witness from its versioned binary encoding.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum WitnessDecodeError {
+    /// Reading failed, or the input ended before the witness did.
+    Io(std::io::Error),
+    /// The input does not begin with [`WITNESS_MAGIC`].
+    BadMagic,
+    /// The witness is encoded with a version this decoder doesn't know.
+    UnsupportedVersion(u16),
+    /// A section is invalid, or sections are out of order.
+    Malformed,
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for WitnessDecodeError {
+    fn from(e: std::io::Error) -> Self {
+        WitnessDecodeError::Io(e)
+    }
+}
+
+#[cfg(feature = "std")]
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(feature = "std")]
+fn write_position(out: &mut Vec<u8>, pos: &TriePosition) {
+    write_varint(out, pos.depth() as u64);
+    out.extend_from_slice(&pos.raw_path()[..(pos.depth() as usize).div_ceil(8)]);
+}
+
+#[cfg(feature = "std")]
+fn write_nodes(out: &mut Vec<u8>, nodes: &[Node]) {
+    write_varint(out, nodes.len() as u64);
+    for node in nodes {
+        out.extend_from_slice(node);
+    }
+}
+
+#[cfg(feature = "std")]
+fn read_stream_varint(reader: &mut impl std::io::Read) -> Result<u64, WitnessDecodeError> {
+    let mut n = 0u64;
+    for i in 0..10 {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        n |= ((byte[0] & 0x7f) as u64) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(WitnessDecodeError::Malformed)
+}
+
+#[cfg(feature = "std")]
+struct SectionReader<'a> {
+    bytes: &'a [u8],
+}
+
+#[cfg(feature = "std")]
+impl<'a> SectionReader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], WitnessDecodeError> {
+        if self.bytes.len() < len {
+            return Err(WitnessDecodeError::Malformed);
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    fn read_byte(&mut self) -> Result<u8, WitnessDecodeError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_varint(&mut self) -> Result<u64, WitnessDecodeError> {
+        let mut n = 0u64;
+        for i in 0..10 {
+            let byte = self.read_byte()?;
+            n |= ((byte & 0x7f) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(WitnessDecodeError::Malformed)
+    }
+
+    // A count of items, each at least a byte long, so it can't exceed the bytes left.
+    fn read_count(&mut self) -> Result<usize, WitnessDecodeError> {
+        match self.read_varint()? {
+            n if n <= self.bytes.len() as u64 => Ok(n as usize),
+            _ => Err(WitnessDecodeError::Malformed),
+        }
+    }
+
+    fn read_node(&mut self) -> Result<Node, WitnessDecodeError> {
+        let mut node = Node::default();
+        node.copy_from_slice(self.read_bytes(32)?);
+        Ok(node)
+    }
+
+    fn read_nodes(&mut self) -> Result<Vec<Node>, WitnessDecodeError> {
+        (0..self.read_count()?).map(|_| self.read_node()).collect()
+    }
+
+    fn read_position(&mut self) -> Result<TriePosition, WitnessDecodeError> {
+        let depth = match self.read_varint()? {
+            depth @ 0..=256 => depth as usize,
+            _ => return Err(WitnessDecodeError::Malformed),
+        };
+        let mut path = KeyPath::default();
+        path[..depth.div_ceil(8)].copy_from_slice(self.read_bytes(depth.div_ceil(8))?);
+        if path.view_bits::<Msb0>()[depth..].any() {
+            return Err(WitnessDecodeError::Malformed);
+        }
+        Ok(TriePosition::from_path_and_depth(path, depth as u16))
+    }
 }
 
 /// Operations provable by a corresponding witness.
+#[derive(Clone)]
 #[cfg_attr(
     feature = "borsh",
     derive(borsh::BorshDeserialize, borsh::BorshSerialize)
@@ -39,6 +1197,7 @@ pub struct WitnessedOperations {
 }
 
 /// A path observed in the witness.
+#[derive(Clone)]
 #[cfg_attr(
     feature = "borsh",
     derive(borsh::BorshDeserialize, borsh::BorshSerialize)
@@ -52,6 +1211,7 @@ pub struct WitnessedPath {
 }
 
 /// A witness of a read value.
+#[derive(Clone)]
 #[cfg_attr(
     feature = "borsh",
     derive(borsh::BorshDeserialize, borsh::BorshSerialize)
@@ -60,13 +1220,15 @@ pub struct WitnessedPath {
 pub struct WitnessedRead {
     /// The key of the read value.
     pub key: KeyPath,
-    /// The hash of the value witnessed. None means no value.
+    /// The hash of the value witnessed. None means no value, while an empty value is witnessed
+    /// by its hash.
     pub value: Option<ValueHash>,
     /// The index of the path in the corresponding witness.
     pub path_index: usize,
 }
 
 /// A witness of a write operation.
+#[derive(Clone)]
 #[cfg_attr(
     feature = "borsh",
     derive(borsh::BorshDeserialize, borsh::BorshSerialize)
@@ -80,3 +1242,230 @@ pub struct WitnessedWrite {
     /// The index of the path in the corresponding witness.
     pub path_index: usize,
 }
+
+/// The value of a witnessed key before the operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshDeserialize, borsh::BorshSerialize)
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WitnessedValue {
+    /// The key of the value.
+    pub key: KeyPath,
+    /// The value itself. `None` means the key had no value.
+    pub value: Option<Vec<u8>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(s: &str) -> WitnessedPath {
+        let path = TriePosition::from_str(s);
+        WitnessedPath {
+            inner: PathProof {
+                terminal: PathProofTerminal::Terminator(path.clone()),
+                siblings: Vec::new(),
+            },
+            path,
+        }
+    }
+
+    fn read(key: u8, path_index: usize) -> WitnessedRead {
+        WitnessedRead {
+            key: [key; 32],
+            value: None,
+            path_index,
+        }
+    }
+
+    fn write(key: u8, path_index: usize) -> WitnessedWrite {
+        WitnessedWrite {
+            key: [key; 32],
+            value: None,
+            path_index,
+        }
+    }
+
+    #[test]
+    fn normalize_sorts_paths_and_operations() {
+        let mut witness = Witness {
+            path_proofs: vec![path("11"), path("0"), path("10")],
+            operations: WitnessedOperations {
+                reads: vec![read(0xC0, 0), read(0x10, 1), read(0x80, 2), read(0x00, 1)],
+                writes: vec![write(0xF0, 0), write(0x90, 2)],
+            },
+            excluded: Vec::new(),
+            binding: None,
+            values: Vec::new(),
+        };
+        assert!(!witness.is_canonical());
+
+        witness.normalize();
+        assert!(witness.is_canonical());
+
+        let paths = witness
+            .path_proofs
+            .iter()
+            .map(|p| p.path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                TriePosition::from_str("0"),
+                TriePosition::from_str("10"),
+                TriePosition::from_str("11"),
+            ]
+        );
+
+        let keys = |reads: &[WitnessedRead]| reads.iter().map(|r| r.key[0]).collect::<Vec<_>>();
+        assert_eq!(keys(witness.reads_for_path(0)), vec![0x00, 0x10]);
+        assert_eq!(keys(witness.reads_for_path(1)), vec![0x80]);
+        assert_eq!(keys(witness.reads_for_path(2)), vec![0xC0]);
+        assert!(witness.reads_for_path(3).is_empty());
+
+        assert!(witness.writes_for_path(0).is_empty());
+        assert_eq!(witness.writes_for_path(1)[0].key, [0x90; 32]);
+        assert_eq!(witness.writes_for_path(2)[0].key, [0xF0; 32]);
+    }
+
+    fn encode(witness: &Witness) -> Vec<u8> {
+        let mut out = Vec::new();
+        witness.encode_to(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        let mut leaf_path = path("0110");
+        leaf_path.inner = PathProof {
+            terminal: PathProofTerminal::Leaf(LeafData {
+                key_path: [0x60; 32],
+                value_hash: [7; 32],
+            }),
+            siblings: vec![[1; 32], [2; 32], [3; 32], [4; 32]],
+        };
+        let mut read = read(0x61, 1);
+        read.value = Some([8; 32]);
+        let witness = Witness {
+            path_proofs: vec![path("00101"), leaf_path],
+            operations: WitnessedOperations {
+                reads: vec![read],
+                writes: vec![write(0x20, 0), write(0x28, 0)],
+            },
+            excluded: vec![SubtreeUpdate {
+                position: TriePosition::from_str("111"),
+                siblings: vec![[5; 32]; 3],
+                prev_root: [6; 32],
+                new_root: [9; 32],
+            }],
+            binding: Some(WitnessBinding {
+                prev_root: [10; 32],
+                new_root: [11; 32],
+                context: b"block 7".to_vec(),
+            }),
+            values: vec![
+                WitnessedValue {
+                    key: [0x20; 32],
+                    value: None,
+                },
+                WitnessedValue {
+                    key: [0x61; 32],
+                    value: Some(b"balance".to_vec()),
+                },
+            ],
+        };
+
+        // Two witnesses in a row, the second with every section empty.
+        let empty = Witness {
+            path_proofs: Vec::new(),
+            operations: WitnessedOperations {
+                reads: Vec::new(),
+                writes: Vec::new(),
+            },
+            excluded: Vec::new(),
+            binding: None,
+            values: Vec::new(),
+        };
+        let mut bytes = encode(&witness);
+        assert_eq!(encode(&empty), b"NWIT\x01\x00\x00");
+        bytes.extend(encode(&empty));
+
+        let mut reader = &bytes[..];
+        let decoded = Witness::decode_from(&mut reader).unwrap();
+        assert_eq!(encode(&decoded), encode(&witness));
+        assert_eq!(decoded.path_proofs[1].path, TriePosition::from_str("0110"));
+        assert_eq!(decoded.operations.reads[0].value, Some([8; 32]));
+        assert_eq!(decoded.binding, witness.binding);
+        assert_eq!(decoded.values, witness.values);
+        let decoded = Witness::decode_from(&mut reader).unwrap();
+        assert!(decoded.path_proofs.is_empty() && decoded.binding.is_none());
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn decoding_skips_unknown_sections() {
+        let witness = Witness {
+            path_proofs: vec![path("1")],
+            operations: WitnessedOperations {
+                reads: vec![read(0x80, 0)],
+                writes: Vec::new(),
+            },
+            excluded: Vec::new(),
+            binding: None,
+            values: Vec::new(),
+        };
+        let mut bytes = encode(&witness);
+        // Replace the end marker by a section from a later crate version, then end.
+        bytes.pop();
+        bytes.extend([42, 3, 0xAA, 0xBB, 0xCC, 0]);
+        let decoded = Witness::decode_from(&mut &bytes[..]).unwrap();
+        assert_eq!(encode(&decoded), encode(&witness));
+    }
+
+    #[test]
+    fn decoding_rejects_invalid_input() {
+        let mut bytes = encode(&Witness {
+            path_proofs: vec![path("1")],
+            operations: WitnessedOperations {
+                reads: Vec::new(),
+                writes: vec![write(0x80, 0)],
+            },
+            excluded: Vec::new(),
+            binding: None,
+            values: Vec::new(),
+        });
+
+        let truncated = &bytes[..bytes.len() - 2];
+        assert!(matches!(
+            Witness::decode_from(&mut &truncated[..]),
+            Err(WitnessDecodeError::Io(_))
+        ));
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(matches!(
+            Witness::decode_from(&mut &bad_magic[..]),
+            Err(WitnessDecodeError::BadMagic)
+        ));
+
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert!(matches!(
+            Witness::decode_from(&mut &newer[..]),
+            Err(WitnessDecodeError::UnsupportedVersion(2))
+        ));
+
+        // The write section repeated after itself.
+        assert_eq!(bytes[bytes.len() - 38], 3);
+        let section = bytes[bytes.len() - 38..bytes.len() - 1].to_vec();
+        bytes.pop();
+        bytes.extend(section);
+        bytes.push(0);
+        assert!(matches!(
+            Witness::decode_from(&mut &bytes[..]),
+            Err(WitnessDecodeError::Malformed)
+        ));
+    }
+}
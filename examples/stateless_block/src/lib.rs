@@ -0,0 +1,168 @@
+//! A stateless verifier of blocks of balance transfers.
+//!
+//! A full node executes a block against its database and produces a [`BlockWitness`]: the witness
+//! of the session, along with the values of all keys read. A verifier holding nothing but the
+//! state root before the block checks the witness, re-executes the block against the values it
+//! carries, and obtains the state root after the block.
+//!
+//! The verifier only depends on `nomt-core` and does not use the standard library.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{
+    collections::{btree_map::Entry, BTreeMap},
+    vec::Vec,
+};
+use nomt_core::{
+    hasher::{Blake3Hasher, ValueHasher},
+    trie::{KeyPath, Node},
+    witness::{Witness, WitnessVerificationError},
+};
+
+/// The hash function of the state trie.
+pub type Hasher = Blake3Hasher;
+
+/// A value in the state.
+pub type Value = Vec<u8>;
+
+/// A transfer of an amount between two accounts.
+#[derive(Debug, Clone, Copy)]
+pub struct Transfer {
+    /// The account debited.
+    pub from: u64,
+    /// The account credited.
+    pub to: u64,
+    /// The amount transferred.
+    pub amount: u64,
+}
+
+/// A block of transfers. Transfers exceeding the balance of the debited account are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct Block {
+    /// The transfers, in execution order.
+    pub transfers: Vec<Transfer>,
+}
+
+/// Everything needed to verify a block besides the state root before it.
+#[derive(Clone)]
+pub struct BlockWitness {
+    /// The witness of the session executing the block.
+    pub witness: Witness,
+    /// The values of all keys read by the block, before the block. `None` means the key had no
+    /// value.
+    pub pre_state: Vec<(KeyPath, Option<Value>)>,
+}
+
+/// The keys read and written by the execution of a block.
+#[derive(Debug, Clone, Default)]
+pub struct Execution {
+    /// The values read from the state before the block, by key.
+    pub reads: BTreeMap<KeyPath, Option<Value>>,
+    /// The values written by the block, by key. `None` means "delete".
+    pub writes: BTreeMap<KeyPath, Option<Value>>,
+}
+
+/// The key under which the balance of an account is stored.
+pub fn account_key(account: u64) -> KeyPath {
+    Hasher::hash_value(&account.to_le_bytes())
+}
+
+/// Encode a balance as a value.
+pub fn encode_balance(balance: u64) -> Value {
+    balance.to_le_bytes().to_vec()
+}
+
+fn decode_balance(value: Option<&Value>) -> u64 {
+    value
+        .and_then(|v| v.as_slice().try_into().ok())
+        .map_or(0, u64::from_le_bytes)
+}
+
+/// Execute a block against the state given by `read`. Each key is read at most once.
+pub fn execute_block<E>(
+    block: &Block,
+    mut read: impl FnMut(KeyPath) -> Result<Option<Value>, E>,
+) -> Result<Execution, E> {
+    let mut execution = Execution::default();
+    let mut balance = |execution: &mut Execution, account: u64| -> Result<u64, E> {
+        let key = account_key(account);
+        if let Some(value) = execution.writes.get(&key) {
+            return Ok(decode_balance(value.as_ref()));
+        }
+        let value = match execution.reads.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(read(key)?),
+        };
+        Ok(decode_balance(value.as_ref()))
+    };
+
+    for transfer in &block.transfers {
+        let from = balance(&mut execution, transfer.from)?;
+        let to = balance(&mut execution, transfer.to)?;
+        if from < transfer.amount || transfer.from == transfer.to {
+            continue;
+        }
+        let to = to.saturating_add(transfer.amount);
+        execution.writes.insert(
+            account_key(transfer.from),
+            Some(encode_balance(from - transfer.amount)),
+        );
+        execution
+            .writes
+            .insert(account_key(transfer.to), Some(encode_balance(to)));
+    }
+    Ok(execution)
+}
+
+/// Errors in block verification.
+#[derive(Debug, Clone, Copy)]
+pub enum VerificationError {
+    /// The witness is not valid against the state root before the block.
+    Witness(WitnessVerificationError),
+    /// A value carried by the block witness was not proven by the witness.
+    UnprovenValue(KeyPath),
+    /// The block read a key without a value in the block witness.
+    MissingValue(KeyPath),
+    /// The writes of the block differ from those of the witness, starting at the given key.
+    WritesDiffer(KeyPath),
+}
+
+/// Verify a block against the state root before it, returning the state root after it.
+pub fn verify_block(
+    prev_root: Node,
+    block: &Block,
+    block_witness: &BlockWitness,
+) -> Result<Node, VerificationError> {
+    let statements = block_witness
+        .witness
+        .verify::<Hasher>(prev_root)
+        .map_err(VerificationError::Witness)?;
+
+    let mut pre_state = BTreeMap::new();
+    for (key, value) in &block_witness.pre_state {
+        if !statements.confirm_read::<Hasher>(key, value.as_deref()) {
+            return Err(VerificationError::UnprovenValue(*key));
+        }
+        pre_state.insert(*key, value.clone());
+    }
+
+    let execution = execute_block(block, |key| {
+        pre_state
+            .get(&key)
+            .cloned()
+            .ok_or(VerificationError::MissingValue(key))
+    })?;
+
+    let writes = execution
+        .writes
+        .iter()
+        .map(|(key, value)| (*key, value.as_deref()))
+        .collect::<Vec<_>>();
+    statements
+        .confirm_writes::<Hasher>(&writes)
+        .map_err(VerificationError::WritesDiffer)?;
+
+    Ok(statements.new_root)
+}
@@ -0,0 +1,118 @@
+use anyhow::{anyhow, Result};
+use nomt::{KeyReadWrite, Nomt, Options, Root, SessionParams, WitnessMode};
+use stateless_block::{
+    account_key, encode_balance, execute_block, verify_block, Block, BlockWitness, Hasher, Transfer,
+};
+
+const NOMT_DB_FOLDER: &str = "stateless_block_db";
+
+// Execute a block on the full node, returning the root before and after it along with the
+// witness to hand to verifiers.
+fn build_block(nomt: &Nomt<Hasher>, block: &Block) -> Result<(Root, Root, BlockWitness)> {
+    let session =
+        nomt.begin_session(SessionParams::default().witness_mode(WitnessMode::read_write()));
+    let prev_root = session.prev_root();
+
+    // Execution reads straight from the database.
+    let execution = execute_block(block, |key| session.read(key))?;
+
+    // Every key read or written is reported to NOMT, in key order.
+    let mut keys = execution
+        .reads
+        .keys()
+        .chain(execution.writes.keys())
+        .copied()
+        .collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+    let actuals = keys
+        .into_iter()
+        .map(|key| {
+            let read = execution.reads.get(&key).cloned();
+            let write = execution.writes.get(&key).cloned();
+            let access = match (read, write) {
+                (Some(read), Some(write)) => KeyReadWrite::ReadThenWrite(read, write),
+                (Some(read), None) => KeyReadWrite::Read(read),
+                (None, Some(write)) => KeyReadWrite::Write(write),
+                (None, None) => unreachable!(),
+            };
+            (key, access)
+        })
+        .collect();
+
+    let mut finished = session.finish(actuals)?;
+    let witness = finished.take_witness().unwrap();
+    let root = finished.root();
+    finished.commit(nomt)?;
+
+    let block_witness = BlockWitness {
+        witness,
+        pre_state: execution.reads.into_iter().collect(),
+    };
+    Ok((prev_root, root, block_witness))
+}
+
+fn main() -> Result<()> {
+    let _ = std::fs::remove_dir_all(NOMT_DB_FOLDER);
+    let mut opts = Options::new();
+    opts.path(NOMT_DB_FOLDER);
+    opts.commit_concurrency(1);
+    let nomt = Nomt::<Hasher>::open(opts)?;
+
+    // Genesis: ten accounts with a balance of 100 each.
+    let genesis = {
+        let mut actuals = (0..10)
+            .map(|account| {
+                (
+                    account_key(account),
+                    KeyReadWrite::Write(Some(encode_balance(100))),
+                )
+            })
+            .collect::<Vec<_>>();
+        actuals.sort_by_key(|(k, _)| *k);
+        actuals
+    };
+    let session = nomt.begin_session(SessionParams::default());
+    session.finish(genesis)?.commit(&nomt)?;
+
+    // The block sends funds to an account which does not exist yet, and contains a transfer
+    // which is skipped for lack of funds.
+    let block = Block {
+        transfers: vec![
+            Transfer {
+                from: 0,
+                to: 1,
+                amount: 40,
+            },
+            Transfer {
+                from: 1,
+                to: 42,
+                amount: 140,
+            },
+            Transfer {
+                from: 2,
+                to: 3,
+                amount: 500,
+            },
+        ],
+    };
+    let (prev_root, new_root, block_witness) = build_block(&nomt, &block)?;
+
+    // The verifier only knows the root before the block.
+    let verified_root = verify_block(prev_root.into_inner(), &block, &block_witness)
+        .map_err(|e| anyhow!("block verification failed: {:?}", e))?;
+    assert_eq!(verified_root, new_root.into_inner());
+    println!("verified block: {} -> {}", prev_root, new_root);
+
+    // A witness carrying a forged balance is rejected.
+    let mut forged = block_witness.clone();
+    forged.pre_state[0].1 = Some(encode_balance(1_000_000));
+    assert!(verify_block(prev_root.into_inner(), &block, &forged).is_err());
+
+    // So is a block which doesn't match the witness.
+    let mut other_block = block.clone();
+    other_block.transfers[0].amount = 50;
+    assert!(verify_block(prev_root.into_inner(), &other_block, &block_witness).is_err());
+
+    Ok(())
+}
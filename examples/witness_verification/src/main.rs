@@ -20,17 +20,12 @@ fn main() -> Result<()> {
         // Among all read operations performed the ones that interact
         // with the current verified path are selected
         //
-        // Each witnessed operation contains an index to the path it needs to be verified against
+        // Each witnessed operation contains an index to the path it needs to be verified against.
+        // Witnesses are in canonical order, so the operations of a path are contiguous.
         //
         // This information could already be known if we committed the batch initially,
         // and thus, the witnessed field could be discarded entirely.
-        for read in witness
-            .operations
-            .reads
-            .iter()
-            .skip_while(|r| r.path_index != i)
-            .take_while(|r| r.path_index == i)
-        {
+        for read in witness.reads_for_path(i) {
             match read.value {
                 // Check for non-existence if the return value was None
                 None => assert!(verified.confirm_nonexistence(&read.key).unwrap()),
@@ -52,13 +47,7 @@ fn main() -> Result<()> {
         // Later, it needs to be verified that all these writes bring
         // the new trie to the expected state
         let mut write_ops = Vec::new();
-        for write in witness
-            .operations
-            .writes
-            .iter()
-            .skip_while(|r| r.path_index != i)
-            .take_while(|r| r.path_index == i)
-        {
+        for write in witness.writes_for_path(i) {
             write_ops.push((write.key, write.value));
         }
 
@@ -1,8 +1,11 @@
-use nomt_core::trie::KeyPath;
+use nomt_core::{
+    hasher::{NodeHasher, ValueHasher},
+    trie::{KeyPath, Node},
+};
 use quickcheck::{empty_shrinker, single_shrinker, Arbitrary, Gen};
 use rand::{Rng as _, SeedableRng as _};
 use rand_pcg::Lcg64Xsh32;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 const EDGE_PREFIX_LENS: &[usize] = &[0, 1, 7, 8, 15, 16, 31, 32, 63, 64, 127, 128, 255];
 
@@ -256,6 +259,151 @@ impl Arbitrary for UniqueSortedKeyPaths {
     }
 }
 
+/// A trie and a batch of writes to it, biased towards batches which restructure the trie: leaves
+/// moving up as their siblings are deleted, subtrees emptied down to a terminator and leaves pushed
+/// down by keys diverging from them deep in the trie.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TrieUpdateCase {
+    /// The contents of the trie before the batch, in ascending key order.
+    pub prev_state: Vec<(KeyPath, Vec<u8>)>,
+    /// The writes of the batch in ascending key order, with `None` for deletions.
+    pub writes: Vec<(KeyPath, Option<Vec<u8>>)>,
+}
+
+impl TrieUpdateCase {
+    /// Of the given contents and writes, the last of each key is kept.
+    pub fn new(
+        prev_state: impl IntoIterator<Item = (KeyPath, Vec<u8>)>,
+        writes: impl IntoIterator<Item = (KeyPath, Option<Vec<u8>>)>,
+    ) -> Self {
+        Self {
+            prev_state: prev_state
+                .into_iter()
+                .collect::<BTreeMap<_, _>>()
+                .into_iter()
+                .collect(),
+            writes: writes
+                .into_iter()
+                .collect::<BTreeMap<_, _>>()
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// The contents of the trie after the batch, in ascending key order.
+    pub fn final_state(&self) -> Vec<(KeyPath, Vec<u8>)> {
+        let mut state = self.prev_state.iter().cloned().collect::<BTreeMap<_, _>>();
+        for (key, value) in &self.writes {
+            match value {
+                Some(value) => state.insert(*key, value.clone()),
+                None => state.remove(key),
+            };
+        }
+        state.into_iter().collect()
+    }
+}
+
+impl Arbitrary for TrieUpdateCase {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let mut prev_state = BTreeMap::new();
+        let mut writes = BTreeMap::new();
+        for _ in 0..usize::arbitrary(g) % 6 {
+            prev_state.insert(TestKeyPath::arbitrary(g).into_inner(), small_value(g));
+        }
+
+        let mut delete_all = false;
+        match u8::arbitrary(g) % 5 {
+            0 => {
+                // One leaf of a pair is deleted, so the other moves up to where they diverge.
+                let pair = DivergingPair::arbitrary(g);
+                prev_state.insert(pair.left, small_value(g));
+                prev_state.insert(pair.right, small_value(g));
+                let deleted = if bool::arbitrary(g) {
+                    pair.left
+                } else {
+                    pair.right
+                };
+                writes.insert(deleted, None);
+            }
+            1 => {
+                // A subtree is emptied, or all but one of its leaves are deleted.
+                let cluster = SharedPrefixCluster::arbitrary(g);
+                let kept = usize::arbitrary(g) % (cluster.members.len() + 1);
+                for (i, member) in cluster.members.into_iter().enumerate() {
+                    prev_state.insert(member, small_value(g));
+                    if i != kept {
+                        writes.insert(member, None);
+                    }
+                }
+            }
+            2 => {
+                // A new key diverges from a leaf, pushing it down. The leaf may be deleted as well.
+                let pair = DivergingPair::arbitrary(g);
+                let (existing, new) = if bool::arbitrary(g) {
+                    (pair.left, pair.right)
+                } else {
+                    (pair.right, pair.left)
+                };
+                prev_state.insert(existing, small_value(g));
+                prev_state.remove(&new);
+                writes.insert(new, Some(small_value(g)));
+                if bool::arbitrary(g) {
+                    writes.insert(existing, None);
+                }
+            }
+            3 => delete_all = true,
+            _ => {
+                for key in arbitrary_prefix_keys(g) {
+                    if bool::arbitrary(g) {
+                        prev_state.insert(key, small_value(g));
+                    }
+                    writes.insert(key, optional_small_value(g));
+                }
+            }
+        }
+
+        // Unrelated writes to the rest of the trie.
+        for key in prev_state.keys() {
+            if delete_all {
+                writes.insert(*key, None);
+            } else if u8::arbitrary(g) % 4 == 0 {
+                writes
+                    .entry(*key)
+                    .or_insert_with(|| optional_small_value(g));
+            }
+        }
+
+        Self::new(prev_state, writes)
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        let current = self.clone();
+        let without_writes = (0..self.writes.len()).map(move |i| {
+            let mut case = current.clone();
+            case.writes.remove(i);
+            case
+        });
+        let current = self.clone();
+        let without_leaves = (0..self.prev_state.len()).map(move |i| {
+            let mut case = current.clone();
+            case.prev_state.remove(i);
+            case
+        });
+        Box::new(without_writes.chain(without_leaves))
+    }
+}
+
+/// The root of the trie with the given contents, in ascending key order, built in memory.
+pub fn reference_root<H: NodeHasher + ValueHasher>(state: &[(KeyPath, Vec<u8>)]) -> Node {
+    nomt_core::update::build_trie::<H>(
+        0,
+        state
+            .iter()
+            .map(|(key, value)| (*key, H::hash_value(value))),
+        |_| {},
+    )
+}
+
 pub fn account_path(id: u64) -> KeyPath {
     TestKeyPath::account_like(id).into_inner()
 }
@@ -315,6 +463,27 @@ fn arbitrary_edge_key(g: &mut Gen) -> TestKeyPath {
     }
 }
 
+// Keys sharing a prefix with each other, as new keys splitting leaves or deletions emptying the
+// subtree below the prefix.
+fn arbitrary_prefix_keys(g: &mut Gen) -> Vec<KeyPath> {
+    let prefix_len = arbitrary_prefix_len(g, false);
+    let prefix_bits = random_bits(g, prefix_len);
+    (0..1 + usize::arbitrary(g) % 4)
+        .map(|_| TestKeyPath::with_prefix_bits(prefix_bits.iter().copied(), u64::arbitrary(g)))
+        .map(TestKeyPath::into_inner)
+        .collect()
+}
+
+fn small_value(g: &mut Gen) -> Vec<u8> {
+    (0..usize::arbitrary(g) % 9)
+        .map(|_| u8::arbitrary(g))
+        .collect()
+}
+
+fn optional_small_value(g: &mut Gen) -> Option<Vec<u8>> {
+    bool::arbitrary(g).then(|| small_value(g))
+}
+
 fn cluster_extra_width(prefix_len_bits: usize) -> usize {
     (255 - prefix_len_bits).min(3)
 }
@@ -412,8 +581,9 @@ mod tests {
     use super::{
         account_path, bit, first_difference, has_exact_cluster_prefix, is_sorted_unique,
         key_diverging_at, key_with_prefix, key_with_prefix_seed, DivergingPair,
-        SharedPrefixCluster, TestKeyPath, UniqueSortedKeyPaths,
+        SharedPrefixCluster, TestKeyPath, TrieUpdateCase, UniqueSortedKeyPaths,
     };
+    use nomt_core::trie::KeyPath;
     use quickcheck::{Arbitrary, QuickCheck};
 
     #[test]
@@ -537,4 +707,29 @@ mod tests {
             .tests(64)
             .quickcheck(property as fn(UniqueSortedKeyPaths) -> bool);
     }
+
+    #[test]
+    fn quickcheck_trie_update_case_invariants() {
+        fn property(case: TrieUpdateCase) -> bool {
+            fn keys<T>(entries: &[(KeyPath, T)]) -> Vec<KeyPath> {
+                entries.iter().map(|(key, _)| *key).collect()
+            }
+            let final_state = case.final_state();
+
+            is_sorted_unique(&keys(&case.prev_state))
+                && is_sorted_unique(&keys(&case.writes))
+                && is_sorted_unique(&keys(&final_state))
+                && case.writes.iter().all(|(key, value)| {
+                    final_state
+                        .binary_search_by_key(key, |(key, _)| *key)
+                        .ok()
+                        .map(|i| &final_state[i].1)
+                        == value.as_ref()
+                })
+        }
+
+        QuickCheck::new()
+            .tests(64)
+            .quickcheck(property as fn(TrieUpdateCase) -> bool);
+    }
 }
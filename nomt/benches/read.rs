@@ -0,0 +1,77 @@
+#[cfg(feature = "benchmarks")]
+mod read {
+    use criterion::Criterion;
+    use nomt::{
+        hasher::Blake3Hasher,
+        read_bench::{self, CacheState, ReadKind},
+        trie::KeyPath,
+        KeyReadWrite, Nomt, Options, SessionParams,
+    };
+    use rand::{RngExt as _, SeedableRng as _};
+    use std::time::Duration;
+
+    const DB_SIZE: usize = 100_000;
+    const SAMPLE_KEYS: usize = 1_000;
+
+    fn open_db(dir: &std::path::Path) -> (Nomt<Blake3Hasher>, Vec<KeyPath>) {
+        let mut o = Options::new();
+        o.path(dir.join("nomt_db"));
+        let nomt = Nomt::<Blake3Hasher>::open(o).unwrap();
+
+        let mut rng = rand_pcg::Lcg64Xsh32::seed_from_u64(0);
+        let mut keys = (0..DB_SIZE)
+            .map(|_| rng.random::<KeyPath>())
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys.dedup();
+
+        let session = nomt.begin_session(SessionParams::default());
+        let actuals = keys
+            .iter()
+            .map(|key| (*key, KeyReadWrite::Write(Some(key[..16].to_vec()))))
+            .collect();
+        session.finish(actuals).unwrap().commit(&nomt).unwrap();
+
+        let sample = (0..SAMPLE_KEYS)
+            .map(|_| keys[rng.random_range(0..keys.len())])
+            .collect();
+        (nomt, sample)
+    }
+
+    pub fn read_benchmark(c: &mut Criterion) {
+        let dir = tempfile::tempdir().unwrap();
+        let (nomt, keys) = open_db(dir.path());
+
+        let mut group = c.benchmark_group("read");
+        let states = [
+            ("cold", CacheState::Cold),
+            ("upper_levels", CacheState::UpperLevels(2)),
+            ("warm", CacheState::Warm),
+        ];
+        for (kind_name, kind) in [("value", ReadKind::Value), ("proof", ReadKind::Proof)] {
+            for (state_name, state) in states {
+                group.bench_function(format!("{kind_name}/{state_name}"), |b| {
+                    b.iter_custom(|iters| {
+                        let keys = keys
+                            .iter()
+                            .cycle()
+                            .take(iters as usize)
+                            .copied()
+                            .collect::<Vec<_>>();
+                        let latencies = read_bench::measure(&nomt, state, kind, &keys).unwrap();
+                        latencies.samples().iter().sum::<Duration>()
+                    })
+                });
+            }
+        }
+        group.finish();
+    }
+}
+
+#[cfg(feature = "benchmarks")]
+criterion::criterion_group!(benches, read::read_benchmark);
+#[cfg(feature = "benchmarks")]
+criterion::criterion_main!(benches);
+
+#[cfg(not(feature = "benchmarks"))]
+fn main() {}
@@ -0,0 +1,71 @@
+//! Tracing of the keys and pages accessed by a session.
+//!
+//! See [`SessionParams::access_trace`](crate::SessionParams::access_trace).
+
+use crate::{KeyPath, KeyReadWrite};
+use nomt_core::page_id::PageId;
+use parking_lot::Mutex;
+use std::collections::BTreeSet;
+
+/// The keys and pages accessed by a session, e.g. to build an access list for the transactions
+/// executed within it.
+///
+/// Obtained from [`FinishedSession::take_access_list`](crate::FinishedSession::take_access_list).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessList {
+    /// The keys read, in ascending order: those read with [`Session::read`](crate::Session::read)
+    /// or proven with [`Session::prove`](crate::Session::prove), and those read according to the
+    /// actuals the session was finished with.
+    pub reads: Vec<KeyPath>,
+    /// The keys written according to the actuals the session was finished with, in ascending
+    /// order.
+    pub writes: Vec<KeyPath>,
+    /// The IDs of the pages on the paths to the terminal nodes of all keys in the actuals, in
+    /// ascending order. `None` unless pages were traced.
+    pub pages: Option<Vec<PageId>>,
+}
+
+// Records the keys read during a session.
+pub(crate) struct AccessRecorder {
+    reads: Mutex<BTreeSet<KeyPath>>,
+    trace_pages: bool,
+}
+
+impl AccessRecorder {
+    pub(crate) fn new(trace_pages: bool) -> Self {
+        AccessRecorder {
+            reads: Mutex::new(BTreeSet::new()),
+            trace_pages,
+        }
+    }
+
+    pub(crate) fn trace_pages(&self) -> bool {
+        self.trace_pages
+    }
+
+    pub(crate) fn record_read(&self, key: KeyPath) {
+        self.reads.lock().insert(key);
+    }
+
+    // Complete the access list with the actuals of the session. Pages are filled in once traced.
+    pub(crate) fn finish(self, actuals: &[(KeyPath, KeyReadWrite)]) -> AccessList {
+        let mut reads = self.reads.into_inner();
+        let mut writes = Vec::new();
+        for (key, read_write) in actuals {
+            if matches!(
+                read_write,
+                KeyReadWrite::Read(_) | KeyReadWrite::ReadThenWrite(_, _)
+            ) {
+                reads.insert(*key);
+            }
+            if read_write.is_write() {
+                writes.push(*key);
+            }
+        }
+        AccessList {
+            reads: reads.into_iter().collect(),
+            writes,
+            pages: None,
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Capture and replay of access patterns.
+//!
+//! With [`Options::access_log`](crate::Options::access_log), the database logs the keys read,
+//! proven and written, along with when. Sessions are sampled as a whole, so that the reads and
+//! writes of a logged session are complete. [`replay`] reproduces the logged accesses against
+//! another database, typically a copy of the one the log was captured on, to investigate its
+//! performance offline.
+//!
+//! The log is a text file. After a header line, each line is an event:
+//!
+//! ```text
+//! <micros> begin <session>
+//! <micros> read <session | -> <key>
+//! <micros> prove <session | -> <key>
+//! <micros> write <session> <key> <value length | ->
+//! <micros> commit <session>
+//! ```
+//!
+//! `micros` is the time since the database was opened, in microseconds. Reads outside of a
+//! session, with [`Nomt::get`], have `-` in place of the session. Keys are in hex. Only the
+//! lengths of written values are logged, and `-` stands for a deletion, so that the log doesn't
+//! hold any values. Sessions committed into an [`Overlay`](crate::Overlay) log no writes.
+
+use crate::{trie::KeyPath, Error, HashAlgorithm, KeyReadWrite, Nomt, Session, SessionParams};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufWriter, Write as _},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+const HEADER: &str = "nomt-access-log 1";
+
+// The log of accesses to a database.
+pub(crate) struct AccessLog {
+    out: Mutex<BufWriter<File>>,
+    start: Instant,
+    sample_rate: f64,
+    next_session: AtomicU64,
+}
+
+// An event of a logged session, or of a read outside of any.
+pub(crate) enum Event {
+    Begin,
+    Read(KeyPath),
+    Prove(KeyPath),
+    Write(KeyPath, Option<usize>),
+    Commit,
+}
+
+impl AccessLog {
+    pub(crate) fn create(path: &Path, sample_rate: f64) -> anyhow::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{HEADER}")?;
+        Ok(AccessLog {
+            out: Mutex::new(out),
+            start: Instant::now(),
+            sample_rate,
+            next_session: AtomicU64::new(1),
+        })
+    }
+
+    // Whether to log a read outside of a session.
+    pub(crate) fn sample(&self) -> bool {
+        self.sample_rate >= 1.0 || rand::random::<f64>() < self.sample_rate
+    }
+
+    // Decide whether to log a new session, and log its beginning if so. Returns its ID.
+    pub(crate) fn begin_session(&self) -> Option<u64> {
+        if !self.sample() {
+            return None;
+        }
+        let session = self.next_session.fetch_add(1, Ordering::Relaxed);
+        self.record(Some(session), Event::Begin);
+        Some(session)
+    }
+
+    // Log an event. Failing to write the log doesn't fail the access.
+    pub(crate) fn record(&self, session: Option<u64>, event: Event) {
+        let micros = self.start.elapsed().as_micros();
+        let session = session.map_or("-".to_string(), |s| s.to_string());
+        let mut out = self.out.lock();
+        let _ = match event {
+            Event::Begin => writeln!(out, "{micros} begin {session}"),
+            Event::Read(key) => writeln!(out, "{micros} read {session} {}", encode_key(&key)),
+            Event::Prove(key) => writeln!(out, "{micros} prove {session} {}", encode_key(&key)),
+            Event::Write(key, len) => writeln!(
+                out,
+                "{micros} write {session} {} {}",
+                encode_key(&key),
+                len.map_or("-".to_string(), |len| len.to_string())
+            ),
+            Event::Commit => writeln!(out, "{micros} commit {session}"),
+        };
+        // Commits are rare enough to flush on, and keep the log current up to the last one.
+        if matches!(event, Event::Commit) {
+            let _ = out.flush();
+        }
+    }
+
+    // Log the writes of a session being finished.
+    pub(crate) fn record_writes(&self, session: u64, actuals: &[(KeyPath, KeyReadWrite)]) {
+        for (key, read_write) in actuals {
+            if let KeyReadWrite::Write(value) | KeyReadWrite::ReadThenWrite(_, value) = read_write {
+                self.record(
+                    Some(session),
+                    Event::Write(*key, value.as_ref().map(Vec::len)),
+                );
+            }
+        }
+    }
+}
+
+/// How quickly [`replay`] goes through the events of a log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pacing {
+    /// Replay each event as soon as the previous one is done.
+    Unpaced,
+    /// Replay each event no earlier than it was logged, relative to the start of the replay.
+    Recorded,
+}
+
+/// The accesses made by [`replay`] and the time they took.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayStats {
+    /// The number of values read.
+    pub reads: u64,
+    /// The total time taken by reads.
+    pub read_time: Duration,
+    /// The time taken by the slowest read.
+    pub max_read_time: Duration,
+    /// The number of keys proven.
+    pub proofs: u64,
+    /// The total time taken by proofs.
+    pub proof_time: Duration,
+    /// The number of sessions committed.
+    pub commits: u64,
+    /// The total time taken to finish and commit sessions.
+    pub commit_time: Duration,
+    /// The time taken by the whole replay.
+    pub elapsed: Duration,
+}
+
+/// Replay the accesses of a log captured with [`Options::access_log`](crate::Options::access_log)
+/// against the database.
+///
+/// Logged sessions are replayed with sessions of their own, opened on their first event. Reads
+/// and proofs are made as logged. Writes are made with values of the logged lengths, filled with
+/// a constant byte, so the roots differ from those of the logged database but the same pages and
+/// leaves are touched.
+///
+/// Events are replayed one at a time on the calling thread. Before a session is committed, the
+/// other replayed sessions are dropped, as a commit waits for all sessions to end; their
+/// following events are replayed with new sessions.
+///
+/// Fails if the log is malformed or if an access fails.
+pub fn replay<T: HashAlgorithm>(
+    nomt: &Nomt<T>,
+    log: impl BufRead,
+    pacing: Pacing,
+) -> anyhow::Result<ReplayStats> {
+    let start = Instant::now();
+    let mut stats = ReplayStats::default();
+    let mut sessions: HashMap<u64, Session<T>> = HashMap::new();
+    let mut writes: HashMap<u64, Vec<(KeyPath, KeyReadWrite)>> = HashMap::new();
+
+    let mut lines = log.lines();
+    match lines.next().transpose()? {
+        Some(header) if header == HEADER => {}
+        _ => anyhow::bail!("not an access log"),
+    }
+
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        let malformed = || anyhow::anyhow!("malformed access log at line {}: {line}", i + 2);
+        let fields = line.split(' ').collect::<Vec<_>>();
+        let (micros, kind, session) = match fields[..] {
+            [micros, kind, session, ..] => (micros, kind, session),
+            _ => return Err(malformed()),
+        };
+        let micros = micros.parse::<u64>().map_err(|_| malformed())?;
+        let session = match session {
+            "-" => None,
+            s => Some(s.parse::<u64>().map_err(|_| malformed())?),
+        };
+        let key = |field: Option<&&str>| field.and_then(|f| decode_key(f)).ok_or_else(malformed);
+
+        if pacing == Pacing::Recorded {
+            let due = Duration::from_micros(micros);
+            if let Some(wait) = due.checked_sub(start.elapsed()) {
+                std::thread::sleep(wait);
+            }
+        }
+
+        match (kind, session) {
+            ("begin", Some(_)) => {}
+            ("read", None) => {
+                let key = key(fields.get(3))?;
+                let t = Instant::now();
+                nomt.get(key)?;
+                stats.record_read(t.elapsed());
+            }
+            ("read", Some(id)) => {
+                let key = key(fields.get(3))?;
+                let session = sessions
+                    .entry(id)
+                    .or_insert_with(|| nomt.begin_session(SessionParams::default()));
+                let t = Instant::now();
+                session.read(key)?;
+                stats.record_read(t.elapsed());
+            }
+            ("prove", session) => {
+                let key = key(fields.get(3))?;
+                let t = Instant::now();
+                match session {
+                    None => {
+                        nomt.begin_session(SessionParams::default()).prove(key)?;
+                    }
+                    Some(id) => {
+                        sessions
+                            .entry(id)
+                            .or_insert_with(|| nomt.begin_session(SessionParams::default()))
+                            .prove(key)?;
+                    }
+                }
+                stats.proofs += 1;
+                stats.proof_time += t.elapsed();
+            }
+            ("write", Some(id)) => {
+                let key = key(fields.get(3))?;
+                let value = match *fields.get(4).ok_or_else(malformed)? {
+                    "-" => None,
+                    len => Some(vec![0xA5; len.parse::<usize>().map_err(|_| malformed())?]),
+                };
+                writes
+                    .entry(id)
+                    .or_default()
+                    .push((key, KeyReadWrite::Write(value)));
+            }
+            ("commit", Some(id)) => {
+                let session = sessions.remove(&id);
+                sessions.clear();
+                let t = Instant::now();
+                let session =
+                    session.unwrap_or_else(|| nomt.begin_session(SessionParams::default()));
+                let mut actuals = writes.remove(&id).unwrap_or_default();
+                actuals.sort_by_key(|(key, _)| *key);
+                session
+                    .finish(actuals)?
+                    .commit(nomt)
+                    .map_err(Error::into_anyhow)?;
+                stats.commits += 1;
+                stats.commit_time += t.elapsed();
+            }
+            _ => return Err(malformed()),
+        }
+    }
+
+    stats.elapsed = start.elapsed();
+    Ok(stats)
+}
+
+fn encode_key(key: &KeyPath) -> String {
+    key.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_key(s: &str) -> Option<KeyPath> {
+    if s.len() != 64 || !s.is_ascii() {
+        return None;
+    }
+    let mut key = KeyPath::default();
+    for (byte, digits) in key.iter_mut().zip(s.as_bytes().chunks(2)) {
+        // UNWRAP: ASCII, so any two bytes are a string.
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).unwrap(), 16).ok()?;
+    }
+    Some(key)
+}
+
+impl ReplayStats {
+    fn record_read(&mut self, time: Duration) {
+        self.reads += 1;
+        self.read_time += time;
+        self.max_read_time = self.max_read_time.max(time);
+    }
+}
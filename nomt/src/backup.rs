@@ -0,0 +1,86 @@
+//! Online backups.
+//!
+//! [`Nomt::create_backup`](crate::Nomt::create_backup) copies a database into a new directory
+//! while it stays in use. The beatree stores and the hash table, which make up nearly all of a
+//! database, are copied first without holding up syncs, while the pages written to them in the
+//! meantime are recorded. Then, with syncs held up, those pages are copied again along with the
+//! remaining files, which are small. The backup is the state of the database as of the last sync
+//! before it completed.
+//!
+//! The meta is copied last, so an interrupted backup is recognized as an incomplete creation
+//! ([`PartialDatabase`](crate::PartialDatabase)) and can be deleted. A backup keeps the identity
+//! of the database it was taken from. Restore it with
+//! [`Nomt::open_backup`](crate::Nomt::open_backup), and clone the restored database with
+//! [`Nomt::clone_to`](crate::Nomt::clone_to) to use it alongside the original.
+
+use crate::{io::PAGE_SIZE, sys::FileExt as _};
+use std::{
+    collections::BTreeSet,
+    fs::{File, OpenOptions},
+    path::Path,
+};
+
+/// The files copied without holding up syncs.
+pub(crate) const BULK_FILES: [&str; 3] = ["ln", "bbn", "ht"];
+
+/// A summary of a backup, returned by [`Nomt::create_backup`](crate::Nomt::create_backup).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackupInfo {
+    /// The sequence number of the sync the backup reflects.
+    pub sync_seqn: u32,
+    /// The number of bytes copied without holding up syncs.
+    pub bulk_bytes: u64,
+    /// The number of pages which were written during the bulk copy and had to be copied again
+    /// while holding up syncs.
+    pub recopied_pages: u64,
+}
+
+/// Copy a file, following links, and sync the copy. Returns the number of bytes copied.
+pub(crate) fn copy_file(src: &Path, dest: &Path) -> std::io::Result<u64> {
+    let len = std::fs::copy(src, dest)?;
+    OpenOptions::new().write(true).open(dest)?.sync_all()?;
+    Ok(len)
+}
+
+/// Copy the given pages of a file again into its copy, and bring the copy to the same length.
+/// Returns the number of pages copied.
+pub(crate) fn recopy_pages(src: &Path, dest: &Path, pages: &BTreeSet<u64>) -> std::io::Result<u64> {
+    let src = File::open(src)?;
+    let dest = OpenOptions::new().write(true).open(dest)?;
+    let len = src.metadata()?.len();
+    dest.set_len(len)?;
+
+    let mut page = vec![0; PAGE_SIZE];
+    let mut copied = 0;
+    for pn in pages {
+        let offset = pn * PAGE_SIZE as u64;
+        if offset >= len {
+            continue;
+        }
+        src.read_exact_at(&mut page, offset)?;
+        dest.write_all_at(&page, offset)?;
+        copied += 1;
+    }
+    dest.sync_all()?;
+    Ok(copied)
+}
+
+/// Copy the files of a database directory other than the lock and the given ones into another
+/// directory, the meta last, and sync it.
+pub(crate) fn copy_remaining(src_dir: &Path, dest_dir: &Path, skip: &[&str]) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(src_dir)? {
+        let entry = entry?;
+        // Files placed outside of the database directory are reached through their links.
+        if !std::fs::metadata(entry.path())?.is_file() {
+            continue;
+        }
+        let name = entry.file_name();
+        let skipped = name == ".lock" || name == "meta" || skip.iter().any(|s| name == *s);
+        if !skipped {
+            copy_file(&entry.path(), &dest_dir.join(&name))?;
+        }
+    }
+    copy_file(&src_dir.join("meta"), &dest_dir.join("meta"))?;
+    crate::sys::sync_dir_path(dest_dir)?;
+    Ok(())
+}
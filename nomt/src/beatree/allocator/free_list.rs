@@ -200,6 +200,102 @@ impl FreeList {
         pages
     }
 
+    /// Like `commit`, but rewrite the whole free list so that the lowest page numbers are popped
+    /// first, and cut the free pages at the end of the store off, lowering `bump` past them.
+    ///
+    /// The pages of the new free list are the lowest of those free as of the last commit and not
+    /// popped since, as all others may still be live. The pages of the old free list are freed.
+    /// If there are too few, the missing pages are bumped instead and nothing is cut off.
+    ///
+    /// O(n) in the length of the free list.
+    pub fn rebuild(
+        &mut self,
+        page_pool: &PagePool,
+        freed: Vec<PageNumber>,
+        bump: &mut PageNumber,
+    ) -> Vec<(PageNumber, FatPage)> {
+        self.pop = false;
+
+        // pages which may be overwritten now, and all free pages.
+        let mut writable = BTreeSet::new();
+        let mut free = BTreeSet::new();
+        for (pn, pns) in self.portions.drain(..) {
+            free.insert(pn);
+            writable.extend(pns);
+        }
+        free.extend(self.released_portions.drain(..));
+        free.extend(freed);
+        free.extend(writable.iter().copied());
+
+        let full_bump = *bump;
+        let mut cut = Vec::new();
+        while free.last().is_some_and(|pn| pn.0 + 1 == bump.0) {
+            // UNWRAP: checked above.
+            cut.push(free.pop_last().unwrap());
+            bump.0 -= 1;
+        }
+
+        // each page of the list holds up to MAX_PNS_PER_PAGE of the other free pages. they must
+        // be below the bump, so give back the lowest pages cut off until enough are.
+        let mut needed = free.len().div_ceil(MAX_PNS_PER_PAGE + 1);
+        let mut writable_below = writable.range(..*bump).count();
+        while writable_below < needed {
+            let Some(pn) = cut.pop() else { break };
+            free.insert(pn);
+            *bump = PageNumber(pn.0 + 1);
+            writable_below += writable.contains(&pn) as usize;
+            needed = free.len().div_ceil(MAX_PNS_PER_PAGE + 1);
+        }
+
+        let mut list_pages = Vec::new();
+        if free.len() == 1 || writable_below < needed {
+            // bumping pages may overwrite those cut off, which may still be live.
+            free.extend(cut);
+            *bump = full_bump;
+            while free.len().div_ceil(MAX_PNS_PER_PAGE) > list_pages.len() {
+                // the last free page can't hold itself.
+                let pn = match writable.pop_first().filter(|_| free.len() > 1) {
+                    Some(pn) => pn,
+                    None => {
+                        bump.0 += 1;
+                        PageNumber(bump.0 - 1)
+                    }
+                };
+                free.remove(&pn);
+                list_pages.push(pn);
+            }
+        } else {
+            list_pages.extend(writable.into_iter().take(needed));
+            for pn in &list_pages {
+                free.remove(pn);
+            }
+        }
+
+        // the head holds the lowest pages, which are popped last to first. there may be one page
+        // too many for full pages, in which case the list is fragmented as `commit` describes.
+        let items = free.into_iter().rev().collect::<Vec<_>>();
+        let mut portions = items.chunks(MAX_PNS_PER_PAGE).collect::<Vec<_>>();
+        if portions.len() < list_pages.len() {
+            let (head, rest) = items.split_at(items.len() - 1);
+            portions = rest.chunks(MAX_PNS_PER_PAGE).chain(Some(head)).collect();
+        }
+        assert_eq!(portions.len(), list_pages.len());
+
+        let mut prev_pn = FREELIST_EMPTY;
+        let mut encoded = Vec::with_capacity(list_pages.len());
+        for (pn, pns) in list_pages.into_iter().zip(portions) {
+            encoded.push((pn, encode_free_list_page(page_pool, prev_pn, pns)));
+            self.portions.push((pn, pns.to_vec()));
+            prev_pn = pn;
+        }
+
+        let (len, fragmented) = len_and_fragmented(&self.portions);
+        self.len = len;
+        self.fragmented = fragmented;
+
+        encoded
+    }
+
     // determines the exact number of pops and bumps which are needed in order to fulfill the
     // request. also schedules pushing of all touched pages' previous page numbers.
     fn preallocate(
@@ -827,4 +923,71 @@ mod tests {
 
         assert_eq!(predicted_pops, actual_pops);
     }
+
+    #[test]
+    fn rebuild_sorts_and_cuts() {
+        let mut free_list = FreeList {
+            portions: vec![(
+                PageNumber(2),
+                vec![PageNumber(9), PageNumber(3), PageNumber(5), PageNumber(4)],
+            )],
+            pop: false,
+            released_portions: Vec::new(),
+            len: 4,
+            fragmented: false,
+        };
+
+        // expected order of events:
+        //   1. (11) is cut off, lowering the bump. (10) is live.
+        //   2. One page is needed for the list. The lowest page free before this sync, (3), is
+        //      taken. (2), the old head, is free.
+        //   3. The other free pages are written highest first, so that the lowest is popped
+        //      first.
+        let page_pool = PagePool::new();
+        let mut bump = PageNumber(12);
+        let result = free_list.rebuild(
+            &page_pool,
+            vec![PageNumber(7), PageNumber(8), PageNumber(11)],
+            &mut bump,
+        );
+
+        assert_eq!(bump, PageNumber(11));
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].0, PageNumber(3));
+        assert_eq!(
+            free_list.portions,
+            vec![(
+                PageNumber(3),
+                [9, 8, 7, 5, 4, 2].into_iter().map(PageNumber).collect()
+            )]
+        );
+        assert_eq!(free_list.len, 6);
+        assert!(!free_list.fragmented);
+    }
+
+    #[test]
+    fn rebuild_keeps_list_below_bump() {
+        let mut free_list = FreeList {
+            portions: vec![(PageNumber(1), vec![PageNumber(5), PageNumber(6)])],
+            pop: false,
+            released_portions: Vec::new(),
+            len: 2,
+            fragmented: false,
+        };
+
+        // expected order of events:
+        //   1. (6), (5) and (4) are cut off, leaving no page free before this sync below the bump.
+        //   2. (4) and (5) are given back, and (5) is taken for the list.
+        let page_pool = PagePool::new();
+        let mut bump = PageNumber(7);
+        let result = free_list.rebuild(&page_pool, vec![PageNumber(4)], &mut bump);
+
+        assert_eq!(bump, PageNumber(6));
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].0, PageNumber(5));
+        assert_eq!(
+            free_list.portions,
+            vec![(PageNumber(5), vec![PageNumber(4), PageNumber(1)])]
+        );
+    }
 }
@@ -1,14 +1,14 @@
-use crate::io::{self, page_pool::FatPage, IoCommand, IoKind, PagePool, PAGE_SIZE};
+use super::Dictionaries;
+use crate::{
+    io::{self, mmap::MappedFile, page_pool::FatPage, IoCommand, IoKind, PagePool, PAGE_SIZE},
+    sys::{AsRawFd, RawFd},
+};
 
 use crossbeam_channel::{Receiver, Sender};
 use parking_lot::{ArcMutexGuard, Mutex};
 use std::{
     collections::BTreeSet,
     fs::File,
-    os::{
-        fd::{AsRawFd, RawFd},
-        unix::fs::MetadataExt,
-    },
     sync::{
         atomic::{AtomicU32, AtomicUsize, Ordering},
         Arc,
@@ -45,6 +45,10 @@ pub const FREELIST_EMPTY: PageNumber = PageNumber(0);
 pub struct Store {
     file: Arc<File>,
     sync: Arc<Mutex<StoreSync>>,
+    /// The mapping serving blocking reads, if reads are memory-mapped.
+    mapped: Option<Arc<MappedFile>>,
+    /// The dictionaries of the values compressed in the pages of a leaf store.
+    dictionaries: Dictionaries,
 }
 
 impl Store {
@@ -55,23 +59,73 @@ impl Store {
         bump: PageNumber,
         free_list_head: Option<PageNumber>,
     ) -> anyhow::Result<Self> {
-        let file_size = file.metadata()?.size() as usize;
+        let file_size = file.metadata()?.len() as usize;
 
         let sync = StoreSync {
             free_list: FreeList::read(page_pool, &file, free_list_head)?,
             bump,
             max_bump: PageNumber((file_size / PAGE_SIZE) as u32),
+            compact: false,
+            shrink_to: None,
         };
 
         Ok(Store {
             file,
             sync: Arc::new(Mutex::new(sync)),
+            mapped: None,
+            dictionaries: Dictionaries::default(),
         })
     }
 
+    /// Return to the given state of a previous sync, reading the free-list back from the file.
+    ///
+    /// This undoes a sync whose meta was never written. It blocks until no sync is in progress.
+    pub fn reset(
+        &self,
+        page_pool: &PagePool,
+        bump: PageNumber,
+        free_list_head: Option<PageNumber>,
+    ) -> anyhow::Result<()> {
+        let free_list = FreeList::read(page_pool, &self.file, free_list_head)?;
+        let mut sync = self.sync.lock();
+        sync.free_list = free_list;
+        sync.bump = bump;
+        sync.shrink_to = None;
+        Ok(())
+    }
+
+    /// Serve blocking reads from the given mapping of the store file.
+    pub fn with_mapping(mut self, mapped: Option<Arc<MappedFile>>) -> Self {
+        self.mapped = mapped;
+        self
+    }
+
     /// Reads the page with the specified page number. Blocks the current thread.
+    ///
+    /// Panics if the read fails. See [`Store::try_query`].
+    pub fn with_dictionaries(mut self, dictionaries: Dictionaries) -> Self {
+        self.dictionaries = dictionaries;
+        self
+    }
+
+    pub fn dictionaries(&self) -> &Dictionaries {
+        &self.dictionaries
+    }
+
     pub fn query(&self, page_pool: &PagePool, pn: PageNumber) -> FatPage {
-        io::read_page(page_pool, &self.file, pn.0 as u64).unwrap()
+        self.try_query(page_pool, pn).unwrap()
+    }
+
+    /// Reads the page with the specified page number. Blocks the current thread.
+    pub fn try_query(&self, page_pool: &PagePool, pn: PageNumber) -> std::io::Result<FatPage> {
+        match self.mapped {
+            Some(ref mapped) => {
+                let mut page = page_pool.alloc_fat_page();
+                mapped.read_page(pn.0 as u64, &mut page)?;
+                Ok(page)
+            }
+            None => io::read_page(page_pool, &self.file, pn.0 as u64),
+        }
     }
 
     /// Create an I/O command for querying a page by number.
@@ -90,6 +144,31 @@ impl Store {
         self.sync.lock().free_list.all_tracked_pages()
     }
 
+    /// Get the number of pages in use, up to the bump, and the number of free pages among them.
+    ///
+    /// Deadlocks if sync is ongoing.
+    pub fn space(&self) -> StoreSpace {
+        let sync = self.sync.lock();
+        StoreSpace {
+            bump: sync.bump.0,
+            free: sync.free_list.as_clean().len(),
+        }
+    }
+
+    /// Make the next sync rebuild the free-list so that the lowest free pages are allocated first,
+    /// and give the free pages at the end of the store back to the file system.
+    ///
+    /// The file is only shrunk when the sync after that starts, as the pages may be read until
+    /// then.
+    pub fn compact_next_sync(&self) {
+        self.sync.lock().compact = true;
+    }
+
+    /// Get the length of the store file, in bytes.
+    pub fn file_len(&self) -> std::io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
     /// Start synchronization. This produces two handles,
     /// a [`SyncAllocator`] and a [`SyncFinisher`].
     ///
@@ -98,7 +177,17 @@ impl Store {
     ///
     /// This will block if another sync is in progress.
     pub fn start_sync(&self) -> (SyncAllocator, SyncFinisher) {
-        let sync = Mutex::lock_arc(&self.sync);
+        let mut sync = Mutex::lock_arc(&self.sync);
+        if let Some(shrink_to) = sync.shrink_to.take() {
+            // a failure only leaves the file longer than needed.
+            if self
+                .file
+                .set_len(shrink_to.0 as u64 * PAGE_SIZE as u64)
+                .is_ok()
+            {
+                sync.max_bump = shrink_to;
+            }
+        }
         let (sync_tx, sync_rx) = crossbeam_channel::bounded(1);
 
         let finisher = SyncFinisher {
@@ -145,11 +234,21 @@ impl StoreReader {
         &self.page_pool
     }
 
+    /// Get the dictionaries of the values compressed in the store.
+    pub fn dictionaries(&self) -> &Dictionaries {
+        self.store.dictionaries()
+    }
+
     /// Reads the page with the specified page number. Blocks the current thread.
     pub fn query(&self, pn: PageNumber) -> FatPage {
         self.store.query(&self.page_pool, pn)
     }
 
+    /// Reads the page with the specified page number. Blocks the current thread.
+    pub fn try_query(&self, pn: PageNumber) -> std::io::Result<FatPage> {
+        self.store.try_query(&self.page_pool, pn)
+    }
+
     /// Create an I/O command for querying a page by number.
     pub fn io_command(&self, pn: PageNumber, user_data: u64) -> IoCommand {
         self.store.io_command(&self.page_pool, pn, user_data)
@@ -164,6 +263,26 @@ struct StoreSync {
     max_bump: PageNumber,
     /// the free-list of pages.
     free_list: FreeList,
+    /// whether the next sync rebuilds the free-list.
+    compact: bool,
+    /// the length to shrink the store to when the next sync starts.
+    shrink_to: Option<PageNumber>,
+}
+
+/// The space taken by a store. See [`Store::space`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreSpace {
+    /// The next page number to bump. All pages below are in use or free.
+    pub bump: u32,
+    /// The number of free pages.
+    pub free: usize,
+}
+
+impl StoreSpace {
+    /// The number of pages below the bump which are not free, the nil page included.
+    pub fn used(&self) -> u32 {
+        self.bump - self.free as u32
+    }
 }
 
 type StoreSyncGuard = ArcMutexGuard<parking_lot::RawMutex, StoreSync>;
@@ -308,7 +427,12 @@ impl SyncFinisher {
 
         // remaining allocations all logically incremented bump.
         let mut next_bump = PageNumber(sync.bump.0 + bumps as u32);
-        let freelist_pages = sync.free_list.commit(page_pool, freed, &mut next_bump);
+        let compact = std::mem::take(&mut sync.compact);
+        let freelist_pages = if compact {
+            sync.free_list.rebuild(page_pool, freed, &mut next_bump)
+        } else {
+            sync.free_list.commit(page_pool, freed, &mut next_bump)
+        };
 
         // writing the free-list pages might require more bumps, which may require growing the file
         // further.
@@ -318,6 +442,9 @@ impl SyncFinisher {
 
         sync.bump = next_bump;
         sync.max_bump = max_bump;
+        if compact && next_bump.0 < max_bump.0 {
+            sync.shrink_to = Some(next_bump);
+        }
 
         let meta = StoreMeta {
             freelist_pn: sync.free_list.head_pn().unwrap_or(FREELIST_EMPTY).0,
@@ -125,15 +125,9 @@ impl BranchNode {
         let cells_end = BRANCH_NODE_HEADER_SIZE + self.n() as usize * 2;
         assert!(cells_end < BRANCH_NODE_BODY_SIZE);
 
-        // SAFETY: This creates a slice of length 2 * N starting at index BRANCH_NODE_HEADER_SIZE.
-        // This is ensured to be within the bounds of the page by the assertion above.
-        unsafe {
-            std::slice::from_raw_parts_mut(
-                self.page[BRANCH_NODE_HEADER_SIZE..BRANCH_NODE_HEADER_SIZE + 2].as_ptr()
-                    as *mut [u8; 2],
-                self.n().into(),
-            )
-        }
+        self.page[BRANCH_NODE_HEADER_SIZE..cells_end]
+            .as_chunks_mut()
+            .0
     }
 
     pub fn raw_separator(&self, i: usize) -> RawSeparator<'_> {
@@ -185,14 +179,7 @@ impl BranchNode {
         assert!(node_pointers_byte_len < BRANCH_NODE_SIZE);
 
         let node_pointers_init = BRANCH_NODE_SIZE - node_pointers_byte_len;
-        // SAFETY: This creates a slice of length 4 * N aligned with the end of the node.
-        // This is ensured to be within the bounds of the page by the assertion above.
-        unsafe {
-            std::slice::from_raw_parts_mut(
-                self.page[node_pointers_init..].as_mut_ptr() as *mut [u8; 4],
-                self.n() as usize,
-            )
-        }
+        self.page[node_pointers_init..].as_chunks_mut().0
     }
 
     fn set_node_pointer(&mut self, i: usize, node_pointer: u32) {
@@ -236,15 +223,7 @@ impl<'a> BranchNodeView<'a> {
         let cells_end = BRANCH_NODE_HEADER_SIZE + self.n() as usize * 2;
         assert!(cells_end < BRANCH_NODE_BODY_SIZE);
 
-        // SAFETY: This creates a slice of length 2 * N starting at index BRANCH_NODE_HEADER_SIZE.
-        // This is ensured to be within the bounds of the page by the assertion above.
-        unsafe {
-            std::slice::from_raw_parts(
-                self.inner[BRANCH_NODE_HEADER_SIZE..BRANCH_NODE_HEADER_SIZE + 2].as_ptr()
-                    as *const [u8; 2],
-                self.n().into(),
-            )
-        }
+        self.inner[BRANCH_NODE_HEADER_SIZE..cells_end].as_chunks().0
     }
 
     pub fn separator(&self, i: usize) -> &'a BitSlice<u8, Msb0> {
@@ -327,19 +306,10 @@ impl<'a> BranchNodeView<'a> {
         assert!(node_pointers_byte_len < BRANCH_NODE_SIZE);
 
         let node_pointers_init = BRANCH_NODE_SIZE - node_pointers_byte_len;
-        // SAFETY: This creates a slice of length 4 * N aligned with the end of the node.
-        // This is ensured to be within the bounds of the page by the assertion above.
-        unsafe {
-            std::slice::from_raw_parts(
-                self.inner[node_pointers_init..].as_ptr() as *const [u8; 4],
-                self.n() as usize,
-            )
-        }
+        self.inner[node_pointers_init..].as_chunks().0
     }
 }
 
-unsafe impl Send for BranchNode {}
-
 // A RawPrefix is made by a tuple of raw bytes and the relative bit length
 pub type RawPrefix<'a> = (&'a [u8], usize);
 
@@ -0,0 +1,276 @@
+//! Dictionaries for compressing overflow values with zstd.
+//!
+//! Values compressed one at a time share no history, so values which are alike compress no
+//! better than any of them alone. A dictionary trained over a sample of the values of a database
+//! captures what they have in common. See `Compression::Zstd`.
+//!
+//! Every dictionary is stored in its own file in the database directory, named after its
+//! version, and is never changed or removed: values compressed with it record its version and
+//! need it for as long as they live. While training, the dictionary of the highest version
+//! compresses new values, and a new version is trained every time the sampled values amount to
+//! 100 times the size of a dictionary, up to 16 versions.
+//!
+//! A dictionary file is written durably before any value compressed with it.
+
+use crate::Compression;
+use std::path::Path;
+
+#[cfg(feature = "zstd")]
+use {
+    parking_lot::{Mutex, RwLock},
+    std::{collections::HashMap, io::Write as _, path::PathBuf, sync::Arc},
+    zstd::dict::{DecoderDictionary, EncoderDictionary},
+};
+
+/// The prefix of the names of dictionary files, which is followed by the version.
+#[cfg(feature = "zstd")]
+const FILE_PREFIX: &str = "zstd_dict_";
+
+/// The size of the sampled values a dictionary is trained over, per byte of the dictionary.
+#[cfg(feature = "zstd")]
+const SAMPLES_PER_BYTE: usize = 100;
+
+/// The maximum number of versions trained for a database.
+#[cfg(feature = "zstd")]
+const MAX_VERSIONS: u32 = 16;
+
+/// Only the beginning of large values is sampled, which is where a dictionary helps the most.
+#[cfg(feature = "zstd")]
+const MAX_SAMPLE_SIZE: usize = 16 * 1024;
+
+/// The zstd dictionaries of a database. This is cheap to clone.
+///
+/// Empty without the `zstd` feature.
+#[derive(Clone, Default)]
+pub struct Dictionaries {
+    #[cfg(feature = "zstd")]
+    inner: Option<Arc<Inner>>,
+}
+
+#[cfg(feature = "zstd")]
+struct Inner {
+    db_dir: PathBuf,
+    decoders: RwLock<HashMap<u32, Arc<DecoderDictionary<'static>>>>,
+    /// The version and the dictionary new values are compressed with.
+    encoder: RwLock<Option<(u32, Arc<EncoderDictionary<'static>>)>>,
+    /// `None` unless dictionaries are trained.
+    training: Option<Mutex<Training>>,
+}
+
+#[cfg(feature = "zstd")]
+struct Training {
+    max_size: usize,
+    level: i32,
+    samples: Vec<u8>,
+    sample_sizes: Vec<usize>,
+    next_version: u32,
+}
+
+#[cfg(not(feature = "zstd"))]
+impl Dictionaries {
+    /// Without the `zstd` feature, there are no dictionaries to load.
+    pub fn open(_db_dir: &Path, _compression: Compression) -> std::io::Result<Self> {
+        Ok(Dictionaries {})
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Dictionaries {
+    /// Load the dictionaries stored in the database directory. If values are compressed with zstd
+    /// and a dictionary size, new values are compressed with the latest dictionary and sampled to
+    /// train the next.
+    pub fn open(db_dir: &Path, compression: Compression) -> std::io::Result<Self> {
+        let training = match compression {
+            Compression::Zstd {
+                level,
+                dictionary_size,
+            } if dictionary_size > 0 => Some((dictionary_size, level)),
+            _ => None,
+        };
+
+        let mut dictionaries = Vec::new();
+        for entry in std::fs::read_dir(db_dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(version) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(FILE_PREFIX))
+                .and_then(|version| version.parse::<u32>().ok())
+            else {
+                continue;
+            };
+            dictionaries.push((version, std::fs::read(entry.path())?));
+        }
+        dictionaries.sort_by_key(|(version, _)| *version);
+
+        let decoders = dictionaries
+            .iter()
+            .map(|(version, dictionary)| (*version, Arc::new(DecoderDictionary::copy(dictionary))))
+            .collect();
+        let latest = dictionaries.last();
+        let encoder = latest
+            .zip(training)
+            .map(|((version, dictionary), (_, level))| {
+                (
+                    *version,
+                    Arc::new(EncoderDictionary::copy(dictionary, level)),
+                )
+            });
+        let next_version = latest.map_or(1, |(version, _)| version + 1);
+        let training =
+            training
+                .filter(|_| next_version <= MAX_VERSIONS)
+                .map(|(max_size, level)| {
+                    Mutex::new(Training {
+                        max_size,
+                        level,
+                        samples: Vec::new(),
+                        sample_sizes: Vec::new(),
+                        next_version,
+                    })
+                });
+
+        Ok(Dictionaries {
+            inner: Some(Arc::new(Inner {
+                db_dir: db_dir.to_path_buf(),
+                decoders: RwLock::new(decoders),
+                encoder: RwLock::new(encoder),
+                training,
+            })),
+        })
+    }
+
+    /// Compress a value with the latest dictionary, sampling it for the training of the next.
+    ///
+    /// Returns the version of the dictionary and the compressed bytes, or `None` if there is no
+    /// dictionary yet. Fails if a newly trained dictionary can't be stored.
+    pub fn compress(&self, value: &[u8]) -> std::io::Result<Option<(u32, Vec<u8>)>> {
+        let Some(ref inner) = self.inner else {
+            return Ok(None);
+        };
+        if let Some(ref training) = inner.training {
+            inner.sample(training, value)?;
+        }
+
+        let Some((version, encoder)) = inner.encoder.read().clone() else {
+            return Ok(None);
+        };
+        let mut compressor = zstd::bulk::Compressor::with_prepared_dictionary(&encoder)?;
+        Ok(Some((version, compressor.compress(value)?)))
+    }
+
+    /// Decompress a value of the given size, compressed with the dictionary of the given version.
+    pub fn decompress(
+        &self,
+        version: u32,
+        compressed: &[u8],
+        value_size: usize,
+    ) -> std::io::Result<Vec<u8>> {
+        let decoder = self
+            .inner
+            .as_ref()
+            .and_then(|inner| inner.decoders.read().get(&version).cloned())
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("no zstd dictionary of version {version}"),
+                )
+            })?;
+        let mut decompressor = zstd::bulk::Decompressor::with_prepared_dictionary(&decoder)?;
+        decompressor.decompress(compressed, value_size)
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Inner {
+    fn sample(&self, training: &Mutex<Training>, value: &[u8]) -> std::io::Result<()> {
+        let mut training = training.lock();
+        if training.next_version > MAX_VERSIONS {
+            return Ok(());
+        }
+        let sample = &value[..std::cmp::min(value.len(), MAX_SAMPLE_SIZE)];
+        training.samples.extend_from_slice(sample);
+        training.sample_sizes.push(sample.len());
+        if training.samples.len() < training.max_size * SAMPLES_PER_BYTE {
+            return Ok(());
+        }
+
+        let samples = std::mem::take(&mut training.samples);
+        let sample_sizes = std::mem::take(&mut training.sample_sizes);
+        let Ok(dictionary) =
+            zstd::dict::from_continuous(&samples, &sample_sizes, training.max_size)
+        else {
+            // The samples are too alike or too few to train over. Keep the current dictionary.
+            return Ok(());
+        };
+
+        let version = training.next_version;
+        self.store(version, &dictionary)?;
+        training.next_version += 1;
+
+        self.decoders
+            .write()
+            .insert(version, Arc::new(DecoderDictionary::copy(&dictionary)));
+        let encoder = EncoderDictionary::copy(&dictionary, training.level);
+        *self.encoder.write() = Some((version, Arc::new(encoder)));
+        Ok(())
+    }
+
+    /// Durably write a dictionary to the database directory.
+    fn store(&self, version: u32, dictionary: &[u8]) -> std::io::Result<()> {
+        let path = self.db_dir.join(format!("{FILE_PREFIX}{version}"));
+        let tmp_path = path.with_extension("tmp");
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(dictionary)?;
+        file.sync_all()?;
+        drop(file);
+
+        std::fs::rename(&tmp_path, &path)?;
+        crate::sys::sync_dir_path(&self.db_dir)
+    }
+}
+
+#[cfg(all(test, feature = "zstd"))]
+mod tests {
+    use super::Dictionaries;
+    use crate::Compression;
+
+    fn value(i: usize) -> Vec<u8> {
+        format!(
+            "{{\"owner\": \"account-{i}\", \"balance\": {}, \"nonce\": {}}}",
+            i * 7,
+            i % 13
+        )
+        .repeat(40)
+        .into_bytes()
+    }
+
+    #[test]
+    fn trained_dictionaries_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let compression = Compression::Zstd {
+            level: 3,
+            dictionary_size: 1024,
+        };
+        let dictionaries = Dictionaries::open(dir.path(), compression).unwrap();
+
+        // Values are compressed without a dictionary until enough of them are sampled.
+        let mut i = 0;
+        while dictionaries.compress(&value(i)).unwrap().is_none() {
+            i += 1;
+            assert!(i < 10_000);
+        }
+        let (version, compressed) = dictionaries.compress(&value(i)).unwrap().unwrap();
+        assert_eq!(version, 1);
+        assert!(dir.path().join("zstd_dict_1").is_file());
+
+        // Reopened without training, the dictionary still decompresses its values.
+        let reopened = Dictionaries::open(dir.path(), Compression::None).unwrap();
+        assert_eq!(reopened.compress(&value(i)).unwrap(), None);
+        let decompressed = reopened
+            .decompress(version, &compressed, value(i).len())
+            .unwrap();
+        assert_eq!(decompressed, value(i));
+        assert!(reopened.decompress(2, &compressed, value(i).len()).is_err());
+    }
+}
@@ -43,6 +43,16 @@ impl Index {
         self.first_key_map.insert(separator, branch)
     }
 
+    /// Iterate over the branches in ascending order of their separators.
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Arc<BranchNode>)> {
+        self.first_key_map.iter()
+    }
+
+    /// Iterate over the branches with separators from the given key on, in ascending order.
+    pub fn iter_from(&self, key: Key) -> impl Iterator<Item = (&Key, &Arc<BranchNode>)> {
+        self.first_key_map.range(key..)
+    }
+
     #[cfg(test)]
     pub fn into_iter(self) -> impl Iterator<Item = (Key, Arc<BranchNode>)> {
         self.first_key_map.into_iter()
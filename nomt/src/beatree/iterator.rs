@@ -154,7 +154,6 @@ pub enum IterOutput<'a> {
     // The iterator has produced a new item.
     Item(Key, &'a [u8]),
     // The iterator has produced a new overflow item. The slice here is the entire overflow cell.
-    #[allow(dead_code)]
     OverflowItem(Key, ValueHash, &'a [u8]),
 }
 
@@ -7,6 +7,7 @@
 /// cells: [Cell; n]
 /// value cell: [u8]
 /// overflow cell: (u64, u256, [NodePointer]) | semantically, (value_size, value_hash, [NodePointer]).
+///   the most significant byte of value_size holds the codec of the value, see `ops::overflow`.
 /// ```
 ///
 /// | n | [(key ++ offset); n] | ----  | [[u8]; n] |
@@ -99,29 +100,34 @@ impl LeafNode {
         (start..end, overflow)
     }
 
+    /// Whether the cell pointers fit the node, and the cells follow them in order without
+    /// overlapping.
+    pub fn is_well_formed(&self) -> bool {
+        if self.n() * 34 >= LEAF_NODE_BODY_SIZE {
+            return false;
+        }
+        let cell_pointers = self.cell_pointers();
+        let mut prev_end = 2 + cell_pointers.len() * 34;
+        (0..cell_pointers.len()).all(|i| {
+            let start = cell_offset(cell_pointers, i).0;
+            let ordered = start >= prev_end && start <= PAGE_SIZE;
+            prev_end = start;
+            ordered
+        })
+    }
+
     pub fn cell_pointers(&self) -> &[[u8; 34]] {
         let cell_pointers_end = self.n() * 34;
         assert!(cell_pointers_end < LEAF_NODE_BODY_SIZE);
 
-        // SAFETY: This creates a slice of length 34 * N starting at index 2. This is ensured
-        // to be within the bounds by the assertion above.
-        unsafe {
-            std::slice::from_raw_parts(self.inner[2..36].as_ptr() as *const [u8; 34], self.n())
-        }
+        self.inner[2..2 + cell_pointers_end].as_chunks().0
     }
 
     fn cell_pointers_mut(&mut self) -> &mut [[u8; 34]] {
         let cell_pointers_end = self.n() * 34;
         assert!(cell_pointers_end < LEAF_NODE_BODY_SIZE);
 
-        // SAFETY: This creates a slice of length 34 * N starting at index 2. This is ensured
-        // to be within the bounds by the assertion above.
-        unsafe {
-            std::slice::from_raw_parts_mut(
-                self.inner[2..36].as_mut_ptr() as *mut [u8; 34],
-                self.n(),
-            )
-        }
+        self.inner[2..2 + cell_pointers_end].as_chunks_mut().0
     }
 }
 
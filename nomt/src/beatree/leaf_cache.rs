@@ -2,6 +2,7 @@
 
 use crate::{
     beatree::{allocator::PageNumber, leaf::node::LeafNode},
+    chaos::Chaos,
     io::PAGE_SIZE,
 };
 use lru::LruCache;
@@ -18,8 +19,8 @@ pub struct LeafCache {
 
 impl LeafCache {
     /// Create a new cache with the given number of shards and the maximum number of items
-    /// to hold. `shards` must be non-zero.
-    pub fn new(shards: usize, leaf_cache_size: usize) -> Self {
+    /// to hold. `shards` must be non-zero. Shards evict extra items at random in chaos mode.
+    pub fn new(shards: usize, leaf_cache_size: usize, chaos: Chaos) -> Self {
         let max_items = (leaf_cache_size * 1024 * 1024) / PAGE_SIZE;
         let items_per_shard = max_items / shards;
         LeafCache {
@@ -32,6 +33,7 @@ impl LeafCache {
                     .map(Mutex::new)
                     .collect::<Vec<_>>(),
                 shard_assigner: RandomState::new(),
+                chaos,
             }),
         }
     }
@@ -40,14 +42,21 @@ impl LeafCache {
     pub fn get(&self, page_number: PageNumber) -> Option<Arc<LeafNode>> {
         let mut shard = self.inner.shard_for(page_number);
 
-        shard.cache.get(&page_number).map(|x| x.clone())
+        shard.cache.get(&page_number).map(|x| x.node.clone())
     }
 
     /// Insert a cache entry. This does not evict anything.
     pub fn insert(&self, page_number: PageNumber, node: Arc<LeafNode>) {
         let mut shard = self.inner.shard_for(page_number);
 
-        shard.cache.put(page_number, node);
+        shard.cache.put(page_number, CacheEntry::new(node));
+    }
+
+    /// Remove a cache entry, if present.
+    pub fn remove(&self, page_number: PageNumber) {
+        let mut shard = self.inner.shard_for(page_number);
+
+        let _ = shard.cache.pop(&page_number);
     }
 
     /// Evict all excess items from the cache.
@@ -57,6 +66,88 @@ impl LeafCache {
             while shard.cache.len() > shard.max_items {
                 let _ = shard.cache.pop_lru();
             }
+            for _ in 0..self.inner.chaos.extra_evictions(shard.cache.len()) {
+                let _ = shard.cache.pop_lru();
+            }
+        }
+    }
+
+    /// The number of items in the cache.
+    pub fn len(&self) -> usize {
+        self.inner
+            .shards
+            .iter()
+            .map(|shard| shard.lock().cache.len())
+            .sum()
+    }
+
+    /// Remove all items from the cache.
+    pub fn clear(&self) {
+        for shard in &self.inner.shards {
+            shard.lock().cache.clear();
+        }
+    }
+
+    /// At most `limit` of the cached leaves, from most to least recently used, without updating
+    /// the LRU state.
+    pub fn hottest(&self, limit: usize) -> Vec<(PageNumber, Arc<LeafNode>)> {
+        let per_shard = self
+            .inner
+            .shards
+            .iter()
+            .map(|shard| {
+                let shard = shard.lock();
+                shard
+                    .cache
+                    .iter()
+                    .take(limit)
+                    .map(|(page_number, entry)| (*page_number, entry.node.clone()))
+                    .collect()
+            })
+            .collect();
+        crate::cache_file::round_robin(per_shard, limit)
+    }
+
+    /// List the cached leaves, without updating the LRU state.
+    #[cfg(feature = "cache-debug")]
+    pub fn snapshot(&self) -> Vec<crate::cache_debug::CachedLeaf> {
+        let mut leaves = Vec::new();
+        for shard in &self.inner.shards {
+            let shard = shard.lock();
+            leaves.extend(shard.cache.iter().map(|(page_number, entry)| {
+                let node = &entry.node;
+                let n = node.n();
+                let size = if n == 0 {
+                    2
+                } else {
+                    2 + crate::beatree::leaf::node::body_size(n, node.values_size(0, n))
+                };
+                crate::cache_debug::CachedLeaf {
+                    page_number: page_number.0,
+                    key_range: (n > 0).then(|| (node.key(0), node.key(n - 1))),
+                    entries: n,
+                    size,
+                    age: entry.inserted.elapsed(),
+                }
+            }));
+        }
+        leaves.sort_by_key(|leaf| leaf.key_range);
+        leaves
+    }
+}
+
+struct CacheEntry {
+    node: Arc<LeafNode>,
+    #[cfg(feature = "cache-debug")]
+    inserted: std::time::Instant,
+}
+
+impl CacheEntry {
+    fn new(node: Arc<LeafNode>) -> Self {
+        CacheEntry {
+            node,
+            #[cfg(feature = "cache-debug")]
+            inserted: std::time::Instant::now(),
         }
     }
 }
@@ -64,6 +155,7 @@ impl LeafCache {
 struct Shared {
     shards: Vec<Mutex<Shard>>,
     shard_assigner: RandomState,
+    chaos: Chaos,
 }
 
 impl Shared {
@@ -77,6 +169,6 @@ impl Shared {
 }
 
 struct Shard {
-    cache: LruCache<PageNumber, Arc<LeafNode>>,
+    cache: LruCache<PageNumber, CacheEntry>,
     max_items: usize,
 }
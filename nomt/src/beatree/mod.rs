@@ -4,22 +4,34 @@ use branch::BRANCH_NODE_SIZE;
 use crossbeam_channel::{Receiver, Sender};
 use imbl::OrdMap;
 
-use leaf::node::MAX_LEAF_VALUE_SIZE;
+use leaf::node::{LeafNode, MAX_LEAF_VALUE_SIZE};
 use nomt_core::trie::ValueHash;
 use ops::overflow;
 use parking_lot::{ArcMutexGuard, Condvar, Mutex, RwLock};
-use std::{fs::File, mem, path::Path, sync::Arc};
+use std::{
+    collections::HashSet,
+    fs::File,
+    mem,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 use threadpool::ThreadPool;
 
 use crate::{
     io::{fsyncer::Fsyncer, FatPage, IoHandle, IoPool, PagePool},
+    metrics::Metrics,
     task::{join_task, spawn_task, TaskResult},
+    Compression,
 };
 
 pub mod iterator;
 
 mod allocator;
 mod branch;
+mod dictionary;
 mod index;
 mod leaf;
 mod leaf_cache;
@@ -28,9 +40,13 @@ mod ops;
 mod writeout;
 
 pub use allocator::PageNumber;
+pub use dictionary::Dictionaries;
 use index::Index;
 pub use iterator::BeatreeIterator;
 use leaf_cache::LeafCache;
+pub use ops::clip_range;
+pub use ops::overflow::cell_pages as overflow_cell_pages;
+pub use ops::Relocations;
 
 #[cfg(feature = "benchmarks")]
 pub mod benches;
@@ -61,13 +77,17 @@ struct Shared {
     /// if there is no sync in progress.
     secondary_staging: Option<OrdMap<Key, ValueChange>>,
     leaf_cache: leaf_cache::LeafCache,
+    /// The state of the stores as of the last sync, which an aborted sync returns to.
+    synced: SyncData,
 }
 
 struct Sync {
     tp: ThreadPool,
-    commit_concurrency: usize,
+    sync_workers: usize,
+    metrics: Metrics,
     bbn_fsync: Arc<Fsyncer>,
     ln_fsync: Arc<Fsyncer>,
+    compression: Compression,
 }
 
 impl Shared {
@@ -89,8 +109,10 @@ impl Tree {
         bbn_bump: u32,
         bbn_file: Arc<File>,
         ln_file: Arc<File>,
-        commit_concurrency: usize,
+        sync_workers: usize,
         leaf_cache_size: usize,
+        compression: Compression,
+        dictionaries: Dictionaries,
     ) -> Result<Tree> {
         let ln_freelist_pn = Some(ln_freelist_pn)
             .map(PageNumber)
@@ -102,9 +124,12 @@ impl Tree {
         let ln_bump = PageNumber(ln_bump);
         let bbn_bump = PageNumber(bbn_bump);
 
-        let leaf_store = Store::open(&page_pool, ln_file.clone(), ln_bump, ln_freelist_pn)?;
+        let leaf_store = Store::open(&page_pool, ln_file.clone(), ln_bump, ln_freelist_pn)?
+            .with_mapping(io_pool.mapped_file(&ln_file))
+            .with_dictionaries(dictionaries);
 
-        let bbn_store = Store::open(&page_pool, bbn_file.clone(), bbn_bump, bbn_freelist_pn)?;
+        let bbn_store = Store::open(&page_pool, bbn_file.clone(), bbn_bump, bbn_freelist_pn)?
+            .with_mapping(io_pool.mapped_file(&bbn_file));
 
         let bbn_freelist_tracked = bbn_store.all_tracked_freelist_pages();
         let index = ops::reconstruct(
@@ -123,15 +148,24 @@ impl Tree {
             bbn_store,
             primary_staging: OrdMap::new(),
             secondary_staging: None,
-            leaf_cache: leaf_cache::LeafCache::new(32, leaf_cache_size),
+            leaf_cache: leaf_cache::LeafCache::new(32, leaf_cache_size, io_pool.chaos().clone()),
+            synced: SyncData {
+                ln_freelist_pn: ln_freelist_pn.unwrap_or(FREELIST_EMPTY).0,
+                ln_bump: ln_bump.0,
+                bbn_freelist_pn: bbn_freelist_pn.unwrap_or(FREELIST_EMPTY).0,
+                bbn_bump: bbn_bump.0,
+                pages_written: 0,
+            },
         };
 
         let sync = Sync {
             // +1 for the begin_sync task.
-            tp: ThreadPool::with_name("beatree-sync".into(), commit_concurrency + 1),
-            commit_concurrency,
+            tp: ThreadPool::with_name("beatree-sync".into(), sync_workers + 1),
+            sync_workers,
+            metrics: io_pool.metrics().clone(),
             bbn_fsync: Arc::new(Fsyncer::new("bbn", bbn_file)),
             ln_fsync: Arc::new(Fsyncer::new("ln", ln_file)),
+            compression,
         };
 
         Ok(Tree {
@@ -142,17 +176,17 @@ impl Tree {
     }
 
     /// Lookup a key in the btree. This blocks the current thread.
-    pub fn lookup(&self, key: Key) -> Option<Vec<u8>> {
+    pub fn lookup(&self, key: Key) -> std::io::Result<Option<Vec<u8>>> {
         let shared = self.shared.read();
 
         // First look up in the primary staging which contains the most recent changes.
         if let Some(val) = shared.primary_staging.get(&key) {
-            return val.as_option().map(|v| v.to_vec());
+            return Ok(val.as_option().map(|v| v.to_vec()));
         }
 
         // Then check the secondary staging which is a bit older, but fresher still than the btree.
         if let Some(val) = shared.secondary_staging.as_ref().and_then(|x| x.get(&key)) {
-            return val.as_option().map(|v| v.to_vec());
+            return Ok(val.as_option().map(|v| v.to_vec()));
         }
 
         // Finally, look up in the btree.
@@ -162,7 +196,201 @@ impl Tree {
             &shared.leaf_cache,
             &shared.leaf_store_rd,
         )
-        .unwrap()
+    }
+
+    /// Lookup the hash of the value of a key which is stored elsewhere. `None` if the key has no
+    /// value or its value is stored in the btree. This blocks the current thread.
+    ///
+    /// See [`ValueChange::hash_only`].
+    pub fn lookup_hash_only(&self, key: Key) -> std::io::Result<Option<ValueHash>> {
+        let shared = self.shared.read();
+
+        let staged = shared
+            .primary_staging
+            .get(&key)
+            .or_else(|| shared.secondary_staging.as_ref().and_then(|x| x.get(&key)));
+        if let Some(val) = staged {
+            return Ok(val.hash_only_value());
+        }
+
+        ops::lookup_hash_only_blocking(
+            key,
+            &shared.bbn_index,
+            &shared.leaf_cache,
+            &shared.leaf_store_rd,
+        )
+    }
+
+    /// Lookup the bytes within the given range of the value of a key in the btree. This blocks the
+    /// current thread.
+    ///
+    /// The range is clipped to the size of the value. Only the pages holding the range are read.
+    pub fn lookup_slice(
+        &self,
+        key: Key,
+        range: std::ops::Range<usize>,
+    ) -> std::io::Result<Option<Vec<u8>>> {
+        let shared = self.shared.read();
+
+        let staged = shared
+            .primary_staging
+            .get(&key)
+            .or_else(|| shared.secondary_staging.as_ref().and_then(|x| x.get(&key)));
+        if let Some(val) = staged {
+            return Ok(val
+                .as_option()
+                .map(|v| v[ops::clip_range(range, v.len())].to_vec()));
+        }
+
+        ops::lookup_slice_blocking(
+            key,
+            range,
+            &shared.bbn_index,
+            &shared.leaf_cache,
+            &shared.leaf_store_rd,
+        )
+    }
+
+    /// The raw FDs of the leaf and branch node stores, which pages are written to.
+    pub fn store_fds(&self) -> (crate::sys::RawFd, crate::sys::RawFd) {
+        let shared = self.shared.read();
+        (shared.leaf_store.store_fd(), shared.bbn_store.store_fd())
+    }
+
+    /// Remove all leaves from the leaf cache.
+    pub fn clear_leaf_cache(&self) {
+        self.shared.read().leaf_cache.clear()
+    }
+
+    /// The number of leaves in the leaf cache.
+    pub fn leaf_cache_len(&self) -> usize {
+        self.shared.read().leaf_cache.len()
+    }
+
+    /// Remove the leaves holding the `cold` keys from the leaf cache, except for those which also
+    /// hold any of the `hot` keys. Returns the number of leaves removed.
+    ///
+    /// This is as of the last sync: leaves with staged changes are not affected.
+    pub fn uncache_leaves(&self, cold: &[Key], hot: &[Key]) -> usize {
+        let shared = self.shared.read();
+        let leaf_pn = |key: &Key| ops::partial_lookup(*key, &shared.bbn_index);
+        let hot = hot.iter().filter_map(leaf_pn).collect::<HashSet<_>>();
+        let cold = cold
+            .iter()
+            .filter_map(leaf_pn)
+            .filter(|pn| !hot.contains(pn))
+            .collect::<HashSet<_>>();
+        for pn in &cold {
+            shared.leaf_cache.remove(*pn);
+        }
+        cold.len()
+    }
+
+    /// List the leaves held in the leaf cache.
+    #[cfg(feature = "cache-debug")]
+    pub fn leaf_cache_snapshot(&self) -> Vec<crate::cache_debug::CachedLeaf> {
+        self.shared.read().leaf_cache.snapshot()
+    }
+
+    /// Copy at most `limit` of the cached leaves, from most to least recently used, along with
+    /// their page numbers.
+    pub fn hottest_leaves(&self, limit: usize) -> Vec<(u32, FatPage)> {
+        let leaves = self.shared.read().leaf_cache.hottest(limit);
+        leaves
+            .into_iter()
+            .map(|(page_number, leaf)| (page_number.0, leaf.inner.clone()))
+            .collect()
+    }
+
+    /// Get at most `limit` of the keys in the cached leaves, those of the most recently used
+    /// leaves first.
+    pub fn hottest_keys(&self, limit: usize) -> Vec<Key> {
+        let leaves = self.shared.read().leaf_cache.hottest(limit);
+        leaves
+            .iter()
+            .flat_map(|(_, leaf)| (0..leaf.n()).map(|i| leaf.key(i)))
+            .take(limit)
+            .collect()
+    }
+
+    /// Load leaves read from the cache file, ordered from hottest to coldest, into the leaf
+    /// cache.
+    pub fn restore_leaf_cache(&self, leaves: Vec<(u32, FatPage)>) {
+        let leaf_cache = self.shared.read().leaf_cache.clone();
+        // Insert the coldest leaves first, so that the hottest are the most recently used.
+        for (page_number, page) in leaves.into_iter().rev() {
+            leaf_cache.insert(PageNumber(page_number), Arc::new(LeafNode { inner: page }));
+        }
+        leaf_cache.evict();
+    }
+
+    /// Check the bottom-level branch nodes and the leaf nodes they refer to, recording the
+    /// inconsistencies found in the report. This reads every leaf node with blocking I/O.
+    ///
+    /// Must not be called concurrently with a sync.
+    pub fn check_integrity(&self, report: &mut crate::integrity::IntegrityReport) {
+        let shared = self.shared.read();
+        let bbn_index = shared.bbn_index.clone();
+        let leaf_store = shared.leaf_store_rd.clone();
+        let freed = shared.leaf_store.all_tracked_freelist_pages();
+        let bump = PageNumber(shared.synced.ln_bump);
+        drop(shared);
+
+        ops::check_integrity(&bbn_index, &leaf_store, &freed, bump, report);
+    }
+
+    /// Get the number of leaves, as of the last sync.
+    pub fn leaf_count(&self) -> usize {
+        let shared = self.shared.read();
+        shared
+            .bbn_index
+            .iter()
+            .map(|(_, branch)| branch.n() as usize)
+            .sum()
+    }
+
+    /// Get the total length of the leaf and branch store files, in bytes.
+    pub fn file_len(&self) -> std::io::Result<u64> {
+        let shared = self.shared.read();
+        Ok(shared.leaf_store.file_len()? + shared.bbn_store.file_len()?)
+    }
+
+    /// Find the values to rewrite to move the pages beyond the number of pages in use in either
+    /// store, scanning at least `max_leaves` leaves from the branch with the separator `start` on.
+    /// See `ops::relocations`.
+    ///
+    /// Must not be called concurrently with a sync, or with changes staged.
+    pub fn relocations(&self, start: Key, max_leaves: usize) -> Relocations {
+        let shared = self.shared.read();
+        let ln_limit = shared.leaf_store.space().used();
+        let bbn_limit = shared.bbn_store.space().used();
+        let bbn_index = shared.bbn_index.clone();
+        let leaf_cache = shared.leaf_cache.clone();
+        let leaf_store = shared.leaf_store_rd.clone();
+        drop(shared);
+
+        ops::relocations(
+            &bbn_index,
+            &leaf_cache,
+            &leaf_store,
+            start,
+            max_leaves,
+            ln_limit,
+            bbn_limit,
+        )
+    }
+
+    /// Make the next sync compact the free-lists of both stores and shrink the store files by the
+    /// free pages at their ends. See `allocator::Store::compact_next_sync`.
+    pub fn compact_next_sync(&self) {
+        let shared = self.shared.read();
+        shared.leaf_store.compact_next_sync();
+        shared.bbn_store.compact_next_sync();
+    }
+
+    /// Stage changes to be written by the next sync. They are visible to lookups right away.
+    pub fn stage(&self, changeset: impl IntoIterator<Item = (Key, ValueChange)>) {
+        Tree::commit(&self.shared, changeset);
     }
 
     /// Returns a controller for the sync process. This is blocked by other `sync`s running as well
@@ -181,6 +409,7 @@ impl Tree {
                 sync_data: Mutex::new(None),
                 bbn_index: Mutex::new(None),
                 pre_swap_rx: Mutex::new(None),
+                unsettled: AtomicBool::new(false),
             }),
             begin_sync_result_tx: Some(begin_sync_result_tx),
             begin_sync_result_rx,
@@ -233,7 +462,7 @@ impl Tree {
         sync: &Sync,
         shared: &Arc<RwLock<Shared>>,
         read_transaction_counter: &ReadTransactionCounter,
-    ) -> std::io::Result<(SyncData, Index, Receiver<TaskResult<()>>)> {
+    ) -> Result<(SyncData, Index, Receiver<TaskResult<()>>), ops::UpdateError> {
         // Take the shared lock. Briefly.
         let staged_changeset;
         let bbn_index;
@@ -315,16 +544,43 @@ impl Tree {
                 page_pool,
                 io_handle,
                 sync.tp.clone(),
-                sync.commit_concurrency,
+                sync.sync_workers,
+                sync.compression,
+                &sync.metrics,
             )
         }
     }
 
-    fn finish_sync(shared: &Arc<RwLock<Shared>>, bbn_index: Index) {
+    fn finish_sync(shared: &Arc<RwLock<Shared>>, bbn_index: Index, sync_data: SyncData) {
         // Take the shared lock again to complete the update to the new shared state
         let mut shared = shared.write();
         shared.secondary_staging = None;
         shared.bbn_index = bbn_index;
+        shared.synced = sync_data;
+    }
+
+    fn abort_sync(shared: &Arc<RwLock<Shared>>) -> anyhow::Result<()> {
+        let mut shared = shared.write();
+
+        // Changes committed after the sync began are more recent than those it was syncing.
+        if let Some(secondary_staging) = shared.secondary_staging.take() {
+            let primary_staging = mem::take(&mut shared.primary_staging);
+            shared.primary_staging = primary_staging.union(secondary_staging);
+        }
+
+        let synced = shared.synced;
+        let free_list_head = |pn| Some(PageNumber(pn)).filter(|&x| x != FREELIST_EMPTY);
+        shared.leaf_store.reset(
+            &shared.page_pool,
+            PageNumber(synced.ln_bump),
+            free_list_head(synced.ln_freelist_pn),
+        )?;
+        shared.bbn_store.reset(
+            &shared.page_pool,
+            PageNumber(synced.bbn_bump),
+            free_list_head(synced.bbn_freelist_pn),
+        )?;
+        Ok(())
     }
 }
 
@@ -336,6 +592,9 @@ pub enum ValueChange {
     /// A new value small enough to fit in a leaf is inserted.
     Insert(Vec<u8>),
     /// A new value which requires an overflow page is inserted.
+    ///
+    /// An empty value stands for a value stored elsewhere, of which only the hash is kept. See
+    /// [`ValueChange::hash_only`].
     InsertOverflow(Vec<u8>, ValueHash),
 }
 
@@ -359,6 +618,22 @@ impl ValueChange {
         }
     }
 
+    /// Create an insertion of a value stored elsewhere, keeping only its hash.
+    ///
+    /// It is stored as an overflow cell without pages, which no value large enough to overflow
+    /// has, and reads as an empty value.
+    pub fn hash_only(value_hash: ValueHash) -> Self {
+        ValueChange::InsertOverflow(Vec::new(), value_hash)
+    }
+
+    /// The hash of the value, if this inserts a value stored elsewhere.
+    pub fn hash_only_value(&self) -> Option<ValueHash> {
+        match self {
+            ValueChange::InsertOverflow(v, value_hash) if v.is_empty() => Some(*value_hash),
+            _ => None,
+        }
+    }
+
     /// Get the value bytes, optionally.
     pub fn as_option(&self) -> Option<&[u8]> {
         match self {
@@ -369,20 +644,23 @@ impl ValueChange {
 }
 
 /// Data generated during update
+#[derive(Clone, Copy)]
 pub struct SyncData {
     pub ln_freelist_pn: u32,
     pub ln_bump: u32,
     pub bbn_freelist_pn: u32,
     pub bbn_bump: u32,
+    /// The number of leaf, branch, overflow and free-list pages written.
+    pub pages_written: usize,
 }
 
-/// Creates the required files for the beatree.
-pub fn create(db_dir: impl AsRef<Path>) -> anyhow::Result<()> {
+/// Creates the leaf and branch files of the beatree at the given paths.
+pub fn create(ln_path: &Path, bbn_path: &Path) -> anyhow::Result<()> {
     // Create the files.
     //
     // Size them to have an empty page at the beginning, this is reserved for the nil page.
-    let ln_fd = File::create(db_dir.as_ref().join("ln"))?;
-    let bbn_fd = File::create(db_dir.as_ref().join("bbn"))?;
+    let ln_fd = File::create(ln_path)?;
+    let bbn_fd = File::create(bbn_path)?;
     ln_fd.set_len(BRANCH_NODE_SIZE as u64)?;
     bbn_fd.set_len(BRANCH_NODE_SIZE as u64)?;
 
@@ -409,8 +687,8 @@ pub fn create(db_dir: impl AsRef<Path>) -> anyhow::Result<()> {
 ///
 /// # Error Handling
 ///
-/// If [`Self::wait_pre_meta`] returns an error, the sync process has failed and the controller
-/// should be discarded.
+/// If [`Self::wait_pre_meta`] returns an error, the sync process has failed. The controller should
+/// be discarded, after calling [`Self::abort`] if the sync is to be attempted again.
 pub struct SyncController {
     // The channel to send the result of the begin sync task. Option is to allow `take`.
     begin_sync_result_tx: Option<Sender<TaskResult<std::io::Result<()>>>>,
@@ -426,6 +704,8 @@ struct SharedSyncController {
     sync_data: Mutex<Option<SyncData>>,
     bbn_index: Mutex<Option<Index>>,
     pre_swap_rx: Mutex<Option<Receiver<TaskResult<()>>>>,
+    /// Set if the sync failed while I/O it issued may still be in flight.
+    unsettled: AtomicBool,
 }
 
 impl SyncController {
@@ -443,7 +723,11 @@ impl SyncController {
             Tree::commit(&inner.shared, changeset);
 
             let (out_meta, out_bbn_index, out_pre_swap_rx) =
-                Tree::prepare_sync(&inner.sync, &inner.shared, &inner.read_transaction_counter)?;
+                Tree::prepare_sync(&inner.sync, &inner.shared, &inner.read_transaction_counter)
+                    .map_err(|e| {
+                        inner.unsettled.store(!e.settled, Ordering::Relaxed);
+                        e.error
+                    })?;
 
             let mut sync_data = inner.sync_data.lock();
             *sync_data = Some(out_meta);
@@ -473,11 +757,13 @@ impl SyncController {
     /// This must be called after [`Self::begin_sync`].
     pub fn wait_pre_meta(&mut self) -> std::io::Result<SyncData> {
         join_task(&self.begin_sync_result_rx)?;
-        self.inner.sync.bbn_fsync.wait()?;
-        self.inner.sync.ln_fsync.wait()?;
+        // Wait for both, so that neither is left outstanding if the other fails.
+        let bbn_fsync = self.inner.sync.bbn_fsync.wait();
+        let ln_fsync = self.inner.sync.ln_fsync.wait();
+        bbn_fsync.and(ln_fsync)?;
 
         // UNWRAP: fsync of bbn and ln above ensures that sync_data is Some.
-        let sync_data = self.inner.sync_data.lock().take().unwrap();
+        let sync_data = self.inner.sync_data.lock().unwrap();
         Ok(sync_data)
     }
 
@@ -489,7 +775,30 @@ impl SyncController {
         let pre_swap_rx = self.inner.pre_swap_rx.lock().take().unwrap();
         join_task(&pre_swap_rx);
         let bbn_index = self.inner.bbn_index.lock().take().unwrap();
-        Tree::finish_sync(&self.inner.shared, bbn_index);
+        let sync_data = self.inner.sync_data.lock().take().unwrap();
+        Tree::finish_sync(&self.inner.shared, bbn_index, sync_data);
+    }
+
+    /// Undo a sync which failed, or is given up, before the manifest was updated, so that it can
+    /// be attempted again with a new controller.
+    ///
+    /// The changes being synced return to staging and the stores return to their state as of the
+    /// last sync, as on reopening. This fails if the sync may have left I/O in flight.
+    ///
+    /// This must be called after [`Self::wait_pre_meta`].
+    pub fn abort(&mut self) -> anyhow::Result<()> {
+        if self.inner.unsettled.load(Ordering::Relaxed) {
+            anyhow::bail!("beatree sync failed with I/O in flight");
+        }
+        // Once the writes have concluded, the new leaves are cached under page numbers which
+        // become free again.
+        if let Some(pre_swap_rx) = self.inner.pre_swap_rx.lock().take() {
+            join_task(&pre_swap_rx);
+            self.inner.shared.read().leaf_cache.clear();
+        }
+        self.inner.bbn_index.lock().take();
+        self.inner.sync_data.lock().take();
+        Tree::abort_sync(&self.inner.shared)
     }
 }
 
@@ -532,6 +841,68 @@ impl ReadTransaction {
         )
     }
 
+    /// Lookup a key as-of this read transaction. This blocks the current thread.
+    pub fn lookup_blocking(&self, key: Key) -> std::io::Result<Option<Vec<u8>>> {
+        if let Some(val) = self.inner.primary_staging.get(&key) {
+            return Ok(val.as_option().map(|v| v.to_vec()));
+        }
+
+        if let Some(val) = self
+            .inner
+            .secondary_staging
+            .as_ref()
+            .and_then(|x| x.get(&key))
+        {
+            return Ok(val.as_option().map(|v| v.to_vec()));
+        }
+
+        ops::lookup_blocking(
+            key,
+            &self.inner.bbn_index,
+            &self.inner.leaf_cache,
+            &self.inner.leaf_store,
+        )
+    }
+
+    /// Lookup the hash of the value of a key which is stored elsewhere as-of this read transaction.
+    /// This blocks the current thread.
+    ///
+    /// See [`Tree::lookup_hash_only`].
+    pub fn lookup_hash_only_blocking(&self, key: Key) -> std::io::Result<Option<ValueHash>> {
+        let staged = self.inner.primary_staging.get(&key).or_else(|| {
+            self.inner
+                .secondary_staging
+                .as_ref()
+                .and_then(|x| x.get(&key))
+        });
+        if let Some(val) = staged {
+            return Ok(val.hash_only_value());
+        }
+
+        ops::lookup_hash_only_blocking(
+            key,
+            &self.inner.bbn_index,
+            &self.inner.leaf_cache,
+            &self.inner.leaf_store,
+        )
+    }
+
+    /// The page number of the leaf which would hold the value of a key as-of this read transaction.
+    ///
+    /// Returns `None` if the key is staged, and so needs no leaf, or is definitely non-existent.
+    pub fn leaf_page_number(&self, key: Key) -> Option<PageNumber> {
+        let staged = self.inner.primary_staging.contains_key(&key)
+            || self
+                .inner
+                .secondary_staging
+                .as_ref()
+                .is_some_and(|x| x.contains_key(&key));
+        if staged {
+            return None;
+        }
+        ops::partial_lookup(key, &self.inner.bbn_index)
+    }
+
     /// Initiate an asynchronous leaf page fetch. This may return immediately if the leaf is cached.
     ///
     /// This is an error-prone, low-level API you should not use unless you know what you are doing.
@@ -685,14 +1056,14 @@ impl AsyncLookup {
     /// returned from `submit` from this lookup. Otherwise, this may panic or silently cause errors.
     ///
     /// This returns `None` if not finished, `Some` otherwise. After returning `Some` once, this
-    /// will return `None` forever.
+    /// will return `None` forever. The result is an error if an overflow value can't be decoded.
     ///
     /// If more lookups are required to finish, this will return an `Err`.
     pub fn try_finish(
         &mut self,
         page: FatPage,
         meta: Option<OverflowPageInfo>,
-    ) -> Option<Option<Vec<u8>>> {
+    ) -> Option<std::io::Result<Option<Vec<u8>>>> {
         match self.state {
             AsyncLookupState::Done => None,
             AsyncLookupState::Initial(ref inner) => {
@@ -700,7 +1071,7 @@ impl AsyncLookup {
                 match ops::finish_lookup_async(self.key, &leaf, &inner.read_tx.leaf_store) {
                     Ok(val) => {
                         self.state = AsyncLookupState::Done;
-                        Some(val)
+                        Some(Ok(val))
                     }
                     Err(overflow) => {
                         self.state = AsyncLookupState::Overflow(overflow, None);
@@ -713,7 +1084,7 @@ impl AsyncLookup {
                 let index = meta
                     .map(|m| m.0)
                     .unwrap_or_else(|| initial_meta.take().unwrap());
-                let res = overflow.complete(index, page).map(Some);
+                let res = overflow.complete(index, page).map(|value| value.map(Some));
 
                 if res.is_some() {
                     self.state = AsyncLookupState::Done;
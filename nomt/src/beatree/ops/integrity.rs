@@ -0,0 +1,79 @@
+//! Checks of the bottom-level branch nodes and the leaf nodes they refer to.
+
+use std::collections::{BTreeSet, HashSet};
+
+use crate::beatree::{
+    allocator::{PageNumber, StoreReader},
+    branch::node::get_key,
+    index::Index,
+    leaf::node::LeafNode,
+    Key,
+};
+use crate::integrity::{Inconsistency, IntegrityReport};
+
+/// Check that the separators of the branch nodes ascend, and that every leaf node is allocated,
+/// referred to once and holds ascending keys within the range of its separator.
+///
+/// `freed` holds the pages tracked by the free-list of the leaf store and `bump` is the first
+/// page number never allocated. This reads every leaf node with blocking I/O.
+pub fn check_integrity(
+    bbn_index: &Index,
+    leaf_store: &StoreReader,
+    freed: &BTreeSet<PageNumber>,
+    bump: PageNumber,
+    report: &mut IntegrityReport,
+) {
+    // The leaves along with their separators, in ascending order.
+    let mut leaves: Vec<(PageNumber, Key)> = Vec::new();
+    let mut prev_separator = None;
+    for (separator, branch) in bbn_index.iter() {
+        report.branch_nodes += 1;
+        let n = branch.n() as usize;
+
+        let mut ordered = n > 0 && get_key(branch, 0) == *separator;
+        for i in 0..n {
+            let key = get_key(branch, i);
+            if prev_separator.is_some_and(|prev| prev >= key) {
+                ordered = false;
+            }
+            prev_separator = Some(key);
+            leaves.push((PageNumber(branch.node_pointer(i)), key));
+        }
+        if !ordered {
+            report.push(Inconsistency::BranchOrder {
+                branch: branch.bbn_pn(),
+            });
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for (i, &(pn, low)) in leaves.iter().enumerate() {
+        report.leaf_nodes += 1;
+        if pn.is_nil() || pn >= bump || freed.contains(&pn) {
+            report.push(Inconsistency::DanglingLeaf { leaf: pn.0 });
+            continue;
+        }
+        if !seen.insert(pn) {
+            report.push(Inconsistency::DuplicateLeaf { leaf: pn.0 });
+            continue;
+        }
+
+        let high = leaves.get(i + 1).map(|&(_, separator)| separator);
+        let leaf = LeafNode {
+            inner: leaf_store.query(pn),
+        };
+        let ordered = leaf.is_well_formed() && {
+            let mut prev = None;
+            (0..leaf.n()).all(|i| {
+                let key = leaf.key(i);
+                let ordered = prev.map_or(key >= low, |prev| key > prev)
+                    && high.is_none_or(|high| key < high);
+                prev = Some(key);
+                ordered
+            })
+        };
+        if !ordered {
+            report.push(Inconsistency::LeafOrder { leaf: pn.0 });
+        }
+    }
+}
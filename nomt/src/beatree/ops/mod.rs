@@ -1,9 +1,8 @@
 //! BTree Operations.
 
-use anyhow::Result;
 use bitvec::prelude::*;
 
-use std::{cmp::Ordering, sync::Arc};
+use std::{cmp::Ordering, ops::Range, sync::Arc};
 
 use super::{
     allocator::{PageNumber, StoreReader},
@@ -15,12 +14,16 @@ use super::{
 };
 
 pub(crate) mod bit_ops;
+mod integrity;
 pub mod overflow;
 mod reconstruction;
+mod relocate;
 mod update;
 
+pub use integrity::check_integrity;
 pub use reconstruction::reconstruct;
-pub use update::update;
+pub use relocate::{relocations, Relocations};
+pub use update::{update, UpdateError};
 
 /// Do a partial lookup of the key in the beatree.
 ///
@@ -42,14 +45,16 @@ pub fn finish_lookup_blocking(
     key: Key,
     leaf: &LeafNode,
     leaf_store: &StoreReader,
-) -> Option<Vec<u8>> {
-    leaf.get(&key).map(|(v, is_overflow)| {
-        if is_overflow {
-            overflow::read_blocking(v, leaf_store)
-        } else {
-            v.to_vec()
-        }
-    })
+) -> std::io::Result<Option<Vec<u8>>> {
+    leaf.get(&key)
+        .map(|(v, is_overflow)| {
+            if is_overflow {
+                overflow::read_blocking(v, leaf_store)
+            } else {
+                Ok(v.to_vec())
+            }
+        })
+        .transpose()
 }
 
 /// Find the associated value associated with the key in the given leaf node, if any.
@@ -62,7 +67,10 @@ pub fn finish_lookup_async(
 ) -> Result<Option<Vec<u8>>, overflow::AsyncReader> {
     leaf.get(&key)
         .map(|(v, is_overflow)| {
-            if is_overflow {
+            if is_overflow && overflow::decode_cell(v).0 == 0 {
+                // a value stored elsewhere has no pages to read.
+                Ok(Vec::new())
+            } else if is_overflow {
                 Err(overflow::AsyncReader::new(v, leaf_store.clone()))
             } else {
                 Ok(v.to_vec())
@@ -71,30 +79,105 @@ pub fn finish_lookup_async(
         .transpose()
 }
 
+/// Clip a range of bytes to a value of the given length.
+pub fn clip_range(range: Range<usize>, len: usize) -> Range<usize> {
+    let start = std::cmp::min(range.start, len);
+    start..range.end.clamp(start, len)
+}
+
+/// Find the bytes within the given range of the value associated with the key in the given leaf
+/// node, if any. The range is clipped to the size of the value.
+///
+/// If the value is an overflow, only the overflow pages holding the range are loaded, with blocking
+/// I/O. A compressed overflow value is loaded whole.
+pub fn finish_lookup_slice_blocking(
+    key: Key,
+    range: Range<usize>,
+    leaf: &LeafNode,
+    leaf_store: &StoreReader,
+) -> std::io::Result<Option<Vec<u8>>> {
+    leaf.get(&key)
+        .map(|(v, is_overflow)| {
+            if is_overflow {
+                overflow::read_slice_blocking(v, range, leaf_store)
+            } else {
+                Ok(v[clip_range(range, v.len())].to_vec())
+            }
+        })
+        .transpose()
+}
+
 /// Lookup a key in the btree using blocking I/O.
 pub fn lookup_blocking(
     key: Key,
     bbn_index: &Index,
     leaf_cache: &LeafCache,
     leaf_store: &StoreReader,
-) -> Result<Option<Vec<u8>>> {
-    let leaf_pn = match partial_lookup(key, bbn_index) {
-        None => return Ok(None),
-        Some(pn) => pn,
+) -> std::io::Result<Option<Vec<u8>>> {
+    let Some(leaf) = load_leaf_blocking(key, bbn_index, leaf_cache, leaf_store)? else {
+        return Ok(None);
+    };
+    finish_lookup_blocking(key, &leaf, leaf_store)
+}
+
+/// Lookup the bytes within the given range of the value of a key in the btree using blocking I/O.
+///
+/// See [`finish_lookup_slice_blocking`].
+pub fn lookup_slice_blocking(
+    key: Key,
+    range: Range<usize>,
+    bbn_index: &Index,
+    leaf_cache: &LeafCache,
+    leaf_store: &StoreReader,
+) -> std::io::Result<Option<Vec<u8>>> {
+    let Some(leaf) = load_leaf_blocking(key, bbn_index, leaf_cache, leaf_store)? else {
+        return Ok(None);
+    };
+    finish_lookup_slice_blocking(key, range, &leaf, leaf_store)
+}
+
+/// Lookup the hash of the value of a key which is stored elsewhere using blocking I/O. See
+/// [`super::ValueChange::hash_only`].
+pub fn lookup_hash_only_blocking(
+    key: Key,
+    bbn_index: &Index,
+    leaf_cache: &LeafCache,
+    leaf_store: &StoreReader,
+) -> std::io::Result<Option<[u8; 32]>> {
+    let Some(leaf) = load_leaf_blocking(key, bbn_index, leaf_cache, leaf_store)? else {
+        return Ok(None);
+    };
+    Ok(match leaf.get(&key) {
+        Some((cell, true)) => match overflow::decode_cell(cell) {
+            (0, value_hash, _) => Some(value_hash),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// Load the leaf which might store the value of the key, through the cache.
+fn load_leaf_blocking(
+    key: Key,
+    bbn_index: &Index,
+    leaf_cache: &LeafCache,
+    leaf_store: &StoreReader,
+) -> std::io::Result<Option<Arc<LeafNode>>> {
+    let Some(leaf_pn) = partial_lookup(key, bbn_index) else {
+        return Ok(None);
     };
 
     let leaf = match leaf_cache.get(leaf_pn) {
         Some(leaf) => leaf,
         None => {
             let leaf = Arc::new(LeafNode {
-                inner: leaf_store.query(leaf_pn),
+                inner: leaf_store.try_query(leaf_pn)?,
             });
             leaf_cache.insert(leaf_pn, leaf.clone());
             leaf
         }
     };
-
-    Ok(finish_lookup_blocking(key, &leaf, leaf_store))
+    Ok(Some(leaf))
 }
 
 /// Binary search a branch node for the child node containing the key. This returns the last child
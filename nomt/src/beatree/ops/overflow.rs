@@ -9,19 +9,170 @@
 //! pointers: [PageNumber; n_pointers]
 //! bytes: [u8; n_bytes]
 //! ```
+//!
+//! A value may be compressed before it is chunked, in which case the pages hold the size of the
+//! value as a little-endian `u32` followed by the compressed bytes. The codec is recorded in the
+//! most significant byte of the value size in the overflow cell, which is zero for values stored
+//! as they are. Values compressed with a zstd dictionary hold the version of the dictionary as a
+//! little-endian `u32` between the size and the compressed bytes.
 use crate::{
     beatree::{
         allocator::{StoreReader, SyncAllocator},
         leaf::node::{MAX_OVERFLOW_CELL_NODE_POINTERS, MAX_OVERFLOW_VALUE_SIZE},
-        PageNumber,
+        Dictionaries, PageNumber,
     },
     io::{page_pool::FatPage, IoCommand, IoHandle, IoKind, PagePool, PAGE_SIZE},
+    Compression,
 };
+use std::ops::Range;
 
 const BODY_SIZE: usize = PAGE_SIZE - 4;
 const MAX_PNS: usize = BODY_SIZE / 4;
 const HEADER_SIZE: usize = 4;
 
+// the codec is stored in the most significant byte of the value size in the cell.
+const CODEC_SHIFT: u32 = 56;
+const VALUE_SIZE_MASK: u64 = (1 << CODEC_SHIFT) - 1;
+
+/// How the bytes of a value are encoded in its overflow pages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    /// The value itself.
+    None,
+    /// The value compressed with LZ4.
+    Lz4,
+    /// The value compressed with zstd.
+    Zstd,
+    /// The value compressed with zstd and a dictionary.
+    ZstdDict,
+}
+
+impl Codec {
+    fn to_byte(self) -> u8 {
+        match self {
+            Codec::None => 0,
+            Codec::Lz4 => 1,
+            Codec::Zstd => 2,
+            Codec::ZstdDict => 3,
+        }
+    }
+
+    fn from_byte(byte: u8) -> std::io::Result<Self> {
+        match byte {
+            0 => Ok(Codec::None),
+            1 => Ok(Codec::Lz4),
+            2 => Ok(Codec::Zstd),
+            3 => Ok(Codec::ZstdDict),
+            _ => Err(invalid_data(format!("unknown overflow value codec {byte}"))),
+        }
+    }
+}
+
+/// Compress a large value, returning the codec and the bytes to store in its overflow pages.
+///
+/// Returns `None` if compression is disabled or does not make the value smaller. Fails if a
+/// dictionary trained over the value can't be stored.
+#[cfg_attr(not(feature = "zstd"), allow(unused_variables))]
+pub fn compress(
+    value: &[u8],
+    compression: Compression,
+    dictionaries: &Dictionaries,
+) -> std::io::Result<Option<(Codec, Vec<u8>)>> {
+    let compressed: Option<(Codec, Vec<u8>)> = match compression {
+        Compression::None => None,
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => Some((Codec::Lz4, lz4_flex::block::compress(value))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd { level, .. } => match dictionaries.compress(value)? {
+            Some((version, compressed)) => {
+                let mut with_version = version.to_le_bytes().to_vec();
+                with_version.extend_from_slice(&compressed);
+                Some((Codec::ZstdDict, with_version))
+            }
+            None => zstd::bulk::compress(value, level)
+                .ok()
+                .map(|compressed| (Codec::Zstd, compressed)),
+        },
+    };
+    let Some((codec, compressed)) = compressed else {
+        return Ok(None);
+    };
+    if 4 + compressed.len() >= value.len() {
+        return Ok(None);
+    }
+
+    let mut stored = Vec::with_capacity(4 + compressed.len());
+    stored.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    stored.extend_from_slice(&compressed);
+    Ok(Some((codec, stored)))
+}
+
+/// Decode the bytes read from the overflow pages of a value, as stored with the given codec.
+///
+/// Fails with [`std::io::ErrorKind::InvalidData`] if the bytes don't decode to a value.
+#[cfg_attr(not(feature = "zstd"), allow(unused_variables))]
+fn decode_value(
+    codec: Codec,
+    stored: Vec<u8>,
+    dictionaries: &Dictionaries,
+) -> std::io::Result<Vec<u8>> {
+    if codec == Codec::None {
+        return Ok(stored);
+    }
+
+    if stored.len() < 4 {
+        return Err(invalid_data(
+            "compressed overflow value without a size".to_string(),
+        ));
+    }
+    let (value_size, compressed) = stored.split_at(4);
+    let value_size = u32::from_le_bytes(value_size.try_into().unwrap()) as usize;
+    if value_size > MAX_OVERFLOW_VALUE_SIZE {
+        return Err(invalid_data(format!(
+            "compressed overflow value of {value_size} bytes"
+        )));
+    }
+    let value = match codec {
+        Codec::None => unreachable!(),
+        Codec::Lz4 => lz4_flex::block::decompress(compressed, value_size)
+            .map_err(|e| invalid_data(format!("corrupted LZ4 overflow value: {e}")))?,
+        #[cfg(feature = "zstd")]
+        Codec::Zstd => zstd::bulk::decompress(compressed, value_size)
+            .map_err(|e| invalid_data(format!("corrupted zstd overflow value: {e}")))?,
+        #[cfg(feature = "zstd")]
+        Codec::ZstdDict => {
+            if compressed.len() < 4 {
+                return Err(invalid_data(
+                    "overflow value without a dictionary version".to_string(),
+                ));
+            }
+            let (version, compressed) = compressed.split_at(4);
+            let version = u32::from_le_bytes(version.try_into().unwrap());
+            dictionaries
+                .decompress(version, compressed, value_size)
+                .map_err(|e| invalid_data(format!("corrupted zstd overflow value: {e}")))?
+        }
+        #[cfg(not(feature = "zstd"))]
+        Codec::Zstd | Codec::ZstdDict => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "overflow value compressed with zstd, but the zstd feature is off",
+            ))
+        }
+    };
+    if value.len() != value_size {
+        return Err(invalid_data(format!(
+            "overflow value decompressed to {} bytes instead of {value_size}",
+            value.len()
+        )));
+    }
+    Ok(value)
+}
+
+fn invalid_data(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
 /// Encode a large value into freshly allocated overflow pages. Returns a vector of page pointers
 /// and the total number of page writes submitted.
 pub fn chunk(
@@ -88,12 +239,16 @@ pub fn chunk(
 }
 
 /// Decode an overflow cell, returning the size of the value plus the pages numbers within the cell.
+///
+/// The size is that of the bytes stored in the pages, which are compressed if the codec of the
+/// cell is not [`Codec::None`].
 pub fn decode_cell<'a>(raw: &'a [u8]) -> (usize, [u8; 32], impl Iterator<Item = PageNumber> + 'a) {
-    // the minimum legal size is the length plus one page pointer plus the value hash.
-    assert!(raw.len() >= 8 + 4 + 32);
+    // the minimum legal size is the length plus the value hash. cells of values stored elsewhere
+    // have no page pointers.
+    assert!(raw.len() >= 8 + 32);
     assert_eq!(raw.len() % 4, 0);
 
-    let value_size = u64::from_le_bytes(raw[0..8].try_into().unwrap()) as usize;
+    let value_size = (u64::from_le_bytes(raw[0..8].try_into().unwrap()) & VALUE_SIZE_MASK) as usize;
     // values bigger than MAX_OVERFLOW_VALUE_SIZE are not allowed.
     assert!(value_size <= MAX_OVERFLOW_VALUE_SIZE);
 
@@ -106,14 +261,35 @@ pub fn decode_cell<'a>(raw: &'a [u8]) -> (usize, [u8; 32], impl Iterator<Item =
     (value_size, value_hash, iter)
 }
 
+/// Get the number of overflow pages holding the value of an overflow cell, including the pages
+/// holding page numbers that don't fit in the cell. Zero for values stored elsewhere.
+pub fn cell_pages(raw: &[u8]) -> usize {
+    let (value_size, _, mut page_numbers) = decode_cell(raw);
+    match page_numbers.next() {
+        None => 0,
+        Some(_) => total_needed_pages(value_size),
+    }
+}
+
+/// Get the codec of the value of an overflow cell. Fails if the codec is unknown.
+pub fn decode_codec(raw: &[u8]) -> std::io::Result<Codec> {
+    Codec::from_byte(raw[7])
+}
+
 /// Encode a list of page numbers into an overflow cell.
-pub fn encode_cell(value_size: usize, value_hash: [u8; 32], pages: &[PageNumber]) -> Vec<u8> {
+pub fn encode_cell(
+    value_size: usize,
+    codec: Codec,
+    value_hash: [u8; 32],
+    pages: &[PageNumber],
+) -> Vec<u8> {
     if value_size > MAX_OVERFLOW_VALUE_SIZE {
         panic!("Value size exceeded MAX_OVERFLOW_VALUE_SIZE");
     }
 
     let mut v = vec![0u8; 8 + 32 + pages.len() * 4];
-    v[0..8].copy_from_slice(&(value_size as u64).to_le_bytes());
+    let header = value_size as u64 | (codec.to_byte() as u64) << CODEC_SHIFT;
+    v[0..8].copy_from_slice(&header.to_le_bytes());
     v[8..40].copy_from_slice(&value_hash);
     for (pn, slice) in pages.iter().zip(v[40..].chunks_mut(4)) {
         slice.copy_from_slice(&pn.0.to_le_bytes());
@@ -178,7 +354,7 @@ fn needed_pages(size: usize) -> usize {
 }
 
 /// Read a large value from pages referenced by an overflow cell using blocking I/O.
-pub fn read_blocking(cell: &[u8], leaf_reader: &StoreReader) -> Vec<u8> {
+pub fn read_blocking(cell: &[u8], leaf_reader: &StoreReader) -> std::io::Result<Vec<u8>> {
     let (value_size, _, cell_pages) = decode_cell(cell);
     let total_pages = total_needed_pages(value_size);
 
@@ -188,7 +364,7 @@ pub fn read_blocking(cell: &[u8], leaf_reader: &StoreReader) -> Vec<u8> {
     page_numbers.extend(cell_pages);
 
     for i in 0..total_pages {
-        let page = leaf_reader.query(page_numbers[i]);
+        let page = leaf_reader.try_query(page_numbers[i])?;
         let (page_pns, bytes) = parse_page(&page);
         page_numbers.extend(page_pns);
         value.extend(bytes);
@@ -197,7 +373,112 @@ pub fn read_blocking(cell: &[u8], leaf_reader: &StoreReader) -> Vec<u8> {
     assert_eq!(page_numbers.len(), total_pages);
     assert_eq!(value.len(), value_size);
 
-    value
+    decode_value(decode_codec(cell)?, value, leaf_reader.dictionaries())
+}
+
+/// The layout of one overflow page of a value, as written by [`chunk`].
+struct PageLayout {
+    // the number of page numbers stored in the page.
+    pointers: usize,
+    // the offset within the value of the first byte stored in the page.
+    value_start: usize,
+    // the number of value bytes stored in the page.
+    bytes: usize,
+}
+
+/// Compute the layout of all the overflow pages of a value of the given size, in order.
+fn page_layout(value_size: usize) -> Vec<PageLayout> {
+    let total_pages = total_needed_pages(value_size);
+    let mut pointers_left = total_pages.saturating_sub(MAX_OVERFLOW_CELL_NODE_POINTERS);
+    let mut value_start = 0;
+
+    (0..total_pages)
+        .map(|_| {
+            let pointers = std::cmp::min(MAX_PNS, pointers_left);
+            let bytes = std::cmp::min(BODY_SIZE - pointers * 4, value_size - value_start);
+            pointers_left -= pointers;
+            let layout = PageLayout {
+                pointers,
+                value_start,
+                bytes,
+            };
+            value_start += bytes;
+            layout
+        })
+        .collect()
+}
+
+/// Read the bytes within the given range of a large value using blocking I/O.
+///
+/// Only the pages holding the bytes, and the pages holding their page numbers, are read, unless the
+/// value is compressed. The range is clipped to the size of the value.
+pub fn read_slice_blocking(
+    cell: &[u8],
+    range: Range<usize>,
+    leaf_reader: &StoreReader,
+) -> std::io::Result<Vec<u8>> {
+    // the bytes of a compressed value are only known once all of it is decompressed.
+    if decode_codec(cell)? != Codec::None {
+        let value = read_blocking(cell, leaf_reader)?;
+        return Ok(value[super::clip_range(range, value.len())].to_vec());
+    }
+
+    let (value_size, _, cell_pages) = decode_cell(cell);
+    let Range { start, end } = super::clip_range(range, value_size);
+    if start == end {
+        return Ok(Vec::new());
+    }
+
+    let layout = page_layout(value_size);
+    let mut needed = layout
+        .iter()
+        .map(|page| page.value_start < end && page.value_start + page.bytes > start)
+        .collect::<Vec<_>>();
+
+    // the page numbers of the pages beyond the cell are stored in the pages before them, in order.
+    // mark the pages holding the page numbers of needed pages as needed, back to front.
+    let mut holders = Vec::with_capacity(layout.len());
+    holders.extend((0..MAX_OVERFLOW_CELL_NODE_POINTERS).map(|_| None));
+    for (i, page) in layout.iter().enumerate() {
+        holders.extend((0..page.pointers).map(|_| Some(i)));
+    }
+    for i in (0..layout.len()).rev() {
+        if let (true, Some(holder)) = (needed[i], holders[i]) {
+            needed[holder] = true;
+        }
+    }
+
+    let mut page_numbers = vec![None; layout.len()];
+    for (slot, pn) in page_numbers.iter_mut().zip(cell_pages) {
+        *slot = Some(pn);
+    }
+
+    let mut value = Vec::with_capacity(end - start);
+    let mut next_pointer = MAX_OVERFLOW_CELL_NODE_POINTERS;
+    for (i, page) in layout.iter().enumerate() {
+        if !needed[i] {
+            next_pointer += page.pointers;
+            continue;
+        }
+
+        // UNWRAP: the holder of a needed page is needed and comes before it.
+        let raw_page = leaf_reader.try_query(page_numbers[i].unwrap())?;
+        let (page_pns, bytes) = parse_page(&raw_page);
+        for pn in page_pns {
+            page_numbers[next_pointer] = Some(pn);
+            next_pointer += 1;
+        }
+        assert_eq!(bytes.len(), page.bytes);
+
+        let from = start.saturating_sub(page.value_start);
+        let to = std::cmp::min(end - page.value_start, page.bytes);
+        if from < to {
+            value.extend_from_slice(&bytes[from..to]);
+        }
+    }
+
+    assert_eq!(value.len(), end - start);
+    Ok(value)
 }
 
 /// A non-blocking reader for an overflow value.
@@ -210,7 +491,8 @@ pub struct AsyncReader {
     process_index: usize,
     store_reader: StoreReader,
     value_size: usize,
-    total_pages: usize,
+    // the raw codec byte of the cell, decoded once the value is read.
+    codec: u8,
 }
 
 impl AsyncReader {
@@ -231,7 +513,7 @@ impl AsyncReader {
             process_index: 0,
             store_reader,
             value_size,
-            total_pages,
+            codec: cell[7],
         }
     }
 
@@ -257,8 +539,9 @@ impl AsyncReader {
     ///
     /// This may panic if the index provided is out of range. Likewise, it may read garbage data.
     ///
-    /// If this returns `Some`, then that is the value and this reader should no longer be used.
-    pub fn complete(&mut self, index: usize, page: FatPage) -> Option<Vec<u8>> {
+    /// If this returns `Some`, then that is the value, or the error decoding it, and this reader
+    /// should no longer be used.
+    pub fn complete(&mut self, index: usize, page: FatPage) -> Option<std::io::Result<Vec<u8>>> {
         self.pages[index].1 = Some(page);
 
         if index == self.process_index {
@@ -266,25 +549,33 @@ impl AsyncReader {
         }
 
         if self.is_done() {
-            assert_eq!(self.pages.len(), self.total_pages);
+            assert_eq!(self.pages.len(), self.total_pages());
             assert_eq!(self.value.len(), self.value_size);
 
-            Some(std::mem::take(&mut self.value))
+            let value = std::mem::take(&mut self.value);
+            Some(
+                Codec::from_byte(self.codec)
+                    .and_then(|codec| decode_value(codec, value, self.store_reader.dictionaries())),
+            )
         } else {
             None
         }
     }
 
+    fn total_pages(&self) -> usize {
+        total_needed_pages(self.value_size)
+    }
+
     fn is_done_requesting(&self) -> bool {
-        self.request_index == self.total_pages
+        self.request_index == self.total_pages()
     }
 
     fn is_done(&self) -> bool {
-        self.process_index == self.total_pages
+        self.process_index == self.total_pages()
     }
 
     fn continue_parse(&mut self) {
-        while self.process_index < self.total_pages {
+        while self.process_index < self.total_pages() {
             let Some(page) = self.pages[self.process_index].1.take() else {
                 break;
             };
@@ -340,10 +631,12 @@ mod tests {
     use crate::beatree::leaf::node::MAX_OVERFLOW_VALUE_SIZE;
 
     use super::{
-        decode_cell, encode_cell, needed_pages, total_needed_pages, PageNumber, BODY_SIZE,
-        MAX_OVERFLOW_CELL_NODE_POINTERS, MAX_PNS,
+        compress, decode_cell, decode_codec, decode_value, encode_cell, needed_pages, page_layout,
+        total_needed_pages, Codec, PageNumber, BODY_SIZE, MAX_OVERFLOW_CELL_NODE_POINTERS, MAX_PNS,
     };
+    use crate::{beatree::Dictionaries, Compression};
     use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
+    use rand::{Rng as _, SeedableRng as _};
 
     #[test]
     fn total_needed_pages_all_in_cell() {
@@ -413,9 +706,40 @@ mod tests {
         assert_eq!(pages1, total_needed_pages(size));
     }
 
+    #[test]
+    fn page_layout_covers_value() {
+        let sizes = [
+            1,
+            BODY_SIZE,
+            BODY_SIZE * MAX_OVERFLOW_CELL_NODE_POINTERS + 1,
+            (BODY_SIZE * MAX_OVERFLOW_CELL_NODE_POINTERS) + (BODY_SIZE * MAX_PNS),
+            1 << 24,
+        ];
+        for size in sizes {
+            let layout = page_layout(size);
+            let total_pages = total_needed_pages(size);
+            assert_eq!(layout.len(), total_pages);
+
+            let mut value_start = 0;
+            for page in &layout {
+                assert_eq!(page.value_start, value_start);
+                assert!(page.pointers * 4 + page.bytes <= BODY_SIZE);
+                value_start += page.bytes;
+            }
+            assert_eq!(value_start, size);
+
+            let pointers = layout.iter().map(|page| page.pointers).sum::<usize>();
+            assert_eq!(
+                pointers,
+                total_pages.saturating_sub(MAX_OVERFLOW_CELL_NODE_POINTERS)
+            );
+        }
+    }
+
     #[derive(Debug, Clone)]
     struct ValidOverflowCell {
         value_size: usize,
+        codec: Codec,
         value_hash: [u8; 32],
         pages: Vec<PageNumber>,
     }
@@ -423,6 +747,9 @@ mod tests {
     impl Arbitrary for ValidOverflowCell {
         fn arbitrary(g: &mut Gen) -> Self {
             let value_size = (usize::arbitrary(g) % MAX_OVERFLOW_VALUE_SIZE) + 1;
+            let codec = *g
+                .choose(&[Codec::None, Codec::Lz4, Codec::Zstd, Codec::ZstdDict])
+                .unwrap();
             let mut value_hash = [0u8; 32];
             for b in value_hash.iter_mut() {
                 *b = u8::arbitrary(g);
@@ -434,6 +761,7 @@ mod tests {
 
             ValidOverflowCell {
                 value_size,
+                codec,
                 value_hash,
                 pages,
             }
@@ -443,12 +771,15 @@ mod tests {
     #[test]
     fn test_encode_decode_cell_roundtrip() {
         fn prop(cell: ValidOverflowCell) -> bool {
-            let encoded = encode_cell(cell.value_size, cell.value_hash, &cell.pages);
+            let encoded = encode_cell(cell.value_size, cell.codec, cell.value_hash, &cell.pages);
             let (decoded_size, decoded_hash, decoded_pages) = decode_cell(&encoded);
 
             let pages_match = decoded_pages.eq(cell.pages.iter().cloned());
 
-            decoded_size == cell.value_size && decoded_hash == cell.value_hash && pages_match
+            decoded_size == cell.value_size
+                && decode_codec(&encoded).unwrap() == cell.codec
+                && decoded_hash == cell.value_hash
+                && pages_match
         }
 
         QuickCheck::new()
@@ -456,6 +787,65 @@ mod tests {
             .quickcheck(prop as fn(ValidOverflowCell) -> bool);
     }
 
+    #[test]
+    #[cfg(feature = "lz4")]
+    fn compressed_value_roundtrip() {
+        let dicts = Dictionaries::default();
+        let value = b"contract code ".repeat(1000);
+        assert_eq!(compress(&value, Compression::None, &dicts).unwrap(), None);
+
+        let (codec, stored) = compress(&value, Compression::Lz4, &dicts).unwrap().unwrap();
+        assert_eq!(codec, Codec::Lz4);
+        assert!(stored.len() < value.len());
+        assert_eq!(decode_value(codec, stored.clone(), &dicts).unwrap(), value);
+
+        // corrupted values and unknown codecs are errors.
+        assert!(decode_value(codec, stored[..stored.len() / 2].to_vec(), &dicts).is_err());
+        assert!(decode_value(codec, stored[..2].to_vec(), &dicts).is_err());
+        assert!(Codec::from_byte(0xff).is_err());
+
+        // incompressible values are stored as they are.
+        let mut value = vec![0; 10_000];
+        rand_pcg::Lcg64Xsh32::from_seed([7; 16]).fill_bytes(&mut value);
+        assert_eq!(compress(&value, Compression::Lz4, &dicts).unwrap(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn dictionary_compressed_value_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let compression = Compression::Zstd {
+            level: 3,
+            dictionary_size: 1024,
+        };
+        let dicts = Dictionaries::open(dir.path(), compression).unwrap();
+        let value = |i: usize| {
+            format!("{{\"code\": \"contract-{i}\"}} ")
+                .repeat(200)
+                .into_bytes()
+        };
+
+        // values are compressed on their own until a dictionary is trained.
+        let mut i = 0;
+        let stored = loop {
+            match compress(&value(i), compression, &dicts).unwrap().unwrap() {
+                (Codec::Zstd, _) => i += 1,
+                (codec, stored) => {
+                    assert_eq!(codec, Codec::ZstdDict);
+                    break stored;
+                }
+            }
+        };
+        assert_eq!(
+            decode_value(Codec::ZstdDict, stored.clone(), &dicts).unwrap(),
+            value(i)
+        );
+
+        // the dictionary is needed to decompress the value.
+        let no_dicts = Dictionaries::default();
+        assert!(decode_value(Codec::ZstdDict, stored, &no_dicts).is_err());
+    }
+
     #[test]
     fn test_decode_cell_safety() {
         fn prop(mut bytes: Vec<u8>) -> TestResult {
@@ -9,7 +9,9 @@
 
 use anyhow::{bail, ensure, Ok, Result};
 use bitvec::prelude::*;
-use std::{collections::BTreeSet, fs::File, mem::ManuallyDrop, os::fd::AsRawFd, ptr, sync::Arc};
+use std::{collections::BTreeSet, fs::File, sync::Arc};
+#[cfg(unix)]
+use std::{mem::ManuallyDrop, ptr};
 
 use crate::beatree::{
     allocator::PageNumber,
@@ -17,6 +19,10 @@ use crate::beatree::{
     index::Index,
 };
 use crate::io::PagePool;
+#[cfg(unix)]
+use crate::sys::AsRawFd as _;
+#[cfg(windows)]
+use crate::sys::FileExt as _;
 
 /// Reconstruct the upper branch nodes of the btree from the bottom branch nodes and the leaf nodes.
 /// This places all branches into the BNP and returns an index into all BBNs.
@@ -70,10 +76,27 @@ pub fn reconstruct(
     Ok(index)
 }
 
+fn check_file_len(len: u64, bump: u32) -> Result<()> {
+    ensure!(
+        len.is_multiple_of(BRANCH_NODE_SIZE as u64),
+        "file size is not a multiple of 4KiB page"
+    );
+    ensure!(
+        len >= BRANCH_NODE_SIZE as u64,
+        "file is too small for BBN store"
+    );
+    ensure!(
+        bump as u64 <= len / BRANCH_NODE_SIZE as u64,
+        "bump is out of bounds"
+    );
+    Ok(())
+}
+
 /// An utility to read sequentially from a file.
 ///
 /// This is backed by an mmap of the file. The kernel is instructed that the contents of the file
 /// should be read sequentially. This will make the kernel to read ahead the file sequentially.
+#[cfg(unix)]
 struct SeqFileReader {
     ptr: *mut u8,
     len: u64,
@@ -85,21 +108,11 @@ struct SeqFileReader {
     bbn_fd: ManuallyDrop<Arc<File>>,
 }
 
+#[cfg(unix)]
 impl SeqFileReader {
     fn new(bbn_fd: Arc<File>, bump: u32) -> Result<Self> {
         let len = bbn_fd.metadata()?.len();
-        ensure!(
-            len % BRANCH_NODE_SIZE as u64 == 0,
-            "file size is not a multiple of 4KiB page"
-        );
-        ensure!(
-            len >= BRANCH_NODE_SIZE as u64,
-            "file is too small for BBN store"
-        );
-        ensure!(
-            bump as u64 <= len / BRANCH_NODE_SIZE as u64,
-            "bump is out of bounds"
-        );
+        check_file_len(len, bump)?;
 
         let pn = 0u32;
         let ptr = unsafe {
@@ -153,6 +166,7 @@ impl SeqFileReader {
     }
 }
 
+#[cfg(unix)]
 impl Drop for SeqFileReader {
     fn drop(&mut self) {
         unsafe {
@@ -166,3 +180,40 @@ impl Drop for SeqFileReader {
         }
     }
 }
+
+/// An utility to read sequentially from a file.
+///
+/// Files can't be mapped on Windows, so this reads the nodes one at a time.
+#[cfg(windows)]
+struct SeqFileReader {
+    bbn_fd: Arc<File>,
+    node: Box<[u8; BRANCH_NODE_SIZE]>,
+    pn: u32,
+    bump: u32,
+}
+
+#[cfg(windows)]
+impl SeqFileReader {
+    fn new(bbn_fd: Arc<File>, bump: u32) -> Result<Self> {
+        let len = bbn_fd.metadata()?.len();
+        check_file_len(len, bump)?;
+        Ok(Self {
+            bbn_fd,
+            node: Box::new([0; BRANCH_NODE_SIZE]),
+            pn: 0,
+            bump,
+        })
+    }
+
+    pub fn next<'a>(&'a mut self) -> Result<Option<(u32, &'a [u8])>> {
+        if self.pn >= self.bump {
+            return Ok(None);
+        }
+
+        let pn = self.pn;
+        let offset = pn as u64 * BRANCH_NODE_SIZE as u64;
+        self.bbn_fd.read_exact_at(&mut self.node[..], offset)?;
+        self.pn += 1;
+        Ok(Some((pn, &self.node[..])))
+    }
+}
@@ -0,0 +1,128 @@
+//! Finding the values to rewrite in order to move pages of the tree toward the start of the store
+//! files.
+//!
+//! Leaves and branches are copied on write, so rewriting any value of a leaf moves the leaf and its
+//! branch to pages allocated by the sync, and rewriting a large value moves its overflow pages.
+
+use std::sync::Arc;
+
+use super::overflow;
+use crate::beatree::{
+    allocator::{PageNumber, StoreReader},
+    index::Index,
+    leaf::node::LeafNode,
+    leaf_cache::LeafCache,
+    Key, ValueChange,
+};
+
+/// The values to rewrite to move the pages of a range of leaves.
+pub struct Relocations {
+    /// The values to stage, as they are stored, in key order.
+    pub changes: Vec<(Key, ValueChange)>,
+    /// The number of leaf, overflow and branch pages moved by the changes.
+    pub pages: usize,
+    /// The number of leaves scanned.
+    pub leaves: usize,
+    /// The separator of the branch to scan next. `None` once all are scanned.
+    pub next: Option<Key>,
+}
+
+/// Scan the leaves of the branches from the separator `start` on for pages at or beyond
+/// `ln_limit` in the leaf store and `bbn_limit` in the branch store, branch by branch until at
+/// least `max_leaves` leaves are scanned.
+///
+/// A large value is moved if any of the page numbers in its overflow cell is beyond the limit.
+/// Leaves read from the store are not cached.
+pub fn relocations(
+    bbn_index: &Index,
+    leaf_cache: &LeafCache,
+    leaf_store: &StoreReader,
+    start: Key,
+    max_leaves: usize,
+    ln_limit: u32,
+    bbn_limit: u32,
+) -> Relocations {
+    let mut relocations = Relocations {
+        changes: Vec::new(),
+        pages: 0,
+        leaves: 0,
+        next: None,
+    };
+
+    for (separator, branch) in bbn_index.iter_from(start) {
+        if relocations.leaves >= max_leaves {
+            relocations.next = Some(*separator);
+            break;
+        }
+
+        // moving any leaf of a branch moves the branch.
+        let mut move_leaf = branch.bbn_pn() >= bbn_limit;
+        if move_leaf {
+            relocations.pages += 1;
+        }
+
+        for i in 0..branch.n() as usize {
+            let leaf_pn = PageNumber(branch.node_pointer(i));
+            let leaf = leaf_cache.get(leaf_pn).unwrap_or_else(|| {
+                Arc::new(LeafNode {
+                    inner: leaf_store.query(leaf_pn),
+                })
+            });
+            relocations.leaves += 1;
+            if leaf_pn.0 >= ln_limit {
+                move_leaf = true;
+                relocations.pages += 1;
+            }
+
+            let changes_before = relocations.changes.len();
+            for j in 0..leaf.n() {
+                let (cell, true) = leaf.value(j) else {
+                    continue;
+                };
+                let (_, _, mut page_numbers) = overflow::decode_cell(cell);
+                if page_numbers.any(|pn| pn.0 >= ln_limit) {
+                    relocations.pages += overflow::cell_pages(cell);
+                    relocations
+                        .changes
+                        .push((leaf.key(j), stored_change(cell, true, leaf_store)));
+                }
+            }
+
+            if move_leaf && relocations.changes.len() == changes_before {
+                // rewrite a value which is cheap to read, if there is one.
+                let j = (0..leaf.n())
+                    .find(|&j| match leaf.value(j) {
+                        (_, false) => true,
+                        (cell, true) => overflow::cell_pages(cell) == 0,
+                    })
+                    .unwrap_or(0);
+                let (cell, is_overflow) = leaf.value(j);
+                relocations.pages += if is_overflow {
+                    overflow::cell_pages(cell)
+                } else {
+                    0
+                };
+                relocations
+                    .changes
+                    .push((leaf.key(j), stored_change(cell, is_overflow, leaf_store)));
+            }
+            move_leaf = false;
+        }
+    }
+
+    relocations
+}
+
+// The change storing the value of a cell again as it is.
+fn stored_change(cell: &[u8], is_overflow: bool, leaf_store: &StoreReader) -> ValueChange {
+    if !is_overflow {
+        return ValueChange::Insert(cell.to_vec());
+    }
+    match overflow::decode_cell(cell) {
+        (0, value_hash, _) => ValueChange::hash_only(value_hash),
+        (_, value_hash, _) => ValueChange::InsertOverflow(
+            overflow::read_blocking(cell, leaf_store).unwrap(),
+            value_hash,
+        ),
+    }
+}
@@ -10,7 +10,8 @@ use crate::beatree::{
     leaf::node::LeafNode,
     leaf_cache::LeafCache,
     ops::{
-        overflow, search_branch,
+        overflow::{self, Codec},
+        search_branch,
         update::{
             extend_range_protocol::{
                 request_range_extension, try_answer_left_neighbor, LeftNeighbor, RightNeighbor,
@@ -20,10 +21,11 @@ use crate::beatree::{
             leaf_updater::{BaseLeaf, DigestResult as LeafDigestResult, LeafUpdater},
         },
     },
-    Key, ValueChange,
+    Dictionaries, Key, ValueChange,
 };
-use crate::io::{IoCommand, IoHandle, IoKind};
+use crate::io::{IoCommand, IoHandle, IoKind, PagePool};
 use crate::task::{join_task, spawn_task};
+use crate::Compression;
 
 /// Tracker of all changes that happen to leaves during an update
 pub type LeavesTracker = super::NodesTracker<LeafNode>;
@@ -76,6 +78,8 @@ pub struct LeafStageOutput {
     pub submitted_io: usize,
     /// Work which should be done after I/O but before sync has finished.
     pub post_io_work: PostIoWork,
+    /// The number of keys in each written leaf.
+    pub leaf_fanout: Vec<usize>,
 }
 
 /// Change the btree's leaves in the specified way
@@ -88,36 +92,23 @@ pub fn run(
     changeset: OrdMap<Key, ValueChange>,
     thread_pool: ThreadPool,
     num_workers: usize,
+    compression: Compression,
 ) -> std::io::Result<LeafStageOutput> {
     if changeset.is_empty() {
         return Ok(LeafStageOutput::default());
     }
 
-    let mut overflow_io = 0;
-    let page_pool = leaf_reader.page_pool().clone();
-
-    let changeset = changeset
-        .iter()
-        .map(|(k, v)| match v {
-            ValueChange::Insert(v) => Ok((*k, Some((v.clone(), false)))),
-            ValueChange::InsertOverflow(large_value, value_hash) => {
-                let (pages, num_writes) =
-                    overflow::chunk(&large_value, &leaf_writer, &page_pool, &io_handle)?;
-                overflow_io += num_writes;
-
-                let cell = overflow::encode_cell(large_value.len(), value_hash.clone(), &pages);
-                Ok((*k, Some((cell, true))))
-            }
-            ValueChange::Delete => Ok((*k, None)),
-        })
-        .collect::<std::io::Result<Vec<_>>>()?;
+    // Workers are assigned ranges of keys. Each worker prepares the values in its range, which
+    // includes writing out overflow values.
+    let keys = changeset.keys().copied().collect::<Vec<_>>();
 
     assert!(num_workers >= 1);
-    let workers = prepare_workers(bbn_index, &changeset, num_workers);
+    let workers = prepare_workers(bbn_index, &keys, num_workers);
     assert!(!workers.is_empty());
 
     let leaf_cache = Arc::new(leaf_cache);
     let changeset = Arc::new(changeset);
+    let keys = Arc::new(keys);
 
     let num_workers = workers.len();
     let (worker_result_tx, worker_result_rx) = crossbeam_channel::bounded(num_workers);
@@ -129,32 +120,45 @@ pub fn run(
         let leaf_writer = leaf_writer.clone();
         let io_handle = io_handle.clone();
         let changeset = changeset.clone();
+        let keys = keys.clone();
 
         let leaf_stage_worker_task = move || {
+            let keys = &keys[worker_params.op_range.clone()];
             let prepared_leaves = preload_and_prepare(
                 &leaf_cache,
                 &leaf_reader,
                 &bbn_index,
                 io_handle.make_new_sibiling_handle(),
-                changeset[worker_params.op_range.clone()]
-                    .iter()
-                    .map(|(k, _)| *k),
+                keys.iter().copied(),
+            )?;
+
+            let (ops, overflow_io) = prepare_ops(
+                &changeset,
+                keys,
+                &leaf_writer,
+                leaf_reader.page_pool(),
+                &io_handle,
+                compression,
+                leaf_reader.dictionaries(),
             )?;
+            drop(changeset);
 
             let mut prepared_leaves_iter = prepared_leaves.into_iter().peekable();
 
             // passing the large `Arc` values by reference ensures that they are dropped at the
             // end of this scope, not the end of `run_worker`.
-            run_worker(
+            let mut output = run_worker(
                 bbn_index,
                 &*leaf_cache,
                 leaf_reader,
                 leaf_writer,
                 io_handle,
                 &mut prepared_leaves_iter,
-                &*changeset,
+                &ops,
                 worker_params,
-            )
+            )?;
+            output.overflow_io = overflow_io;
+            Ok::<_, std::io::Error>(output)
         };
 
         let worker_result_tx = worker_result_tx.clone();
@@ -167,9 +171,9 @@ pub fn run(
 
     // we don't want to block other sync steps on deallocating these memory regions.
     drop(changeset);
+    drop(keys);
 
     let mut output = LeafStageOutput::default();
-    output.submitted_io += overflow_io;
 
     for _ in 0..num_workers {
         let worker_output = join_task(&worker_result_rx)?;
@@ -199,8 +203,9 @@ fn apply_worker_changes(
         }
 
         let new_pn = leaf_entry.inserted.as_ref().map(|(_, pn)| *pn);
-        if new_pn.is_some() {
+        if let Some((leaf, _)) = leaf_entry.inserted.as_ref() {
             output.submitted_io += 1;
+            output.leaf_fanout.push(leaf.n());
         }
 
         if let Some(prev_pn) = leaf_entry.deleted {
@@ -210,6 +215,7 @@ fn apply_worker_changes(
         output.leaf_changeset.push((*key, new_pn));
     }
 
+    output.submitted_io += worker_output.overflow_io;
     output.submitted_io += worker_output.leaves_tracker.extra_freed.len();
     output
         .freed_pages
@@ -231,7 +237,7 @@ fn filter_leaves_changeset(leaf_changeset: &mut Vec<(Key, Option<PageNumber>)>)
     // The deleted page was already inserted in the freed_pages, thus,
     // now only the inserted page must be kept, resulting in a standard
     // modification of the same page, but made by two workers.
-    for i in 0..leaf_changeset.len() - 1 {
+    for i in 0..leaf_changeset.len().saturating_sub(1) {
         if leaf_changeset[i].0 == leaf_changeset[i + 1].0 {
             // PANICS: If two changesets refer to the same page, they
             // are expected to be treated as a single one, with one
@@ -343,9 +349,59 @@ pub fn enforce_first_leaf_separator(
     }
 }
 
+// A key along with its prepared cell and whether that cell refers to overflow pages, or `None`
+// for a deletion.
+type PreparedOp = (Key, Option<(Vec<u8>, bool)>);
+
+// Prepare the values of the given keys for ingestion into leaves, compressing and writing out
+// overflow values.
+//
+// Returns the prepared values and the number of submitted overflow page writes.
+fn prepare_ops(
+    changeset: &OrdMap<Key, ValueChange>,
+    keys: &[Key],
+    leaf_writer: &SyncAllocator,
+    page_pool: &PagePool,
+    io_handle: &IoHandle,
+    compression: Compression,
+    dictionaries: &Dictionaries,
+) -> std::io::Result<(Vec<PreparedOp>, usize)> {
+    let (Some(first), Some(last)) = (keys.first(), keys.last()) else {
+        return Ok((Vec::new(), 0));
+    };
+
+    let mut overflow_io = 0;
+    let ops = changeset
+        .range(*first..=*last)
+        .map(|(k, v)| match v {
+            ValueChange::Insert(v) => Ok((*k, Some((v.clone(), false)))),
+            ValueChange::InsertOverflow(large_value, value_hash) if large_value.is_empty() => {
+                // A value stored elsewhere.
+                let cell = overflow::encode_cell(0, Codec::None, *value_hash, &[]);
+                Ok((*k, Some((cell, true))))
+            }
+            ValueChange::InsertOverflow(large_value, value_hash) => {
+                let compressed = overflow::compress(large_value, compression, dictionaries)?;
+                let (codec, stored) = match compressed {
+                    Some((codec, ref compressed)) => (codec, &compressed[..]),
+                    None => (Codec::None, &large_value[..]),
+                };
+                let (pages, num_writes) =
+                    overflow::chunk(stored, leaf_writer, page_pool, io_handle)?;
+                overflow_io += num_writes;
+
+                let cell = overflow::encode_cell(stored.len(), codec, *value_hash, &pages);
+                Ok((*k, Some((cell, true))))
+            }
+            ValueChange::Delete => Ok((*k, None)),
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    Ok((ops, overflow_io))
+}
+
 fn prepare_workers(
     bbn_index: &Index,
-    changeset: &[(Key, Option<(Vec<u8>, bool)>)],
+    changeset: &[Key],
     worker_count: usize,
 ) -> Vec<WorkerParams<LeafNode>> {
     let mut remaining_workers = worker_count;
@@ -385,7 +441,7 @@ fn prepare_workers(
         // UNWRAP: first worker is pushed at the beginning of the range.
         let prev_worker = workers.last_mut().unwrap();
 
-        match indexed_leaf(bbn_index, changeset_remaining[pivot_idx].0) {
+        match indexed_leaf(bbn_index, changeset_remaining[pivot_idx]) {
             None => break,
             Some((_, None, _)) => break,
             Some((separator, Some(_), _)) => {
@@ -397,7 +453,7 @@ fn prepare_workers(
                     - changeset_remaining[..pivot_idx]
                         .iter()
                         .rev()
-                        .take_while(|(k, _)| k >= &separator)
+                        .take_while(|k| *k >= &separator)
                         .count();
 
                 if prev_worker_ops == 0 {
@@ -548,6 +604,8 @@ fn reset_leaf_base_fresh(
 struct LeafWorkerOutput {
     leaves_tracker: LeavesTracker,
     overflow_deleted: Vec<Vec<u8>>,
+    // the number of overflow page writes submitted while preparing the values.
+    overflow_io: usize,
 }
 
 fn run_worker(
@@ -581,10 +639,10 @@ fn run_worker(
         &mut leaf_updater,
         has_extended_range,
         prepared_leaves,
-        changeset[worker_params.op_range.start].0,
+        changeset[0].0,
     );
 
-    for (key, op) in &changeset[worker_params.op_range.clone()] {
+    for (key, op) in changeset {
         // ensure key is in scope for leaf updater. if not, digest it. merge rightwards until
         // done _or_ key is in scope.
         while !leaf_updater.is_in_scope(&key) {
@@ -693,6 +751,7 @@ fn run_worker(
     Ok(LeafWorkerOutput {
         leaves_tracker: new_leaf_state.leaves_tracker,
         overflow_deleted,
+        overflow_io: 0,
     })
 }
 
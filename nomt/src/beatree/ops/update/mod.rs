@@ -14,7 +14,9 @@ use crate::beatree::{
     Key, SyncData, ValueChange,
 };
 use crate::io::{IoHandle, PagePool};
+use crate::metrics::{Metric, Metrics};
 use crate::task::{spawn_task, TaskResult};
+use crate::Compression;
 
 mod branch_ops;
 mod branch_stage;
@@ -41,6 +43,23 @@ const LEAF_MERGE_THRESHOLD: usize = LEAF_NODE_BODY_SIZE / 2;
 const LEAF_BULK_SPLIT_THRESHOLD: usize = (LEAF_NODE_BODY_SIZE * 9) / 5;
 const LEAF_BULK_SPLIT_TARGET: usize = (LEAF_NODE_BODY_SIZE * 3) / 4;
 
+/// An error from [`update`].
+#[derive(Debug)]
+pub struct UpdateError {
+    pub error: std::io::Error,
+    /// Whether all I/O issued by the update has concluded. Only then is it safe to start another.
+    pub settled: bool,
+}
+
+impl From<std::io::Error> for UpdateError {
+    fn from(error: std::io::Error) -> Self {
+        UpdateError {
+            error,
+            settled: false,
+        }
+    }
+}
+
 /// Change the btree in the specified way. Updates the branch index in-place.
 ///
 /// The changeset is a list of key value pairs to be added or removed from the btree.
@@ -54,7 +73,9 @@ pub fn update(
     io_handle: IoHandle,
     thread_pool: ThreadPool,
     workers: usize,
-) -> std::io::Result<(SyncData, Index, Receiver<TaskResult<()>>)> {
+    compression: Compression,
+    metrics: &Metrics,
+) -> Result<(SyncData, Index, Receiver<TaskResult<()>>), UpdateError> {
     let leaf_reader = StoreReader::new(leaf_store.clone(), page_pool.clone());
     let (leaf_writer, leaf_finisher) = leaf_store.start_sync();
     let (bbn_writer, bbn_finisher) = bbn_store.start_sync();
@@ -68,7 +89,11 @@ pub fn update(
         changeset,
         thread_pool.clone(),
         workers,
+        compression,
     )?;
+    for &n in &leaf_stage_outputs.leaf_fanout {
+        metrics.observe(Metric::LeafFanout, n as u64);
+    }
 
     let branch_stage_outputs = branch_stage::run(
         &mut bbn_index,
@@ -93,10 +118,21 @@ pub fn update(
     crate::beatree::writeout::submit_freelist_write(&io_handle, &leaf_store, ln_freelist_pages);
     crate::beatree::writeout::submit_freelist_write(&io_handle, &bbn_store, bbn_freelist_pages);
 
-    // make sure that all write requests succeeded.
+    // make sure that all write requests succeeded. All of them are waited for even if one fails,
+    // so that none is left in flight.
+    let mut result = Ok(());
     for _ in 0..total_io {
         // UNWRAP: we receive only what we sent. No `RecvErr` expected.
-        io_handle.recv().unwrap().result?;
+        let complete = io_handle.recv().unwrap().result;
+        if result.is_ok() {
+            result = complete;
+        }
+    }
+    if let Err(error) = result {
+        return Err(UpdateError {
+            error,
+            settled: true,
+        });
     }
 
     let (tx, rx) = crossbeam_channel::bounded(1);
@@ -113,6 +149,7 @@ pub fn update(
             ln_bump: ln_meta.bump,
             bbn_freelist_pn: bbn_meta.freelist_pn,
             bbn_bump: bbn_meta.bump,
+            pages_written: total_io,
         },
         bbn_index,
         rx,
@@ -20,6 +20,7 @@ use crate::{
         },
         Index, ValueChange,
     },
+    chaos::Chaos,
     io::{start_test_io_pool, IoPool, PagePool},
 };
 use lazy_static::lazy_static;
@@ -133,13 +134,15 @@ fn init_beatree() -> TreeData {
             .map(|(k, v)| (k, ValueChange::Insert(v)))
             .collect(),
         Index::default(),
-        LeafCache::new(1, 1024),
+        LeafCache::new(1, 1024, Chaos::default()),
         leaf_store,
         bbn_store,
         PAGE_POOL.clone(),
         IO_POOL.make_handle(),
         THREAD_POOL.clone(),
         1,
+        crate::Compression::None,
+        IO_POOL.metrics(),
     )
     .unwrap();
 
@@ -239,7 +242,7 @@ fn exec_leaf_stage(
     let (leaf_writer, leaf_finisher) = leaf_store.start_sync();
 
     let bbn_index = &TREE_DATA.bbn_index;
-    let leaf_cache = LeafCache::new(commit_concurrency, 1024);
+    let leaf_cache = LeafCache::new(commit_concurrency, 1024, Chaos::default());
     let leaf_page_numbers = leaf_page_numbers(&bbn_index, changeset.keys().cloned());
 
     let io_handle = IO_POOL.make_handle();
@@ -252,6 +255,7 @@ fn exec_leaf_stage(
         changeset.into_iter().collect(),
         THREAD_POOL.clone(),
         commit_concurrency,
+        crate::Compression::None,
     )
     .unwrap();
 
@@ -5,7 +5,7 @@ use super::meta_map::MetaMap;
 use crate::io::{self, PagePool, PAGE_SIZE};
 use std::{
     fs::{File, OpenOptions},
-    path::PathBuf,
+    path::Path,
 };
 
 /// The offsets of the HT file.
@@ -28,7 +28,8 @@ impl HTOffsets {
     }
 }
 
-fn expected_file_len(num_pages: u32) -> u64 {
+/// The length of an HT file with the given number of buckets.
+pub fn expected_file_len(num_pages: u32) -> u64 {
     (num_meta_byte_pages(num_pages) + num_pages) as u64 * PAGE_SIZE as u64
 }
 
@@ -43,7 +44,9 @@ pub fn open(
     ht_fd: &File,
 ) -> anyhow::Result<(HTOffsets, MetaMap)> {
     if ht_fd.metadata()?.len() != expected_file_len(num_pages) {
-        anyhow::bail!("Store corrupted; unexpected file length");
+        return Err(crate::error::corruption(
+            "Store corrupted; unexpected file length",
+        ));
     }
 
     let num_meta_byte_pages = num_meta_byte_pages(num_pages);
@@ -60,11 +63,37 @@ pub fn open(
     ))
 }
 
-/// Creates the store file. Fails if store file already exists.
+/// Creates an empty HT file with the given number of buckets at `path`, which must not exist.
+///
+/// Returns the file, opened for reading and writing, along with its offsets and empty meta map.
+pub fn create_empty(path: &Path, num_pages: u32) -> std::io::Result<(File, HTOffsets, MetaMap)> {
+    let ht_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+    ht_file.set_len(expected_file_len(num_pages))?;
+
+    let num_meta_byte_pages = num_meta_byte_pages(num_pages);
+    let meta_bytes = vec![0; num_meta_byte_pages as usize * PAGE_SIZE];
+    Ok((
+        ht_file,
+        HTOffsets {
+            data_page_offset: num_meta_byte_pages as u64,
+        },
+        MetaMap::from_bytes(meta_bytes, num_pages as usize),
+    ))
+}
+
+/// Creates the hash-table file and the WAL at the given paths.
 ///
 /// Lays out the meta page. If `preallocate` is true, preallocates the blocks for the file.
-pub fn create(path: PathBuf, num_pages: u32, preallocate: bool) -> std::io::Result<()> {
-    let ht_path = path.join("ht");
+pub fn create(
+    ht_path: &Path,
+    wal_path: &Path,
+    num_pages: u32,
+    preallocate: bool,
+) -> std::io::Result<()> {
     let ht_file = OpenOptions::new().write(true).create(true).open(ht_path)?;
 
     // number of pages + pages required for meta bits.
@@ -76,7 +105,6 @@ pub fn create(path: PathBuf, num_pages: u32, preallocate: bool) -> std::io::Resu
     ht_file.sync_all()?;
     drop(ht_file);
 
-    let wal_path = path.join("wal");
     let wal_file = OpenOptions::new().write(true).create(true).open(wal_path)?;
     wal_file.sync_all()?;
     drop(wal_file);
@@ -34,6 +34,11 @@ impl MetaMap {
         self.buckets
     }
 
+    // the number of pages the meta-map occupies on disk.
+    pub fn page_count(&self) -> usize {
+        self.bitvec.len() / 4096
+    }
+
     pub fn set_full(&mut self, bucket: usize, hash: u64) {
         self.bitvec[bucket] = full_entry(hash);
     }
@@ -5,7 +5,7 @@ use std::{
     collections::HashSet,
     fmt,
     fs::File,
-    os::{fd::AsRawFd, unix::fs::FileExt},
+    path::Path,
     sync::{
         atomic::{AtomicU64, AtomicUsize, Ordering},
         Arc,
@@ -14,19 +14,27 @@ use std::{
 use threadpool::ThreadPool;
 
 use crate::{
+    integrity::{Inconsistency, IntegrityReport},
     io::{self, page_pool::FatPage, IoCommand, IoHandle, IoKind, PagePool, PAGE_SIZE},
     page_cache::{Page, PageCache},
     store::{BucketInfo, DirtyPage},
+    sys::{AsRawFd, FileExt},
     task::{join_task, spawn_task, TaskResult},
 };
 
-use self::{ht_file::HTOffsets, meta_map::MetaMap};
+use self::{
+    ht_file::HTOffsets,
+    meta_map::MetaMap,
+    resize::{Shadow, ShadowChange},
+};
 
 pub use self::ht_file::create;
+pub use self::resize::recover as recover_resize;
 pub use wal::WalBlobBuilder;
 
 mod ht_file;
 mod meta_map;
+mod resize;
 mod wal;
 pub(crate) mod writeout;
 
@@ -47,11 +55,14 @@ impl std::error::Error for BucketExhaustion {}
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BucketIndex(u64);
 
-#[cfg(test)]
 impl BucketIndex {
     pub fn new(index: u64) -> Self {
         BucketIndex(index)
     }
+
+    pub fn get(&self) -> u64 {
+        self.0
+    }
 }
 
 /// Essentially an `Arc<Option<BucketIndex>>` that can be mutated atomically.
@@ -108,10 +119,14 @@ pub struct Shared {
     ht_fd: File,
     sync_tp: ThreadPool,
     capacity: usize,
+    wal_compression: bool,
+    /// The shadow table of a resize in progress, if any.
+    resize: Mutex<Option<Shadow>>,
 }
 
 impl DB {
     /// Opens an existing bitbox database.
+    #[allow(clippy::too_many_arguments)]
     pub fn open(
         sync_seqn: u32,
         num_pages: u32,
@@ -119,15 +134,22 @@ impl DB {
         page_pool: PagePool,
         ht_fd: File,
         wal_fd: File,
+        wal_compression: bool,
+        read_only: bool,
     ) -> anyhow::Result<Self> {
-        let (store, mut meta_map) = match ht_file::open(num_pages, &page_pool, &ht_fd) {
-            Ok(x) => x,
-            Err(e) => {
-                anyhow::bail!("encountered error in opening store: {e:?}");
+        let (store, mut meta_map) = ht_file::open(num_pages, &page_pool, &ht_fd)
+            .map_err(|e| e.context("encountered error in opening store"))?;
+
+        if read_only {
+            // The WAL can't be applied without writing. It only matters when it belongs to the
+            // last sync, whose HT pages may then not have been written yet, or is still being
+            // written.
+            if wal_fd.metadata()?.len() > 0
+                && !matches!(wal_sync_seqn(&page_pool, &wal_fd), Ok(seqn) if seqn != sync_seqn)
+            {
+                anyhow::bail!("the hash table is being updated by a sync, try again later");
             }
-        };
-
-        if wal_fd.metadata()?.len() > 0 {
+        } else if wal_fd.metadata()?.len() > 0 {
             recover(
                 sync_seqn,
                 &ht_fd,
@@ -141,7 +163,7 @@ impl DB {
 
         let occupied_buckets = meta_map.full_count();
 
-        let wal_blob_builder = WalBlobBuilder::new()?;
+        let wal_blob_builder = WalBlobBuilder::new(wal_compression)?;
         let capacity = meta_map.len();
         Ok(Self {
             shared: Arc::new(Shared {
@@ -155,6 +177,8 @@ impl DB {
                 ht_fd,
                 sync_tp: ThreadPool::with_name("bitbox-sync".into(), 2),
                 capacity,
+                wal_compression,
+                resize: Mutex::new(None),
             }),
         })
     }
@@ -171,6 +195,174 @@ impl DB {
         SyncController::new(self.clone())
     }
 
+    /// The HT file.
+    pub fn ht_fd(&self) -> &File {
+        &self.shared.ht_fd
+    }
+
+    /// Start resizing the hash table to `num_pages` buckets, by building a shadow table next to
+    /// the HT file at `ht_path`. See the [`resize`] module.
+    ///
+    /// Must not be called concurrently with a sync.
+    pub fn begin_resize(&self, ht_path: &Path, num_pages: u32) -> anyhow::Result<()> {
+        let mut resize = self.shared.resize.lock();
+        if resize.is_some() {
+            anyhow::bail!("a hash table resize is already in progress");
+        }
+        if num_pages as usize == self.shared.capacity {
+            anyhow::bail!("the hash table already has {} buckets", num_pages);
+        }
+        let occupied = self.shared.occupied_buckets.load(Ordering::Relaxed);
+        if num_pages as usize <= occupied {
+            anyhow::bail!(
+                "cannot resize the hash table to {} buckets, {} are occupied",
+                num_pages,
+                occupied
+            );
+        }
+        *resize = Some(Shadow::create(ht_path, num_pages)?);
+        Ok(())
+    }
+
+    /// Copy the next `n` buckets into the shadow table of the resize in progress.
+    ///
+    /// Returns the number of buckets copied so far. Must not be called concurrently with a sync.
+    pub fn resize_step(&self, io_handle: &IoHandle, n: u64) -> anyhow::Result<u64> {
+        let mut resize = self.shared.resize.lock();
+        let Some(shadow) = resize.as_mut() else {
+            anyhow::bail!("no hash table resize in progress");
+        };
+        shadow.copy(&self.shared, io_handle, n)?;
+        Ok(shadow.cursor())
+    }
+
+    /// Abandon the resize in progress, if any, and remove its shadow table.
+    pub fn cancel_resize(&self) -> std::io::Result<()> {
+        match self.shared.resize.lock().take() {
+            Some(shadow) => shadow.discard(),
+            None => Ok(()),
+        }
+    }
+
+    /// Copy the remaining buckets into the shadow table and make it durable, along with an empty
+    /// WAL. Returns the number of buckets of the shadow table.
+    ///
+    /// The resize is committed by writing a meta with that number of buckets afterwards. The WAL
+    /// is emptied first because its bucket indices only apply to the current table. Must not be
+    /// called concurrently with a sync.
+    pub fn seal_resize(&self, io_handle: &IoHandle) -> anyhow::Result<u32> {
+        let mut resize = self.shared.resize.lock();
+        let Some(shadow) = resize.as_mut() else {
+            anyhow::bail!("no hash table resize in progress");
+        };
+        shadow.copy(&self.shared, io_handle, self.shared.capacity as u64)?;
+        shadow.seal()?;
+        writeout::truncate_wal(&self.shared.wal_fd, true)?;
+        Ok(shadow.num_pages())
+    }
+
+    /// Move the sealed shadow table into place and open the resized hash table, once the meta
+    /// refers to it.
+    pub fn switch_resized(&self, sync_seqn: u32) -> anyhow::Result<Self> {
+        let Some(shadow) = self.shared.resize.lock().take() else {
+            anyhow::bail!("no hash table resize in progress");
+        };
+        let num_pages = shadow.num_pages();
+        let ht_path = shadow.switch()?;
+
+        let mut options = std::fs::OpenOptions::new();
+        options.read(true).write(true);
+        // Keep bypassing the cache of the operating system, if the HT file did.
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::OpenOptionsExt as _;
+            let flags = unsafe { libc::fcntl(self.shared.ht_fd.as_raw_fd(), libc::F_GETFL) };
+            if flags != -1 && flags & libc::O_DIRECT != 0 {
+                options.custom_flags(libc::O_DIRECT);
+            }
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::OpenOptionsExt as _;
+            options.custom_flags(crate::sys::windows::FILE_FLAG_NO_BUFFERING);
+        }
+        let ht_fd = options.open(&ht_path)?;
+        #[cfg(target_os = "macos")]
+        unsafe {
+            libc::fcntl(ht_fd.as_raw_fd(), libc::F_NOCACHE, 1);
+        }
+
+        Self::open(
+            sync_seqn,
+            num_pages,
+            self.shared.seed,
+            self.shared.page_pool.clone(),
+            ht_fd,
+            self.shared.wal_fd.try_clone()?,
+            self.shared.wal_compression,
+            /* read_only */ false,
+        )
+    }
+
+    /// Check every occupied bucket and the WAL, recording the inconsistencies found in the
+    /// report. Returns the IDs of the pages held by the buckets.
+    ///
+    /// Must not be called concurrently with a sync.
+    pub fn check_integrity(&self, report: &mut IntegrityReport) -> anyhow::Result<HashSet<PageId>> {
+        let shared = &self.shared;
+        let meta_map = shared.meta_map.read();
+
+        let mut page_ids = HashSet::new();
+        for bucket in 0..meta_map.len() {
+            if meta_map.hint_empty(bucket) || meta_map.hint_tombstone(bucket) {
+                continue;
+            }
+            report.buckets += 1;
+            let bucket = bucket as u64;
+
+            let page = io::read_page(
+                &shared.page_pool,
+                &shared.ht_fd,
+                shared.store.data_page_index(bucket),
+            )?;
+            // UNWRAP: the slice is 32 bytes long.
+            let raw_page_id: [u8; 32] = page[PAGE_SIZE - 32..].try_into().unwrap();
+            let Some(page_id) = decode_stored_page_id(raw_page_id) else {
+                report.push(Inconsistency::InvalidPageId { bucket });
+                continue;
+            };
+
+            let hash = hash_raw_page_id(raw_page_id, &shared.seed);
+            if meta_map.hint_not_match(bucket as usize, hash) {
+                report.push(Inconsistency::BucketHashMismatch { bucket, page_id });
+                continue;
+            }
+            if !probe_reaches(hash, bucket, &meta_map) {
+                report.push(Inconsistency::UnreachableBucket {
+                    bucket,
+                    page_id: page_id.clone(),
+                });
+            }
+            if let Some(page_id) = page_ids.replace(page_id) {
+                report.push(Inconsistency::DuplicatePage { bucket, page_id });
+            }
+        }
+
+        // The WAL is empty unless a sync was interrupted.
+        if shared.wal_fd.metadata()?.len() > 0 {
+            match read_wal(&shared.page_pool, &shared.wal_fd, meta_map.len() as u64) {
+                Ok(entries) => report.wal_entries = entries,
+                Err(e) => match crate::Error::from(e) {
+                    crate::Error::Corruption(e) => {
+                        report.push(Inconsistency::InvalidWal(format!("{e:#}")))
+                    }
+                    e => return Err(e.into_anyhow()),
+                },
+            }
+        }
+        Ok(page_ids)
+    }
+
     fn prepare_sync(
         &self,
         sync_seqn: u32,
@@ -181,6 +373,7 @@ impl DB {
         (
             Vec<(u64, Arc<FatPage>)>,
             Vec<(PageId, Option<(Page, BucketIndex)>)>,
+            Vec<ShadowChange>,
         ),
         BucketExhaustion,
     > {
@@ -188,6 +381,10 @@ impl DB {
 
         let mut meta_map = self.shared.meta_map.write();
 
+        // The changes are also applied to the shadow table of a resize in progress.
+        let resizing = self.shared.resize.lock().is_some();
+        let mut shadow_changes = Vec::new();
+
         let mut changed_meta_pages = HashSet::new();
         let mut ht_pages = Vec::new();
         let mut cache_updates = Vec::new();
@@ -209,6 +406,9 @@ impl DB {
                 meta_map.set_tombstone(bucket as usize);
                 changed_meta_pages.insert(meta_map.page_index(bucket as usize));
                 cache_updates.push((page_id.clone(), None));
+                if resizing {
+                    shadow_changes.push((page_id.encode(), bucket, None));
+                }
 
                 wal_blob_builder.write_clear(bucket);
             } else {
@@ -258,7 +458,11 @@ impl DB {
                     page_id.clone(),
                     Some((dirty_page.page.clone(), BucketIndex(bucket))),
                 ));
-                ht_pages.push((pn, dirty_page.page.into_inner()));
+                let page = dirty_page.page.into_inner();
+                if resizing {
+                    shadow_changes.push((page_id.encode(), bucket, Some(page.clone())));
+                }
+                ht_pages.push((pn, page));
             }
         }
 
@@ -289,22 +493,24 @@ impl DB {
 
         wal_blob_builder.finalize();
 
-        Ok((ht_pages, cache_updates))
+        Ok((ht_pages, cache_updates, shadow_changes))
     }
 }
 
 pub struct SyncController {
     db: DB,
     /// The channel to send the result of the pre-meta sync errors. Option is to allow `take`.
-    pre_meta_result_tx: Option<Sender<TaskResult<std::io::Result<()>>>>,
+    pre_meta_result_tx: Option<Sender<TaskResult<std::io::Result<usize>>>>,
     /// he channel to receive the result of the pre-meta sync errors.
-    pre_meta_result_rx: Receiver<TaskResult<std::io::Result<()>>>,
+    pre_meta_result_rx: Receiver<TaskResult<std::io::Result<usize>>>,
     /// The channel to send the result of the begin_sync task. Option is to allow `take`.
     begin_sync_result_tx: Option<Sender<TaskResult<Result<(), BucketExhaustion>>>>,
     /// The channel to receive the result of the the begin_sync task.
     begin_sync_result_rx: Receiver<TaskResult<Result<(), BucketExhaustion>>>,
     /// The pages along with their page numbers to write out to the HT file.
     ht_to_write: Arc<Mutex<Option<Vec<(u64, Arc<FatPage>)>>>>,
+    /// The changed pages to apply to the shadow table of a resize in progress.
+    shadow_changes: Arc<Mutex<Vec<ShadowChange>>>,
 }
 
 impl SyncController {
@@ -318,6 +524,7 @@ impl SyncController {
             begin_sync_result_tx: Some(begin_sync_result_tx),
             begin_sync_result_rx,
             ht_to_write: Arc::new(Mutex::new(None)),
+            shadow_changes: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
@@ -333,6 +540,7 @@ impl SyncController {
         let page_pool = self.db.shared.page_pool.clone();
         let bitbox = self.db.clone();
         let ht_to_write = self.ht_to_write.clone();
+        let shadow_changes = self.shadow_changes.clone();
         let wal_blob_builder = self.db.shared.wal_blob_builder.clone();
         // UNWRAP: safe because begin_sync is called only once.
         let pre_meta_result_tx = self.pre_meta_result_tx.take().unwrap();
@@ -341,13 +549,14 @@ impl SyncController {
 
             // if fails The sync coordinator will poison the database and all further commits will
             // be rejected. Therefore, there is no need to perform cleanup.
-            let (ht_pages, cache_updates) = bitbox.prepare_sync(
+            let (ht_pages, cache_updates, changes) = bitbox.prepare_sync(
                 sync_seqn,
                 &page_pool,
                 updated_pages,
                 &mut *wal_blob_builder,
             )?;
             drop(wal_blob_builder);
+            *shadow_changes.lock() = changes;
 
             // Set the hash-table pages before spawning WAL writeout so they don't race with it.
             *ht_to_write.lock() = Some(ht_pages);
@@ -368,13 +577,17 @@ impl SyncController {
         );
     }
 
-    fn spawn_wal_writeout(pre_meta_result_tx: Sender<TaskResult<std::io::Result<()>>>, bitbox: DB) {
+    fn spawn_wal_writeout(
+        pre_meta_result_tx: Sender<TaskResult<std::io::Result<usize>>>,
+        bitbox: DB,
+    ) {
         let bitbox = bitbox.clone();
         let tp = bitbox.shared.sync_tp.clone();
         let wal_writeout_task = move || {
             let wal_blob_builder = bitbox.shared.wal_blob_builder.lock();
             let wal_slice = wal_blob_builder.as_slice();
-            writeout::write_wal(&bitbox.shared.wal_fd, wal_slice)
+            writeout::write_wal(&bitbox.shared.wal_fd, wal_slice)?;
+            Ok(wal_slice.len())
         };
 
         spawn_task(&tp, wal_writeout_task, pre_meta_result_tx);
@@ -382,21 +595,32 @@ impl SyncController {
 
     /// Wait for the pre-meta operations to complete.
     ///
-    /// This includes WAL file to be written out.
+    /// This includes WAL file to be written out. Returns the number of bytes written to the WAL.
     ///
     /// Must be invoked by the sync thread. Blocking.
-    pub fn wait_pre_meta(&self) -> anyhow::Result<()> {
+    pub fn wait_pre_meta(&self) -> anyhow::Result<usize> {
         join_task(&self.begin_sync_result_rx)?;
-        join_task(&self.pre_meta_result_rx)?;
-        Ok(())
+        let wal_bytes = join_task(&self.pre_meta_result_rx)?;
+        Ok(wal_bytes)
+    }
+
+    /// Write the WAL out again, after [`Self::wait_pre_meta`] failed to.
+    ///
+    /// Must be invoked by the sync thread. Blocking. Returns the number of bytes written.
+    pub fn rewrite_wal(&self) -> std::io::Result<usize> {
+        let wal_blob_builder = self.db.shared.wal_blob_builder.lock();
+        let wal_slice = wal_blob_builder.as_slice();
+        writeout::write_wal(&self.db.shared.wal_fd, wal_slice)?;
+        Ok(wal_slice.len())
     }
 
     /// Write out the HT pages and truncate the WAL file.
     ///
     /// Has to be called after the manifest is updated. Must be invoked by the sync
-    /// thread. Blocking.
-    pub fn post_meta(&self, io_handle: IoHandle) -> std::io::Result<()> {
+    /// thread. Blocking. Returns the number of HT pages written.
+    pub fn post_meta(&self, io_handle: IoHandle) -> std::io::Result<usize> {
         let ht_pages = self.ht_to_write.lock().take().unwrap();
+        let ht_page_count = ht_pages.len();
         // Writeout the HT pages and truncate the WAL file.
         //
         // Why don't we fsync the truncation of the WAL file? Because it should not be necessary.
@@ -414,8 +638,37 @@ impl SyncController {
         // Therefore, we can safely avoid blocking on the truncation here.
         writeout::write_ht(io_handle, &self.db.shared.ht_fd, ht_pages)?;
         writeout::truncate_wal(&self.db.shared.wal_fd, false)?;
-        Ok(())
+
+        let shadow_changes = std::mem::take(&mut *self.shadow_changes.lock());
+        if let Some(shadow) = self.db.shared.resize.lock().as_mut() {
+            shadow.apply(&self.db.shared.seed, shadow_changes);
+        }
+        Ok(ht_page_count)
+    }
+}
+
+/// Read the sequence number of the sync the WAL was written for.
+fn wal_sync_seqn(page_pool: &PagePool, wal_fd: &File) -> anyhow::Result<u32> {
+    use crate::bitbox::wal::WalBlobReader;
+    let wal_reader = WalBlobReader::new(page_pool, wal_fd)?;
+    Ok(wal_reader.sync_seqn())
+}
+
+/// Read all entries of the WAL, returning their number.
+fn read_wal(page_pool: &PagePool, wal_fd: &File, buckets: u64) -> anyhow::Result<u64> {
+    use crate::bitbox::wal::WalBlobReader;
+    let mut wal_reader = WalBlobReader::new(page_pool, wal_fd)?;
+    let mut entries = 0;
+    while let Some(entry) = wal_reader.read_entry()? {
+        let (wal::WalEntry::Clear { bucket } | wal::WalEntry::Update { bucket, .. }) = entry;
+        if bucket >= buckets {
+            return Err(crate::error::corruption(format!(
+                "WAL entry of bucket {bucket} out of range"
+            )));
+        }
+        entries += 1;
     }
+    Ok(entries)
 }
 
 /// Perform recovery by applying the WAL to the HT file.
@@ -506,17 +759,11 @@ fn recover(
     //
     // We now write those pages out to the HT file.
     for changed_meta_page_ix in changed_meta_page_ixs {
-        unsafe {
-            let page = page_pool.alloc();
-            // SAFETY: page is a fresh allocation from page pool and it's not aliased.
-            let page_data = page.as_mut_slice();
-            page_data[..].copy_from_slice(meta_map.page_slice(changed_meta_page_ix));
-
-            let pn = ht_offsets.meta_bytes_index(changed_meta_page_ix as u64);
-            ht_fd.write_all_at(page_data, pn * PAGE_SIZE as u64)?;
+        let mut page = page_pool.alloc_fat_page();
+        page.copy_from_slice(meta_map.page_slice(changed_meta_page_ix));
 
-            page_pool.dealloc(page);
-        }
+        let pn = ht_offsets.meta_bytes_index(changed_meta_page_ix as u64);
+        ht_fd.write_all_at(&page, pn * PAGE_SIZE as u64)?;
     }
 
     // Finally, we collapse the WAL file and fsync.
@@ -617,7 +864,7 @@ impl PageLoad {
 }
 
 /// Describes the utilization of buckets in the hash-table at a point in time.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct HashTableUtilization {
     /// The maximum number of buckets in the hash-table.
     pub capacity: usize,
@@ -651,7 +898,12 @@ fn allocate_bucket(
     meta_map: &mut MetaMap,
     seed: &[u8; 16],
 ) -> Option<BucketIndex> {
-    let mut probe_seq = ProbeSequence::new(page_id, &meta_map, seed);
+    allocate_bucket_with_hash(hash_page_id(page_id, seed), meta_map)
+}
+
+/// Allocates a bucket in the meta map for the page ID with the given hash.
+fn allocate_bucket_with_hash(hash: u64, meta_map: &mut MetaMap) -> Option<BucketIndex> {
+    let mut probe_seq = ProbeSequence::with_hash(hash, meta_map);
 
     let mut i = 0;
     loop {
@@ -680,6 +932,33 @@ fn hash_raw_page_id(page_id: [u8; 32], seed: &[u8; 16]) -> u64 {
     twox_hash::xxhash3_64::Hasher::oneshot_with_seed(seed_u64, &page_id)
 }
 
+/// Whether probing for the page ID with the given hash reaches the bucket.
+/// Decode the ID of a page as stored in its bucket, by [`PageId::encode`].
+///
+/// The encoding is that understood by [`PageId::decode`], shifted up by one more sextet.
+fn decode_stored_page_id(raw_page_id: [u8; 32]) -> Option<PageId> {
+    let mut shifted = [0u8; 32];
+    for i in 1..32 {
+        shifted[i] = (raw_page_id[i - 1] << 2) | (raw_page_id[i] >> 6);
+    }
+    shifted[0] = raw_page_id[0] >> 6;
+    let page_id = PageId::decode(shifted).ok()?;
+    (page_id.encode() == raw_page_id).then_some(page_id)
+}
+
+fn probe_reaches(hash: u64, bucket: u64, meta_map: &MetaMap) -> bool {
+    let mut probe_seq = ProbeSequence::with_hash(hash, meta_map);
+    // Give up once the sequence could have visited every bucket.
+    for _ in 0..meta_map.len() {
+        match probe_seq.next(meta_map) {
+            ProbeResult::PossibleHit(b) if b == bucket => return true,
+            ProbeResult::Empty(_) => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
 #[derive(Clone, Copy)]
 struct ProbeSequence {
     hash: u64,
@@ -695,7 +974,10 @@ enum ProbeResult {
 
 impl ProbeSequence {
     fn new(page_id: &PageId, meta_map: &MetaMap, seed: &[u8; 16]) -> Self {
-        let hash = hash_page_id(page_id, seed);
+        Self::with_hash(hash_page_id(page_id, seed), meta_map)
+    }
+
+    fn with_hash(hash: u64, meta_map: &MetaMap) -> Self {
         Self {
             hash,
             bucket: hash % meta_map.len() as u64,
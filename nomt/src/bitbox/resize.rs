@@ -0,0 +1,266 @@
+//! Resizing the hash table while commits continue.
+//!
+//! A resize builds a shadow table with the new number of buckets next to the HT file, under the
+//! same name with a `.resize` suffix. The buckets of the current table are rehashed into the
+//! shadow in chunks, between commits, in order of their index. The shadow therefore always holds
+//! exactly the pages of the buckets below its cursor. Commits carry on against the current table,
+//! and the pages they change in buckets below the cursor are changed in the shadow as well.
+//!
+//! Once all buckets are copied, the meta map of the shadow is written out and the shadow is
+//! synced. The switch is committed by writing a meta with the new number of buckets, after which
+//! the shadow is renamed over the HT file. On open, a shadow left behind by a crash is renamed
+//! into place if the meta already refers to it, and removed otherwise.
+
+use super::{
+    allocate_bucket_with_hash, hash_raw_page_id, ht_file, ht_file::HTOffsets, meta_map::MetaMap,
+    ProbeResult, ProbeSequence, Shared,
+};
+use crate::{
+    io::{FatPage, IoCommand, IoHandle, IoKind, PAGE_SIZE},
+    sys::{AsRawFd as _, FileExt as _},
+};
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// A page changed by a commit while a resize is in progress: the page ID, the bucket of the page
+/// in the current table and the new contents of the page, or `None` if it was cleared.
+pub type ShadowChange = ([u8; 32], u64, Option<Arc<FatPage>>);
+
+/// The shadow table of a resize in progress.
+pub struct Shadow {
+    path: PathBuf,
+    target: PathBuf,
+    fd: File,
+    offsets: HTOffsets,
+    meta_map: MetaMap,
+    /// The buckets of the current table below this have been copied into the shadow.
+    cursor: u64,
+    /// Why the shadow could not be kept up to date with a commit, if it could not.
+    failed: Option<String>,
+}
+
+impl Shadow {
+    /// Create an empty shadow table with `num_pages` buckets for the HT file at `ht_path`.
+    pub fn create(ht_path: &Path, num_pages: u32) -> io::Result<Self> {
+        let target = std::fs::canonicalize(ht_path)?;
+        let path = shadow_path(&target);
+        // Remove the shadow of an earlier resize which failed to be removed.
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let (fd, offsets, meta_map) = ht_file::create_empty(&path, num_pages)?;
+        Ok(Shadow {
+            path,
+            target,
+            fd,
+            offsets,
+            meta_map,
+            cursor: 0,
+            failed: None,
+        })
+    }
+
+    /// The number of buckets of the shadow table.
+    pub fn num_pages(&self) -> u32 {
+        self.meta_map.len() as u32
+    }
+
+    /// The number of buckets of the current table copied so far.
+    pub fn cursor(&self) -> u64 {
+        self.cursor
+    }
+
+    /// Copy the next `n` buckets of the current table into the shadow.
+    ///
+    /// Must not be called concurrently with a sync.
+    pub fn copy(&mut self, db: &Shared, io_handle: &IoHandle, n: u64) -> anyhow::Result<()> {
+        if let Some(ref reason) = self.failed {
+            anyhow::bail!("hash table resize failed: {}", reason);
+        }
+
+        let meta_map = db.meta_map.read();
+        let end = (self.cursor + n).min(meta_map.len() as u64);
+
+        let mut submitted = 0;
+        for bucket in self.cursor..end {
+            if meta_map.hint_empty(bucket as usize) || meta_map.hint_tombstone(bucket as usize) {
+                continue;
+            }
+            let command = IoCommand {
+                kind: IoKind::Read(
+                    db.ht_fd.as_raw_fd(),
+                    db.store.data_page_index(bucket),
+                    db.page_pool.alloc_fat_page(),
+                ),
+                user_data: bucket,
+            };
+            // UNWRAP: I/O pool is not expected to hangup.
+            io_handle.send(command).unwrap();
+            submitted += 1;
+        }
+
+        let mut result = Ok(());
+        for _ in 0..submitted {
+            let completion = io_handle.recv()?;
+            if let Err(e) = completion.result {
+                result = Err(e.into());
+                continue;
+            }
+            if result.is_ok() {
+                // UNWRAP: only `Read` commands were submitted.
+                let page = completion.command.kind.unwrap_buf();
+                result = self.insert(&db.seed, &page);
+            }
+        }
+        result?;
+
+        self.cursor = end;
+        Ok(())
+    }
+
+    /// Apply the changes of a commit to the pages already copied into the shadow.
+    ///
+    /// A failure is not reported to the commit, which must not fail on account of a resize, but
+    /// by the next call to [`Self::copy`].
+    pub fn apply(&mut self, seed: &[u8; 16], changes: Vec<ShadowChange>) {
+        if self.failed.is_some() {
+            return;
+        }
+        for (page_id, bucket, page) in changes {
+            if bucket >= self.cursor {
+                // Copied along with the rest of the current table later on.
+                continue;
+            }
+            if let Err(e) = self.apply_change(seed, page_id, page) {
+                self.failed = Some(e.to_string());
+                return;
+            }
+        }
+    }
+
+    fn apply_change(
+        &mut self,
+        seed: &[u8; 16],
+        page_id: [u8; 32],
+        page: Option<Arc<FatPage>>,
+    ) -> anyhow::Result<()> {
+        let hash = hash_raw_page_id(page_id, seed);
+        let existing = self.find(&page_id, hash)?;
+        match (page, existing) {
+            (Some(page), Some(bucket)) => self.write_page(bucket, &page)?,
+            (Some(page), None) => self.insert(seed, &page)?,
+            (None, Some(bucket)) => self.meta_map.set_tombstone(bucket as usize),
+            (None, None) => {}
+        }
+        Ok(())
+    }
+
+    // Find the bucket holding the given page, by reading the labels of the candidate buckets.
+    fn find(&self, page_id: &[u8; 32], hash: u64) -> io::Result<Option<u64>> {
+        let mut probe_seq = ProbeSequence::with_hash(hash, &self.meta_map);
+        loop {
+            match probe_seq.next(&self.meta_map) {
+                ProbeResult::Tombstone(_) => continue,
+                ProbeResult::Empty(_) => return Ok(None),
+                ProbeResult::PossibleHit(bucket) => {
+                    let mut label = [0; 32];
+                    let pn = self.offsets.data_page_index(bucket);
+                    self.fd.read_exact_at(
+                        &mut label,
+                        pn * PAGE_SIZE as u64 + (PAGE_SIZE - 32) as u64,
+                    )?;
+                    if label == *page_id {
+                        return Ok(Some(bucket));
+                    }
+                }
+            }
+        }
+    }
+
+    // Insert a page which is not in the shadow yet.
+    fn insert(&mut self, seed: &[u8; 16], page: &FatPage) -> anyhow::Result<()> {
+        // UNWRAP: the slice is 32 bytes long.
+        let page_id: [u8; 32] = page[PAGE_SIZE - 32..].try_into().unwrap();
+        let hash = hash_raw_page_id(page_id, seed);
+        let Some(bucket) = allocate_bucket_with_hash(hash, &mut self.meta_map) else {
+            anyhow::bail!(
+                "the new hash table of {} buckets is too small",
+                self.num_pages()
+            );
+        };
+        self.write_page(bucket.0, page)?;
+        Ok(())
+    }
+
+    fn write_page(&self, bucket: u64, page: &FatPage) -> io::Result<()> {
+        let pn = self.offsets.data_page_index(bucket);
+        self.fd.write_all_at(&page[..], pn * PAGE_SIZE as u64)
+    }
+
+    /// Write out the meta map and sync the shadow, once all buckets have been copied.
+    pub fn seal(&self) -> anyhow::Result<()> {
+        if let Some(ref reason) = self.failed {
+            anyhow::bail!("hash table resize failed: {}", reason);
+        }
+        for ix in 0..self.meta_map.page_count() {
+            let pn = self.offsets.meta_bytes_index(ix as u64);
+            self.fd
+                .write_all_at(self.meta_map.page_slice(ix), pn * PAGE_SIZE as u64)?;
+        }
+        self.fd.sync_all()?;
+        Ok(())
+    }
+
+    /// Rename the sealed shadow over the HT file, returning the path of the HT file.
+    ///
+    /// This must only be done once the meta refers to the shadow.
+    pub fn switch(self) -> io::Result<PathBuf> {
+        drop(self.fd);
+        switch(&self.path, &self.target)?;
+        Ok(self.target)
+    }
+
+    /// Remove the shadow.
+    pub fn discard(self) -> io::Result<()> {
+        drop(self.fd);
+        std::fs::remove_file(&self.path)
+    }
+}
+
+/// Finish or discard a resize interrupted by a crash. Must be called before the HT file at
+/// `ht_path` is opened, with the number of buckets recorded in the meta.
+pub fn recover(ht_path: &Path, num_pages: u32) -> io::Result<()> {
+    let target = std::fs::canonicalize(ht_path)?;
+    let path = shadow_path(&target);
+    let shadow_len = match std::fs::metadata(&path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    // The number of buckets never stays the same across a resize, so the meta refers to the
+    // shadow exactly when the HT file has the wrong length for it.
+    let expected_len = ht_file::expected_file_len(num_pages);
+    if std::fs::metadata(&target)?.len() != expected_len && shadow_len == expected_len {
+        switch(&path, &target)
+    } else {
+        std::fs::remove_file(&path)
+    }
+}
+
+fn shadow_path(target: &Path) -> PathBuf {
+    let mut name = target.as_os_str().to_os_string();
+    name.push(".resize");
+    PathBuf::from(name)
+}
+
+fn switch(path: &Path, target: &Path) -> io::Result<()> {
+    std::fs::rename(path, target)?;
+    // UNWRAP: canonical file paths always have a parent.
+    crate::sys::sync_dir_path(target.parent().unwrap())
+}
@@ -2,6 +2,12 @@ const WAL_ENTRY_TAG_START: u8 = 1;
 const WAL_ENTRY_TAG_END: u8 = 2;
 const WAL_ENTRY_TAG_CLEAR: u8 = 3;
 const WAL_ENTRY_TAG_UPDATE: u8 = 4;
+/// Followed by the uncompressed length (u64), the compressed length (u64), the CRC32 of the
+/// compressed bytes (u32) and the LZ4-compressed entries, up to and including the end tag.
+const WAL_ENTRY_TAG_COMPRESSED: u8 = 5;
+
+/// The size of the start tag and the sync sequence number.
+const WAL_START_SIZE: usize = 5;
 
 pub use read::{WalBlobReader, WalEntry};
 pub use write::WalBlobBuilder;
@@ -1,12 +1,15 @@
 //! The read-path for the WAL.
 
-use super::{WAL_ENTRY_TAG_CLEAR, WAL_ENTRY_TAG_END, WAL_ENTRY_TAG_START, WAL_ENTRY_TAG_UPDATE};
+use super::{
+    WAL_ENTRY_TAG_CLEAR, WAL_ENTRY_TAG_COMPRESSED, WAL_ENTRY_TAG_END, WAL_ENTRY_TAG_START,
+    WAL_ENTRY_TAG_UPDATE,
+};
 use crate::{
+    error::corruption,
     io::{self, PagePool, PAGE_SIZE},
     merkle::ElidedChildren,
     page_diff::PageDiff,
 };
-use anyhow::bail;
 use std::{fs::File, io::Seek};
 
 #[derive(Debug, PartialEq, Eq)]
@@ -46,7 +49,9 @@ impl WalBlobReader {
         let stat = wal_fd.metadata()?;
         let file_size = stat.len() as usize;
         if file_size % PAGE_SIZE != 0 {
-            anyhow::bail!("WAL file size is not a multiple of the page size");
+            return Err(corruption(
+                "WAL file size is not a multiple of the page size",
+            ));
         }
 
         wal_fd.seek(std::io::SeekFrom::Start(0))?;
@@ -95,7 +100,7 @@ impl WalBlobReader {
                 let page_id: [u8; 32] = self.read_buf()?;
                 let page_diff: [u8; 16] = self.read_buf()?;
                 let page_diff = PageDiff::from_bytes(page_diff)
-                    .ok_or_else(|| anyhow::anyhow!("Invalid page diff"))?;
+                    .ok_or_else(|| corruption("Invalid page diff"))?;
 
                 let changed_count = page_diff.count();
                 let mut changed_nodes = Vec::with_capacity(changed_count);
@@ -116,7 +121,7 @@ impl WalBlobReader {
                     bucket,
                 }))
             }
-            _ => bail!("unknown WAL entry tag: {entry_tag}"),
+            _ => Err(corruption(format!("unknown WAL entry tag: {entry_tag}"))),
         }
     }
 
@@ -125,16 +130,47 @@ impl WalBlobReader {
         if entry_tag == WAL_ENTRY_TAG_START {
             self.sync_seqn = self.read_u32()?;
 
+            if self.wal.get(self.offset) == Some(&WAL_ENTRY_TAG_COMPRESSED) {
+                self.offset += 1;
+                self.decompress_entries()?;
+            }
+
             Ok(())
         } else {
-            bail!("unexpected WAL entry tag at start: {entry_tag}");
+            Err(corruption(format!(
+                "unexpected WAL entry tag at start: {entry_tag}"
+            )))
+        }
+    }
+
+    /// Replace the WAL with the decompressed entries following a compressed entry tag.
+    fn decompress_entries(&mut self) -> anyhow::Result<()> {
+        let uncompressed_len = self.read_u64()? as usize;
+        let compressed_len = self.read_u64()? as usize;
+        let crc = self.read_u32()?;
+        if compressed_len > self.wal.len() - self.offset {
+            return Err(corruption("Unexpected end of WAL file"));
+        }
+
+        let compressed = &self.wal[self.offset..self.offset + compressed_len];
+        if crc32fast::hash(compressed) != crc {
+            return Err(corruption("WAL checksum mismatch"));
         }
+        let entries = lz4_flex::block::decompress(compressed, uncompressed_len)
+            .map_err(|e| corruption(format!("Failed to decompress WAL: {e}")))?;
+        if entries.len() != uncompressed_len {
+            return Err(corruption("WAL decompressed to an unexpected length"));
+        }
+
+        self.wal = entries;
+        self.offset = 0;
+        Ok(())
     }
 
     /// Reads a single byte from the WAL file.
     fn read_byte(&mut self) -> anyhow::Result<u8> {
         if self.offset >= self.wal.len() {
-            bail!("Unexpected end of WAL file");
+            return Err(corruption("Unexpected end of WAL file"));
         }
         let byte = self.wal[self.offset];
         self.offset += 1;
@@ -144,11 +180,11 @@ impl WalBlobReader {
     /// Reads a [u8; N] array from the WAL file.
     fn read_buf<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
         if self.offset + N > self.wal.len() {
-            bail!("Unexpected end of WAL file");
+            return Err(corruption("Unexpected end of WAL file"));
         }
         let array = self.wal[self.offset..self.offset + N]
             .try_into()
-            .map_err(|_| anyhow::anyhow!("Failed to read [u8; {N}] from WAL file"))?;
+            .map_err(|_| corruption(format!("Failed to read [u8; {N}] from WAL file")))?;
         self.offset += N;
         Ok(array)
     }
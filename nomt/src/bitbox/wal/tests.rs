@@ -1,9 +1,11 @@
 use super::{WalBlobBuilder, WalBlobReader, WalEntry};
 use crate::{io::page_pool::PagePool, merkle::ElidedChildren, page_diff::PageDiff};
-use std::{fs::OpenOptions, io::Write as _};
+use std::{
+    fs::OpenOptions,
+    io::{Read as _, Seek as _, Write as _},
+};
 
-#[test]
-fn test_write_read() {
+fn write_blob(compress: bool) -> (tempfile::TempDir, std::fs::File) {
     let tempdir = tempfile::tempdir().unwrap();
     let wal_filename = tempdir.path().join("wal");
     std::fs::create_dir_all(tempdir.path()).unwrap();
@@ -13,7 +15,7 @@ fn test_write_read() {
         options.open(&wal_filename).unwrap()
     };
 
-    let mut builder = WalBlobBuilder::new().unwrap();
+    let mut builder = WalBlobBuilder::new(compress).unwrap();
     builder.reset(69);
     builder.write_clear(0);
     builder.write_update(
@@ -53,6 +55,21 @@ fn test_write_read() {
     builder.finalize();
     wal_fd.write_all(builder.as_slice()).unwrap();
     wal_fd.sync_data().unwrap();
+    (tempdir, wal_fd)
+}
+
+#[test]
+fn test_write_read() {
+    check_write_read(false);
+}
+
+#[test]
+fn test_write_read_compressed() {
+    check_write_read(true);
+}
+
+fn check_write_read(compress: bool) {
+    let (_tempdir, wal_fd) = write_blob(compress);
 
     let page_pool = PagePool::new();
     let mut reader = WalBlobReader::new(&page_pool, &wal_fd).unwrap();
@@ -108,3 +125,30 @@ fn test_write_read() {
     );
     assert_eq!(reader.read_entry().unwrap(), None);
 }
+
+#[test]
+fn test_compressed_smaller() {
+    let (_tempdir, plain) = write_blob(false);
+    let (_tempdir, compressed) = write_blob(true);
+    assert!(compressed.metadata().unwrap().len() < plain.metadata().unwrap().len());
+}
+
+#[test]
+fn test_compressed_corruption_detected() {
+    let (_tempdir, mut wal_fd) = write_blob(true);
+
+    // Flip a byte within the compressed entries, past the start and compressed entry headers.
+    let mut corrupted = vec![0; 64];
+    wal_fd
+        .seek(std::io::SeekFrom::Start(0))
+        .and_then(|_| wal_fd.read_exact(&mut corrupted))
+        .unwrap();
+    corrupted[40] ^= 0xff;
+    wal_fd
+        .seek(std::io::SeekFrom::Start(0))
+        .and_then(|_| wal_fd.write_all(&corrupted))
+        .unwrap();
+
+    let page_pool = PagePool::new();
+    assert!(WalBlobReader::new(&page_pool, &wal_fd).is_err());
+}
@@ -1,16 +1,22 @@
 //! The write-path for the WAL.
 
-use super::{WAL_ENTRY_TAG_CLEAR, WAL_ENTRY_TAG_END, WAL_ENTRY_TAG_START, WAL_ENTRY_TAG_UPDATE};
+use super::{
+    WAL_ENTRY_TAG_CLEAR, WAL_ENTRY_TAG_COMPRESSED, WAL_ENTRY_TAG_END, WAL_ENTRY_TAG_START,
+    WAL_ENTRY_TAG_UPDATE, WAL_START_SIZE,
+};
 use crate::{io::PAGE_SIZE, merkle::ElidedChildren, page_diff::PageDiff};
 
 const MAX_SIZE: usize = 1 << 37; // 128 GiB
 
-struct Mmap {
+/// The memory holding a WAL blob. This is an anonymous mapping, which can be grown in place.
+#[cfg(not(any(feature = "safe-page-pool", windows)))]
+struct Buffer {
     ptr: *mut u8,
     size: usize,
 }
 
-impl Mmap {
+#[cfg(not(any(feature = "safe-page-pool", windows)))]
+impl Buffer {
     fn new(size: usize) -> anyhow::Result<Self> {
         let ptr = unsafe {
             libc::mmap(
@@ -29,6 +35,27 @@ impl Mmap {
         Ok(Self { ptr, size })
     }
 
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Grow the mapping to `new_size`, which should be a multiple of the page size, preserving
+    /// the first `len` bytes.
+    fn grow(&mut self, new_size: usize, len: usize) -> anyhow::Result<()> {
+        if self.resize(new_size) {
+            return Ok(());
+        }
+
+        // Resizing in place did not succeed. We could try to create a new mapping and copy
+        // the data over.
+        let new_buffer = Buffer::new(new_size)?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.ptr, new_buffer.ptr, len);
+        }
+        *self = new_buffer;
+        Ok(())
+    }
+
     /// Try to resize this mapping.
     ///
     /// The `new_size` should be a multiple of the page size.
@@ -60,9 +87,40 @@ impl Mmap {
         #[cfg(not(target_os = "linux"))]
         false
     }
+
+    /// Copy `bytes` to `pos`. The range must be within the size of the buffer.
+    fn write_at(&mut self, pos: usize, bytes: &[u8]) {
+        assert!(pos + bytes.len() <= self.size);
+        // SAFETY: the range is within the mapping, as asserted above, and `bytes` cannot overlap
+        // with the mapping since it is borrowed mutably.
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), self.ptr.add(pos), bytes.len());
+        }
+    }
+
+    /// Zero the bytes from `from` up to `to`. The range must be within the size of the buffer.
+    fn zero(&mut self, from: usize, to: usize) {
+        assert!(from <= to && to <= self.size);
+        // SAFETY:
+        // - `dst` is never null.
+        // - `dst` is always naturally aligned because it's a byte.
+        // - `to` is at most the size of the mmap. Thus `dst + count` never lands past the end of
+        //   the mmap.
+        // - `0` is a valid value for `u8`.
+        unsafe {
+            std::ptr::write_bytes(self.ptr.add(from), 0, to - from);
+        }
+    }
+
+    /// The first `len` bytes of the buffer.
+    fn slice(&self, len: usize) -> &[u8] {
+        assert!(len <= self.size);
+        unsafe { std::slice::from_raw_parts(self.ptr, len) }
+    }
 }
 
-impl Drop for Mmap {
+#[cfg(not(any(feature = "safe-page-pool", windows)))]
+impl Drop for Buffer {
     fn drop(&mut self) {
         unsafe {
             let _ = libc::munmap(self.ptr as *mut libc::c_void, self.size);
@@ -70,28 +128,70 @@ impl Drop for Mmap {
     }
 }
 
+#[cfg(not(any(feature = "safe-page-pool", windows)))]
+unsafe impl Send for Buffer {}
+
+/// The memory holding a WAL blob. This is a heap allocation, which is not aligned to the page
+/// size.
+#[cfg(any(feature = "safe-page-pool", windows))]
+struct Buffer(Vec<u8>);
+
+#[cfg(any(feature = "safe-page-pool", windows))]
+impl Buffer {
+    fn new(size: usize) -> anyhow::Result<Self> {
+        Ok(Buffer(vec![0; size]))
+    }
+
+    fn size(&self) -> usize {
+        self.0.len()
+    }
+
+    fn grow(&mut self, new_size: usize, _len: usize) -> anyhow::Result<()> {
+        self.0.resize(new_size, 0);
+        Ok(())
+    }
+
+    fn write_at(&mut self, pos: usize, bytes: &[u8]) {
+        self.0[pos..pos + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn zero(&mut self, from: usize, to: usize) {
+        self.0[from..to].fill(0);
+    }
+
+    fn slice(&self, len: usize) -> &[u8] {
+        &self.0[..len]
+    }
+}
+
 /// A builder for a WAL blob.
 pub struct WalBlobBuilder {
-    mmap: Mmap,
-    /// The position at which the next byte will be written. Never reaches `mmap.size`.
+    buf: Buffer,
+    /// The position at which the next byte will be written. Never reaches the size of `buf`.
     cur: usize,
+    /// Whether to compress the entries when finalizing.
+    compress: bool,
 }
 
 impl WalBlobBuilder {
-    pub fn new() -> anyhow::Result<Self> {
-        Self::with_initial_size(1 << 30)
+    pub fn new(compress: bool) -> anyhow::Result<Self> {
+        let mut builder = Self::with_initial_size(1 << 30)?;
+        builder.compress = compress;
+        Ok(builder)
     }
 
     fn with_initial_size(size: usize) -> anyhow::Result<Self> {
-        let mmap = Mmap::new(size)?;
-        Ok(Self { mmap, cur: 0 })
+        let buf = Buffer::new(size)?;
+        Ok(Self {
+            buf,
+            cur: 0,
+            compress: false,
+        })
     }
 
     pub fn write_clear(&mut self, bucket_index: u64) {
-        unsafe {
-            self.write_byte(WAL_ENTRY_TAG_CLEAR);
-            self.write(&bucket_index.to_le_bytes());
-        }
+        self.write_byte(WAL_ENTRY_TAG_CLEAR);
+        self.write(&bucket_index.to_le_bytes());
     }
 
     pub fn write_update(
@@ -102,76 +202,53 @@ impl WalBlobBuilder {
         elided_children: ElidedChildren,
         bucket_index: u64,
     ) {
-        unsafe {
-            // SAFETY: Those do not overlap with the mmap.
-            self.write_byte(WAL_ENTRY_TAG_UPDATE);
-            self.write(&page_id);
-            self.write(&page_diff.as_bytes());
-            for changed in changed {
-                self.write(&changed);
-            }
-            self.write(&elided_children.to_bytes());
-            self.write(&bucket_index.to_le_bytes());
+        self.write_byte(WAL_ENTRY_TAG_UPDATE);
+        self.write(&page_id);
+        self.write(&page_diff.as_bytes());
+        for changed in changed {
+            self.write(&changed);
         }
+        self.write(&elided_children.to_bytes());
+        self.write(&bucket_index.to_le_bytes());
     }
 
     fn write_byte(&mut self, byte: u8) {
-        unsafe {
-            // SAFETY: This slice trivially does not overlap with the mmap.
-            self.write(&[byte]);
-        }
+        self.write(&[byte]);
     }
 
-    /// # Safety
-    ///
-    /// The `bytes` mut not overlap with the mmap.
-    unsafe fn write(&mut self, bytes: &[u8]) {
+    fn write(&mut self, bytes: &[u8]) {
         let pos = self.cur;
         let new_cur = self.cur.checked_add(bytes.len()).unwrap();
-        if new_cur >= self.mmap.size {
+        if new_cur >= self.buf.size() {
             // Grow once to the required size
             if let Err(e) = self.grow(new_cur) {
                 panic!("WAL blob too large: {e}");
             }
         }
-        // NB: `self.mmap` could have changed after the grow.
         self.cur = new_cur;
-        unsafe {
-            std::ptr::copy_nonoverlapping(bytes.as_ptr(), self.mmap.ptr.add(pos), bytes.len());
-        }
+        self.buf.write_at(pos, bytes);
     }
 
-    /// Grow the mmap to at least `min_new_size` bytes.
+    /// Grow the buffer to at least `min_new_size` bytes.
     ///
     /// Since this grows by at least doubling the current size, the resulting size might be
     /// larger than `min_new_size`.
     ///
-    /// Returns `Ok(())` if the mmap was successfully resized.
+    /// Returns `Ok(())` if the buffer was successfully resized.
     #[cold]
     fn grow(&mut self, min_new_size: usize) -> anyhow::Result<()> {
-        if self.mmap.size >= MAX_SIZE {
+        if self.buf.size() >= MAX_SIZE {
             anyhow::bail!("WAL blob too large (exceeded 128 GiB limit)");
         }
 
         // Start with current size and double until we cover min_new_size
-        let mut new_size = self.mmap.size;
+        let mut new_size = self.buf.size();
         while new_size < min_new_size && new_size < MAX_SIZE {
             new_size = new_size.saturating_mul(2);
         }
         new_size = new_size.min(MAX_SIZE);
 
-        if self.mmap.resize(new_size) {
-            return Ok(());
-        }
-
-        // Resizing in place did not succeed. We could try to create a new mapping and copy
-        // the data over.
-        let new_mmap = Mmap::new(new_size)?;
-        unsafe {
-            std::ptr::copy_nonoverlapping(self.mmap.ptr, new_mmap.ptr, self.mmap.size);
-        }
-        self.mmap = new_mmap;
-        Ok(())
+        self.buf.grow(new_size, self.cur)
     }
 
     /// Resets the builder preparing it for a new batch of writes.
@@ -181,9 +258,7 @@ impl WalBlobBuilder {
         self.cur = 0;
 
         self.write_byte(WAL_ENTRY_TAG_START);
-
-        // SAFETY: these bytes live on the stack and do not overlap with the map.
-        unsafe { self.write(&sync_seqn.to_le_bytes()) }
+        self.write(&sync_seqn.to_le_bytes());
     }
 
     /// Finalizes the builder.
@@ -194,8 +269,10 @@ impl WalBlobBuilder {
     /// The pointer is aligned to the page size.
     pub fn finalize(&mut self) {
         self.write_byte(WAL_ENTRY_TAG_END);
+        if self.compress {
+            self.compress_entries();
+        }
 
-        let ptr = self.mmap.ptr;
         // round up to the nearest page size.
         let len = (self.cur + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
 
@@ -204,31 +281,33 @@ impl WalBlobBuilder {
         //
         // The hope is that should there be memory  pressure, the memory would be swapped out as
         // needed.
-        let cur = self.cur;
-        unsafe {
-            // Zero memory from `cur` to the end of the blob (which is `len`).
-            let dst = ptr.add(cur);
-            let count = len - cur;
-            if count > 0 {
-                // SAFETY:
-                // - `dst` is never null.
-                // - `dst` is always naturally aligned because it's a byte.
-                // - `cur` is always less than the size of the mmap. Thus `dst + count` never lands
-                //   past the end of the mmap.
-                // - `0` is a valid value for `u8`.
-                std::ptr::write_bytes(dst, 0, count);
-            }
-        }
+        //
+        // Zero memory from `cur` to the end of the blob (which is `len`).
+        self.buf.zero(self.cur, len);
         self.cur = len;
     }
 
+    /// Replace all entries following the start entry, including the end tag, with a single
+    /// compressed entry.
+    fn compress_entries(&mut self) {
+        let entries = &self.as_slice()[WAL_START_SIZE..];
+        let uncompressed_len = entries.len() as u64;
+        let compressed = lz4_flex::block::compress(entries);
+        let crc = crc32fast::hash(&compressed);
+
+        self.cur = WAL_START_SIZE;
+        self.write_byte(WAL_ENTRY_TAG_COMPRESSED);
+        self.write(&uncompressed_len.to_le_bytes());
+        self.write(&(compressed.len() as u64).to_le_bytes());
+        self.write(&crc.to_le_bytes());
+        self.write(&compressed);
+    }
+
     pub fn as_slice(&self) -> &[u8] {
-        unsafe { std::slice::from_raw_parts(self.mmap.ptr, self.cur) }
+        self.buf.slice(self.cur)
     }
 }
 
-unsafe impl Send for WalBlobBuilder {}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -244,16 +323,16 @@ mod tests {
 
         // Fill up most of the initial capacity
         let data = vec![42u8; 4000];
-        unsafe { builder.write(&data) };
+        builder.write(&data);
         assert_eq!(builder.cur, 4000);
-        assert_eq!(builder.mmap.size, 4096);
+        assert_eq!(builder.buf.size(), 4096);
 
         // Write more data that forces a grow
         let more_data = vec![43u8; 2000];
-        unsafe { builder.write(&more_data) };
+        builder.write(&more_data);
 
         // Should have grown to accommodate the additional data
-        assert!(builder.mmap.size > 4096);
+        assert!(builder.buf.size() > 4096);
         assert_eq!(builder.cur, 6000);
 
         // Verify we can still write after growing
@@ -269,11 +348,11 @@ mod tests {
         for i in 0..5 {
             let size = 1000 * (i + 1);
             let data = vec![i as u8; size];
-            unsafe { builder.write(&data) };
+            builder.write(&data);
         }
 
         // Should have grown multiple times to fit all data
-        assert!(builder.mmap.size >= 15000);
+        assert!(builder.buf.size() >= 15000);
         assert_eq!(builder.cur, 15000);
     }
 }
@@ -8,16 +8,30 @@
 use std::{
     fs::File,
     io::{Seek as _, SeekFrom, Write},
-    os::fd::AsRawFd as _,
     sync::Arc,
 };
 
-use crate::io::{FatPage, IoCommand, IoHandle, IoKind};
+use crate::{
+    io::{FatPage, IoCommand, IoHandle, IoKind},
+    sys::AsRawFd as _,
+};
 
 pub(super) fn write_wal(mut wal_fd: &File, wal_blob: &[u8]) -> std::io::Result<()> {
     wal_fd.set_len(0)?;
     wal_fd.seek(SeekFrom::Start(0))?;
+    // The blob is only aligned to the page size when it is backed by an anonymous mapping. Go
+    // through an aligned page otherwise, since the WAL may be opened with `O_DIRECT` or
+    // `FILE_FLAG_NO_BUFFERING`.
+    #[cfg(not(any(feature = "safe-page-pool", windows)))]
     wal_fd.write_all(wal_blob)?;
+    #[cfg(any(feature = "safe-page-pool", windows))]
+    {
+        let mut page = crate::io::PagePool::new().alloc_fat_page();
+        for chunk in wal_blob.chunks(crate::io::PAGE_SIZE) {
+            page[..chunk.len()].copy_from_slice(chunk);
+            wal_fd.write_all(&page[..chunk.len()])?;
+        }
+    }
     wal_fd.sync_all()?;
     Ok(())
 }
@@ -0,0 +1,44 @@
+//! Snapshots of the in-memory caches, for diagnosing cache behavior.
+//!
+//! This module is only available with the `cache-debug` feature, which also makes every cache
+//! entry record the time it was inserted. See [`Nomt::cache_snapshot`](crate::Nomt::cache_snapshot).
+
+use nomt_core::page_id::PageId;
+use std::time::Duration;
+
+/// A page held in the page cache.
+#[derive(Debug, Clone)]
+pub struct CachedPage {
+    /// The ID of the page.
+    pub page_id: PageId,
+    /// The depth of the page in the page tree. The root page has depth 0.
+    pub depth: usize,
+    /// Whether the page is in the upper levels of the tree, which are never evicted.
+    pub pinned: bool,
+    /// The time since this copy of the page was inserted into the cache.
+    pub age: Duration,
+}
+
+/// A leaf node held in the leaf cache.
+#[derive(Debug, Clone)]
+pub struct CachedLeaf {
+    /// The page number of the leaf node in the leaf store.
+    pub page_number: u32,
+    /// The first and last key stored in the leaf. `None` if the leaf is empty.
+    pub key_range: Option<([u8; 32], [u8; 32])>,
+    /// The number of entries in the leaf.
+    pub entries: usize,
+    /// The number of bytes of the page used by the leaf.
+    pub size: usize,
+    /// The time since the leaf was inserted into the cache.
+    pub age: Duration,
+}
+
+/// The contents of the page cache and the leaf cache at one point in time.
+#[derive(Debug, Clone)]
+pub struct CacheSnapshot {
+    /// The cached pages, ordered by depth and then by page ID.
+    pub pages: Vec<CachedPage>,
+    /// The cached leaves, ordered by key range.
+    pub leaves: Vec<CachedLeaf>,
+}
@@ -0,0 +1,254 @@
+//! Persistence of the page and leaf caches across restarts.
+//!
+//! On a clean shutdown the hottest cached pages and leaves are written to the `cache` file in the
+//! database directory, and on the next open they are loaded back, so that the database does not
+//! start with cold caches. See [`crate::Options::cache_file_size`].
+//!
+//! The file format:
+//!
+//! ```text
+//! header:  magic "NOMTCACH" | version: u8 | sync_seqn: u32 | root: [u8; 32]
+//!          | pages: u32 | leaves: u32
+//! page:    depth: u8 | child indices: [u8; depth] | bucket index: u64 | data: [u8; PAGE_SIZE]
+//! leaf:    page number: u32 | data: [u8; PAGE_SIZE]
+//! trailer: crc32 of everything before the crc: u32
+//! ```
+//!
+//! All integers are little-endian. Entries are ordered from hottest to coldest. The cached data
+//! is only valid for the exact state it was written at, so the file is ignored unless the sync
+//! sequence number and root in its header match the database. It is removed once read, so a
+//! crash never leaves a file behind which might be mistaken for a valid one.
+
+use crate::{
+    bitbox::BucketIndex,
+    io::{FatPage, PagePool, PAGE_SIZE},
+    page_cache::PageCache,
+    store::Store,
+};
+use nomt_core::{
+    page_id::{ChildPageIndex, PageId, ROOT_PAGE_ID},
+    trie::Node,
+};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+const FILE_NAME: &str = "cache";
+const TMP_FILE_NAME: &str = "cache.tmp";
+const MAGIC: [u8; 8] = *b"NOMTCACH";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 8 + 1 + 4 + 32 + 4 + 4;
+
+/// Take up to `limit` items from the lists, one from each list in turn.
+///
+/// This merges the per-shard recency orders of the caches into a single approximate one.
+pub(crate) fn round_robin<T>(lists: Vec<Vec<T>>, limit: usize) -> Vec<T> {
+    let mut iters = lists.into_iter().map(Vec::into_iter).collect::<Vec<_>>();
+    let mut out = Vec::new();
+    while out.len() < limit {
+        let before = out.len();
+        for iter in &mut iters {
+            if out.len() == limit {
+                break;
+            }
+            out.extend(iter.next());
+        }
+        if out.len() == before {
+            break;
+        }
+    }
+    out
+}
+
+/// Write the hottest contents of the caches to the cache file in `db_dir`, up to `size` MiB.
+///
+/// The root page and the upper levels of the page tree come first, then the most recently used
+/// leaves, and then the most recently used of the remaining pages. Leaves are favored over deeper
+/// pages, because every read of a value needs a leaf while pages are only needed for updates and
+/// proofs.
+///
+/// `sync_seqn` and `root` must describe the state of the database, and the caller must ensure
+/// that nothing is committed while this runs.
+pub fn save(
+    db_dir: &Path,
+    size: usize,
+    sync_seqn: u32,
+    root: Node,
+    page_cache: &PageCache,
+    store: &Store,
+) -> io::Result<()> {
+    let budget = size * 1024 * 1024 / PAGE_SIZE;
+
+    let mut pages = page_cache.upper_level_pages();
+    pages.truncate(budget);
+    let leaves = store.hottest_leaves(budget - pages.len());
+    let recent = budget - pages.len() - leaves.len();
+    pages.extend(page_cache.recent_pages(recent));
+
+    let tmp_path = db_dir.join(TMP_FILE_NAME);
+    let file = File::create(&tmp_path)?;
+    let mut writer = CrcWriter {
+        inner: BufWriter::new(&file),
+        crc: crc32fast::Hasher::new(),
+    };
+
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&[VERSION])?;
+    writer.write_all(&sync_seqn.to_le_bytes())?;
+    writer.write_all(&root)?;
+    writer.write_all(&(pages.len() as u32).to_le_bytes())?;
+    writer.write_all(&(leaves.len() as u32).to_le_bytes())?;
+    for (page_id, page, bucket_index) in &pages {
+        let path = page_id.length_dependent_encoding();
+        writer.write_all(&[path.len() as u8])?;
+        writer.write_all(path)?;
+        writer.write_all(&bucket_index.get().to_le_bytes())?;
+        writer.write_all(&page.page_data()[..PAGE_SIZE])?;
+    }
+    for (page_number, leaf) in &leaves {
+        writer.write_all(&page_number.to_le_bytes())?;
+        writer.write_all(&leaf[..PAGE_SIZE])?;
+    }
+    let CrcWriter { mut inner, crc } = writer;
+    inner.write_all(&crc.finalize().to_le_bytes())?;
+    inner.flush()?;
+    drop(inner);
+
+    file.sync_all()?;
+    std::fs::rename(tmp_path, db_dir.join(FILE_NAME))
+}
+
+/// Load the cache file in `db_dir` into the caches and remove it.
+///
+/// Nothing is loaded if there is no cache file, if it is damaged, or if it was written at a
+/// different sync sequence number or root. Returns whether the caches were loaded.
+pub fn load(
+    db_dir: &Path,
+    sync_seqn: u32,
+    root: Node,
+    page_pool: &PagePool,
+    page_cache: &PageCache,
+    store: &Store,
+) -> io::Result<bool> {
+    let path = db_dir.join(FILE_NAME);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let contents = read(file, sync_seqn, root, page_pool);
+    std::fs::remove_file(&path)?;
+
+    match contents {
+        Ok(Some((pages, leaves))) => {
+            page_cache.restore(pages);
+            store.restore_leaf_cache(leaves);
+            Ok(true)
+        }
+        // A stale or damaged cache file is not an error, the caches just start cold.
+        Ok(None) => Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+type Contents = (Vec<(PageId, FatPage, BucketIndex)>, Vec<(u32, FatPage)>);
+
+fn read(
+    file: File,
+    sync_seqn: u32,
+    root: Node,
+    page_pool: &PagePool,
+) -> io::Result<Option<Contents>> {
+    let mut reader = CrcReader {
+        inner: BufReader::new(file),
+        crc: crc32fast::Hasher::new(),
+    };
+
+    let mut header = [0; HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+    if header[0..8] != MAGIC
+        || header[8] != VERSION
+        || header[9..13] != sync_seqn.to_le_bytes()
+        || header[13..45] != root
+    {
+        return Ok(None);
+    }
+    // UNWRAP: the slices are 4 bytes long.
+    let num_pages = u32::from_le_bytes(header[45..49].try_into().unwrap());
+    let num_leaves = u32::from_le_bytes(header[49..53].try_into().unwrap());
+
+    let mut pages = Vec::new();
+    for _ in 0..num_pages {
+        let mut depth = [0; 1];
+        reader.read_exact(&mut depth)?;
+        let mut path = vec![0; depth[0] as usize];
+        reader.read_exact(&mut path)?;
+        let Some(page_id) = decode_page_id(&path) else {
+            return Ok(None);
+        };
+        let mut bucket_index = [0; 8];
+        reader.read_exact(&mut bucket_index)?;
+        let mut page = page_pool.alloc_fat_page();
+        reader.read_exact(&mut page[..PAGE_SIZE])?;
+        pages.push((
+            page_id,
+            page,
+            BucketIndex::new(u64::from_le_bytes(bucket_index)),
+        ));
+    }
+
+    let mut leaves = Vec::new();
+    for _ in 0..num_leaves {
+        let mut page_number = [0; 4];
+        reader.read_exact(&mut page_number)?;
+        let mut page = page_pool.alloc_fat_page();
+        reader.read_exact(&mut page[..PAGE_SIZE])?;
+        leaves.push((u32::from_le_bytes(page_number), page));
+    }
+
+    let crc = reader.crc.clone().finalize();
+    let mut expected = [0; 4];
+    reader.inner.read_exact(&mut expected)?;
+    if crc != u32::from_le_bytes(expected) {
+        return Ok(None);
+    }
+    Ok(Some((pages, leaves)))
+}
+
+fn decode_page_id(path: &[u8]) -> Option<PageId> {
+    let mut page_id = ROOT_PAGE_ID;
+    for &child_index in path {
+        page_id = page_id
+            .child_page_id(ChildPageIndex::new(child_index)?)
+            .ok()?;
+    }
+    Some(page_id)
+}
+
+struct CrcWriter<W> {
+    inner: W,
+    crc: crc32fast::Hasher,
+}
+
+impl<W: Write> CrcWriter<W> {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.crc.update(buf);
+        self.inner.write_all(buf)
+    }
+}
+
+struct CrcReader<R> {
+    inner: R,
+    crc: crc32fast::Hasher,
+}
+
+impl<R: Read> CrcReader<R> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(buf)?;
+        self.crc.update(buf);
+        Ok(())
+    }
+}
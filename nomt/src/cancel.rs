@@ -0,0 +1,83 @@
+//! Cancellation of long-running operations.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// A token for cancelling long-running operations, such as [`crate::migration::migrate`] and
+/// [`crate::Nomt::apply_diff`].
+///
+/// Operations check the token periodically and fail with [`Cancelled`] once it is cancelled or
+/// its deadline has passed. A cancelled operation leaves the database as it was before the
+/// operation began, or, for migrations, at the last checkpoint.
+///
+/// This is cheap to clone. Clones share the cancellation state.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    /// Create a token which is cancelled only by [`CancellationToken::cancel`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a token which is cancelled once `timeout` has elapsed, or by
+    /// [`CancellationToken::cancel`].
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self::with_deadline(Instant::now() + timeout)
+    }
+
+    /// Create a token which is cancelled at `deadline`, or by [`CancellationToken::cancel`].
+    pub fn with_deadline(deadline: Instant) -> Self {
+        CancellationToken {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            deadline: Some(deadline),
+        }
+    }
+
+    /// Cancel all operations using this token or any of its clones.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the token has been cancelled or its deadline has passed.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Returns an error if the token has been cancelled.
+    pub(crate) fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The error returned by operations which were cancelled through a [`CancellationToken`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("operation cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Returns an error if the token is present and has been cancelled.
+pub(crate) fn check(cancel: Option<&CancellationToken>) -> Result<(), Cancelled> {
+    cancel.map_or(Ok(()), CancellationToken::check)
+}
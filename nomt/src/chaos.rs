@@ -0,0 +1,87 @@
+//! Fault injection for canary deployments.
+//!
+//! With the `chaos` feature and `Options::chaos`, I/O completions are randomly delayed and the
+//! page and leaf caches randomly evict more than they need to. Both are rare and bounded, and
+//! neither changes the results of any operation, only their timing, so that real workloads can
+//! surface latent race conditions. Every injected fault is counted in the metrics.
+//!
+//! Without the feature, all of this compiles down to nothing.
+
+#[cfg(feature = "chaos")]
+use crate::metrics::{Metric, Metrics};
+#[cfg(feature = "chaos")]
+use std::{sync::Arc, time::Duration};
+
+/// The faults injected in chaos mode. See [`crate::Options::chaos`].
+#[cfg(feature = "chaos")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChaosConfig {
+    /// The probability that an I/O completion is delayed.
+    pub io_delay_probability: f64,
+    /// The longest delay of an I/O completion. Delays are uniformly distributed up to this.
+    pub max_io_delay: Duration,
+    /// The probability that a shard of the page or leaf cache evicts extra entries when the
+    /// cache is trimmed. At most an eighth of the shard is evicted at once.
+    pub eviction_probability: f64,
+}
+
+#[cfg(feature = "chaos")]
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        ChaosConfig {
+            io_delay_probability: 0.001,
+            max_io_delay: Duration::from_millis(10),
+            eviction_probability: 0.01,
+        }
+    }
+}
+
+/// A handle to the chaos mode of a database, cheap to clone. Without the `chaos` feature, or when
+/// chaos mode is off, it never injects anything.
+#[derive(Clone, Default)]
+pub struct Chaos {
+    #[cfg(feature = "chaos")]
+    inner: Option<Arc<(ChaosConfig, Metrics)>>,
+}
+
+impl Chaos {
+    /// Create the chaos mode of a database opened with the given options.
+    #[cfg_attr(not(feature = "chaos"), allow(unused_variables))]
+    pub fn new(o: &crate::Options, metrics: crate::metrics::Metrics) -> Self {
+        Chaos {
+            #[cfg(feature = "chaos")]
+            inner: o.chaos.clone().map(|config| Arc::new((config, metrics))),
+        }
+    }
+
+    /// Maybe sleep before delivering an I/O completion.
+    #[inline]
+    pub fn delay_io(&self) {
+        #[cfg(feature = "chaos")]
+        if let Some((config, metrics)) = self.inner.as_deref() {
+            if rand::random::<f64>() < config.io_delay_probability {
+                metrics.count(Metric::ChaosIoDelays);
+                std::thread::sleep(config.max_io_delay.mul_f64(rand::random::<f64>()));
+            }
+        }
+    }
+
+    /// The number of extra entries a cache shard holding `len` entries should evict, after
+    /// evicting down to its limit.
+    ///
+    /// Evicting the least recently used entries beyond the limit is what a smaller cache would
+    /// have done, so this is always safe.
+    #[inline]
+    #[cfg_attr(not(feature = "chaos"), allow(unused_variables))]
+    pub fn extra_evictions(&self, len: usize) -> usize {
+        #[cfg(feature = "chaos")]
+        if let Some((config, metrics)) = self.inner.as_deref() {
+            if len > 0 && rand::random::<f64>() < config.eviction_probability {
+                let n = rand::random_range(1..=(len / 8).max(1));
+                metrics.count_n(Metric::ChaosEvictions, n as u64);
+                return n;
+            }
+        }
+        0
+    }
+}
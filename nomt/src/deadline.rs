@@ -0,0 +1,115 @@
+//! Commits with a deadline. See [`crate::FinishedSession::commit_by`].
+
+use std::time::{Duration, Instant};
+
+use crate::store::SyncTimings;
+
+/// Where the time of a commit with a deadline went, and whether the deadline was met.
+///
+/// See [`crate::FinishedSession::commit_by`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommitReport {
+    /// The deadline the commit was given.
+    pub deadline: Instant,
+    /// When the commit completed.
+    pub completed_at: Instant,
+    /// The time spent waiting for ongoing sessions and commits to finish.
+    pub waiting: Duration,
+    /// The time spent appending to the rollback log.
+    pub rollback: Duration,
+    /// The time spent persisting the changes, by stage.
+    pub sync: SyncTimings,
+    /// The time from the call to the completion of the commit.
+    pub total: Duration,
+    /// Whether the work which can wait for the next commit was skipped to meet the deadline: the
+    /// commit statistics, evicting the leaves of keys hinted cold and starting an automatic
+    /// resize of the hash table.
+    pub skipped_optional_work: bool,
+}
+
+impl CommitReport {
+    /// Whether the commit completed by the deadline.
+    pub fn deadline_met(&self) -> bool {
+        self.completed_at <= self.deadline
+    }
+
+    /// How long after the deadline the commit completed. Zero if it was met.
+    pub fn overrun(&self) -> Duration {
+        self.completed_at.saturating_duration_since(self.deadline)
+    }
+}
+
+// Times the steps of a commit with a deadline as they complete.
+pub(crate) struct CommitClock {
+    deadline: Instant,
+    started: Instant,
+    lap: Instant,
+    waiting: Duration,
+    rollback: Duration,
+    sync: SyncTimings,
+    skipped_optional_work: bool,
+}
+
+impl CommitClock {
+    pub fn start(deadline: Instant) -> Self {
+        let now = Instant::now();
+        CommitClock {
+            deadline,
+            started: now,
+            lap: now,
+            waiting: Duration::ZERO,
+            rollback: Duration::ZERO,
+            sync: SyncTimings::default(),
+            skipped_optional_work: false,
+        }
+    }
+
+    /// The guards of the commit were acquired.
+    pub fn waited(&mut self) {
+        self.waiting = self.lap();
+    }
+
+    /// The rollback delta was logged.
+    pub fn rolled_back(&mut self) {
+        self.rollback = self.lap();
+    }
+
+    /// The changes were persisted, taking the given time in each stage.
+    pub fn synced(&mut self, timings: SyncTimings) {
+        self.sync = timings;
+    }
+
+    /// Decide whether to skip the optional work after the sync. It is skipped once less time is
+    /// left until the deadline than the commit has taken so far.
+    pub fn skip_optional_work(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(self.started);
+        self.skipped_optional_work = self.deadline.saturating_duration_since(now) < elapsed;
+        self.skipped_optional_work
+    }
+
+    /// Whether the optional work was skipped.
+    pub fn skipped_optional_work(&self) -> bool {
+        self.skipped_optional_work
+    }
+
+    pub fn finish(self) -> CommitReport {
+        let completed_at = Instant::now();
+        CommitReport {
+            deadline: self.deadline,
+            completed_at,
+            waiting: self.waiting,
+            rollback: self.rollback,
+            sync: self.sync,
+            total: completed_at.saturating_duration_since(self.started),
+            skipped_optional_work: self.skipped_optional_work,
+        }
+    }
+
+    fn lap(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(self.lap);
+        self.lap = now;
+        elapsed
+    }
+}
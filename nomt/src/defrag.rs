@@ -0,0 +1,240 @@
+//! Defragmenting the beatree files in the background. See [`crate::Nomt::defragment`].
+//!
+//! The leaves are scanned in key order, a few at a time. The values needed to move the leaves,
+//! branches and overflow pages found beyond the number of pages in use are written again, and
+//! synced like a commit with the access lock held for writing, so that sessions and commits
+//! proceed in between steps. These syncs also sort the free-lists, so that the lowest free pages
+//! are used first, and cut the free pages at the ends of the files off.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use parking_lot::{Condvar, Mutex, RwLock};
+
+use crate::{cancel::CancellationToken, page_cache::PageCache, store::Store};
+
+// The longest wait for sessions to end between checks for cancellation.
+const MAX_WAIT: Duration = Duration::from_millis(50);
+
+/// Parameters of a defragmentation of the beatree. See [`crate::Nomt::defragment`].
+#[derive(Debug, Clone)]
+pub struct DefragParams {
+    leaves_per_step: usize,
+    pause: Duration,
+}
+
+impl Default for DefragParams {
+    fn default() -> Self {
+        DefragParams {
+            leaves_per_step: 1024,
+            pause: Duration::from_millis(10),
+        }
+    }
+}
+
+impl DefragParams {
+    /// Set the number of leaves scanned per step. Each step is synced like a commit, during which
+    /// no sessions can begin. Fewer leaves make shorter steps, but more of them.
+    ///
+    /// Default: 1024.
+    pub fn leaves_per_step(mut self, leaves: usize) -> Self {
+        self.leaves_per_step = leaves.max(1);
+        self
+    }
+
+    /// Set the time to wait after each step, leaving the disk to commits.
+    ///
+    /// Default: 10ms.
+    pub fn pause(mut self, pause: Duration) -> Self {
+        self.pause = pause;
+        self
+    }
+}
+
+/// The state of a defragmentation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DefragStatus {
+    /// The leaves are still being scanned, or the files are being shrunk.
+    Running,
+    /// All leaves were scanned and the files shrunk.
+    Finished,
+    /// The defragmentation was cancelled. The pages moved so far stay moved.
+    Cancelled,
+    /// The defragmentation failed with the given error.
+    Failed(String),
+}
+
+/// The progress of a defragmentation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefragProgress {
+    /// The number of leaves scanned so far.
+    pub leaves_scanned: u64,
+    /// The number of leaves when the defragmentation started. Commits may change it meanwhile.
+    pub leaves_total: u64,
+    /// The number of leaf, branch and overflow pages moved so far.
+    pub pages_moved: u64,
+    /// The length of the beatree files when the defragmentation started, in bytes.
+    pub initial_bytes: u64,
+    /// The length of the beatree files after the last step, in bytes.
+    pub bytes: u64,
+    /// Whether the defragmentation is still running, and how it ended otherwise.
+    pub status: DefragStatus,
+}
+
+/// A handle to a defragmentation running in the background.
+///
+/// This is cheap to clone. Dropping the handle does not stop the defragmentation.
+#[derive(Clone)]
+pub struct DefragHandle {
+    shared: Arc<Shared>,
+    cancel: CancellationToken,
+}
+
+struct Shared {
+    leaves_scanned: AtomicU64,
+    leaves_total: u64,
+    pages_moved: AtomicU64,
+    initial_bytes: u64,
+    bytes: AtomicU64,
+    status: Mutex<DefragStatus>,
+    cvar: Condvar,
+}
+
+impl DefragHandle {
+    /// Get the progress made so far.
+    pub fn progress(&self) -> DefragProgress {
+        let status = self.shared.status.lock().clone();
+        self.progress_with(status)
+    }
+
+    /// Stop the defragmentation after the step in progress.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Block until the defragmentation has finished, been cancelled or failed.
+    pub fn wait(&self) -> DefragProgress {
+        let mut status = self.shared.status.lock();
+        while *status == DefragStatus::Running {
+            self.shared.cvar.wait(&mut status);
+        }
+        let status = status.clone();
+        self.progress_with(status)
+    }
+
+    fn progress_with(&self, status: DefragStatus) -> DefragProgress {
+        DefragProgress {
+            leaves_scanned: self.shared.leaves_scanned.load(Ordering::Relaxed),
+            leaves_total: self.shared.leaves_total,
+            pages_moved: self.shared.pages_moved.load(Ordering::Relaxed),
+            initial_bytes: self.shared.initial_bytes,
+            bytes: self.shared.bytes.load(Ordering::Relaxed),
+            status,
+        }
+    }
+}
+
+/// Defragment the beatree of the store on a background thread.
+pub fn spawn(
+    store: Store,
+    access_lock: Arc<RwLock<()>>,
+    page_cache: PageCache,
+    params: DefragParams,
+) -> anyhow::Result<DefragHandle> {
+    let initial_bytes = store.beatree_file_len()?;
+    let handle = DefragHandle {
+        shared: Arc::new(Shared {
+            leaves_scanned: AtomicU64::new(0),
+            leaves_total: store.beatree_leaf_count() as u64,
+            pages_moved: AtomicU64::new(0),
+            initial_bytes,
+            bytes: AtomicU64::new(initial_bytes),
+            status: Mutex::new(DefragStatus::Running),
+            cvar: Condvar::new(),
+        }),
+        cancel: CancellationToken::new(),
+    };
+
+    let _thread = std::thread::Builder::new()
+        .name("nomt-defrag".to_string())
+        .spawn({
+            let handle = handle.clone();
+            move || {
+                let status = match defragment(&store, &access_lock, page_cache, params, &handle) {
+                    Ok(true) => DefragStatus::Finished,
+                    Ok(false) => DefragStatus::Cancelled,
+                    Err(e) => DefragStatus::Failed(e.to_string()),
+                };
+                // Release the database before reporting the end, so that a database dropped
+                // after waiting for this is closed once the drop returns.
+                drop((store, access_lock));
+                *handle.shared.status.lock() = status;
+                handle.shared.cvar.notify_all();
+            }
+        })
+        .expect("failed to spawn defragmentation thread");
+
+    Ok(handle)
+}
+
+// Returns `false` if cancelled.
+fn defragment(
+    store: &Store,
+    access_lock: &RwLock<()>,
+    page_cache: PageCache,
+    params: DefragParams,
+    handle: &DefragHandle,
+) -> anyhow::Result<bool> {
+    // The first step only sorts the free-lists, so that the pages moved go to the lowest free
+    // pages. After the scan, one step frees the pages moved by the last, and the next shrinks the
+    // files by them.
+    let mut next = None;
+    let mut compacted = false;
+    let mut final_syncs = 0;
+    while final_syncs < 2 {
+        // The write gate comes first, so that a freeze doesn't find the access lock held.
+        let guards = loop {
+            if handle.cancel.is_cancelled() {
+                return Ok(false);
+            }
+            let gate = store.write_gate();
+            if let Some(guard) = access_lock.try_write_for(MAX_WAIT) {
+                break (gate, guard);
+            }
+        };
+
+        if compacted && next.is_none() {
+            final_syncs += 1;
+        }
+        let relocations =
+            store.defragment_step(page_cache.clone(), next, params.leaves_per_step)?;
+        next = if compacted {
+            relocations.next
+        } else {
+            Some([0; 32])
+        };
+        compacted = true;
+
+        let shared = &handle.shared;
+        shared
+            .leaves_scanned
+            .fetch_add(relocations.leaves as u64, Ordering::Relaxed);
+        shared
+            .pages_moved
+            .fetch_add(relocations.pages as u64, Ordering::Relaxed);
+        shared
+            .bytes
+            .store(store.beatree_file_len()?, Ordering::Relaxed);
+
+        drop(guards);
+        if final_syncs < 2 {
+            std::thread::sleep(params.pause);
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,345 @@
+//! The error type of opening, committing to and rolling back the database, and the errors of
+//! sessions and reads.
+
+use crate::{Cancelled, OutOfSpace, Precondition, Root};
+use nomt_core::trie::{KeyPath, ValueHash};
+use std::fmt;
+
+/// The error of opening the database, committing to it, resuming its sync or rolling it back.
+///
+/// The variants tell how the failure can be handled. Those carrying an [`anyhow::Error`] keep the
+/// full context of the failure, and [`Error::downcast_ref`] finds the typed errors in it, like
+/// the [`std::io::Error`] which caused it.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// An I/O operation failed. A failed commit poisons the database.
+    Io(anyhow::Error),
+    /// The files of the database hold invalid data.
+    Corruption(anyhow::Error),
+    /// The disk filled up while syncing a commit, which waits to be resumed with
+    /// [`crate::Nomt::resume_sync`].
+    OutOfSpace(OutOfSpace),
+    /// A prior commit failed, and the database refuses further commits. See
+    /// [`crate::Nomt::is_poisoned`].
+    Poisoned,
+    /// Rolling back, or recording the rollback delta of a commit, failed.
+    Rollback(anyhow::Error),
+    /// The operation was refused, as described by the [`SessionMisuse`].
+    Misuse(SessionMisuse),
+    /// The operation was cancelled, for example by the yield hook.
+    Cancelled,
+    /// Any other failure.
+    Other(anyhow::Error),
+}
+
+impl Error {
+    /// Returns the error of type `E` this was caused by, if any. Works like
+    /// [`anyhow::Error::downcast_ref`].
+    pub fn downcast_ref<E>(&self) -> Option<&E>
+    where
+        E: fmt::Display + fmt::Debug + Send + Sync + 'static,
+    {
+        match self {
+            Error::Io(e) | Error::Corruption(e) | Error::Rollback(e) | Error::Other(e) => {
+                e.downcast_ref()
+            }
+            Error::OutOfSpace(e) => (e as &dyn std::any::Any).downcast_ref(),
+            Error::Misuse(e) => (e as &dyn std::any::Any).downcast_ref(),
+            Error::Cancelled => (&Cancelled as &dyn std::any::Any).downcast_ref(),
+            Error::Poisoned => None,
+        }
+    }
+
+    /// Convert into an [`anyhow::Error`] holding the error this was classified from, so that it
+    /// downcasts to the same types.
+    pub fn into_anyhow(self) -> anyhow::Error {
+        match self {
+            Error::Io(e) | Error::Corruption(e) | Error::Rollback(e) | Error::Other(e) => e,
+            Error::OutOfSpace(e) => e.into(),
+            Error::Poisoned => Poisoned.into(),
+            Error::Misuse(e) => e.into(),
+            Error::Cancelled => Cancelled.into(),
+        }
+    }
+
+    /// Classify the failure of a rollback, for which anything but the more specific kinds of
+    /// errors is [`Error::Rollback`].
+    pub(crate) fn rollback(e: anyhow::Error) -> Self {
+        match Error::from(e) {
+            Error::Io(e) | Error::Corruption(e) | Error::Other(e) => Error::Rollback(e),
+            e => e,
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        let e = match e.downcast::<SessionMisuse>() {
+            Ok(misuse) => return Error::Misuse(misuse),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<OutOfSpace>() {
+            Ok(out_of_space) => return Error::OutOfSpace(out_of_space),
+            Err(e) => e,
+        };
+        if e.is::<Cancelled>() {
+            Error::Cancelled
+        } else if e.is::<Poisoned>() {
+            Error::Poisoned
+        } else if e.chain().any(|cause| cause.is::<std::io::Error>()) {
+            Error::Io(e)
+        } else if e.chain().any(|cause| cause.is::<Corruption>()) {
+            Error::Corruption(e)
+        } else {
+            Error::Other(e)
+        }
+    }
+}
+
+impl From<SessionMisuse> for Error {
+    fn from(misuse: SessionMisuse) -> Self {
+        Error::Misuse(misuse)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) | Error::Corruption(e) | Error::Rollback(e) | Error::Other(e) => {
+                fmt::Display::fmt(e, f)
+            }
+            Error::OutOfSpace(e) => fmt::Display::fmt(e, f),
+            Error::Poisoned => fmt::Display::fmt(&Poisoned, f),
+            Error::Misuse(e) => fmt::Display::fmt(e, f),
+            Error::Cancelled => fmt::Display::fmt(&Cancelled, f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) | Error::Corruption(e) | Error::Rollback(e) | Error::Other(e) => {
+                e.source()
+            }
+            Error::OutOfSpace(e) => e.source(),
+            Error::Poisoned | Error::Misuse(_) | Error::Cancelled => None,
+        }
+    }
+}
+
+/// The internal error of refusing a commit because the database is poisoned.
+#[derive(Debug)]
+pub(crate) struct Poisoned;
+
+impl fmt::Display for Poisoned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Store is poisoned due to prior error")
+    }
+}
+
+impl std::error::Error for Poisoned {}
+
+/// The internal error of finding invalid data in the files of the database.
+#[derive(Debug)]
+pub(crate) struct Corruption(pub(crate) String);
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Corruption {}
+
+/// An error reporting invalid data in the files of the database.
+pub(crate) fn corruption(message: impl Into<String>) -> anyhow::Error {
+    Corruption(message.into()).into()
+}
+
+/// The error returned by [`crate::Session::finish`] when the session would place a leaf deeper than
+/// allowed by [`crate::Options::max_trie_depth`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrieDepthExceeded {
+    /// The key of the deepest leaf.
+    pub key: KeyPath,
+    /// The depth the leaf would be placed at.
+    pub depth: usize,
+    /// The maximum depth.
+    pub max_depth: usize,
+}
+
+impl std::fmt::Display for TrieDepthExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "leaf at depth {} exceeds the maximum trie depth of {}",
+            self.depth, self.max_depth
+        )
+    }
+}
+
+impl std::error::Error for TrieDepthExceeded {}
+
+/// The error returned by [`crate::Session::finish`] when the actuals write a key more than once and
+/// [`crate::Options::duplicate_write_policy`] is [`crate::DuplicateWritePolicy::Reject`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateWrites {
+    /// The keys written more than once, in ascending order.
+    pub keys: Vec<KeyPath>,
+}
+
+impl std::fmt::Display for DuplicateWrites {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} key(s) written more than once", self.keys.len())
+    }
+}
+
+impl std::error::Error for DuplicateWrites {}
+
+/// The error returned when reading a value which is stored elsewhere and of which only the hash
+/// is kept. See [`crate::Session::write_value_hash`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueNotStored {
+    /// The key.
+    pub key: KeyPath,
+    /// The hash of the value.
+    pub value_hash: ValueHash,
+}
+
+impl std::fmt::Display for ValueNotStored {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "value of key {} is not stored locally",
+            self.key
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        )
+    }
+}
+
+impl std::error::Error for ValueNotStored {}
+
+/// The error returned when reading from a database opened with [`crate::Nomt::open_read_only`]
+/// after the writer has synced past the state the reader serves.
+///
+/// From the moment its next sync completes, the writer updates the hash table in place and may
+/// reuse the space of values the reader still refers to, so reads can no longer be trusted.
+/// Reopen the database to read the latest state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaleView {
+    /// The sequence number of the sync the reader serves the state of.
+    pub view_sync_seqn: u32,
+    /// The sequence number of the last sync completed by the writer. This equals
+    /// `view_sync_seqn` if the writer has only written checkpoints of its next sync since (see
+    /// [`crate::Options::sync_checkpoint_interval`]).
+    pub writer_sync_seqn: u32,
+}
+
+impl std::fmt::Display for StaleView {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the read-only view as of sync {} is stale, the writer has completed sync {}",
+            self.view_sync_seqn, self.writer_sync_seqn
+        )
+    }
+}
+
+impl std::error::Error for StaleView {}
+
+/// The error returned when sessions, finished sessions or overlays are used in a way the database
+/// can't honor. Commits report it as [`crate::Error::Misuse`], other operations wrap it in their
+/// [`anyhow::Error`].
+///
+/// Sessions begun at the same root compete: the first changeset committed wins, and the others
+/// are refused with [`SessionMisuse::StaleChangeset`]. Committing consumes the changeset, so the
+/// same one can't be committed twice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionMisuse {
+    /// The changeset builds on a root which is no longer the root of the database, because another
+    /// changeset was committed since.
+    StaleChangeset {
+        /// The root the changeset builds on.
+        expected: Root,
+        /// The current root of the database.
+        actual: Root,
+    },
+    /// The changeset builds on a root which a rollback, performed since the session began,
+    /// replaced.
+    RolledBack {
+        /// The root the changeset builds on.
+        expected: Root,
+        /// The current root of the database.
+        actual: Root,
+    },
+    /// The overlay builds on a parent overlay which is not the last one committed.
+    OverlayParentNotCommitted,
+    /// The calling thread began a session which is still live, and the operation would wait for
+    /// it forever. Finish or drop the session first.
+    LiveSessionOnThread,
+    /// The changeset was built by a session which read values through from the remote archive.
+    /// The local merkle pages don't cover those values, so the changeset can't be committed. See
+    /// [`crate::Options::read_through`].
+    ReadThrough,
+}
+
+impl std::fmt::Display for SessionMisuse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionMisuse::StaleChangeset { expected, actual } => write!(
+                f,
+                "Changeset no longer valid (expected previous root {:?}, got {:?})",
+                expected, actual
+            ),
+            SessionMisuse::RolledBack { expected, actual } => write!(
+                f,
+                "Changeset invalidated by a rollback (expected previous root {:?}, got {:?})",
+                expected, actual
+            ),
+            SessionMisuse::OverlayParentNotCommitted => write!(f, "Overlay parent not committed"),
+            SessionMisuse::LiveSessionOnThread => {
+                write!(f, "the calling thread holds a live session")
+            }
+            SessionMisuse::ReadThrough => {
+                write!(f, "the changeset was built on values read through")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SessionMisuse {}
+
+/// A [`Precondition`] which did not hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViolatedPrecondition {
+    /// The key the precondition was attached to.
+    pub key: KeyPath,
+    /// The precondition.
+    pub precondition: Precondition,
+    /// The hash of the value the key actually holds, if any.
+    pub actual: Option<ValueHash>,
+}
+
+/// The error returned by [`crate::Session::finish`] when any of the session's preconditions do
+/// not hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreconditionsViolated {
+    /// The violated preconditions, in ascending key order.
+    pub violated: Vec<ViolatedPrecondition>,
+}
+
+impl std::fmt::Display for PreconditionsViolated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} session precondition(s) violated",
+            self.violated.len()
+        )
+    }
+}
+
+impl std::error::Error for PreconditionsViolated {}
@@ -0,0 +1,244 @@
+//! Resizing the hash table in the background. See [`crate::Nomt::resize_hash_table`].
+//!
+//! The buckets are copied into the new hash table in chunks, each under the sync lock of the
+//! store, so that commits proceed in between. Switching over to the new hash table waits for all
+//! sessions to end, like a commit does, since the bucket indices cached for pages only apply to
+//! the old one.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use parking_lot::{Condvar, Mutex, RwLock};
+
+use crate::{
+    cancel::CancellationToken,
+    metrics::{Metric, Metrics},
+    store::Store,
+};
+
+// The number of buckets copied per chunk.
+const CHUNK_BUCKETS: u64 = 4096;
+
+// The longest wait for sessions to end between checks for cancellation.
+const MAX_WAIT: Duration = Duration::from_millis(50);
+
+// The most commits an automatic resize waits for after failing.
+const MAX_AUTO_BACKOFF: u64 = 1024;
+
+/// The resizes started by commits. See [`crate::Options::hashtable_auto_resize`].
+///
+/// After each failure, automatic resizes wait for twice as many commits as after the last one,
+/// up to 1024.
+pub struct AutoResize {
+    /// The occupancy beyond which commits start doubling the hash table.
+    pub load_factor: f64,
+    /// The most recently started automatic resize, until it is seen to end.
+    started: Option<HashTableResizeHandle>,
+    failures: u32,
+    /// The commits left to wait for before trying again.
+    backoff: u64,
+}
+
+impl AutoResize {
+    pub fn new(load_factor: f64) -> Self {
+        AutoResize {
+            load_factor,
+            started: None,
+            failures: 0,
+            backoff: 0,
+        }
+    }
+
+    /// Called after a commit. Returns whether a resize may be started, which is the case unless
+    /// an automatic resize is still running or failures are being backed off from.
+    pub fn poll(&mut self, metrics: &Metrics) -> bool {
+        if let Some(ref started) = self.started {
+            match started.progress().status {
+                HashTableResizeStatus::Running => return false,
+                HashTableResizeStatus::Finished => self.failures = 0,
+                HashTableResizeStatus::Cancelled => {}
+                HashTableResizeStatus::Failed(_) => self.fail(metrics),
+            }
+            self.started = None;
+        }
+
+        if self.backoff > 0 {
+            self.backoff -= 1;
+            return false;
+        }
+        true
+    }
+
+    /// Record a resize started automatically.
+    pub fn started(&mut self, handle: HashTableResizeHandle) {
+        self.started = Some(handle);
+    }
+
+    /// Record an automatic resize which failed to start or failed.
+    pub fn fail(&mut self, metrics: &Metrics) {
+        self.failures = self.failures.saturating_add(1);
+        self.backoff = 1u64
+            .checked_shl(self.failures)
+            .map_or(MAX_AUTO_BACKOFF, |backoff| backoff.min(MAX_AUTO_BACKOFF));
+        metrics.count(Metric::HashTableResizeFailures);
+    }
+}
+
+/// The state of a hash table resize.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HashTableResizeStatus {
+    /// Buckets are still being copied, or the switch is waiting for sessions to end.
+    Running,
+    /// The database has switched over to the new hash table.
+    Finished,
+    /// The resize was cancelled before the switch. The hash table keeps its size.
+    Cancelled,
+    /// The resize failed with the given error.
+    ///
+    /// Unless the database is poisoned, the hash table keeps its size.
+    Failed(String),
+}
+
+/// The progress of a hash table resize.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashTableResizeProgress {
+    /// The number of buckets of the old hash table copied so far.
+    pub buckets_copied: u64,
+    /// The number of buckets of the old hash table.
+    pub buckets_total: u64,
+    /// Whether the resize is still running, and how it ended otherwise.
+    pub status: HashTableResizeStatus,
+}
+
+/// A handle to a hash table resize running in the background.
+///
+/// This is cheap to clone. Dropping the handle does not stop the resize.
+#[derive(Clone)]
+pub struct HashTableResizeHandle {
+    shared: Arc<Shared>,
+    cancel: CancellationToken,
+}
+
+struct Shared {
+    buckets_copied: AtomicU64,
+    buckets_total: u64,
+    status: Mutex<HashTableResizeStatus>,
+    cvar: Condvar,
+}
+
+impl HashTableResizeHandle {
+    /// Get the progress made so far.
+    pub fn progress(&self) -> HashTableResizeProgress {
+        let status = self.shared.status.lock().clone();
+        self.progress_with(status)
+    }
+
+    /// Stop the resize and remove the new hash table, unless the switch is already underway.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Block until the resize has finished, been cancelled or failed.
+    pub fn wait(&self) -> HashTableResizeProgress {
+        let mut status = self.shared.status.lock();
+        while *status == HashTableResizeStatus::Running {
+            self.shared.cvar.wait(&mut status);
+        }
+        let status = status.clone();
+        self.progress_with(status)
+    }
+
+    fn progress_with(&self, status: HashTableResizeStatus) -> HashTableResizeProgress {
+        HashTableResizeProgress {
+            buckets_copied: self.shared.buckets_copied.load(Ordering::Relaxed),
+            buckets_total: self.shared.buckets_total,
+            status,
+        }
+    }
+}
+
+/// Copy the buckets of the resize begun in the store on a background thread, and then call
+/// `switch` with the access lock held for writing.
+///
+/// `switch` is expected to finish the resize in the store and to drop everything which refers to
+/// the old hash table.
+pub fn spawn(
+    store: Store,
+    access_lock: Arc<RwLock<()>>,
+    buckets_total: u64,
+    switch: impl FnOnce() -> anyhow::Result<()> + Send + 'static,
+) -> HashTableResizeHandle {
+    let handle = HashTableResizeHandle {
+        shared: Arc::new(Shared {
+            buckets_copied: AtomicU64::new(0),
+            buckets_total,
+            status: Mutex::new(HashTableResizeStatus::Running),
+            cvar: Condvar::new(),
+        }),
+        cancel: CancellationToken::new(),
+    };
+
+    let _thread = std::thread::Builder::new()
+        .name("nomt-ht-resize".to_string())
+        .spawn({
+            let handle = handle.clone();
+            move || {
+                let status = match resize(&store, &access_lock, &handle, switch) {
+                    Ok(true) => HashTableResizeStatus::Finished,
+                    Ok(false) => HashTableResizeStatus::Cancelled,
+                    Err(e) => HashTableResizeStatus::Failed(e.to_string()),
+                };
+                if status != HashTableResizeStatus::Finished {
+                    let _ = store.cancel_hash_table_resize();
+                }
+                // Release the database before reporting the end, so that a database dropped
+                // after waiting for this is closed once the drop returns.
+                drop((store, access_lock));
+                *handle.shared.status.lock() = status;
+                handle.shared.cvar.notify_all();
+            }
+        })
+        .expect("failed to spawn hash table resize thread");
+
+    handle
+}
+
+// Returns `false` if cancelled before the switch.
+fn resize(
+    store: &Store,
+    access_lock: &RwLock<()>,
+    handle: &HashTableResizeHandle,
+    switch: impl FnOnce() -> anyhow::Result<()>,
+) -> anyhow::Result<bool> {
+    loop {
+        if handle.cancel.is_cancelled() {
+            return Ok(false);
+        }
+        let copied = store.hash_table_resize_step(CHUNK_BUCKETS)?;
+        handle
+            .shared
+            .buckets_copied
+            .store(copied, Ordering::Relaxed);
+        if copied == handle.shared.buckets_total {
+            break;
+        }
+    }
+
+    // The write gate comes first, so that a freeze doesn't find the access lock held.
+    let _guards = loop {
+        if handle.cancel.is_cancelled() {
+            return Ok(false);
+        }
+        let gate = store.write_gate();
+        if let Some(guard) = access_lock.try_write_for(MAX_WAIT) {
+            break (gate, guard);
+        }
+    };
+    switch()?;
+    Ok(true)
+}
@@ -0,0 +1,326 @@
+//! Checks of the consistency of the files of a database.
+//!
+//! [`Nomt::check_integrity`](crate::Nomt::check_integrity) walks the buckets of the hash table,
+//! the branch and leaf nodes of the beatree and the WAL, and with [`IntegrityLevel::Full`] also the
+//! merkle pages and the values. It returns an [`IntegrityReport`] listing every inconsistency
+//! found, rather than stopping at the first one.
+//!
+//! Failing to read the files is an error, not an inconsistency.
+
+use crate::{
+    io::{page_pool::FatPage, PAGE_SIZE},
+    merkle::ElidedChildren,
+    store::{Store, StoredValue},
+    HashAlgorithm,
+};
+use nomt_core::{
+    page::{DEPTH, NODES_PER_PAGE},
+    page_id::{ChildPageIndex, PageId, ROOT_PAGE_ID},
+    trie::{self, InternalData, KeyPath, Node, TERMINATOR},
+};
+use std::collections::HashSet;
+
+/// How thoroughly to check the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrityLevel {
+    /// Check the buckets of the hash table, the branch and leaf nodes of the beatree and the WAL.
+    ///
+    /// This reads every occupied bucket and every leaf node once.
+    Structure,
+    /// Also check that the merkle pages hash up to the root, and that the values hash up to the
+    /// same root.
+    ///
+    /// This additionally reads every value, including those in overflow pages.
+    Full,
+}
+
+/// The outcome of [`Nomt::check_integrity`](crate::Nomt::check_integrity).
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityReport {
+    /// The number of occupied buckets of the hash table checked.
+    pub buckets: u64,
+    /// The number of bottom-level branch nodes checked.
+    pub branch_nodes: u64,
+    /// The number of leaf nodes checked.
+    pub leaf_nodes: u64,
+    /// The number of entries read back from the WAL. Zero unless a sync was interrupted.
+    pub wal_entries: u64,
+    /// The number of merkle pages walked from the root page. Zero unless the level is
+    /// [`IntegrityLevel::Full`].
+    pub pages: u64,
+    /// The number of values hashed into the root. Zero unless the level is
+    /// [`IntegrityLevel::Full`].
+    pub values: u64,
+    /// The inconsistencies found, in the order they were found.
+    pub inconsistencies: Vec<Inconsistency>,
+}
+
+impl IntegrityReport {
+    /// Whether no inconsistencies were found.
+    pub fn is_consistent(&self) -> bool {
+        self.inconsistencies.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, inconsistency: Inconsistency) {
+        self.inconsistencies.push(inconsistency);
+    }
+}
+
+/// An inconsistency found by [`Nomt::check_integrity`](crate::Nomt::check_integrity).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Inconsistency {
+    /// The bucket holds a page whose ID is invalid.
+    InvalidPageId {
+        /// The index of the bucket.
+        bucket: u64,
+    },
+    /// The meta byte of the bucket does not match the hash of the ID of the page it holds.
+    BucketHashMismatch {
+        /// The index of the bucket.
+        bucket: u64,
+        /// The ID of the page held by the bucket.
+        page_id: PageId,
+    },
+    /// Probing the hash table for the page does not reach the bucket holding it.
+    UnreachableBucket {
+        /// The index of the bucket.
+        bucket: u64,
+        /// The ID of the page held by the bucket.
+        page_id: PageId,
+    },
+    /// The page is held by more than one bucket.
+    DuplicatePage {
+        /// The index of the bucket holding the page again.
+        bucket: u64,
+        /// The ID of the page.
+        page_id: PageId,
+    },
+    /// The WAL cannot be read back.
+    InvalidWal(String),
+    /// The separators of a branch node are not in ascending order, or do not follow those of the
+    /// previous branch node.
+    BranchOrder {
+        /// The page number of the branch node in the branch node store.
+        branch: u32,
+    },
+    /// A branch node refers to a leaf node which is not allocated in the leaf store.
+    DanglingLeaf {
+        /// The page number of the leaf node in the leaf store.
+        leaf: u32,
+    },
+    /// More than one branch node entry refers to the leaf node.
+    DuplicateLeaf {
+        /// The page number of the leaf node in the leaf store.
+        leaf: u32,
+    },
+    /// The keys of a leaf node are not in ascending order, or out of the range given by the
+    /// separators of the branch node referring to it.
+    LeafOrder {
+        /// The page number of the leaf node in the leaf store.
+        leaf: u32,
+    },
+    /// An internal node of a merkle page is not the hash of its children.
+    NodeHashMismatch {
+        /// The ID of the page.
+        page_id: PageId,
+        /// The index of the node in the page.
+        node_index: usize,
+    },
+    /// The top nodes of the page do not hash to the node above it, in the parent page or the
+    /// root.
+    PageHashMismatch {
+        /// The ID of the page.
+        page_id: PageId,
+    },
+    /// A page below an internal node is missing, without being marked as elided.
+    MissingPage {
+        /// The ID of the page.
+        page_id: PageId,
+    },
+    /// A page held by the hash table is not reachable from the root page.
+    OrphanPage {
+        /// The ID of the page.
+        page_id: PageId,
+    },
+    /// A value in overflow pages does not decode, or does not hash to the value hash stored along
+    /// with it.
+    ValueHashMismatch {
+        /// The key of the value.
+        key: KeyPath,
+    },
+    /// The values do not hash up to the root.
+    RootMismatch {
+        /// The root of the database.
+        recorded: Node,
+        /// The root of the trie of the values.
+        computed: Node,
+    },
+}
+
+/// Check the database. The caller keeps commits out.
+pub(crate) fn check<H: HashAlgorithm>(
+    store: &Store,
+    root: Node,
+    level: IntegrityLevel,
+) -> anyhow::Result<IntegrityReport> {
+    let mut report = IntegrityReport::default();
+    let stored_pages = store.check_integrity(&mut report)?;
+    if level == IntegrityLevel::Full {
+        check_pages::<H>(store, root, stored_pages, &mut report)?;
+        check_values::<H>(store, root, &mut report)?;
+    }
+    Ok(report)
+}
+
+/// Walk the merkle pages from the root page, checking the hash of every internal node.
+fn check_pages<H: HashAlgorithm>(
+    store: &Store,
+    root: Node,
+    mut stored_pages: HashSet<PageId>,
+    report: &mut IntegrityReport,
+) -> anyhow::Result<()> {
+    // The pages to visit, with the node their top nodes must hash to.
+    let mut pending = Vec::new();
+    if trie::is_internal::<H>(&root) {
+        pending.push((ROOT_PAGE_ID, root));
+    } else if let Some((page, _)) = store.load_page(ROOT_PAGE_ID)? {
+        // A root which is a leaf or the terminator leaves the root page empty.
+        if node(&page, 0) != TERMINATOR || node(&page, 1) != TERMINATOR {
+            report.push(Inconsistency::PageHashMismatch {
+                page_id: ROOT_PAGE_ID,
+            });
+        }
+        stored_pages.remove(&ROOT_PAGE_ID);
+    }
+
+    while let Some((page_id, expected)) = pending.pop() {
+        let Some((page, _)) = store.load_page(page_id.clone())? else {
+            report.push(Inconsistency::MissingPage { page_id });
+            continue;
+        };
+        report.pages += 1;
+
+        if hash_children::<H>(&page, None) != expected {
+            report.push(Inconsistency::PageHashMismatch {
+                page_id: page_id.clone(),
+            });
+        }
+
+        let elided = ElidedChildren::from_bytes(
+            // UNWRAP: the slice is 8 bytes long.
+            page[PAGE_SIZE - 32 - 8..PAGE_SIZE - 32].try_into().unwrap(),
+        );
+        // Only the nodes below internal nodes are part of the trie.
+        let bottom_layer = NODES_PER_PAGE - (1 << DEPTH);
+        let mut internal = vec![0, 1];
+        while let Some(node_index) = internal.pop() {
+            let node = node(&page, node_index);
+            if !trie::is_internal::<H>(&node) {
+                continue;
+            }
+            if node_index < bottom_layer {
+                if hash_children::<H>(&page, Some(node_index)) != node {
+                    report.push(Inconsistency::NodeHashMismatch {
+                        page_id: page_id.clone(),
+                        node_index,
+                    });
+                }
+                internal.extend([node_index * 2 + 2, node_index * 2 + 3]);
+                continue;
+            }
+
+            // UNWRAP: the bottom layer has 64 nodes.
+            let child_index = ChildPageIndex::new((node_index - bottom_layer) as u8).unwrap();
+            if elided.is_elided(child_index.clone()) {
+                continue;
+            }
+            match page_id.child_page_id(child_index) {
+                Ok(child_id) => pending.push((child_id, node)),
+                Err(_) => report.push(Inconsistency::NodeHashMismatch {
+                    page_id: page_id.clone(),
+                    node_index,
+                }),
+            }
+        }
+        stored_pages.remove(&page_id);
+    }
+
+    let mut orphans = stored_pages.into_iter().collect::<Vec<_>>();
+    orphans.sort();
+    for page_id in orphans {
+        report.push(Inconsistency::OrphanPage { page_id });
+    }
+    Ok(())
+}
+
+/// Hash all values into a trie and compare its root to the root of the database.
+fn check_values<H: HashAlgorithm>(
+    store: &Store,
+    root: Node,
+    report: &mut IntegrityReport,
+) -> anyhow::Result<()> {
+    let mut values = store.iter_values([0; 32], None);
+    let mut error = None;
+    let mut count = 0;
+    let mut mismatches = Vec::new();
+    let leaves = std::iter::from_fn(|| {
+        let (key, value_hash) = match values.next_stored()? {
+            Ok((key, StoredValue::Inline(value))) => (key, H::hash_value(&value)),
+            Ok((key, StoredValue::Overflow(value_hash))) => {
+                match store.load_value(key) {
+                    Ok(Some(value)) if H::hash_value(&value) == value_hash => {}
+                    Ok(_) => mismatches.push(Inconsistency::ValueHashMismatch { key }),
+                    // A value which can't be decompressed is as wrong as one of another hash.
+                    Err(e)
+                        if e.downcast_ref::<std::io::Error>()
+                            .is_some_and(|e| e.kind() == std::io::ErrorKind::InvalidData) =>
+                    {
+                        mismatches.push(Inconsistency::ValueHashMismatch { key })
+                    }
+                    Err(e) => {
+                        error = Some(e);
+                        return None;
+                    }
+                }
+                (key, value_hash)
+            }
+            // Values stored elsewhere only have their hash.
+            Ok((key, StoredValue::HashOnly(value_hash))) => (key, value_hash),
+            Err(e) => {
+                error = Some(e);
+                return None;
+            }
+        };
+        count += 1;
+        Some((key, value_hash))
+    });
+    let computed = nomt_core::update::build_trie::<H>(0, leaves, |_| {});
+    if let Some(e) = error {
+        return Err(e);
+    }
+
+    report.values = count;
+    report.inconsistencies.extend(mismatches);
+    if computed != root {
+        report.push(Inconsistency::RootMismatch {
+            recorded: root,
+            computed,
+        });
+    }
+    Ok(())
+}
+
+fn node(page: &FatPage, index: usize) -> Node {
+    // UNWRAP: the slice is 32 bytes long.
+    page[index * 32..(index + 1) * 32].try_into().unwrap()
+}
+
+/// Hash the children of the node at the given index, or the top nodes of the page.
+fn hash_children<H: HashAlgorithm>(page: &FatPage, node_index: Option<usize>) -> Node {
+    let left = node_index.map_or(0, |i| i * 2 + 2);
+    H::hash_internal(&InternalData {
+        left: node(page, left),
+        right: node(page, left + 1),
+    })
+}
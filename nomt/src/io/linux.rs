@@ -1,8 +1,12 @@
-use super::{CompleteIo, IoCommand, IoKind, IoKindResult, IoPacket, PagePool, PAGE_SIZE};
+use super::{
+    Chaos, CompleteIo, CompletionSender, IoCommand, IoKind, IoKindResult, IoPacket, Metrics,
+    PagePool, WriteThrottle, PAGE_SIZE,
+};
+use crate::metrics::Metric;
 use crossbeam_channel::{Receiver, Sender, TryRecvError};
 use io_uring::{cqueue, opcode, squeue, types, IoUring};
 use slab::Slab;
-use std::collections::VecDeque;
+use std::{collections::VecDeque, sync::Arc, time::Instant};
 use threadpool::ThreadPool;
 
 const RING_CAPACITY: u32 = 1024;
@@ -37,18 +41,30 @@ pub fn check_iou_permissions() -> super::IoUringPermission {
 
 struct PendingIo {
     command: IoCommand,
-    completion_sender: Sender<CompleteIo>,
+    completion_sender: CompletionSender,
+    sent_at: Option<Instant>,
 }
 
 pub fn start_io_worker(
     page_pool: PagePool,
     io_workers_tp: &ThreadPool,
     io_workers: usize,
+    throttle: Arc<WriteThrottle>,
+    chaos: Chaos,
+    metrics: Metrics,
 ) -> Sender<IoPacket> {
     // main bound is from the pending slab.
     let (command_tx, command_rx) = crossbeam_channel::unbounded();
 
-    start_workers(page_pool, io_workers_tp, command_rx, io_workers);
+    start_workers(
+        page_pool,
+        io_workers_tp,
+        command_rx,
+        io_workers,
+        throttle,
+        chaos,
+        metrics,
+    );
 
     command_tx
 }
@@ -58,17 +74,29 @@ fn start_workers(
     io_workers_tp: &ThreadPool,
     command_rx: Receiver<IoPacket>,
     io_workers: usize,
+    throttle: Arc<WriteThrottle>,
+    chaos: Chaos,
+    metrics: Metrics,
 ) {
     for _ in 0..io_workers {
         io_workers_tp.execute({
             let page_pool = page_pool.clone();
             let command_rx = command_rx.clone();
-            move || run_worker(page_pool, command_rx)
+            let throttle = throttle.clone();
+            let chaos = chaos.clone();
+            let metrics = metrics.clone();
+            move || run_worker(page_pool, command_rx, throttle, chaos, metrics)
         });
     }
 }
 
-fn run_worker(page_pool: PagePool, command_rx: Receiver<IoPacket>) {
+fn run_worker(
+    page_pool: PagePool,
+    command_rx: Receiver<IoPacket>,
+    throttle: Arc<WriteThrottle>,
+    chaos: Chaos,
+    metrics: Metrics,
+) {
     let mut pending: Slab<PendingIo> = Slab::with_capacity(MAX_IN_FLIGHT);
 
     let mut ring = IoUring::<squeue::Entry, cqueue::Entry>::builder()
@@ -93,6 +121,7 @@ fn run_worker(page_pool: PagePool, command_rx: Receiver<IoPacket>) {
                 let PendingIo {
                     command,
                     completion_sender,
+                    sent_at,
                 } = pending.remove(completion_event.user_data() as usize);
 
                 // io_uring never uses errno to pass back error information.
@@ -109,13 +138,14 @@ fn run_worker(page_pool: PagePool, command_rx: Receiver<IoPacket>) {
                         retries.push_back(IoPacket {
                             command,
                             completion_sender,
+                            sent_at,
                         });
                         continue;
                     }
                 };
 
                 let complete = CompleteIo { command, result };
-                let _ = completion_sender.send(complete);
+                super::complete_io(&throttle, &chaos, &completion_sender, sent_at, complete);
             }
         } else if shutdown {
             // No pending IOs and we are shutting down. That means we can exit the worker.
@@ -163,6 +193,7 @@ fn run_worker(page_pool: PagePool, command_rx: Receiver<IoPacket>) {
             let pending_index = pending.insert(PendingIo {
                 command: next_io.command,
                 completion_sender: next_io.completion_sender,
+                sent_at: next_io.sent_at,
             });
 
             let entry = submission_entry(&mut pending.get_mut(pending_index).unwrap().command)
@@ -175,6 +206,7 @@ fn run_worker(page_pool: PagePool, command_rx: Receiver<IoPacket>) {
         // 3. submit all together.
         if to_submit {
             submit_queue.sync();
+            metrics.observe(Metric::IoQueueDepth, pending.len() as u64);
         }
 
         let wait = if pending.len() == MAX_IN_FLIGHT { 1 } else { 0 };
@@ -0,0 +1,131 @@
+//! Memory-mapped reads, used with [`crate::ReadBackend::Mmap`].
+//!
+//! Reads of a mapped file copy the page out of a shared, read-only mapping of the file on the
+//! calling thread instead of issuing a syscall. Writes still go through the I/O pool and reach
+//! the mapping through the page cache of the operating system.
+//!
+//! Files may grow while they are mapped. A read beyond the end of the mapping maps the file again
+//! at its current size.
+//!
+//! Files can't be mapped on Windows.
+
+use super::{FatPage, PAGE_SIZE};
+use crate::sys::RawFd;
+use parking_lot::RwLock;
+use std::{collections::HashMap, fs::File, io, sync::Arc};
+
+/// The files mapped for reads, by file descriptor.
+pub type MappedFiles = HashMap<RawFd, Arc<MappedFile>>;
+
+/// A file mapped for reading pages.
+pub struct MappedFile {
+    file: File,
+    mapping: RwLock<Mapping>,
+}
+
+impl MappedFile {
+    /// Map the file at its current size.
+    pub fn new(file: File) -> io::Result<Self> {
+        let mapping = Mapping::new(&file)?;
+        Ok(MappedFile {
+            file,
+            mapping: RwLock::new(mapping),
+        })
+    }
+
+    /// Copy the page with the given page number into `page`.
+    ///
+    /// Fails with [`io::ErrorKind::UnexpectedEof`] if the page lies beyond the end of the file.
+    pub fn read_page(&self, pn: u64, page: &mut FatPage) -> io::Result<()> {
+        let offset = pn as usize * PAGE_SIZE;
+        {
+            let mapping = self.mapping.read();
+            if let Some(data) = mapping.page(offset) {
+                page.copy_from_slice(data);
+                return Ok(());
+            }
+        }
+
+        let mut mapping = self.mapping.write();
+        if mapping.page(offset).is_none() {
+            *mapping = Mapping::new(&self.file)?;
+        }
+        match mapping.page(offset) {
+            Some(data) => {
+                page.copy_from_slice(data);
+                Ok(())
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("page {pn} is beyond the end of the mapped file"),
+            )),
+        }
+    }
+}
+
+struct Mapping {
+    ptr: *const u8,
+    len: usize,
+}
+
+// SAFETY: the mapping is read-only and only unmapped on drop.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    #[cfg(windows)]
+    fn new(_file: &File) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "memory-mapped reads are not supported on Windows",
+        ))
+    }
+
+    #[cfg(unix)]
+    fn new(file: &File) -> io::Result<Self> {
+        use crate::sys::AsRawFd as _;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            return Ok(Mapping {
+                ptr: std::ptr::null(),
+                len: 0,
+            });
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            ptr: ptr as *const u8,
+            len,
+        })
+    }
+
+    fn page(&self, offset: usize) -> Option<&[u8]> {
+        if offset + PAGE_SIZE > self.len {
+            return None;
+        }
+        // SAFETY: the range is within the mapping, which lives as long as `self`.
+        Some(unsafe { std::slice::from_raw_parts(self.ptr.add(offset), PAGE_SIZE) })
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if self.len > 0 {
+            unsafe {
+                libc::munmap(self.ptr as *mut libc::c_void, self.len);
+            }
+        }
+    }
+}
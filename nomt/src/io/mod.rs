@@ -1,30 +1,47 @@
-#[cfg(not(target_family = "unix"))]
-std::compile_error!("NOMT only supports Unix-based OSs");
+#[cfg(not(any(unix, windows)))]
+std::compile_error!("NOMT only supports Unix-based OSs and Windows");
 
+use crate::{
+    chaos::Chaos,
+    metrics::Metrics,
+    sys::{AsRawFd as _, RawFd},
+};
 use crossbeam_channel::{Receiver, RecvError, SendError, Sender, TryRecvError};
+use mmap::{MappedFile, MappedFiles};
 use page_pool::Page;
+use parking_lot::Mutex;
 use std::{
     fmt,
     fs::File,
-    os::fd::RawFd,
     sync::{Arc, Weak},
+    task::Waker,
+    time::{Duration, Instant},
 };
 use threadpool::ThreadPool;
 
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
 #[path = "linux.rs"]
 mod platform;
 
-#[cfg(not(target_os = "linux"))]
-#[path = "unix.rs"]
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+#[path = "portable.rs"]
 mod platform;
 
 pub mod fsyncer;
+pub mod mmap;
+#[cfg(not(any(feature = "safe-page-pool", windows)))]
+pub mod page_pool;
+#[cfg(any(feature = "safe-page-pool", windows))]
+#[path = "page_pool_safe.rs"]
 pub mod page_pool;
+pub mod throttle;
+pub mod write_tracker;
 
 pub const PAGE_SIZE: usize = 4096;
 
 pub use page_pool::{FatPage, PagePool};
+pub use throttle::WriteThrottle;
+pub use write_tracker::{TrackedWrites, WriteTracker};
 
 /// Whether the current device has permission to use io_uring.
 ///
@@ -34,8 +51,9 @@ pub enum IoUringPermission {
     Allowed,
     /// The device does not have permission to use io_uring.
     Denied,
-    /// This version of NOMT was compiled targeting a non-Linux platform, or a version of Linux
-    /// where the syscalls or flags are not available, so io_uring is not supported.
+    /// This version of NOMT was compiled targeting a non-Linux platform or without the
+    /// `io-uring` feature, or a version of Linux where the syscalls or flags are not available,
+    /// so io_uring is not supported.
     ///
     /// Note that a 6.x or newer Linux kernel is required to support the flags NOMT uses.
     NotSupported,
@@ -43,7 +61,8 @@ pub enum IoUringPermission {
 
 /// Check whether the current device has permission to use io_uring.
 ///
-/// On non-Linux platforms, this will always return `NotSupported`.
+/// On non-Linux platforms, or without the `io-uring` feature, this will always return
+/// `NotSupported`.
 pub fn check_iou_permissions() -> IoUringPermission {
     platform::check_iou_permissions()
 }
@@ -117,27 +136,100 @@ pub struct CompleteIo {
     pub result: std::io::Result<()>,
 }
 
+// Sends completions back to a handle, waking the task waiting on it, if any.
+#[derive(Clone)]
+struct CompletionSender {
+    sender: Sender<CompleteIo>,
+    waker: Option<Arc<Mutex<Option<Waker>>>>,
+}
+
+impl CompletionSender {
+    fn new(sender: Sender<CompleteIo>, wakes: bool) -> Self {
+        CompletionSender {
+            sender,
+            waker: wakes.then(Default::default),
+        }
+    }
+
+    fn send(&self, complete: CompleteIo) -> Result<(), SendError<CompleteIo>> {
+        self.sender.send(complete)?;
+        // The waker is taken after sending, so a task registering it before checking for
+        // completions never misses one.
+        if let Some(waker) = self.waker.as_ref().and_then(|waker| waker.lock().take()) {
+            waker.wake();
+        }
+        Ok(())
+    }
+}
+
 struct IoPacket {
     command: IoCommand,
-    completion_sender: Sender<CompleteIo>,
+    completion_sender: CompletionSender,
+    // The time of submission, for foreground I/O when the write throttle is enabled.
+    sent_at: Option<Instant>,
+}
+
+// Send back a completion, feeding the latency of foreground I/O to the throttle.
+fn complete_io(
+    throttle: &WriteThrottle,
+    chaos: &Chaos,
+    completion_sender: &CompletionSender,
+    sent_at: Option<Instant>,
+    complete: CompleteIo,
+) {
+    chaos.delay_io();
+    if let Some(sent_at) = sent_at {
+        let now = Instant::now();
+        throttle.record(now.saturating_duration_since(sent_at), now);
+    }
+    let _ = completion_sender.send(complete);
 }
 
 /// Create an I/O worker managing an io_uring and sending responses back via channels to a number
 /// of handles.
-pub fn start_io_pool(io_workers: usize, page_pool: PagePool) -> IoPool {
+///
+/// Background writes are paced to keep the latency of all other I/O within
+/// `write_throttle_target`, if provided. See the [`throttle`] module. Completions are delayed
+/// at random in chaos mode. The depth of the io_uring queues is recorded in the metrics.
+pub fn start_io_pool(
+    io_workers: usize,
+    page_pool: PagePool,
+    write_throttle_target: Option<Duration>,
+    chaos: Chaos,
+    metrics: Metrics,
+) -> IoPool {
     let io_workers_tp = ThreadPool::with_name("io-worker".to_string(), io_workers);
-    let sender = platform::start_io_worker(page_pool.clone(), &io_workers_tp, io_workers);
+    let throttle = Arc::new(WriteThrottle::new(write_throttle_target));
+    let sender = platform::start_io_worker(
+        page_pool.clone(),
+        &io_workers_tp,
+        io_workers,
+        throttle.clone(),
+        chaos.clone(),
+        metrics.clone(),
+    );
     let sender = Some(Arc::new(sender));
     IoPool {
         sender,
         page_pool,
         io_workers_tp,
+        throttle,
+        chaos,
+        metrics,
+        mapped: Arc::new(MappedFiles::new()),
+        write_tracker: Arc::new(WriteTracker::default()),
     }
 }
 
 #[cfg(test)]
 pub fn start_test_io_pool(io_workers: usize, page_pool: PagePool) -> IoPool {
-    start_io_pool(io_workers, page_pool)
+    start_io_pool(
+        io_workers,
+        page_pool,
+        None,
+        Chaos::default(),
+        Metrics::new(false),
+    )
 }
 
 /// A manager for the broader I/O pool. This can be used to create new I/O handles.
@@ -153,13 +245,61 @@ pub struct IoPool {
     sender: Option<Arc<Sender<IoPacket>>>,
     page_pool: PagePool,
     io_workers_tp: ThreadPool,
+    throttle: Arc<WriteThrottle>,
+    chaos: Chaos,
+    metrics: Metrics,
+    /// The files whose reads are served from a memory mapping.
+    mapped: Arc<MappedFiles>,
+    write_tracker: Arc<WriteTracker>,
 }
 
 impl IoPool {
+    /// Serve reads of the given files from memory mappings instead of the I/O workers.
+    ///
+    /// This only affects handles created afterwards.
+    pub fn map_for_reads<'a>(
+        &mut self,
+        files: impl IntoIterator<Item = &'a File>,
+    ) -> std::io::Result<()> {
+        let mut mapped = MappedFiles::clone(&self.mapped);
+        for file in files {
+            let mapped_file = MappedFile::new(file.try_clone()?)?;
+            mapped.insert(file.as_raw_fd(), Arc::new(mapped_file));
+        }
+        self.mapped = Arc::new(mapped);
+        Ok(())
+    }
+
+    /// Get the mapping serving reads of the given file, if any.
+    pub fn mapped_file(&self, file: &File) -> Option<Arc<MappedFile>> {
+        self.mapped.get(&file.as_raw_fd()).cloned()
+    }
+
     /// Create a new I/O handle.
     ///
     /// This will panic if the I/O pool has been shut down.
     pub fn make_handle(&self) -> IoHandle {
+        self.make_handle_inner(false, false)
+    }
+
+    /// Create a new I/O handle which wakes the task registered with
+    /// [`IoHandle::register_waker`] upon every completion.
+    ///
+    /// This will panic if the I/O pool has been shut down.
+    pub fn make_waking_handle(&self) -> IoHandle {
+        self.make_handle_inner(false, true)
+    }
+
+    /// Create a new I/O handle for background writes, which are not needed for a commit to be
+    /// durable. Commands sent on it are paced by the write throttle and do not count towards the
+    /// observed latency.
+    ///
+    /// This will panic if the I/O pool has been shut down.
+    pub fn make_background_handle(&self) -> IoHandle {
+        self.make_handle_inner(true, false)
+    }
+
+    fn make_handle_inner(&self, background: bool, wakes: bool) -> IoHandle {
         let (completion_sender, completion_receiver) = crossbeam_channel::unbounded();
         let sender = self
             .sender
@@ -168,15 +308,40 @@ impl IoPool {
         let sender = Arc::downgrade(sender);
         IoHandle {
             sender,
-            completion_sender,
+            completion_sender: CompletionSender::new(completion_sender, wakes),
             completion_receiver,
+            throttle: self.throttle.clone(),
+            background,
+            mapped: self.mapped.clone(),
+            write_tracker: self.write_tracker.clone(),
         }
     }
 
+    /// Start recording the pages written to the given files through any handle of this pool.
+    /// Returns `None` if writes are already being tracked.
+    pub fn track_writes(&self, fds: &[RawFd]) -> Option<TrackedWrites> {
+        self.write_tracker.track(fds)
+    }
+
+    /// The write throttle shared by all handles of this pool.
+    pub fn write_throttle(&self) -> &WriteThrottle {
+        &self.throttle
+    }
+
     pub fn page_pool(&self) -> &PagePool {
         &self.page_pool
     }
 
+    /// The chaos mode of the database this pool belongs to.
+    pub fn chaos(&self) -> &Chaos {
+        &self.chaos
+    }
+
+    /// The metrics of the database this pool belongs to.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
     /// Initiate the shutdown procedure.
     ///
     /// This will return only after all the I/O workers are shut down.
@@ -201,21 +366,41 @@ impl IoPool {
 #[derive(Clone)]
 pub struct IoHandle {
     sender: Weak<Sender<IoPacket>>,
-    completion_sender: Sender<CompleteIo>,
+    completion_sender: CompletionSender,
     completion_receiver: Receiver<CompleteIo>,
+    throttle: Arc<WriteThrottle>,
+    background: bool,
+    mapped: Arc<MappedFiles>,
+    write_tracker: Arc<WriteTracker>,
 }
 
 impl IoHandle {
-    /// Send an I/O command. This fails if the channel has hung up, but does not block the thread.
-    pub fn send(&self, command: IoCommand) -> Result<(), SendError<IoCommand>> {
+    /// Send an I/O command. This fails if the channel has hung up, but does not block the thread,
+    /// unless this is a background handle and the write throttle is engaged.
+    pub fn send(&self, mut command: IoCommand) -> Result<(), SendError<IoCommand>> {
+        // Reads of mapped files are completed right away.
+        if let IoKind::Read(fd, pn, ref mut page) = command.kind {
+            if let Some(file) = self.mapped.get(&fd) {
+                let result = file.read_page(pn, page);
+                let _ = self.completion_sender.send(CompleteIo { command, result });
+                return Ok(());
+            }
+        }
+
         let sender = match self.sender.upgrade() {
             Some(sender) => sender,
             None => return Err(SendError(command)),
         };
+        self.write_tracker.record(&command.kind);
+        if self.background {
+            self.throttle.pace();
+        }
+        let sent_at = (!self.background && self.throttle.is_enabled()).then(Instant::now);
         sender
             .send(IoPacket {
                 command,
                 completion_sender: self.completion_sender.clone(),
+                sent_at,
             })
             .map_err(|SendError(packet)| SendError(packet.command))
     }
@@ -236,6 +421,23 @@ impl IoHandle {
         &self.completion_receiver
     }
 
+    /// Have the next completion wake the given waker, replacing any waker registered before.
+    ///
+    /// Register before checking for completions with [`Self::try_recv`], so that none is missed.
+    /// This panics unless the handle was made with [`IoPool::make_waking_handle`].
+    pub fn register_waker(&self, waker: &Waker) {
+        let slot = self
+            .completion_sender
+            .waker
+            .as_ref()
+            .expect("register_waker on a handle which does not wake");
+        let mut slot = slot.lock();
+        match *slot {
+            Some(ref registered) if registered.will_wake(waker) => (),
+            _ => *slot = Some(waker.clone()),
+        }
+    }
+
     /// Creates a new handle that can be used to submit I/O commands.
     ///
     /// Unlike [`Self::clone`] this creates a new handle that can be used independently of the
@@ -244,17 +446,22 @@ impl IoHandle {
     /// This will panic if the I/O pool has been shut down.
     pub fn make_new_sibiling_handle(&self) -> IoHandle {
         let (completion_sender, completion_receiver) = crossbeam_channel::unbounded();
+        let wakes = self.completion_sender.waker.is_some();
         IoHandle {
             sender: self.sender.clone(),
-            completion_sender,
+            completion_sender: CompletionSender::new(completion_sender, wakes),
             completion_receiver,
+            throttle: self.throttle.clone(),
+            background: self.background,
+            mapped: self.mapped.clone(),
+            write_tracker: self.write_tracker.clone(),
         }
     }
 }
 
 /// Read a page from the file at the given page number.
 pub fn read_page(page_pool: &PagePool, fd: &File, pn: u64) -> std::io::Result<FatPage> {
-    use std::os::unix::fs::FileExt as _;
+    use crate::sys::FileExt as _;
     let mut page = page_pool.alloc_fat_page();
     fd.read_exact_at(&mut page[..], pn * PAGE_SIZE as u64)?;
     Ok(page)
@@ -68,11 +68,13 @@ pub struct FatPage {
 
 impl FatPage {
     /// See [`Page::as_ptr`].
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
     pub fn as_ptr(&self) -> *const u8 {
         self.page.as_ptr()
     }
 
     /// See [`Page::as_mut_ptr`].
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
     pub fn as_mut_ptr(&self) -> *mut u8 {
         self.page.as_mut_ptr()
     }
@@ -0,0 +1,91 @@
+//! A page pool without unsafe code, used with the `safe-page-pool` feature and on Windows.
+//!
+//! Every page is a separate, reference-counted heap allocation aligned to the page size, and is
+//! freed as soon as the last reference is dropped. There is no reuse of freed pages. [`Page`]s
+//! share the allocation of the [`FatPage`] they were taken from, and writing to a [`FatPage`]
+//! whose contents are shared copies them first.
+
+use super::PAGE_SIZE;
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+#[derive(Clone)]
+#[repr(C, align(4096))]
+struct PageBuf([u8; PAGE_SIZE]);
+
+/// A page reference to the pool.
+#[derive(Clone)]
+pub struct Page(Arc<PageBuf>);
+
+impl Page {
+    /// Returns a pointer to the page.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.0 .0.as_ptr()
+    }
+}
+
+/// An owned page.
+pub struct FatPage {
+    buf: Arc<PageBuf>,
+}
+
+impl FatPage {
+    /// Returns a pointer to the page.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub fn as_ptr(&self) -> *const u8 {
+        self.buf.0.as_ptr()
+    }
+
+    /// Returns a mutable pointer to the page.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        Arc::make_mut(&mut self.buf).0.as_mut_ptr()
+    }
+
+    /// Returns a [`Page`] sharing the contents of this page as of now.
+    pub fn page(&self) -> Page {
+        Page(self.buf.clone())
+    }
+}
+
+impl Clone for FatPage {
+    fn clone(&self) -> Self {
+        FatPage {
+            buf: Arc::new((*self.buf).clone()),
+        }
+    }
+}
+
+impl Deref for FatPage {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf.0
+    }
+}
+
+impl DerefMut for FatPage {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut Arc::make_mut(&mut self.buf).0
+    }
+}
+
+/// [`PagePool`] allocates pages used in IO operations.
+#[derive(Clone, Default)]
+pub struct PagePool;
+
+impl PagePool {
+    /// Creates a new page pool.
+    pub fn new() -> Self {
+        PagePool
+    }
+
+    /// Allocates a new [`FatPage`]. The contents are zeroed.
+    pub fn alloc_fat_page(&self) -> FatPage {
+        FatPage {
+            buf: Arc::new(PageBuf([0; PAGE_SIZE])),
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! The portable I/O backend: a pool of threads issuing blocking positional reads and writes.
+//!
+//! Used on every platform but Linux with the `io-uring` feature.
+
+use super::{
+    Chaos, CompleteIo, IoCommand, IoKind, IoKindResult, IoPacket, Metrics, PagePool, WriteThrottle,
+    PAGE_SIZE,
+};
+use crossbeam_channel::{Receiver, Sender};
+use std::sync::Arc;
+use threadpool::ThreadPool;
+
+pub fn check_iou_permissions() -> super::IoUringPermission {
+    super::IoUringPermission::NotSupported
+}
+
+pub fn start_io_worker(
+    page_pool: PagePool,
+    io_workers_tp: &ThreadPool,
+    io_workers: usize,
+    throttle: Arc<WriteThrottle>,
+    chaos: Chaos,
+    _metrics: Metrics,
+) -> Sender<IoPacket> {
+    let (command_tx, command_rx) = crossbeam_channel::unbounded();
+
+    for _ in 0..io_workers {
+        spawn_worker_thread(
+            page_pool.clone(),
+            io_workers_tp,
+            command_rx.clone(),
+            throttle.clone(),
+            chaos.clone(),
+        );
+    }
+
+    command_tx
+}
+
+fn spawn_worker_thread(
+    page_pool: PagePool,
+    io_workers_tp: &ThreadPool,
+    command_rx: Receiver<IoPacket>,
+    throttle: Arc<WriteThrottle>,
+    chaos: Chaos,
+) {
+    let work = move || loop {
+        let Ok(packet) = command_rx.recv() else {
+            // Why the `drop` here?
+            //
+            // `command_rx` receives the IoPacket's which are ultimately parameterized by buffers.
+            // Those buffers are allocated in the `page_pool`. If the `page_pool` is deallocated
+            // before this worker thread is done, that's a use-after-free.
+            //
+            // So in other words, we plumb `page_pool` all the way here and drop it here only to
+            // ensure safety.
+            drop(page_pool);
+            return;
+        };
+        let complete = execute(packet.command);
+        super::complete_io(
+            &throttle,
+            &chaos,
+            &packet.completion_sender,
+            packet.sent_at,
+            complete,
+        );
+    };
+
+    io_workers_tp.execute(work);
+}
+
+fn execute(mut command: IoCommand) -> CompleteIo {
+    let result = loop {
+        let res = match command.kind {
+            IoKind::Read(fd, page_index, ref mut page) => {
+                crate::sys::pread(fd, &mut page[..], page_index * PAGE_SIZE as u64)
+            }
+            IoKind::Write(fd, page_index, ref page) => {
+                crate::sys::pwrite(fd, &page[..], page_index * PAGE_SIZE as u64)
+            }
+            IoKind::WriteArc(fd, page_index, ref page) => {
+                crate::sys::pwrite(fd, &page[..], page_index * PAGE_SIZE as u64)
+            }
+            IoKind::WriteRaw(fd, page_index, ref page) => {
+                // SAFETY: the page is allocated in the page pool, which outlives the worker.
+                let page = unsafe { std::slice::from_raw_parts(page.as_ptr(), PAGE_SIZE) };
+                crate::sys::pwrite(fd, page, page_index * PAGE_SIZE as u64)
+            }
+        };
+        let res = match res {
+            Ok(n) => n as isize,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => break Err(e),
+        };
+        match command.kind.get_result(res) {
+            IoKindResult::Ok => break Ok(()),
+            IoKindResult::Err => break Err(std::io::Error::last_os_error()),
+            IoKindResult::Retry => (),
+        }
+    };
+
+    CompleteIo { command, result }
+}
@@ -0,0 +1,184 @@
+//! Pacing of background writes according to the observed latency of foreground I/O.
+//!
+//! Every completed foreground I/O feeds its latency, measured from submission to completion and
+//! so including the time spent queued, into an exponentially weighted moving average. Background
+//! writes consult the average before being submitted: while it exceeds the target, each write is
+//! delayed in proportion to the excess. This adapts to whatever the device can sustain instead of
+//! relying on a static rate limit.
+//!
+//! An average which has not been refreshed recently is ignored, as it no longer says anything
+//! about the device and there is no foreground I/O to protect.
+//!
+//! Background writes are never delayed while a commit with a deadline is being persisted, as it
+//! waits for them. See [`WriteThrottle::prioritize`].
+
+use std::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+/// The weight of a new sample in the moving average, as a power of two.
+const EWMA_SHIFT: u32 = 3;
+
+/// The age after which the moving average is ignored.
+const STALE_AFTER: Duration = Duration::from_millis(50);
+
+/// The longest a single background write is delayed.
+const MAX_DELAY: Duration = Duration::from_millis(5);
+
+pub struct WriteThrottle {
+    target: Option<Duration>,
+    epoch: Instant,
+    // nanoseconds
+    latency_ewma: AtomicU64,
+    // nanoseconds since `epoch`
+    last_sample: AtomicU64,
+    // nanoseconds
+    total_delay: AtomicU64,
+    // the number of live `Prioritized` guards
+    prioritized: AtomicUsize,
+}
+
+impl WriteThrottle {
+    /// Create a throttle keeping the foreground latency within `target`. With `None`, background
+    /// writes are never delayed.
+    pub fn new(target: Option<Duration>) -> Self {
+        WriteThrottle {
+            target,
+            epoch: Instant::now(),
+            latency_ewma: AtomicU64::new(0),
+            last_sample: AtomicU64::new(0),
+            total_delay: AtomicU64::new(0),
+            prioritized: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether background writes may be delayed at all.
+    pub fn is_enabled(&self) -> bool {
+        self.target.is_some()
+    }
+
+    /// Record the latency of a foreground I/O which completed at `now`.
+    pub fn record(&self, latency: Duration, now: Instant) {
+        if !self.is_enabled() {
+            return;
+        }
+        let sample = latency.as_nanos() as u64;
+        let _ = self
+            .latency_ewma
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |avg| {
+                Some(avg - (avg >> EWMA_SHIFT) + (sample >> EWMA_SHIFT))
+            });
+        self.last_sample
+            .fetch_max(self.nanos_since_epoch(now), Ordering::Relaxed);
+    }
+
+    /// The delay to apply to a background write submitted at `now`.
+    pub fn delay(&self, now: Instant) -> Duration {
+        let Some(target) = self.target else {
+            return Duration::ZERO;
+        };
+        if self.prioritized.load(Ordering::Relaxed) > 0 {
+            return Duration::ZERO;
+        }
+        let last_sample = self.last_sample.load(Ordering::Relaxed);
+        if self.nanos_since_epoch(now).saturating_sub(last_sample) > STALE_AFTER.as_nanos() as u64 {
+            return Duration::ZERO;
+        }
+        let avg = Duration::from_nanos(self.latency_ewma.load(Ordering::Relaxed));
+        avg.saturating_sub(target).min(MAX_DELAY)
+    }
+
+    /// Block the current thread for as long as a background write should be delayed.
+    pub fn pace(&self) {
+        let delay = self.delay(Instant::now());
+        if !delay.is_zero() {
+            self.total_delay
+                .fetch_add(delay.as_nanos() as u64, Ordering::Relaxed);
+            std::thread::sleep(delay);
+        }
+    }
+
+    /// Stop delaying background writes until the returned guard is dropped.
+    pub fn prioritize(&self) -> Prioritized<'_> {
+        self.prioritized.fetch_add(1, Ordering::Relaxed);
+        Prioritized(self)
+    }
+
+    /// The total time background writes have been delayed.
+    pub fn total_delay(&self) -> Duration {
+        Duration::from_nanos(self.total_delay.load(Ordering::Relaxed))
+    }
+
+    fn nanos_since_epoch(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.epoch).as_nanos() as u64
+    }
+}
+
+/// Keeps background writes from being delayed while alive. See [`WriteThrottle::prioritize`].
+pub struct Prioritized<'a>(&'a WriteThrottle);
+
+impl Drop for Prioritized<'_> {
+    fn drop(&mut self) {
+        self.0.prioritized.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WriteThrottle, MAX_DELAY, STALE_AFTER};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn disabled_never_delays() {
+        let throttle = WriteThrottle::new(None);
+        let now = Instant::now();
+        throttle.record(Duration::from_secs(1), now);
+        assert_eq!(throttle.delay(now), Duration::ZERO);
+    }
+
+    #[test]
+    fn delay_follows_latency() {
+        let target = Duration::from_micros(500);
+        let throttle = WriteThrottle::new(Some(target));
+        let now = Instant::now();
+
+        // Nothing observed yet.
+        assert_eq!(throttle.delay(now), Duration::ZERO);
+
+        for _ in 0..100 {
+            throttle.record(Duration::from_micros(100), now);
+        }
+        assert_eq!(throttle.delay(now), Duration::ZERO);
+
+        // The device saturates: the average converges towards 2ms.
+        for _ in 0..100 {
+            throttle.record(Duration::from_millis(2), now);
+        }
+        let delay = throttle.delay(now);
+        assert!(delay > Duration::from_millis(1) && delay <= Duration::from_micros(1500));
+
+        // Far beyond the target, the delay is capped.
+        for _ in 0..100 {
+            throttle.record(Duration::from_secs(1), now);
+        }
+        assert_eq!(throttle.delay(now), MAX_DELAY);
+
+        // Nothing is delayed while prioritized.
+        {
+            let _prioritized = throttle.prioritize();
+            assert_eq!(throttle.delay(now), Duration::ZERO);
+        }
+        assert_eq!(throttle.delay(now), MAX_DELAY);
+
+        // Without foreground I/O, the average goes stale and nothing is delayed.
+        let later = now + STALE_AFTER * 2;
+        assert_eq!(throttle.delay(later), Duration::ZERO);
+
+        // Latency recovers.
+        for _ in 0..200 {
+            throttle.record(Duration::from_micros(100), later);
+        }
+        assert_eq!(throttle.delay(later), Duration::ZERO);
+    }
+}
@@ -0,0 +1,83 @@
+//! Tracking the pages written to files, for copying them while they are in use.
+//!
+//! Every write sent on a handle of the pool is offered to the tracker. While nothing is tracked,
+//! this is a single relaxed load.
+
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use super::IoKind;
+use crate::sys::RawFd;
+
+/// Records the page numbers written to a set of files, shared by all handles of a pool.
+#[derive(Default)]
+pub struct WriteTracker {
+    active: AtomicBool,
+    written: Mutex<Option<HashMap<RawFd, BTreeSet<u64>>>>,
+}
+
+impl WriteTracker {
+    /// Start recording the writes to the given files. Returns `None` if writes are already
+    /// tracked.
+    ///
+    /// Writes already sent are not recorded, so this should be called while none are in flight.
+    pub fn track(self: &Arc<Self>, fds: &[RawFd]) -> Option<TrackedWrites> {
+        let mut written = self.written.lock();
+        if written.is_some() {
+            return None;
+        }
+        *written = Some(fds.iter().map(|fd| (*fd, BTreeSet::new())).collect());
+        self.active.store(true, Ordering::Relaxed);
+        Some(TrackedWrites {
+            tracker: self.clone(),
+        })
+    }
+
+    /// Record the command, if it is a write to a tracked file.
+    pub fn record(&self, kind: &IoKind) {
+        if !self.active.load(Ordering::Relaxed) {
+            return;
+        }
+        let (fd, pn) = match *kind {
+            IoKind::Read(..) => return,
+            IoKind::Write(fd, pn, _)
+            | IoKind::WriteArc(fd, pn, _)
+            | IoKind::WriteRaw(fd, pn, _) => (fd, pn),
+        };
+        if let Some(pages) = self
+            .written
+            .lock()
+            .as_mut()
+            .and_then(|written| written.get_mut(&fd))
+        {
+            pages.insert(pn);
+        }
+    }
+}
+
+/// The writes recorded since [`WriteTracker::track`]. Tracking stops when dropped.
+pub struct TrackedWrites {
+    tracker: Arc<WriteTracker>,
+}
+
+impl TrackedWrites {
+    /// Stop tracking and return the page numbers written to each file, in ascending order.
+    pub fn finish(self) -> HashMap<RawFd, BTreeSet<u64>> {
+        self.tracker.active.store(false, Ordering::Relaxed);
+        // UNWRAP: tracking is only stopped here or when dropped.
+        self.tracker.written.lock().take().unwrap()
+    }
+}
+
+impl Drop for TrackedWrites {
+    fn drop(&mut self) {
+        self.tracker.active.store(false, Ordering::Relaxed);
+        self.tracker.written.lock().take();
+    }
+}
@@ -5,30 +5,83 @@
 use bitvec::prelude::*;
 use io::PagePool;
 use metrics::{Metric, Metrics};
-use std::{mem, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashSet},
+    mem,
+    ops::{Bound, RangeBounds},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
+use access_list::AccessRecorder;
+use access_log::AccessLog;
+use chaos::Chaos;
+use deadline::CommitClock;
 use merkle::{UpdatePool, Updater};
+use namespace::{CommitStats, Namespace};
 use nomt_core::{
     hasher::{NodeHasher, ValueHasher},
     page_id::ROOT_PAGE_ID,
-    proof::PathProof,
-    trie::{InternalData, KeyPath, LeafData, Node, TERMINATOR},
+    proof::{MultiProof, PathProof, PrefixNode, PrefixProof, RangeProof},
+    trie::{InternalData, KeyPath, LeafData, Node, ValueHash, TERMINATOR},
+    trie_pos::TriePosition,
 };
-use overlay::{LiveOverlay, OverlayMarker};
+use overlay::{LiveOverlay, OverlayMarker, OverlaySpill};
 use page_cache::PageCache;
-use parking_lot::{ArcRwLockReadGuard, Mutex, RwLock};
+use parking_lot::{ArcRwLockReadGuard, Mutex, RwLock, RwLockWriteGuard};
+use read_repair::{ReadRepair, ReadRepairStats};
+use read_through::{ReadThrough, ReadThroughStats};
+use session::{
+    embed_witness_values, merge_duplicate_actuals, merge_hash_writes, LiveSessions, SessionAccess,
+};
 use store::{Store, ValueTransaction};
-
+use trace::Trace;
+
+pub use access_list::AccessList;
+pub use cancel::{CancellationToken, Cancelled};
+#[cfg(feature = "chaos")]
+pub use chaos::ChaosConfig;
+pub use deadline::CommitReport;
+pub use defrag::{DefragHandle, DefragParams, DefragProgress, DefragStatus};
+pub use error::{
+    DuplicateWrites, Error, PreconditionsViolated, SessionMisuse, StaleView, TrieDepthExceeded,
+    ValueNotStored, ViolatedPrecondition,
+};
+pub use ht_resize::{HashTableResizeHandle, HashTableResizeProgress, HashTableResizeStatus};
+pub use integrity::{IntegrityLevel, IntegrityReport};
 pub use io::IoUringPermission;
+pub use merkle::{PrepopulateHandle, PrepopulateProgress, PrepopulateStatus};
 pub use nomt_core::hasher;
 pub use nomt_core::proof;
+pub use nomt_core::quad;
 pub use nomt_core::trie;
 pub use nomt_core::witness::{
-    Witness, WitnessedOperations, WitnessedPath, WitnessedRead, WitnessedWrite,
+    ExcludedSubtree, Witness, WitnessBinding, WitnessDecodeError, WitnessEquivalenceError,
+    WitnessExclusionError, WitnessStatements, WitnessVerificationError, WitnessedOperations,
+    WitnessedPath, WitnessedRead, WitnessedValue, WitnessedWrite,
+};
+pub use options::{
+    Compression, DeepPathPolicy, DuplicateWritePolicy, Options, PanicOnSyncMode, ReadBackend,
+    RollbackCachePolicy, StorageLayout,
 };
-pub use options::{Options, PanicOnSyncMode};
 pub use overlay::{InvalidAncestors, Overlay};
-pub use store::HashTableUtilization;
+pub use range_iter::RangeIter;
+pub use read_async::ReadValue;
+pub use rollback::{RollbackCacheStats, RollbackDiskUsage, RollbackStats, RolledBackKey};
+pub use seglog::LogArchiveStats;
+pub use session::{AccessTraceMode, LocalValue, Precondition, SessionSyncMode, StorageClass};
+pub use store::{
+    DbId, HashTableUtilization, HotPrefix, Lineage, LineageOrigin, LineageRollback,
+    OptionsJournalEntry, OutOfSpace, PartialDatabase, SpaceStats, SyncPhase, SyncTimings,
+    ValueIter, WriteStats,
+};
+
+pub use trie_stats::TrieStats;
+pub use witness_stats::WitnessStats;
+pub use yielding::YieldPoint;
 
 // beatree module needs to be exposed to be benchmarked and fuzzed
 #[cfg(any(feature = "benchmarks", feature = "fuzz"))]
@@ -37,26 +90,62 @@ pub mod beatree;
 #[cfg(not(any(feature = "benchmarks", feature = "fuzz")))]
 mod beatree;
 
+pub mod access_log;
+pub mod backup;
+#[cfg(feature = "cache-debug")]
+pub mod cache_debug;
+pub mod integrity;
+pub mod light_state;
+pub mod metrics;
+pub mod migration;
+pub mod namespace;
+#[cfg(feature = "benchmarks")]
+pub mod read_bench;
+pub mod read_repair;
+pub mod read_through;
+pub mod sharding;
+pub mod state_diff;
+pub mod sync_protocol;
+pub mod system_keys;
+
+mod access_list;
 mod bitbox;
+mod cache_file;
+mod cancel;
+mod chaos;
+mod deadline;
+mod defrag;
+mod error;
+mod ht_resize;
 mod merkle;
-mod metrics;
 mod options;
 mod overlay;
 mod page_cache;
 mod page_diff;
 mod page_region;
+mod range_iter;
+mod read_async;
 mod rollback;
 mod rw_pass_cell;
 mod seglog;
+mod session;
 mod store;
 mod sys;
 mod task;
+mod trace;
+mod trie_stats;
+mod witness_stats;
+mod yielding;
 
 mod io;
 
 const MAX_COMMIT_CONCURRENCY: usize = 64;
 
 /// A full value stored within the trie.
+///
+/// Empty values are values like any other: they are stored, hashed into a leaf and proven. The
+/// absence of a value is expressed with `None` throughout the API, so `Some(vec![])` and `None`
+/// are never conflated.
 pub type Value = Vec<u8>;
 
 struct Shared {
@@ -64,9 +153,18 @@ struct Shared {
     root: Root,
     /// The marker of the last committed overlay. `None` if the last commit was not an overlay.
     last_commit_marker: Option<OverlayMarker>,
+    /// The number of rollbacks performed since the database was opened.
+    rollbacks: u64,
+    /// The effect of the last rollback on the caches.
+    last_rollback_cache: Option<RollbackCacheStats>,
+    /// The roots published by the last commit.
+    last_commit: Option<CommitStats>,
 }
 
 /// Whether a key was read, written, or both, along with old and new values.
+///
+/// A `None` value means that the key has no value, or is deleted when written. An empty value is
+/// `Some(vec![])`.
 #[derive(Debug, Clone)]
 pub enum KeyReadWrite {
     /// The key was read. Contains the read value.
@@ -136,6 +234,77 @@ impl KeyReadWrite {
     }
 }
 
+/// The reverse delta of a commit retained in the rollback log: the value hashes it overwrote,
+/// along with a proof of them against the state the commit was applied to.
+///
+/// Produced by [`Nomt::export_reverse_delta`]. This is what a challenger needs to show what a
+/// state transition overwrote, e.g. in a fraud proof.
+#[derive(Debug, Clone)]
+pub struct ProvenReverseDelta {
+    /// The root before the commit, which the priors are proven against.
+    pub pre_root: Root,
+    /// The root after the commit.
+    pub post_root: Root,
+    /// Every key written by the commit, in ascending order, along with the hash of its value
+    /// before the commit. `None` if the key had no value.
+    pub priors: Vec<(KeyPath, Option<ValueHash>)>,
+    /// A proof of all the priors against `pre_root`.
+    pub proof: MultiProof,
+}
+
+impl ProvenReverseDelta {
+    /// Verify the proof against `pre_root` and check every prior against it.
+    pub fn verify<H: NodeHasher>(&self) -> bool {
+        let Ok(verified) = proof::verify_multi_proof::<H>(&self.proof, self.pre_root.into_inner())
+        else {
+            return false;
+        };
+        self.priors.iter().all(|(key_path, prior)| {
+            let confirmed = match prior {
+                Some(value_hash) => verified.confirm_value(&LeafData {
+                    key_path: *key_path,
+                    value_hash: *value_hash,
+                }),
+                None => verified.confirm_nonexistence(key_path),
+            };
+            matches!(confirmed, Ok(true))
+        })
+    }
+}
+
+/// The values within a range of keys, along with a proof that they are all of them.
+///
+/// Produced by [`Nomt::prove_range`].
+#[derive(Debug, Clone)]
+pub struct ProvenRange {
+    /// The root the range is proven against.
+    pub root: Root,
+    /// The exclusive end of the proven range. This is where the next chunk of a dump begins.
+    /// `None` if the range reaches the end of the key space.
+    pub end: Option<KeyPath>,
+    /// All key-value pairs in the range, in ascending key order. Values of which only the hash is
+    /// kept are given by their hash.
+    pub values: Vec<(KeyPath, LocalValue)>,
+    /// The proof of the range. Verify it with [`proof::verify_range`] and check the values against
+    /// the value hashes of the returned leaves, e.g. with [`LocalValue::value_hash`].
+    pub proof: RangeProof,
+}
+
+/// The values of all keys beginning with a prefix, along with a proof of the subtree holding them.
+///
+/// Produced by [`Nomt::prove_prefix`].
+#[derive(Debug, Clone)]
+pub struct ProvenPrefix {
+    /// The root the prefix is proven against.
+    pub root: Root,
+    /// All key-value pairs with the prefix, in ascending key order. `None` if there were too many
+    /// to list. Values of which only the hash is kept are given by their hash.
+    pub values: Option<Vec<(KeyPath, LocalValue)>>,
+    /// The proof of the subtree. Verify it with [`proof::verify_prefix`] and check the values
+    /// against the value hashes of the returned leaves.
+    pub proof: PrefixProof,
+}
+
 /// The root of the Merkle Trie.
 #[derive(Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(
@@ -204,20 +373,86 @@ pub struct Nomt<T> {
     shared: Arc<Mutex<Shared>>,
     /// Used to protect the multiple-readers-one-writer API
     access_lock: Arc<RwLock<()>>,
+    /// The number of live sessions which began ahead of any pending commit.
+    anchored_sessions: Arc<AtomicUsize>,
+    /// The live sessions holding the access lock, by the thread which began them.
+    live_sessions: Arc<LiveSessions>,
     metrics: Metrics,
+    read_through: Option<Arc<ReadThrough>>,
+    read_repair: Option<Arc<ReadRepair>>,
+    access_log: Option<Arc<AccessLog>>,
+    overlay_spill: Option<Arc<OverlaySpill>>,
+    max_trie_depth: Option<(usize, DeepPathPolicy)>,
+    duplicate_write_policy: DuplicateWritePolicy,
+    reserve_system_keyspace: bool,
+    namespace_bits: Option<usize>,
+    rollback_cache_policy: RollbackCachePolicy,
+    page_cache_upper_levels: usize,
+    prepopulate_rate: Option<u32>,
+    /// The database directory and the size of the cache file, if enabled.
+    cache_file: Option<(std::path::PathBuf, usize)>,
+    /// The most recently started background prepopulation of the page cache.
+    prepopulation: Mutex<Option<PrepopulateHandle>>,
+    /// The automatic resizes of the hash table, if enabled.
+    hashtable_auto_resize: Option<Mutex<ht_resize::AutoResize>>,
+    /// The most recently started resize of the hash table.
+    resize: Mutex<Option<HashTableResizeHandle>>,
+    /// The most recently started defragmentation of the beatree.
+    defrag: Mutex<Option<DefragHandle>>,
+    /// Set while frozen by [`Nomt::freeze`].
+    frozen: Mutex<Option<store::Frozen>>,
     _marker: std::marker::PhantomData<T>,
 }
 
 impl<T: HashAlgorithm> Nomt<T> {
+    /// Restore the backup at `backup`, made with [`Nomt::create_backup`], into the directory of
+    /// the options, which must not exist or be empty, and open it.
+    ///
+    /// The files are placed according to [`Options::layout`]. The restored database keeps
+    /// the identity of the one the backup was taken from. See the [`backup`] module.
+    pub fn open_backup(backup: impl AsRef<std::path::Path>, o: Options) -> Result<Self, Error> {
+        store::restore_backup(backup.as_ref(), &o)?;
+        Self::open(o)
+    }
+
+    /// Open an existing database for reading only, alongside the process which may have it open
+    /// for writing, such as an indexer running next to a validator on the same files.
+    ///
+    /// The directory is not locked and the files are opened read-only. Nothing is recovered or
+    /// recorded on opening: the database is served as of the last sync completed by the writer,
+    /// and opening fails if the writer is updating the hash table at that moment. Commits,
+    /// rollbacks and all other writes fail. Rollback and the cache file are disabled, whatever the
+    /// options say.
+    ///
+    /// The view is not kept up to date with later syncs of the writer, which updates the hash
+    /// table in place and reuses the space freed by earlier syncs. Reads of values and proofs
+    /// check the sync sequence number of the writer before and after reading, and fail with
+    /// [`StaleView`] once the writer has completed a sync past the view. Reopen the database to
+    /// catch up, for example once per block.
+    pub fn open_read_only(mut o: Options) -> Result<Self, Error> {
+        o.read_only = true;
+        o.rollback = false;
+        o.cache_file_size = 0;
+        Self::open(o)
+    }
+
     /// Open the database with the given options.
     ///
     /// It is recommended to check io_uring permissions before calling this function by calling
     /// [`check_iou_permissions`]
-    pub fn open(mut o: Options) -> anyhow::Result<Self> {
+    pub fn open(o: Options) -> Result<Self, Error> {
+        Ok(Self::open_inner(o)?)
+    }
+
+    fn open_inner(mut o: Options) -> anyhow::Result<Self> {
         if o.commit_concurrency == 0 {
             anyhow::bail!("commit concurrency must be greater than zero".to_string());
         }
 
+        if o.hashtable_auto_resize.is_some() && o.read_backend == ReadBackend::Mmap {
+            anyhow::bail!("the hash table can't be resized automatically with mmap reads");
+        }
+
         if o.commit_concurrency > MAX_COMMIT_CONCURRENCY {
             o.commit_concurrency = MAX_COMMIT_CONCURRENCY;
         }
@@ -225,27 +460,90 @@ impl<T: HashAlgorithm> Nomt<T> {
         let metrics = Metrics::new(o.metrics);
 
         let page_pool = PagePool::new();
-        let store = Store::open(&o, page_pool.clone())?;
+        let chaos = Chaos::new(&o, metrics.clone());
+        let store = Store::open(&o, page_pool.clone(), chaos.clone(), metrics.clone())?;
         let root_page = store.load_page(ROOT_PAGE_ID)?;
-        let page_cache = PageCache::new(root_page, &o, metrics.clone());
+        let page_cache = PageCache::new(root_page, &o, metrics.clone(), chaos);
         let root = compute_root_node::<T>(&page_cache, &store);
-
-        if o.prepopulate_page_cache {
-            let io_handle = store.io_pool().make_handle();
-            merkle::prepopulate_cache(io_handle, &page_cache, &store, o.page_cache_upper_levels)?;
+        // Loading the cache file consumes it, which is left to the writer.
+        if !o.read_only {
+            cache_file::load(
+                &o.path,
+                store.sync_seqn(),
+                root,
+                &page_pool,
+                &page_cache,
+                &store,
+            )?;
         }
 
+        let access_log = o
+            .access_log
+            .as_ref()
+            .map(|(path, sample_rate)| AccessLog::create(path, *sample_rate).map(Arc::new))
+            .transpose()?;
+
+        let access_lock = Arc::new(RwLock::new(()));
+        let prepopulation = o.prepopulate_page_cache.then(|| {
+            merkle::spawn_prepopulate_cache(
+                page_cache.clone(),
+                store.clone(),
+                access_lock.clone(),
+                o.page_cache_upper_levels,
+                o.prepopulate_page_cache_rate,
+            )
+        });
+
+        let read_through = o
+            .read_through
+            .take()
+            .filter(|config| config.target_root != Root(root))
+            .map(|config| Arc::new(ReadThrough::new::<T>(config)));
+
         Ok(Self {
-            merkle_update_pool: UpdatePool::new(o.commit_concurrency, o.warm_up),
+            merkle_update_pool: UpdatePool::new(
+                o.commit_concurrency,
+                o.warm_up,
+                o.yield_hook.take(),
+            ),
             page_cache,
             page_pool,
             store,
             shared: Arc::new(Mutex::new(Shared {
                 root: Root(root),
                 last_commit_marker: None,
+                rollbacks: 0,
+                last_rollback_cache: None,
+                last_commit: None,
             })),
-            access_lock: Arc::new(RwLock::new(())),
+            access_lock,
+            anchored_sessions: Arc::new(AtomicUsize::new(0)),
+            live_sessions: Arc::new(LiveSessions::default()),
             metrics,
+            read_through,
+            read_repair: o
+                .read_repair
+                .take()
+                .map(|config| Arc::new(ReadRepair::new::<T>(config))),
+            access_log,
+            overlay_spill: o
+                .overlay_memory_budget
+                .map(|budget| Arc::new(OverlaySpill::new(budget, o.path.join("overlay_spill")))),
+            max_trie_depth: o.max_trie_depth,
+            duplicate_write_policy: o.duplicate_write_policy,
+            reserve_system_keyspace: o.reserve_system_keyspace,
+            namespace_bits: o.namespace_bits,
+            rollback_cache_policy: o.rollback_cache_policy,
+            page_cache_upper_levels: o.page_cache_upper_levels,
+            prepopulate_rate: o.prepopulate_page_cache_rate,
+            cache_file: (o.cache_file_size > 0).then(|| (o.path.clone(), o.cache_file_size)),
+            prepopulation: Mutex::new(prepopulation),
+            hashtable_auto_resize: o
+                .hashtable_auto_resize
+                .map(|load_factor| Mutex::new(ht_resize::AutoResize::new(load_factor))),
+            resize: Mutex::new(None),
+            defrag: Mutex::new(None),
+            frozen: Mutex::new(None),
             _marker: std::marker::PhantomData,
         })
     }
@@ -268,7 +566,279 @@ impl<T: HashAlgorithm> Nomt<T> {
     #[doc(hidden)]
     pub fn read(&self, path: KeyPath) -> anyhow::Result<Option<Value>> {
         let _guard = self.access_lock.read();
-        self.store.load_value(path)
+        self.store.read_view(|| self.store.load_value(path))
+    }
+
+    /// Get the value stored under the given key as of the current root.
+    ///
+    /// This reads like [`Session::read`] on a fresh session, but without setting up the trie
+    /// update machinery a session carries, which makes it suitable for serving point lookups.
+    /// Like a session, it blocks syncs from starting until it returns.
+    ///
+    /// Returns `None` if there is no value under the key. Fails with [`ValueNotStored`] if only
+    /// the hash of the value is kept, with [`StaleView`] if the database was opened read-only and
+    /// the writer has moved on, and otherwise only if I/O fails, or under the same conditions as
+    /// [`Session::read`] if read-through or read repair is configured.
+    pub fn get(&self, path: KeyPath) -> anyhow::Result<Option<Value>> {
+        self.get_many([path])
+            .map(|mut values| values.pop().flatten())
+    }
+
+    /// Get the values stored under the given keys as of the current root, in the order of the
+    /// keys.
+    ///
+    /// All the values are read from the same state. See [`Nomt::get`].
+    pub fn get_many(
+        &self,
+        paths: impl IntoIterator<Item = KeyPath>,
+    ) -> anyhow::Result<Vec<Option<Value>>> {
+        let read_through_active = self.read_through.as_ref().is_some_and(|r| r.is_active());
+        if self.read_repair.is_some() || read_through_active {
+            // These need the proving and root tracking of a session.
+            let session = self.begin_session(SessionParams::default());
+            return paths.into_iter().map(|path| session.read(path)).collect();
+        }
+
+        let _guard = self.access_lock.read();
+        self.store.read_view(|| {
+            paths
+                .into_iter()
+                .map(|path| {
+                    if let Some(access_log) = self.access_log.as_ref().filter(|log| log.sample()) {
+                        access_log.record(None, access_log::Event::Read(path));
+                    }
+                    let _maybe_guard = self.metrics.record(Metric::ValueFetchTime);
+                    let value = self.store.load_value(path)?;
+                    if value.as_ref().is_some_and(|v| v.is_empty()) {
+                        if let Some(value_hash) = self.store.load_value_hash_only(path)? {
+                            return Err(ValueNotStored {
+                                key: path,
+                                value_hash,
+                            }
+                            .into());
+                        }
+                    }
+                    Ok(value)
+                })
+                .collect()
+        })
+    }
+
+    /// Read the value stored under the given key in the system keyspace.
+    ///
+    /// Returns `None` if there is no value, which is always the case unless the system keyspace
+    /// is reserved (see [`Options::reserve_system_keyspace`]).
+    pub fn read_system(&self, key: system_keys::SystemKey) -> anyhow::Result<Option<Value>> {
+        self.read(key.key_path())
+    }
+
+    /// Iterate over the values stored under the keys in the half-open range `[start, end)`.
+    ///
+    /// Keys are yielded in ascending order. This is the order of the leaves in the trie from left
+    /// to right, so that each value can be matched with a proof of its position.
+    ///
+    /// The iterator reflects the state of the database as of its creation and blocks syncs from
+    /// starting until dropped. Values of which only the hash is kept are yielded as
+    /// [`ValueNotStored`] errors; see [`ValueIter::next_local`].
+    pub fn iter_values(&self, start: KeyPath, end: Option<KeyPath>) -> ValueIter {
+        self.store.iter_values(start, end)
+    }
+
+    /// Read the values under the keys in the half-open range `[start, end)`, along with a proof
+    /// against the current root that no other keys in the range have values.
+    ///
+    /// At most `limit` values are returned. If the range holds more, the proven range ends before
+    /// the next key and [`ProvenRange::end`] is that key. A whole database can be dumped
+    /// verifiably by starting each chunk at the end of the previous one.
+    ///
+    /// This blocks commits from starting until it returns.
+    pub fn prove_range(
+        &self,
+        start: KeyPath,
+        end: Option<KeyPath>,
+        limit: usize,
+    ) -> anyhow::Result<ProvenRange> {
+        // The session keeps commits out, so the values and the proofs are of the same root.
+        let session = self.begin_session(SessionParams::default());
+
+        let mut values = Vec::new();
+        let mut upper = None;
+        let mut stored = self.store.iter_values(start, None);
+        while let Some(item) = stored.next_local() {
+            let (key, value) = item?;
+            if values.len() == limit || end.is_some_and(|end| key >= end) {
+                upper = Some(key);
+                break;
+            }
+            values.push((key, value));
+        }
+
+        // The range is cut short if the limit was reached before its end.
+        let end = match (upper, end) {
+            (Some(upper), Some(end)) if upper < end => Some(upper),
+            (Some(upper), None) => Some(upper),
+            (_, end) => end,
+        };
+
+        let proof = RangeProof {
+            start: session.prove(start)?,
+            leaves: values
+                .iter()
+                .map(|(key, _)| session.prove(*key))
+                .collect::<anyhow::Result<_>>()?,
+            upper: upper.map(|key| session.prove(key)).transpose()?,
+        };
+
+        Ok(ProvenRange {
+            root: session.prev_root(),
+            end,
+            values,
+            proof,
+        })
+    }
+
+    /// Build the experimental 4-ary trie of all the values in the database.
+    ///
+    /// The database commits to the binary trie only. This builds the 4-ary trie over the same
+    /// values in memory, for comparing roots, proof sizes and hashing costs; see [`quad`].
+    /// Witnesses of it are created with [`quad::QuadTrie::witness`].
+    ///
+    /// This reads every value and blocks syncs from starting until it returns.
+    pub fn experimental_quad_trie(&self) -> anyhow::Result<quad::QuadTrie> {
+        let mut leaves = Vec::new();
+        let mut stored = self.store.iter_values([0; 32], None);
+        while let Some(item) = stored.next_local() {
+            let (key_path, value) = item?;
+            leaves.push(trie::LeafData {
+                key_path,
+                value_hash: value.value_hash::<T>(),
+            });
+        }
+        Ok(quad::QuadTrie::build(leaves))
+    }
+
+    /// Prove the subtree of all keys beginning with `prefix` against the current root, listing
+    /// their values if there are at most `max_leaves` of them.
+    ///
+    /// The proof holds the root of the subtree and the siblings along the path to it, so the
+    /// subtree can be proven without the values when there are too many to list. To prove the
+    /// subtree against the root of a session, use [`Session::prove_prefix`].
+    ///
+    /// This blocks commits from starting until it returns.
+    pub fn prove_prefix(
+        &self,
+        prefix: &BitSlice<u8, Msb0>,
+        max_leaves: usize,
+    ) -> anyhow::Result<ProvenPrefix> {
+        // The session keeps commits out, so the values and the proof are of the same root.
+        self.begin_session(SessionParams::default())
+            .prove_prefix(prefix, max_leaves)
+    }
+
+    /// Get the root of a namespace as of the current root. See the [`namespace`] module.
+    ///
+    /// This blocks commits from starting until it returns.
+    pub fn namespace_root(&self, namespace: Namespace) -> anyhow::Result<Node> {
+        self.begin_session(SessionParams::default())
+            .namespace_root(namespace)
+    }
+
+    /// Read the values of the given keys, along with a single proof of all of them against the
+    /// current root, as a [light state](light_state).
+    ///
+    /// Keys without a value are included with a proof of their absence. Duplicate keys are
+    /// included once. [`Nomt::hot_keys`] is a good source of keys for bootstrapping light clients.
+    ///
+    /// This blocks commits from starting until it returns.
+    pub fn export_light_state(
+        &self,
+        keys: impl IntoIterator<Item = KeyPath>,
+    ) -> anyhow::Result<light_state::LightState> {
+        // The session keeps commits out, so the values and the proof are of the same root.
+        let session = self.begin_session(SessionParams::default());
+
+        let mut keys = keys.into_iter().collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys.dedup();
+        let proof = session.prove_multi(&keys)?;
+        let entries = keys
+            .into_iter()
+            .map(|key| Ok((key, session.read(key)?)))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(light_state::LightState {
+            root: session.prev_root(),
+            entries,
+            proof,
+        })
+    }
+
+    /// Get at most `limit` recently read or written keys, hottest first.
+    ///
+    /// These are the keys of the leaves held in the leaf cache, those of the most recently used
+    /// leaves first. Keys sharing a leaf with a hot key are included along with it.
+    pub fn hot_keys(&self, limit: usize) -> Vec<KeyPath> {
+        self.store.hottest_keys(limit)
+    }
+
+    /// Apply a [state diff](state_diff) in a single commit, verifying that it leads to
+    /// `expected_root`.
+    ///
+    /// `expected_root` should come from a source trusted independently of the diff. The diff is
+    /// rejected without changing the database if the database is not at the root the diff applies
+    /// to, if the diff is corrupted, or if applying it does not lead to `expected_root`.
+    ///
+    /// The sync sequence numbers of the diff are informational: the database advances by a single
+    /// commit, no matter how many commits the diff spans.
+    ///
+    /// If `cancel` is cancelled before the commit begins, this fails with [`Cancelled`] and the
+    /// database is left unchanged.
+    pub fn apply_diff<'a, R: std::io::Read>(
+        &self,
+        diff: state_diff::StateDiffReader<R>,
+        expected_root: Root,
+        cancel: impl Into<Option<&'a CancellationToken>>,
+    ) -> anyhow::Result<()> {
+        let cancel = cancel.into();
+        let header = *diff.header();
+        if header.to_root != expected_root {
+            anyhow::bail!(
+                "apply_diff: the diff leads to {} but {} is expected",
+                header.to_root,
+                expected_root,
+            );
+        }
+
+        let session = self.begin_session(SessionParams::default());
+        if session.prev_root() != header.from_root {
+            anyhow::bail!(
+                "apply_diff: the database is at {} but the diff applies to {}",
+                session.prev_root(),
+                header.from_root,
+            );
+        }
+
+        // The whole diff is read before committing, so a corrupted diff is rejected.
+        let mut actuals = Vec::new();
+        for (i, change) in diff.enumerate() {
+            if i % 1024 == 0 {
+                cancel::check(cancel)?;
+            }
+            let (key, value) = change?;
+            session.warm_up(key);
+            actuals.push((key, KeyReadWrite::Write(value)));
+        }
+
+        let finished = session.finish(actuals)?;
+        if finished.root() != expected_root {
+            anyhow::bail!(
+                "apply_diff: applying the diff leads to {} but {} is expected",
+                finished.root(),
+                expected_root,
+            );
+        }
+        cancel::check(cancel)?;
+        finished.commit(self).map_err(Error::into_anyhow)
     }
 
     /// Returns the current sync sequence number.
@@ -279,7 +849,8 @@ impl<T: HashAlgorithm> Nomt<T> {
 
     /// Whether the database is poisoned.
     ///
-    /// A database becomes poisoned when an error occurred during a commit operation.
+    /// A database becomes poisoned when an error occurred during a commit operation, other than
+    /// running out of disk space in a phase which [`Nomt::resume_sync`] can resume.
     ///
     /// From this point on, the database is in an inconsistent state and should be considered
     /// read-only. Any further modifying operations will return an error.
@@ -290,10 +861,75 @@ impl<T: HashAlgorithm> Nomt<T> {
         self.store.is_poisoned()
     }
 
+    /// The phase of persisting a commit which ran out of disk space, if a commit is waiting for
+    /// [`Nomt::resume_sync`].
+    pub fn stalled_sync(&self) -> Option<SyncPhase> {
+        self.store.stalled_sync()
+    }
+
+    /// Complete the commit which failed with [`OutOfSpace`], once disk space has been freed.
+    ///
+    /// The sync starts over from the phase which ran out of space, keeping the work done before
+    /// it. Until then, the database serves reads including that commit, but refuses further
+    /// commits. Running out of space in other phases, such as writing the meta or the rollback
+    /// log, poisons the database as any other error does.
+    ///
+    /// Returns `false` if no commit is waiting. Fails with [`OutOfSpace`] again if there is still
+    /// not enough space. This function will block until all ongoing sessions and commits have
+    /// finished.
+    pub fn resume_sync(&self) -> Result<bool, Error> {
+        let _gate = self.store.write_gate();
+        let _write_guard = self.write_access()?;
+        Ok(self.store.resume_sync()?)
+    }
+
+    // Take the access lock for writing, waiting for all sessions to end. Fails rather than
+    // waiting forever if the calling thread began a session which is still live.
+    fn write_access(&self) -> Result<RwLockWriteGuard<'_, ()>, SessionMisuse> {
+        if let Some(guard) = self.access_lock.try_write() {
+            return Ok(guard);
+        }
+        if self.live_sessions.on_current_thread() {
+            return Err(SessionMisuse::LiveSessionOnThread);
+        }
+        Ok(self.access_lock.write())
+    }
+
+    // Take the access lock for a session. Returns whether the session began ahead of any pending
+    // commit.
+    fn session_access_lock(
+        &self,
+        sync_mode: SessionSyncMode,
+    ) -> (ArcRwLockReadGuard<parking_lot::RawRwLock, ()>, bool) {
+        if let Some(guard) = RwLock::try_read_arc(&self.access_lock) {
+            return (guard, true);
+        }
+
+        // A commit is pending or writing. A recursive read lock doesn't queue behind a commit
+        // waiting for the current readers, but only the sessions which began ahead of the commit
+        // may let a PreSync session through. Otherwise, PreSync sessions overlapping each other
+        // would hold the commit off forever.
+        if sync_mode == SessionSyncMode::PreSync
+            && self.anchored_sessions.load(Ordering::Acquire) > 0
+        {
+            return (RwLock::read_arc_recursive(&self.access_lock), false);
+        }
+        (RwLock::read_arc(&self.access_lock), true)
+    }
+
+    /// Whether a commit or rollback is writing, or waiting for the live sessions to end.
+    ///
+    /// This is used for testing for now.
+    #[doc(hidden)]
+    pub fn is_commit_pending(&self) -> bool {
+        self.access_lock.is_locked_exclusive()
+    }
+
     /// Create a new [`Session`] object with the given parameters.
     ///
     /// This will block if there are any ongoing commits or rollbacks. Multiple sessions may
-    /// coexist.
+    /// coexist. Whether the session waits for commits and rollbacks which are waiting for other
+    /// sessions is decided by [`SessionParams::sync_mode`].
     ///
     /// The [`Session`] is a read-only handle on the database and is used to create a changeset to
     /// be applied to the database. Sessions provide read interfaces and additionally coordinate
@@ -309,9 +945,18 @@ impl<T: HashAlgorithm> Nomt<T> {
         // We must take the access guard before instantiating the rollback delta,
         // because it creates a read transaction and any commits or rollbacks will block
         // indefinitely for us to finish.
-        let access_guard = params
-            .take_global_guard
-            .then(|| RwLock::read_arc(&self.access_lock));
+        //
+        let access_guard = params.take_global_guard.then(|| {
+            let (guard, anchored) = self.session_access_lock(params.sync_mode);
+            if anchored {
+                self.anchored_sessions.fetch_add(1, Ordering::AcqRel);
+            }
+            SessionAccess {
+                _guard: guard,
+                _registration: self.live_sessions.register(),
+                anchored: anchored.then(|| self.anchored_sessions.clone()),
+            }
+        });
 
         let store = self.store.clone();
         let rollback_delta = if params.record_rollback_delta {
@@ -325,6 +970,18 @@ impl<T: HashAlgorithm> Nomt<T> {
         let prev_root = live_overlay
             .parent_root()
             .unwrap_or_else(|| self.root().into_inner());
+        // Pages of the overlay carry the bucket indices of the hash table it was built against.
+        let ht_generation = live_overlay
+            .ht_generation()
+            .unwrap_or_else(|| self.store.ht_generation());
+
+        let read_through = self.read_through.clone().filter(|read_through| {
+            if read_through.is_active() && read_through.target_root() == Root(prev_root) {
+                // The local database has caught up with the target.
+                read_through.deactivate();
+            }
+            read_through.is_active()
+        });
 
         Session {
             store,
@@ -339,31 +996,80 @@ impl<T: HashAlgorithm> Nomt<T> {
             rollback_delta,
             overlay: live_overlay,
             witness_mode: params.witness,
+            access_recorder: params
+                .access_trace
+                .keys
+                .then(|| AccessRecorder::new(params.access_trace.pages)),
             access_guard,
+            // The sessions of rollbacks are not the user's.
+            access_log: self
+                .access_log
+                .clone()
+                .filter(|_| params.take_global_guard)
+                .and_then(|log| log.begin_session().map(|id| (log, id))),
+            overlay_spill: self.overlay_spill.clone(),
             prev_root: Root(prev_root),
+            rollback_epoch: self.shared.lock().rollbacks,
+            ht_generation,
+            read_through,
+            read_through_used: AtomicBool::new(false),
+            read_repair: self.read_repair.clone(),
+            max_trie_depth: self.max_trie_depth,
+            duplicate_write_policy: self.duplicate_write_policy,
+            reserve_system_keyspace: self.reserve_system_keyspace,
+            namespace: params.namespace,
+            trace: params.trace.child("nomt.session"),
+            preconditions: Vec::new(),
+            storage_hints: Vec::new(),
+            hash_writes: Vec::new(),
             _marker: std::marker::PhantomData,
         }
     }
 
     /// Perform a rollback of the last `n` commits.
     ///
-    /// This function will block until all ongoing commits or [`Session`]s are finished.
+    /// This function will block until all ongoing commits or [`Session`]s are finished. Fails with
+    /// [`SessionMisuse::LiveSessionOnThread`] instead if the calling thread began a session which
+    /// is still live. Finished sessions and overlays built before the rollback can't be committed
+    /// after it.
     ///
     /// Fails if the DB is not configured for rollback or doesn't have enough commits logged to
     /// rollback.
-    pub fn rollback(&self, n: usize) -> anyhow::Result<()> {
+    pub fn rollback(&self, n: usize) -> Result<(), Error> {
+        self.rollback_inner(n, false).map(drop)
+    }
+
+    /// Perform a rollback of the last `n` commits like [`Nomt::rollback`], and return the keys
+    /// whose values were changed by it, ordered by key.
+    ///
+    /// This lets caches built on top of the database be invalidated selectively. Keys which were
+    /// written in the rolled back commits but ended up with their original value are not
+    /// included. Telling them apart takes reading and hashing the value of every key the rollback
+    /// restores, which [`Nomt::rollback`] doesn't do.
+    pub fn rollback_with_changes(&self, n: usize) -> Result<Vec<RolledBackKey>, Error> {
+        self.rollback_inner(n, true)
+    }
+
+    fn rollback_inner(&self, n: usize, report_changes: bool) -> Result<Vec<RolledBackKey>, Error> {
         if n == 0 {
-            return Ok(());
+            return Ok(Vec::new());
         }
 
-        let _write_guard = self.access_lock.write();
+        let _gate = self.store.write_gate();
+        let _write_guard = self.write_access()?;
+        self.store.ensure_writable()?;
 
         let Some(rollback) = self.store.rollback() else {
-            anyhow::bail!("rollback: not enabled");
+            return Err(Error::Rollback(anyhow::anyhow!("rollback: not enabled")));
         };
-        let Some(traceback) = rollback.truncate(n)? else {
-            anyhow::bail!("rollback: not enough logged for rolling back");
+        let Some(traceback) = rollback.truncate(n).map_err(Error::rollback)? else {
+            return Err(Error::Rollback(anyhow::anyhow!(
+                "rollback: not enough logged for rolling back"
+            )));
         };
+        self.store.record_rollback(n);
+        let pages_before = self.page_cache.len();
+        let leaves_before = self.store.leaf_cache_len();
 
         // Begin a new session. We do not allow rollback for this operation because that would
         // interfere with the rollback log: if another rollback were to be issued, it must rollback
@@ -375,86 +1081,858 @@ impl<T: HashAlgorithm> Nomt<T> {
         session_params.take_global_guard = false;
         let sess = self.begin_session(session_params);
 
-        // Convert the traceback into a series of write commands.
+        // Convert the traceback into a series of write commands, noting the values which change.
         let mut actuals = Vec::new();
+        let mut changed = Vec::new();
         for (key, value) in traceback {
             sess.warm_up(key);
-            let value = KeyReadWrite::Write(value);
-            actuals.push((key, value));
+            if report_changes {
+                let prior = sess.read(key)?.map(|v| T::hash_value(&v));
+                let restored = value.as_ref().map(|v| T::hash_value(v));
+                if prior != restored {
+                    changed.push(RolledBackKey {
+                        key,
+                        prior,
+                        restored,
+                    });
+                }
+            }
+            actuals.push((key, KeyReadWrite::Write(value)));
         }
 
         sess.finish(actuals)?.commit(&self)?;
 
-        Ok(())
+        // The commit patched the cached pages and leaves it touched.
+        if self.rollback_cache_policy == RollbackCachePolicy::Clear {
+            self.page_cache.clear(self.page_cache_upper_levels);
+            self.store.clear_leaf_cache();
+        }
+        let cache_stats = RollbackCacheStats {
+            pages_before,
+            pages_after: self.page_cache.len(),
+            leaves_before,
+            leaves_after: self.store.leaf_cache_len(),
+        };
+
+        let mut shared = self.shared.lock();
+        shared.rollbacks += 1;
+        shared.last_rollback_cache = Some(cache_stats);
+
+        Ok(changed)
+    }
+
+    /// Export the reverse delta of the `n`-th most recent commit retained in the rollback log,
+    /// proven against the state before that commit. `n` is 1 for the most recent commit.
+    ///
+    /// The state before the commit is restored in memory from the rollback log, as
+    /// [`Nomt::rollback`] would restore it, but nothing is written. This blocks until the proofs
+    /// are computed.
+    ///
+    /// Fails if the DB is not configured for rollback, doesn't have `n` commits logged, or if
+    /// another commit happens meanwhile.
+    pub fn export_reverse_delta(&self, n: usize) -> anyhow::Result<ProvenReverseDelta> {
+        let Some(rollback) = self.store.rollback() else {
+            anyhow::bail!("export_reverse_delta: rollback not enabled");
+        };
+        if n == 0 {
+            anyhow::bail!("export_reverse_delta: commits are counted from 1");
+        }
+
+        // Restore the state before the given deltas, the most recent first, in an overlay on top
+        // of the current state.
+        let restore = |session: Session<T>, deltas: &[rollback::Delta]| {
+            let mut traceback = BTreeMap::new();
+            for delta in deltas {
+                traceback.extend(delta.priors.iter().map(|(k, v)| (*k, v.clone())));
+            }
+            let actuals = traceback
+                .into_iter()
+                .map(|(key, value)| (key, KeyReadWrite::Write(value)))
+                .collect();
+            session
+                .finish(actuals)
+                .map(|finished| finished.into_overlay())
+        };
+        let restore_params = || SessionParams {
+            record_rollback_delta: false,
+            ..SessionParams::default()
+        };
+
+        // The session keeps commits out while the log is read.
+        let session = self.begin_session(restore_params());
+        let current_root = session.prev_root();
+        let Some(deltas) = rollback.recent(n) else {
+            anyhow::bail!("export_reverse_delta: not enough logged");
+        };
+        let pre_state = restore(session, &deltas)?;
+        let post_root = if n == 1 {
+            current_root
+        } else {
+            let session = self.begin_session(restore_params());
+            if session.prev_root() != current_root {
+                anyhow::bail!("export_reverse_delta: interrupted by a commit");
+            }
+            restore(session, &deltas[..n - 1])?.root()
+        };
+
+        // UNWRAP: `recent` returns `n` deltas and `n` is at least 1.
+        let delta = deltas.last().unwrap();
+        let mut priors = delta
+            .priors
+            .iter()
+            .map(|(key, value)| (*key, value.as_ref().map(|v| T::hash_value(v))))
+            .collect::<Vec<_>>();
+        priors.sort_unstable_by_key(|(key, _)| *key);
+
+        let params = SessionParams::default()
+            .overlay([&pre_state])
+            .map_err(|e| anyhow::anyhow!("export_reverse_delta: {:?}", e))?;
+        let session = self.begin_session(params);
+        // The overlay is built on the current state, unless another commit happened meanwhile.
+        if self.root() != current_root {
+            anyhow::bail!("export_reverse_delta: interrupted by a commit");
+        }
+        let keys = priors.iter().map(|(key, _)| *key).collect::<Vec<_>>();
+        Ok(ProvenReverseDelta {
+            pre_root: pre_state.root(),
+            post_root,
+            priors,
+            proof: session.prove_multi(&keys)?,
+        })
     }
 
     /// Return Nomt's metrics.
-    /// To collect them, they need to be activated at [`Nomt`] creation
-    #[doc(hidden)]
+    /// To collect them, they need to be activated at [`Nomt`] creation with
+    /// [`Options::metrics`]. See the [`metrics`] module.
     pub fn metrics(&self) -> Metrics {
         self.metrics.clone()
     }
 
+    /// Take a snapshot of the contents of the page cache and the leaf cache.
+    ///
+    /// This does not affect which entries are evicted next.
+    #[cfg(feature = "cache-debug")]
+    pub fn cache_snapshot(&self) -> cache_debug::CacheSnapshot {
+        cache_debug::CacheSnapshot {
+            pages: self.page_cache.snapshot(),
+            leaves: self.store.leaf_cache_snapshot(),
+        }
+    }
+
+    /// Remove pages and leaves from the in-memory caches, so that subsequent reads go to disk.
+    ///
+    /// Pages deeper than `keep_levels` are removed from the page cache, and all leaves are removed
+    /// from the leaf cache. The root page, at depth 0, is always kept.
+    ///
+    /// This is meant for measuring reads under controlled cache conditions. The database files
+    /// bypass the cache of the operating system, except on tmpfs. Blocks until all sessions have
+    /// been dropped.
+    pub fn clear_caches(&self, keep_levels: usize) {
+        let _write_guard = self.access_lock.write();
+        self.page_cache.clear(keep_levels);
+        self.store.clear_leaf_cache();
+    }
+
+    /// Load the first `levels` levels of the page tree below the root into the page cache,
+    /// blocking until all pages are loaded.
+    ///
+    /// Pages deeper than [`Options::page_cache_upper_levels`] may be evicted by the next commit.
+    pub fn prepopulate_page_cache(&self, levels: usize) -> anyhow::Result<()> {
+        let io_handle = self.store.io_pool().make_handle();
+        merkle::prepopulate_cache(
+            io_handle,
+            &self.page_cache,
+            &self.store,
+            &self.access_lock,
+            levels,
+        )?;
+        Ok(())
+    }
+
+    /// Start loading the first `levels` levels of the page tree below the root into the page
+    /// cache on a background thread, at the rate given by [`Options::prepopulate_page_cache_rate`].
+    ///
+    /// This is what [`Options::prepopulate_page_cache`] does on startup. Sessions and commits
+    /// proceed while the pages are loaded. A prepopulation still running is cancelled, and the
+    /// new one is reported by [`Nomt::page_cache_prepopulation`] from then on.
+    pub fn prepopulate_page_cache_in_background(&self, levels: usize) -> PrepopulateHandle {
+        let handle = merkle::spawn_prepopulate_cache(
+            self.page_cache.clone(),
+            self.store.clone(),
+            self.access_lock.clone(),
+            levels,
+            self.prepopulate_rate,
+        );
+        let previous = self.prepopulation.lock().replace(handle.clone());
+        if let Some(previous) = previous {
+            previous.cancel();
+            previous.wait();
+        }
+        handle
+    }
+
+    /// Get the most recently started background prepopulation of the page cache, if any.
+    pub fn page_cache_prepopulation(&self) -> Option<PrepopulateHandle> {
+        self.prepopulation.lock().clone()
+    }
+
+    /// Get the total time background writes have been delayed by the write throttle since the
+    /// database was opened.
+    ///
+    /// See [`Options::write_throttle_target`].
+    pub fn background_write_delay(&self) -> std::time::Duration {
+        self.store.background_write_delay()
+    }
+
     /// Get the hash-table space utilization.
     pub fn hash_table_utilization(&self) -> HashTableUtilization {
         self.store.hash_table_utilization()
     }
-}
 
-/// A configuration type used to inform NOMT whether to generate witnesses of accessed data.
-pub struct WitnessMode(bool);
+    /// Start resizing the hash table to the given number of buckets on a background thread.
+    ///
+    /// The buckets are copied into a new hash table next to the current one, in chunks between
+    /// commits, so that sessions and commits proceed meanwhile. Once all buckets are copied, the
+    /// database switches over to the new hash table as soon as no sessions are live. The switch is
+    /// atomic: after a crash, the database is opened with either the old or the new hash table.
+    ///
+    /// The page cache is emptied by the switch, apart from the root page. Changesets and overlays
+    /// of sessions begun before the switch can still be committed afterwards, but their pages
+    /// are looked up again in the new hash table when they are.
+    ///
+    /// Fails if a resize is already running, if the hash table already has that many buckets or
+    /// at least that many are occupied, or with [`ReadBackend::Mmap`].
+    ///
+    /// See [`Options::hashtable_auto_resize`] to start resizes automatically.
+    pub fn resize_hash_table(&self, buckets: u32) -> anyhow::Result<HashTableResizeHandle> {
+        let mut resize = self.resize.lock();
+        if let Some(running) = resize.as_ref() {
+            if running.progress().status == HashTableResizeStatus::Running {
+                anyhow::bail!("a hash table resize is already in progress");
+            }
+        }
 
-impl WitnessMode {
-    /// Witness all reads and writes to the trie.
-    pub fn read_write() -> Self {
-        WitnessMode(true)
+        let buckets_total = self.hash_table_utilization().capacity as u64;
+        self.store.begin_hash_table_resize(buckets)?;
+
+        let switch = {
+            let store = self.store.clone();
+            let page_cache = self.page_cache.clone();
+            move || {
+                store.finish_hash_table_resize()?;
+                let root_page = store.load_page(ROOT_PAGE_ID)?;
+                page_cache.reset(root_page);
+                Ok(())
+            }
+        };
+        let handle = ht_resize::spawn(
+            self.store.clone(),
+            self.access_lock.clone(),
+            buckets_total,
+            switch,
+        );
+        *resize = Some(handle.clone());
+        Ok(handle)
     }
 
-    /// Do not generate a witness.
-    pub fn disabled() -> Self {
-        WitnessMode(false)
+    /// Get the most recently started resize of the hash table, if any.
+    pub fn hash_table_resize(&self) -> Option<HashTableResizeHandle> {
+        self.resize.lock().clone()
     }
-}
-
-/// Parameters for instantiating a session.
-pub struct SessionParams {
-    // INTERNAL: only false during rollback. determines whether the rollback delta is built
-    record_rollback_delta: bool,
-    // INTERNAL: only false during rollback. determines whether a global read lock is taken
-    take_global_guard: bool,
 
-    witness: WitnessMode,
-    overlay: LiveOverlay,
-}
+    // Start doubling the hash table if it is fuller than `Options::hashtable_auto_resize` allows
+    // and no resize is running. Called after commits.
+    fn auto_resize_hash_table(&self) {
+        let Some(ref auto_resize) = self.hashtable_auto_resize else {
+            return;
+        };
+        let mut auto_resize = auto_resize.lock();
+        if !auto_resize.poll(&self.metrics) {
+            return;
+        }
+        let utilization = self.hash_table_utilization();
+        if utilization.occupancy_rate() <= auto_resize.load_factor {
+            return;
+        }
+        let running = self
+            .resize
+            .lock()
+            .as_ref()
+            .is_some_and(|resize| resize.progress().status == HashTableResizeStatus::Running);
+        if !running {
+            match self.resize_hash_table((utilization.capacity as u32).saturating_mul(2)) {
+                Ok(handle) => auto_resize.started(handle),
+                Err(_) => auto_resize.fail(&self.metrics),
+            }
+        }
+    }
 
-impl Default for SessionParams {
-    fn default() -> Self {
-        SessionParams {
-            record_rollback_delta: true,
-            take_global_guard: true,
-            witness: WitnessMode::disabled(),
-            // UNWRAP: empty live overlay always valid.
-            overlay: LiveOverlay::new(None).unwrap(),
+    /// Start defragmenting the beatree on a background thread, moving the pages of the leaves,
+    /// branches and large values toward the start of their files and shrinking the files by the
+    /// free pages this leaves at their ends.
+    ///
+    /// The leaves are scanned in steps of [`DefragParams::leaves_per_step`], each of which writes
+    /// the moved pages again and is synced like a commit, with no sessions live. Sessions and
+    /// commits proceed between steps. The steps count toward the write statistics, and their
+    /// background writes are delayed by the write throttle like those of commits.
+    ///
+    /// Fails if a defragmentation is already running or the database is read-only.
+    pub fn defragment(&self, params: DefragParams) -> anyhow::Result<DefragHandle> {
+        let mut defrag = self.defrag.lock();
+        if let Some(running) = defrag.as_ref() {
+            if running.progress().status == DefragStatus::Running {
+                anyhow::bail!("a defragmentation is already in progress");
+            }
         }
+        self.store.ensure_writable()?;
+
+        let handle = defrag::spawn(
+            self.store.clone(),
+            self.access_lock.clone(),
+            self.page_cache.clone(),
+            params,
+        )?;
+        *defrag = Some(handle.clone());
+        Ok(handle)
     }
-}
 
-impl SessionParams {
-    /// Whether to generate a witness of the read and written keys. Default: disabled
+    /// Get the most recently started defragmentation of the beatree, if any.
+    pub fn defragmentation(&self) -> Option<DefragHandle> {
+        self.defrag.lock().clone()
+    }
+
+    /// Get the bytes written by the most recent commit, logical and physical. `None` if nothing
+    /// has been committed since the database was opened.
     ///
-    /// If `WitnessMode::read_write()` is provided, then when this session has concluded it will be
-    /// possible to use [`FinishedSession::take_witness`] to get the recorded witness.
-    pub fn witness_mode(mut self, witness: WitnessMode) -> Self {
-        self.witness = witness;
-        self
+    /// See [`WriteStats::write_amplification`].
+    pub fn last_commit_write_stats(&self) -> Option<WriteStats> {
+        self.store.last_commit_write_stats()
     }
 
-    /// Use a set of live overlays (ancestors, in descending order) as a parent. Default: None
+    /// Get the bytes written by all commits since the database was opened.
+    pub fn total_write_stats(&self) -> WriteStats {
+        self.store.total_write_stats()
+    }
+
+    /// Get the `n` key prefixes written the most within the tracked window of commits, most
+    /// written first.
     ///
-    /// Errors are returned if the set of ancestor overlays provided are not _sound_ or _complete_.
+    /// Empty unless enabled with [`Options::prefix_write_stats`].
+    pub fn hot_prefixes(&self, n: usize) -> Vec<HotPrefix> {
+        self.store.hot_prefixes(n)
+    }
+
+    /// Get what happened to the obsolete segments of the rollback log when the database was
+    /// opened. `None` if rollback is not enabled.
     ///
-    /// An error is returned if the overlays do not represent a chain, starting from the first.
-    /// i.e. the second overlay must be an ancestor of the first, and the third must be an ancestor
+    /// See [`Options::rollback_log_archive`].
+    pub fn rollback_log_archive_stats(&self) -> Option<LogArchiveStats> {
+        self.store
+            .rollback()
+            .map(|rollback| rollback.archive_stats())
+    }
+
+    /// Get the disk usage of the rollback log: the size of the reverse delta kept for each commit
+    /// which can be rolled back, and the total size of the log files. `None` if rollback is not
+    /// enabled.
+    ///
+    /// The size of the delta written by the last commit is also part of
+    /// [`Nomt::last_commit_write_stats`]. See [`Options::rollback_disk_alert`] to be notified when
+    /// the log grows too large.
+    pub fn rollback_stats(&self) -> anyhow::Result<Option<RollbackStats>> {
+        match self.store.rollback() {
+            None => Ok(None),
+            Some(rollback) => Ok(Some(rollback.stats()?)),
+        }
+    }
+
+    /// Get the number of pages and leaves cached before and after the last [`Nomt::rollback`]
+    /// since the database was opened. `None` if there was none.
+    ///
+    /// See [`Options::rollback_cache_policy`].
+    pub fn rollback_cache_stats(&self) -> Option<RollbackCacheStats> {
+        self.shared.lock().last_rollback_cache
+    }
+
+    /// Get the roots published by the last commit since the database was opened. `None` if there
+    /// was none, or if the last commit skipped recording them to meet its deadline (see
+    /// [`FinishedSession::commit_by`]).
+    ///
+    /// The roots of the namespaces are recorded with [`Options::namespace_bits`].
+    pub fn commit_stats(&self) -> Option<CommitStats> {
+        self.shared.lock().last_commit.clone()
+    }
+
+    // Record the roots published by a commit. Called while the commit still keeps sessions and
+    // other commits out, so that the roots of the namespaces are those of the root.
+    // Evict the leaves of the keys a committed session hinted cold, unless they hold hot keys.
+    fn apply_storage_hints(&self, hints: &[(KeyPath, StorageClass)]) {
+        let hinted = |class| {
+            hints
+                .iter()
+                .filter(|(_, c)| *c == class)
+                .map(|(key, _)| *key)
+                .collect::<Vec<_>>()
+        };
+        let cold = hinted(StorageClass::Cold);
+        if !cold.is_empty() {
+            let hot = hinted(StorageClass::Hot);
+            let uncached = self.store.uncache_leaves(&cold, &hot);
+            self.metrics
+                .count_n(Metric::ColdLeavesUncached, uncached as u64);
+        }
+    }
+
+    fn record_commit_stats(&self) -> anyhow::Result<()> {
+        let root = self.root();
+        let namespace_roots = match self.namespace_bits {
+            None => Vec::new(),
+            Some(bits) => {
+                let session = self.begin_session(SessionParams {
+                    record_rollback_delta: false,
+                    take_global_guard: false,
+                    ..SessionParams::default()
+                });
+                (0..1 << bits)
+                    .map(|index| session.namespace_root(Namespace::new(index, bits)))
+                    .collect::<anyhow::Result<_>>()?
+            }
+        };
+        self.shared.lock().last_commit = Some(CommitStats {
+            root,
+            namespace_roots,
+        });
+        Ok(())
+    }
+
+    /// Measure the disk space used by the database against the size of the values stored in it.
+    ///
+    /// See [`SpaceStats::space_amplification`]. This reads every value in the database and blocks
+    /// commits from starting until it returns, so it is meant for occasional diagnostics.
+    pub fn space_stats(&self) -> anyhow::Result<SpaceStats> {
+        let _guard = self.access_lock.read();
+        self.store.space_stats()
+    }
+
+    /// Gather statistics of the trie and of the pages storing it: the number of leaves and their
+    /// depths, the number and fill factor of merkle pages and the number of overflow pages.
+    ///
+    /// See [`TrieStats`]. This reads every leaf and merkle page in the database and blocks commits
+    /// from starting until it returns, so it is meant for occasional diagnostics, e.g. sizing the
+    /// hash table and the caches.
+    pub fn stats(&self) -> anyhow::Result<TrieStats> {
+        let _guard = self.access_lock.read();
+        trie_stats::collect::<T>(&self.store, self.root().into_inner())
+    }
+
+    /// Check the files of the database for inconsistencies, to the given [level](IntegrityLevel).
+    ///
+    /// See the [`integrity`] module. Inconsistencies are listed in the report, while failing to
+    /// read the files is an error. Fails if a sync is stalled, since the files are not consistent
+    /// until it is resumed. This blocks commits from starting until it returns, so it is meant
+    /// for occasional diagnostics.
+    pub fn check_integrity(&self, level: IntegrityLevel) -> anyhow::Result<IntegrityReport> {
+        let _guard = self.access_lock.read();
+        if let Some(phase) = self.store.stalled_sync() {
+            anyhow::bail!("cannot check integrity while the sync is stalled at {phase:?}");
+        }
+        integrity::check::<T>(&self.store, self.root().into_inner(), level)
+    }
+
+    /// Get the identity of the database and where it came from.
+    ///
+    /// Every database is assigned a random identifier on creation. Databases created by
+    /// [`Nomt::clone_to`] record the identifier and sync sequence number of their origin, and the
+    /// most recent [`Nomt::rollback`] is recorded as well. All of this is stored in the meta file
+    /// and survives restarts.
+    ///
+    /// Databases created by versions of NOMT which didn't record the lineage have the nil
+    /// identifier and record nothing until upgraded with [`Options::upgrade_format`].
+    pub fn lineage(&self) -> Lineage {
+        self.store.lineage()
+    }
+
+    /// The history of the effective options the database was opened with, oldest first.
+    ///
+    /// An entry is recorded whenever the database is opened with options differing from the
+    /// previous entry, along with the sync sequence number at that time, so that changes in
+    /// behavior can be correlated with changes in configuration. Creation-time options, such as
+    /// the number of hash-table buckets, reflect the database rather than the options given.
+    pub fn options_journal(&self) -> anyhow::Result<Vec<OptionsJournalEntry>> {
+        self.store.options_journal()
+    }
+
+    /// Copy the database into the given directory, which must not exist or be empty.
+    ///
+    /// The copy is a separate database with its own identifier, recording this one as its origin
+    /// in its [`Lineage`]. Restoring a backup should be done by cloning it, so that the restored
+    /// database can be told apart from the backup. Directories copied by other means keep the
+    /// identifier of the original.
+    ///
+    /// This blocks commits from starting until it returns.
+    pub fn clone_to(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let _guard = self.access_lock.read();
+        self.store.clone_to(path.as_ref())
+    }
+
+    /// Copy the database into the given directory, which must not exist or be empty, while it
+    /// stays in use. Restore the backup with [`Nomt::open_backup`].
+    ///
+    /// Unlike [`Nomt::clone_to`], this does not block commits while copying the bulk of the
+    /// database. It only holds up syncs at the end, to copy the pages changed in the meantime and
+    /// the small files. See the [`backup`] module.
+    pub fn create_backup(
+        &self,
+        path: impl AsRef<std::path::Path>,
+    ) -> anyhow::Result<backup::BackupInfo> {
+        self.store.backup_to(path.as_ref())
+    }
+
+    /// Freeze the database for a maintenance window, such as copying its directory with external
+    /// backup tools, until [`Nomt::thaw`].
+    ///
+    /// This waits for the commit in progress, if any, and then holds up commits, rollbacks and
+    /// resizes of the hash table. Every commit is durable once it returns, so the files are a
+    /// consistent copy of the database as of the last commit while frozen. Reads and sessions
+    /// continue; a commit waits for the thaw without holding them up.
+    ///
+    /// The lock on the directory is released, so that tools respecting it can access the files.
+    /// Other processes must not open the database until it is thawed.
+    ///
+    /// Fails if the database is already frozen. A commit in progress may be waiting for sessions
+    /// to end, and this waits for them as well.
+    pub fn freeze(&self) -> anyhow::Result<()> {
+        let mut frozen = self.frozen.lock();
+        if frozen.is_some() {
+            anyhow::bail!("the database is already frozen");
+        }
+        *frozen = Some(self.store.freeze());
+        Ok(())
+    }
+
+    /// Resume commits after [`Nomt::freeze`].
+    ///
+    /// Fails if the lock on the directory can't be taken again, such as when another process
+    /// opened the database meanwhile, in which case the database stays frozen. Does nothing if
+    /// the database is not frozen.
+    pub fn thaw(&self) -> anyhow::Result<()> {
+        let mut frozen = self.frozen.lock();
+        let Some(guard) = frozen.take() else {
+            return Ok(());
+        };
+        if let Err((guard, e)) = self.store.thaw(guard) {
+            *frozen = Some(guard);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Whether the database is frozen by [`Nomt::freeze`].
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.lock().is_some()
+    }
+
+    /// Write all values fetched by read-through to the local database in a single commit.
+    ///
+    /// Returns the number of values written. This is a no-op if read-through is not configured.
+    /// Sessions begun afterwards can commit changes building on the written values, which
+    /// sessions reading them through can't. See [`Options::read_through`].
+    ///
+    /// This function will block until all ongoing sessions and commits have finished.
+    pub fn persist_fetched(&self) -> anyhow::Result<usize> {
+        let Some(ref read_through) = self.read_through else {
+            return Ok(0);
+        };
+
+        let pending = read_through.take_pending();
+        let written = pending.len();
+        if !pending.is_empty() {
+            let session = self.begin_session(SessionParams::default());
+            let actuals = pending
+                .into_iter()
+                .map(|(key, value)| (key, KeyReadWrite::Write(Some(value))))
+                .collect();
+            session
+                .finish(actuals)?
+                .commit(self)
+                .map_err(Error::into_anyhow)?;
+        }
+
+        if self.root() == read_through.target_root() {
+            read_through.deactivate();
+        }
+
+        Ok(written)
+    }
+
+    /// Get the state of read-through, if configured. See [`Options::read_through`].
+    pub fn read_through_stats(&self) -> Option<ReadThroughStats> {
+        self.read_through.as_ref().map(|r| r.stats())
+    }
+
+    /// Write all values repaired by read repair to the local database in a single commit.
+    ///
+    /// Only values repaired as-of the current root are written; the others have been superseded
+    /// by later commits. The commit leaves the root unchanged. Returns the number of values
+    /// written. This is a no-op if read repair is not configured. See [`Options::read_repair`].
+    ///
+    /// This function will block until all ongoing sessions and commits have finished.
+    pub fn persist_repairs(&self) -> anyhow::Result<usize> {
+        let Some(ref read_repair) = self.read_repair else {
+            return Ok(0);
+        };
+
+        let session = self.begin_session(SessionParams::default());
+        let pending = read_repair.take_pending(session.prev_root());
+        let written = pending.len();
+        if !pending.is_empty() {
+            let actuals = pending
+                .into_iter()
+                .map(|(key, value)| (key, KeyReadWrite::Write(value)))
+                .collect();
+            session
+                .finish(actuals)?
+                .commit(self)
+                .map_err(Error::into_anyhow)?;
+        }
+        Ok(written)
+    }
+
+    /// Get the counters of read repair, if configured. See [`Options::read_repair`].
+    pub fn read_repair_stats(&self) -> Option<ReadRepairStats> {
+        self.read_repair.as_ref().map(|r| r.stats())
+    }
+}
+
+impl<T> Drop for Nomt<T> {
+    fn drop(&mut self) {
+        // The resize waits for a thaw.
+        drop(self.frozen.get_mut().take());
+
+        // The prepopulation holds the store open. Stop it, so that the database is closed once
+        // this returns.
+        if let Some(prepopulation) = self.prepopulation.get_mut().take() {
+            prepopulation.cancel();
+            prepopulation.wait();
+        }
+        if let Some(resize) = self.resize.get_mut().take() {
+            resize.cancel();
+            resize.wait();
+        }
+        if let Some(defrag) = self.defrag.get_mut().take() {
+            defrag.cancel();
+            defrag.wait();
+        }
+
+        // A session outliving the database holds the access lock. Rather than wait for it, skip
+        // the cache file, as well as when the caches may be ahead of the state on disk.
+        if let Some((db_dir, size)) = &self.cache_file {
+            if let Some(_write_guard) = self.access_lock.try_write() {
+                if self.store.ensure_writable().is_ok() {
+                    let _ = cache_file::save(
+                        db_dir,
+                        *size,
+                        self.store.sync_seqn(),
+                        self.shared.lock().root.into_inner(),
+                        &self.page_cache,
+                        &self.store,
+                    );
+                }
+            }
+        }
+    }
+}
+
+// A predicate selecting the keys to witness.
+type KeyPredicate = Arc<dyn Fn(&KeyPath) -> bool + Send + Sync>;
+
+/// A configuration type used to inform NOMT whether to generate witnesses of accessed data.
+pub struct WitnessMode {
+    enabled: bool,
+    excluded: Vec<TriePosition>,
+    retained: Option<KeyPredicate>,
+    context: Option<Vec<u8>>,
+    values: bool,
+}
+
+impl WitnessMode {
+    /// Witness all reads and writes to the trie.
+    pub fn read_write() -> Self {
+        WitnessMode {
+            enabled: true,
+            excluded: Vec::new(),
+            retained: None,
+            context: None,
+            values: false,
+        }
+    }
+
+    /// Do not generate a witness.
+    pub fn disabled() -> Self {
+        WitnessMode {
+            enabled: false,
+            excluded: Vec::new(),
+            retained: None,
+            context: None,
+            values: false,
+        }
+    }
+
+    /// Exclude the reads and writes of keys beginning with `prefix` from the witness, e.g. for
+    /// private state.
+    ///
+    /// Instead of the paths to those keys, the witness proves the root of the subtree at the
+    /// prefix before the session and commits to its root after the session. See
+    /// [`Witness::exclude_subtrees`]. Finishing a session fails if it accesses keys with the
+    /// prefix while the trie has no node at the prefix, as is the case when fewer than two keys
+    /// begin with it. Protocols typically seed each excluded prefix with two fixed keys.
+    ///
+    /// Panics if the prefix is longer than 256 bits.
+    pub fn exclude_prefix(mut self, prefix: &BitSlice<u8, Msb0>) -> Self {
+        assert!(prefix.len() <= 256, "prefix longer than a key path");
+        self.excluded.push(match prefix.is_empty() {
+            true => TriePosition::new(),
+            false => TriePosition::from_bitslice(prefix),
+        });
+        self
+    }
+
+    /// Witness only the reads and writes of the given keys, e.g. the few keys of a large batch which
+    /// are proven to an external party.
+    ///
+    /// The other keys are replaced by the largest subtrees holding none of the given keys, whose
+    /// roots are handled like those of excluded prefixes: proven before the session and taken as
+    /// given after it. See [`Witness::retain_keys`]. Keys sharing a path with a given key remain
+    /// in the witness.
+    pub fn only_keys(self, keys: impl IntoIterator<Item = KeyPath>) -> Self {
+        let keys = keys.into_iter().collect::<HashSet<_>>();
+        self.only_keys_matching(move |key| keys.contains(key))
+    }
+
+    /// Witness only the reads and writes of the keys matching the predicate.
+    ///
+    /// See [`WitnessMode::only_keys`].
+    pub fn only_keys_matching(
+        mut self,
+        predicate: impl Fn(&KeyPath) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.retained = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Bind the witness to the roots of the session and the given context, e.g. a chain id and
+    /// block number, so that it cannot be replayed against another block.
+    ///
+    /// See [`Witness::bind`] and [`Witness::verify_in_context`].
+    pub fn bind_to_context(mut self, context: impl Into<Vec<u8>>) -> Self {
+        self.context = Some(context.into());
+        self
+    }
+
+    /// Embed the values of the witnessed keys before the session in the witness: the values read
+    /// and the values replaced by writes, so that verifiers don't need to obtain them elsewhere.
+    ///
+    /// Values stored elsewhere, see [`Session::write_value_hash`], are not embedded. See
+    /// [`Witness::prior_value`] and [`Witness::verify_values`].
+    pub fn include_values(mut self) -> Self {
+        self.values = true;
+        self
+    }
+}
+
+/// Parameters for instantiating a session.
+pub struct SessionParams {
+    // INTERNAL: only false during rollback. determines whether the rollback delta is built
+    record_rollback_delta: bool,
+    // INTERNAL: only false during rollback. determines whether a global read lock is taken
+    take_global_guard: bool,
+
+    witness: WitnessMode,
+    access_trace: AccessTraceMode,
+    overlay: LiveOverlay,
+    sync_mode: SessionSyncMode,
+    namespace: Option<Namespace>,
+    trace: Trace,
+}
+
+impl Default for SessionParams {
+    fn default() -> Self {
+        SessionParams {
+            record_rollback_delta: true,
+            take_global_guard: true,
+            witness: WitnessMode::disabled(),
+            access_trace: AccessTraceMode::disabled(),
+            sync_mode: SessionSyncMode::WaitForSync,
+            // UNWRAP: empty live overlay always valid.
+            overlay: LiveOverlay::new(None).unwrap(),
+            namespace: None,
+            trace: Trace::default(),
+        }
+    }
+}
+
+impl SessionParams {
+    /// Whether to generate a witness of the read and written keys. Default: disabled
+    ///
+    /// If `WitnessMode::read_write()` is provided, then when this session has concluded it will be
+    /// possible to use [`FinishedSession::take_witness`] to get the recorded witness.
+    pub fn witness_mode(mut self, witness: WitnessMode) -> Self {
+        self.witness = witness;
+        self
+    }
+
+    /// Whether to trace the keys, and optionally the pages, accessed by the session.
+    /// Default: disabled
+    ///
+    /// If enabled, then when this session has concluded it will be possible to use
+    /// [`FinishedSession::take_access_list`] to get the traced [`AccessList`].
+    pub fn access_trace(mut self, access_trace: AccessTraceMode) -> Self {
+        self.access_trace = access_trace;
+        self
+    }
+
+    /// How to order the session against pending commits and rollbacks.
+    /// Default: [`SessionSyncMode::WaitForSync`]
+    pub fn sync_mode(mut self, sync_mode: SessionSyncMode) -> Self {
+        self.sync_mode = sync_mode;
+        self
+    }
+
+    /// Restrict the session to the keys of a namespace. Default: None
+    ///
+    /// [`Session::finish`] fails with [`KeyOutsideNamespace`](namespace::KeyOutsideNamespace) if
+    /// the actuals hold a key outside of the namespace, so that the witness covers the namespace
+    /// alone. See the [`namespace`] module.
+    pub fn namespace(mut self, namespace: Namespace) -> Self {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    /// Record the session as OpenTelemetry spans under the span active in the given context, so
+    /// that the traces of the caller extend into the database. Default: None
+    ///
+    /// The session records a `nomt.session` span, lasting until the session or the finished
+    /// session is committed or dropped. Within it, each [`Session::read`] is a `nomt.read` span,
+    /// [`Session::finish`] a `nomt.finish` span and [`FinishedSession::commit`] a `nomt.commit`
+    /// span. The sync of the commit records a span per stage below that: `nomt.sync.wal`,
+    /// `nomt.sync.values`, `nomt.sync.meta` and `nomt.sync.post_meta`. A failed finish or commit
+    /// sets the status of its span to an error.
+    ///
+    /// [`Overlay::commit`] and syncs outside of a traced session record their spans under the
+    /// span active in the current context of the calling thread, if any. Spans are created with
+    /// the global tracer named `nomt`.
+    #[cfg(feature = "opentelemetry")]
+    pub fn trace_context(mut self, cx: opentelemetry::Context) -> Self {
+        self.trace = Trace::new(cx);
+        self
+    }
+
+    /// Use a set of live overlays (ancestors, in descending order) as a parent. Default: None
+    ///
+    /// Errors are returned if the set of ancestor overlays provided are not _sound_ or _complete_.
+    ///
+    /// An error is returned if the overlays do not represent a chain, starting from the first.
+    /// i.e. the second overlay must be an ancestor of the first, and the third must be an ancestor
     /// of the second, and so on. This can be thought of as soundness.
     ///
     /// An error is returned if the final overlay's parent has not yet been committed. The complete
@@ -482,10 +1960,29 @@ pub struct Session<T> {
     rollback_delta: Option<rollback::ReverseDeltaBuilder>,
     overlay: LiveOverlay,
     witness_mode: WitnessMode,
+    access_recorder: Option<AccessRecorder>,
     // Note: this needs to be after rollback_delta and merkle_updater in declaration order,
     // so this is dropped after all read transactions are taken, even when the session is dropped.
-    access_guard: Option<ArcRwLockReadGuard<parking_lot::RawRwLock, ()>>,
+    access_guard: Option<SessionAccess>,
+    /// The access log and the ID of this session in it, if the session is logged.
+    access_log: Option<(Arc<AccessLog>, u64)>,
+    overlay_spill: Option<Arc<OverlaySpill>>,
     prev_root: Root,
+    /// The number of rollbacks performed when the session began.
+    rollback_epoch: u64,
+    ht_generation: u64,
+    read_through: Option<Arc<ReadThrough>>,
+    /// Whether a value was read through from the remote archive.
+    read_through_used: AtomicBool,
+    read_repair: Option<Arc<ReadRepair>>,
+    max_trie_depth: Option<(usize, DeepPathPolicy)>,
+    duplicate_write_policy: DuplicateWritePolicy,
+    reserve_system_keyspace: bool,
+    namespace: Option<Namespace>,
+    trace: Trace,
+    preconditions: Vec<(KeyPath, Precondition)>,
+    storage_hints: Vec<(KeyPath, StorageClass)>,
+    hash_writes: Vec<(KeyPath, ValueHash)>,
     _marker: std::marker::PhantomData<T>,
 }
 
@@ -505,17 +2002,6 @@ impl<T> Session<T> {
         self.merkle_updater.warm_up(path);
     }
 
-    /// Synchronously read the value stored under the given key.
-    ///
-    /// Returns `None` if the value is not stored under the given key. Fails only if I/O fails.
-    pub fn read(&self, path: KeyPath) -> anyhow::Result<Option<Value>> {
-        let _maybe_guard = self.metrics.record(Metric::ValueFetchTime);
-        if let Some(value_change) = self.overlay.value(&path) {
-            return Ok(value_change.as_option().map(|v| v.to_vec()));
-        }
-        self.store.load_value(path)
-    }
-
     /// Returns the [`Root`] at which this session is based off of.
     pub fn prev_root(&self) -> Root {
         self.prev_root
@@ -549,9 +2035,296 @@ impl<T> Session<T> {
             rollback.tentative_preserve_prior(path);
         }
     }
+
+    /// Abandon the session without computing a root or writing anything.
+    ///
+    /// This blocks until the background work started by the session has stopped: the warm-up
+    /// worker is interrupted and its prefetched pages are freed, the rollback delta builder is
+    /// joined, and all read transactions pinning the current database state are released before
+    /// the session's hold on the database is. Nothing is written to disk and the page cache is left
+    /// as it was.
+    ///
+    /// Dropping a session has the same effect; this function just makes the intent explicit.
+    pub fn abort(self) {
+        let Session {
+            merkle_updater,
+            rollback_delta,
+            access_guard,
+            ..
+        } = self;
+
+        drop(merkle_updater);
+        drop(rollback_delta);
+        drop(access_guard);
+    }
 }
 
 impl<T: HashAlgorithm> Session<T> {
+    /// Start fetching the values of the given keys and their merkle paths in the background,
+    /// ahead of reading them.
+    ///
+    /// The leaves holding the values are loaded into the leaf cache and the merkle pages are
+    /// fetched as by [`Session::warm_up`], all at once, so that the reads, proofs and commit which
+    /// follow find them in memory instead of waiting on the disk one at a time. This suits block
+    /// executors which know the access list of a block before executing it.
+    ///
+    /// The fetches are issued by the warm-up worker, which the first call starts if warm-up is
+    /// not enabled with [`Options::warm_up`]. Prefetching is only a hint: it never changes the
+    /// result of a read, keys with values in the overlays are skipped, and fetches which fail are
+    /// left to the reads to retry.
+    pub fn prefetch(&self, keys: impl IntoIterator<Item = KeyPath>) {
+        for key in keys {
+            self.merkle_updater.prefetch::<T>(key);
+        }
+    }
+
+    /// Synchronously read the value stored under the given key.
+    ///
+    /// Returns `None` if the value is not stored under the given key. Fails with
+    /// [`ValueNotStored`] if only the hash of the value is kept (see
+    /// [`Session::write_value_hash`]), and otherwise only if I/O fails, or if read-through is
+    /// active and the remote archive fails. If read repair is configured, failed and damaged
+    /// reads are only surfaced if the repair fails as well.
+    pub fn read(&self, path: KeyPath) -> anyhow::Result<Option<Value>> {
+        let _maybe_guard = self.metrics.record(Metric::ValueFetchTime);
+        let _trace = self.trace.child("nomt.read");
+        if let Some(access_recorder) = &self.access_recorder {
+            access_recorder.record_read(path);
+        }
+        self.log_access(access_log::Event::Read(path));
+        self.store.read_view(|| self.load_value(path))
+    }
+
+    /// Synchronously read the value stored under the given key, or its hash if the value is
+    /// stored elsewhere.
+    ///
+    /// This is like [`Session::read`], but succeeds for values written with
+    /// [`Session::write_value_hash`].
+    pub fn read_local(&self, path: KeyPath) -> anyhow::Result<Option<LocalValue>> {
+        let _maybe_guard = self.metrics.record(Metric::ValueFetchTime);
+        if let Some(access_recorder) = &self.access_recorder {
+            access_recorder.record_read(path);
+        }
+        self.log_access(access_log::Event::Read(path));
+        self.store.read_view(|| self.load_local(path))
+    }
+
+    /// Synchronously read `len` bytes of the value stored under the given key, starting at
+    /// `offset`.
+    ///
+    /// The slice is cut short at the end of the value, and is empty if `offset` is beyond it. Of a
+    /// large value, only the pages holding the slice are read, which makes this much cheaper than
+    /// [`Session::read`] for reading e.g. a header. Returns `None` if the value is not stored
+    /// under the given key.
+    ///
+    /// If read-through or read repair is configured, the whole value is read to be checked. Fails
+    /// with [`ValueNotStored`] like [`Session::read`].
+    pub fn read_slice(
+        &self,
+        path: KeyPath,
+        offset: usize,
+        len: usize,
+    ) -> anyhow::Result<Option<Value>> {
+        let _maybe_guard = self.metrics.record(Metric::ValueFetchTime);
+        if let Some(access_recorder) = &self.access_recorder {
+            access_recorder.record_read(path);
+        }
+        self.log_access(access_log::Event::Read(path));
+        let range = offset..offset.saturating_add(len);
+        let clip = |value: Value| value[beatree::clip_range(range.clone(), value.len())].to_vec();
+        if self.read_repair.is_some() || self.read_through.is_some() {
+            return Ok(self.store.read_view(|| self.load_value(path))?.map(clip));
+        }
+        let value = match self.overlay.value(&path) {
+            Some(value_change) => value_change.as_option().map(|v| clip(v.to_vec())),
+            None => self
+                .store
+                .read_view(|| self.store.load_value_slice(path, range.clone()))?,
+        };
+        // A value stored elsewhere reads as empty, as does any slice beyond the end of a value.
+        if value.as_ref().is_some_and(|v| v.is_empty()) {
+            if let Some(value_hash) = self.hash_only(path)? {
+                return Err(ValueNotStored {
+                    key: path,
+                    value_hash,
+                }
+                .into());
+            }
+        }
+        Ok(value)
+    }
+
+    /// Iterate over the values in the given range of keys, in ascending order.
+    ///
+    /// The values include the changes of the overlays the session is based on. The iterator
+    /// borrows the session, and so reflects the state the session is based on throughout. It
+    /// blocks syncs from starting until dropped, like [`Nomt::iter_values`].
+    pub fn iter_range(&self, range: impl RangeBounds<KeyPath>) -> RangeIter<'_> {
+        match range_iter::half_open(range.start_bound(), range.end_bound()) {
+            None => RangeIter::empty(),
+            Some((start, end)) => RangeIter::new(
+                self.store.iter_values(start, end),
+                Box::new(self.overlay.value_iter(start, end)),
+            ),
+        }
+    }
+
+    /// Read the value stored under the given key without blocking the thread.
+    ///
+    /// The returned future is driven by the completions of the reads it submits to the I/O
+    /// workers, so it does not need a thread of its own. It resolves like [`Session::read`] would
+    /// and does not borrow the session, but reads the state the session is based on regardless
+    /// of when it is polled.
+    ///
+    /// If read-through or read repair is configured, the value is read synchronously before this
+    /// returns, like [`Session::read`] does. A value of which only the hash is kept fails with
+    /// [`ValueNotStored`], and a database opened with [`Nomt::open_read_only`] fails with
+    /// [`StaleView`] if the writer syncs past the view before the read completes, like they do
+    /// for [`Session::read`].
+    pub fn read_async(&self, path: KeyPath) -> ReadValue {
+        if let Some(access_recorder) = &self.access_recorder {
+            access_recorder.record_read(path);
+        }
+        self.log_access(access_log::Event::Read(path));
+        if self.overlay.value(&path).is_some()
+            || self.read_repair.is_some()
+            || self.read_through.is_some()
+        {
+            return ReadValue::ready(self.store.read_view(|| self.load_value(path)));
+        }
+        ReadValue::start(&self.store, path, self.store.io_pool().make_waking_handle())
+    }
+
+    fn log_access(&self, event: access_log::Event) {
+        if let Some((access_log, session)) = &self.access_log {
+            access_log.record(Some(*session), event);
+        }
+    }
+
+    fn load_value(&self, path: KeyPath) -> anyhow::Result<Option<Value>> {
+        match self.load_local(path)? {
+            None => Ok(None),
+            Some(LocalValue::Stored(value)) => Ok(Some(value)),
+            Some(LocalValue::HashOnly(value_hash)) => Err(ValueNotStored {
+                key: path,
+                value_hash,
+            }
+            .into()),
+        }
+    }
+
+    fn load_local(&self, path: KeyPath) -> anyhow::Result<Option<LocalValue>> {
+        if let Some(value_change) = self.overlay.value(&path) {
+            return Ok(match value_change.hash_only_value() {
+                Some(value_hash) => Some(LocalValue::HashOnly(value_hash)),
+                None => value_change
+                    .as_option()
+                    .map(|v| LocalValue::Stored(v.to_vec())),
+            });
+        }
+        let loaded = self.store.load_value(path);
+        // A value stored elsewhere reads as empty. Tell it apart before read repair would take
+        // it for a damaged value.
+        if loaded
+            .as_ref()
+            .is_ok_and(|v| v.as_ref().is_some_and(|v| v.is_empty()))
+        {
+            if let Some(value_hash) = self.store.load_value_hash_only(path)? {
+                return Ok(Some(LocalValue::HashOnly(value_hash)));
+            }
+        }
+        let value = match &self.read_repair {
+            None => loaded?,
+            Some(read_repair) => {
+                read_repair.check(path, self.prev_root, loaded, || self.prove(path))?
+            }
+        };
+        let value = match (value, &self.read_through) {
+            (None, Some(read_through)) if read_through.is_active() => {
+                self.read_through_used.store(true, Ordering::Relaxed);
+                read_through.read(path)?
+            }
+            (value, _) => value,
+        };
+        Ok(value.map(LocalValue::Stored))
+    }
+
+    // The hash of the value under the key if only the hash is kept, as of the state the session
+    // is based on.
+    fn hash_only(&self, path: KeyPath) -> anyhow::Result<Option<ValueHash>> {
+        match self.overlay.value(&path) {
+            Some(value_change) => Ok(value_change.hash_only_value()),
+            None => self.store.load_value_hash_only(path),
+        }
+    }
+
+    /// Write the hash of a value stored elsewhere, e.g. in a data availability layer, under the
+    /// given key.
+    ///
+    /// The trie commits to the hash as it would to the value, so roots and proofs are the same as
+    /// if the value itself were written, but only the hash is kept. Reading the key afterwards
+    /// fails with [`ValueNotStored`], carrying the hash; [`Session::read_local`] returns it.
+    /// Iterators yield [`ValueNotStored`] for such keys, as do light state exports and migration,
+    /// while prefix and range proofs list them as [`LocalValue::HashOnly`].
+    ///
+    /// The write is applied by [`Session::finish`] on top of the actuals, the last write to a key
+    /// winning. A key may be listed as read in the actuals, but not as written. Hash-only writes
+    /// are refused if rollback is enabled, since their prior values are not preserved.
+    pub fn write_value_hash(&mut self, path: KeyPath, value_hash: ValueHash) {
+        self.hash_writes.push((path, value_hash));
+    }
+
+    /// Require the current value of a key to satisfy a precondition for the session to finish.
+    ///
+    /// Preconditions are checked by [`Session::finish`] against the state the session is based
+    /// on, and it fails with [`PreconditionsViolated`] listing every precondition which does not
+    /// hold. Since a finished session can only be committed on top of that same state, the
+    /// check and the commit are atomic: a concurrent change to the key makes the commit fail
+    /// instead.
+    ///
+    /// The key should also be listed as a read in the actuals if the session is to be proven.
+    pub fn require(&mut self, path: KeyPath, precondition: Precondition) {
+        self.preconditions.push((path, precondition));
+    }
+
+    /// Tell the database how often a key written by the session is expected to be read.
+    ///
+    /// Without hints, the leaves written by a commit are kept in the leaf cache, and the database
+    /// only learns over many commits which of them are never read again. Hinting keys as
+    /// [`StorageClass::Cold`] leaves their leaves on disk from the start, keeping the cache for
+    /// data which is read. The hint is applied once the session is committed, directly or as an
+    /// overlay, the last hint for a key winning. Hints for keys the session doesn't write are
+    /// ignored. Reading a cold key loads its leaf into the cache as usual.
+    ///
+    /// The hints only steer the leaf cache. They aren't persisted, and neither the placement of
+    /// leaves on disk nor [`Nomt::defragment`] takes them into account.
+    pub fn hint_storage_class(&mut self, path: KeyPath, class: StorageClass) {
+        self.storage_hints.push((path, class));
+    }
+
+    fn check_preconditions(&mut self) -> anyhow::Result<()> {
+        let mut preconditions = mem::take(&mut self.preconditions);
+        preconditions.sort_by_key(|(path, _)| *path);
+
+        let mut violated = Vec::new();
+        for (key, precondition) in preconditions {
+            let actual = self.read_local(key)?.map(|v| v.value_hash::<T>());
+            if !precondition.holds(actual) {
+                violated.push(ViolatedPrecondition {
+                    key,
+                    precondition,
+                    actual,
+                });
+            }
+        }
+
+        if violated.is_empty() {
+            Ok(())
+        } else {
+            Err(PreconditionsViolated { violated }.into())
+        }
+    }
+
     /// Get a merkle proof for the given key path.
     ///
     /// This will block until the proof is fetched from the database.
@@ -559,40 +2332,291 @@ impl<T: HashAlgorithm> Session<T> {
     ///
     /// Fails only if I/O fails. Proves either the existence or non-existence of the key.
     pub fn prove(&self, path: KeyPath) -> anyhow::Result<PathProof> {
-        Ok(self.merkle_updater.prove::<T>(path)?)
+        if let Some(access_recorder) = &self.access_recorder {
+            access_recorder.record_read(path);
+        }
+        self.log_access(access_log::Event::Prove(path));
+        self.store
+            .read_view(|| Ok(self.merkle_updater.prove::<T>(path)?))
+    }
+
+    /// Prove the subtree of all keys beginning with `prefix` against the root this session is
+    /// based on, listing their values if there are at most `max_leaves` of them.
+    ///
+    /// The values and the proof include the changes of the overlays the session is based on. To
+    /// prove the subtree at a [`TriePosition`], pass its [`path`](TriePosition::path).
+    ///
+    /// Fails only if I/O fails or the prefix is longer than a key path.
+    pub fn prove_prefix(
+        &self,
+        prefix: &BitSlice<u8, Msb0>,
+        max_leaves: usize,
+    ) -> anyhow::Result<ProvenPrefix> {
+        if prefix.len() > 256 {
+            anyhow::bail!("prove_prefix: prefix of {} bits is too long", prefix.len());
+        }
+
+        // The first key with the prefix, and the first key after all of those with it.
+        let mut start = KeyPath::default();
+        start.view_bits_mut::<Msb0>()[..prefix.len()].copy_from_bitslice(prefix);
+        let end = prefix.iter().by_vals().rposition(|bit| !bit).map(|i| {
+            let mut end = KeyPath::default();
+            let bits = end.view_bits_mut::<Msb0>();
+            bits[..i].copy_from_bitslice(&prefix[..i]);
+            bits.set(i, true);
+            end
+        });
+
+        let path_proof = self.prove(start)?;
+        let depth = path_proof.siblings.len();
+        let node = if depth <= prefix.len() {
+            PrefixNode::Terminal(path_proof.terminal)
+        } else {
+            // The path continues below the prefix, so the node at the prefix is internal.
+            let terminal = path_proof.terminal.node::<T>();
+            let path = &start.view_bits::<Msb0>()[prefix.len()..depth];
+            let below = path_proof.siblings[prefix.len()..].iter().rev().cloned();
+            PrefixNode::Internal(proof::hash_path::<T>(terminal, path, below))
+        };
+        let mut siblings = path_proof.siblings;
+        siblings.truncate(prefix.len());
+
+        let mut values = Vec::new();
+        let mut range = self.iter_range((
+            Bound::Included(start),
+            end.map_or(Bound::Unbounded, Bound::Excluded),
+        ));
+        while let Some(item) = range.next_local()? {
+            values.push(item);
+            if values.len() > max_leaves {
+                break;
+            }
+        }
+        let values = (values.len() <= max_leaves).then_some(values);
+        let leaves = values.as_ref().map(|values| {
+            values
+                .iter()
+                .map(|(key_path, value)| LeafData {
+                    key_path: *key_path,
+                    value_hash: value.value_hash::<T>(),
+                })
+                .collect()
+        });
+
+        Ok(ProvenPrefix {
+            root: self.prev_root,
+            values,
+            proof: PrefixProof {
+                node,
+                siblings,
+                leaves,
+            },
+        })
+    }
+
+    /// Get the root of a namespace as of the root this session is based on. See the [`namespace`]
+    /// module.
+    ///
+    /// Fails only if I/O fails.
+    pub fn namespace_root(&self, namespace: Namespace) -> anyhow::Result<Node> {
+        let proven = self.prove_prefix(&namespace.prefix(), 0)?;
+        Ok(namespace.root_from_proof::<T>(&proven.proof))
+    }
+
+    /// Get a single merkle proof for all of the given key paths.
+    ///
+    /// This proves each key like [`Session::prove`] and combines the proofs into a
+    /// [`MultiProof`], in which keys sharing a terminal node share a path. Verify it with
+    /// [`proof::verify_multi_proof`] and check each key with
+    /// [`VerifiedMultiProof::confirm_value`](proof::VerifiedMultiProof::confirm_value) or
+    /// [`VerifiedMultiProof::confirm_nonexistence`](proof::VerifiedMultiProof::confirm_nonexistence).
+    ///
+    /// Fails only if I/O fails.
+    pub fn prove_multi(&self, paths: &[KeyPath]) -> anyhow::Result<MultiProof> {
+        let mut paths = paths.to_vec();
+        paths.sort_unstable();
+        paths.dedup();
+        let mut path_proofs = paths
+            .into_iter()
+            .map(|path| self.prove(path))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // Keys in ascending order have terminals in ascending order, but neighbouring keys may
+        // end at the same terminal.
+        path_proofs.dedup_by(|a, b| a.terminal.path() == b.terminal.path());
+        Ok(MultiProof::from_path_proofs(path_proofs))
+    }
+
+    // The values of the accessed keys before the session, sorted by key. Written keys which were
+    // not read are loaded, unless their values are stored elsewhere.
+    fn prior_values(
+        &self,
+        actuals: &[(KeyPath, KeyReadWrite)],
+        hash_writes: &[(KeyPath, ValueHash)],
+    ) -> anyhow::Result<Vec<(KeyPath, Option<Value>)>> {
+        let mut prior_values = Vec::with_capacity(actuals.len() + hash_writes.len());
+        let written = actuals
+            .iter()
+            .filter_map(|(path, read_write)| match read_write {
+                KeyReadWrite::Read(value) | KeyReadWrite::ReadThenWrite(value, _) => {
+                    prior_values.push((*path, value.clone()));
+                    None
+                }
+                KeyReadWrite::Write(_) => Some(*path),
+            })
+            .collect::<Vec<_>>();
+        for path in written
+            .into_iter()
+            .chain(hash_writes.iter().map(|(path, _)| *path))
+        {
+            match self.load_local(path)? {
+                None => prior_values.push((path, None)),
+                Some(LocalValue::Stored(value)) => prior_values.push((path, Some(value))),
+                Some(LocalValue::HashOnly(_)) => {}
+            }
+        }
+        prior_values.sort_by_key(|(path, _)| *path);
+        prior_values.dedup_by_key(|(path, _)| *path);
+        Ok(prior_values)
     }
 
     /// Finish the session. Provide the actual reads and writes (in sorted order) that are to be
     /// considered within the finished session.
     ///
     /// This function blocks until the merkle root and changeset are computed.
-    pub fn finish(
+    ///
+    /// Fails if the system keyspace is reserved (see [`Options::reserve_system_keyspace`]) and
+    /// any of the keys in the system keyspace is written, or with [`PreconditionsViolated`] if any
+    /// precondition attached with [`Session::require`] does not hold. A key may be listed more
+    /// than once; see [`Options::duplicate_write_policy`] for how such entries are handled.
+    pub fn finish(self, actuals: Vec<(KeyPath, KeyReadWrite)>) -> anyhow::Result<FinishedSession> {
+        let trace = self.trace.child("nomt.finish");
+        let store = self.store.clone();
+        let result = store.read_view(|| self.finish_with_system_values(actuals, Vec::new()));
+        trace.record_result(&result);
+        result
+    }
+
+    // Finish the session, additionally writing the given system values if the system keyspace
+    // is reserved.
+    pub(crate) fn finish_with_system_values(
         mut self,
-        actuals: Vec<(KeyPath, KeyReadWrite)>,
+        mut actuals: Vec<(KeyPath, KeyReadWrite)>,
+        mut system_values: Vec<(system_keys::SystemKey, Value)>,
     ) -> anyhow::Result<FinishedSession> {
         if cfg!(debug_assertions) {
             // Check that the actuals are sorted by key path.
             for i in 1..actuals.len() {
                 assert!(
-                    actuals[i].0 > actuals[i - 1].0,
+                    actuals[i].0 >= actuals[i - 1].0,
                     "actuals are not sorted at index {}",
                     i
                 );
             }
         }
+        let duplicate_writes = merge_duplicate_actuals(&mut actuals);
+        self.metrics
+            .count_n(Metric::DuplicateWrites, duplicate_writes.len() as u64);
+        if !duplicate_writes.is_empty()
+            && self.duplicate_write_policy == DuplicateWritePolicy::Reject
+        {
+            return Err(DuplicateWrites {
+                keys: duplicate_writes,
+            }
+            .into());
+        }
+        self.check_preconditions()?;
+        let mut hash_writes = mem::take(&mut self.hash_writes);
+        if !hash_writes.is_empty() && self.rollback_delta.is_some() {
+            anyhow::bail!("values stored elsewhere can't be written with rollback enabled");
+        }
+        // The last write to a key wins: reversed, the stable sort puts it first.
+        hash_writes.reverse();
+        hash_writes.sort_by_key(|(path, _)| *path);
+        hash_writes.dedup_by_key(|(path, _)| *path);
+        if let Some(namespace) = self.namespace {
+            if let Some(key) = actuals
+                .iter()
+                .map(|(key, _)| key)
+                .chain(hash_writes.iter().map(|(key, _)| key))
+                .find(|key| !namespace.contains(key))
+            {
+                return Err(namespace::KeyOutsideNamespace {
+                    namespace,
+                    key: *key,
+                }
+                .into());
+            }
+        }
+        if self.reserve_system_keyspace {
+            if let Some(key) = actuals
+                .iter()
+                .filter(|(_, read_write)| read_write.is_write())
+                .map(|(key, _)| key)
+                .chain(hash_writes.iter().map(|(key, _)| key))
+                .find(|key| system_keys::is_system_key(key))
+            {
+                anyhow::bail!(
+                    "session writes key {} in the reserved system keyspace",
+                    key.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+                );
+            }
+            let format_version = system_keys::SystemKey::FormatVersion;
+            if self.load_value(format_version.key_path())?.is_none() {
+                system_values.push((
+                    format_version,
+                    system_keys::FORMAT_VERSION.to_le_bytes().to_vec(),
+                ));
+            }
+            system_keys::add_system_writes(&mut actuals, system_values);
+        }
+
         let rollback_delta = self
             .rollback_delta
             .take()
             .map(|delta_builder| delta_builder.finalize(&actuals));
 
+        let prior_values = if self.witness_mode.enabled && self.witness_mode.values {
+            self.prior_values(&actuals, &hash_writes)?
+        } else {
+            Vec::new()
+        };
+
         let mut compact_actuals = Vec::with_capacity(actuals.len());
         for (path, read_write) in &actuals {
             compact_actuals.push((path.clone(), read_write.to_compact::<T>()));
         }
+        let compact_actuals = merge_hash_writes(compact_actuals, &hash_writes)?;
+
+        let merkle_update_handle = self.merkle_updater.update_and_prove::<T>(
+            compact_actuals,
+            self.witness_mode.enabled,
+            self.access_recorder
+                .as_ref()
+                .is_some_and(|recorder| recorder.trace_pages()),
+            self.max_trie_depth.map(|(max_depth, _)| max_depth),
+        )?;
+
+        let mut access_list = self
+            .access_recorder
+            .take()
+            .map(|recorder| recorder.finish(&actuals));
+        if let Some((access_log, session)) = &self.access_log {
+            access_log.record_writes(*session, &actuals);
+        }
 
-        let merkle_update_handle = self
-            .merkle_updater
-            .update_and_prove::<T>(compact_actuals, self.witness_mode.0)?;
+        // The last hint for a key wins, and only hints for written keys are kept.
+        let mut storage_hints = mem::take(&mut self.storage_hints);
+        storage_hints.reverse();
+        storage_hints.sort_by_key(|(path, _)| *path);
+        storage_hints.dedup_by_key(|(path, _)| *path);
+        storage_hints.retain(|(path, _)| {
+            actuals
+                .binary_search_by_key(path, |(key, _)| *key)
+                .is_ok_and(|i| actuals[i].1.is_write())
+                || hash_writes
+                    .binary_search_by_key(path, |(key, _)| *key)
+                    .is_ok()
+        });
 
         let mut tx = self.store.new_value_tx();
         for (path, read_write) in actuals {
@@ -600,14 +2624,82 @@ impl<T: HashAlgorithm> Session<T> {
                 tx.write_value::<T>(path, value);
             }
         }
+        for (path, value_hash) in hash_writes {
+            tx.write_value_hash(path, value_hash);
+        }
+
+        let mut merkle_output = merkle_update_handle
+            .join()
+            .map_err(yielding::io_error_into_anyhow)?;
+        if let Some(access_list) = access_list.as_mut() {
+            access_list.pages = merkle_output.traced_pages.take();
+        }
+
+        let deep_leaves = merkle_output.deep_leaves;
+        self.metrics
+            .count_n(Metric::DeepPaths, deep_leaves.count as u64);
+        if let (Some((max_depth, DeepPathPolicy::Reject)), Some((key, depth))) =
+            (self.max_trie_depth, deep_leaves.deepest)
+        {
+            return Err(TrieDepthExceeded {
+                key,
+                depth,
+                max_depth,
+            }
+            .into());
+        }
+
+        let pages_touched = merkle_output
+            .witness
+            .as_ref()
+            .map(|witness| witness_stats::pages_touched(&witness.path_proofs));
+        if let Some(witness) = merkle_output.witness.as_mut() {
+            if !self.witness_mode.excluded.is_empty() {
+                witness
+                    .exclude_subtrees::<T>(&self.witness_mode.excluded)
+                    .map_err(|e| anyhow::anyhow!("failed to exclude subtrees: {:?}", e))?;
+            }
+            if let Some(retained) = &self.witness_mode.retained {
+                witness
+                    .retain_keys::<T>(|key| retained(key))
+                    .map_err(|e| anyhow::anyhow!("failed to filter witnessed keys: {:?}", e))?;
+            }
+            if let Some(context) = &self.witness_mode.context {
+                witness.bind(
+                    self.prev_root.into_inner(),
+                    merkle_output.root,
+                    context.clone(),
+                );
+            }
+            if self.witness_mode.values {
+                embed_witness_values(witness, prior_values);
+            }
+        }
+        let witness_stats =
+            merkle_output
+                .witness
+                .as_ref()
+                .zip(pages_touched)
+                .map(|(witness, pages_touched)| {
+                    WitnessStats::new(witness, merkle_output.pages_fetched, pages_touched)
+                });
 
-        let merkle_output = merkle_update_handle.join()?;
         Ok(FinishedSession {
             value_transaction: tx,
             merkle_output,
             rollback_delta,
             parent_overlay: self.overlay,
+            overlay_spill: self.overlay_spill.clone(),
+            trace: self.trace.clone(),
             prev_root: self.prev_root,
+            rollback_epoch: self.rollback_epoch,
+            ht_generation: self.ht_generation,
+            access_list,
+            witness_stats,
+            duplicate_writes,
+            storage_hints,
+            access_log: self.access_log.take(),
+            read_through_used: self.read_through_used.load(Ordering::Relaxed),
             take_global_guard: self.access_guard.is_some(),
         })
     }
@@ -625,7 +2717,17 @@ pub struct FinishedSession {
     merkle_output: merkle::Output,
     rollback_delta: Option<rollback::Delta>,
     parent_overlay: LiveOverlay,
+    overlay_spill: Option<Arc<OverlaySpill>>,
+    trace: Trace,
     prev_root: Root,
+    rollback_epoch: u64,
+    ht_generation: u64,
+    access_list: Option<AccessList>,
+    witness_stats: Option<WitnessStats>,
+    duplicate_writes: Vec<KeyPath>,
+    storage_hints: Vec<(KeyPath, StorageClass)>,
+    access_log: Option<(Arc<AccessLog>, u64)>,
+    read_through_used: bool,
     // INTERNAL: whether to take a write guard while committing. always true except during rollback.
     take_global_guard: bool,
 }
@@ -649,6 +2751,30 @@ impl FinishedSession {
         self.merkle_output.witness.take()
     }
 
+    /// Get the accounting of the witness: the pages the update fetched and touched, and the
+    /// siblings and bytes of the witness.
+    ///
+    /// `Some` if this session was configured with proving (see [`SessionParams::witness_mode`]),
+    /// even after the witness is taken. The witness is accounted for after excluding subtrees,
+    /// retaining keys, binding and embedding values.
+    pub fn witness_stats(&self) -> Option<WitnessStats> {
+        self.witness_stats
+    }
+
+    /// Take the access list, if any.
+    ///
+    /// If this session was configured with access tracing (see [`SessionParams::access_trace`]),
+    /// this will be `Some` on the first call and `None` thereafter.
+    pub fn take_access_list(&mut self) -> Option<AccessList> {
+        self.access_list.take()
+    }
+
+    /// The keys which the actuals wrote more than once, in ascending order. Only the last write
+    /// of each was kept. See [`Options::duplicate_write_policy`].
+    pub fn duplicate_writes(&self) -> &[KeyPath] {
+        &self.duplicate_writes
+    }
+
     /// Transform this into an overlay that can be queried in memory and used as the base for
     /// further in-memory [`Session`]s.
     pub fn into_overlay(self) -> Overlay {
@@ -659,34 +2785,103 @@ impl FinishedSession {
             .collect();
         let values = self.value_transaction.into_iter().collect();
 
-        self.parent_overlay.finish(
+        let mut overlay = self.parent_overlay.finish(
             self.prev_root.into_inner(),
             self.merkle_output.root,
             updated_pages,
             values,
             self.rollback_delta,
-        )
+            self.ht_generation,
+        );
+        if self.read_through_used {
+            overlay.mark_read_through();
+        }
+        overlay.set_storage_hints(self.storage_hints);
+        if let Some(spill) = &self.overlay_spill {
+            spill.admit(&overlay);
+        }
+        overlay
     }
 
     /// Commit this session to disk directly.
     ///
-    /// This function will block until all ongoing sessions and commits have finished.
+    /// This function will block until all ongoing sessions and commits have finished. Fails with
+    /// [`SessionMisuse::LiveSessionOnThread`] instead if the calling thread began another session
+    /// which is still live.
     ///
-    /// This will return an error if I/O fails or if the changeset is no longer valid.
-    /// The changeset may be invalidated if another competing session, overlay, or rollback was
-    /// committed.
-    pub fn commit<T: HashAlgorithm>(self, nomt: &Nomt<T>) -> Result<(), anyhow::Error> {
-        let _write_guard = self.take_global_guard.then(|| nomt.access_lock.write());
+    /// This will return an error if I/O fails or if the changeset is no longer valid, with
+    /// [`SessionMisuse::StaleChangeset`] if another competing session or overlay was committed
+    /// and [`SessionMisuse::RolledBack`] if a rollback was.
+    pub fn commit<T: HashAlgorithm>(self, nomt: &Nomt<T>) -> Result<(), Error> {
+        // Keep the session span open until the commit is done.
+        let session_trace = self.trace.clone();
+        let trace = session_trace.child("nomt.commit");
+        let _attached = trace.attach();
+        let result = self.commit_inner(nomt, None);
+        trace.record_result(&result);
+        if result.is_ok() {
+            nomt.auto_resize_hash_table();
+        }
+        result
+    }
+
+    /// Commit this session to disk directly, aiming to complete by `deadline`.
+    ///
+    /// This behaves like [`FinishedSession::commit`], but gives the commit priority over other
+    /// work: background writes it waits for are not delayed by the write throttle (see
+    /// [`Options::write_throttle_target`]) and the hot prefix statistics skip it (see
+    /// [`Nomt::hot_prefixes`]). The commit is carried out even if the deadline passes in the
+    /// meantime, as abandoning it midway would leave nothing to show for the time spent.
+    ///
+    /// Once the changes are synced, the work which can wait is skipped if less time is left until
+    /// the deadline than the commit has taken so far: [`Nomt::commit_stats`] is cleared rather
+    /// than recorded, the leaves of keys hinted cold (see [`Session::hint_storage_class`]) stay
+    /// cached and no automatic resize of the hash table (see [`Options::hashtable_auto_resize`])
+    /// is started until a later commit. See [`CommitReport::skipped_optional_work`].
+    ///
+    /// The witness is built by [`Session::finish`] and so doesn't weigh on the commit. Sessions
+    /// which must also meet the deadline should not record one.
+    ///
+    /// Returns whether the deadline was met along with where the time went.
+    pub fn commit_by<T: HashAlgorithm>(
+        self,
+        nomt: &Nomt<T>,
+        deadline: Instant,
+    ) -> Result<CommitReport, Error> {
+        let session_trace = self.trace.clone();
+        let trace = session_trace.child("nomt.commit");
+        let _attached = trace.attach();
+        let mut clock = CommitClock::start(deadline);
+        let result = self.commit_inner(nomt, Some(&mut clock));
+        trace.record_result(&result);
+        if result.is_ok() && !clock.skipped_optional_work() {
+            nomt.auto_resize_hash_table();
+        }
+        result.map(|()| clock.finish())
+    }
+
+    fn commit_inner<T: HashAlgorithm>(
+        self,
+        nomt: &Nomt<T>,
+        mut clock: Option<&mut CommitClock>,
+    ) -> Result<(), Error> {
+        if self.read_through_used {
+            return Err(SessionMisuse::ReadThrough.into());
+        }
+        // A rollback committing its session holds the gate already.
+        let _gate = self.take_global_guard.then(|| nomt.store.write_gate());
+        let _write_guard = match self.take_global_guard {
+            true => Some(nomt.write_access()?),
+            false => None,
+        };
+        nomt.store.ensure_writable()?;
+        if let Some(clock) = clock.as_deref_mut() {
+            clock.waited();
+        }
 
         {
             let mut shared = nomt.shared.lock();
-            if shared.root != self.prev_root {
-                anyhow::bail!(
-                    "Changeset no longer valid (expected previous root {:?}, got {:?})",
-                    self.prev_root,
-                    shared.root
-                );
-            }
+            self.check_base(&shared)?;
             shared.root = Root(self.merkle_output.root);
             shared.last_commit_marker = None;
         }
@@ -694,7 +2889,10 @@ impl FinishedSession {
         if let Some(rollback_delta) = self.rollback_delta {
             // UNWRAP: if rollback_delta is `Some`, then rollback must be also `Some`.
             let rollback = nomt.store.rollback().unwrap();
-            rollback.commit(rollback_delta)?;
+            rollback.commit(rollback_delta).map_err(Error::rollback)?;
+        }
+        if let Some(clock) = clock.as_deref_mut() {
+            clock.rolled_back();
         }
 
         nomt.store.commit(
@@ -703,20 +2901,44 @@ impl FinishedSession {
             self.merkle_output
                 .updated_pages
                 .into_frozen_iter(/* into_overlay */ false),
-        )
+            self.ht_generation,
+            clock.is_some(),
+        )?;
+        let skip_optional_work = match clock {
+            Some(clock) => {
+                clock.synced(nomt.store.last_sync_timings());
+                clock.skip_optional_work()
+            }
+            None => false,
+        };
+        if skip_optional_work {
+            nomt.shared.lock().last_commit = None;
+        } else {
+            nomt.apply_storage_hints(&self.storage_hints);
+            nomt.record_commit_stats()?;
+        }
+        if let Some((access_log, session)) = self.access_log {
+            access_log.record(Some(session), access_log::Event::Commit);
+        }
+        Ok(())
     }
 
     /// Commit this session to disk directly without blocking.
     ///
     /// This function will return `Ok(Some(Self))` if there are any ongoing sessions or commits.
     ///
-    /// This will return an error if I/O fails or if the changeset is no longer valid.
-    /// The changeset may be invalidated if another competing session, overlay, or rollback was
-    /// committed.
+    /// This will return an error if I/O fails or if the changeset is no longer valid, as with
+    /// [`FinishedSession::commit`].
     pub fn try_commit_nonblocking<T: HashAlgorithm>(
         mut self,
         nomt: &Nomt<T>,
-    ) -> Result<Option<Self>, anyhow::Error> {
+    ) -> Result<Option<Self>, Error> {
+        if self.read_through_used {
+            return Err(SessionMisuse::ReadThrough.into());
+        }
+        let Some(gate) = nomt.store.try_write_gate() else {
+            return Ok(Some(self));
+        };
         let write_guard = self
             .take_global_guard
             .then(|| nomt.access_lock.try_write())
@@ -724,11 +2946,18 @@ impl FinishedSession {
         if write_guard.is_none() {
             return Ok(Some(self));
         }
+        nomt.store.ensure_writable()?;
 
-        if let Some(rollback_delta) = self.rollback_delta {
+        // Check before logging the rollback delta, so that a refused changeset isn't logged.
+        self.check_base(&nomt.shared.lock())?;
+
+        if let Some(rollback_delta) = self.rollback_delta.take() {
             // UNWRAP: if rollback_delta is `Some`, then rollback must be also `Some`.
             let rollback = nomt.store.rollback().unwrap();
-            if let Some(delta) = rollback.commit_nonblocking(rollback_delta)? {
+            if let Some(delta) = rollback
+                .commit_nonblocking(rollback_delta)
+                .map_err(Error::rollback)?
+            {
                 self.rollback_delta = Some(delta);
                 return Ok(Some(self));
             }
@@ -736,13 +2965,6 @@ impl FinishedSession {
 
         {
             let mut shared = nomt.shared.lock();
-            if shared.root != self.prev_root {
-                anyhow::bail!(
-                    "Changeset no longer valid (expected previous root {:?}, got {:?})",
-                    self.prev_root,
-                    shared.root
-                );
-            }
             shared.root = Root(self.merkle_output.root);
             shared.last_commit_marker = None;
         }
@@ -753,23 +2975,68 @@ impl FinishedSession {
             self.merkle_output
                 .updated_pages
                 .into_frozen_iter(/* into_overlay */ false),
+            self.ht_generation,
+            false,
         )?;
+        nomt.apply_storage_hints(&self.storage_hints);
+        nomt.record_commit_stats()?;
+        if let Some((access_log, session)) = self.access_log {
+            access_log.record(Some(session), access_log::Event::Commit);
+        }
 
+        // Starting a resize takes the gate again.
+        drop(write_guard);
+        drop(gate);
+        nomt.auto_resize_hash_table();
         Ok(None)
     }
+
+    // Check that the changeset builds on the current root.
+    fn check_base(&self, shared: &Shared) -> Result<(), SessionMisuse> {
+        if shared.root == self.prev_root {
+            Ok(())
+        } else if shared.rollbacks != self.rollback_epoch {
+            Err(SessionMisuse::RolledBack {
+                expected: self.prev_root,
+                actual: shared.root,
+            })
+        } else {
+            Err(SessionMisuse::StaleChangeset {
+                expected: self.prev_root,
+                actual: shared.root,
+            })
+        }
+    }
 }
 
 impl Overlay {
     /// Commit the changes from this overlay to the underlying database.
     ///
-    /// This function will block until all ongoing sessions and commits have finished.
+    /// This function will block until all ongoing sessions and commits have finished. Fails with
+    /// [`SessionMisuse::LiveSessionOnThread`] instead if the calling thread began a session which
+    /// is still live.
     ///
     /// This will return an error if I/O fails or if the changeset is no longer valid, or if the
     /// overlay has an uncommitted parent. An overlay may be invalidated by a competing commit or
-    /// rollback.
-    pub fn commit<T: HashAlgorithm>(self, nomt: &Nomt<T>) -> anyhow::Result<()> {
+    /// rollback, with [`SessionMisuse::StaleChangeset`]. A refused overlay isn't marked committed,
+    /// so overlays built on top of it can't be committed either.
+    pub fn commit<T: HashAlgorithm>(self, nomt: &Nomt<T>) -> Result<(), Error> {
+        let trace = Trace::current("nomt.commit");
+        let _attached = trace.attach();
+        let result = self.commit_inner(nomt);
+        trace.record_result(&result);
+        if result.is_ok() {
+            nomt.auto_resize_hash_table();
+        }
+        result
+    }
+
+    fn commit_inner<T: HashAlgorithm>(self, nomt: &Nomt<T>) -> Result<(), Error> {
+        if self.used_read_through() {
+            return Err(SessionMisuse::ReadThrough.into());
+        }
         if !self.parent_matches_marker(nomt.shared.lock().last_commit_marker.as_ref()) {
-            anyhow::bail!("Overlay parent not committed");
+            return Err(SessionMisuse::OverlayParentNotCommitted.into());
         }
 
         let root = self.root();
@@ -778,38 +3045,42 @@ impl Overlay {
             .into_iter()
             .map(|(page_id, dirty_page)| (page_id.clone(), dirty_page.clone()))
             .collect();
-        let values: Vec<_> = self
-            .value_changes()
-            .iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect();
+        let values = self.value_changes();
         let rollback_delta = self.rollback_delta().map(|delta| delta.clone());
 
-        let _write_guard = nomt.access_lock.write();
-
-        let marker = self.mark_committed();
+        let _gate = nomt.store.write_gate();
+        let _write_guard = nomt.write_access()?;
+        nomt.store.ensure_writable()?;
 
         {
             let mut shared = nomt.shared.lock();
             if shared.root != self.prev_root() {
-                anyhow::bail!(
-                    "Changeset no longer valid (expected previous root {:?}, got {:?})",
-                    self.prev_root(),
-                    shared.root
-                );
+                return Err(SessionMisuse::StaleChangeset {
+                    expected: self.prev_root(),
+                    actual: shared.root,
+                }
+                .into());
             }
             shared.root = root;
-            shared.last_commit_marker = Some(marker);
+            shared.last_commit_marker = Some(self.mark_committed());
         }
 
         if let Some(rollback_delta) = rollback_delta {
             // UNWRAP: if rollback_delta is `Some`, then rollback must be also `Some`.
             let rollback = nomt.store.rollback().unwrap();
-            rollback.commit(rollback_delta)?;
+            rollback.commit(rollback_delta).map_err(Error::rollback)?;
         }
 
-        nomt.store
-            .commit(values, nomt.page_cache.clone(), page_changes)
+        nomt.store.commit(
+            values,
+            nomt.page_cache.clone(),
+            page_changes,
+            self.ht_generation(),
+            false,
+        )?;
+        nomt.apply_storage_hints(self.storage_hints());
+        nomt.record_commit_stats()?;
+        Ok(())
     }
 
     /// Commit the changes from this overlay to the underlying database without blocking.
@@ -822,9 +3093,12 @@ impl Overlay {
     pub fn try_commit_nonblocking<T: HashAlgorithm>(
         self,
         nomt: &Nomt<T>,
-    ) -> anyhow::Result<Option<Self>> {
+    ) -> Result<Option<Self>, Error> {
+        if self.used_read_through() {
+            return Err(SessionMisuse::ReadThrough.into());
+        }
         if !self.parent_matches_marker(nomt.shared.lock().last_commit_marker.as_ref()) {
-            anyhow::bail!("Overlay parent not committed");
+            return Err(SessionMisuse::OverlayParentNotCommitted.into());
         }
 
         let root = self.root();
@@ -833,42 +3107,51 @@ impl Overlay {
             .into_iter()
             .map(|(page_id, dirty_page)| (page_id.clone(), dirty_page.clone()))
             .collect();
-        let values: Vec<_> = self
-            .value_changes()
-            .iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect();
+        let values = self.value_changes();
         let rollback_delta = self.rollback_delta().map(|delta| delta.clone());
 
+        let Some(gate) = nomt.store.try_write_gate() else {
+            return Ok(Some(self));
+        };
         let write_guard = nomt.access_lock.try_write();
         if write_guard.is_none() {
             return Ok(Some(self));
         }
-
-        let marker = self.mark_committed();
+        nomt.store.ensure_writable()?;
 
         {
             let mut shared = nomt.shared.lock();
             if shared.root != self.prev_root() {
-                anyhow::bail!(
-                    "Changeset no longer valid (expected previous root {:?}, got {:?})",
-                    self.prev_root(),
-                    shared.root
-                );
+                return Err(SessionMisuse::StaleChangeset {
+                    expected: self.prev_root(),
+                    actual: shared.root,
+                }
+                .into());
             }
             shared.root = root;
-            shared.last_commit_marker = Some(marker);
+            shared.last_commit_marker = Some(self.mark_committed());
         }
 
         if let Some(rollback_delta) = rollback_delta {
             // UNWRAP: if rollback_delta is `Some`, then rollback must be also `Some`.
             let rollback = nomt.store.rollback().unwrap();
-            rollback.commit(rollback_delta)?;
+            rollback.commit(rollback_delta).map_err(Error::rollback)?;
         }
 
-        nomt.store
-            .commit(values, nomt.page_cache.clone(), page_changes)?;
+        nomt.store.commit(
+            values,
+            nomt.page_cache.clone(),
+            page_changes,
+            self.ht_generation(),
+            false,
+        )?;
+        nomt.apply_storage_hints(self.storage_hints());
+        nomt.record_commit_stats()?;
 
+        // Starting a resize takes the gate again.
+        drop(write_guard);
+        drop(gate);
+        nomt.auto_resize_hash_table();
         Ok(None)
     }
 }
@@ -946,7 +3229,8 @@ fn compute_root_node<H: HashAlgorithm>(page_cache: &PageCache, store: &Store) ->
 
 /// Check whether the current device has permission to use io_uring.
 ///
-/// On non-Linux platforms, this will always return `NotSupported`.
+/// On non-Linux platforms, or without the `io-uring` feature, this will always return
+/// `NotSupported`.
 pub fn check_iou_permissions() -> IoUringPermission {
     crate::io::check_iou_permissions()
 }
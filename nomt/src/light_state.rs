@@ -0,0 +1,455 @@
+//! Light state bundles: a verifiable snapshot of a subset of the state.
+//!
+//! A light state holds the root, the values of a chosen set of keys and a single multi-proof of
+//! all of them against the root. It is meant as a boot package for light clients, which can
+//! answer queries for the keys in the bundle right away, once the bundle is verified against a
+//! root obtained from a trusted source. Keys without a value are included as well, with a proof
+//! of their absence.
+//!
+//! Light states are created with [`Nomt::export_light_state`](crate::Nomt::export_light_state),
+//! commonly for the keys given by [`Nomt::hot_keys`](crate::Nomt::hot_keys).
+//!
+//! The encoding is canonical: a light state has exactly one encoding.
+//!
+//! ```text
+//! header:  magic "NOMTLITE" | version: u8 | root: [u8; 32] | entry count: u32
+//! entry:   key: [u8; 32] | value: varint (0 = no value, n + 1 = value of n bytes) | value bytes
+//! proof:   path count: u32 | paths | sibling count: u32 | siblings: [u8; 32] each
+//! path:    depth: u16 | 0 | key path: [u8; 32] | value hash: [u8; 32]          (leaf)
+//!          depth: u16 | 1 | position: [u8; ceil(depth / 8)], zero-padded       (terminator)
+//! trailer: crc32 of everything before the crc: u32
+//! ```
+//!
+//! All integers are little-endian and varints are LEB128. Entries are sorted by key, without
+//! duplicates, and the paths of the proof are sorted as required by [`proof::verify_multi_proof`].
+
+use crate::{HashAlgorithm, Root, Value};
+use bitvec::prelude::*;
+use nomt_core::{
+    proof::{
+        self, KeyOutOfScope, MultiPathProof, MultiProof, MultiProofVerificationError,
+        PathProofTerminal,
+    },
+    trie::{KeyPath, LeafData},
+    trie_pos::TriePosition,
+};
+use std::io::{self, Read, Write};
+
+const MAGIC: [u8; 8] = *b"NOMTLITE";
+const VERSION: u8 = 1;
+const LEAF: u8 = 0;
+const TERMINATOR: u8 = 1;
+
+/// The values of a set of keys along with a proof of them against a root.
+///
+/// Produced by [`Nomt::export_light_state`](crate::Nomt::export_light_state).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LightState {
+    /// The root the values are proven against.
+    pub root: Root,
+    /// The keys and their values in ascending key order. `None` if the key has no value.
+    pub entries: Vec<(KeyPath, Option<Value>)>,
+    /// A proof of all entries against the root.
+    pub proof: MultiProof,
+}
+
+/// The reason a light state failed verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightStateVerificationError {
+    /// The light state is of a different root than expected.
+    RootMismatch,
+    /// The proof is not a valid proof against the root.
+    InvalidProof(MultiProofVerificationError),
+    /// The proof does not cover the key.
+    KeyNotProven(KeyPath),
+    /// The value of the key does not match the proof.
+    ValueMismatch(KeyPath),
+}
+
+impl std::fmt::Display for LightStateVerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RootMismatch => write!(f, "light state is of an unexpected root"),
+            Self::InvalidProof(e) => write!(f, "light state proof is invalid: {:?}", e),
+            Self::KeyNotProven(key) => write!(f, "key {:?} is not proven", key),
+            Self::ValueMismatch(key) => write!(f, "value of key {:?} does not match", key),
+        }
+    }
+}
+
+impl std::error::Error for LightStateVerificationError {}
+
+impl LightState {
+    /// Verify every entry against `expected_root`, which must come from a source trusted
+    /// independently of the light state.
+    pub fn verify<H: HashAlgorithm>(
+        &self,
+        expected_root: Root,
+    ) -> Result<(), LightStateVerificationError> {
+        if self.root != expected_root {
+            return Err(LightStateVerificationError::RootMismatch);
+        }
+        let verified = proof::verify_multi_proof::<H>(&self.proof, self.root.into_inner())
+            .map_err(LightStateVerificationError::InvalidProof)?;
+
+        for (key, value) in &self.entries {
+            let matches = match value {
+                Some(value) => verified.confirm_value(&LeafData {
+                    key_path: *key,
+                    value_hash: H::hash_value(value),
+                }),
+                None => verified.confirm_nonexistence(key),
+            };
+            match matches {
+                Ok(true) => {}
+                Ok(false) => return Err(LightStateVerificationError::ValueMismatch(*key)),
+                Err(KeyOutOfScope) => return Err(LightStateVerificationError::KeyNotProven(*key)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Look up the value of a key. Returns `None` if the key is not in the light state, and
+    /// `Some(None)` if it is but has no value.
+    ///
+    /// This is only as trustworthy as the light state, so verify it first.
+    pub fn get(&self, key: &KeyPath) -> Option<Option<&[u8]>> {
+        self.entries
+            .binary_search_by_key(key, |(key, _)| *key)
+            .ok()
+            .map(|i| self.entries[i].1.as_deref())
+    }
+
+    /// Write the canonical encoding of the light state.
+    ///
+    /// Fails if the entries are not in strictly ascending key order or the proof cannot be
+    /// encoded.
+    pub fn encode(&self, inner: impl Write) -> io::Result<()> {
+        let mut writer = Writer {
+            inner,
+            crc: crc32fast::Hasher::new(),
+        };
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(self.root.as_ref())?;
+        writer.write_len(self.entries.len())?;
+
+        for (i, (key, value)) in self.entries.iter().enumerate() {
+            if i > 0 && *key <= self.entries[i - 1].0 {
+                return Err(invalid_input(
+                    "light state: keys must be in ascending order",
+                ));
+            }
+            writer.write_all(key)?;
+            match value {
+                None => writer.write_varint(0)?,
+                Some(value) => {
+                    writer.write_varint(value.len() as u64 + 1)?;
+                    writer.write_all(value)?;
+                }
+            }
+        }
+
+        writer.write_len(self.proof.paths.len())?;
+        for path in &self.proof.paths {
+            let depth = u16::try_from(path.depth)
+                .ok()
+                .filter(|depth| *depth <= 256)
+                .ok_or_else(|| invalid_input("light state: path too deep"))?;
+            writer.write_all(&depth.to_le_bytes())?;
+            match path.terminal {
+                PathProofTerminal::Leaf(ref leaf) => {
+                    writer.write_all(&[LEAF])?;
+                    writer.write_all(&leaf.key_path)?;
+                    writer.write_all(&leaf.value_hash)?;
+                }
+                PathProofTerminal::Terminator(ref position) => {
+                    if position.depth() != depth {
+                        return Err(invalid_input(
+                            "light state: terminator not at the depth of its path",
+                        ));
+                    }
+                    let mut bits = [0u8; 32];
+                    bits.view_bits_mut::<Msb0>()[..depth as usize]
+                        .copy_from_bitslice(position.path());
+                    writer.write_all(&[TERMINATOR])?;
+                    writer.write_all(&bits[..(depth as usize).div_ceil(8)])?;
+                }
+            }
+        }
+
+        writer.write_len(self.proof.siblings.len())?;
+        for sibling in &self.proof.siblings {
+            writer.write_all(sibling)?;
+        }
+
+        let crc = writer.crc.clone().finalize();
+        writer.inner.write_all(&crc.to_le_bytes())?;
+        writer.inner.flush()
+    }
+
+    /// Read a light state from its canonical encoding.
+    ///
+    /// This only checks the encoding. Use [`LightState::verify`] to check the contents.
+    pub fn decode(inner: impl Read) -> io::Result<Self> {
+        let mut reader = Reader {
+            inner,
+            crc: crc32fast::Hasher::new(),
+        };
+        if reader.read_array::<8>()? != MAGIC {
+            return Err(invalid_data("light state: bad magic"));
+        }
+        if reader.read_array::<1>()? != [VERSION] {
+            return Err(invalid_data("light state: unsupported version"));
+        }
+        let root = Root::from(reader.read_array::<32>()?);
+
+        let entry_count = reader.read_u32()?;
+        let mut entries: Vec<(KeyPath, Option<Value>)> = Vec::new();
+        for _ in 0..entry_count {
+            let key = reader.read_array::<32>()?;
+            if entries.last().is_some_and(|(prev, _)| key <= *prev) {
+                return Err(invalid_data("light state: keys out of order"));
+            }
+            let value = match reader.read_varint()? {
+                0 => None,
+                len => Some(reader.read_vec(len - 1)?),
+            };
+            entries.push((key, value));
+        }
+
+        let path_count = reader.read_u32()?;
+        let mut paths = Vec::new();
+        for _ in 0..path_count {
+            let depth = u16::from_le_bytes(reader.read_array::<2>()?);
+            if depth > 256 {
+                return Err(invalid_data("light state: path too deep"));
+            }
+            let terminal = match reader.read_array::<1>()? {
+                [LEAF] => PathProofTerminal::Leaf(LeafData {
+                    key_path: reader.read_array::<32>()?,
+                    value_hash: reader.read_array::<32>()?,
+                }),
+                [TERMINATOR] => {
+                    let mut bits = [0u8; 32];
+                    let len = (depth as usize).div_ceil(8);
+                    reader.read_exact(&mut bits[..len])?;
+                    if bits.view_bits::<Msb0>()[depth as usize..].any() {
+                        return Err(invalid_data("light state: bad terminator padding"));
+                    }
+                    PathProofTerminal::Terminator(if depth == 0 {
+                        TriePosition::new()
+                    } else {
+                        TriePosition::from_path_and_depth(bits, depth)
+                    })
+                }
+                _ => return Err(invalid_data("light state: bad path kind")),
+            };
+            paths.push(MultiPathProof {
+                terminal,
+                depth: depth as usize,
+            });
+        }
+
+        let sibling_count = reader.read_u32()?;
+        let mut siblings = Vec::new();
+        for _ in 0..sibling_count {
+            siblings.push(reader.read_array::<32>()?);
+        }
+
+        let expected_crc = reader.crc.clone().finalize();
+        let mut crc = [0; 4];
+        reader.inner.read_exact(&mut crc)?;
+        if u32::from_le_bytes(crc) != expected_crc {
+            return Err(invalid_data("light state: checksum mismatch"));
+        }
+
+        Ok(LightState {
+            root,
+            entries,
+            proof: MultiProof { paths, siblings },
+        })
+    }
+}
+
+struct Writer<W: Write> {
+    inner: W,
+    crc: crc32fast::Hasher,
+}
+
+impl<W: Write> Writer<W> {
+    fn write_len(&mut self, len: usize) -> io::Result<()> {
+        let len = u32::try_from(len).map_err(|_| invalid_input("light state: too large"))?;
+        self.write_all(&len.to_le_bytes())
+    }
+
+    fn write_varint(&mut self, mut n: u64) -> io::Result<()> {
+        loop {
+            let byte = (n & 0x7F) as u8;
+            n >>= 7;
+            if n == 0 {
+                return self.write_all(&[byte]);
+            }
+            self.write_all(&[byte | 0x80])?;
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.crc.update(buf);
+        self.inner.write_all(buf)
+    }
+}
+
+struct Reader<R: Read> {
+    inner: R,
+    crc: crc32fast::Hasher,
+}
+
+impl<R: Read> Reader<R> {
+    fn read_u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.read_array::<4>()?))
+    }
+
+    fn read_varint(&mut self) -> io::Result<u64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let [byte] = self.read_array::<1>()?;
+            n |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                // A trailing zero byte would make for a second encoding of the same number.
+                if byte == 0 && shift > 0 {
+                    break;
+                }
+                return Ok(n);
+            }
+        }
+        Err(invalid_data("light state: bad varint"))
+    }
+
+    fn read_vec(&mut self, len: u64) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        (&mut self.inner).take(len).read_to_end(&mut buf)?;
+        if buf.len() as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.crc.update(&buf);
+        Ok(buf)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(buf)?;
+        self.crc.update(buf);
+        Ok(())
+    }
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn invalid_input(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LightState;
+    use crate::Root;
+    use nomt_core::{
+        proof::{MultiPathProof, MultiProof, PathProofTerminal},
+        trie::LeafData,
+        trie_pos::TriePosition,
+    };
+
+    fn light_state() -> LightState {
+        let mut terminator = [0; 32];
+        terminator[0] = 0b1011_0000;
+        LightState {
+            root: Root::from([1; 32]),
+            entries: vec![
+                ([0; 32], Some(vec![])),
+                ([7; 32], None),
+                ([0xFF; 32], Some(vec![42; 300])),
+            ],
+            proof: MultiProof {
+                paths: vec![
+                    MultiPathProof {
+                        terminal: PathProofTerminal::Leaf(LeafData {
+                            key_path: [0; 32],
+                            value_hash: [2; 32],
+                        }),
+                        depth: 3,
+                    },
+                    MultiPathProof {
+                        terminal: PathProofTerminal::Terminator(TriePosition::from_path_and_depth(
+                            terminator, 5,
+                        )),
+                        depth: 5,
+                    },
+                ],
+                siblings: vec![[3; 32], [4; 32]],
+            },
+        }
+    }
+
+    fn encode(light_state: &LightState) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        light_state.encode(&mut encoded).unwrap();
+        encoded
+    }
+
+    #[test]
+    fn roundtrip() {
+        let encoded = encode(&light_state());
+        assert_eq!(LightState::decode(&encoded[..]).unwrap(), light_state());
+
+        // The header, three entries, the leaf and terminator paths, the siblings and the trailer.
+        assert_eq!(
+            encoded.len(),
+            45 + 33 + 33 + (32 + 2 + 300) + 4 + (3 + 64) + (3 + 1) + 4 + 64 + 4
+        );
+    }
+
+    #[test]
+    fn unordered_keys_rejected() {
+        let mut light_state = light_state();
+        light_state.entries.swap(0, 1);
+        assert!(light_state.encode(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn corruption_detected() {
+        let encoded = encode(&light_state());
+        for i in [20, encoded.len() / 2, encoded.len() - 1] {
+            let mut corrupted = encoded.clone();
+            corrupted[i] ^= 0x01;
+            assert!(LightState::decode(&corrupted[..]).is_err());
+        }
+        assert!(LightState::decode(&encoded[..encoded.len() - 3]).is_err());
+    }
+
+    #[test]
+    fn overlong_varint_rejected() {
+        let light_state = LightState {
+            root: Root::from([1; 32]),
+            entries: vec![([0; 32], None)],
+            proof: MultiProof {
+                paths: vec![],
+                siblings: vec![],
+            },
+        };
+        let encoded = encode(&light_state);
+        // Replace the value length of 0 with 0x80 0x00 and fix up the checksum.
+        let mut overlong = encoded[..45 + 32].to_vec();
+        overlong.extend_from_slice(&[0x80, 0x00]);
+        overlong.extend_from_slice(&encoded[45 + 33..encoded.len() - 4]);
+        overlong.extend_from_slice(&crc32fast::hash(&overlong).to_le_bytes());
+        assert!(LightState::decode(&overlong[..]).is_err());
+    }
+}
@@ -1,8 +1,24 @@
 //! Utility for prepopulating the first N layers of the cache.
+//!
+//! The page tree is walked breadth-first, one batch of sibling pages at a time. The access lock
+//! is held for reading while a batch is loaded, so that commits, which may replace or delete the
+//! pages on disk, never interleave with a batch. Between batches, the walk may be paused to stay
+//! within a rate limit, and checked for cancellation.
 
-use std::io;
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use parking_lot::{Condvar, Mutex, RwLock};
 
 use crate::{
+    cancel::CancellationToken,
     io::IoHandle,
     page_cache::{PageCache, PageMut},
     store::{PageLoad, PageLoader, Store},
@@ -10,6 +26,125 @@ use crate::{
 
 use nomt_core::page_id::{ChildPageIndex, PageId, MAX_PAGE_DEPTH, NUM_CHILDREN, ROOT_PAGE_ID};
 
+// The longest pause between checks for cancellation while rate limited.
+const MAX_PAUSE: Duration = Duration::from_millis(50);
+
+/// The state of a page cache prepopulation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrepopulateStatus {
+    /// Pages are still being loaded.
+    Running,
+    /// All pages of the requested levels have been loaded.
+    Finished,
+    /// The prepopulation was cancelled before all pages were loaded.
+    Cancelled,
+    /// Loading a page failed with the given error.
+    Failed(String),
+}
+
+/// The progress of a page cache prepopulation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrepopulateProgress {
+    /// The number of pages loaded into the page cache so far.
+    pub pages_loaded: u64,
+    /// Whether the prepopulation is still running, and how it ended otherwise.
+    pub status: PrepopulateStatus,
+}
+
+/// A handle to a page cache prepopulation running in the background.
+///
+/// This is cheap to clone. Dropping the handle does not stop the prepopulation.
+#[derive(Clone)]
+pub struct PrepopulateHandle {
+    shared: Arc<Shared>,
+    cancel: CancellationToken,
+}
+
+struct Shared {
+    pages_loaded: AtomicU64,
+    status: Mutex<PrepopulateStatus>,
+    cvar: Condvar,
+}
+
+impl PrepopulateHandle {
+    /// Get the progress made so far.
+    pub fn progress(&self) -> PrepopulateProgress {
+        let status = self.shared.status.lock().clone();
+        PrepopulateProgress {
+            pages_loaded: self.shared.pages_loaded.load(Ordering::Relaxed),
+            status,
+        }
+    }
+
+    /// Stop the prepopulation after the batch of pages being loaded. Pages already loaded stay
+    /// in the page cache.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Block until the prepopulation has finished, been cancelled or failed.
+    pub fn wait(&self) -> PrepopulateProgress {
+        let mut status = self.shared.status.lock();
+        while *status == PrepopulateStatus::Running {
+            self.shared.cvar.wait(&mut status);
+        }
+        PrepopulateProgress {
+            pages_loaded: self.shared.pages_loaded.load(Ordering::Relaxed),
+            status: status.clone(),
+        }
+    }
+}
+
+/// Start prepopulating the given number of levels of the page tree into the page cache on a
+/// background thread, loading at most `max_pages_per_second` pages per second, if given.
+pub fn spawn(
+    page_cache: PageCache,
+    store: Store,
+    access_lock: Arc<RwLock<()>>,
+    levels: usize,
+    max_pages_per_second: Option<u32>,
+) -> PrepopulateHandle {
+    let handle = PrepopulateHandle {
+        shared: Arc::new(Shared {
+            pages_loaded: AtomicU64::new(0),
+            status: Mutex::new(PrepopulateStatus::Running),
+            cvar: Condvar::new(),
+        }),
+        cancel: CancellationToken::new(),
+    };
+
+    let _thread = std::thread::Builder::new()
+        .name("nomt-prepopulate".to_string())
+        .spawn({
+            let handle = handle.clone();
+            move || {
+                let io_handle = store.io_pool().make_handle();
+                let status = match prepopulate_inner(
+                    io_handle,
+                    &page_cache,
+                    &store,
+                    &access_lock,
+                    levels,
+                    max_pages_per_second,
+                    &handle.cancel,
+                    &handle.shared.pages_loaded,
+                ) {
+                    Ok(true) => PrepopulateStatus::Finished,
+                    Ok(false) => PrepopulateStatus::Cancelled,
+                    Err(e) => PrepopulateStatus::Failed(e.to_string()),
+                };
+                // Release the database before reporting the end, so that a database dropped
+                // after waiting for this is closed once the drop returns.
+                drop((page_cache, store));
+                *handle.shared.status.lock() = status;
+                handle.shared.cvar.notify_all();
+            }
+        })
+        .expect("failed to spawn prepopulate thread");
+
+    handle
+}
+
 /// Prepopulate the given number of levels of the page tree into the page cache.
 ///
 /// This function blocks until the prepopulation has finished.
@@ -17,80 +152,121 @@ pub fn prepopulate(
     io_handle: IoHandle,
     page_cache: &PageCache,
     store: &Store,
+    access_lock: &RwLock<()>,
     levels: usize,
 ) -> io::Result<()> {
-    let page_loader = store.page_loader();
-    let mut loads = Vec::new();
+    prepopulate_inner(
+        io_handle,
+        page_cache,
+        store,
+        access_lock,
+        levels,
+        None,
+        &CancellationToken::new(),
+        &AtomicU64::new(0),
+    )
+    .map(|_| ())
+}
 
+// Returns `false` if cancelled before all pages were loaded.
+#[allow(clippy::too_many_arguments)]
+fn prepopulate_inner(
+    io_handle: IoHandle,
+    page_cache: &PageCache,
+    store: &Store,
+    access_lock: &RwLock<()>,
+    levels: usize,
+    max_pages_per_second: Option<u32>,
+    cancel: &CancellationToken,
+    pages_loaded: &AtomicU64,
+) -> io::Result<bool> {
     let levels = std::cmp::min(levels, MAX_PAGE_DEPTH);
+    let start = Instant::now();
 
-    // dispatch all page loads recursively.
-    dispatch_recursive(ROOT_PAGE_ID, &page_loader, &io_handle, &mut loads, levels)?;
+    // pages whose children are still to be loaded.
+    let mut parents = VecDeque::new();
+    if levels > 0 {
+        parents.push_back(ROOT_PAGE_ID);
+    }
 
-    let mut completed = 0;
+    while let Some(parent) = parents.pop_front() {
+        if let Some(rate) = max_pages_per_second {
+            let loaded = pages_loaded.load(Ordering::Relaxed);
+            let due = start + Duration::from_secs_f64(loaded as f64 / rate as f64);
+            while let Some(pause) = due.checked_duration_since(Instant::now()) {
+                if cancel.is_cancelled() {
+                    break;
+                }
+                std::thread::sleep(pause.min(MAX_PAUSE));
+            }
+        }
+        if cancel.is_cancelled() {
+            return Ok(false);
+        }
+
+        let _guard = access_lock.read();
+        // A new loader for every batch, since the hash table may have been resized in between.
+        let page_loader = store.page_loader();
+        for page_id in load_children(&parent, &page_loader, &io_handle, page_cache)? {
+            pages_loaded.fetch_add(1, Ordering::Relaxed);
+            if page_id.depth() < levels {
+                parents.push_back(page_id);
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+// load all children of the given page into the page cache, returning those which exist.
+fn load_children(
+    parent: &PageId,
+    page_loader: &PageLoader,
+    io_handle: &IoHandle,
+    page_cache: &PageCache,
+) -> io::Result<Vec<PageId>> {
+    let mut loads: Vec<PageLoad> = Vec::with_capacity(NUM_CHILDREN);
+    for child_index in 0..NUM_CHILDREN {
+        // UNWRAP: all indices up to NUM_CHILDREN are allowed.
+        let child_index = ChildPageIndex::new(child_index as u8).unwrap();
+
+        // UNWRAP: the parent is above the maximum depth and child index is valid.
+        let child_page_id = parent.child_page_id(child_index).unwrap();
+
+        let mut page_load = page_loader.start_load(child_page_id);
+        if page_loader.probe(&mut page_load, io_handle, loads.len() as u64) {
+            // probe has been dispatched.
+            loads.push(page_load);
+        }
+    }
+
+    let mut loaded = Vec::new();
+    let mut remaining = loads.len();
 
     // wait on I/O results.
-    while completed < loads.len() {
+    while remaining > 0 {
         // UNWRAP: we don't expect the I/O pool to go down. fatal error.
         let complete_io = io_handle.recv().expect("I/O Pool Down");
         complete_io.result?;
-        let load_index = complete_io.command.user_data as usize;
-        let load = &mut loads[load_index];
+        let load = &mut loads[complete_io.command.user_data as usize];
 
         // UNWRAP: all submitted requests are of kind Read(FatPage).
         if let Some((page, bucket)) = load.try_complete(complete_io.command.kind.unwrap_buf()) {
-            completed += 1;
+            remaining -= 1;
             page_cache.insert(
                 load.page_id().clone(),
                 PageMut::pristine_with_data(page).freeze(),
                 bucket,
             );
+            loaded.push(load.page_id().clone());
         } else {
             // misprobe. try again.
-            if !page_loader.probe(load, &io_handle, complete_io.command.user_data) {
+            if !page_loader.probe(load, io_handle, complete_io.command.user_data) {
                 // guaranteed empty.
-                completed += 1;
+                remaining -= 1;
             }
         }
     }
 
-    Ok(())
-}
-
-// dispatch page loads for all the children of the given page.
-fn dispatch_recursive(
-    page_id: PageId,
-    page_loader: &PageLoader,
-    io_handle: &IoHandle,
-    loads: &mut Vec<PageLoad>,
-    levels_remaining: usize,
-) -> io::Result<()> {
-    if levels_remaining == 0 {
-        return Ok(());
-    }
-
-    for child_index in 0..NUM_CHILDREN {
-        // UNWRAP: all indices up to NUM_CHILDREN are allowed.
-        let child_index = ChildPageIndex::new(child_index as u8).unwrap();
-
-        // UNWRAP: depth is not out of bounds and child index is valid.
-        let child_page_id = page_id.child_page_id(child_index).unwrap();
-
-        let mut page_load = page_loader.start_load(child_page_id.clone());
-
-        let next_index = loads.len() as u64;
-        if page_loader.probe(&mut page_load, io_handle, next_index) {
-            // probe has been dispatched.
-            loads.push(page_load);
-            dispatch_recursive(
-                child_page_id,
-                page_loader,
-                io_handle,
-                loads,
-                levels_remaining - 1,
-            )?;
-        }
-    }
-
-    Ok(())
+    Ok(loaded)
 }
@@ -7,14 +7,18 @@ use page_set::{FrozenSharedPageSet, PageSet};
 use parking_lot::Mutex;
 
 use nomt_core::{
-    page_id::{ChildPageIndex, PageId},
+    page_id::{ChildPageIndex, PageId, ROOT_PAGE_ID},
     proof::{PathProof, PathProofTerminal},
     trie::{self, KeyPath, Node, ValueHash},
     trie_pos::TriePosition,
 };
 use seek::{Seek, Seeker};
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Range,
+    sync::{Arc, OnceLock},
+};
 
 use crate::{
     beatree::ReadTransaction as BeatreeReadTx,
@@ -24,6 +28,7 @@ use crate::{
     rw_pass_cell::WritePassEnvelope,
     store::{BucketIndex, DirtyPage, SharedMaybeBucketIndex, Store},
     task::{join_task, spawn_task, TaskResult},
+    yielding::{YieldHook, YieldPoint, Yielder},
     HashAlgorithm, Witness, WitnessedOperations, WitnessedPath, WitnessedRead, WitnessedWrite,
 };
 use threadpool::ThreadPool;
@@ -34,7 +39,10 @@ mod page_walker;
 mod seek;
 mod worker;
 
-pub use cache_prepopulate::prepopulate as prepopulate_cache;
+pub use cache_prepopulate::{
+    prepopulate as prepopulate_cache, spawn as spawn_prepopulate_cache, PrepopulateHandle,
+    PrepopulateProgress, PrepopulateStatus,
+};
 pub use page_walker::UpdatedPage;
 
 #[cfg(doc)]
@@ -151,7 +159,7 @@ pub enum KeyReadWrite {
 }
 
 impl KeyReadWrite {
-    fn is_write(&self) -> bool {
+    pub(crate) fn is_write(&self) -> bool {
         match self {
             KeyReadWrite::Read => false,
             KeyReadWrite::Write(_) | KeyReadWrite::ReadThenWrite(_) => true,
@@ -179,6 +187,7 @@ impl KeyReadWrite {
 pub struct UpdatePool {
     worker_tp: ThreadPool,
     do_warm_up: bool,
+    yield_hook: Option<YieldHook>,
 }
 
 impl UpdatePool {
@@ -187,13 +196,14 @@ impl UpdatePool {
     /// # Panics
     ///
     /// Panics if `num_workers` is zero.
-    pub fn new(num_workers: usize, do_warm_up: bool) -> Self {
+    pub fn new(num_workers: usize, do_warm_up: bool, yield_hook: Option<YieldHook>) -> Self {
         UpdatePool {
             worker_tp: threadpool::Builder::new()
                 .num_threads(num_workers)
                 .thread_name("nomt-commit".to_string())
                 .build(),
             do_warm_up,
+            yield_hook,
         }
     }
 
@@ -219,10 +229,13 @@ impl UpdatePool {
             root,
         };
 
-        let warm_up = if self.do_warm_up {
-            Some(spawn_warm_up::<H>(&self.worker_tp, params))
+        let (warm_up, lazy_warm_up) = if self.do_warm_up {
+            (
+                OnceLock::from(spawn_warm_up::<H>(&self.worker_tp, params)),
+                None,
+            )
         } else {
-            None
+            (OnceLock::new(), Some(params))
         };
 
         let beatree_read_tx = store.read_transaction();
@@ -230,6 +243,8 @@ impl UpdatePool {
         Updater {
             worker_tp: self.worker_tp.clone(),
             warm_up,
+            lazy_warm_up,
+            yield_hook: self.yield_hook.clone(),
             page_cache,
             root,
             store,
@@ -246,7 +261,11 @@ impl UpdatePool {
 pub struct Updater {
     worker_tp: ThreadPool,
     page_cache: PageCache,
-    warm_up: Option<WarmUpHandle>,
+    warm_up: OnceLock<WarmUpHandle>,
+    // The parameters to start the warm-up worker with on the first prefetch, when it is not
+    // started along with the updater.
+    lazy_warm_up: Option<worker::WarmUpParams>,
+    yield_hook: Option<YieldHook>,
     root: Node,
     store: Store,
     page_pool: PagePool,
@@ -257,28 +276,53 @@ pub struct Updater {
 impl Updater {
     /// Warm up the given key-path by pre-fetching the relevant pages.
     pub fn warm_up(&self, key_path: KeyPath) {
-        if let Some(ref warm_up) = self.warm_up {
-            let _ = warm_up.warmup_tx.send(WarmUpCommand { key_path });
+        if let Some(warm_up) = self.warm_up.get() {
+            let _ = warm_up.warmup_tx.send(WarmUpCommand {
+                key_path,
+                prefetch_value: false,
+            });
         }
     }
 
+    /// Prefetch the pages of the given key-path along with the leaf holding its value, starting
+    /// the warm-up worker if it is not running.
+    pub fn prefetch<H: HashAlgorithm>(&self, key_path: KeyPath) {
+        let warm_up = self.warm_up.get_or_init(|| {
+            // UNWRAP: the parameters are kept whenever the warm-up worker is not started along
+            // with the updater.
+            let params = self.lazy_warm_up.clone().unwrap();
+            spawn_warm_up::<H>(&self.worker_tp, params)
+        });
+        let _ = warm_up.warmup_tx.send(WarmUpCommand {
+            key_path,
+            prefetch_value: true,
+        });
+    }
+
     /// Update the trie with the given key-value read/write operations.
     ///
     /// Key-paths should be in sorted order
     /// and should appear at most once within the vector. Witness specifies whether or not
-    /// to collect the witness of the operation.
+    /// to collect the witness of the operation, and `trace_pages` whether to collect the IDs of
+    /// the pages on the paths to the terminals of the keys.
     pub fn update_and_prove<H: HashAlgorithm>(
-        self,
+        mut self,
         read_write: Vec<(KeyPath, KeyReadWrite)>,
         witness: bool,
+        trace_pages: bool,
+        max_depth: Option<usize>,
     ) -> std::io::Result<UpdateHandle> {
-        if let Some(ref warm_up) = self.warm_up {
+        let warm_up = self.warm_up.take();
+        if let Some(ref warm_up) = warm_up {
             let _ = warm_up.finish_tx.send(());
         }
         let shared = Arc::new(UpdateShared {
             witness,
+            trace_pages,
+            max_depth,
             overlay: self.overlay.clone(),
             read_write,
+            yield_hook: self.yield_hook.clone(),
             root_page_pending: Mutex::new(Vec::with_capacity(64)),
         });
 
@@ -286,8 +330,10 @@ impl Updater {
         let shard_regions = (0..num_workers).map(ShardIndex::Shard).collect::<Vec<_>>();
 
         // receive warm-ups from worker.
-        let (warm_ups, warm_page_set) = if let Some(ref warm_up) = self.warm_up {
-            let output = join_task(&warm_up.output_rx)?;
+        let (warm_ups, warm_page_set) = if let Some(ref warm_up) = warm_up {
+            // UNWRAP: the warm-up worker only gives up when its channels are disconnected, and
+            // they are kept alive until here.
+            let output = join_task(&warm_up.output_rx)?.unwrap();
             (output.paths, Some(output.pages))
         } else {
             (HashMap::new(), None)
@@ -367,6 +413,23 @@ impl Updater {
     }
 }
 
+impl Drop for Updater {
+    fn drop(&mut self) {
+        // If the updater is dropped without being used, stop the warm-up worker and wait for it,
+        // so that its read transaction and prefetched pages are released before we return.
+        if let Some(warm_up) = self.warm_up.take() {
+            let WarmUpHandle {
+                finish_tx,
+                warmup_tx,
+                output_rx,
+            } = warm_up;
+            drop(finish_tx);
+            drop(warmup_tx);
+            let _ = output_rx.recv();
+        }
+    }
+}
+
 /// A handle for waiting on the results of a commit operation.
 pub struct UpdateHandle {
     shared: Arc<UpdateShared>,
@@ -376,6 +439,9 @@ pub struct UpdateHandle {
 
 impl UpdateHandle {
     /// Wait on the results of the commit operation.
+    ///
+    /// Fails with an I/O error carrying [`crate::Cancelled`] if the yield hook cancelled the
+    /// update.
     pub fn join(self) -> std::io::Result<Output> {
         let mut new_root = None;
 
@@ -385,12 +451,16 @@ impl UpdateHandle {
                 reads: Vec::new(),
                 writes: Vec::new(),
             },
+            excluded: Vec::new(),
+            binding: None,
+            values: Vec::new(),
         });
 
         let mut updated_pages = Vec::new();
-
-        let mut path_proof_offset = 0;
-        let mut witnessed_start = 0;
+        let mut worker_witnesses = Vec::new();
+        let mut deep_leaves = DeepLeaves::default();
+        let mut traced_pages = self.shared.trace_pages.then(BTreeSet::new);
+        let mut pages_fetched = 0;
 
         for _ in 0..self.num_workers {
             let output = join_task(&self.worker_rx)?;
@@ -401,52 +471,77 @@ impl UpdateHandle {
             }
 
             updated_pages.push(output.updated_pages);
+            deep_leaves.merge(output.deep_leaves);
+            pages_fetched += output.pages_fetched;
 
-            // if the Commit worker collected the witnessed paths
-            // then we need to aggregate them
-            if let Some(witnessed_paths) = output.witnessed_paths {
-                // UNWRAP: the same `UpdateShared` object is used to decide whether
-                // to collect witnesses or not. If the commit worker did so,
-                // `maybe_witness` and `maybe_witnessed_ops` must be initialized to contain
-                // all witnesses from all workers.
-                let witness = maybe_witness.as_mut().unwrap();
-
-                let path_proof_count = witnessed_paths.len();
-                witness.path_proofs.reserve(witnessed_paths.len());
-                for (path_index, (path, leaf_data, batch_size)) in
-                    witnessed_paths.into_iter().enumerate()
-                {
-                    witness.path_proofs.push(path);
-                    let witnessed_end = witnessed_start + batch_size;
-                    for (k, v) in &self.shared.read_write[witnessed_start..witnessed_end] {
-                        if v.is_read() {
-                            let value_hash = leaf_data.as_ref().and_then(|leaf_data| {
-                                if &leaf_data.key_path == k {
-                                    Some(leaf_data.value_hash)
-                                } else {
-                                    None
-                                }
-                            });
-
-                            witness.operations.reads.push(WitnessedRead {
-                                key: *k,
-                                value: value_hash,
-                                path_index: path_index + path_proof_offset,
-                            });
-                        }
-                        if let Some(written) = v.written_value() {
-                            witness.operations.writes.push(WitnessedWrite {
-                                key: *k,
-                                value: written,
-                                path_index: path_index + path_proof_offset,
-                            });
-                        }
+            // if the workers collected witnessed paths then we need to aggregate them
+            if let Some(worker_witness) = output.witness {
+                worker_witnesses.push(worker_witness);
+            }
+            if let (Some(traced_pages), Some(terminals)) =
+                (traced_pages.as_mut(), output.traced_terminals)
+            {
+                for terminal in terminals {
+                    let mut page_id = terminal.page_id().unwrap_or(ROOT_PAGE_ID);
+                    while page_id != ROOT_PAGE_ID {
+                        let parent = page_id.parent_page_id();
+                        traced_pages.insert(page_id);
+                        page_id = parent;
                     }
-                    witnessed_start = witnessed_end;
+                    traced_pages.insert(ROOT_PAGE_ID);
                 }
+            }
+        }
 
-                path_proof_offset += path_proof_count;
+        if let Some(witness) = maybe_witness.as_mut() {
+            // Workers conclude in any order, each with its part of the witness in the canonical
+            // order: paths sorted by the keys they cover, which is also the lexicographic order of
+            // the paths. The workers cover disjoint ranges of keys, so putting their parts in
+            // order restores the canonical order of the whole.
+            worker_witnesses.retain(|w| !w.paths.is_empty());
+            worker_witnesses.sort_unstable_by_key(|w| w.paths[0].0);
+            Yielder::new(self.shared.yield_hook.clone(), YieldPoint::Sort)
+                .tick(worker_witnesses.len())
+                .map_err(std::io::Error::other)?;
+
+            let mut yielder =
+                Yielder::new(self.shared.yield_hook.clone(), YieldPoint::WitnessBuild);
+            let n_paths = worker_witnesses.iter().map(|w| w.paths.len()).sum();
+            let n_reads = worker_witnesses.iter().map(|w| w.reads.len()).sum();
+            let n_writes = worker_witnesses.iter().map(|w| w.writes.len()).sum();
+            witness.path_proofs.reserve(n_paths);
+            witness.operations.reads.reserve(n_reads);
+            witness.operations.writes.reserve(n_writes);
+            for worker_witness in worker_witnesses {
+                yielder
+                    .tick(worker_witness.paths.len())
+                    .map_err(std::io::Error::other)?;
+                let offset = witness.path_proofs.len();
+                witness
+                    .path_proofs
+                    .extend(worker_witness.paths.into_iter().map(|(_, path)| path));
+                witness
+                    .operations
+                    .reads
+                    .extend(worker_witness.reads.into_iter().map(|read| WitnessedRead {
+                        path_index: read.path_index + offset,
+                        ..read
+                    }));
+                witness
+                    .operations
+                    .writes
+                    .extend(
+                        worker_witness
+                            .writes
+                            .into_iter()
+                            .map(|write| WitnessedWrite {
+                                path_index: write.path_index + offset,
+                                ..write
+                            }),
+                    );
             }
+
+            debug_assert!(witness.is_canonical());
         }
 
         // UNWRAP: one thread always produces the root.
@@ -454,6 +549,9 @@ impl UpdateHandle {
             root: new_root.unwrap(),
             updated_pages: UpdatedPages(updated_pages),
             witness: maybe_witness,
+            deep_leaves,
+            traced_pages: traced_pages.map(|pages| pages.into_iter().collect()),
+            pages_fetched,
         })
     }
 }
@@ -466,6 +564,41 @@ pub struct Output {
     pub updated_pages: UpdatedPages,
     /// Optional witness
     pub witness: Option<Witness>,
+    /// The leaves placed deeper than the maximum depth. Empty if no maximum depth was given.
+    pub deep_leaves: DeepLeaves,
+    /// The pages on the paths to the terminals of all keys, in ascending order. `None` unless
+    /// page tracing was requested.
+    pub traced_pages: Option<Vec<PageId>>,
+    /// The number of pages loaded from disk by the seeks of the keys, during the warm-up or the
+    /// update. Pages found in memory are not counted.
+    pub pages_fetched: usize,
+}
+
+/// Leaves placed deeper than the maximum depth given to an update.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DeepLeaves {
+    /// The number of such leaves.
+    pub count: usize,
+    /// The deepest such leaf and its depth.
+    pub deepest: Option<(KeyPath, usize)>,
+}
+
+impl DeepLeaves {
+    fn record(&mut self, key: KeyPath, depth: usize) {
+        self.count += 1;
+        if self.deepest.is_none_or(|(_, d)| depth > d) {
+            self.deepest = Some((key, depth));
+        }
+    }
+
+    fn merge(&mut self, other: DeepLeaves) {
+        self.count += other.count;
+        if let Some((key, depth)) = other.deepest {
+            if self.deepest.is_none_or(|(_, d)| depth > d) {
+                self.deepest = Some((key, depth));
+            }
+        }
+    }
 }
 
 struct UpdateCommand {
@@ -475,6 +608,8 @@ struct UpdateCommand {
 
 struct WarmUpCommand {
     key_path: KeyPath,
+    // Whether to load the leaf holding the value of the key as well.
+    prefetch_value: bool,
 }
 
 struct WarmUpOutput {
@@ -491,18 +626,96 @@ enum RootPagePending {
     Node(Node),
 }
 
+// The part of the witness gathered by a worker: the paths it witnessed and the operations on the
+// keys they cover. The path indices of the operations are local to the worker until the parts of
+// all workers are joined.
+#[derive(Default)]
+struct WorkerWitness {
+    // the witnessed paths, along with the index of the first key they cover in `read_write`.
+    paths: Vec<(usize, WitnessedPath)>,
+    reads: Vec<WitnessedRead>,
+    writes: Vec<WitnessedWrite>,
+}
+
+impl WorkerWitness {
+    // add a path, along with the terminal and the range of `read_write` it covers.
+    fn push(
+        &mut self,
+        path: WitnessedPath,
+        terminal: Option<trie::LeafData>,
+        read_write: &[(KeyPath, KeyReadWrite)],
+        range: Range<usize>,
+    ) {
+        let path_index = self.paths.len();
+        self.paths.push((range.start, path));
+        for (k, v) in &read_write[range] {
+            if v.is_read() {
+                let value_hash = terminal
+                    .as_ref()
+                    .filter(|leaf_data| &leaf_data.key_path == k)
+                    .map(|leaf_data| leaf_data.value_hash);
+                self.reads.push(WitnessedRead {
+                    key: *k,
+                    value: value_hash,
+                    path_index,
+                });
+            }
+            if let Some(written) = v.written_value() {
+                self.writes.push(WitnessedWrite {
+                    key: *k,
+                    value: written,
+                    path_index,
+                });
+            }
+        }
+    }
+
+    // put the paths in the order of the keys they cover. paths are mostly pushed in order, but
+    // those whose pages had to be fetched first are pushed later.
+    fn sort(&mut self) {
+        if self.paths.is_sorted_by_key(|(start, _)| *start) {
+            return;
+        }
+        let mut order = (0..self.paths.len()).collect::<Vec<_>>();
+        order.sort_unstable_by_key(|i| self.paths[*i].0);
+        let mut new_index = vec![0; order.len()];
+        for (new, old) in order.iter().enumerate() {
+            new_index[*old] = new;
+        }
+
+        self.paths.sort_unstable_by_key(|(start, _)| *start);
+        // the operations of each path are in key order, so a stable sort keeps them that way.
+        for read in &mut self.reads {
+            read.path_index = new_index[read.path_index];
+        }
+        self.reads.sort_by_key(|read| read.path_index);
+        for write in &mut self.writes {
+            write.path_index = new_index[write.path_index];
+        }
+        self.writes.sort_by_key(|write| write.path_index);
+    }
+}
+
 struct WorkerOutput {
     root: Option<Node>,
-    witnessed_paths: Option<Vec<(WitnessedPath, Option<trie::LeafData>, usize)>>,
+    witness: Option<WorkerWitness>,
+    // positions of the terminals of all keys, if pages are traced.
+    traced_terminals: Option<Vec<TriePosition>>,
     updated_pages: Vec<UpdatedPage>,
+    deep_leaves: DeepLeaves,
+    // the number of pages loaded from disk by the seeks of the keys.
+    pages_fetched: usize,
 }
 
 impl WorkerOutput {
-    fn new(witness: bool) -> Self {
+    fn new(witness: bool, trace_pages: bool) -> Self {
         WorkerOutput {
             root: None,
-            witnessed_paths: if witness { Some(Vec::new()) } else { None },
+            witness: witness.then(WorkerWitness::default),
+            traced_terminals: trace_pages.then(Vec::new),
             updated_pages: Vec::new(),
+            deep_leaves: DeepLeaves::default(),
+            pages_fetched: 0,
         }
     }
 }
@@ -510,10 +723,14 @@ impl WorkerOutput {
 // Shared data used in committing.
 struct UpdateShared {
     read_write: Vec<(KeyPath, KeyReadWrite)>,
+    yield_hook: Option<YieldHook>,
     // nodes needing to be written to pages above a shard.
     root_page_pending: Mutex<Vec<(TriePosition, RootPagePending)>>,
     overlay: LiveOverlay,
     witness: bool,
+    trace_pages: bool,
+    // leaves placed deeper than this are recorded in the output.
+    max_depth: Option<usize>,
 }
 
 impl UpdateShared {
@@ -553,7 +770,7 @@ impl UpdateShared {
 struct WarmUpHandle {
     finish_tx: Sender<()>,
     warmup_tx: Sender<WarmUpCommand>,
-    output_rx: Receiver<TaskResult<std::io::Result<WarmUpOutput>>>,
+    output_rx: Receiver<TaskResult<std::io::Result<Option<WarmUpOutput>>>>,
 }
 
 fn spawn_warm_up<H: HashAlgorithm>(
@@ -614,3 +831,60 @@ fn get_in_memory_page(
                 .map(|(page, bucket)| (page, BucketInfo::Known(bucket)))
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyReadWrite, WorkerWitness};
+    use crate::{PathProof, WitnessedPath};
+    use nomt_core::{proof::PathProofTerminal, trie_pos::TriePosition};
+
+    fn path() -> WitnessedPath {
+        WitnessedPath {
+            inner: PathProof {
+                terminal: PathProofTerminal::Terminator(TriePosition::new()),
+                siblings: Vec::new(),
+            },
+            path: TriePosition::new(),
+        }
+    }
+
+    #[test]
+    fn worker_witness_sorts_paths_pushed_out_of_order() {
+        let read_write = (0..5u8)
+            .map(|i| {
+                let op = if i % 2 == 0 {
+                    KeyReadWrite::Read
+                } else {
+                    KeyReadWrite::ReadThenWrite(Some([i; 32]))
+                };
+                ([i; 32], op)
+            })
+            .collect::<Vec<_>>();
+
+        let mut witness = WorkerWitness::default();
+        witness.push(path(), None, &read_write, 1..3);
+        witness.push(path(), None, &read_write, 4..5);
+        witness.push(path(), None, &read_write, 0..1);
+        witness.push(path(), None, &read_write, 3..4);
+        witness.sort();
+
+        let starts = witness
+            .paths
+            .iter()
+            .map(|(start, _)| *start)
+            .collect::<Vec<_>>();
+        assert_eq!(starts, vec![0, 1, 3, 4]);
+        let reads = witness
+            .reads
+            .iter()
+            .map(|read| (read.key[0], read.path_index))
+            .collect::<Vec<_>>();
+        assert_eq!(reads, vec![(0, 0), (1, 1), (2, 1), (3, 2), (4, 3)]);
+        let writes = witness
+            .writes
+            .iter()
+            .map(|write| (write.key[0], write.path_index))
+            .collect::<Vec<_>>();
+        assert_eq!(writes, vec![(1, 1), (3, 2)]);
+    }
+}
@@ -305,8 +305,8 @@ impl SeekRequest {
         if collected_leaf_data.is_empty() {
             let leaves_data = overlay_leaves.filter_map(|(overlay_key, overlay_valuechange)| {
                 let value_hash = match overlay_valuechange {
-                    ValueChange::Insert(value) => H::hash_value(value),
-                    ValueChange::InsertOverflow(_, value_hash) => *value_hash,
+                    ValueChange::Insert(value) => H::hash_value(&value),
+                    ValueChange::InsertOverflow(_, value_hash) => value_hash,
                     _ => {
                         // Overlays sometimes contain "naked deletions", i.e. deleted items that
                         // never existed in the beatree.
@@ -341,8 +341,8 @@ impl SeekRequest {
                     .map(|(key_path, _)| key_path);
 
                 let value_hash = match overlay_valuechange {
-                    ValueChange::Insert(value) => H::hash_value(value),
-                    ValueChange::InsertOverflow(_, value_hash) => *value_hash,
+                    ValueChange::Insert(value) => H::hash_value(&value),
+                    ValueChange::InsertOverflow(_, value_hash) => value_hash,
                     // Deleted key, skip it.
                     ValueChange::Delete if key_path == Some(&overlay_key) => {
                         beatree_leaf_idx += 1;
@@ -431,7 +431,7 @@ impl RequestState {
                 }
                 // If an insertion is found within the overlay, it is expected to be
                 // the item associated with the leaf that is being fetched.
-                ValueChange::Insert(value) => H::hash_value(value),
+                ValueChange::Insert(value) => H::hash_value(&value),
                 ValueChange::InsertOverflow(_, value_hash) => value_hash.clone(),
             };
 
@@ -935,7 +935,6 @@ pub struct Seek {
     /// The number of I/Os loaded uniquely for this `Seek`.
     /// This does not include pages loaded from the cache, or pages which were already requested
     /// for another seek.
-    #[allow(dead_code)]
     pub ios: usize,
 }
 
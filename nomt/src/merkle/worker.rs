@@ -16,10 +16,12 @@ use nomt_core::{
     page_id::ROOT_PAGE_ID,
     proof::PathProofTerminal,
     trie::{KeyPath, Node, ValueHash},
+    trie_pos::TriePosition,
 };
 
 use std::{
     collections::{HashMap, VecDeque},
+    ops::Range,
     sync::Arc,
 };
 
@@ -32,11 +34,13 @@ use super::{
 };
 
 use crate::{
-    io::PagePool,
+    beatree::{self, AsyncLeafLoad, PageNumber},
+    io::{IoHandle, PagePool},
     page_cache::{PageCache, ShardIndex},
     page_region::PageRegion,
     rw_pass_cell::WritePass,
     store::Store,
+    yielding::{YieldPoint, Yielder},
     HashAlgorithm, PathProof, WitnessedPath,
 };
 
@@ -50,6 +54,7 @@ pub(super) struct UpdateParams {
     pub command: UpdateCommand,
 }
 
+#[derive(Clone)]
 pub(super) struct WarmUpParams {
     pub page_cache: PageCache,
     pub overlay: LiveOverlay,
@@ -61,11 +66,12 @@ pub(super) fn run_warm_up<H: HashAlgorithm>(
     params: WarmUpParams,
     warmup_rx: Receiver<WarmUpCommand>,
     finish_rx: Receiver<()>,
-) -> std::io::Result<WarmUpOutput> {
+) -> std::io::Result<Option<WarmUpOutput>> {
     let page_loader = params.store.page_loader();
     let io_handle = params.store.io_pool().make_handle();
     let page_pool = params.store.io_pool().page_pool().clone();
     let page_io_receiver = io_handle.receiver().clone();
+    let leaf_prefetcher = LeafPrefetcher::new(&params.store, params.overlay.clone());
 
     // We always run with `WithoutDependents` here, and the mode is adjusted later, during `update`.
     let page_set = PageSet::new(page_pool, None);
@@ -80,7 +86,14 @@ pub(super) fn run_warm_up<H: HashAlgorithm>(
         true,
     );
 
-    warm_up_phase(page_io_receiver, seeker, page_set, warmup_rx, finish_rx)
+    warm_up_phase(
+        page_io_receiver,
+        seeker,
+        page_set,
+        leaf_prefetcher,
+        warmup_rx,
+        finish_rx,
+    )
 }
 
 pub(super) fn run_update<H: HashAlgorithm>(params: UpdateParams) -> std::io::Result<WorkerOutput> {
@@ -120,17 +133,22 @@ fn warm_up_phase<H: HashAlgorithm>(
     page_io_receiver: Receiver<crate::io::CompleteIo>,
     mut seeker: Seeker<H>,
     mut page_set: PageSet,
+    mut leaf_prefetcher: LeafPrefetcher,
     warmup_rx: Receiver<WarmUpCommand>,
     finish_rx: Receiver<()>,
-) -> std::io::Result<WarmUpOutput> {
+) -> std::io::Result<Option<WarmUpOutput>> {
+    let leaf_io_receiver = leaf_prefetcher.io_handle.receiver().clone();
+
     let mut select_all = Select::new();
     let warmup_idx = select_all.recv(&warmup_rx);
     let finish_idx = select_all.recv(&finish_rx);
     let page_idx = select_all.recv(&page_io_receiver);
+    let leaf_idx = select_all.recv(&leaf_io_receiver);
 
     let mut select_no_work = Select::new();
     let finish_no_work_idx = select_no_work.recv(&finish_rx);
     let page_no_work_idx = select_no_work.recv(&page_io_receiver);
+    let leaf_no_work_idx = select_no_work.recv(&leaf_io_receiver);
 
     let mut warm_ups = HashMap::new();
 
@@ -147,11 +165,14 @@ fn warm_up_phase<H: HashAlgorithm>(
             if index == finish_no_work_idx {
                 match finish_rx.try_recv() {
                     Err(TryRecvError::Empty) => continue,
-                    Err(_) => panic!("Warm-Up worker, unexpected failure of the finish channel",),
+                    // The updater was dropped without finishing: abandon all in-flight work.
+                    Err(TryRecvError::Disconnected) => return Ok(None),
                     Ok(()) => break,
                 }
             } else if index == page_no_work_idx {
                 seeker.try_recv_page(&mut page_set)?;
+            } else if index == leaf_no_work_idx {
+                leaf_prefetcher.try_recv_leaves();
             } else {
                 unreachable!()
             }
@@ -161,19 +182,25 @@ fn warm_up_phase<H: HashAlgorithm>(
             if index == finish_idx {
                 match finish_rx.try_recv() {
                     Err(TryRecvError::Empty) => continue,
-                    Err(_) => panic!("Warm-Up worker, unexpected failure of the finish channel",),
+                    // The updater was dropped without finishing: abandon all in-flight work.
+                    Err(TryRecvError::Disconnected) => return Ok(None),
                     Ok(()) => break,
                 }
             } else if index == warmup_idx {
                 let warm_up_command = match warmup_rx.try_recv() {
                     Ok(command) => command,
                     Err(TryRecvError::Empty) => continue,
-                    Err(_) => panic!("Warm-Up worker, unexpected failure of the warmup channel"),
+                    Err(TryRecvError::Disconnected) => return Ok(None),
                 };
 
                 seeker.push(warm_up_command.key_path);
+                if warm_up_command.prefetch_value {
+                    leaf_prefetcher.push(warm_up_command.key_path);
+                }
             } else if index == page_idx {
                 seeker.try_recv_page(&mut page_set)?;
+            } else if index == leaf_idx {
+                leaf_prefetcher.try_recv_leaves();
             } else {
                 unreachable!()
             }
@@ -191,10 +218,64 @@ fn warm_up_phase<H: HashAlgorithm>(
         }
     }
 
-    Ok(WarmUpOutput {
+    Ok(Some(WarmUpOutput {
         pages: page_set.freeze(),
         paths: warm_ups,
-    })
+    }))
+}
+
+// Loads the beatree leaves holding the values of prefetched keys into the leaf cache, so that
+// reading the values does not wait on the disk.
+//
+// Prefetches are only hints: leaves which fail to load are left to the reads, and those still in
+// flight when the warm-up finishes are abandoned.
+struct LeafPrefetcher {
+    read_tx: beatree::ReadTransaction,
+    overlay: LiveOverlay,
+    io_handle: IoHandle,
+    loads: HashMap<PageNumber, AsyncLeafLoad>,
+}
+
+impl LeafPrefetcher {
+    fn new(store: &Store, overlay: LiveOverlay) -> Self {
+        LeafPrefetcher {
+            read_tx: store.read_transaction(),
+            overlay,
+            io_handle: store.io_pool().make_handle(),
+            loads: HashMap::new(),
+        }
+    }
+
+    fn push(&mut self, key_path: KeyPath) {
+        if self.overlay.value(&key_path).is_some() {
+            return;
+        }
+        let Some(page_number) = self.read_tx.leaf_page_number(key_path) else {
+            return;
+        };
+        if self.loads.contains_key(&page_number) {
+            return;
+        }
+        let user_data = page_number.0 as u64;
+        if let Err(load) = self
+            .read_tx
+            .load_leaf_async(page_number, &self.io_handle, user_data)
+        {
+            self.loads.insert(page_number, load);
+        }
+    }
+
+    fn try_recv_leaves(&mut self) {
+        while let Ok(complete_io) = self.io_handle.try_recv() {
+            let page_number = PageNumber(complete_io.command.user_data as u32);
+            // UNWRAP: a load is kept for every read submitted.
+            let load = self.loads.remove(&page_number).unwrap();
+            if complete_io.result.is_ok() {
+                // UNWRAP: the I/O command submitted by `load_leaf_async` is always a `Read`.
+                load.finish(complete_io.command.kind.unwrap_buf());
+            }
+        }
+    }
 }
 
 fn update<H: HashAlgorithm>(
@@ -209,19 +290,31 @@ fn update<H: HashAlgorithm>(
     let UpdateCommand { shared, write_pass } = command;
     let write_pass = write_pass.into_inner();
 
-    let mut output = WorkerOutput::new(shared.witness);
+    let mut output = WorkerOutput::new(shared.witness, shared.trace_pages);
 
     let mut page_set = PageSet::new(page_pool, warm_page_set);
 
     let updater = RangeUpdater::<H>::new(root, shared.clone(), write_pass, &page_cache);
 
+    let got_master_pass = updater
+        .update(&mut seeker, &mut output, &mut page_set, warm_ups)?
+        .is_some();
+
+    // order the witnessed paths here, in parallel with the other workers.
+    if let Some(witness) = output.witness.as_mut() {
+        witness.sort();
+    }
+
     // one lucky thread gets the master write pass.
-    match updater.update(&mut seeker, &mut output, &mut page_set, warm_ups)? {
-        None => return Ok(output),
-        Some(write_pass) => write_pass,
-    };
+    if !got_master_pass {
+        return Ok(output);
+    }
 
     let pending_ops = shared.take_root_pending();
+    Yielder::new(shared.yield_hook.clone(), YieldPoint::Sort)
+        .tick(pending_ops.len())
+        .map_err(std::io::Error::other)?;
+    let mut yielder = Yielder::new(shared.yield_hook.clone(), YieldPoint::MerkleUpdate);
     let mut root_page_updater = PageWalker::<H>::new(root, None);
 
     // Ensure the root page updater holds the root page. It is possible that this worker did not
@@ -246,8 +339,14 @@ fn update<H: HashAlgorithm>(
                 range_end,
                 prev_terminal,
             } => {
+                yielder
+                    .tick(range_end - range_start)
+                    .map_err(std::io::Error::other)?;
                 let ops = subtrie_ops(&shared.read_write[range_start..range_end]);
                 let ops = nomt_core::update::leaf_ops_spliced(prev_terminal, &ops);
+                if let Some(max_depth) = shared.max_depth {
+                    record_deep_leaves(&mut output, max_depth, &trie_pos, ops.clone());
+                }
                 root_page_updater.advance_and_replace(&page_set, trie_pos.clone(), ops);
             }
         }
@@ -358,6 +457,10 @@ impl<H: HashAlgorithm> RangeUpdater<H> {
             .as_ref()
             .map_or(true, |p_id| !self.region.contains_exclusive(p_id));
 
+        if let Some(ref mut traced_terminals) = output.traced_terminals {
+            traced_terminals.push(seek_result.position.clone());
+        }
+
         if is_non_exclusive {
             self.shared.push_pending_subtrie(
                 seek_result.position.clone(),
@@ -366,7 +469,7 @@ impl<H: HashAlgorithm> RangeUpdater<H> {
                 seek_result.terminal.clone(),
             );
 
-            if let Some(ref mut witnessed_paths) = output.witnessed_paths {
+            if let Some(ref mut witness) = output.witness {
                 let path = WitnessedPath {
                     inner: PathProof {
                         // if the terminal lands in the non-exclusive area, then the path to it is
@@ -379,7 +482,12 @@ impl<H: HashAlgorithm> RangeUpdater<H> {
                     },
                     path: seek_result.position,
                 };
-                witnessed_paths.push((path, seek_result.terminal, batch_size));
+                witness.push(
+                    path,
+                    seek_result.terminal,
+                    &self.shared.read_write,
+                    start_index..next_index,
+                );
             }
 
             return next_index;
@@ -393,7 +501,7 @@ impl<H: HashAlgorithm> RangeUpdater<H> {
         } else {
             None
         };
-        self.attempt_advance(output, page_set, seek_result, ops, batch_size);
+        self.attempt_advance(output, page_set, seek_result, ops, start_index..next_index);
 
         next_index
     }
@@ -406,18 +514,21 @@ impl<H: HashAlgorithm> RangeUpdater<H> {
         page_set: &PageSet,
         seek_result: Seek,
         ops: Option<Vec<(KeyPath, Option<ValueHash>)>>,
-        batch_size: usize,
+        batch: Range<usize>,
     ) {
         match ops {
             None => self.page_walker.advance(seek_result.position.clone()),
             Some(ref ops) => {
                 let ops = nomt_core::update::leaf_ops_spliced(seek_result.terminal.clone(), &ops);
+                if let Some(max_depth) = self.shared.max_depth {
+                    record_deep_leaves(output, max_depth, &seek_result.position, ops.clone());
+                }
                 self.page_walker
                     .advance_and_replace(page_set, seek_result.position.clone(), ops)
             }
         };
 
-        if let Some(ref mut witnessed_paths) = output.witnessed_paths {
+        if let Some(ref mut witness) = output.witness {
             let siblings = {
                 // nodes may have been altered prior to seeking - the page walker tracks which ones.
                 let mut siblings = seek_result.siblings;
@@ -437,7 +548,7 @@ impl<H: HashAlgorithm> RangeUpdater<H> {
                 },
                 path: seek_result.position,
             };
-            witnessed_paths.push((path, seek_result.terminal, batch_size));
+            witness.push(path, seek_result.terminal, &self.shared.read_write, batch);
         }
     }
 
@@ -453,6 +564,7 @@ impl<H: HashAlgorithm> RangeUpdater<H> {
         let mut skips = 0;
 
         let mut warmed_up: VecDeque<Seek> = VecDeque::new();
+        let mut yielder = Yielder::new(self.shared.yield_hook.clone(), YieldPoint::MerkleUpdate);
 
         // 1. drive until work is done.
         while start_index < self.range_end || !seeker.is_empty() {
@@ -473,6 +585,7 @@ impl<H: HashAlgorithm> RangeUpdater<H> {
             match completion {
                 None => {}
                 Some(seek_result) => {
+                    output.pages_fetched += seek_result.ios;
                     // skip completions until we're past the end of the last batch.
                     if skips > 0 {
                         skips -= 1;
@@ -483,6 +596,7 @@ impl<H: HashAlgorithm> RangeUpdater<H> {
                         // account for stuff we pushed that was already covered by the terminal
                         // we just popped off.
                         let batch_size = end_index - start_index;
+                        yielder.tick(batch_size).map_err(std::io::Error::other)?;
                         // note: pushes and batch size are both at least 1.
                         skips = std::cmp::min(pushes, batch_size) - 1;
                         pushes = pushes.saturating_sub(batch_size);
@@ -534,6 +648,41 @@ impl<H: HashAlgorithm> RangeUpdater<H> {
     }
 }
 
+// Record the leaves deeper than `max_depth` among those replacing the terminal at `position`.
+//
+// A leaf lies one level below the deepest internal node it shares with a neighbor, which is found
+// from the number of bits its key shares with the neighboring keys. A lone leaf takes the place
+// of the terminal.
+fn record_deep_leaves(
+    output: &mut WorkerOutput,
+    max_depth: usize,
+    position: &TriePosition,
+    ops: impl Iterator<Item = (KeyPath, ValueHash)>,
+) {
+    let mut keys = ops.map(|(key, _)| key).peekable();
+    let mut prev = None;
+    while let Some(key) = keys.next() {
+        let depth = match (prev.map(|p| shared_bits(&p, &key)), keys.peek()) {
+            (None, None) => position.depth() as usize,
+            (with_prev, next) => {
+                let with_next = next.map(|n| shared_bits(&key, n));
+                with_prev.max(with_next).unwrap_or(0) + 1
+            }
+        };
+        if depth > max_depth {
+            output.deep_leaves.record(key, depth);
+        }
+        prev = Some(key);
+    }
+}
+
+fn shared_bits(a: &KeyPath, b: &KeyPath) -> usize {
+    match a.iter().zip(b).position(|(x, y)| x != y) {
+        Some(i) => i * 8 + (a[i] ^ b[i]).leading_zeros() as usize,
+        None => 256,
+    }
+}
+
 fn subtrie_ops(read_write: &[(KeyPath, KeyReadWrite)]) -> Vec<(KeyPath, Option<ValueHash>)> {
     read_write
         .iter()
@@ -1,3 +1,10 @@
+//! Metrics of the internals of a database.
+//!
+//! When enabled with [`crate::Options::metrics`], a database collects counters and histograms of
+//! its page cache, I/O, syncs and beatree in a [`Metrics`] handle, returned by
+//! [`crate::Nomt::metrics`]. With the `prometheus` feature, `Metrics::render_prometheus` renders
+//! them in the Prometheus text exposition format.
+
 use std::sync::{
     atomic::{AtomicU64, Ordering},
     Arc,
@@ -5,42 +12,143 @@ use std::sync::{
 
 const METRICS_NOT_ENABLED: &str = "Metrics are not enabled";
 
-/// Metrics collector, if active, it provides Counters and Timers
+/// The number of buckets of a histogram bounded by powers of two, from `2^0` to `2^40`. Another
+/// bucket holds everything larger.
+const HISTOGRAM_BUCKETS: usize = 41;
+
+/// Metrics collector, if active, it provides Counters and Histograms
 #[derive(Clone)]
 pub struct Metrics {
     metrics: Option<Arc<ActiveMetrics>>,
 }
 
+/// A stage of persisting a commit, timed in the metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SyncStage {
+    /// Writing the updated pages to the write-ahead log.
+    Wal,
+    /// Writing the journal of a checkpointed sync. See [`crate::Options::sync_checkpoint_interval`].
+    Journal,
+    /// Writing the values to the beatree.
+    Values,
+    /// Writing the meta, which makes the commit durable.
+    Meta,
+    /// Writing the updated pages to the hash table and releasing the freed pages, after the meta.
+    PostMeta,
+}
+
+impl SyncStage {
+    const ALL: [SyncStage; 5] = [
+        SyncStage::Wal,
+        SyncStage::Journal,
+        SyncStage::Values,
+        SyncStage::Meta,
+        SyncStage::PostMeta,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    #[cfg(feature = "prometheus")]
+    fn label(self) -> &'static str {
+        match self {
+            SyncStage::Wal => "wal",
+            SyncStage::Journal => "journal",
+            SyncStage::Values => "values",
+            SyncStage::Meta => "meta",
+            SyncStage::PostMeta => "post_meta",
+        }
+    }
+}
+
 /// Metrics that can be collected during execution
 #[derive(PartialEq, Eq, Hash)]
-pub enum Metric {
+pub(crate) enum Metric {
     /// Counter of total page requests
     PageRequests,
     /// Counter of page requests cache misses over all page requests
     PageCacheMisses,
-    /// Timer used to record average page fetch time
+    /// Timer used to record page fetch times
     PageFetchTime,
-    /// Timer used to record average value fetch time during reads
+    /// Timer used to record value fetch times during reads
     ValueFetchTime,
+    /// Counter of leaves placed deeper than the maximum trie depth
+    DeepPaths,
+    /// Counter of keys written more than once within a session
+    DuplicateWrites,
+    /// Counter of leaves removed from the leaf cache for holding keys hinted cold
+    ColdLeavesUncached,
+    /// Counter of automatic hash table resizes which failed to start or failed
+    HashTableResizeFailures,
+    /// Histogram of the I/Os in flight on an io_uring after each submission
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    IoQueueDepth,
+    /// Timer used to record the latency of a stage of a sync
+    SyncTime(SyncStage),
+    /// Histogram of the number of keys in each leaf written by a sync
+    LeafFanout,
+    /// Counter of I/O completions delayed in chaos mode
+    #[cfg(feature = "chaos")]
+    ChaosIoDelays,
+    /// Counter of cache entries spuriously evicted in chaos mode
+    #[cfg(feature = "chaos")]
+    ChaosEvictions,
 }
 
 struct ActiveMetrics {
     page_requests: AtomicU64,
     page_cache_misses: AtomicU64,
-    page_fetch_time: Timer,
-    value_fetch_time: Timer,
+    page_fetch_time: AtomicHistogram,
+    value_fetch_time: AtomicHistogram,
+    deep_paths: AtomicU64,
+    duplicate_writes: AtomicU64,
+    cold_leaves_uncached: AtomicU64,
+    hashtable_resize_failures: AtomicU64,
+    io_queue_depth: AtomicHistogram,
+    sync_time: [AtomicHistogram; 5],
+    leaf_fanout: AtomicHistogram,
+    #[cfg(feature = "chaos")]
+    chaos_io_delays: AtomicU64,
+    #[cfg(feature = "chaos")]
+    chaos_evictions: AtomicU64,
+}
+
+impl ActiveMetrics {
+    fn histogram(&self, metric: &Metric) -> Option<&AtomicHistogram> {
+        match metric {
+            Metric::PageFetchTime => Some(&self.page_fetch_time),
+            Metric::ValueFetchTime => Some(&self.value_fetch_time),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Metric::IoQueueDepth => Some(&self.io_queue_depth),
+            Metric::SyncTime(stage) => Some(&self.sync_time[stage.index()]),
+            Metric::LeafFanout => Some(&self.leaf_fanout),
+            _ => None,
+        }
+    }
 }
 
 impl Metrics {
     /// Returns the Metrics object, active or not based on the specified input
-    pub fn new(active: bool) -> Self {
+    pub(crate) fn new(active: bool) -> Self {
         Self {
             metrics: if active {
                 Some(Arc::new(ActiveMetrics {
                     page_requests: AtomicU64::new(0),
                     page_cache_misses: AtomicU64::new(0),
-                    page_fetch_time: Timer::new(),
-                    value_fetch_time: Timer::new(),
+                    page_fetch_time: AtomicHistogram::new(),
+                    value_fetch_time: AtomicHistogram::new(),
+                    deep_paths: AtomicU64::new(0),
+                    duplicate_writes: AtomicU64::new(0),
+                    cold_leaves_uncached: AtomicU64::new(0),
+                    hashtable_resize_failures: AtomicU64::new(0),
+                    io_queue_depth: AtomicHistogram::new(),
+                    sync_time: std::array::from_fn(|_| AtomicHistogram::new()),
+                    leaf_fanout: AtomicHistogram::new(),
+                    #[cfg(feature = "chaos")]
+                    chaos_io_delays: AtomicU64::new(0),
+                    #[cfg(feature = "chaos")]
+                    chaos_evictions: AtomicU64::new(0),
                 }))
             } else {
                 None
@@ -48,33 +156,63 @@ impl Metrics {
         }
     }
 
+    /// Whether metrics are collected.
+    pub fn is_enabled(&self) -> bool {
+        self.metrics.is_some()
+    }
+
     /// Increase the Counter specified by the input
     ///
     /// panics if the specified [`Metric`] is not a Counter
-    pub fn count(&self, metric: Metric) {
+    pub(crate) fn count(&self, metric: Metric) {
+        self.count_n(metric, 1)
+    }
+
+    /// Increase the Counter specified by the input by `n`
+    ///
+    /// panics if the specified [`Metric`] is not a Counter
+    pub(crate) fn count_n(&self, metric: Metric, n: u64) {
         if let Some(ref metrics) = self.metrics {
             let counter = match metric {
                 Metric::PageRequests => &metrics.page_requests,
                 Metric::PageCacheMisses => &metrics.page_cache_misses,
+                Metric::DeepPaths => &metrics.deep_paths,
+                Metric::DuplicateWrites => &metrics.duplicate_writes,
+                Metric::ColdLeavesUncached => &metrics.cold_leaves_uncached,
+                Metric::HashTableResizeFailures => &metrics.hashtable_resize_failures,
+                #[cfg(feature = "chaos")]
+                Metric::ChaosIoDelays => &metrics.chaos_io_delays,
+                #[cfg(feature = "chaos")]
+                Metric::ChaosEvictions => &metrics.chaos_evictions,
                 _ => panic!("Specified metric is not a Counter"),
             };
 
-            counter.fetch_add(1, Ordering::Relaxed);
+            counter.fetch_add(n, Ordering::Relaxed);
         }
     }
 
-    /// Returns a guard that, when dropped, will record the time passed since creation
+    /// Add an observation to the Histogram specified by the input
     ///
-    /// panics if the specified [`Metric`] is not a Timer
-    pub fn record<'a>(&'a self, metric: Metric) -> Option<impl Drop + 'a> {
-        self.metrics.as_ref().and_then(|metrics| {
-            let timer = match metric {
-                Metric::PageFetchTime => &metrics.page_fetch_time,
-                Metric::ValueFetchTime => &metrics.value_fetch_time,
-                _ => panic!("Specified metric is not a Timer"),
-            };
+    /// panics if the specified [`Metric`] is not a Histogram
+    pub(crate) fn observe(&self, metric: Metric, value: u64) {
+        if let Some(ref metrics) = self.metrics {
+            metrics
+                .histogram(&metric)
+                .expect("Specified metric is not a Histogram")
+                .observe(value);
+        }
+    }
 
-            Some(timer.record())
+    /// Returns a guard that, when dropped, will record the time passed since creation, in
+    /// nanoseconds
+    ///
+    /// panics if the specified [`Metric`] is not a Histogram
+    pub(crate) fn record<'a>(&'a self, metric: Metric) -> Option<impl Drop + 'a> {
+        self.metrics.as_ref().map(|metrics| {
+            metrics
+                .histogram(&metric)
+                .expect("Specified metric is not a Histogram")
+                .record()
         })
     }
 
@@ -97,13 +235,56 @@ impl Metrics {
                 );
             }
 
-            if let Some(mean) = metrics.page_fetch_time.mean() {
+            if let Some(mean) = metrics.page_fetch_time.snapshot().mean() {
                 println!("  page fetch mean       {}", pretty_display_ns(mean));
             }
 
-            if let Some(mean) = metrics.value_fetch_time.mean() {
+            if let Some(mean) = metrics.value_fetch_time.snapshot().mean() {
                 println!("  value fetch mean      {}", pretty_display_ns(mean));
             }
+
+            for stage in SyncStage::ALL {
+                if let Some(mean) = metrics.sync_time[stage.index()].snapshot().mean() {
+                    let name = format!("sync {:?} mean", stage).to_lowercase();
+                    println!("  {:<21} {}", name, pretty_display_ns(mean));
+                }
+            }
+
+            if let Some(mean) = metrics.io_queue_depth.snapshot().mean() {
+                println!("  io queue depth mean   {}", mean);
+            }
+
+            if let Some(mean) = metrics.leaf_fanout.snapshot().mean() {
+                println!("  leaf fanout mean      {}", mean);
+            }
+
+            let deep_paths = metrics.deep_paths.load(Ordering::Relaxed);
+            if deep_paths != 0 {
+                println!("  deep paths            {}", deep_paths);
+            }
+
+            let duplicate_writes = metrics.duplicate_writes.load(Ordering::Relaxed);
+            if duplicate_writes != 0 {
+                println!("  duplicate writes      {}", duplicate_writes);
+            }
+
+            let cold_leaves_uncached = metrics.cold_leaves_uncached.load(Ordering::Relaxed);
+            if cold_leaves_uncached != 0 {
+                println!("  cold leaves uncached  {}", cold_leaves_uncached);
+            }
+
+            let resize_failures = metrics.hashtable_resize_failures.load(Ordering::Relaxed);
+            if resize_failures != 0 {
+                println!("  ht resize failures    {}", resize_failures);
+            }
+
+            #[cfg(feature = "chaos")]
+            {
+                let io_delays = metrics.chaos_io_delays.load(Ordering::Relaxed);
+                let evictions = metrics.chaos_evictions.load(Ordering::Relaxed);
+                println!("  chaos I/O delays      {}", io_delays);
+                println!("  chaos evictions       {}", evictions);
+            }
         } else {
             println!("Metrics collection was not activated")
         }
@@ -127,25 +308,298 @@ impl Metrics {
             .expect(METRICS_NOT_ENABLED)
     }
 
-    /// Average page fetch time.
+    /// The fraction of page requests served by the page cache.
     /// Returns None if there were no requests.
     /// Panics if metrics are not enabled.
-    pub fn get_page_fetch_time(&self) -> Option<u64> {
+    pub fn get_page_cache_hit_rate(&self) -> Option<f64> {
+        let requests = self.get_page_requests();
+        let misses = self.get_page_cache_misses();
+        (requests != 0).then(|| 1.0 - misses as f64 / requests as f64)
+    }
+
+    /// Counter of leaves placed deeper than the maximum trie depth.
+    /// Panics if metrics are not enabled.
+    pub fn get_deep_paths(&self) -> u64 {
+        self.metrics
+            .as_ref()
+            .map(|metrics| metrics.deep_paths.load(Ordering::Relaxed))
+            .expect(METRICS_NOT_ENABLED)
+    }
+
+    /// Counter of keys written more than once within a session.
+    /// Panics if metrics are not enabled.
+    pub fn get_duplicate_writes(&self) -> u64 {
+        self.metrics
+            .as_ref()
+            .map(|metrics| metrics.duplicate_writes.load(Ordering::Relaxed))
+            .expect(METRICS_NOT_ENABLED)
+    }
+
+    /// Counter of leaves removed from the leaf cache for holding keys hinted cold.
+    /// Panics if metrics are not enabled.
+    pub fn get_cold_leaves_uncached(&self) -> u64 {
+        self.metrics
+            .as_ref()
+            .map(|metrics| metrics.cold_leaves_uncached.load(Ordering::Relaxed))
+            .expect(METRICS_NOT_ENABLED)
+    }
+
+    /// Counter of automatic hash table resizes which failed to start or failed.
+    /// Panics if metrics are not enabled.
+    pub fn get_hashtable_resize_failures(&self) -> u64 {
         self.metrics
             .as_ref()
-            .map(|metrics| metrics.page_fetch_time.mean())
+            .map(|metrics| metrics.hashtable_resize_failures.load(Ordering::Relaxed))
             .expect(METRICS_NOT_ENABLED)
     }
 
+    /// Counter of I/O completions delayed in chaos mode.
+    /// Panics if metrics are not enabled.
+    #[cfg(feature = "chaos")]
+    pub fn get_chaos_io_delays(&self) -> u64 {
+        self.metrics
+            .as_ref()
+            .map(|metrics| metrics.chaos_io_delays.load(Ordering::Relaxed))
+            .expect(METRICS_NOT_ENABLED)
+    }
+
+    /// Counter of cache entries spuriously evicted in chaos mode.
+    /// Panics if metrics are not enabled.
+    #[cfg(feature = "chaos")]
+    pub fn get_chaos_evictions(&self) -> u64 {
+        self.metrics
+            .as_ref()
+            .map(|metrics| metrics.chaos_evictions.load(Ordering::Relaxed))
+            .expect(METRICS_NOT_ENABLED)
+    }
+
+    /// Average page fetch time.
+    /// Returns None if there were no requests.
+    /// Panics if metrics are not enabled.
+    pub fn get_page_fetch_time(&self) -> Option<u64> {
+        self.histogram(|metrics| &metrics.page_fetch_time).mean()
+    }
+
     /// Average value fetch time during reads.
     /// Returns None if there were no requests.
     /// Panics if metrics are not enabled.
     pub fn get_value_fetch_time(&self) -> Option<u64> {
+        self.histogram(|metrics| &metrics.value_fetch_time).mean()
+    }
+
+    /// Histogram of value fetch times during reads, in nanoseconds.
+    /// Panics if metrics are not enabled.
+    pub fn get_value_fetch_times(&self) -> Histogram {
+        self.histogram(|metrics| &metrics.value_fetch_time)
+    }
+
+    /// Histogram of the number of I/Os in flight on an io_uring after each submission. Empty
+    /// without io_uring.
+    /// Panics if metrics are not enabled.
+    pub fn get_io_queue_depth(&self) -> Histogram {
+        self.histogram(|metrics| &metrics.io_queue_depth)
+    }
+
+    /// Histogram of the latencies of a stage of syncs, in nanoseconds.
+    /// Panics if metrics are not enabled.
+    pub fn get_sync_time(&self, stage: SyncStage) -> Histogram {
+        self.histogram(|metrics| &metrics.sync_time[stage.index()])
+    }
+
+    /// Histogram of the number of keys in each leaf written by syncs.
+    /// Panics if metrics are not enabled.
+    pub fn get_leaf_fanout(&self) -> Histogram {
+        self.histogram(|metrics| &metrics.leaf_fanout)
+    }
+
+    fn histogram(&self, f: impl FnOnce(&ActiveMetrics) -> &AtomicHistogram) -> Histogram {
         self.metrics
             .as_ref()
-            .map(|metrics| metrics.value_fetch_time.mean())
+            .map(|metrics| f(metrics).snapshot())
             .expect(METRICS_NOT_ENABLED)
     }
+
+    /// Render the collected metrics in the Prometheus text exposition format, with names
+    /// prefixed by `nomt_`. Latencies are in seconds.
+    ///
+    /// Returns an empty string if metrics are not enabled.
+    #[cfg(feature = "prometheus")]
+    pub fn render_prometheus(&self) -> String {
+        use std::fmt::Write as _;
+
+        let Some(ref metrics) = self.metrics else {
+            return String::new();
+        };
+
+        let mut out = String::new();
+        let mut counter = |name: &str, help: &str, value: &AtomicU64| {
+            let _ = writeln!(out, "# HELP nomt_{name} {help}");
+            let _ = writeln!(out, "# TYPE nomt_{name} counter");
+            let _ = writeln!(out, "nomt_{name} {}", value.load(Ordering::Relaxed));
+        };
+        counter(
+            "page_requests_total",
+            "Pages requested from the page cache.",
+            &metrics.page_requests,
+        );
+        counter(
+            "page_cache_misses_total",
+            "Page requests which missed the page cache.",
+            &metrics.page_cache_misses,
+        );
+        counter(
+            "deep_paths_total",
+            "Leaves placed deeper than the maximum trie depth.",
+            &metrics.deep_paths,
+        );
+        counter(
+            "duplicate_writes_total",
+            "Keys written more than once within a session.",
+            &metrics.duplicate_writes,
+        );
+        counter(
+            "cold_leaves_uncached_total",
+            "Leaves removed from the leaf cache for holding keys hinted cold.",
+            &metrics.cold_leaves_uncached,
+        );
+        counter(
+            "hashtable_resize_failures_total",
+            "Automatic hash table resizes which failed to start or failed.",
+            &metrics.hashtable_resize_failures,
+        );
+        #[cfg(feature = "chaos")]
+        {
+            counter(
+                "chaos_io_delays_total",
+                "I/O completions delayed in chaos mode.",
+                &metrics.chaos_io_delays,
+            );
+            counter(
+                "chaos_evictions_total",
+                "Cache entries spuriously evicted in chaos mode.",
+                &metrics.chaos_evictions,
+            );
+        }
+
+        if let Some(hit_rate) = self.get_page_cache_hit_rate() {
+            let _ = writeln!(
+                out,
+                "# HELP nomt_page_cache_hit_ratio Fraction of page requests served by the page cache."
+            );
+            let _ = writeln!(out, "# TYPE nomt_page_cache_hit_ratio gauge");
+            let _ = writeln!(out, "nomt_page_cache_hit_ratio {hit_rate}");
+        }
+
+        let nanos = 1e-9;
+        let mut histogram = |name: &str, help: &str, series: &[(&str, Histogram)], scale: f64| {
+            let _ = writeln!(out, "# HELP nomt_{name} {help}");
+            let _ = writeln!(out, "# TYPE nomt_{name} histogram");
+            for (labels, histogram) in series {
+                histogram.render_prometheus(&mut out, name, labels, scale);
+            }
+        };
+        histogram(
+            "page_fetch_seconds",
+            "Latency of page fetches.",
+            &[("", metrics.page_fetch_time.snapshot())],
+            nanos,
+        );
+        histogram(
+            "value_fetch_seconds",
+            "Latency of value reads.",
+            &[("", metrics.value_fetch_time.snapshot())],
+            nanos,
+        );
+        let sync_times = SyncStage::ALL.map(|stage| {
+            (
+                format!("phase=\"{}\"", stage.label()),
+                metrics.sync_time[stage.index()].snapshot(),
+            )
+        });
+        histogram(
+            "sync_phase_seconds",
+            "Latency of the phases of syncs.",
+            &sync_times
+                .iter()
+                .map(|(labels, histogram)| (labels.as_str(), histogram.clone()))
+                .collect::<Vec<_>>(),
+            nanos,
+        );
+        histogram(
+            "io_queue_depth",
+            "I/Os in flight on an io_uring after each submission.",
+            &[("", metrics.io_queue_depth.snapshot())],
+            1.0,
+        );
+        histogram(
+            "beatree_leaf_fanout",
+            "Keys in each leaf written by syncs.",
+            &[("", metrics.leaf_fanout.snapshot())],
+            1.0,
+        );
+        out
+    }
+}
+
+/// A snapshot of a histogram of observations, bucketed by powers of two.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    /// The inclusive upper bound of each bucket along with the number of observations in it, in
+    /// ascending order. The bounds are `2^0` to `2^40`, followed by `u64::MAX` for the rest.
+    pub buckets: Vec<(u64, u64)>,
+    /// The number of observations.
+    pub count: u64,
+    /// The sum of all observations.
+    pub sum: u64,
+}
+
+impl Histogram {
+    /// The mean of the observations. `None` if there are none.
+    pub fn mean(&self) -> Option<u64> {
+        self.sum.checked_div(self.count)
+    }
+
+    /// An upper bound on the given quantile of the observations, between 0 and 1: the bound of
+    /// the bucket holding it. `None` if there are no observations.
+    pub fn quantile(&self, q: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        self.buckets.iter().find_map(|(bound, n)| {
+            seen += n;
+            (seen >= rank).then_some(*bound)
+        })
+    }
+
+    #[cfg(feature = "prometheus")]
+    fn render_prometheus(&self, out: &mut String, name: &str, labels: &str, scale: f64) {
+        use std::fmt::Write as _;
+
+        let sep = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (bound, n) in &self.buckets[..HISTOGRAM_BUCKETS] {
+            cumulative += n;
+            let _ = writeln!(
+                out,
+                "nomt_{name}_bucket{{{labels}{sep}le=\"{}\"}} {cumulative}",
+                *bound as f64 * scale
+            );
+        }
+        let _ = writeln!(
+            out,
+            "nomt_{name}_bucket{{{labels}{sep}le=\"+Inf\"}} {}",
+            self.count
+        );
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{labels}}}")
+        };
+        let _ = writeln!(out, "nomt_{name}_sum{labels} {}", self.sum as f64 * scale);
+        let _ = writeln!(out, "nomt_{name}_count{labels} {}", self.count);
+    }
 }
 
 fn pretty_display_ns(ns: u64) -> String {
@@ -163,44 +617,101 @@ fn pretty_display_ns(ns: u64) -> String {
     format!("{val} {unit}")
 }
 
-struct Timer {
-    number_of_records: AtomicU64,
+struct AtomicHistogram {
+    buckets: [AtomicU64; HISTOGRAM_BUCKETS + 1],
+    count: AtomicU64,
     sum: AtomicU64,
 }
 
-impl Timer {
+impl AtomicHistogram {
     fn new() -> Self {
-        Timer {
-            number_of_records: AtomicU64::new(0),
+        AtomicHistogram {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
             sum: AtomicU64::new(0),
         }
     }
 
-    fn mean(&self) -> Option<u64> {
-        let n = self.number_of_records.load(Ordering::Relaxed);
-        let sum = self.sum.load(Ordering::Relaxed);
-        sum.checked_div(n)
+    fn observe(&self, value: u64) {
+        // the smallest power of two at least as large as the value.
+        let bucket = match value {
+            0 | 1 => 0,
+            _ => (64 - (value - 1).leading_zeros() as usize).min(HISTOGRAM_BUCKETS),
+        };
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Histogram {
+        let buckets = self
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(i, n)| {
+                let bound = if i < HISTOGRAM_BUCKETS {
+                    1 << i
+                } else {
+                    u64::MAX
+                };
+                (bound, n.load(Ordering::Relaxed))
+            })
+            .collect();
+        Histogram {
+            buckets,
+            count: self.count.load(Ordering::Relaxed),
+            sum: self.sum.load(Ordering::Relaxed),
+        }
     }
 
     fn record<'a>(&'a self) -> impl Drop + 'a {
         struct TimerGuard<'a> {
             start: std::time::Instant,
-            n: &'a AtomicU64,
-            sum: &'a AtomicU64,
+            histogram: &'a AtomicHistogram,
         }
 
         impl Drop for TimerGuard<'_> {
             fn drop(&mut self) {
                 let elapsed = self.start.elapsed().as_nanos() as u64;
-                self.n.fetch_add(1, Ordering::Relaxed);
-                self.sum.fetch_add(elapsed, Ordering::Relaxed);
+                self.histogram.observe(elapsed);
             }
         }
 
         TimerGuard {
             start: std::time::Instant::now(),
-            n: &self.number_of_records,
-            sum: &self.sum,
+            histogram: self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_powers_of_two() {
+        let histogram = AtomicHistogram::new();
+        for value in [0, 1, 2, 3, 4, 5, 1 << 40, (1 << 40) + 1, u64::MAX / 2] {
+            histogram.observe(value);
         }
+        let snapshot = histogram.snapshot();
+        let count = |bound: u64| {
+            snapshot
+                .buckets
+                .iter()
+                .find(|(b, _)| *b == bound)
+                .unwrap()
+                .1
+        };
+        assert_eq!(count(1), 2);
+        assert_eq!(count(2), 1);
+        assert_eq!(count(4), 2);
+        assert_eq!(count(8), 1);
+        assert_eq!(count(1 << 40), 1);
+        assert_eq!(count(u64::MAX), 2);
+        assert_eq!(snapshot.count, 9);
+        assert_eq!(snapshot.quantile(0.0), Some(1));
+        assert_eq!(snapshot.quantile(0.5), Some(4));
+        assert_eq!(snapshot.quantile(1.0), Some(u64::MAX));
     }
 }
@@ -0,0 +1,458 @@
+//! Migration of a database into a new key-hashing scheme or hash function.
+//!
+//! A migration streams every key-value pair out of a source database, maps each key to its new
+//! key path, and bulk-loads the result into a destination database in batches. The destination
+//! may use a different [`HashAlgorithm`] than the source, which makes this the path for changing
+//! the node or value hash function of an existing network.
+//!
+//! Progress is recorded in a checkpoint file after every batch, so an interrupted migration can
+//! be resumed by calling [`migrate`] again with the same source, destination and checkpoint.
+
+use crate::{
+    cancel,
+    system_keys::{self, SystemKey},
+    CancellationToken, Error, HashAlgorithm, KeyReadWrite, Nomt, Root, SessionParams,
+};
+use nomt_core::trie::KeyPath;
+use std::{
+    fs::File,
+    io::{Read as _, Write as _},
+    path::{Path, PathBuf},
+};
+
+const CHECKPOINT_MAGIC: [u8; 4] = *b"NMIG";
+const CHECKPOINT_VERSION: u32 = 1;
+const CHECKPOINT_SIZE: usize = 4 + 4 + 32 * 3 + 33 * 2 + 8 * 2;
+
+/// Options for a [`migrate`] run.
+#[derive(Debug, Clone)]
+pub struct MigrationOptions {
+    batch_size: usize,
+    checkpoint_path: Option<PathBuf>,
+    cancel: Option<CancellationToken>,
+}
+
+impl MigrationOptions {
+    /// Create a new `MigrationOptions` instance with the default values.
+    pub fn new() -> Self {
+        Self {
+            batch_size: 10_000,
+            checkpoint_path: None,
+            cancel: None,
+        }
+    }
+
+    /// Set the maximum number of keys committed to the destination per batch.
+    ///
+    /// Must be more than 0.
+    ///
+    /// Default: 10000.
+    pub fn batch_size(&mut self, batch_size: usize) {
+        assert!(batch_size > 0);
+        self.batch_size = batch_size;
+    }
+
+    /// Set the path of the checkpoint file.
+    ///
+    /// If set, the progress of the migration is recorded there after each batch and an existing
+    /// checkpoint is used to resume an interrupted migration. Without a checkpoint, the migration
+    /// must run to completion in one go.
+    ///
+    /// Default: none.
+    pub fn checkpoint_path(&mut self, path: impl Into<PathBuf>) {
+        self.checkpoint_path = Some(path.into());
+    }
+
+    /// Set a token for cancelling the migration.
+    ///
+    /// The token is checked between batches and before each commit. A cancelled migration fails
+    /// with [`Cancelled`](crate::Cancelled) and can be resumed from the checkpoint, if one is set.
+    ///
+    /// Default: none.
+    pub fn cancellation_token(&mut self, token: CancellationToken) {
+        self.cancel = Some(token);
+    }
+}
+
+impl Default for MigrationOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The outcome of a completed migration.
+#[derive(Debug, Clone)]
+pub struct MigrationReport {
+    /// The total number of keys written to the destination, including those written by earlier,
+    /// interrupted runs.
+    pub migrated: u64,
+    /// The number of batches committed during this run.
+    pub batches: u64,
+    /// Whether this run resumed from an existing checkpoint.
+    pub resumed: bool,
+    /// The root of the destination after the migration.
+    pub root: Root,
+}
+
+/// Migrate all key-value pairs from `source` into `dest`, mapping every key with `remap`.
+///
+/// `remap` is given the old key path and the value stored under it and must return the new key
+/// path. It must be injective: two source keys mapping to the same destination key is an error.
+///
+/// The destination must be empty unless a checkpoint of a previous run is present. The source
+/// must not be modified while the migration is in progress, including between an interrupted run
+/// and its resumption.
+///
+/// Each batch is committed to the destination as a single session. Values are re-hashed with the
+/// destination's hash function, so the migration fails with
+/// [`ValueNotStored`](crate::ValueNotStored) if the source holds a value of which only the hash
+/// is kept.
+///
+/// If the destination reserves the system keyspace, the system values of the source are not
+/// migrated, and every batch records the root of the source as
+/// [`SystemKey::MigrationSource`] in the destination.
+pub fn migrate<Old: HashAlgorithm, New: HashAlgorithm>(
+    source: &Nomt<Old>,
+    dest: &Nomt<New>,
+    options: &MigrationOptions,
+    mut remap: impl FnMut(&KeyPath, &[u8]) -> KeyPath,
+) -> anyhow::Result<MigrationReport> {
+    let source_root = source.root();
+
+    let existing = match options.checkpoint_path {
+        Some(ref path) if path.exists() => Some(Checkpoint::read(path)?),
+        _ => None,
+    };
+
+    let resumed = existing.is_some();
+    let mut progress = match existing {
+        Some(checkpoint) => checkpoint.resume_point(source_root, dest.root())?,
+        None => {
+            if !dest.is_empty() {
+                anyhow::bail!("migration: destination is not empty and there is no checkpoint");
+            }
+            Progress {
+                cursor: Cursor::Start,
+                migrated: 0,
+            }
+        }
+    };
+
+    let mut batches = 0;
+    loop {
+        cancel::check(options.cancel.as_ref())?;
+
+        let start = match progress.cursor {
+            Cursor::Start => [0; 32],
+            Cursor::After(ref key) => match next_key(key) {
+                Some(start) => start,
+                None => break,
+            },
+            Cursor::Done => break,
+        };
+
+        if source.root() != source_root {
+            anyhow::bail!("migration: source was modified during the migration");
+        }
+
+        // Read the next batch from the source. The iterator is dropped before committing so it
+        // doesn't hold up syncs of the source for longer than needed.
+        let mut batch = Vec::with_capacity(options.batch_size);
+        let mut last_source_key = None;
+        let mut exhausted = true;
+        for item in source.store.iter_values(start, None) {
+            let (key, value) = item?;
+            if batch.len() == options.batch_size {
+                exhausted = false;
+                break;
+            }
+            // The system values of the destination are managed by the destination.
+            if !(dest.reserve_system_keyspace && system_keys::is_system_key(&key)) {
+                batch.push((remap(&key, &value), value));
+            }
+            last_source_key = Some(key);
+        }
+
+        let next_cursor = match (exhausted, last_source_key) {
+            (true, _) | (false, None) => Cursor::Done,
+            (false, Some(key)) => Cursor::After(key),
+        };
+
+        if batch.is_empty() {
+            progress.cursor = next_cursor;
+            continue;
+        }
+
+        batch.sort_unstable_by_key(|(key, _)| *key);
+        for pair in batch.windows(2) {
+            if pair[0].0 == pair[1].0 {
+                anyhow::bail!(
+                    "migration: key collision at destination key {}",
+                    hex_key(&pair[0].0)
+                );
+            }
+        }
+
+        let prev_dest_root = dest.root();
+        let session = dest.begin_session(SessionParams::default());
+        for (key, _) in &batch {
+            if session.read(*key)?.is_some() {
+                anyhow::bail!(
+                    "migration: key collision at destination key {}",
+                    hex_key(key)
+                );
+            }
+            session.warm_up(*key);
+        }
+
+        let written = batch.len() as u64;
+        let actuals = batch
+            .into_iter()
+            .map(|(key, value)| (key, KeyReadWrite::Write(Some(value))))
+            .collect();
+        let finished = session.finish_with_system_values(
+            actuals,
+            vec![(
+                SystemKey::MigrationSource,
+                source_root.into_inner().to_vec(),
+            )],
+        )?;
+
+        let next_progress = Progress {
+            cursor: next_cursor,
+            migrated: progress.migrated + written,
+        };
+
+        cancel::check(options.cancel.as_ref())?;
+
+        // The checkpoint is written before the commit and records both the prior and the next
+        // progress. On resumption, the destination root tells which one of them is current.
+        if let Some(ref path) = options.checkpoint_path {
+            Checkpoint {
+                source_root,
+                prev_dest_root,
+                dest_root: finished.root(),
+                prev: progress,
+                next: next_progress.clone(),
+            }
+            .write(path)?;
+        }
+
+        finished.commit(dest).map_err(Error::into_anyhow)?;
+        progress = next_progress;
+        batches += 1;
+    }
+
+    if let Some(ref path) = options.checkpoint_path {
+        let root = dest.root();
+        Checkpoint {
+            source_root,
+            prev_dest_root: root,
+            dest_root: root,
+            prev: progress.clone(),
+            next: progress.clone(),
+        }
+        .write(path)?;
+    }
+
+    Ok(MigrationReport {
+        migrated: progress.migrated,
+        batches,
+        resumed,
+        root: dest.root(),
+    })
+}
+
+/// The position of the migration within the source keyspace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Cursor {
+    /// Nothing has been migrated yet.
+    Start,
+    /// All keys up to and including this one have been migrated.
+    After(KeyPath),
+    /// All keys have been migrated.
+    Done,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Progress {
+    cursor: Cursor,
+    migrated: u64,
+}
+
+/// The on-disk record of migration progress.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Checkpoint {
+    source_root: Root,
+    /// The root of the destination before the last batch was committed.
+    prev_dest_root: Root,
+    /// The root of the destination after the last batch was committed.
+    dest_root: Root,
+    /// The progress before the last batch.
+    prev: Progress,
+    /// The progress after the last batch.
+    next: Progress,
+}
+
+impl Checkpoint {
+    fn resume_point(self, source_root: Root, dest_root: Root) -> anyhow::Result<Progress> {
+        if self.source_root != source_root {
+            anyhow::bail!(
+                "migration: source root changed since the checkpoint (expected {:?}, got {:?})",
+                self.source_root,
+                source_root,
+            );
+        }
+
+        if dest_root == self.dest_root {
+            Ok(self.next)
+        } else if dest_root == self.prev_dest_root {
+            // The last batch was never committed.
+            Ok(self.prev)
+        } else {
+            anyhow::bail!(
+                "migration: destination root {:?} does not match the checkpoint",
+                dest_root
+            )
+        }
+    }
+
+    fn encode(&self) -> [u8; CHECKPOINT_SIZE] {
+        let mut buf = [0u8; CHECKPOINT_SIZE];
+        buf[0..4].copy_from_slice(&CHECKPOINT_MAGIC);
+        buf[4..8].copy_from_slice(&CHECKPOINT_VERSION.to_le_bytes());
+        buf[8..40].copy_from_slice(self.source_root.as_ref());
+        buf[40..72].copy_from_slice(self.prev_dest_root.as_ref());
+        buf[72..104].copy_from_slice(self.dest_root.as_ref());
+        encode_cursor(&self.prev.cursor, &mut buf[104..137]);
+        encode_cursor(&self.next.cursor, &mut buf[137..170]);
+        buf[170..178].copy_from_slice(&self.prev.migrated.to_le_bytes());
+        buf[178..186].copy_from_slice(&self.next.migrated.to_le_bytes());
+        buf
+    }
+
+    fn decode(buf: &[u8; CHECKPOINT_SIZE]) -> anyhow::Result<Self> {
+        if buf[0..4] != CHECKPOINT_MAGIC {
+            anyhow::bail!("migration checkpoint: invalid magic");
+        }
+        let version = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+        if version != CHECKPOINT_VERSION {
+            anyhow::bail!("migration checkpoint: unsupported version {}", version);
+        }
+        let root = |range: std::ops::Range<usize>| -> Root {
+            let bytes: [u8; 32] = buf[range].try_into().unwrap();
+            Root::from(bytes)
+        };
+        Ok(Self {
+            source_root: root(8..40),
+            prev_dest_root: root(40..72),
+            dest_root: root(72..104),
+            prev: Progress {
+                cursor: decode_cursor(&buf[104..137])?,
+                migrated: u64::from_le_bytes(buf[170..178].try_into().unwrap()),
+            },
+            next: Progress {
+                cursor: decode_cursor(&buf[137..170])?,
+                migrated: u64::from_le_bytes(buf[178..186].try_into().unwrap()),
+            },
+        })
+    }
+
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        let mut buf = [0u8; CHECKPOINT_SIZE];
+        File::open(path)?.read_exact(&mut buf)?;
+        Self::decode(&buf)
+    }
+
+    /// Atomically replace the checkpoint file at the given path.
+    fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&self.encode())?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&tmp_path, path)?;
+        // The rename is only durable once the directory holding the checkpoint is synced.
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        crate::sys::sync_dir_path(dir)?;
+        Ok(())
+    }
+}
+
+fn encode_cursor(cursor: &Cursor, buf: &mut [u8]) {
+    match cursor {
+        Cursor::Start => buf[0] = 0,
+        Cursor::After(key) => {
+            buf[0] = 1;
+            buf[1..33].copy_from_slice(key);
+        }
+        Cursor::Done => buf[0] = 2,
+    }
+}
+
+fn decode_cursor(buf: &[u8]) -> anyhow::Result<Cursor> {
+    match buf[0] {
+        0 => Ok(Cursor::Start),
+        1 => Ok(Cursor::After(buf[1..33].try_into().unwrap())),
+        2 => Ok(Cursor::Done),
+        tag => anyhow::bail!("migration checkpoint: invalid cursor tag {}", tag),
+    }
+}
+
+/// The smallest key path greater than the given one. `None` if the key is the maximum.
+fn next_key(key: &KeyPath) -> Option<KeyPath> {
+    let mut next = *key;
+    for byte in next.iter_mut().rev() {
+        if *byte == u8::MAX {
+            *byte = 0;
+        } else {
+            *byte += 1;
+            return Some(next);
+        }
+    }
+    None
+}
+
+fn hex_key(key: &KeyPath) -> String {
+    key.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{next_key, Checkpoint, Cursor, Progress};
+    use crate::Root;
+
+    #[test]
+    fn next_key_carries() {
+        let mut key = [0u8; 32];
+        key[31] = 0xff;
+        let mut expected = [0u8; 32];
+        expected[30] = 1;
+        assert_eq!(next_key(&key), Some(expected));
+        assert_eq!(next_key(&[0xff; 32]), None);
+    }
+
+    #[test]
+    fn checkpoint_roundtrip() {
+        let checkpoint = Checkpoint {
+            source_root: Root::from([1; 32]),
+            prev_dest_root: Root::from([2; 32]),
+            dest_root: Root::from([3; 32]),
+            prev: Progress {
+                cursor: Cursor::Start,
+                migrated: 0,
+            },
+            next: Progress {
+                cursor: Cursor::After([4; 32]),
+                migrated: 1000,
+            },
+        };
+        assert_eq!(
+            Checkpoint::decode(&checkpoint.encode()).unwrap(),
+            checkpoint
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! Several logical tries in one database.
+//!
+//! A [`Namespace`] is the subtree of all keys beginning with a fixed prefix of up to
+//! [`MAX_NAMESPACE_BITS`] bits, e.g. one for state, one for receipts and one for contract storage.
+//! The namespaces of a database share its I/O, caches and syncs, and a commit updates all of them
+//! at once under a single root.
+//!
+//! The root of a namespace is the node of the trie at its prefix: the root of the subtree of its
+//! keys. It changes only when the keys of the namespace do, and is proven against the root of the
+//! database by a [`PrefixProof`]. A session begun with
+//! [`SessionParams::namespace`](crate::SessionParams::namespace) may only access the keys of its
+//! namespace, so its witness covers nothing else.
+//!
+//! Keys are placed in a namespace with [`Namespace::key_path`], which replaces their first bits
+//! with the prefix. Those bits are lost, so keys should be hashes whose remaining bits are still
+//! unique. The system keyspace (see [`system_keys`](crate::system_keys)) lies within the last
+//! namespace.
+//!
+//! With [`Options::namespace_bits`](crate::Options::namespace_bits), every commit records the
+//! roots of all the namespaces it published in a [`CommitStats`]. A commit switches the roots of
+//! all namespaces at once, so these are never a mix of roots of different commits.
+
+use crate::{HashAlgorithm, Root};
+use bitvec::prelude::*;
+use nomt_core::{
+    proof::{PathProofTerminal, PrefixNode, PrefixProof},
+    trie::{KeyPath, Node, TERMINATOR},
+};
+
+/// The maximum number of bits of a namespace prefix.
+pub const MAX_NAMESPACE_BITS: usize = 8;
+
+/// One of `2^bits` namespaces, holding the keys whose first `bits` bits are its index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Namespace {
+    index: u8,
+    bits: u8,
+}
+
+impl Namespace {
+    /// The namespace with the given index, out of `2^bits`.
+    ///
+    /// Panics if `bits` is zero or more than [`MAX_NAMESPACE_BITS`], or if the index is not below
+    /// `2^bits`.
+    pub fn new(index: usize, bits: usize) -> Self {
+        assert!(
+            bits > 0 && bits <= MAX_NAMESPACE_BITS,
+            "namespaces must have between 1 and {} bits",
+            MAX_NAMESPACE_BITS
+        );
+        assert!(index < 1 << bits, "namespace index out of range");
+        Namespace {
+            index: index as u8,
+            bits: bits as u8,
+        }
+    }
+
+    /// The index of the namespace.
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    /// The number of bits of the prefix.
+    pub fn bits(&self) -> usize {
+        self.bits as usize
+    }
+
+    /// The prefix of the keys of the namespace.
+    pub fn prefix(&self) -> BitVec<u8, Msb0> {
+        let byte = [self.index << (8 - self.bits)];
+        byte.view_bits::<Msb0>()[..self.bits()].to_bitvec()
+    }
+
+    /// Place a key in the namespace, replacing its first bits with the prefix.
+    pub fn key_path(&self, mut key: KeyPath) -> KeyPath {
+        let mask = 0xFFu8.checked_shr(self.bits as u32).unwrap_or(0);
+        key[0] = (key[0] & mask) | (self.index << (8 - self.bits));
+        key
+    }
+
+    /// Whether the key is in the namespace.
+    pub fn contains(&self, key: &KeyPath) -> bool {
+        key[0] >> (8 - self.bits) == self.index
+    }
+
+    /// The root of the namespace given a proof of its prefix, such as that of
+    /// [`Session::prove_prefix`](crate::Session::prove_prefix). [`TERMINATOR`] if the namespace
+    /// is empty.
+    ///
+    /// The proof is not verified.
+    pub fn root_from_proof<T: HashAlgorithm>(&self, proof: &PrefixProof) -> Node {
+        match &proof.node {
+            PrefixNode::Internal(node) => *node,
+            // A single key is hoisted above the prefix.
+            PrefixNode::Terminal(PathProofTerminal::Leaf(leaf))
+                if self.contains(&leaf.key_path) =>
+            {
+                T::hash_leaf(leaf)
+            }
+            PrefixNode::Terminal(_) => TERMINATOR,
+        }
+    }
+}
+
+/// The roots published by a commit. See [`crate::Nomt::commit_stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitStats {
+    /// The root of the database.
+    pub root: Root,
+    /// The root of each namespace of [`Options::namespace_bits`](crate::Options::namespace_bits)
+    /// bits, by index. Empty if that is not set.
+    pub namespace_roots: Vec<Node>,
+}
+
+/// The error returned by [`Session::finish`](crate::Session::finish) when a session begun in a
+/// namespace accessed a key outside of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyOutsideNamespace {
+    /// The namespace of the session.
+    pub namespace: Namespace,
+    /// The first key accessed outside of it.
+    pub key: KeyPath,
+}
+
+impl std::fmt::Display for KeyOutsideNamespace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "key outside of namespace {} of {}",
+            self.namespace.index,
+            1u32 << self.namespace.bits
+        )
+    }
+}
+
+impl std::error::Error for KeyOutsideNamespace {}
@@ -1,22 +1,42 @@
-use std::path::PathBuf;
+use crate::{
+    read_repair::ReadRepairConfig,
+    read_through::{ReadThroughConfig, RemoteArchive},
+    rollback::DiskAlert,
+    yielding::YieldHook,
+    Cancelled, RollbackDiskUsage, Root, SyncPhase, YieldPoint,
+};
+use std::{
+    path::PathBuf,
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
 
 /// Options when opening a [`crate::Nomt`] instance.
 #[derive(Debug)]
 pub struct Options {
     /// The path to the directory where the trie is stored.
     pub(crate) path: PathBuf,
+    /// The directories of the files placed outside of `path`.
+    pub(crate) layout: StorageLayout,
     /// The number of commit workers. Values over 64 will be rounded down to 64.
     pub(crate) commit_concurrency: usize,
+    pub(crate) beatree_sync_workers: Option<usize>,
     /// The number of io_uring instances, or I/O threads on non-Linux platforms.
     pub(crate) io_workers: usize,
     /// Enable or disable metrics collection.
     pub(crate) metrics: bool,
     pub(crate) bitbox_num_pages: u32,
     pub(crate) bitbox_seed: [u8; 16],
+    pub(crate) hashtable_auto_resize: Option<f64>,
     pub(crate) panic_on_sync: Option<PanicOnSyncMode>,
+    pub(crate) simulate_out_of_space: Option<(SyncPhase, Arc<AtomicBool>)>,
     pub(crate) rollback: bool,
     /// The maximum number of commits that can be rolled back.
     pub(crate) max_rollback_log_len: u32,
+    /// The maximum number of segments and bytes of obsolete rollback log segments to keep.
+    pub(crate) rollback_log_archive: (usize, u64),
+    pub(crate) rollback_disk_alert: Option<DiskAlert>,
+    pub(crate) rollback_cache_policy: RollbackCachePolicy,
     pub(crate) warm_up: bool,
     /// Whether to preallocate the hashtable file.
     pub(crate) preallocate_ht: bool,
@@ -26,10 +46,35 @@ pub struct Options {
     /// The maximum size of the leaf cache specified in MiB, rounded down
     /// to the nearest byte multiple of [`crate::io::PAGE_SIZE`].
     pub(crate) leaf_cache_size: usize,
-    /// Whether to prepopulate the upper layers of the page cache on startup.
+    /// Whether to prepopulate the upper layers of the page cache in the background on startup.
     /// This incurs some I/O on startup but leads to predictable worst-case performance.
     pub(crate) prepopulate_page_cache: bool,
+    /// The maximum number of pages loaded per second while prepopulating the page cache.
+    pub(crate) prepopulate_page_cache_rate: Option<u32>,
     pub(crate) page_cache_upper_levels: usize,
+    /// The maximum size of the cache file specified in MiB. 0 disables the cache file.
+    pub(crate) cache_file_size: usize,
+    pub(crate) read_through: Option<ReadThroughConfig>,
+    pub(crate) read_repair: Option<ReadRepairConfig>,
+    /// The file to log accesses to and the fraction of sessions logged.
+    pub(crate) access_log: Option<(PathBuf, f64)>,
+    pub(crate) sync_checkpoint_interval: Option<usize>,
+    pub(crate) wal_compression: bool,
+    pub(crate) compression: Compression,
+    pub(crate) max_trie_depth: Option<(usize, DeepPathPolicy)>,
+    pub(crate) duplicate_write_policy: DuplicateWritePolicy,
+    pub(crate) write_throttle_target: Option<Duration>,
+    pub(crate) overlay_memory_budget: Option<usize>,
+    pub(crate) prefix_write_stats: Option<(usize, usize)>,
+    pub(crate) read_backend: ReadBackend,
+    pub(crate) reserve_system_keyspace: bool,
+    pub(crate) namespace_bits: Option<usize>,
+    pub(crate) upgrade_format: bool,
+    pub(crate) yield_hook: Option<YieldHook>,
+    /// Set by [`crate::Nomt::open_read_only`].
+    pub(crate) read_only: bool,
+    #[cfg(feature = "chaos")]
+    pub(crate) chaos: Option<crate::ChaosConfig>,
 }
 
 impl Options {
@@ -39,20 +84,48 @@ impl Options {
 
         Self {
             path: PathBuf::from("nomt_db"),
+            layout: StorageLayout::default(),
             commit_concurrency: 1,
+            beatree_sync_workers: None,
             io_workers: 3,
             metrics: false,
             bitbox_num_pages: 64_000,
             bitbox_seed,
+            hashtable_auto_resize: None,
             panic_on_sync: None,
+            simulate_out_of_space: None,
             rollback: false,
             max_rollback_log_len: 100,
+            rollback_log_archive: (0, 0),
+            rollback_disk_alert: None,
+            rollback_cache_policy: RollbackCachePolicy::Patch,
             warm_up: false,
             preallocate_ht: true,
             page_cache_size: 256,
             leaf_cache_size: 256,
             prepopulate_page_cache: false,
+            prepopulate_page_cache_rate: None,
             page_cache_upper_levels: 2,
+            cache_file_size: 0,
+            read_through: None,
+            read_repair: None,
+            access_log: None,
+            sync_checkpoint_interval: None,
+            wal_compression: false,
+            compression: Compression::None,
+            max_trie_depth: None,
+            duplicate_write_policy: DuplicateWritePolicy::LastWriteWins,
+            write_throttle_target: None,
+            overlay_memory_budget: None,
+            read_backend: ReadBackend::Io,
+            prefix_write_stats: None,
+            reserve_system_keyspace: false,
+            namespace_bits: None,
+            upgrade_format: false,
+            yield_hook: None,
+            read_only: false,
+            #[cfg(feature = "chaos")]
+            chaos: None,
         }
     }
 
@@ -64,6 +137,18 @@ impl Options {
         self.path = path.into();
     }
 
+    /// Place some of the database files in directories other than [`Options::path`], for example
+    /// to keep the WAL on a low-latency device.
+    ///
+    /// The layout is fixed when the database is created. When opening an existing database, the
+    /// files are found wherever they were placed, and opening fails if a directory given here
+    /// differs from the one holding the file. See [`StorageLayout`].
+    ///
+    /// Default: all files in [`Options::path`].
+    pub fn layout(&mut self, layout: StorageLayout) {
+        self.layout = layout;
+    }
+
     /// Set the maximum number of concurrent commit workers.
     ///
     /// Values over 64 will be rounded down to 64.
@@ -73,6 +158,19 @@ impl Options {
         self.commit_concurrency = commit_concurrency;
     }
 
+    /// Set the number of workers writing out the value store during sync.
+    ///
+    /// The changed keys are split into that many ranges, whose leaves, overflow values and
+    /// branches are written out in parallel.
+    ///
+    /// Values over 64 will be rounded down to 64. May not be zero.
+    ///
+    /// Default: the commit concurrency.
+    pub fn beatree_sync_workers(&mut self, workers: usize) {
+        assert!(workers > 0);
+        self.beatree_sync_workers = Some(workers);
+    }
+
     /// Set metrics collection on or off.
     ///
     /// Default: off.
@@ -93,6 +191,22 @@ impl Options {
         self.bitbox_num_pages = hashtable_buckets;
     }
 
+    /// Start resizing the hash table to double its buckets whenever a commit leaves more than
+    /// `load_factor` of them occupied. See [`crate::Nomt::resize_hash_table`].
+    ///
+    /// After a resize fails to start or fails, the next is only tried after twice as many commits
+    /// as after the previous failure, up to 1024. Failures are counted by
+    /// [`crate::metrics::Metrics::get_hashtable_resize_failures`]. `load_factor` must be between
+    /// 0 and 1.
+    ///
+    /// Opening fails if this is combined with [`ReadBackend::Mmap`].
+    ///
+    /// Default: disabled.
+    pub fn hashtable_auto_resize(&mut self, load_factor: f64) {
+        assert!((0.0..=1.0).contains(&load_factor));
+        self.hashtable_auto_resize = Some(load_factor);
+    }
+
     /// Set the seed for the hash function used by the bitbox store.
     ///
     /// Useful for reproducibility.
@@ -107,6 +221,14 @@ impl Options {
         self.panic_on_sync = Some(mode);
     }
 
+    /// Fail the given phase of sync as if the disk were full, for as long as `trigger` is set.
+    ///
+    /// The work of the phase is done, and then undone. Useful to test the handling of
+    /// [`crate::OutOfSpace`].
+    pub fn simulate_out_of_space(&mut self, phase: SyncPhase, trigger: Arc<AtomicBool>) {
+        self.simulate_out_of_space = Some((phase, trigger));
+    }
+
     /// Set to `true` to enable rolling back committed sessions.
     pub fn rollback(&mut self, rollback: bool) {
         self.rollback = rollback;
@@ -121,6 +243,56 @@ impl Options {
         self.max_rollback_log_len = max_rollback_log_len;
     }
 
+    /// Keep obsolete segments of the rollback log for diagnostics, instead of deleting them.
+    ///
+    /// Segments become obsolete when they no longer hold any commit which can be rolled back,
+    /// e.g. after a crash interrupted pruning of the log. When the database is opened, obsolete
+    /// segments are moved to an archive in the database directory, and the oldest archived
+    /// segments are deleted until at most `max_segments` segments totaling at most `max_bytes`
+    /// remain. See [`crate::Nomt::rollback_log_archive_stats`].
+    ///
+    /// Only relevant if rollback is enabled.
+    ///
+    /// Default: 0 segments and 0 bytes, i.e. obsolete segments are deleted.
+    pub fn rollback_log_archive(&mut self, max_segments: usize, max_bytes: u64) {
+        self.rollback_log_archive = (max_segments, max_bytes);
+    }
+
+    /// Set what happens to the page and leaf caches when commits are rolled back.
+    ///
+    /// A rollback writes the restored values like any commit, so the cached pages and leaves it
+    /// touches are patched in place and all others stay valid. Clearing the caches instead
+    /// makes every read after a rollback go to disk until the caches are warm again, which is
+    /// only useful to rule out the caches when diagnosing a rollback. The effect of the last
+    /// rollback on the caches is reported by [`crate::Nomt::rollback_cache_stats`].
+    ///
+    /// Only relevant if rollback is enabled.
+    ///
+    /// Default: [`RollbackCachePolicy::Patch`].
+    pub fn rollback_cache_policy(&mut self, policy: RollbackCachePolicy) {
+        self.rollback_cache_policy = policy;
+    }
+
+    /// Call `alert` when the rollback log takes more than `max_fraction` of the file system
+    /// holding the database.
+    ///
+    /// The disk usage is checked at the end of every commit. The alert is called once when the
+    /// threshold is crossed, and again only after the log has dropped below it in the meantime,
+    /// e.g. because [`Options::max_rollback_log_len`] was lowered. It is called from a background
+    /// thread which the commit waits for, so it should return quickly. The current usage is
+    /// reported by [`crate::Nomt::rollback_stats`].
+    ///
+    /// Only relevant if rollback is enabled.
+    ///
+    /// Default: none.
+    pub fn rollback_disk_alert(
+        &mut self,
+        max_fraction: f64,
+        alert: impl Fn(RollbackDiskUsage) + Send + Sync + 'static,
+    ) {
+        self.rollback_disk_alert = Some(DiskAlert::new(max_fraction, Arc::new(alert)));
+    }
+
     /// Configure whether merkle page fetches should be warmed up while sessions are ongoing.
     ///
     /// Enabling this feature can pessimize performance.
@@ -164,12 +336,26 @@ impl Options {
     /// Sets whether to prepopulate the upper levels of the page cache on startup.
     /// Has no effect if [`Options::page_cache_upper_levels`] is set to 0.
     ///
+    /// The pages are loaded on a background thread, so opening the database does not wait for
+    /// them. Its progress is reported by [`crate::Nomt::page_cache_prepopulation`].
+    ///
     /// This incurs some I/O on startup but leads to predictable worst-case performance.
     /// Default: false
     pub fn prepopulate_page_cache(&mut self, prepopulate: bool) {
         self.prepopulate_page_cache = prepopulate;
     }
 
+    /// Limit the pages loaded by background prepopulation of the page cache to
+    /// `pages_per_second`, so that it competes less with foreground I/O.
+    ///
+    /// Applies to the prepopulation on startup and to
+    /// [`crate::Nomt::prepopulate_page_cache_in_background`].
+    ///
+    /// Default: unlimited.
+    pub fn prepopulate_page_cache_rate(&mut self, pages_per_second: u32) {
+        self.prepopulate_page_cache_rate = Some(pages_per_second.max(1));
+    }
+
     /// Sets the number of upper levels of the page tree to keep permanently
     /// cached.
     ///
@@ -180,6 +366,281 @@ impl Options {
     pub fn page_cache_upper_levels(&mut self, upper_levels: usize) {
         self.page_cache_upper_levels = upper_levels;
     }
+
+    /// Sets the maximum size of the cache file in MiB, or 0 to disable it.
+    ///
+    /// When enabled, the hottest contents of the page and leaf caches are written to a cache file
+    /// in the database directory when the database is dropped, and loaded back into the caches
+    /// when it is next opened, so that routine restarts do not begin with cold caches. The upper
+    /// levels of the page tree are kept first, followed by the most recently used leaves and
+    /// pages.
+    ///
+    /// The file is ignored if the database changed after it was written, for example when it was
+    /// not closed cleanly. This works independently of [`Options::prepopulate_page_cache`].
+    ///
+    /// Default: 0.
+    pub fn cache_file_size(&mut self, size: usize) {
+        self.cache_file_size = size;
+    }
+
+    /// Serve reads of values missing from the local database from a remote archive, as-of the
+    /// given target root.
+    ///
+    /// Fetched values are verified against `target_root` and can be written locally with
+    /// [`crate::Nomt::persist_fetched`]. Read-through is switched off once the local root equals
+    /// `target_root`. Only values are fetched, not merkle pages or leaves, so sessions which read
+    /// a value through can't be committed until it is persisted. See [`crate::read_through`] for
+    /// details.
+    ///
+    /// Default: disabled.
+    pub fn read_through(&mut self, archive: Arc<dyn RemoteArchive>, target_root: Root) {
+        self.read_through = Some(ReadThroughConfig {
+            archive,
+            target_root,
+        });
+    }
+
+    /// Check a random fraction of the values read by sessions against the merkle trie, and
+    /// repair damaged values from a remote archive, such as a replica.
+    ///
+    /// `sample_rate` is the fraction of reads checked, between 0 and 1. Reads failing with an I/O
+    /// error are repaired regardless. Repaired values can be written locally with
+    /// [`crate::Nomt::persist_repairs`]. See [`crate::read_repair`] for details.
+    ///
+    /// Default: disabled.
+    pub fn read_repair(&mut self, archive: Arc<dyn RemoteArchive>, sample_rate: f64) {
+        assert!((0.0..=1.0).contains(&sample_rate));
+        self.read_repair = Some(ReadRepairConfig {
+            archive,
+            sample_rate,
+        });
+    }
+
+    /// Split commits changing more than this many values into several internal syncs, each
+    /// concluded by a checkpoint on disk.
+    ///
+    /// This bounds the beatree work between two meta writes, and a crash in the middle of such a
+    /// commit resumes from the last checkpoint on the next open instead of discarding the whole
+    /// commit. The new root still becomes visible atomically, once the last chunk is written.
+    ///
+    /// The sync sequence number still advances by one per commit. This does not lower the total
+    /// latency of a commit, it raises it: the value changes of the commit are first written to a
+    /// journal file and synced, so every value is written twice, and each checkpoint adds a meta
+    /// write and its fsync. As the journal is written before the first checkpoint, its cost grows
+    /// with the commit. [`crate::WriteStats::journal_bytes`] and [`crate::SyncTimings::journal`]
+    /// measure it.
+    ///
+    /// Requires format version 4. See [`Options::upgrade_format`].
+    ///
+    /// Default: disabled.
+    pub fn sync_checkpoint_interval(&mut self, values: usize) {
+        self.sync_checkpoint_interval = Some(values.max(1));
+    }
+
+    /// Whether to compress the hash-table write-ahead log with LZ4.
+    ///
+    /// Each sync writes its WAL entries as a single compressed block, protected by a CRC32. This
+    /// trades CPU time during sync for fewer bytes written. The savings come from page
+    /// identifiers, diffs and bucket indices; node hashes do not compress.
+    ///
+    /// WALs written with and without compression can both be recovered, so this may be changed
+    /// between opens.
+    ///
+    /// Default: false.
+    pub fn wal_compression(&mut self, compress: bool) {
+        self.wal_compression = compress;
+    }
+
+    /// How to compress values too large to be stored in leaf nodes of the value store, such as
+    /// contract code.
+    ///
+    /// Each value is compressed on its own when it is written, unless zstd is given a dictionary
+    /// size, and stored compressed only if that makes it smaller. The codec is recorded along with the value, so values written with any
+    /// setting remain readable, and this may be changed between opens. Values compressed with zstd
+    /// can only be read with the `zstd` feature enabled.
+    ///
+    /// Smaller values are never compressed. Databases created by older versions of NOMT must be
+    /// upgraded with [`Options::upgrade_format`] to be opened with compression, after which older
+    /// versions can no longer open them.
+    ///
+    /// Default: [`Compression::None`].
+    pub fn compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Set the maximum depth of leaves in the trie and what to do with sessions exceeding it.
+    ///
+    /// With hashed keys, deep leaves only arise by chance. When key paths are chosen directly by
+    /// users, an adversary can pick keys sharing long prefixes and force paths up to 256 nodes
+    /// deep, which makes proofs of those keys correspondingly large. This bounds the size of any
+    /// proof.
+    ///
+    /// Leaves placed deeper than `max_depth` are counted in the [`crate::Nomt::metrics`] under
+    /// either policy.
+    ///
+    /// Default: unlimited.
+    pub fn max_trie_depth(&mut self, max_depth: usize, policy: DeepPathPolicy) {
+        self.max_trie_depth = Some((max_depth, policy));
+    }
+
+    /// Set what to do with sessions whose actuals write the same key more than once.
+    ///
+    /// The actuals passed to [`crate::Session::finish`] may list a key several times, as long
+    /// as they are sorted. The entries for a key are merged in order: the first read is kept and
+    /// the last write wins. Keys written more than once are reported by
+    /// [`crate::FinishedSession::duplicate_writes`] and counted in the [`crate::Nomt::metrics`]
+    /// under either policy.
+    ///
+    /// Default: [`DuplicateWritePolicy::LastWriteWins`].
+    pub fn duplicate_write_policy(&mut self, policy: DuplicateWritePolicy) {
+        self.duplicate_write_policy = policy;
+    }
+
+    /// Throttle background writes to keep the latency of foreground I/O within `target`.
+    ///
+    /// The latency of every foreground I/O, from submission to completion, is tracked as a moving
+    /// average. While it exceeds the target, the device is considered saturated and each
+    /// background write is delayed in proportion to the excess. Background writes are those not
+    /// needed for a commit to be durable: currently the hash-table writeout following the meta,
+    /// which the WAL already covers.
+    ///
+    /// This trades longer syncs for steadier reads and writes elsewhere. The total delay is
+    /// reported by [`crate::Nomt::background_write_delay`].
+    ///
+    /// Default: no throttling.
+    pub fn write_throttle_target(&mut self, target: Duration) {
+        self.write_throttle_target = Some(target);
+    }
+
+    /// Bound the memory used by the values of all live [`crate::Overlay`]s to `bytes`.
+    ///
+    /// Whenever a new overlay exceeds the budget, the values of the oldest live overlays are moved
+    /// to a temporary segment in the database directory until the rest fits, and are read back
+    /// from there on access. Merkle pages stay in memory, as do deletions. If the segment cannot
+    /// be written, the values stay in memory as well.
+    ///
+    /// The budget only counts the bytes of the values, not the keys or the bookkeeping.
+    ///
+    /// Default: unbounded.
+    pub fn overlay_memory_budget(&mut self, bytes: usize) {
+        self.overlay_memory_budget = Some(bytes);
+    }
+
+    /// Track the writes to keys grouped by their first `prefix_len` bytes over the last `window`
+    /// commits.
+    ///
+    /// This shows which parts of the key space are written the most, for example to tell which
+    /// modules of an application are worth moving to cheaper storage patterns. The hottest
+    /// prefixes are reported by [`crate::Nomt::hot_prefixes`]. Counts are kept in memory only and
+    /// start empty when the database is opened.
+    ///
+    /// `prefix_len` must be between 1 and 32 and `window` must be more than 0.
+    ///
+    /// Default: disabled.
+    pub fn prefix_write_stats(&mut self, prefix_len: usize, window: usize) {
+        assert!((1..=32).contains(&prefix_len));
+        assert!(window > 0);
+        self.prefix_write_stats = Some((prefix_len, window));
+    }
+
+    /// Log the keys accessed, with timestamps, to the file at `path`, which is replaced if it
+    /// exists. The log can be replayed against a copy of the database with
+    /// [`crate::access_log::replay`].
+    ///
+    /// `sample_rate` is the fraction of sessions and of reads outside of sessions logged, between
+    /// 0 and 1. The accesses of a logged session are logged in full. The log is flushed on every
+    /// logged commit and when the database is closed. See [`crate::access_log`] for the format.
+    ///
+    /// Default: disabled.
+    pub fn access_log(&mut self, path: impl Into<PathBuf>, sample_rate: f64) {
+        assert!((0.0..=1.0).contains(&sample_rate));
+        self.access_log = Some((path.into(), sample_rate));
+    }
+
+    /// Set how pages of the hash-table and the leaf store are read.
+    ///
+    /// See [`ReadBackend`].
+    ///
+    /// Default: [`ReadBackend::Io`].
+    pub fn read_backend(&mut self, read_backend: ReadBackend) {
+        self.read_backend = read_backend;
+    }
+
+    /// Reserve the system keyspace for metadata managed by NOMT, which can then be proven under
+    /// the root of the trie.
+    ///
+    /// Sessions writing keys in the system keyspace fail. The first commit to a database without
+    /// a [`SystemKey::FormatVersion`](crate::system_keys::SystemKey::FormatVersion) writes it,
+    /// which changes the root. See [`crate::system_keys`].
+    ///
+    /// Default: false.
+    pub fn reserve_system_keyspace(&mut self, reserve: bool) {
+        self.reserve_system_keyspace = reserve;
+    }
+
+    /// Upgrade a database created by an older version of NOMT to the current format on opening.
+    ///
+    /// Databases of older formats are otherwise left in their format, which older versions of
+    /// NOMT can still open, and lack what it doesn't hold: databases of format version 1 have
+    /// the nil [`crate::Lineage`] identifier and don't record their clones and rollbacks, and
+    /// databases of format versions before 3 can't have values compressed with
+    /// [`Options::compression`], and databases of format versions before 4 can't use
+    /// [`Options::sync_checkpoint_interval`]. Once upgraded, a database can no longer be opened by older
+    /// versions. Opening read-only fails if an upgrade is due.
+    ///
+    /// Default: false.
+    pub fn upgrade_format(&mut self, upgrade: bool) {
+        self.upgrade_format = upgrade;
+    }
+
+    /// Record the roots of the namespaces of `bits` bits published by every commit, which
+    /// [`crate::Nomt::commit_stats`] returns. See [`crate::namespace`].
+    ///
+    /// Each commit proves the prefixes of all `2^bits` namespaces once it is done.
+    ///
+    /// Panics if `bits` is zero or more than
+    /// [`MAX_NAMESPACE_BITS`](crate::namespace::MAX_NAMESPACE_BITS).
+    ///
+    /// Default: none.
+    pub fn namespace_bits(&mut self, bits: usize) {
+        assert!(
+            bits > 0 && bits <= crate::namespace::MAX_NAMESPACE_BITS,
+            "namespaces must have between 1 and {} bits",
+            crate::namespace::MAX_NAMESPACE_BITS
+        );
+        self.namespace_bits = Some(bits);
+    }
+
+    /// Call `hook` after every `every` units of long CPU-bound work done while finishing a
+    /// session: updating the merkle trie, sorting, and building the witness. See [`YieldPoint`]
+    /// for the units of each.
+    ///
+    /// The hook may yield the thread, record progress or check a deadline. Returning
+    /// [`Cancelled`] abandons the session, whose [`crate::Session::finish`] then fails with an
+    /// error which downcasts to [`Cancelled`]. The hook is called concurrently from the commit
+    /// worker threads as well as the thread finishing the session.
+    ///
+    /// Default: none.
+    pub fn yield_hook(
+        &mut self,
+        every: usize,
+        hook: impl Fn(YieldPoint) -> Result<(), Cancelled> + Send + Sync + 'static,
+    ) {
+        self.yield_hook = Some(YieldHook::new(every, Arc::new(hook)));
+    }
+
+    /// Enable chaos mode, injecting the faults described by `config`.
+    ///
+    /// Meant for canary deployments: I/O completions are occasionally delayed and the caches
+    /// occasionally evict more than they need to, surfacing latent race conditions under real
+    /// workloads. Results are unaffected. When metrics are enabled, injected faults are counted
+    /// in [`crate::Nomt::metrics`].
+    ///
+    /// Default: disabled.
+    #[cfg(feature = "chaos")]
+    pub fn chaos(&mut self, config: crate::ChaosConfig) {
+        self.chaos = Some(config);
+    }
 }
 
 #[test]
@@ -188,6 +649,121 @@ fn page_size_is_4096() {
     assert_eq!(crate::io::PAGE_SIZE, 4096);
 }
 
+/// What to do with sessions placing leaves deeper than [`Options::max_trie_depth`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeepPathPolicy {
+    /// Fail [`crate::Session::finish`] with a [`crate::TrieDepthExceeded`] error.
+    Reject,
+    /// Allow the session.
+    Allow,
+}
+
+/// What to do with the caches when commits are rolled back. See
+/// [`Options::rollback_cache_policy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RollbackCachePolicy {
+    /// Patch the cached pages and leaves touched by the rollback, keeping all others.
+    Patch,
+    /// Clear the caches after the rollback, except for the pages in
+    /// [`Options::page_cache_upper_levels`].
+    Clear,
+}
+
+/// What to do with sessions writing a key more than once. See
+/// [`Options::duplicate_write_policy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicateWritePolicy {
+    /// Keep the last write of each key.
+    LastWriteWins,
+    /// Fail [`crate::Session::finish`] with a [`crate::DuplicateWrites`] error.
+    Reject,
+}
+
+/// How values stored in overflow pages are compressed. See [`Options::compression`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Store values as they are.
+    #[default]
+    None,
+    /// Compress values with LZ4, which is fast but compresses less.
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// Compress values with zstd at the given level, from 1 to 22.
+    ///
+    /// With a dictionary size, values are also compressed with a dictionary trained over a sample
+    /// of the values of the database, which captures what they have in common and makes values
+    /// which are alike compress much better. Values are sampled as they are written, and once the
+    /// sampled values amount to 100 times the dictionary size, a dictionary is trained over them
+    /// and stored in the database directory. Later values are compressed with the latest
+    /// dictionary, and sampled to train the next, up to 16 dictionaries. Every value records the
+    /// version of the dictionary it was compressed with, and dictionaries are kept for as long as
+    /// the database, so this may be changed between opens.
+    #[cfg(feature = "zstd")]
+    Zstd {
+        /// The compression level. Higher levels compress better but more slowly.
+        level: i32,
+        /// The maximum size of a dictionary in bytes, or 0 to compress without dictionaries.
+        /// Dictionaries of 16 to 112 KiB are typical.
+        dictionary_size: usize,
+    },
+}
+
+/// The directories holding the database files placed outside of [`Options::path`].
+/// See [`Options::layout`].
+///
+/// A file placed elsewhere is created under a unique name in the given directory, and linked into
+/// the database directory with a symbolic link under its usual name. The directory must already
+/// exist, and may be shared by several databases. The meta file, rollback logs and other small
+/// files always stay in the database directory.
+///
+/// Moving or deleting the database directory leaves the files placed elsewhere behind.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StorageLayout {
+    pub(crate) wal: Option<PathBuf>,
+    pub(crate) hash_table: Option<PathBuf>,
+    pub(crate) beatree: Option<PathBuf>,
+}
+
+impl StorageLayout {
+    /// Create a layout keeping all files in the database directory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the directory of the write-ahead log of the hash-table, which is written and synced
+    /// on every commit.
+    pub fn wal(&mut self, dir: impl Into<PathBuf>) {
+        self.wal = Some(dir.into());
+    }
+
+    /// Set the directory of the hash-table file, which holds the pages of the merkle trie.
+    pub fn hash_table(&mut self, dir: impl Into<PathBuf>) {
+        self.hash_table = Some(dir.into());
+    }
+
+    /// Set the directory of the leaf and branch files of the value store.
+    pub fn beatree(&mut self, dir: impl Into<PathBuf>) {
+        self.beatree = Some(dir.into());
+    }
+}
+
+/// How pages are read from the database files. See [`Options::read_backend`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReadBackend {
+    /// Read through the I/O pool, using io_uring or a thread pool issuing syscalls. Files are
+    /// opened with `O_DIRECT` where supported, bypassing the cache of the operating system.
+    #[default]
+    Io,
+    /// Copy pages out of memory mappings of the hash-table and the leaf store, on the reading
+    /// thread. This avoids syscalls and thread hand-offs, but relies on the cache of the operating
+    /// system, so the files are not opened with `O_DIRECT`.
+    ///
+    /// Meant for databases which fit in memory. Reads of pages which are not resident block on a
+    /// page fault. Writes are unaffected. Not supported on Windows, where opening the database
+    /// fails.
+    Mmap,
+}
+
 /// Modes for panicking during sync.
 #[derive(Clone, Copy, Debug)]
 pub enum PanicOnSyncMode {
@@ -195,4 +771,8 @@ pub enum PanicOnSyncMode {
     PostMeta,
     /// Before the meta has been swapped, but after the WAL is written.
     PostWal,
+    /// After the first intermediate meta of a checkpointed sync has been written.
+    ///
+    /// See [`Options::sync_checkpoint_interval`].
+    PostCheckpoint,
 }
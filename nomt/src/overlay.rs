@@ -16,14 +16,23 @@
 //!
 //! Creating a new overlay is an O(n) operation in the amount of changes relative to the parent,
 //! both in terms of new changes and outdated ancestors.
+//!
+//! When many overlays are alive, their values may exceed the memory budget set with
+//! [`crate::Options::overlay_memory_budget`]. The values of the oldest overlays are then spilled
+//! to a temporary segment on disk and read back from there on access. Merkle pages always stay in
+//! memory.
 
-use crate::{beatree::ValueChange, store::DirtyPage, Root};
+use crate::{beatree::ValueChange, store::DirtyPage, Root, StorageClass};
 use nomt_core::{
     page_id::PageId,
-    trie::{KeyPath, Node},
+    trie::{KeyPath, Node, ValueHash},
 };
+use parking_lot::{Mutex, RwLock};
 
-use std::collections::HashMap;
+use crate::sys::FileExt as _;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::sync::{
     atomic::{AtomicUsize, Ordering},
     Arc, Weak,
@@ -45,6 +54,34 @@ impl Overlay {
         Root(self.inner.prev_root)
     }
 
+    /// Get the generation of the hash table the bucket indices of this overlay refer to.
+    pub(super) fn ht_generation(&self) -> u64 {
+        self.inner.ht_generation
+    }
+
+    /// Whether the session building this overlay read values through from a remote archive.
+    pub(super) fn used_read_through(&self) -> bool {
+        self.inner.read_through_used
+    }
+
+    /// Mark this overlay, which must not be shared yet, as built on values read through from a
+    /// remote archive.
+    pub(super) fn mark_read_through(&mut self) {
+        // UNWRAP: a fresh overlay is referenced only once.
+        Arc::get_mut(&mut self.inner).unwrap().read_through_used = true;
+    }
+
+    /// Get the storage-class hints of the session building this overlay.
+    pub(super) fn storage_hints(&self) -> &[(KeyPath, StorageClass)] {
+        &self.inner.storage_hints
+    }
+
+    /// Set the storage-class hints of this overlay, which must not be shared yet.
+    pub(super) fn set_storage_hints(&mut self, hints: Vec<(KeyPath, StorageClass)>) {
+        // UNWRAP: a fresh overlay is referenced only once.
+        Arc::get_mut(&mut self.inner).unwrap().storage_hints = hints;
+    }
+
     /// Check whether the parent of this overlay matches the provided marker.
     /// If the provided marker is `None`, then this checks that this overlay doesn't have a parent.
     pub(super) fn parent_matches_marker(&self, marker: Option<&OverlayMarker>) -> bool {
@@ -60,9 +97,25 @@ impl Overlay {
         &self.inner.data.pages
     }
 
-    /// Get the merkle page changes associated uniquely with this overlay.
-    pub(super) fn value_changes(&self) -> &HashMap<KeyPath, ValueChange> {
-        &self.inner.data.values
+    /// Get the value changes associated uniquely with this overlay, reading back spilled values.
+    pub(super) fn value_changes(&self) -> Vec<(KeyPath, ValueChange)> {
+        let values = self.inner.data.values.read();
+        values
+            .map
+            .keys()
+            .map(|key| {
+                // UNWRAP: the key is in the map.
+                (*key, values.get(key).unwrap())
+            })
+            .collect()
+    }
+
+    /// The number of bytes of values of this overlay held in memory.
+    ///
+    /// This shrinks when the values are spilled to disk. See
+    /// [`crate::Options::overlay_memory_budget`].
+    pub fn resident_bytes(&self) -> usize {
+        self.inner.data.values.read().resident
     }
 
     /// Get the rollback delta associated uniquely with this overlay.
@@ -87,6 +140,11 @@ struct OverlayInner {
     // ordered by recency.
     ancestor_data: Vec<Weak<Data>>,
     rollback_delta: Option<crate::rollback::Delta>,
+    ht_generation: u64,
+    // whether the session building the overlay read values through from a remote archive.
+    read_through_used: bool,
+    // the storage-class hints of the session building the overlay, applied once it's committed.
+    storage_hints: Vec<(KeyPath, StorageClass)>,
 }
 
 /// A marker indicating the overlay uniquely, until dropped. Used to enforce commit order.
@@ -212,7 +270,7 @@ impl Index {
 /// Data associated with a single overlay.
 struct Data {
     pages: HashMap<PageId, DirtyPage>,
-    values: HashMap<KeyPath, ValueChange>,
+    values: RwLock<Values>,
     status: OverlayStatus,
     parent_status: Option<OverlayStatus>,
 }
@@ -223,6 +281,199 @@ impl Drop for Data {
     }
 }
 
+/// The value changes of a single overlay, some of which may be spilled to disk.
+struct Values {
+    map: HashMap<KeyPath, StoredValue>,
+    // the total length of the resident values.
+    resident: usize,
+    // the segment holding the spilled values, if any have been spilled.
+    segment: Option<Arc<File>>,
+}
+
+enum StoredValue {
+    Resident(ValueChange),
+    // The value of an insertion, written to the segment at the given offset.
+    Spilled {
+        offset: u64,
+        len: usize,
+        overflow_hash: Option<ValueHash>,
+    },
+}
+
+impl Values {
+    fn new(changes: HashMap<KeyPath, ValueChange>) -> Self {
+        Values {
+            resident: changes.values().map(value_len).sum(),
+            map: changes
+                .into_iter()
+                .map(|(key, change)| (key, StoredValue::Resident(change)))
+                .collect(),
+            segment: None,
+        }
+    }
+
+    fn get(&self, key: &KeyPath) -> Option<ValueChange> {
+        let (offset, len, overflow_hash) = match self.map.get(key)? {
+            StoredValue::Resident(change) => return Some(change.clone()),
+            StoredValue::Spilled {
+                offset,
+                len,
+                overflow_hash,
+            } => (*offset, *len, *overflow_hash),
+        };
+
+        let mut value = vec![0; len];
+        // UNWRAP: a segment is set once any value is spilled.
+        if let Err(e) = self
+            .segment
+            .as_ref()
+            .unwrap()
+            .read_exact_at(&mut value, offset)
+        {
+            panic!("failed to read spilled overlay value: {e}");
+        }
+        Some(match overflow_hash {
+            None => ValueChange::Insert(value),
+            Some(value_hash) => ValueChange::InsertOverflow(value, value_hash),
+        })
+    }
+
+    // Write all resident values to the end of the segment and drop them from memory.
+    fn spill(&mut self, segment: &Arc<File>, end: &mut u64) -> std::io::Result<()> {
+        let mut buf = Vec::with_capacity(self.resident);
+        let mut spilled = Vec::new();
+        for (key, stored) in &self.map {
+            let StoredValue::Resident(change) = stored else {
+                continue;
+            };
+            let (value, overflow_hash) = match change {
+                ValueChange::Insert(value) => (value, None),
+                ValueChange::InsertOverflow(value, value_hash) => (value, Some(*value_hash)),
+                ValueChange::Delete => continue,
+            };
+            if value.is_empty() {
+                continue;
+            }
+            spilled.push((
+                *key,
+                StoredValue::Spilled {
+                    offset: *end + buf.len() as u64,
+                    len: value.len(),
+                    overflow_hash,
+                },
+            ));
+            buf.extend_from_slice(value);
+        }
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        segment.write_all_at(&buf, *end)?;
+        *end += buf.len() as u64;
+        self.resident -= buf.len();
+        self.segment = Some(segment.clone());
+        self.map.extend(spilled);
+        Ok(())
+    }
+}
+
+fn value_len(change: &ValueChange) -> usize {
+    match change {
+        ValueChange::Delete => 0,
+        ValueChange::Insert(value) | ValueChange::InsertOverflow(value, _) => value.len(),
+    }
+}
+
+/// Spills the values of the oldest live overlays to a temporary segment on disk, once the values
+/// of all live overlays exceed a memory budget.
+///
+/// The segment is created in the database directory on the first spill and unlinked right away,
+/// so it never outlives the process. It is truncated whenever no overlay with spilled values is
+/// left alive.
+pub(crate) struct OverlaySpill {
+    budget: usize,
+    path: PathBuf,
+    state: Mutex<SpillState>,
+}
+
+struct SpillState {
+    // the data of all admitted overlays, oldest first.
+    overlays: VecDeque<Weak<Data>>,
+    segment: Option<Arc<File>>,
+    end: u64,
+}
+
+impl OverlaySpill {
+    pub(crate) fn new(budget: usize, path: PathBuf) -> Self {
+        OverlaySpill {
+            budget,
+            path,
+            state: Mutex::new(SpillState {
+                overlays: VecDeque::new(),
+                segment: None,
+                end: 0,
+            }),
+        }
+    }
+
+    /// Account for a newly created overlay and spill the oldest live overlays until the values
+    /// left in memory fit the budget.
+    ///
+    /// If the segment cannot be written, the values stay in memory.
+    pub(crate) fn admit(&self, overlay: &Overlay) {
+        let mut state = self.state.lock();
+        state.overlays.retain(|data| data.strong_count() > 0);
+        state
+            .overlays
+            .push_back(Arc::downgrade(&overlay.inner.data));
+        let live = state
+            .overlays
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect::<Vec<_>>();
+
+        if state.end > 0 && live.iter().all(|data| data.values.read().segment.is_none()) {
+            if let Some(segment) = state.segment.as_ref() {
+                if segment.set_len(0).is_ok() {
+                    state.end = 0;
+                }
+            }
+        }
+
+        let mut resident: usize = live.iter().map(|data| data.values.read().resident).sum();
+        for data in &live {
+            if resident <= self.budget {
+                break;
+            }
+            let mut values = data.values.write();
+            let before = values.resident;
+            if state.spill(&self.path, &mut values).is_err() {
+                return;
+            }
+            resident -= before - values.resident;
+        }
+    }
+}
+
+impl SpillState {
+    fn spill(&mut self, path: &Path, values: &mut Values) -> std::io::Result<()> {
+        let segment = match self.segment {
+            Some(ref segment) => segment.clone(),
+            None => {
+                let file = std::fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(path)?;
+                std::fs::remove_file(path)?;
+                self.segment.insert(Arc::new(file)).clone()
+            }
+        };
+        values.spill(&segment, &mut self.end)
+    }
+}
+
 /// An error type indicating that the ancestors provided did not match.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum InvalidAncestors {
@@ -327,18 +578,25 @@ impl LiveOverlay {
             .and_then(|parent| parent.index.values.get(key))
             .and_then(|seqn| seqn.checked_sub(self.min_seqn))
             .map(|seqn_diff| self.value_inner(key, seqn_diff))
-            .cloned()
     }
 
-    fn value_inner(&self, key: &KeyPath, seqn_diff: u64) -> &ValueChange {
+    fn value_inner(&self, key: &KeyPath, seqn_diff: u64) -> ValueChange {
         if seqn_diff as usize == self.ancestor_data.len() {
             // UNWRAP: parent existence checked above
             // UNWRAP: index indicates that data exists.
-            self.parent.as_ref().unwrap().data.values.get(key).unwrap()
+            self.parent
+                .as_ref()
+                .unwrap()
+                .data
+                .values
+                .read()
+                .get(key)
+                .unwrap()
         } else {
             // UNWRAP: index indicates that data exists.
             self.ancestor_data[self.ancestor_data.len() - seqn_diff as usize - 1]
                 .values
+                .read()
                 .get(key)
                 .unwrap()
         }
@@ -349,7 +607,7 @@ impl LiveOverlay {
         &'a self,
         start: KeyPath,
         end: Option<KeyPath>,
-    ) -> impl Iterator<Item = (KeyPath, &'a ValueChange)> {
+    ) -> impl Iterator<Item = (KeyPath, ValueChange)> + 'a {
         self.parent
             .as_ref()
             .map(move |parent| {
@@ -373,6 +631,7 @@ impl LiveOverlay {
         page_changes: HashMap<PageId, DirtyPage>,
         value_changes: HashMap<KeyPath, ValueChange>,
         rollback_delta: Option<crate::rollback::Delta>,
+        ht_generation: u64,
     ) -> Overlay {
         let new_seqn = self.parent.as_ref().map_or(0, |p| p.seqn + 1);
 
@@ -403,17 +662,26 @@ impl LiveOverlay {
                 root,
                 data: Arc::new(Data {
                     pages: page_changes,
-                    values: value_changes,
+                    values: RwLock::new(Values::new(value_changes)),
                     status: OverlayStatus::new_live(),
                     parent_status,
                 }),
                 seqn: new_seqn,
                 ancestor_data,
                 rollback_delta,
+                ht_generation,
+                read_through_used: false,
+                storage_hints: Vec::new(),
             }),
         }
     }
 
+    /// Get the generation of the hash table the bucket indices of the overlay refer to. If this
+    /// is an empty overlay, returns `None`.
+    pub(super) fn ht_generation(&self) -> Option<u64> {
+        self.parent.as_ref().map(|p| p.ht_generation)
+    }
+
     /// Get the overlay's root. If this is an empty overlay, returns `None`.
     pub(super) fn parent_root(&self) -> Option<Node> {
         self.parent.as_ref().map(|p| p.root)
@@ -462,6 +730,7 @@ mod tests {
             HashMap::new(),
             HashMap::new(),
             None,
+            0,
         );
         let a1 = LiveOverlay::new(None).unwrap().finish(
             [1; 32],
@@ -469,6 +738,7 @@ mod tests {
             HashMap::new(),
             HashMap::new(),
             None,
+            0,
         );
 
         let mut ancestors = VecDeque::new();
@@ -479,6 +749,7 @@ mod tests {
             HashMap::new(),
             HashMap::new(),
             None,
+            0,
         );
         ancestors.push_front(b);
 
@@ -497,6 +768,7 @@ mod tests {
             HashMap::new(),
             HashMap::new(),
             None,
+            0,
         );
 
         let mut ancestors = VecDeque::new();
@@ -507,6 +779,7 @@ mod tests {
             HashMap::new(),
             HashMap::new(),
             None,
+            0,
         );
         ancestors.push_front(b);
         let c = LiveOverlay::new(&ancestors).unwrap().finish(
@@ -515,6 +788,7 @@ mod tests {
             HashMap::new(),
             HashMap::new(),
             None,
+            0,
         );
         ancestors.push_front(c);
 
@@ -546,6 +820,7 @@ mod tests {
             HashMap::new(),
             HashMap::new(),
             None,
+            0,
         );
 
         let mut ancestors = VecDeque::new();
@@ -556,6 +831,7 @@ mod tests {
             HashMap::new(),
             HashMap::new(),
             None,
+            0,
         );
 
         drop(ancestors);
@@ -571,6 +847,7 @@ mod tests {
             HashMap::new(),
             HashMap::new(),
             None,
+            0,
         );
 
         let mut ancestors = VecDeque::new();
@@ -581,6 +858,7 @@ mod tests {
             HashMap::new(),
             HashMap::new(),
             None,
+            0,
         );
         ancestors[0].inner.data.status.commit();
         drop(ancestors);
@@ -597,6 +875,7 @@ mod tests {
             HashMap::new(),
             HashMap::new(),
             None,
+            0,
         );
 
         let mut ancestors = VecDeque::new();
@@ -607,6 +886,7 @@ mod tests {
             HashMap::new(),
             HashMap::new(),
             None,
+            0,
         );
         ancestors.push_front(b);
         let c = LiveOverlay::new(&ancestors).unwrap().finish(
@@ -615,6 +895,7 @@ mod tests {
             HashMap::new(),
             HashMap::new(),
             None,
+            0,
         );
         ancestors.push_front(c);
 
@@ -648,13 +929,13 @@ mod tests {
         let value_map = vec![(key1, value1a)].into_iter().collect();
         let a = LiveOverlay::new(None)
             .unwrap()
-            .finish([0; 32], [1; 32], page_map, value_map, None);
+            .finish([0; 32], [1; 32], page_map, value_map, None, 0);
 
         let page_map = vec![(ROOT_PAGE_ID, page1b)].into_iter().collect();
         let value_map = vec![(key1, value1b)].into_iter().collect();
         let b = LiveOverlay::new(Some(&a))
             .unwrap()
-            .finish([1; 32], [2; 32], page_map, value_map, None);
+            .finish([1; 32], [2; 32], page_map, value_map, None, 0);
 
         let c = LiveOverlay::new([&b, &a]).unwrap();
 
@@ -714,7 +995,7 @@ mod tests {
             let value_map = [(key, value)].into_iter().collect();
             let overlay = LiveOverlay::new(&ancestors)
                 .unwrap()
-                .finish([0; 32], [1; 32], page_map, value_map, None);
+                .finish([0; 32], [1; 32], page_map, value_map, None, 0);
             ancestors.push_front(overlay);
         }
 
@@ -759,6 +1040,7 @@ mod tests {
             vec![(ROOT_PAGE_ID, page)].into_iter().collect(),
             HashMap::new(),
             None,
+            0,
         );
         let b = LiveOverlay::new([&a]).unwrap().finish(
             [1; 32],
@@ -766,6 +1048,7 @@ mod tests {
             vec![(ROOT_PAGE_ID, page2)].into_iter().collect(),
             HashMap::new(),
             None,
+            0,
         );
         a.mark_committed();
 
@@ -822,13 +1105,13 @@ mod tests {
             .collect();
         let a = LiveOverlay::new(None)
             .unwrap()
-            .finish([0; 32], [1; 32], page_map, value_map, None);
+            .finish([0; 32], [1; 32], page_map, value_map, None, 0);
 
         let page_map = vec![(page_id_2.clone(), page_2b)].into_iter().collect();
         let value_map = vec![(key_2, val_2b.clone())].into_iter().collect();
         let b = LiveOverlay::new([&a])
             .unwrap()
-            .finish([0; 32], [1; 32], page_map, value_map, None);
+            .finish([0; 32], [1; 32], page_map, value_map, None, 0);
 
         a.mark_committed();
 
@@ -838,6 +1121,7 @@ mod tests {
             HashMap::new(),
             HashMap::new(),
             None,
+            0,
         );
 
         // ensure everything from seqn 0 has been pruned.
@@ -1,5 +1,6 @@
 use crate::{
     bitbox::BucketIndex,
+    chaos::Chaos,
     io::{page_pool::FatPage, PagePool, PAGE_SIZE},
     merkle::ElidedChildren,
     metrics::{Metric, Metrics},
@@ -7,6 +8,7 @@ use crate::{
     rw_pass_cell::{Region, RegionContains, RwPassDomain, WritePass},
     Options,
 };
+use crossbeam::epoch::{self, Atomic, Owned};
 use fxhash::FxBuildHasher;
 use lru::LruCache;
 use nomt_core::{
@@ -14,8 +16,15 @@ use nomt_core::{
     page_id::{ChildPageIndex, PageId, NUM_CHILDREN, ROOT_PAGE_ID},
     trie::Node,
 };
-use parking_lot::{Mutex, RwLock};
-use std::{collections::HashMap, fmt, num::NonZeroUsize, sync::Arc};
+use parking_lot::Mutex;
+use std::{
+    fmt,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 // Total number of nodes stored in one Page. It depends on the `DEPTH`
 // of the rootless sub-binary tree stored in a page following this formula:
@@ -149,83 +158,187 @@ impl fmt::Debug for Page {
 struct CacheEntry {
     page_data: Arc<FatPage>,
     bucket_index: BucketIndex,
+    // Set by lookups, which do not update the LRU order, to give the page a second chance when
+    // it is due for eviction.
+    referenced: AtomicBool,
+    #[cfg(feature = "cache-debug")]
+    inserted: std::time::Instant,
 }
 
 impl CacheEntry {
+    fn page(&self) -> Page {
+        Page {
+            inner: self.page_data.clone(),
+        }
+    }
+
     fn init(page_data: Arc<FatPage>, bucket_index: BucketIndex) -> Self {
         CacheEntry {
             page_data,
             bucket_index,
+            referenced: AtomicBool::new(false),
+            #[cfg(feature = "cache-debug")]
+            inserted: std::time::Instant::now(),
+        }
+    }
+
+    fn mark_referenced(&self) {
+        // Only write if needed, to keep the cache line of a hot page shared between readers.
+        if !self.referenced.load(Ordering::Relaxed) {
+            self.referenced.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[cfg(feature = "cache-debug")]
+    fn snapshot(&self, page_id: &PageId, pinned: bool) -> crate::cache_debug::CachedPage {
+        crate::cache_debug::CachedPage {
+            page_id: page_id.clone(),
+            depth: page_id.depth(),
+            pinned,
+            age: self.inserted.elapsed(),
+        }
+    }
+}
+
+type PageMap = imbl::HashMap<PageId, Arc<CacheEntry>, FxBuildHasher>;
+
+/// A value which readers load without taking any lock, and which writers replace as a whole.
+///
+/// Replaced values are reclaimed once no reader can still be holding them.
+struct Published<T> {
+    current: Atomic<T>,
+}
+
+impl<T> Published<T> {
+    fn new(value: T) -> Self {
+        Published {
+            current: Atomic::new(value),
+        }
+    }
+
+    fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        let guard = epoch::pin();
+        let current = self.current.load(Ordering::Acquire, &guard);
+        // SAFETY: the pointer is never null, and the value is not reclaimed while pinned.
+        f(unsafe { current.deref() })
+    }
+
+    fn publish(&self, value: T) {
+        let guard = epoch::pin();
+        let old = self
+            .current
+            .swap(Owned::new(value), Ordering::AcqRel, &guard);
+        // SAFETY: the old value is unreachable now, and readers still holding it are pinned.
+        unsafe { guard.defer_destroy(old) };
+        guard.flush();
+    }
+}
+
+impl<T> Drop for Published<T> {
+    fn drop(&mut self) {
+        // SAFETY: readers borrow `self`, so there are none left.
+        unsafe {
+            drop(
+                self.current
+                    .load(Ordering::Relaxed, epoch::unprotected())
+                    .into_owned(),
+            )
         }
     }
 }
 
 // Each shard has its own domain and handles a sub-tree of the page tree, defined by a
 // continuous set of children of the root page.
+//
+// Lookups read the published pages of the shard without locking. Changes are made to a copy
+// under the lock, which is published afterwards.
 struct CacheShard {
     region: PageRegion,
+    pages: Published<PageMap>,
     locked: Mutex<CacheShardLocked>,
     page_limit: NonZeroUsize,
 }
 
+impl CacheShard {
+    fn publish(&self, locked: &CacheShardLocked) {
+        self.pages.publish(locked.pages.clone());
+    }
+}
+
 struct CacheShardLocked {
-    // storage for pages in the levels of the tree which we always cache.
-    fixed_level_cache: HashMap<PageId, CacheEntry, FxBuildHasher>,
-    cached: LruCache<PageId, CacheEntry, FxBuildHasher>,
+    // All pages of the shard, including those in the levels of the tree which we always cache.
+    pages: PageMap,
+    // The order of use of the pages below those levels.
+    lru: LruCache<PageId, (), FxBuildHasher>,
 }
 
 impl CacheShardLocked {
-    fn get(&mut self, fixed_levels: usize, page_id: &PageId) -> Option<&CacheEntry> {
-        if page_id.depth() <= fixed_levels {
-            self.fixed_level_cache.get(page_id)
-        } else {
-            self.cached.get(page_id)
-        }
-    }
-
     fn get_or_insert(
         &mut self,
         fixed_levels: usize,
         page_id: PageId,
         entry: impl FnOnce() -> CacheEntry,
-    ) -> &CacheEntry {
-        if page_id.depth() <= fixed_levels {
-            &*self.fixed_level_cache.entry(page_id).or_insert_with(entry)
-        } else {
-            self.cached.get_or_insert(page_id, entry)
+    ) -> Arc<CacheEntry> {
+        if let Some(entry) = self.pages.get(&page_id) {
+            self.lru.promote(&page_id);
+            return entry.clone();
         }
+        let entry = Arc::new(entry());
+        self.insert_entry(fixed_levels, page_id, entry.clone());
+        entry
     }
 
     fn insert(&mut self, fixed_levels: usize, page_id: PageId, entry: CacheEntry) {
-        if page_id.depth() <= fixed_levels {
-            self.fixed_level_cache.insert(page_id, entry);
-        } else {
-            self.cached.put(page_id, entry);
-        }
+        self.insert_entry(fixed_levels, page_id, Arc::new(entry));
     }
 
-    fn remove(&mut self, fixed_levels: usize, page_id: &PageId) {
-        if page_id.depth() <= fixed_levels {
-            self.fixed_level_cache.remove(page_id);
-        } else {
-            self.cached.pop(page_id);
+    fn insert_entry(&mut self, fixed_levels: usize, page_id: PageId, entry: Arc<CacheEntry>) {
+        if page_id.depth() > fixed_levels {
+            self.lru.put(page_id.clone(), ());
         }
+        self.pages.insert(page_id, entry);
+    }
+
+    fn remove(&mut self, page_id: &PageId) {
+        self.lru.pop(page_id);
+        self.pages.remove(page_id);
     }
 
     fn evict(&mut self, limit: NonZeroUsize) {
-        // preserve everything in the fixed level cache, removing only the variable cache.
-        while self.cached.len() > limit.get() {
-            let _ = self.cached.pop_lru();
+        // preserve everything in the fixed levels, removing only from the LRU.
+        while self.lru.len() > limit.get() {
+            // UNWRAP: the LRU is not empty.
+            let (page_id, ()) = self.lru.pop_lru().unwrap();
+            let referenced = self
+                .pages
+                .get(&page_id)
+                .is_some_and(|entry| entry.referenced.swap(false, Ordering::Relaxed));
+            if referenced {
+                // Used since it was last due. Every page gets this chance only once per pass,
+                // so the loop ends.
+                self.lru.put(page_id, ());
+            } else {
+                self.pages.remove(&page_id);
+            }
+        }
+    }
+
+    fn pop_lru(&mut self) {
+        if let Some((page_id, ())) = self.lru.pop_lru() {
+            self.pages.remove(&page_id);
         }
     }
 }
 
 struct Shared {
     shards: Vec<CacheShard>,
-    root_page: RwLock<Option<CacheEntry>>,
+    root_page: Published<Option<Arc<CacheEntry>>>,
+    // Serializes changes to the root page.
+    root_page_write: Mutex<()>,
     page_rw_pass_domain: RwPassDomain,
     fixed_levels: usize,
     metrics: Metrics,
+    chaos: Chaos,
 }
 
 fn shard_regions(num_shards: usize) -> Vec<(PageRegion, usize)> {
@@ -279,9 +392,10 @@ fn make_shards(num_shards: usize, page_cache_size: usize) -> Vec<CacheShard> {
         .into_iter()
         .map(|(region, count)| CacheShard {
             region,
+            pages: Published::new(PageMap::with_hasher(FxBuildHasher::default())),
             locked: Mutex::new(CacheShardLocked {
-                fixed_level_cache: HashMap::with_hasher(FxBuildHasher::default()),
-                cached: LruCache::unbounded_with_hasher(FxBuildHasher::default()),
+                pages: PageMap::with_hasher(FxBuildHasher::default()),
+                lru: LruCache::unbounded_with_hasher(FxBuildHasher::default()),
             }),
             // UNWRAP: both factors are non-zero
             page_limit: NonZeroUsize::new(page_limit_per_root_child * count).unwrap(),
@@ -300,7 +414,9 @@ pub enum ShardIndex {
 
 /// The page-cache stores full pages and can be shared between threads.
 ///
-/// It has a sharded representation for efficient concurrent access.
+/// It has a sharded representation for efficient concurrent access. Lookups take no locks: each
+/// shard publishes a persistent map of its pages after every change, and replaced maps are
+/// reclaimed with epoch-based reclamation.
 #[derive(Clone)]
 pub struct PageCache {
     shared: Arc<Shared>,
@@ -312,19 +428,22 @@ impl PageCache {
         root_page_data: Option<(FatPage, BucketIndex)>,
         o: &Options,
         metrics: impl Into<Option<Metrics>>,
+        chaos: Chaos,
     ) -> Self {
         let domain = RwPassDomain::new();
 
         let root_page_entry =
-            root_page_data.map(|(page, bucket)| CacheEntry::init(Arc::new(page), bucket));
+            root_page_data.map(|(page, bucket)| Arc::new(CacheEntry::init(Arc::new(page), bucket)));
 
         Self {
             shared: Arc::new(Shared {
                 shards: make_shards(o.commit_concurrency, o.page_cache_size),
-                root_page: RwLock::new(root_page_entry),
+                root_page: Published::new(root_page_entry),
+                root_page_write: Mutex::new(()),
                 page_rw_pass_domain: domain,
                 metrics: metrics.into().unwrap_or(Metrics::new(false)),
                 fixed_levels: o.page_cache_upper_levels,
+                chaos,
             }),
         }
     }
@@ -344,36 +463,26 @@ impl PageCache {
 
     /// Query the cache for the page data at the given [`PageId`].
     ///
-    /// Returns `None` if not in the cache.
+    /// Returns `None` if not in the cache. This takes no locks.
     pub fn get(&self, page_id: PageId) -> Option<(Page, BucketIndex)> {
         self.shared.metrics.count(Metric::PageRequests);
+        let lookup = |entry: &Arc<CacheEntry>| {
+            entry.mark_referenced();
+            (entry.page(), entry.bucket_index)
+        };
         let shard_index = match self.shard_index_for(&page_id) {
-            None => {
-                let cache_item = self.shared.root_page.read();
-                let cache_item = cache_item.as_ref()?;
-                return Some((
-                    Page {
-                        inner: cache_item.page_data.clone(),
-                    },
-                    cache_item.bucket_index,
-                ));
-            }
+            None => return self.shared.root_page.read(|root| root.as_ref().map(lookup)),
             Some(i) => i,
         };
 
-        let mut shard = self.shard(shard_index).locked.lock();
-        match shard.get(self.shared.fixed_levels, &page_id) {
-            Some(cache_item) => Some((
-                Page {
-                    inner: cache_item.page_data.clone(),
-                },
-                cache_item.bucket_index,
-            )),
-            None => {
-                self.shared.metrics.count(Metric::PageCacheMisses);
-                None
-            }
+        let found = self
+            .shard(shard_index)
+            .pages
+            .read(|pages| pages.get(&page_id).map(lookup));
+        if found.is_none() {
+            self.shared.metrics.count(Metric::PageCacheMisses);
         }
+        found
     }
 
     /// Acquire a write pass for all pages in the cache.
@@ -405,27 +514,25 @@ impl PageCache {
     pub fn insert(&self, page_id: PageId, page: Page, bucket_index: BucketIndex) -> Page {
         let shard_index = match self.shard_index_for(&page_id) {
             None => {
-                let mut cache_item = self.shared.root_page.write();
-                if let Some(root_page) = cache_item.as_ref() {
-                    return Page {
-                        inner: root_page.page_data.clone(),
-                    };
-                } else {
-                    *cache_item = Some(CacheEntry::init(page.inner.clone(), bucket_index));
-                    return page;
+                let _write_guard = self.shared.root_page_write.lock();
+                if let Some(root_page) = self.shared.root_page.read(|root| root.clone()) {
+                    return root_page.page();
                 }
+                let entry = CacheEntry::init(page.inner.clone(), bucket_index);
+                self.shared.root_page.publish(Some(Arc::new(entry)));
+                return page;
             }
             Some(i) => i,
         };
 
-        let mut shard = self.shard(shard_index).locked.lock();
-        let cache_entry = shard.get_or_insert(self.shared.fixed_levels, page_id, || {
+        let shard = self.shard(shard_index);
+        let mut locked = shard.locked.lock();
+        let cache_entry = locked.get_or_insert(self.shared.fixed_levels, page_id, || {
             CacheEntry::init(page.inner, bucket_index)
         });
+        shard.publish(&locked);
 
-        Page {
-            inner: cache_entry.page_data.clone(),
-        }
+        cache_entry.page()
     }
 
     /// Absorb a set of altered pages into the cache.
@@ -441,9 +548,12 @@ impl PageCache {
 
         for (page_id, maybe_page) in updated_pages {
             if page_id == ROOT_PAGE_ID {
-                let mut root_page = self.shared.root_page.write();
-                *root_page = maybe_page
-                    .map(|(page, bucket_index)| CacheEntry::init(page.inner, bucket_index));
+                let _write_guard = self.shared.root_page_write.lock();
+                self.shared
+                    .root_page
+                    .publish(maybe_page.map(|(page, bucket_index)| {
+                        Arc::new(CacheEntry::init(page.inner, bucket_index))
+                    }));
 
                 continue;
             }
@@ -458,13 +568,20 @@ impl PageCache {
                     CacheEntry::init(page.inner, bucket_index),
                 );
             } else {
-                shard_guards[shard_index].remove(self.shared.fixed_levels, &page_id)
+                shard_guards[shard_index].remove(&page_id)
             }
         }
+
+        for (shard, guard) in self.shared.shards.iter().zip(shard_guards) {
+            shard.publish(&guard);
+        }
     }
 
     /// Evict stale pages for the cache. This should only be used after all dirty pages have been
     /// prepared for writeout with `prepare_transaction`.
+    ///
+    /// Pages are evicted in LRU order, except that pages found by lookups since they were last
+    /// considered are kept once more.
     pub fn evict(&self) {
         let shard_guards = self
             .shared
@@ -475,7 +592,136 @@ impl PageCache {
 
         for (shard, mut guard) in self.shared.shards.iter().zip(shard_guards) {
             guard.evict(shard.page_limit);
+            for _ in 0..self.shared.chaos.extra_evictions(guard.lru.len()) {
+                guard.pop_lru();
+            }
+            shard.publish(&guard);
+        }
+    }
+
+    /// The number of pages in the cache, including the root page.
+    pub fn len(&self) -> usize {
+        let root = self.shared.root_page.read(|root| root.is_some()) as usize;
+        self.shared
+            .shards
+            .iter()
+            .map(|shard| shard.locked.lock().pages.len())
+            .sum::<usize>()
+            + root
+    }
+
+    /// Remove all pages deeper than `keep_levels` from the cache. The root page is always kept.
+    pub fn clear(&self, keep_levels: usize) {
+        for shard in &self.shared.shards {
+            let mut locked = shard.locked.lock();
+            locked
+                .pages
+                .retain(|page_id, _| page_id.depth() <= keep_levels);
+            let stale = locked
+                .lru
+                .iter()
+                .map(|(page_id, _)| page_id)
+                .filter(|page_id| page_id.depth() > keep_levels)
+                .cloned()
+                .collect::<Vec<_>>();
+            for page_id in stale {
+                locked.lru.pop(&page_id);
+            }
+            shard.publish(&locked);
+        }
+    }
+
+    /// Remove all pages from the cache, and replace the root page.
+    pub fn reset(&self, root_page_data: Option<(FatPage, BucketIndex)>) {
+        for shard in &self.shared.shards {
+            let mut locked = shard.locked.lock();
+            locked.pages.clear();
+            locked.lru.clear();
+            shard.publish(&locked);
+        }
+        let _write_guard = self.shared.root_page_write.lock();
+        self.shared.root_page.publish(
+            root_page_data.map(|(page, bucket)| Arc::new(CacheEntry::init(Arc::new(page), bucket))),
+        );
+    }
+
+    /// The root page and the pages in the upper levels, ordered by depth.
+    pub fn upper_level_pages(&self) -> Vec<(PageId, Page, BucketIndex)> {
+        let mut pages = Vec::new();
+        if let Some(root_page) = self.shared.root_page.read(|root| root.clone()) {
+            pages.push((ROOT_PAGE_ID, root_page.page(), root_page.bucket_index));
+        }
+        for shard in &self.shared.shards {
+            shard.pages.read(|shard_pages| {
+                pages.extend(
+                    shard_pages
+                        .iter()
+                        .filter(|(page_id, _)| page_id.depth() <= self.shared.fixed_levels)
+                        .map(|(page_id, entry)| {
+                            (page_id.clone(), entry.page(), entry.bucket_index)
+                        }),
+                )
+            });
         }
+        pages.sort_by_key(|(page_id, _, _)| page_id.depth());
+        pages
+    }
+
+    /// At most `limit` of the pages below the upper levels, from most to least recently used,
+    /// without updating the LRU state.
+    pub fn recent_pages(&self, limit: usize) -> Vec<(PageId, Page, BucketIndex)> {
+        let per_shard = self
+            .shared
+            .shards
+            .iter()
+            .map(|shard| {
+                let locked = shard.locked.lock();
+                locked
+                    .lru
+                    .iter()
+                    .take(limit)
+                    .filter_map(|(page_id, ())| {
+                        let entry = locked.pages.get(page_id)?;
+                        Some((page_id.clone(), entry.page(), entry.bucket_index))
+                    })
+                    .collect()
+            })
+            .collect();
+        crate::cache_file::round_robin(per_shard, limit)
+    }
+
+    /// Load pages read from the cache file, ordered from hottest to coldest, into the cache.
+    ///
+    /// Pages already present are kept.
+    pub fn restore(&self, pages: Vec<(PageId, FatPage, BucketIndex)>) {
+        // Insert the coldest pages first, so that the hottest are the most recently used.
+        for (page_id, page, bucket_index) in pages.into_iter().rev() {
+            let page = Page {
+                inner: Arc::new(page),
+            };
+            self.insert(page_id, page, bucket_index);
+        }
+        self.evict();
+    }
+
+    /// List the cached pages, without updating the LRU state.
+    ///
+    /// Pages in the upper levels, which are never evicted, are reported as pinned.
+    #[cfg(feature = "cache-debug")]
+    pub fn snapshot(&self) -> Vec<crate::cache_debug::CachedPage> {
+        let mut pages = Vec::new();
+        if let Some(root_page) = self.shared.root_page.read(|root| root.clone()) {
+            pages.push(root_page.snapshot(&ROOT_PAGE_ID, true));
+        }
+        for shard in &self.shared.shards {
+            shard.pages.read(|shard_pages| {
+                pages.extend(shard_pages.iter().map(|(page_id, entry)| {
+                    entry.snapshot(page_id, page_id.depth() <= self.shared.fixed_levels)
+                }))
+            });
+        }
+        pages.sort_by_key(|p| (p.depth, p.page_id.encode()));
+        pages
     }
 
     fn shard(&self, index: usize) -> &CacheShard {
@@ -520,3 +766,80 @@ unsafe impl RegionContains<ShardIndex> for ShardIndex {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{BucketIndex, Page, PageCache};
+    use crate::{chaos::Chaos, io::PagePool, Options};
+    use nomt_core::page_id::{ChildPageIndex, PageId, ROOT_PAGE_ID};
+    use std::sync::Arc;
+
+    // A cache of 256 pages below the root, in one shard.
+    fn page_cache() -> (PageCache, PagePool) {
+        let mut o = Options::new();
+        o.commit_concurrency(1);
+        o.page_cache_size(1);
+        o.page_cache_upper_levels(0);
+        (
+            PageCache::new(None, &o, None, Chaos::default()),
+            PagePool::new(),
+        )
+    }
+
+    fn page_id(i: usize) -> PageId {
+        let child = |i: usize| ChildPageIndex::new(i as u8).unwrap();
+        ROOT_PAGE_ID
+            .child_page_id(child(i / 64))
+            .unwrap()
+            .child_page_id(child(i % 64))
+            .unwrap()
+    }
+
+    fn insert(cache: &PageCache, page_pool: &PagePool, i: usize) {
+        let page = Page {
+            inner: Arc::new(page_pool.alloc_fat_page()),
+        };
+        cache.insert(page_id(i), page, BucketIndex::new(i as u64));
+    }
+
+    #[test]
+    fn lookups_give_a_second_chance() {
+        let (cache, page_pool) = page_cache();
+        for i in 0..258 {
+            insert(&cache, &page_pool, i);
+        }
+
+        // The two least recently inserted pages are due, but the first one was looked up since.
+        let (_, bucket) = cache.get(page_id(0)).unwrap();
+        assert_eq!(bucket.get(), 0);
+        cache.evict();
+        assert!(cache.get(page_id(0)).is_some());
+        assert!(cache.get(page_id(1)).is_none());
+        assert!(cache.get(page_id(2)).is_none());
+        assert!(cache.get(page_id(257)).is_some());
+    }
+
+    #[test]
+    fn changes_are_visible_to_other_threads() {
+        let (cache, page_pool) = page_cache();
+        insert(&cache, &page_pool, 7);
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..1000 {
+                        assert_eq!(cache.get(page_id(7)).unwrap().1.get(), 7);
+                    }
+                });
+            }
+            for i in 100..200 {
+                insert(&cache, &page_pool, i);
+            }
+        });
+        cache.batch_update(vec![(page_id(7), None)]);
+        assert!(cache.get(page_id(7)).is_none());
+        assert_eq!(cache.get(page_id(150)).unwrap().1.get(), 150);
+
+        cache.reset(None);
+        assert!(cache.get(page_id(150)).is_none());
+    }
+}
@@ -0,0 +1,173 @@
+//! Iterating the values of a session in key order. See [`crate::Session::iter_range`].
+
+use std::{iter::Peekable, ops::Bound};
+
+use nomt_core::trie::KeyPath;
+
+use crate::{beatree::ValueChange, store::ValueIter, LocalValue, Value, ValueNotStored};
+
+/// An iterator over the key-value pairs of a session within a range, in ascending key order.
+///
+/// This merges the values stored in the database with the changes of the overlays the session
+/// is based on, and reflects the state the session is based on throughout.
+///
+/// Values of which only the hash is kept are yielded as [`ValueNotStored`] errors, after which
+/// iteration continues with the next key.
+pub struct RangeIter<'a> {
+    // `None` if the range is empty.
+    stored: Option<ValueIter>,
+    next_stored: Option<(KeyPath, LocalValue)>,
+    changes: Peekable<Box<dyn Iterator<Item = (KeyPath, ValueChange)> + 'a>>,
+    started: bool,
+}
+
+impl<'a> RangeIter<'a> {
+    pub(crate) fn new(
+        stored: ValueIter,
+        changes: Box<dyn Iterator<Item = (KeyPath, ValueChange)> + 'a>,
+    ) -> Self {
+        RangeIter {
+            stored: Some(stored),
+            next_stored: None,
+            changes: changes.peekable(),
+            started: false,
+        }
+    }
+
+    pub(crate) fn empty() -> Self {
+        RangeIter {
+            stored: None,
+            next_stored: None,
+            changes: (Box::new(std::iter::empty()) as Box<dyn Iterator<Item = _>>).peekable(),
+            started: true,
+        }
+    }
+
+    fn advance_stored(&mut self) -> anyhow::Result<()> {
+        self.next_stored = match self.stored {
+            Some(ref mut stored) => stored.next_local().transpose()?,
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Like [`Iterator::next`], but yielding the hashes of values stored elsewhere instead of
+    /// failing.
+    pub(crate) fn next_local(&mut self) -> anyhow::Result<Option<(KeyPath, LocalValue)>> {
+        if !self.started {
+            self.started = true;
+            self.advance_stored()?;
+        }
+        loop {
+            let stored_key = self.next_stored.as_ref().map(|(key, _)| *key);
+            let change_key = self.changes.peek().map(|(key, _)| *key);
+            match (stored_key, change_key) {
+                (None, None) => return Ok(None),
+                (Some(stored_key), change_key) if change_key.is_none_or(|k| stored_key < k) => {
+                    let next = self.next_stored.take();
+                    self.advance_stored()?;
+                    return Ok(next);
+                }
+                (stored_key, _) => {
+                    // Changes take precedence over the stored value of the same key.
+                    // UNWRAP: `changes` has a next item if `stored_key` is not before it.
+                    let (key, change) = self.changes.next().unwrap();
+                    if stored_key == Some(key) {
+                        self.advance_stored()?;
+                    }
+                    if let Some(value_hash) = change.hash_only_value() {
+                        return Ok(Some((key, LocalValue::HashOnly(value_hash))));
+                    }
+                    if let Some(value) = change.as_option() {
+                        return Ok(Some((key, LocalValue::Stored(value.to_vec()))));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for RangeIter<'_> {
+    type Item = anyhow::Result<(KeyPath, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(match self.next_local().transpose()? {
+            Ok((key, LocalValue::Stored(value))) => Ok((key, value)),
+            Ok((key, LocalValue::HashOnly(value_hash))) => {
+                Err(ValueNotStored { key, value_hash }.into())
+            }
+            Err(e) => Err(e),
+        })
+    }
+}
+
+/// Convert range bounds into the half-open range `[start, end)`, with `None` for the end of the
+/// key space. Returns `None` if the range is empty.
+pub(crate) fn half_open(
+    start: Bound<&KeyPath>,
+    end: Bound<&KeyPath>,
+) -> Option<(KeyPath, Option<KeyPath>)> {
+    let start = match start {
+        Bound::Included(key) => *key,
+        Bound::Excluded(key) => next_key(key)?,
+        Bound::Unbounded => KeyPath::default(),
+    };
+    let end = match end {
+        Bound::Included(key) => next_key(key),
+        Bound::Excluded(key) => Some(*key),
+        Bound::Unbounded => None,
+    };
+    match end {
+        Some(end) if end <= start => None,
+        end => Some((start, end)),
+    }
+}
+
+// The key following the given one, or `None` if it is the last one.
+fn next_key(key: &KeyPath) -> Option<KeyPath> {
+    let mut next = *key;
+    for byte in next.iter_mut().rev() {
+        let (incremented, carry) = byte.overflowing_add(1);
+        *byte = incremented;
+        if !carry {
+            return Some(next);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::half_open;
+    use std::ops::{Bound, RangeBounds};
+
+    fn bounds(range: impl RangeBounds<[u8; 32]>) -> Option<([u8; 32], Option<[u8; 32]>)> {
+        half_open(range.start_bound(), range.end_bound())
+    }
+
+    #[test]
+    fn range_bounds() {
+        let key = |last: u8| {
+            let mut key = [0; 32];
+            key[31] = last;
+            key
+        };
+        assert_eq!(bounds(key(1)..key(3)), Some((key(1), Some(key(3)))));
+        assert_eq!(bounds(key(1)..=key(3)), Some((key(1), Some(key(4)))));
+        assert_eq!(bounds(..), Some((key(0), None)));
+        assert_eq!(bounds(key(3)..key(3)), None);
+        assert_eq!(bounds(key(3)..key(1)), None);
+        assert_eq!(bounds(..=[0xff; 32]), Some((key(0), None)));
+
+        let mut carried = [0; 32];
+        carried[30] = 1;
+        assert_eq!(
+            bounds((Bound::Excluded(key(0xff)), Bound::Unbounded)),
+            Some((carried, None))
+        );
+        assert_eq!(
+            bounds((Bound::Excluded([0xff; 32]), Bound::Unbounded)),
+            None
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Reading values without blocking the thread. See [`crate::Session::read_async`].
+//!
+//! The lookup of a value is driven by the completions of the leaf and overflow page reads it
+//! submits. Completions arrive on a waking I/O handle, which wakes the task polling the lookup as
+//! they come in from the I/O workers.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{
+    beatree::{AsyncLookup, OverflowPageInfo, ReadTransaction},
+    io::IoHandle,
+    store::Store,
+    KeyPath, Value, ValueNotStored,
+};
+
+/// A value being read in the background, returned by [`crate::Session::read_async`].
+///
+/// This resolves to the value stored under the key, or `None` if there is none. It fails if I/O
+/// fails, with [`ValueNotStored`] if only the hash of the value is kept, and with
+/// [`crate::StaleView`] if the database is opened read-only and the writer synced past the view
+/// before the read completed. Dropping it abandons the read.
+pub struct ReadValue {
+    state: ReadState,
+}
+
+enum ReadState {
+    Ready(Option<anyhow::Result<Option<Value>>>),
+    Pending(Box<PendingRead>),
+}
+
+struct PendingRead {
+    lookup: AsyncLookup,
+    io_handle: IoHandle,
+    // The overflow pages in flight, by user data. The initial read has user data 0 and none.
+    overflow_pages: HashMap<u64, OverflowPageInfo>,
+    next_user_data: u64,
+    resolve: Resolve,
+}
+
+// What is checked once the value is looked up.
+struct Resolve {
+    store: Store,
+    read_tx: ReadTransaction,
+    key: KeyPath,
+}
+
+impl Resolve {
+    fn resolve(&self, value: Option<Value>) -> anyhow::Result<Option<Value>> {
+        // A value stored elsewhere is looked up as empty. Its leaf was just looked up, so telling
+        // it apart reads nothing.
+        if value.as_ref().is_some_and(|v| v.is_empty()) {
+            if let Some(value_hash) = self.read_tx.lookup_hash_only_blocking(self.key)? {
+                return Err(ValueNotStored {
+                    key: self.key,
+                    value_hash,
+                }
+                .into());
+            }
+        }
+        self.store.check_view()?;
+        Ok(value)
+    }
+}
+
+impl ReadValue {
+    pub(crate) fn ready(result: anyhow::Result<Option<Value>>) -> Self {
+        ReadValue {
+            state: ReadState::Ready(Some(result)),
+        }
+    }
+
+    /// Start looking up the key in a read transaction of the store, submitting reads along the
+    /// handle, which must be made with [`crate::io::IoPool::make_waking_handle`].
+    ///
+    /// The view of a store opened read-only is checked before the lookup and after it, like
+    /// [`Store::read_view`] does.
+    pub(crate) fn start(store: &Store, key: KeyPath, io_handle: IoHandle) -> Self {
+        if let Err(e) = store.check_view() {
+            return Self::ready(Err(e));
+        }
+        let resolve = Resolve {
+            store: store.clone(),
+            read_tx: store.read_transaction(),
+            key,
+        };
+        match resolve.read_tx.lookup_async(key, &io_handle, 0) {
+            Ok(value) => Self::ready(resolve.resolve(value)),
+            Err(lookup) => ReadValue {
+                state: ReadState::Pending(Box::new(PendingRead {
+                    lookup,
+                    io_handle,
+                    overflow_pages: HashMap::new(),
+                    next_user_data: 1,
+                    resolve,
+                })),
+            },
+        }
+    }
+}
+
+impl PendingRead {
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<anyhow::Result<Option<Value>>> {
+        self.io_handle.register_waker(cx.waker());
+        while let Ok(complete_io) = self.io_handle.try_recv() {
+            if let Err(err) = complete_io.result {
+                return Poll::Ready(Err(err.into()));
+            }
+            let meta = self.overflow_pages.remove(&complete_io.command.user_data);
+            // UNWRAP: the lookup only submits `Read` commands, which yield a page.
+            let page = complete_io.command.kind.unwrap_buf();
+            if let Some(value) = self.lookup.try_finish(page, meta) {
+                return Poll::Ready(
+                    value
+                        .map_err(Into::into)
+                        .and_then(|v| self.resolve.resolve(v)),
+                );
+            }
+            while let Some(meta) = self.lookup.submit(&self.io_handle, self.next_user_data) {
+                self.overflow_pages.insert(self.next_user_data, meta);
+                self.next_user_data += 1;
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Future for ReadValue {
+    type Output = anyhow::Result<Option<Value>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.get_mut().state {
+            ReadState::Ready(ref mut result) => {
+                Poll::Ready(result.take().expect("ReadValue polled after completion"))
+            }
+            ReadState::Pending(ref mut pending) => pending.poll(cx),
+        }
+    }
+}
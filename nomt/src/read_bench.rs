@@ -0,0 +1,142 @@
+//! Utilities for measuring read latency under controlled cache conditions.
+//!
+//! Reads are only comparable when the caches they go through are in a known state. This module
+//! brings the page cache and the leaf cache into one of the states of [`CacheState`] before
+//! reads are timed, using [`Nomt::clear_caches`] and [`Nomt::prepopulate_page_cache`].
+//!
+//! This module is only available with the `benchmarks` feature.
+
+use crate::{trie::KeyPath, HashAlgorithm, Nomt, SessionParams};
+use std::time::{Duration, Instant};
+
+/// The contents of the in-memory caches when a read begins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheState {
+    /// Only the root page is cached.
+    Cold,
+    /// The given number of levels of the page tree below the root are cached. No deeper pages and
+    /// no leaves are cached.
+    UpperLevels(usize),
+    /// All pages and leaves needed to read the measured keys are cached.
+    Warm,
+}
+
+/// The path a read takes through the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadKind {
+    /// Read a value from the beatree, through the leaf cache.
+    Value,
+    /// Fetch a merkle proof from the page tree, through the page cache.
+    Proof,
+}
+
+/// The latencies of a series of reads.
+#[derive(Debug, Clone)]
+pub struct ReadLatencies {
+    sorted: Vec<Duration>,
+}
+
+impl ReadLatencies {
+    fn new(mut samples: Vec<Duration>) -> Self {
+        samples.sort_unstable();
+        ReadLatencies { sorted: samples }
+    }
+
+    /// The latencies of all reads, from fastest to slowest.
+    pub fn samples(&self) -> &[Duration] {
+        &self.sorted
+    }
+
+    /// The mean latency. Zero if there were no reads.
+    pub fn mean(&self) -> Duration {
+        if self.sorted.is_empty() {
+            return Duration::ZERO;
+        }
+        self.sorted.iter().sum::<Duration>() / self.sorted.len() as u32
+    }
+
+    /// The latency which the given fraction of reads did not exceed, e.g. `0.99` for the 99th
+    /// percentile. Zero if there were no reads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fraction` is not within `0.0..=1.0`.
+    pub fn percentile(&self, fraction: f64) -> Duration {
+        assert!((0.0..=1.0).contains(&fraction), "fraction out of range");
+        if self.sorted.is_empty() {
+            return Duration::ZERO;
+        }
+        let index = ((self.sorted.len() - 1) as f64 * fraction).round() as usize;
+        self.sorted[index]
+    }
+}
+
+/// Bring the caches into the given state. The caches are warmed by reading `keys` in both ways,
+/// which is needed only for [`CacheState::Warm`].
+///
+/// Blocks until all sessions have been dropped.
+pub fn set_cache_state<T: HashAlgorithm>(
+    nomt: &Nomt<T>,
+    state: CacheState,
+    keys: &[KeyPath],
+) -> anyhow::Result<()> {
+    match state {
+        CacheState::Cold => nomt.clear_caches(0),
+        CacheState::UpperLevels(levels) => {
+            nomt.clear_caches(levels);
+            nomt.prepopulate_page_cache(levels)?;
+        }
+        CacheState::Warm => {
+            for key in keys {
+                read(nomt, ReadKind::Value, *key)?;
+                read(nomt, ReadKind::Proof, *key)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read `key` once and return the time taken. Opening the session for a proof is not timed.
+pub fn read<T: HashAlgorithm>(
+    nomt: &Nomt<T>,
+    kind: ReadKind,
+    key: KeyPath,
+) -> anyhow::Result<Duration> {
+    match kind {
+        ReadKind::Value => {
+            let start = Instant::now();
+            let _ = nomt.read(key)?;
+            Ok(start.elapsed())
+        }
+        ReadKind::Proof => {
+            let session = nomt.begin_session(SessionParams::default());
+            let start = Instant::now();
+            let _ = session.prove(key)?;
+            Ok(start.elapsed())
+        }
+    }
+}
+
+/// Read each of `keys` once, with the caches in the given state at the start of every read.
+///
+/// The caches are left as the last read left them. Blocks until all sessions have been dropped.
+pub fn measure<T: HashAlgorithm>(
+    nomt: &Nomt<T>,
+    state: CacheState,
+    kind: ReadKind,
+    keys: &[KeyPath],
+) -> anyhow::Result<ReadLatencies> {
+    set_cache_state(nomt, state, keys)?;
+    let mut samples = Vec::with_capacity(keys.len());
+    for key in keys {
+        // Pages within the kept levels are never removed by reads, so clearing is enough to
+        // restore the state.
+        match state {
+            CacheState::Cold => nomt.clear_caches(0),
+            CacheState::UpperLevels(levels) => nomt.clear_caches(levels),
+            CacheState::Warm => {}
+        }
+        samples.push(read(nomt, kind, *key)?);
+    }
+    Ok(ReadLatencies::new(samples))
+}
@@ -0,0 +1,157 @@
+//! Verification and repair of values read from the local database.
+//!
+//! Values in the local database are not checksummed, so a damaged sector of the value store goes
+//! unnoticed until a proof built from the database fails to verify elsewhere. With read repair
+//! configured, a sample of the values read by sessions is checked against the merkle trie: the
+//! local trie proves the key, and the proof must verify against the root of the session and lead
+//! to the value read. Reads failing with an I/O error are always treated as damaged.
+//!
+//! A damaged value is fetched from a [`RemoteArchive`], such as a replica, along with a proof,
+//! which is checked before the value is returned in place of the damaged one. Repaired values are
+//! buffered in memory and written to the local database with [`crate::Nomt::persist_repairs`].
+//! An error is only surfaced if the repair fails as well.
+
+use crate::{
+    read_through::{verify_value, RemoteArchive},
+    HashAlgorithm, Root, Value,
+};
+use nomt_core::{proof::PathProof, trie::KeyPath};
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// Counters describing the reads checked and repaired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadRepairStats {
+    /// The number of values checked against the merkle trie.
+    pub checked: u64,
+    /// The number of values found damaged, including reads which failed with an I/O error.
+    pub damaged: u64,
+    /// The number of damaged values replaced by a value fetched from the remote archive.
+    pub repaired: u64,
+    /// The number of damaged values which could not be repaired.
+    pub unrepaired: u64,
+    /// The number of repaired values not yet written to the local database.
+    pub pending: usize,
+}
+
+/// The read repair configuration given in [`crate::Options::read_repair`].
+#[derive(Clone)]
+pub(crate) struct ReadRepairConfig {
+    pub archive: Arc<dyn RemoteArchive>,
+    pub sample_rate: f64,
+}
+
+impl std::fmt::Debug for ReadRepairConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ReadRepairConfig")
+            .field("sample_rate", &self.sample_rate)
+            .finish()
+    }
+}
+
+pub(crate) struct ReadRepair {
+    archive: Arc<dyn RemoteArchive>,
+    sample_rate: f64,
+    checked: AtomicU64,
+    damaged: AtomicU64,
+    repaired: AtomicU64,
+    unrepaired: AtomicU64,
+    // repaired values, along with the root they were repaired as-of.
+    pending: Mutex<BTreeMap<KeyPath, (Root, Option<Value>)>>,
+    verify: fn(&Option<Value>, &PathProof, KeyPath, Root) -> bool,
+}
+
+impl ReadRepair {
+    pub fn new<T: HashAlgorithm>(config: ReadRepairConfig) -> Self {
+        ReadRepair {
+            archive: config.archive,
+            sample_rate: config.sample_rate,
+            checked: AtomicU64::new(0),
+            damaged: AtomicU64::new(0),
+            repaired: AtomicU64::new(0),
+            unrepaired: AtomicU64::new(0),
+            pending: Mutex::new(BTreeMap::new()),
+            verify: verify_value::<T>,
+        }
+    }
+
+    /// Check the outcome of a local read of the key as-of `root`, repairing it if it is damaged.
+    ///
+    /// `prove` proves the key with the local trie. It is only called if the read is sampled.
+    pub fn check(
+        &self,
+        key_path: KeyPath,
+        root: Root,
+        local: anyhow::Result<Option<Value>>,
+        prove: impl FnOnce() -> anyhow::Result<PathProof>,
+    ) -> anyhow::Result<Option<Value>> {
+        if let Some((repaired_root, value)) = self.pending.lock().get(&key_path) {
+            if *repaired_root == root {
+                return Ok(value.clone());
+            }
+        }
+
+        let error = match local {
+            Ok(value) => {
+                if self.sample_rate == 0.0 || rand::random::<f64>() >= self.sample_rate {
+                    return Ok(value);
+                }
+                self.checked.fetch_add(1, Ordering::Relaxed);
+                match prove() {
+                    Ok(proof) if (self.verify)(&value, &proof, key_path, root) => return Ok(value),
+                    Ok(_) => anyhow::anyhow!("value does not match the merkle trie"),
+                    Err(e) => e,
+                }
+            }
+            Err(e) => e,
+        };
+
+        self.damaged.fetch_add(1, Ordering::Relaxed);
+        match self.fetch(key_path, root) {
+            Ok(value) => {
+                self.repaired.fetch_add(1, Ordering::Relaxed);
+                self.pending.lock().insert(key_path, (root, value.clone()));
+                Ok(value)
+            }
+            Err(repair_error) => {
+                self.unrepaired.fetch_add(1, Ordering::Relaxed);
+                Err(error.context(format!(
+                    "read repair: failed to repair damaged value: {repair_error:#}"
+                )))
+            }
+        }
+    }
+
+    fn fetch(&self, key_path: KeyPath, root: Root) -> anyhow::Result<Option<Value>> {
+        let remote = self.archive.fetch(root, key_path)?;
+        if !(self.verify)(&remote.value, &remote.proof, key_path, root) {
+            anyhow::bail!("remote archive served an invalid proof");
+        }
+        Ok(remote.value)
+    }
+
+    /// Take all repaired values which are still current as-of the given root.
+    pub fn take_pending(&self, root: Root) -> Vec<(KeyPath, Option<Value>)> {
+        std::mem::take(&mut *self.pending.lock())
+            .into_iter()
+            .filter(|(_, (repaired_root, _))| *repaired_root == root)
+            .map(|(k, (_, v))| (k, v))
+            .collect()
+    }
+
+    pub fn stats(&self) -> ReadRepairStats {
+        ReadRepairStats {
+            checked: self.checked.load(Ordering::Relaxed),
+            damaged: self.damaged.load(Ordering::Relaxed),
+            repaired: self.repaired.load(Ordering::Relaxed),
+            unrepaired: self.unrepaired.load(Ordering::Relaxed),
+            pending: self.pending.lock().len(),
+        }
+    }
+}
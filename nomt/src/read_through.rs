@@ -0,0 +1,178 @@
+//! Transparent read-through of values from a remote archive.
+//!
+//! A node which is still importing state can serve reads as-of a target root before the import
+//! completes. When a session reads a key which is absent locally, the value is fetched from a
+//! [`RemoteArchive`] along with a proof, the proof is checked against the target root, and the
+//! result is returned to the caller.
+//!
+//! Fetched values are buffered in memory and written to the local database with
+//! [`crate::Nomt::persist_fetched`]. The merkle pages covering them are rebuilt locally by that
+//! commit, so nothing but values and proofs needs to be fetched. Once the local root reaches the
+//! target root, read-through switches itself off.
+//!
+//! Only values are read through. Merkle pages and beatree leaves missing locally are not fetched
+//! on a miss, and the pages of a session are loaded from the local database only. Read-through
+//! therefore serves reads, not writes: a session which read a value through would build its
+//! changes on pages which don't cover it, so committing it, or the overlay made of it, fails with
+//! [`crate::SessionMisuse::ReadThrough`]. To build on fetched values, persist them first and
+//! begin a new session.
+//!
+//! While read-through is active, the local database is expected to be written only by the state
+//! import converging on the target root. A local deletion of a key would otherwise make reads
+//! fall through to the remote value as-of the target root.
+
+use crate::{HashAlgorithm, Root, Value};
+use nomt_core::{
+    proof::PathProof,
+    trie::{KeyPath, LeafData},
+};
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// A source of values as-of some root, such as an archive node reachable over the network.
+///
+/// Implementations are free to block. Whatever is returned is verified before being used.
+pub trait RemoteArchive: Send + Sync {
+    /// Fetch the value stored under the key as-of the given root, along with a proof of it.
+    fn fetch(&self, root: Root, key_path: KeyPath) -> anyhow::Result<RemoteValue>;
+}
+
+/// A value served by a [`RemoteArchive`].
+#[derive(Debug, Clone)]
+pub struct RemoteValue {
+    /// The value, or `None` if the key has no value.
+    pub value: Option<Value>,
+    /// A proof of the path to the key.
+    pub proof: PathProof,
+}
+
+/// Counters describing the state of read-through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadThroughStats {
+    /// Whether read-through is still active.
+    pub active: bool,
+    /// The total number of values fetched from the remote archive.
+    pub fetched: u64,
+    /// The number of fetched values not yet written to the local database.
+    pub pending: usize,
+}
+
+/// The read-through configuration given in [`crate::Options::read_through`].
+#[derive(Clone)]
+pub(crate) struct ReadThroughConfig {
+    pub archive: Arc<dyn RemoteArchive>,
+    pub target_root: Root,
+}
+
+impl std::fmt::Debug for ReadThroughConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ReadThroughConfig")
+            .field("target_root", &self.target_root)
+            .finish()
+    }
+}
+
+pub(crate) struct ReadThrough {
+    archive: Arc<dyn RemoteArchive>,
+    target_root: Root,
+    active: AtomicBool,
+    fetched: AtomicU64,
+    pending: Mutex<BTreeMap<KeyPath, Option<Value>>>,
+    verify: fn(&RemoteValue, KeyPath, Root) -> bool,
+}
+
+impl ReadThrough {
+    pub fn new<T: HashAlgorithm>(config: ReadThroughConfig) -> Self {
+        ReadThrough {
+            archive: config.archive,
+            target_root: config.target_root,
+            active: AtomicBool::new(true),
+            fetched: AtomicU64::new(0),
+            pending: Mutex::new(BTreeMap::new()),
+            verify: verify_remote::<T>,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub fn deactivate(&self) {
+        self.active.store(false, Ordering::Relaxed);
+        self.pending.lock().clear();
+    }
+
+    pub fn target_root(&self) -> Root {
+        self.target_root
+    }
+
+    /// Read a value missing from the local database, fetching it if necessary.
+    pub fn read(&self, key_path: KeyPath) -> anyhow::Result<Option<Value>> {
+        if let Some(value) = self.pending.lock().get(&key_path) {
+            return Ok(value.clone());
+        }
+
+        let remote = self.archive.fetch(self.target_root, key_path)?;
+        if !(self.verify)(&remote, key_path, self.target_root) {
+            anyhow::bail!(
+                "read-through: remote archive served an invalid proof for key {:?}",
+                key_path
+            );
+        }
+
+        self.fetched.fetch_add(1, Ordering::Relaxed);
+        self.pending.lock().insert(key_path, remote.value.clone());
+        Ok(remote.value)
+    }
+
+    /// Take all fetched values not yet written locally. Absent values are dropped, as there is
+    /// nothing to write for them.
+    pub fn take_pending(&self) -> Vec<(KeyPath, Value)> {
+        std::mem::take(&mut *self.pending.lock())
+            .into_iter()
+            .filter_map(|(k, v)| v.map(|v| (k, v)))
+            .collect()
+    }
+
+    pub fn stats(&self) -> ReadThroughStats {
+        ReadThroughStats {
+            active: self.is_active(),
+            fetched: self.fetched.load(Ordering::Relaxed),
+            pending: self.pending.lock().len(),
+        }
+    }
+}
+
+fn verify_remote<T: HashAlgorithm>(remote: &RemoteValue, key_path: KeyPath, root: Root) -> bool {
+    verify_value::<T>(&remote.value, &remote.proof, key_path, root)
+}
+
+// Whether the proof shows that the key has the given value under the root.
+pub(crate) fn verify_value<T: HashAlgorithm>(
+    value: &Option<Value>,
+    proof: &PathProof,
+    key_path: KeyPath,
+    root: Root,
+) -> bool {
+    use bitvec::prelude::*;
+
+    let Ok(verified) = proof.verify::<T>(key_path.view_bits::<Msb0>(), root.into_inner()) else {
+        return false;
+    };
+
+    let confirmed = match value {
+        Some(ref value) => verified.confirm_value(&LeafData {
+            key_path,
+            value_hash: T::hash_value(value),
+        }),
+        None => verified.confirm_nonexistence(&key_path),
+    };
+
+    confirmed.unwrap_or(false)
+}
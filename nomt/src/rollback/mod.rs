@@ -13,7 +13,10 @@ use std::{
     fs::File,
     io::Cursor,
     path::PathBuf,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 
 use crate::{
@@ -23,13 +26,13 @@ use crate::{
 use crossbeam::channel::Sender;
 use crossbeam_channel::Receiver;
 use dashmap::DashMap;
-use nomt_core::trie::KeyPath;
+use nomt_core::trie::{KeyPath, ValueHash};
 use parking_lot::Mutex;
 use threadpool::ThreadPool;
 
 use self::reverse_delta_worker::{DeltaBuilderCommand, LoadValueAsync, StoreLoadValueAsync};
 use crate::{
-    seglog::{self, RecordId, SegmentedLog},
+    seglog::{self, ArchiveRetention, LogArchiveStats, RecordId, SegmentedLog},
     KeyReadWrite,
 };
 
@@ -52,8 +55,14 @@ struct InMemory {
     /// are re-read from disk and stored here.
     log: VecDeque<(RecordId, Delta)>,
 
+    /// The on-disk sizes of the deltas in `log`, in the same order.
+    delta_bytes: VecDeque<u64>,
+
     /// If this is set, then the next writeout will truncate the log at this offset.
     pending_truncate: Option<u64>,
+
+    /// The number of bytes appended to the on-disk log since the last writeout.
+    appended_bytes: u64,
 }
 
 struct Shared {
@@ -64,26 +73,116 @@ struct Shared {
     /// The number of items that we should keep in the log. Deltas that are past this limit are
     /// discarded.
     max_rollback_log_len: usize,
+    db_dir_fd: Arc<File>,
+    disk_alert: Option<DiskAlert>,
+    /// Whether the disk alert was raised and the log has not dropped below the threshold since.
+    disk_alert_raised: AtomicBool,
+}
+
+/// A key whose value was changed by a rollback. See [`crate::Nomt::rollback_with_changes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RolledBackKey {
+    /// The key.
+    pub key: KeyPath,
+    /// The hash of the value before the rollback. `None` if the key was not present.
+    pub prior: Option<ValueHash>,
+    /// The hash of the value after the rollback. `None` if the key was deleted.
+    pub restored: Option<ValueHash>,
+}
+
+/// The number of pages and leaves cached before and after a [`crate::Nomt::rollback`].
+///
+/// See [`crate::Options::rollback_cache_policy`]. The counts after the rollback include the
+/// entries it loaded or patched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RollbackCacheStats {
+    /// The number of pages in the page cache before the rollback.
+    pub pages_before: usize,
+    /// The number of pages in the page cache after the rollback.
+    pub pages_after: usize,
+    /// The number of leaves in the leaf cache before the rollback.
+    pub leaves_before: usize,
+    /// The number of leaves in the leaf cache after the rollback.
+    pub leaves_after: usize,
+}
+
+/// The disk usage of the rollback log. See [`crate::Nomt::rollback_stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RollbackStats {
+    /// The on-disk sizes of the reverse deltas in the log, in bytes, from the oldest commit to
+    /// the most recent. Each delta allows one commit to be rolled back.
+    pub delta_bytes: Vec<u64>,
+    /// The total size of the files of the rollback log, in bytes. The log is pruned in whole
+    /// segments, so this may exceed the sum of `delta_bytes`.
+    pub log_bytes: u64,
+}
+
+/// The disk usage passed to the alert set with [`crate::Options::rollback_disk_alert`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RollbackDiskUsage {
+    /// The total size of the files of the rollback log, in bytes.
+    pub log_bytes: u64,
+    /// The capacity of the file system holding the database, in bytes.
+    pub disk_bytes: u64,
+}
+
+impl RollbackDiskUsage {
+    /// The fraction of the file system taken by the rollback log.
+    pub fn fraction(&self) -> f64 {
+        if self.disk_bytes == 0 {
+            0.0
+        } else {
+            self.log_bytes as f64 / self.disk_bytes as f64
+        }
+    }
+}
+
+type AlertFn = dyn Fn(RollbackDiskUsage) + Send + Sync;
+
+/// The alert given in [`crate::Options::rollback_disk_alert`].
+#[derive(Clone)]
+pub(crate) struct DiskAlert {
+    max_fraction: f64,
+    hook: Arc<AlertFn>,
+}
+
+impl std::fmt::Debug for DiskAlert {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DiskAlert")
+            .field("max_fraction", &self.max_fraction)
+            .finish()
+    }
+}
+
+impl DiskAlert {
+    pub fn new(max_fraction: f64, hook: Arc<AlertFn>) -> Self {
+        DiskAlert { max_fraction, hook }
+    }
 }
 
 impl InMemory {
     fn new() -> Self {
         Self {
             log: VecDeque::new(),
+            delta_bytes: VecDeque::new(),
             pending_truncate: None,
+            appended_bytes: 0,
         }
     }
 
     /// Push a delta into the in-memory cache.
-    fn push_recent(&mut self, record_id: RecordId, delta: Delta) {
+    fn push_recent(&mut self, record_id: RecordId, delta: Delta, delta_bytes: u64) {
         self.log.push_back((record_id, delta));
+        self.delta_bytes.push_back(delta_bytes);
     }
 
     fn pop_recent(&mut self) -> Option<(RecordId, Delta)> {
+        self.delta_bytes.pop_back();
         self.log.pop_back()
     }
 
     fn pop_oldest(&mut self) -> Option<(RecordId, Delta)> {
+        self.delta_bytes.pop_front();
         self.log.pop_front()
     }
 
@@ -109,19 +208,21 @@ impl Rollback {
         db_dir_fd: Arc<File>,
         rollback_start_active: u64,
         rollback_end_active: u64,
+        archive_retention: ArchiveRetention,
+        disk_alert: Option<DiskAlert>,
     ) -> anyhow::Result<Self> {
         let mut in_memory = InMemory::new();
         let seglog = seglog::open(
             db_dir_path,
-            db_dir_fd,
+            db_dir_fd.clone(),
             "rollback".to_string(),
             MAX_SEGMENT_SIZE,
-            rollback_start_active.into(),
-            rollback_end_active.into(),
+            (rollback_start_active.into(), rollback_end_active.into()),
+            archive_retention,
             |record_id, payload| {
                 let mut cursor = Cursor::new(payload);
                 let delta = Delta::decode(&mut cursor)?;
-                in_memory.push_recent(record_id, delta);
+                in_memory.push_recent(record_id, delta, seglog::record_size(payload.len()));
                 Ok(())
             },
         )?;
@@ -131,10 +232,35 @@ impl Rollback {
             in_memory: Mutex::new(in_memory),
             seglog: Mutex::new(seglog),
             max_rollback_log_len: max_rollback_log_len as usize,
+            db_dir_fd,
+            disk_alert,
+            disk_alert_raised: AtomicBool::new(false),
         });
         Ok(Self { shared })
     }
 
+    /// Get the statistics of the archive of obsolete log segments, as of opening the log.
+    pub fn archive_stats(&self) -> LogArchiveStats {
+        self.shared.seglog.lock().archive_stats()
+    }
+
+    /// Get the disk usage of the log.
+    pub fn stats(&self) -> std::io::Result<RollbackStats> {
+        let delta_bytes = self
+            .shared
+            .in_memory
+            .lock()
+            .delta_bytes
+            .iter()
+            .copied()
+            .collect();
+        let log_bytes = self.shared.seglog.lock().disk_bytes()?;
+        Ok(RollbackStats {
+            delta_bytes,
+            log_bytes,
+        })
+    }
+
     /// Begin a rollback delta.
     pub fn delta_builder(
         &self,
@@ -169,7 +295,9 @@ impl Rollback {
         let mut seglog = self.shared.seglog.lock();
 
         let record_id = seglog.append(&delta_bytes)?;
-        in_memory.push_recent(record_id, delta);
+        let record_size = seglog::record_size(delta_bytes.len());
+        in_memory.appended_bytes += record_size;
+        in_memory.push_recent(record_id, delta, record_size);
         Ok(())
     }
 
@@ -190,10 +318,30 @@ impl Rollback {
         };
 
         let record_id = seglog.append(&delta_bytes)?;
-        in_memory.push_recent(record_id, delta);
+        let record_size = seglog::record_size(delta_bytes.len());
+        in_memory.appended_bytes += record_size;
+        in_memory.push_recent(record_id, delta, record_size);
         Ok(None)
     }
 
+    /// Returns copies of the last `n` deltas, the most recent first, without modifying the log.
+    /// `None` if fewer than `n` deltas are logged.
+    pub fn recent(&self, n: usize) -> Option<Vec<Delta>> {
+        let in_memory = self.shared.in_memory.lock();
+        if n > in_memory.total_len() {
+            return None;
+        }
+        Some(
+            in_memory
+                .log
+                .iter()
+                .rev()
+                .take(n)
+                .map(|(_, delta)| delta.clone())
+                .collect(),
+        )
+    }
+
     /// Truncates the rollback log by removing the last `n` deltas.
     ///
     /// This function returns the keys and values that we should apply to the database to restore
@@ -256,6 +404,7 @@ impl Rollback {
         let seglog = self.shared.seglog.lock();
 
         let pending_truncate = in_memory.pending_truncate.take();
+        let appended_bytes = std::mem::take(&mut in_memory.appended_bytes);
 
         // NOTE: for now, if there is a pending truncate, we ignore everything else.
         if let Some(pending_truncate) = pending_truncate {
@@ -265,6 +414,7 @@ impl Rollback {
                 rollback_end_live: pending_truncate,
                 prune_to_new_start_live: None,
                 prune_to_new_end_live: Some(pending_truncate),
+                appended_bytes,
             };
         }
 
@@ -281,6 +431,7 @@ impl Rollback {
             rollback_end_live: rollback_end_live.0,
             prune_to_new_start_live,
             prune_to_new_end_live: None,
+            appended_bytes,
         }
     }
 
@@ -304,9 +455,38 @@ impl Rollback {
             let mut seglog = self.shared.seglog.lock();
             seglog.prune_recent(new_end_live.into())?;
         }
+        self.check_disk_alert();
         Ok(())
     }
 
+    // Call the disk alert when the log grows past its threshold. It is raised again only after
+    // the log has dropped below the threshold in the meantime.
+    //
+    // Failing to measure the disk usage is not worth failing the sync over, so the check is
+    // skipped instead.
+    fn check_disk_alert(&self) {
+        let Some(alert) = &self.shared.disk_alert else {
+            return;
+        };
+        let Ok(log_bytes) = self.shared.seglog.lock().disk_bytes() else {
+            return;
+        };
+        let Ok(disk_bytes) = crate::sys::fs_capacity(&self.shared.db_dir_fd) else {
+            return;
+        };
+        let usage = RollbackDiskUsage {
+            log_bytes,
+            disk_bytes,
+        };
+        if usage.fraction() <= alert.max_fraction {
+            self.shared
+                .disk_alert_raised
+                .store(false, Ordering::Relaxed);
+        } else if !self.shared.disk_alert_raised.swap(true, Ordering::Relaxed) {
+            (alert.hook)(usage);
+        }
+    }
+
     #[cfg(test)]
     pub fn seglog(&self) -> parking_lot::MutexGuard<'_, SegmentedLog> {
         self.shared.seglog.lock()
@@ -345,6 +525,14 @@ impl SyncController {
         res
     }
 
+    /// The number of bytes appended to the rollback log by the commits covered by this sync.
+    ///
+    /// Must be called after [`Self::begin_sync`].
+    pub fn appended_bytes(&self) -> u64 {
+        // UNWRAP: `writeout_data` is set in `begin_sync`.
+        self.writeout_data.as_ref().unwrap().appended_bytes
+    }
+
     /// This should be called after the meta has been updated.
     ///
     /// This function doesn't block.
@@ -377,6 +565,8 @@ struct WriteoutData {
     prune_to_new_start_live: Option<u64>,
     /// If this is `Some`, then the [`Rollback::writeout_end`] should be called with this value.
     prune_to_new_end_live: Option<u64>,
+    /// The number of bytes appended to the log since the previous writeout.
+    appended_bytes: u64,
 }
 
 pub struct ReverseDeltaBuilder {
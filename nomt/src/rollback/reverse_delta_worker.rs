@@ -334,7 +334,9 @@ impl AsyncPending for AsyncLookup {
         completion: FatPage,
         overflow_meta: Option<OverflowPageInfo>,
     ) -> Option<Option<Vec<u8>>> {
+        // TODO handle error properly
         self.try_finish(completion, overflow_meta)
+            .map(|value| value.unwrap())
     }
 }
 
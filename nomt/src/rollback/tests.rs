@@ -1,7 +1,8 @@
 use std::{collections::BTreeSet, fs::OpenOptions, sync::Arc};
 
 use super::{
-    reverse_delta_worker::AsyncPending, BTreeMap, KeyPath, KeyReadWrite, LoadValueAsync, Rollback,
+    reverse_delta_worker::AsyncPending, ArchiveRetention, BTreeMap, KeyPath, KeyReadWrite,
+    LoadValueAsync, Rollback,
 };
 use crossbeam::channel::{Receiver, Sender};
 use hex_literal::hex;
@@ -138,8 +139,16 @@ fn truncate_works() {
         Some(b"old_value3".to_vec()),
     );
 
-    let rollback =
-        Rollback::read(MAX_ROLLBACK_LOG_LEN, db_dir_path, Arc::new(db_dir_fd), 0, 0).unwrap();
+    let rollback = Rollback::read(
+        MAX_ROLLBACK_LOG_LEN,
+        db_dir_path,
+        Arc::new(db_dir_fd),
+        0,
+        0,
+        ArchiveRetention::default(),
+        None,
+    )
+    .unwrap();
     let builder = rollback.delta_builder_inner(store.async_reader());
     builder.tentative_preserve_prior([1; 32]);
     builder.tentative_preserve_prior([2; 32]);
@@ -205,8 +214,16 @@ fn without_tentative_preserve_prior() {
         Some(b"old_value3".to_vec()),
     );
 
-    let rollback =
-        Rollback::read(MAX_ROLLBACK_LOG_LEN, db_dir_path, Arc::new(db_dir_fd), 0, 0).unwrap();
+    let rollback = Rollback::read(
+        MAX_ROLLBACK_LOG_LEN,
+        db_dir_path,
+        Arc::new(db_dir_fd),
+        0,
+        0,
+        ArchiveRetention::default(),
+        None,
+    )
+    .unwrap();
     let builder = rollback.delta_builder_inner(store.async_reader());
     let delta = builder.finalize(&[
         (
@@ -261,8 +278,16 @@ fn delta_builder_doesnt_load_read_then_write_priors() {
     let mut store = MockStore::new();
     store.trap(key_1);
 
-    let rollback =
-        Rollback::read(MAX_ROLLBACK_LOG_LEN, db_dir_path, Arc::new(db_dir_fd), 0, 0).unwrap();
+    let rollback = Rollback::read(
+        MAX_ROLLBACK_LOG_LEN,
+        db_dir_path,
+        Arc::new(db_dir_fd),
+        0,
+        0,
+        ArchiveRetention::default(),
+        None,
+    )
+    .unwrap();
     let builder = rollback.delta_builder_inner(store.async_reader());
     let delta = builder.finalize(&[(
         key_1,
@@ -294,8 +319,16 @@ fn rollback_writeout_start_and_end() {
         .unwrap();
     let store = MockStore::new();
 
-    let rollback =
-        Rollback::read(MAX_ROLLBACK_LOG_LEN, db_dir_path, Arc::new(db_dir_fd), 0, 0).unwrap();
+    let rollback = Rollback::read(
+        MAX_ROLLBACK_LOG_LEN,
+        db_dir_path,
+        Arc::new(db_dir_fd),
+        0,
+        0,
+        ArchiveRetention::default(),
+        None,
+    )
+    .unwrap();
 
     // fill the rollback with the max amount of deltas + 1
     for _ in 0..MAX_ROLLBACK_LOG_LEN + 1 {
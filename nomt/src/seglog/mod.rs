@@ -40,6 +40,11 @@ const RECORD_ALIGNMENT: u32 = 4096; // 4K alignment
 const HEADER_SIZE: u32 = 12; // 8 bytes for record ID, 4 bytes for payload length
 const MAX_RECORD_PAYLOAD_SIZE: u32 = 1 << 30; // 1 GiB
 
+/// The number of bytes a record with a payload of the given length occupies on disk.
+pub fn record_size(payload_len: usize) -> u64 {
+    (HEADER_SIZE as u64 + payload_len as u64).next_multiple_of(RECORD_ALIGNMENT as u64)
+}
+
 /// A record ID.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct RecordId(pub u64);
@@ -124,6 +129,31 @@ impl<'a> RecordHeaderMut<'a> {
     }
 }
 
+/// The limits on the obsolete segments kept in the archive of a log.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ArchiveRetention {
+    /// The maximum number of archived segments.
+    pub max_segments: usize,
+    /// The maximum total size of archived segments, in bytes.
+    pub max_bytes: u64,
+}
+
+/// The obsolete segments moved to the archive of a log and trimmed from it when the log was
+/// opened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LogArchiveStats {
+    /// The number of segments which were found to be obsolete and moved to the archive.
+    pub archived_segments: usize,
+    /// The number of archived segments deleted to stay within the retention limits.
+    pub trimmed_segments: usize,
+    /// The total size of the deleted segments, in bytes.
+    pub trimmed_bytes: u64,
+    /// The number of segments remaining in the archive.
+    pub retained_segments: usize,
+    /// The total size of the segments remaining in the archive, in bytes.
+    pub retained_bytes: u64,
+}
+
 #[derive(Debug)]
 struct Segment {
     /// The ID of the segment.
@@ -173,6 +203,8 @@ pub struct SegmentedLog {
     segments: Vec<Segment>,
     /// The head segment file writer.
     head_segment_writer: Option<SegmentFileWriter>,
+    /// What happened to the obsolete segments when the log was opened.
+    archive_stats: LogArchiveStats,
 }
 
 impl SegmentedLog {
@@ -224,7 +256,7 @@ impl SegmentedLog {
         if root_dir_fsync {
             // To uphold the guarantees provided by this function we should fsync the directory
             // after a new segment file is created.
-            self.root_dir_fd.sync_all()?;
+            crate::sys::sync_dir(&self.root_dir_fd)?;
         }
 
         Ok(record_id)
@@ -363,7 +395,7 @@ impl SegmentedLog {
             fs::remove_file(self.root_dir_path.join(filename))?;
             self.segments.pop();
         }
-        self.root_dir_fd.sync_data()?;
+        crate::sys::sync_dir(&self.root_dir_fd)?;
 
         if let Some(head_segment_writer) = self.head_segment_writer.take().take() {
             let file = head_segment_writer.into_inner();
@@ -402,6 +434,20 @@ impl SegmentedLog {
     pub fn live_range(&self) -> (RecordId, RecordId) {
         (self.start_live, self.end_live)
     }
+
+    /// Get the total size of the segment files, in bytes.
+    pub fn disk_bytes(&self) -> std::io::Result<u64> {
+        let mut bytes = 0;
+        for segment in &self.segments {
+            bytes += fs::metadata(&segment.path)?.len();
+        }
+        Ok(bytes)
+    }
+
+    /// Get the statistics of the archive of obsolete segments, as of opening the log.
+    pub fn archive_stats(&self) -> LogArchiveStats {
+        self.archive_stats
+    }
 }
 
 struct Recovery {
@@ -570,8 +616,14 @@ impl Recovery {
         self.live_segment_start.is_some() && self.live_segment_end.is_none()
     }
 
-    /// Remove the segments that do not contain any live records.
-    fn remove_nonlive_segments(mut self) -> Result<Vec<Segment>> {
+    /// Move the segments that do not contain any live records to the archive and trim the
+    /// archive to the retention limits.
+    fn archive_nonlive_segments(
+        mut self,
+        root_dir_path: &Path,
+        filename_prefix: &str,
+        retention: ArchiveRetention,
+    ) -> Result<(Vec<Segment>, LogArchiveStats)> {
         let live_segments_indices: Option<(usize, usize)> =
             match (self.live_segment_start, self.live_segment_end) {
                 (None, None) => None,
@@ -590,13 +642,60 @@ impl Recovery {
             nonlive_segments = mem::take(&mut self.candidates);
         }
 
-        for segment in nonlive_segments {
-            fs::remove_file(segment.path)?;
+        let archive_prefix = archive_prefix(filename_prefix);
+        let mut archived = scan_archive(root_dir_path, &archive_prefix)?;
+        let first_id = archived.last().map_or(1, |(id, _, _)| id + 1);
+        let mut stats = LogArchiveStats::default();
+        for (id, segment) in (first_id..).zip(nonlive_segments) {
+            let size = segment.path.metadata()?.len();
+            let path = root_dir_path.join(segment_filename::format(&archive_prefix, id));
+            fs::rename(&segment.path, &path)?;
+            archived.push((id, path, size));
+            stats.archived_segments += 1;
+        }
+
+        // Keep the most recently archived segments which fit within the limits.
+        for (_, path, size) in archived.into_iter().rev() {
+            if stats.retained_segments < retention.max_segments
+                && stats.retained_bytes + size <= retention.max_bytes
+            {
+                stats.retained_segments += 1;
+                stats.retained_bytes += size;
+            } else {
+                fs::remove_file(path)?;
+                stats.trimmed_segments += 1;
+                stats.trimmed_bytes += size;
+            }
         }
-        Ok(live_segments)
+        Ok((live_segments, stats))
     }
 }
 
+/// The prefix of the archived segments of the log with the given prefix.
+///
+/// This must not start with the prefix of the log, so that archived segments are not mistaken for
+/// segments of the log.
+fn archive_prefix(filename_prefix: &str) -> String {
+    format!("archived.{filename_prefix}")
+}
+
+/// Returns the ID, path and size of every archived segment, ordered by ID.
+fn scan_archive(root_dir_path: &Path, archive_prefix: &str) -> Result<Vec<(u32, PathBuf, u64)>> {
+    let mut archived = Vec::new();
+    for entry in fs::read_dir(root_dir_path)? {
+        let entry = entry?;
+        let filename = entry.file_name();
+        if let Some(filename) = filename.to_str() {
+            if filename.starts_with(archive_prefix) {
+                let id = segment_filename::parse(archive_prefix, filename)?;
+                archived.push((id, entry.path(), entry.metadata()?.len()));
+            }
+        }
+    }
+    archived.sort_by_key(|(id, _, _)| *id);
+    Ok(archived)
+}
+
 /// Scans the segment file and returns the file offset of the end of the specified record.
 fn scan_record_end(path: &Path, end_live: RecordId) -> std::io::Result<Option<u64>> {
     let mut seg_reader = SegmentFileReader::new(File::open(path)?, None)?;
@@ -645,14 +744,17 @@ fn truncate_head_segment(
 /// This function will read the records in the live range and pass them to the provided
 /// callback. The records passed in the callback fall in the live range.
 ///
+/// Segments outside of the live range are moved to the archive, which is then trimmed to
+/// `archive_retention`.
+///
 /// Returns early in case an error is encountered.
 pub fn open<F>(
     root_dir_path: PathBuf,
     root_dir_fd: Arc<File>,
     filename_prefix: String,
     max_segment_size: u64,
-    start_live: RecordId,
-    end_live: RecordId,
+    (start_live, end_live): (RecordId, RecordId),
+    archive_retention: ArchiveRetention,
     mut process_record: F,
 ) -> anyhow::Result<SegmentedLog>
 where
@@ -714,7 +816,8 @@ where
         );
     }
 
-    let mut segments = recovery.remove_nonlive_segments()?;
+    let (mut segments, archive_stats) =
+        recovery.archive_nonlive_segments(&root_dir_path, &filename_prefix, archive_retention)?;
     let mut head_segment_writer = None;
     if let Some(head) = segments.last_mut() {
         head.max = end_live;
@@ -730,13 +833,15 @@ where
         end_live,
         segments,
         head_segment_writer,
+        archive_stats,
     })
 }
 
 #[cfg(test)]
 mod tests {
     use super::{
-        open, segment_filename, RecordId, Result, SegmentFileWriter, SegmentedLog, RECORD_ALIGNMENT,
+        open, segment_filename, ArchiveRetention, LogArchiveStats, RecordId, Result,
+        SegmentFileWriter, SegmentedLog, RECORD_ALIGNMENT,
     };
     use std::{
         fs::{self, File},
@@ -759,6 +864,9 @@ mod tests {
         max_segment_size_for_n_records(2)
     }
 
+    /// A freshly opened log along with the records replayed while opening it.
+    type OpenedLog = (SegmentedLog, Vec<(RecordId, Vec<u8>)>);
+
     struct TestHarness {
         temp_dir: TempDir,
         filename_prefix: String,
@@ -778,16 +886,31 @@ mod tests {
             max_segment_size: u64,
             start_live: impl Into<RecordId>,
             end_live: impl Into<RecordId>,
-        ) -> Result<(SegmentedLog, Vec<(RecordId, Vec<u8>)>)> {
-            let root_dir_fd = File::open(self.temp_dir.path())?;
+        ) -> Result<OpenedLog> {
+            self.open_log_with_retention(
+                max_segment_size,
+                start_live,
+                end_live,
+                ArchiveRetention::default(),
+            )
+        }
+
+        fn open_log_with_retention(
+            &self,
+            max_segment_size: u64,
+            start_live: impl Into<RecordId>,
+            end_live: impl Into<RecordId>,
+            archive_retention: ArchiveRetention,
+        ) -> Result<OpenedLog> {
+            let root_dir_fd = crate::sys::open_dir(self.temp_dir.path())?;
             let mut records = Vec::new();
             let log = open(
                 self.temp_dir.path().to_path_buf(),
                 Arc::new(root_dir_fd),
                 self.filename_prefix.clone(),
                 max_segment_size,
-                start_live.into(),
-                end_live.into(),
+                (start_live.into(), end_live.into()),
+                archive_retention,
                 |record_id, payload| {
                     records.push((record_id, payload.to_vec()));
                     Ok(())
@@ -1043,6 +1166,55 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn recovery_archives_nonlive_segments() -> Result<()> {
+        let h = TestHarness::new()?;
+        for id in 1..=3 {
+            h.new_segment(id)?
+                .write_record(id as u64, &[1u8; 10])?
+                .write()?;
+        }
+        let segment_size = RECORD_ALIGNMENT as u64;
+        let retention = ArchiveRetention {
+            max_segments: 1,
+            max_bytes: u64::MAX,
+        };
+
+        // The two oldest segments are archived, and only the most recent of them is kept.
+        let (log, records) =
+            h.open_log_with_retention(default_max_segment_size(), 3, 3, retention)?;
+        assert_eq!(records, vec![(RecordId::from(3), vec![1u8; 10])]);
+        assert_eq!(
+            log.archive_stats(),
+            LogArchiveStats {
+                archived_segments: 2,
+                trimmed_segments: 1,
+                trimmed_bytes: segment_size,
+                retained_segments: 1,
+                retained_bytes: segment_size,
+            }
+        );
+        h.assert_segment_file_count(1)?;
+        let archived = |id| {
+            h.temp_dir
+                .path()
+                .join(segment_filename::format("archived.test", id))
+        };
+        assert!(!archived(1).exists());
+        assert!(archived(2).exists());
+        drop(log);
+
+        // Archived segments are kept across openings and are trimmed to the new limits.
+        let (log, _) = h.open_log_with_retention(default_max_segment_size(), 3, 3, retention)?;
+        assert_eq!(log.archive_stats().retained_segments, 1);
+        drop(log);
+        let (log, _) = h.open_log(default_max_segment_size(), 3, 3)?;
+        assert_eq!(log.archive_stats().trimmed_segments, 1);
+        assert_eq!(log.archive_stats().retained_segments, 0);
+
+        Ok(())
+    }
+
     #[test]
     fn recovery_fail_gap_in_segments() -> Result<()> {
         let h = TestHarness::new()?;
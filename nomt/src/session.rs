@@ -0,0 +1,268 @@
+//! The parameters of sessions and the types they use to describe keys and values.
+
+use crate::{merkle, KeyReadWrite, Value, Witness, WitnessedValue};
+use nomt_core::{
+    hasher::ValueHasher,
+    trie::{KeyPath, ValueHash},
+};
+use parking_lot::{ArcRwLockReadGuard, Mutex};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::ThreadId,
+};
+
+/// A configuration type used to inform NOMT whether to trace the data accessed by a session.
+///
+/// Tracing is independent of the [`crate::WitnessMode`] and much cheaper than witnessing.
+#[derive(Debug, Clone, Copy)]
+pub struct AccessTraceMode {
+    pub(crate) keys: bool,
+    pub(crate) pages: bool,
+}
+
+impl AccessTraceMode {
+    /// Trace the keys read and written.
+    pub fn keys() -> Self {
+        AccessTraceMode {
+            keys: true,
+            pages: false,
+        }
+    }
+
+    /// Trace the keys read and written, and the pages on the paths to them.
+    pub fn keys_and_pages() -> Self {
+        AccessTraceMode {
+            keys: true,
+            pages: true,
+        }
+    }
+
+    /// Do not trace accesses.
+    pub fn disabled() -> Self {
+        AccessTraceMode {
+            keys: false,
+            pages: false,
+        }
+    }
+}
+
+/// Which state a session observes when it is begun while a commit or rollback is pending.
+///
+/// A commit waits for all live sessions to be dropped before it starts writing, and sessions
+/// cannot be begun while it writes. This decides how a new session orders itself against a commit
+/// which is waiting for live sessions.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SessionSyncMode {
+    /// Wait for pending commits and rollbacks to finish and observe the root they produce.
+    ///
+    /// A commit is pending once it has started waiting for live sessions.
+    #[default]
+    WaitForSync,
+    /// Observe the pre-sync root: if other sessions are live, begin alongside them without
+    /// waiting for pending commits or rollbacks.
+    ///
+    /// The pending commit then also waits for this session. Since the commit will change the
+    /// root, committing this session afterwards fails. If there are no live sessions, a commit
+    /// may already be writing; the pre-sync state no longer exists then, and the session waits
+    /// for it as with [`SessionSyncMode::WaitForSync`]. Use [`crate::Session::prev_root`] to learn
+    /// which root the session observes.
+    ///
+    /// A session only begins ahead of a pending commit while a session which began before the
+    /// commit is live. Once only sessions which began ahead of it are left, new sessions wait, so
+    /// that a stream of overlapping sessions can't hold the commit off forever.
+    PreSync,
+}
+
+/// A condition on the current value of a key, attached to a session with
+/// [`crate::Session::require`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precondition {
+    /// The key must hold a value with the given hash.
+    ValueHash(ValueHash),
+    /// The key must not hold a value.
+    Absent,
+}
+
+impl Precondition {
+    pub(crate) fn holds(&self, actual: Option<ValueHash>) -> bool {
+        match self {
+            Precondition::ValueHash(hash) => actual == Some(*hash),
+            Precondition::Absent => actual.is_none(),
+        }
+    }
+}
+
+/// How often a written key is expected to be read, given to [`crate::Session::hint_storage_class`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageClass {
+    /// The key is read often. Its leaf is kept in the leaf cache after the commit, as for keys
+    /// without a hint, even if it also holds cold keys.
+    Hot,
+    /// The key is rarely read. Its leaf is left out of the leaf cache after the commit, unless it
+    /// also holds a hot key.
+    Cold,
+}
+
+/// A value as stored by this database. See [`crate::Session::write_value_hash`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub enum LocalValue {
+    /// The value is stored locally.
+    Stored(Value),
+    /// The value is stored elsewhere and only its hash is kept.
+    HashOnly(ValueHash),
+}
+
+impl LocalValue {
+    /// The hash of the value.
+    pub fn value_hash<T: ValueHasher>(&self) -> ValueHash {
+        match self {
+            LocalValue::Stored(value) => T::hash_value(value),
+            LocalValue::HashOnly(value_hash) => *value_hash,
+        }
+    }
+}
+
+// The number of live sessions begun by each thread, so that operations which wait for all
+// sessions to end can refuse to wait on a thread which would never end its own.
+#[derive(Default)]
+pub(crate) struct LiveSessions(Mutex<HashMap<ThreadId, usize>>);
+
+impl LiveSessions {
+    pub(crate) fn register(self: &Arc<Self>) -> SessionRegistration {
+        let thread = std::thread::current().id();
+        *self.0.lock().entry(thread).or_default() += 1;
+        SessionRegistration {
+            sessions: self.clone(),
+            thread,
+        }
+    }
+
+    pub(crate) fn on_current_thread(&self) -> bool {
+        self.0.lock().contains_key(&std::thread::current().id())
+    }
+}
+
+// The registration of a live session, removed when dropped.
+pub(crate) struct SessionRegistration {
+    sessions: Arc<LiveSessions>,
+    thread: ThreadId,
+}
+
+impl Drop for SessionRegistration {
+    fn drop(&mut self) {
+        let mut sessions = self.sessions.0.lock();
+        if let Some(count) = sessions.get_mut(&self.thread) {
+            *count -= 1;
+            if *count == 0 {
+                sessions.remove(&self.thread);
+            }
+        }
+    }
+}
+
+// The hold of a session on the database.
+pub(crate) struct SessionAccess {
+    pub(crate) _guard: ArcRwLockReadGuard<parking_lot::RawRwLock, ()>,
+    pub(crate) _registration: SessionRegistration,
+    // The count of anchored sessions, if the session began ahead of any pending commit.
+    pub(crate) anchored: Option<Arc<AtomicUsize>>,
+}
+
+impl Drop for SessionAccess {
+    fn drop(&mut self) {
+        // Before the guard is released, so that the count never includes a released session.
+        if let Some(anchored) = self.anchored.take() {
+            anchored.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+// Merge the entries of keys listed more than once in the sorted actuals. The first read of a key
+// is kept and the last write wins, since later reads only observe the session's own writes.
+//
+// Returns the keys written more than once.
+pub(crate) fn merge_duplicate_actuals(actuals: &mut Vec<(KeyPath, KeyReadWrite)>) -> Vec<KeyPath> {
+    if actuals.windows(2).all(|w| w[0].0 != w[1].0) {
+        return Vec::new();
+    }
+
+    let mut duplicate_writes = Vec::new();
+    let mut merged: Vec<(KeyPath, KeyReadWrite)> = Vec::with_capacity(actuals.len());
+    for (key, read_write) in actuals.drain(..) {
+        let prev = match merged.last_mut() {
+            Some((prev_key, prev)) if *prev_key == key => prev,
+            _ => {
+                merged.push((key, read_write));
+                continue;
+            }
+        };
+        if let KeyReadWrite::Write(value) | KeyReadWrite::ReadThenWrite(_, value) = read_write {
+            if prev.is_write() && duplicate_writes.last() != Some(&key) {
+                duplicate_writes.push(key);
+            }
+            prev.write(value);
+        }
+    }
+    *actuals = merged;
+    duplicate_writes
+}
+
+// Embed the prior values of the keys left in the witness, e.g. after excluding subtrees.
+pub(crate) fn embed_witness_values(
+    witness: &mut Witness,
+    prior_values: Vec<(KeyPath, Option<Value>)>,
+) {
+    let mut keys = witness
+        .operations
+        .reads
+        .iter()
+        .map(|r| r.key)
+        .chain(witness.operations.writes.iter().map(|w| w.key))
+        .collect::<HashSet<_>>();
+    witness.values = prior_values
+        .into_iter()
+        .filter(|(key, _)| keys.remove(key))
+        .map(|(key, value)| WitnessedValue { key, value })
+        .collect();
+}
+
+// Merge the hash-only writes, sorted and unique by key, into the sorted compact actuals. Fails if a
+// key is also written in the actuals.
+pub(crate) fn merge_hash_writes(
+    compact_actuals: Vec<(KeyPath, merkle::KeyReadWrite)>,
+    hash_writes: &[(KeyPath, ValueHash)],
+) -> anyhow::Result<Vec<(KeyPath, merkle::KeyReadWrite)>> {
+    if hash_writes.is_empty() {
+        return Ok(compact_actuals);
+    }
+
+    let mut merged = Vec::with_capacity(compact_actuals.len() + hash_writes.len());
+    let mut hash_writes = hash_writes.iter().peekable();
+    for (key, read_write) in compact_actuals {
+        while let Some((hash_key, value_hash)) = hash_writes.next_if(|(k, _)| *k < key) {
+            merged.push((*hash_key, merkle::KeyReadWrite::Write(Some(*value_hash))));
+        }
+        match hash_writes.next_if(|(k, _)| *k == key) {
+            None => merged.push((key, read_write)),
+            Some(_) if read_write.is_write() => anyhow::bail!(
+                "key {} is written both in the actuals and by hash",
+                key.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+            ),
+            Some((_, value_hash)) => {
+                merged.push((key, merkle::KeyReadWrite::ReadThenWrite(Some(*value_hash))))
+            }
+        }
+    }
+    merged.extend(
+        hash_writes.map(|(key, value_hash)| (*key, merkle::KeyReadWrite::Write(Some(*value_hash)))),
+    );
+    Ok(merged)
+}
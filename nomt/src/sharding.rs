@@ -0,0 +1,167 @@
+//! Sharding a key space across several NOMT instances.
+//!
+//! A [`ShardedNomt`] routes each key to one of `2^shard_bits` instances by the first `shard_bits`
+//! bits of its key path, and combines the roots of the instances into a single root with a small
+//! fixed-depth tree. Keys are proven against the combined root with a [`ShardedPathProof`]. See
+//! [`nomt_core::proof::combine_shard_roots`] for the layout of the tree of shard roots, so that
+//! light clients can verify proofs without this crate.
+//!
+//! The shards are independent databases. Commits are split across the shards and committed one
+//! after the other, so a failure part-way through a commit leaves the shards at different
+//! versions, and reads, proofs and commits made concurrently with a commit may observe only part
+//! of it. Users who need a consistent view must serialize access to the [`ShardedNomt`].
+
+use crate::{Error, HashAlgorithm, KeyReadWrite, Nomt, Options, Root, SessionParams, Value};
+use bitvec::prelude::*;
+use nomt_core::{
+    proof::{combine_shard_roots, shard_index, shard_siblings, ShardedPathProof, MAX_SHARD_BITS},
+    trie::{KeyPath, Node},
+};
+
+/// A set of NOMT instances, each holding the keys starting with a different prefix.
+pub struct ShardedNomt<T: HashAlgorithm> {
+    shards: Vec<Nomt<T>>,
+    shard_bits: usize,
+}
+
+impl<T: HashAlgorithm> ShardedNomt<T> {
+    /// Combine open instances into a sharded database. Shard `i` holds the keys whose first
+    /// `shard_bits` bits are `i`, where `2^shard_bits` is the number of shards.
+    ///
+    /// Fails if the number of shards is not a power of two of at most `2^MAX_SHARD_BITS`, or if a
+    /// shard holds a key belonging to another shard.
+    pub fn new(shards: Vec<Nomt<T>>) -> anyhow::Result<Self> {
+        if !shards.len().is_power_of_two() || shards.len() > 1 << MAX_SHARD_BITS {
+            anyhow::bail!(
+                "the number of shards must be a power of two of at most {}, got {}",
+                1 << MAX_SHARD_BITS,
+                shards.len()
+            );
+        }
+        let shard_bits = shards.len().trailing_zeros() as usize;
+
+        for (i, shard) in shards.iter().enumerate() {
+            let start = shard_start(i, shard_bits);
+            let end = (i + 1 < shards.len()).then(|| shard_start(i + 1, shard_bits));
+            let below = shard.iter_values([0; 32], Some(start)).next_stored();
+            let above = end.and_then(|end| shard.iter_values(end, None).next_stored());
+            if let Some(item) = below.or(above) {
+                let (key, _) = item?;
+                anyhow::bail!(
+                    "shard {} holds a key of shard {}",
+                    i,
+                    shard_index(&key, shard_bits)
+                );
+            }
+        }
+
+        Ok(ShardedNomt { shards, shard_bits })
+    }
+
+    /// Open an instance for each of the given options and combine them with [`ShardedNomt::new`].
+    pub fn open(options: impl IntoIterator<Item = Options>) -> anyhow::Result<Self> {
+        let shards = options
+            .into_iter()
+            .map(|o| Nomt::open(o).map_err(Error::into_anyhow))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Self::new(shards)
+    }
+
+    /// The number of bits of the key path used to select a shard.
+    pub fn shard_bits(&self) -> usize {
+        self.shard_bits
+    }
+
+    /// All shards, in shard order.
+    pub fn shards(&self) -> &[Nomt<T>] {
+        &self.shards
+    }
+
+    /// Get the index of the shard holding the key.
+    pub fn shard_for(&self, key_path: &KeyPath) -> usize {
+        shard_index(key_path, self.shard_bits)
+    }
+
+    /// The roots of all shards, in shard order.
+    pub fn shard_roots(&self) -> Vec<Root> {
+        self.shards.iter().map(|shard| shard.root()).collect()
+    }
+
+    /// The root combining the roots of all shards.
+    pub fn root(&self) -> Root {
+        Root::from(combine_shard_roots::<T>(&self.shard_nodes()))
+    }
+
+    fn shard_nodes(&self) -> Vec<Node> {
+        self.shard_roots()
+            .into_iter()
+            .map(|root| root.into_inner())
+            .collect()
+    }
+
+    /// Returns the value stored under the given key. Fails only if I/O fails.
+    pub fn read(&self, key_path: KeyPath) -> anyhow::Result<Option<Value>> {
+        self.shards[self.shard_for(&key_path)].read(key_path)
+    }
+
+    /// Prove the given key against the combined root of the shards.
+    pub fn prove(&self, key_path: KeyPath) -> anyhow::Result<ShardedPathProof> {
+        let shard = self.shard_for(&key_path);
+        let session = self.shards[shard].begin_session(SessionParams::default());
+        let proof = session.prove(key_path)?;
+
+        let mut roots = self.shard_nodes();
+        roots[shard] = session.prev_root().into_inner();
+        Ok(ShardedPathProof {
+            shard_siblings: shard_siblings::<T>(&roots, shard),
+            shard_root: roots[shard],
+            proof,
+        })
+    }
+
+    /// Commit the given actuals, split across the shards holding the keys. The actuals must be
+    /// sorted by key path. Returns the new combined root.
+    ///
+    /// The sessions of all affected shards are finished before any of them is committed, so
+    /// invalid actuals leave all shards unchanged.
+    pub fn commit(&self, actuals: Vec<(KeyPath, KeyReadWrite)>) -> anyhow::Result<Root> {
+        if !actuals.is_sorted_by(|(a, _), (b, _)| a < b) {
+            anyhow::bail!("actuals must be sorted by key path without duplicates");
+        }
+
+        let mut per_shard: Vec<(usize, Vec<(KeyPath, KeyReadWrite)>)> = Vec::new();
+        for (key_path, read_write) in actuals {
+            let shard = self.shard_for(&key_path);
+            match per_shard.last_mut() {
+                Some((last, shard_actuals)) if *last == shard => {
+                    shard_actuals.push((key_path, read_write))
+                }
+                _ => per_shard.push((shard, vec![(key_path, read_write)])),
+            }
+        }
+
+        let finished = per_shard
+            .into_iter()
+            .map(|(shard, shard_actuals)| {
+                let session = self.shards[shard].begin_session(SessionParams::default());
+                Ok((shard, session.finish(shard_actuals)?))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        for (shard, finished) in finished {
+            finished
+                .commit(&self.shards[shard])
+                .map_err(Error::into_anyhow)?;
+        }
+        Ok(self.root())
+    }
+}
+
+// The first key path of the given shard.
+fn shard_start(shard: usize, shard_bits: usize) -> KeyPath {
+    let mut key_path = [0; 32];
+    if shard_bits > 0 {
+        key_path.view_bits_mut::<Msb0>()[..shard_bits].store_be(shard);
+    }
+    key_path
+}
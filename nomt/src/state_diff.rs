@@ -0,0 +1,354 @@
+//! State diffs: the changes to the key-value pairs between two states of a database.
+//!
+//! A state diff lists every key which was added, changed or deleted between two sync sequence
+//! numbers, along with the new value of each key that was not deleted. A follower at the first
+//! state can apply it with [`Nomt::apply_diff`](crate::Nomt::apply_diff) to reach the second state in a single commit,
+//! without replaying the individual commits in between. Unlike a witness, a state diff carries no
+//! proofs. Instead, the root reached by applying it is checked against a root the follower
+//! obtained by other means.
+//!
+//! The file format is designed to be small:
+//!
+//! ```text
+//! header:  magic "NOMTDIFF" | version: u8 | from_seqn: u32 | to_seqn: u32
+//!          | from_root: [u8; 32] | to_root: [u8; 32]
+//! entry:   shared: u8 | key suffix: [u8; 32 - shared] | value: varint (0 = deleted, n + 1 =
+//!          value of n bytes) | value bytes
+//! trailer: 0xFF | crc32 of everything before the crc: u32
+//! ```
+//!
+//! All integers are little-endian and varints are LEB128. Entries are sorted by key, and each key
+//! is stored as the suffix following the bytes it shares with the previous key.
+
+use crate::{Root, Value};
+use nomt_core::trie::KeyPath;
+use std::io::{self, Read, Write};
+
+const MAGIC: [u8; 8] = *b"NOMTDIFF";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 8 + 1 + 4 + 4 + 32 + 32;
+const END_MARKER: u8 = 0xFF;
+
+/// The states a state diff leads from and to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateDiffHeader {
+    /// The sync sequence number of the state the diff applies to.
+    pub from_seqn: u32,
+    /// The sync sequence number of the state the diff leads to.
+    pub to_seqn: u32,
+    /// The root of the state the diff applies to.
+    pub from_root: Root,
+    /// The root of the state the diff leads to.
+    pub to_root: Root,
+}
+
+impl StateDiffHeader {
+    fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0; HEADER_SIZE];
+        buf[0..8].copy_from_slice(&MAGIC);
+        buf[8] = VERSION;
+        buf[9..13].copy_from_slice(&self.from_seqn.to_le_bytes());
+        buf[13..17].copy_from_slice(&self.to_seqn.to_le_bytes());
+        buf[17..49].copy_from_slice(self.from_root.as_ref());
+        buf[49..81].copy_from_slice(self.to_root.as_ref());
+        buf
+    }
+
+    fn decode(buf: &[u8; HEADER_SIZE]) -> io::Result<Self> {
+        if buf[0..8] != MAGIC {
+            return Err(invalid_data("state diff: bad magic"));
+        }
+        if buf[8] != VERSION {
+            return Err(invalid_data("state diff: unsupported version"));
+        }
+        Ok(StateDiffHeader {
+            from_seqn: u32::from_le_bytes(buf[9..13].try_into().unwrap()),
+            to_seqn: u32::from_le_bytes(buf[13..17].try_into().unwrap()),
+            from_root: Root::from(<[u8; 32]>::try_from(&buf[17..49]).unwrap()),
+            to_root: Root::from(<[u8; 32]>::try_from(&buf[49..81]).unwrap()),
+        })
+    }
+}
+
+/// Writes a state diff.
+///
+/// Changes must be written in strictly ascending key order and the writer must be concluded with
+/// [`StateDiffWriter::finish`].
+pub struct StateDiffWriter<W: Write> {
+    inner: W,
+    crc: crc32fast::Hasher,
+    prev_key: Option<KeyPath>,
+}
+
+impl<W: Write> StateDiffWriter<W> {
+    /// Begin a state diff by writing the header.
+    pub fn new(inner: W, header: &StateDiffHeader) -> io::Result<Self> {
+        let mut writer = StateDiffWriter {
+            inner,
+            crc: crc32fast::Hasher::new(),
+            prev_key: None,
+        };
+        writer.write_all(&header.encode())?;
+        Ok(writer)
+    }
+
+    /// Write the new value of a key. `None` indicates that the key was deleted.
+    ///
+    /// Fails if the key is not greater than the previously written key.
+    pub fn write(&mut self, key: KeyPath, value: Option<&[u8]>) -> io::Result<()> {
+        let shared = match self.prev_key {
+            Some(prev) if key <= prev => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "state diff: keys must be written in ascending order",
+                ))
+            }
+            Some(prev) => prev.iter().zip(&key).take_while(|(a, b)| a == b).count(),
+            None => 0,
+        };
+        self.prev_key = Some(key);
+
+        self.write_all(&[shared as u8])?;
+        self.write_all(&key[shared..])?;
+        match value {
+            None => self.write_varint(0),
+            Some(value) => {
+                self.write_varint(value.len() as u64 + 1)?;
+                self.write_all(value)
+            }
+        }
+    }
+
+    /// Conclude the state diff and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_all(&[END_MARKER])?;
+        let crc = self.crc.clone().finalize();
+        self.inner.write_all(&crc.to_le_bytes())?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_varint(&mut self, mut n: u64) -> io::Result<()> {
+        let mut buf = [0; 10];
+        let mut len = 0;
+        loop {
+            buf[len] = (n & 0x7F) as u8;
+            n >>= 7;
+            if n == 0 {
+                len += 1;
+                break;
+            }
+            buf[len] |= 0x80;
+            len += 1;
+        }
+        self.write_all(&buf[..len])
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.crc.update(buf);
+        self.inner.write_all(buf)
+    }
+}
+
+/// Reads a state diff.
+///
+/// This is an iterator over the changes in ascending key order. The checksum is verified once the
+/// last change has been read, so a change must not be acted upon before the iterator is exhausted
+/// without an error.
+pub struct StateDiffReader<R: Read> {
+    inner: R,
+    crc: crc32fast::Hasher,
+    header: StateDiffHeader,
+    prev_key: Option<KeyPath>,
+    done: bool,
+}
+
+impl<R: Read> StateDiffReader<R> {
+    /// Begin reading a state diff by reading the header.
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut buf = [0; HEADER_SIZE];
+        inner.read_exact(&mut buf)?;
+        let header = StateDiffHeader::decode(&buf)?;
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(&buf);
+        Ok(StateDiffReader {
+            inner,
+            crc,
+            header,
+            prev_key: None,
+            done: false,
+        })
+    }
+
+    /// The header of the state diff.
+    pub fn header(&self) -> &StateDiffHeader {
+        &self.header
+    }
+
+    fn read_change(&mut self) -> io::Result<Option<(KeyPath, Option<Value>)>> {
+        let [shared] = self.read_array::<1>()?;
+        if shared == END_MARKER {
+            let crc = u32::from_le_bytes({
+                let mut buf = [0; 4];
+                self.inner.read_exact(&mut buf)?;
+                buf
+            });
+            if crc != self.crc.clone().finalize() {
+                return Err(invalid_data("state diff: checksum mismatch"));
+            }
+            return Ok(None);
+        }
+
+        let shared = shared as usize;
+        let mut key = [0; 32];
+        match self.prev_key {
+            Some(ref prev) if shared < 32 => key[..shared].copy_from_slice(&prev[..shared]),
+            None if shared == 0 => {}
+            _ => return Err(invalid_data("state diff: bad key prefix")),
+        }
+        self.read_exact(&mut key[shared..])?;
+        if self.prev_key.is_some_and(|prev| key <= prev) {
+            return Err(invalid_data("state diff: keys out of order"));
+        }
+        self.prev_key = Some(key);
+
+        let value = match self.read_varint()? {
+            0 => None,
+            len => {
+                let len = usize::try_from(len - 1)
+                    .map_err(|_| invalid_data("state diff: value too large"))?;
+                let mut value = Vec::new();
+                (&mut self.inner).take(len as u64).read_to_end(&mut value)?;
+                if value.len() != len {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                self.crc.update(&value);
+                Some(value)
+            }
+        };
+        Ok(Some((key, value)))
+    }
+
+    fn read_varint(&mut self) -> io::Result<u64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let [byte] = self.read_array::<1>()?;
+            n |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(invalid_data("state diff: bad varint"))
+    }
+
+    fn read_array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(buf)?;
+        self.crc.update(buf);
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for StateDiffReader<R> {
+    type Item = io::Result<(KeyPath, Option<Value>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_change() {
+            Ok(Some(change)) => Some(Ok(change)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StateDiffHeader, StateDiffReader, StateDiffWriter};
+    use crate::Root;
+    use std::io;
+
+    fn header() -> StateDiffHeader {
+        StateDiffHeader {
+            from_seqn: 3,
+            to_seqn: 9,
+            from_root: Root::from([1; 32]),
+            to_root: Root::from([2; 32]),
+        }
+    }
+
+    fn changes() -> Vec<([u8; 32], Option<Vec<u8>>)> {
+        let mut shared_prefix = [7; 32];
+        shared_prefix[31] = 8;
+        vec![
+            ([0; 32], Some(vec![])),
+            ([7; 32], None),
+            (shared_prefix, Some(vec![42; 300])),
+            ([0xFF; 32], Some(vec![1, 2, 3])),
+        ]
+    }
+
+    fn encode() -> Vec<u8> {
+        let mut writer = StateDiffWriter::new(Vec::new(), &header()).unwrap();
+        for (key, value) in changes() {
+            writer.write(key, value.as_deref()).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let encoded = encode();
+        let reader = StateDiffReader::new(&encoded[..]).unwrap();
+        assert_eq!(reader.header(), &header());
+        let read = reader.collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(read, changes());
+
+        // The header, four entries and the trailer. The third key shares all but its last byte
+        // with the second, so only that byte is stored.
+        assert_eq!(encoded.len(), 81 + 34 + 34 + (1 + 1 + 2 + 300) + 37 + 5);
+    }
+
+    #[test]
+    fn out_of_order_write_rejected() {
+        let mut writer = StateDiffWriter::new(Vec::new(), &header()).unwrap();
+        writer.write([5; 32], None).unwrap();
+        assert!(writer.write([5; 32], None).is_err());
+        assert!(writer.write([4; 32], None).is_err());
+    }
+
+    #[test]
+    fn corruption_detected() {
+        let encoded = encode();
+        for i in [90, encoded.len() / 2, encoded.len() - 1] {
+            let mut corrupted = encoded.clone();
+            corrupted[i] ^= 0x01;
+            let result = StateDiffReader::new(&corrupted[..])
+                .and_then(|reader| reader.collect::<io::Result<Vec<_>>>());
+            assert!(result.is_err());
+        }
+
+        let truncated = &encoded[..encoded.len() - 3];
+        let result = StateDiffReader::new(truncated)
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>();
+        assert!(result.is_err());
+    }
+}
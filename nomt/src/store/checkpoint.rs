@@ -0,0 +1,306 @@
+//! Checkpointed syncs.
+//!
+//! A sync carrying more value changes than the configured checkpoint interval is split into
+//! several beatree syncs, each concluded by its own meta write. This bounds the amount of beatree
+//! work between metas and lets a crash resume from the last checkpoint instead of redoing the
+//! whole flush.
+//!
+//! The intermediate metas keep the sequence number of the last sync and count the chunks applied
+//! in their `checkpoint` field. The merkle side is not split: the bitbox WAL is written once, up
+//! front, under the sequence number of the final meta, which is one past the last sync. Only the
+//! final meta makes it current, so the new root becomes visible in a single step, and the sequence
+//! number advances by one per commit.
+//!
+//! Before the first intermediate meta is written, all value changes are persisted to a journal
+//! in the database directory. On open, if the meta carries the journal's base sequence number,
+//! the chunks past its checkpoint are replayed from the journal and the final meta is written,
+//! after which the bitbox WAL is recovered as usual.
+//!
+//! This trades total commit latency for resumability: every value change is written twice, once
+//! to the journal and once to the beatree, and each chunk adds a meta write and its fsync. The
+//! journal is written before any chunk, so its cost grows with the commit. The journal's share is
+//! reported by [`crate::WriteStats::journal_bytes`] and [`crate::SyncTimings::journal`].
+
+use super::meta::Meta;
+use crate::{beatree, io::PagePool};
+use std::{
+    fs::File,
+    io::{Read as _, Write as _},
+    path::Path,
+};
+
+const MAGIC: [u8; 4] = *b"NCKP";
+const VERSION: u32 = 2;
+const FILE_NAME: &str = "checkpoint";
+const TMP_FILE_NAME: &str = "checkpoint.tmp";
+const HEADER_SIZE: usize = 44;
+
+const TAG_DELETE: u8 = 0;
+const TAG_INSERT: u8 = 1;
+const TAG_INSERT_OVERFLOW: u8 = 2;
+
+/// All value changes of a checkpointed sync.
+pub struct Journal {
+    /// The sequence number of the last sync before this one.
+    pub base_seqn: u32,
+    /// The rollback live range to record in the metas of this sync.
+    pub rollback_start_live: u64,
+    pub rollback_end_live: u64,
+    /// The number of value changes applied between two metas.
+    pub chunk_size: usize,
+    pub changes: Vec<(beatree::Key, beatree::ValueChange)>,
+}
+
+impl Journal {
+    /// The number of chunks the changes are applied in, each but the last concluded by an
+    /// intermediate meta.
+    pub fn num_chunks(&self) -> u32 {
+        self.changes.len().div_ceil(self.chunk_size).max(1) as u32
+    }
+
+    /// Durably write the journal to the database directory, replacing any previous one.
+    ///
+    /// Returns the number of bytes written.
+    pub fn write(&self, db_dir: &Path) -> std::io::Result<usize> {
+        let tmp_path = db_dir.join(TMP_FILE_NAME);
+        let mut file = File::create(&tmp_path)?;
+        let buf = self.encode();
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        drop(file);
+
+        std::fs::rename(&tmp_path, db_dir.join(FILE_NAME))?;
+        crate::sys::sync_dir_path(db_dir)?;
+        Ok(buf.len())
+    }
+
+    /// Read the journal from the database directory, if there is one.
+    pub fn read(db_dir: &Path) -> anyhow::Result<Option<Journal>> {
+        let mut file = match File::open(db_dir.join(FILE_NAME)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        Journal::decode(&buf).map(Some)
+    }
+
+    /// Remove the journal from the database directory.
+    pub fn remove(db_dir: &Path) -> std::io::Result<()> {
+        match std::fs::remove_file(db_dir.join(FILE_NAME)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        crate::sys::sync_dir_path(db_dir)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_SIZE + self.changes.len() * 48);
+        buf.extend_from_slice(&MAGIC);
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        buf.extend_from_slice(&self.base_seqn.to_le_bytes());
+        buf.extend_from_slice(&self.rollback_start_live.to_le_bytes());
+        buf.extend_from_slice(&self.rollback_end_live.to_le_bytes());
+        buf.extend_from_slice(&(self.chunk_size as u64).to_le_bytes());
+        buf.extend_from_slice(&(self.changes.len() as u64).to_le_bytes());
+
+        for (key, change) in &self.changes {
+            buf.extend_from_slice(key);
+            let value = match change {
+                beatree::ValueChange::Delete => {
+                    buf.push(TAG_DELETE);
+                    continue;
+                }
+                beatree::ValueChange::Insert(value) => {
+                    buf.push(TAG_INSERT);
+                    value
+                }
+                beatree::ValueChange::InsertOverflow(value, value_hash) => {
+                    buf.push(TAG_INSERT_OVERFLOW);
+                    buf.extend_from_slice(value_hash);
+                    value
+                }
+            };
+            buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            buf.extend_from_slice(value);
+        }
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> anyhow::Result<Journal> {
+        let mut reader = Reader { buf };
+        if reader.take(4)? != MAGIC {
+            anyhow::bail!("checkpoint journal: invalid magic");
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            anyhow::bail!("checkpoint journal: unsupported version {}", version);
+        }
+        let base_seqn = reader.u32()?;
+        let rollback_start_live = reader.u64()?;
+        let rollback_end_live = reader.u64()?;
+        let chunk_size = reader.u64()? as usize;
+        let len = reader.u64()? as usize;
+        if chunk_size == 0 {
+            anyhow::bail!("checkpoint journal: invalid header");
+        }
+
+        let mut changes = Vec::with_capacity(len);
+        for _ in 0..len {
+            let key: beatree::Key = reader.take(32)?.try_into().unwrap();
+            let change = match reader.take(1)?[0] {
+                TAG_DELETE => beatree::ValueChange::Delete,
+                TAG_INSERT => {
+                    let len = reader.u32()? as usize;
+                    beatree::ValueChange::Insert(reader.take(len)?.to_vec())
+                }
+                TAG_INSERT_OVERFLOW => {
+                    let value_hash = reader.take(32)?.try_into().unwrap();
+                    let len = reader.u32()? as usize;
+                    beatree::ValueChange::InsertOverflow(reader.take(len)?.to_vec(), value_hash)
+                }
+                tag => anyhow::bail!("checkpoint journal: invalid tag {}", tag),
+            };
+            changes.push((key, change));
+        }
+
+        Ok(Journal {
+            base_seqn,
+            rollback_start_live,
+            rollback_end_live,
+            chunk_size,
+            changes,
+        })
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        if self.buf.len() < n {
+            anyhow::bail!("checkpoint journal: unexpected end of file");
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+/// Apply a chunk of value changes to the beatree and conclude it with a meta write.
+///
+/// Returns the meta written and the number of beatree pages written.
+pub fn sync_chunk(
+    page_pool: &PagePool,
+    meta_fd: &File,
+    beatree: &beatree::Tree,
+    chunk: Vec<(beatree::Key, beatree::ValueChange)>,
+    make_meta: impl FnOnce(&beatree::SyncData) -> Meta,
+) -> anyhow::Result<(Meta, usize)> {
+    let mut beatree_sync = beatree.sync();
+    beatree_sync.begin_sync(chunk);
+    let sync_data = beatree_sync.wait_pre_meta()?;
+    let meta = make_meta(&sync_data);
+    Meta::write(page_pool, meta_fd, &meta)?;
+    beatree_sync.post_meta();
+    Ok((meta, sync_data.pages_written))
+}
+
+/// Finish a checkpointed sync interrupted by a crash, if there is one.
+///
+/// This must be called after the beatree is opened and before the bitbox is, since the bitbox
+/// WAL can only be recovered once the final meta is written. Returns the current meta.
+pub fn recover(
+    db_dir: &Path,
+    page_pool: &PagePool,
+    meta_fd: &File,
+    beatree: &beatree::Tree,
+    mut meta: Meta,
+) -> anyhow::Result<Meta> {
+    let Some(journal) = Journal::read(db_dir)? else {
+        return Ok(meta);
+    };
+
+    if meta.sync_seqn != journal.base_seqn {
+        // Either the sync has concluded, or this journal is not related to the current state.
+        Journal::remove(db_dir)?;
+        return Ok(meta);
+    }
+
+    let num_chunks = journal.num_chunks();
+    if meta.checkpoint >= num_chunks {
+        return Err(crate::error::corruption(format!(
+            "checkpoint {} of a sync of {} chunks",
+            meta.checkpoint, num_chunks
+        )));
+    }
+    let done = meta.checkpoint as usize;
+    let mut changes = journal.changes.into_iter().skip(done * journal.chunk_size);
+    for checkpoint in meta.checkpoint + 1..=num_chunks {
+        let chunk = changes.by_ref().take(journal.chunk_size).collect();
+        // The last chunk concludes the sync.
+        let (sync_seqn, checkpoint) = if checkpoint == num_chunks {
+            (journal.base_seqn + 1, 0)
+        } else {
+            (journal.base_seqn, checkpoint)
+        };
+        let prev = meta.clone();
+        (meta, _) = sync_chunk(page_pool, meta_fd, beatree, chunk, |sync_data| Meta {
+            ln_freelist_pn: sync_data.ln_freelist_pn,
+            ln_bump: sync_data.ln_bump,
+            bbn_freelist_pn: sync_data.bbn_freelist_pn,
+            bbn_bump: sync_data.bbn_bump,
+            sync_seqn,
+            rollback_start_live: journal.rollback_start_live,
+            rollback_end_live: journal.rollback_end_live,
+            checkpoint,
+            ..prev
+        })?;
+    }
+
+    Journal::remove(db_dir)?;
+    Ok(meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Journal;
+    use crate::beatree::ValueChange;
+
+    #[test]
+    fn journal_roundtrip() {
+        let journal = Journal {
+            base_seqn: 3,
+            rollback_start_live: 1,
+            rollback_end_live: 9,
+            chunk_size: 2,
+            changes: vec![
+                ([1; 32], ValueChange::Delete),
+                ([2; 32], ValueChange::Insert(vec![1, 2, 3])),
+                ([3; 32], ValueChange::InsertOverflow(vec![4; 5000], [5; 32])),
+            ],
+        };
+
+        let decoded = Journal::decode(&journal.encode()).unwrap();
+        assert_eq!(decoded.base_seqn, 3);
+        assert_eq!(decoded.rollback_start_live, 1);
+        assert_eq!(decoded.rollback_end_live, 9);
+        assert_eq!(decoded.chunk_size, 2);
+        assert_eq!(decoded.changes, journal.changes);
+
+        let encoded = journal.encode();
+        assert!(Journal::decode(&encoded[..encoded.len() - 1]).is_err());
+    }
+}
@@ -20,7 +20,7 @@ impl Flock {
             .create(true)
             .open(lock_path)?;
 
-        match crate::sys::unix::try_lock_exclusive(&lock_fd) {
+        match crate::sys::try_lock_exclusive(&lock_fd) {
             Ok(_) => Ok(Self { lock_fd }),
             Err(e) => {
                 anyhow::bail!("Failed to lock directory: {e}");
@@ -31,7 +31,7 @@ impl Flock {
 
 impl Drop for Flock {
     fn drop(&mut self) {
-        if let Err(e) = crate::sys::unix::unlock(&self.lock_fd) {
+        if let Err(e) = crate::sys::unlock(&self.lock_fd) {
             eprintln!("Failed to unlock directory lock: {e}");
         }
     }
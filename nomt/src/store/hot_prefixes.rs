@@ -0,0 +1,143 @@
+//! Write counts per key prefix over a sliding window of commits.
+
+use super::stats::logical_change_size;
+use crate::beatree::{Key, ValueChange};
+use std::collections::{HashMap, VecDeque};
+
+/// The writes to keys sharing a prefix within the tracked window of commits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotPrefix {
+    /// The common prefix of the written keys.
+    pub prefix: Vec<u8>,
+    /// The number of values inserted, updated or deleted.
+    pub writes: u64,
+    /// Key and value bytes of the changed values. A deletion counts only its key.
+    pub logical_bytes: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub(super) struct Counts {
+    writes: u64,
+    logical_bytes: u64,
+}
+
+/// The counts of a single commit, keyed by prefix. The prefix is padded with zeros.
+pub(super) type Tally = HashMap<Key, Counts>;
+
+pub(super) struct PrefixWriteTracker {
+    prefix_len: usize,
+    window: usize,
+    commits: VecDeque<Tally>,
+    totals: HashMap<Key, Counts>,
+}
+
+impl PrefixWriteTracker {
+    pub(super) fn new(prefix_len: usize, window: usize) -> Self {
+        PrefixWriteTracker {
+            prefix_len,
+            window,
+            commits: VecDeque::with_capacity(window),
+            totals: HashMap::new(),
+        }
+    }
+
+    /// Count the changes of a commit by prefix.
+    pub(super) fn tally(&self, changes: &[(Key, ValueChange)]) -> Tally {
+        let mut tally = Tally::new();
+        for (key, change) in changes {
+            let mut prefix = [0; 32];
+            prefix[..self.prefix_len].copy_from_slice(&key[..self.prefix_len]);
+            let counts = tally.entry(prefix).or_default();
+            counts.writes += 1;
+            counts.logical_bytes += logical_change_size(change.as_option());
+        }
+        tally
+    }
+
+    /// Add the tally of a commit to the window, evicting the oldest commit if the window is full.
+    pub(super) fn push(&mut self, tally: Tally) {
+        if self.commits.len() == self.window {
+            // UNWRAP: the window is never empty.
+            for (prefix, counts) in self.commits.pop_front().unwrap() {
+                // UNWRAP: every prefix in the window is in the totals.
+                let total = self.totals.get_mut(&prefix).unwrap();
+                total.writes -= counts.writes;
+                total.logical_bytes -= counts.logical_bytes;
+                if total.writes == 0 {
+                    self.totals.remove(&prefix);
+                }
+            }
+        }
+        for (prefix, counts) in &tally {
+            let total = self.totals.entry(*prefix).or_default();
+            total.writes += counts.writes;
+            total.logical_bytes += counts.logical_bytes;
+        }
+        self.commits.push_back(tally);
+    }
+
+    /// The `n` prefixes with the most writes in the window, most written first. Ties are broken
+    /// by the bytes written, then by the prefix.
+    pub(super) fn top(&self, n: usize) -> Vec<HotPrefix> {
+        let mut hot = self
+            .totals
+            .iter()
+            .map(|(prefix, counts)| HotPrefix {
+                prefix: prefix[..self.prefix_len].to_vec(),
+                writes: counts.writes,
+                logical_bytes: counts.logical_bytes,
+            })
+            .collect::<Vec<_>>();
+        hot.sort_unstable_by(|a, b| {
+            b.writes
+                .cmp(&a.writes)
+                .then(b.logical_bytes.cmp(&a.logical_bytes))
+                .then_with(|| a.prefix.cmp(&b.prefix))
+        });
+        hot.truncate(n);
+        hot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrefixWriteTracker;
+    use crate::beatree::ValueChange;
+
+    fn key(first: u8, second: u8) -> [u8; 32] {
+        let mut key = [0xFF; 32];
+        key[0] = first;
+        key[1] = second;
+        key
+    }
+
+    #[test]
+    fn sliding_window() {
+        let mut tracker = PrefixWriteTracker::new(1, 2);
+        let insert = |first, second| (key(first, second), ValueChange::Insert(vec![0; 8]));
+
+        let tally = tracker.tally(&[insert(1, 0), insert(1, 1), insert(2, 0)]);
+        tracker.push(tally);
+        let tally = tracker.tally(&[insert(2, 1), (key(3, 0), ValueChange::Delete)]);
+        tracker.push(tally);
+
+        let top = tracker.top(10);
+        assert_eq!(top.len(), 3);
+        assert_eq!((top[0].prefix.as_slice(), top[0].writes), (&[1][..], 2));
+        assert_eq!(top[0].logical_bytes, 2 * 40);
+        assert_eq!((top[1].prefix.as_slice(), top[1].writes), (&[2][..], 2));
+        assert_eq!(
+            (top[2].prefix.as_slice(), top[2].logical_bytes),
+            (&[3][..], 32)
+        );
+        assert_eq!(tracker.top(1).len(), 1);
+
+        // The first commit leaves the window.
+        let tally = tracker.tally(&[insert(3, 1)]);
+        tracker.push(tally);
+        let top = tracker.top(10);
+        assert_eq!(top.len(), 2);
+        assert_eq!((top[0].prefix.as_slice(), top[0].writes), (&[3][..], 2));
+        assert_eq!((top[1].prefix.as_slice(), top[1].writes), (&[2][..], 1));
+    }
+}
@@ -0,0 +1,115 @@
+//! Placement of database files outside of the database directory.
+//!
+//! See [`crate::StorageLayout`]. A file placed elsewhere is created under a unique name, so that
+//! several databases, or an abandoned creation, can share a directory, and is linked into the
+//! database directory under its usual name. Everything else reaches the file through the link.
+
+use crate::StorageLayout;
+use std::path::{Path, PathBuf};
+
+/// The paths of the files of a new database.
+pub(super) struct FilePaths {
+    pub wal: PathBuf,
+    pub ht: PathBuf,
+    pub ln: PathBuf,
+    pub bbn: PathBuf,
+}
+
+// The files which may be placed elsewhere, along with their configured directories.
+fn placements(layout: &StorageLayout) -> [(&'static str, Option<&Path>); 4] {
+    [
+        ("wal", layout.wal.as_deref()),
+        ("ht", layout.hash_table.as_deref()),
+        ("ln", layout.beatree.as_deref()),
+        ("bbn", layout.beatree.as_deref()),
+    ]
+}
+
+impl FilePaths {
+    /// Choose the paths of the files of a new database being created in `db_dir`.
+    pub fn new(db_dir: &Path, layout: &StorageLayout) -> anyhow::Result<Self> {
+        let suffix = rand::random::<u64>();
+        let mut paths = placements(layout).into_iter().map(|(name, dir)| match dir {
+            None => Ok(db_dir.join(name)),
+            Some(dir) => {
+                if !dir.is_dir() {
+                    anyhow::bail!("{} directory {} does not exist", name, dir.display());
+                }
+                Ok(std::path::absolute(dir)?.join(format!("{name}-{suffix:016x}")))
+            }
+        });
+        // UNWRAP: there are exactly four placements.
+        Ok(FilePaths {
+            wal: paths.next().unwrap()?,
+            ht: paths.next().unwrap()?,
+            ln: paths.next().unwrap()?,
+            bbn: paths.next().unwrap()?,
+        })
+    }
+
+    fn files(&self) -> [(&'static str, &Path); 4] {
+        [
+            ("wal", &self.wal),
+            ("ht", &self.ht),
+            ("ln", &self.ln),
+            ("bbn", &self.bbn),
+        ]
+    }
+
+    /// Link the files placed elsewhere into `db_dir`, once they are created and synced.
+    ///
+    /// The directories holding them are synced, but not `db_dir`.
+    pub fn link(&self, db_dir: &Path) -> anyhow::Result<()> {
+        for (name, path) in self.files() {
+            if path.parent() == Some(db_dir) {
+                continue;
+            }
+            // UNWRAP: paths placed elsewhere are absolute file paths.
+            crate::sys::sync_dir_path(path.parent().unwrap())?;
+            crate::sys::symlink(path, &db_dir.join(name))?;
+        }
+        Ok(())
+    }
+
+    /// Remove the files placed elsewhere, after a failed creation.
+    pub fn remove(&self, db_dir: &Path) {
+        for (_, path) in self.files() {
+            if path.parent() != Some(db_dir) {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// Remove the files linked into the directory of an abandoned creation.
+pub(super) fn remove_linked(db_dir: &Path) -> anyhow::Result<()> {
+    for (name, _) in placements(&StorageLayout::default()) {
+        if let Ok(target) = std::fs::read_link(db_dir.join(name)) {
+            match std::fs::remove_file(target) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that the files of an existing database are in the directories given in the layout.
+pub(super) fn check(db_dir: &Path, layout: &StorageLayout) -> anyhow::Result<()> {
+    for (name, dir) in placements(layout) {
+        let Some(dir) = dir else {
+            continue;
+        };
+        let file = std::fs::canonicalize(db_dir.join(name))?;
+        let expected = std::fs::canonicalize(dir)?;
+        if file.parent() != Some(expected.as_path()) {
+            anyhow::bail!(
+                "{} is at {}, not in the configured directory {}",
+                name,
+                file.display(),
+                dir.display()
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+//! Database identity and lineage.
+//!
+//! Every database is stamped with a random identifier when it is created. Clones record the
+//! identifier and sync sequence number of the database they were cloned from, and rollbacks record
+//! the sync sequence number they started from, so that a directory can be traced back to where
+//! it came from.
+
+use std::fmt;
+
+/// The unique identifier of a database: a random (version 4) UUID.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct DbId(pub [u8; 16]);
+
+impl DbId {
+    /// Generate a new random identifier.
+    pub(crate) fn generate() -> Self {
+        let mut bytes: [u8; 16] = rand::random();
+        // Set the version (4) and the variant (RFC 4122).
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        DbId(bytes)
+    }
+
+    /// Whether this is the nil identifier, which is never assigned to a database.
+    pub fn is_nil(&self) -> bool {
+        self.0 == [0; 16]
+    }
+}
+
+impl fmt::Display for DbId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                f.write_str("-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DbId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DbId({})", self)
+    }
+}
+
+/// The database a database was cloned from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineageOrigin {
+    /// The identifier of the database cloned from.
+    pub parent: DbId,
+    /// The sync sequence number of the parent at the time of cloning.
+    pub parent_sync_seqn: u32,
+}
+
+/// The most recent rollback of a database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineageRollback {
+    /// The sync sequence number before the rollback.
+    pub from_sync_seqn: u32,
+    /// The number of commits rolled back.
+    pub commits: u32,
+}
+
+/// The identity of a database and where it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lineage {
+    /// The identifier of the database.
+    pub id: DbId,
+    /// The database this one was cloned from. `None` if it was created empty.
+    pub origin: Option<LineageOrigin>,
+    /// The most recent rollback. `None` if the database was never rolled back.
+    pub last_rollback: Option<LineageRollback>,
+}
+
+impl Lineage {
+    /// The lineage of a newly created database.
+    pub(crate) fn new() -> Self {
+        Lineage {
+            id: DbId::generate(),
+            origin: None,
+            last_rollback: None,
+        }
+    }
+
+    /// The encoded size of a lineage.
+    pub(crate) const ENCODED_SIZE: usize = 44;
+
+    pub(crate) fn encode_to(&self, buf: &mut [u8]) {
+        let origin = self.origin.unwrap_or(LineageOrigin {
+            parent: DbId::default(),
+            parent_sync_seqn: 0,
+        });
+        let last_rollback = self.last_rollback.unwrap_or(LineageRollback {
+            from_sync_seqn: 0,
+            commits: 0,
+        });
+        buf[0..16].copy_from_slice(&self.id.0);
+        buf[16..32].copy_from_slice(&origin.parent.0);
+        buf[32..36].copy_from_slice(&origin.parent_sync_seqn.to_le_bytes());
+        buf[36..40].copy_from_slice(&last_rollback.from_sync_seqn.to_le_bytes());
+        buf[40..44].copy_from_slice(&last_rollback.commits.to_le_bytes());
+    }
+
+    pub(crate) fn decode(buf: &[u8]) -> Self {
+        let id = DbId(buf[0..16].try_into().unwrap());
+        let parent = DbId(buf[16..32].try_into().unwrap());
+        let parent_sync_seqn = u32::from_le_bytes(buf[32..36].try_into().unwrap());
+        let from_sync_seqn = u32::from_le_bytes(buf[36..40].try_into().unwrap());
+        let commits = u32::from_le_bytes(buf[40..44].try_into().unwrap());
+        Lineage {
+            id,
+            origin: (!parent.is_nil()).then_some(LineageOrigin {
+                parent,
+                parent_sync_seqn,
+            }),
+            last_rollback: (commits != 0).then_some(LineageRollback {
+                from_sync_seqn,
+                commits,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DbId;
+
+    #[test]
+    fn db_id_is_v4_uuid() {
+        let id = DbId::generate();
+        let s = id.to_string();
+        assert_eq!(s.len(), 36);
+        assert_eq!(&s[14..15], "4");
+        assert!(matches!(&s[19..20], "8" | "9" | "a" | "b"));
+        assert_eq!(
+            DbId([0xab; 16]).to_string(),
+            "abababab-abab-abab-abab-abababababab"
+        );
+    }
+}
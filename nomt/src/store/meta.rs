@@ -1,13 +1,20 @@
+use crate::sys::FileExt as _;
 /// The utility functions for handling the metadata file.
 use anyhow::Result;
 use std::fs::File;
-use std::os::unix::fs::FileExt as _;
 
+use super::lineage::{DbId, Lineage};
 use crate::io::{self, PagePool};
 
 pub(crate) const MAGIC: [u8; 4] = *b"NOMT";
-pub(crate) const VERSION: u32 = 1;
-pub(crate) const META_SIZE: usize = 64;
+/// Version 2 added the lineage. Version 3 added compressed overflow values. Version 4 added the
+/// checkpoint of an unfinished checkpointed sync.
+pub(crate) const VERSION: u32 = 4;
+/// The first version which may hold compressed overflow values.
+pub(crate) const COMPRESSION_VERSION: u32 = 3;
+/// The first version which may record the checkpoints of a checkpointed sync.
+pub(crate) const CHECKPOINT_VERSION: u32 = 4;
+pub(crate) const META_SIZE: usize = 68 + Lineage::ENCODED_SIZE;
 
 /// This data structure describes the state of the btree.
 #[derive(Clone, Debug)]
@@ -42,6 +49,11 @@ pub struct Meta {
     pub rollback_start_live: u64,
     /// The last live record ID in the rollback seglog.
     pub rollback_end_live: u64,
+    /// The identity and history of the database. Metas of version 1 carry the nil identifier.
+    pub lineage: Lineage,
+    /// The number of chunks of the sync following `sync_seqn` already applied to the beatree, if
+    /// it is checkpointed and unfinished. 0 otherwise, and in metas before version 4.
+    pub checkpoint: u32,
 }
 
 impl Meta {
@@ -60,6 +72,8 @@ impl Meta {
             bitbox_seed,
             rollback_start_live: 0,
             rollback_end_live: 0,
+            lineage: Lineage::new(),
+            checkpoint: 0,
         }
     }
 
@@ -76,6 +90,9 @@ impl Meta {
         buf[32..48].copy_from_slice(&self.bitbox_seed);
         buf[48..56].copy_from_slice(&self.rollback_start_live.to_le_bytes());
         buf[56..64].copy_from_slice(&self.rollback_end_live.to_le_bytes());
+        self.lineage
+            .encode_to(&mut buf[64..64 + Lineage::ENCODED_SIZE]);
+        buf[64 + Lineage::ENCODED_SIZE..META_SIZE].copy_from_slice(&self.checkpoint.to_le_bytes());
     }
 
     pub fn decode(buf: &[u8]) -> Self {
@@ -91,6 +108,25 @@ impl Meta {
         let bitbox_seed = buf[32..48].try_into().unwrap();
         let rollback_start_live = u64::from_le_bytes(buf[48..56].try_into().unwrap());
         let rollback_end_live = u64::from_le_bytes(buf[56..64].try_into().unwrap());
+        // Version 1 metas are followed by whatever happened to be in the page.
+        let lineage = if version >= 2 {
+            Lineage::decode(&buf[64..64 + Lineage::ENCODED_SIZE])
+        } else {
+            Lineage {
+                id: DbId::default(),
+                origin: None,
+                last_rollback: None,
+            }
+        };
+        let checkpoint = if version >= CHECKPOINT_VERSION {
+            u32::from_le_bytes(
+                buf[64 + Lineage::ENCODED_SIZE..META_SIZE]
+                    .try_into()
+                    .unwrap(),
+            )
+        } else {
+            0
+        };
         Self {
             magic,
             version,
@@ -103,6 +139,8 @@ impl Meta {
             bitbox_seed,
             rollback_start_live,
             rollback_end_live,
+            lineage,
+            checkpoint,
         }
     }
 
@@ -133,7 +171,7 @@ impl Meta {
             Ok(())
         } else {
             // Collect all the errors and return them in a single anyhow error.
-            Err(anyhow::anyhow!(errors.join("\n")))
+            Err(crate::error::corruption(errors.join("\n")))
         }
     }
 
@@ -155,6 +193,7 @@ impl Meta {
 #[cfg(test)]
 mod tests {
     use super::{Meta, META_SIZE};
+    use crate::store::lineage::{DbId, Lineage, LineageOrigin, LineageRollback};
     use quickcheck::quickcheck;
 
     impl quickcheck::Arbitrary for Meta {
@@ -171,6 +210,18 @@ mod tests {
                 bitbox_seed: u128::arbitrary(g).to_le_bytes(),
                 rollback_start_live: u64::arbitrary(g),
                 rollback_end_live: u64::arbitrary(g),
+                lineage: Lineage {
+                    id: DbId(u128::arbitrary(g).to_le_bytes()),
+                    origin: bool::arbitrary(g).then(|| LineageOrigin {
+                        parent: DbId((u128::arbitrary(g) | 1).to_le_bytes()),
+                        parent_sync_seqn: u32::arbitrary(g),
+                    }),
+                    last_rollback: bool::arbitrary(g).then(|| LineageRollback {
+                        from_sync_seqn: u32::arbitrary(g),
+                        commits: u32::arbitrary(g).max(1),
+                    }),
+                },
+                checkpoint: u32::arbitrary(g),
             }
         }
     }
@@ -191,7 +242,9 @@ mod tests {
             meta.bitbox_num_pages == decoded.bitbox_num_pages &&
             meta.bitbox_seed == decoded.bitbox_seed &&
             meta.rollback_start_live == decoded.rollback_start_live &&
-            meta.rollback_end_live == decoded.rollback_end_live
+            meta.rollback_end_live == decoded.rollback_end_live &&
+            (meta.version < 2 || meta.lineage == decoded.lineage) &&
+            (meta.version < 4 || meta.checkpoint == decoded.checkpoint)
         }
     }
 }
@@ -4,32 +4,63 @@
 //! b-tree key-value storage (beatree).
 
 use crate::{
+    backup::{self, BackupInfo},
     beatree, bitbox,
+    chaos::Chaos,
+    integrity::IntegrityReport,
     io::{self, page_pool::FatPage, IoPool, PagePool},
+    metrics::{Metric, Metrics},
     page_cache::{Page, PageCache},
     page_diff::PageDiff,
     rollback::Rollback,
-    ValueHasher,
+    seglog::ArchiveRetention,
+    sys::AsRawFd as _,
+    ReadBackend, ValueHasher,
 };
+use anyhow::Context as _;
 use flock::Flock;
 use meta::Meta;
-use nomt_core::{page_id::PageId, trie::KeyPath};
-use parking_lot::Mutex;
+use nomt_core::{
+    page_id::PageId,
+    trie::{KeyPath, ValueHash},
+};
+use parking_lot::{Mutex, RwLock};
 use std::{
     fs::{File, OpenOptions},
-    sync::{atomic::AtomicBool, Arc},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
 };
 
 #[cfg(target_os = "linux")]
 use std::os::unix::fs::OpenOptionsExt as _;
+#[cfg(windows)]
+use std::os::windows::fs::OpenOptionsExt as _;
 
 pub use self::page_loader::{PageLoad, PageLoader};
 pub use bitbox::{BucketIndex, HashTableUtilization, SharedMaybeBucketIndex};
+pub use hot_prefixes::HotPrefix;
+pub use lineage::{DbId, Lineage, LineageOrigin, LineageRollback};
+pub use options_journal::OptionsJournalEntry;
+pub use partial::PartialDatabase;
+pub use stats::{SpaceStats, WriteStats};
+pub use sync::{OutOfSpace, SyncPhase, SyncTimings};
+pub use value_iter::{StoredValue, ValueIter};
 
+mod checkpoint;
 mod flock;
+mod hot_prefixes;
+mod layout;
+mod lineage;
 mod meta;
+mod options_journal;
 mod page_loader;
+mod partial;
+mod stats;
 mod sync;
+mod value_iter;
 
 /// This is a lightweight handle and can be cloned cheaply.
 #[derive(Clone)]
@@ -40,12 +71,27 @@ pub struct Store {
 
 struct Shared {
     values: beatree::Tree,
-    pages: bitbox::DB,
+    /// Replaced once a resize of the hash table is committed.
+    pages: RwLock<bitbox::DB>,
+    /// Incremented under the sync lock whenever `pages` is replaced.
+    ht_generation: AtomicU64,
     rollback: Option<Rollback>,
     io_pool: IoPool,
     meta_fd: File,
-    flock: Option<flock::Flock>,
+    /// Released while the store is frozen. Never taken by a read-only store.
+    flock: Mutex<Option<flock::Flock>>,
+    /// Whether the store was opened read-only, refusing all writes.
+    read_only: bool,
+    /// Taken for reading by everything that writes to the files, and for writing by a freeze.
+    write_gate: Arc<RwLock<()>>,
     poisoned: AtomicBool,
+    db_dir_path: PathBuf,
+    /// The stats of the last commit, and the totals of all commits since opening.
+    write_stats: Mutex<(Option<WriteStats>, WriteStats)>,
+    /// The writes per key prefix over the recent commits, if enabled.
+    prefix_writes: Option<Mutex<hot_prefixes::PrefixWriteTracker>>,
+    /// The logical bytes and prefix tally of the commit whose sync ran out of disk space.
+    stalled_commit: Mutex<Option<(u64, Option<hot_prefixes::Tally>)>>,
 
     // Retained for the lifetime of the store.
     _db_dir_fd: Arc<File>,
@@ -53,83 +99,167 @@ struct Shared {
 
 impl Store {
     /// Open the store with the provided `Options`.
-    pub fn open(o: &crate::Options, page_pool: PagePool) -> anyhow::Result<Self> {
+    ///
+    /// When opened for [`crate::Nomt::open_read_only`], the store is opened alongside the process
+    /// which may hold it: the directory is not locked, the files are opened read-only and nothing is
+    /// recovered or recorded on opening. The store is then as of its last sync.
+    pub fn open(
+        o: &crate::Options,
+        page_pool: PagePool,
+        chaos: Chaos,
+        metrics: Metrics,
+    ) -> anyhow::Result<Self> {
         let db_dir_fd;
         let flock;
+        let read_only = o.read_only;
 
         let should_create = !o.path.exists() || is_directory_empty(o.path.as_path())?;
-        if should_create {
+        if should_create && read_only {
+            anyhow::bail!("no database to open read-only at {}", o.path.display());
+        } else if should_create {
             // NB: note TOCTOU here. Deemed acceptable for this case.
-            (db_dir_fd, flock) = create(&page_pool, &o)?;
+            let (fd, lock) = create(&page_pool, o)?;
+            (db_dir_fd, flock) = (fd, Some(lock));
         } else {
-            let mut options = OpenOptions::new();
-            options.read(true);
-            db_dir_fd = options.open(&o.path)?;
-            flock = flock::Flock::lock(&o.path, ".lock")?;
+            db_dir_fd = crate::sys::open_dir(&o.path)?;
+            flock = match read_only {
+                true => None,
+                false => Some(flock::Flock::lock(&o.path, ".lock")?),
+            };
+            partial::check_created(&o.path)?;
+            layout::check(&o.path, &o.layout)?;
         }
         let db_dir_fd = Arc::new(db_dir_fd);
 
         cfg_if::cfg_if! {
             if #[cfg(target_os = "linux")] {
-                // O_DIRECT is not supported on tmpfs.
-                let o_direct: bool;
-                match crate::sys::linux::fs_check(&db_dir_fd) {
-                    Ok(fsck) => {
-                        o_direct = !fsck.is_tmpfs();
-                    },
-                    Err(_) => {
-                        o_direct = false;
-                    },
-                }
+                // O_DIRECT is not supported on tmpfs. Files placed outside of the database
+                // directory may be on another file system.
+                let o_direct = |name: &str| {
+                    File::open(o.path.join(name))
+                        .and_then(|file| crate::sys::linux::fs_check(&file))
+                        .is_ok_and(|fsck| !fsck.is_tmpfs())
+                };
             }
         }
 
-        let io_pool = io::start_io_pool(o.io_workers, page_pool.clone());
+        let mut io_pool = io::start_io_pool(
+            o.io_workers,
+            page_pool.clone(),
+            o.write_throttle_target,
+            chaos,
+            metrics,
+        );
+        // Mapped files are read through the cache of the operating system.
+        let mmap = o.read_backend == ReadBackend::Mmap;
 
         let meta_fd = {
             let mut options = OpenOptions::new();
-            options.read(true).write(true);
+            options.read(true).write(!read_only);
             #[cfg(target_os = "linux")]
-            if o_direct {
+            if o_direct("meta") {
                 options.custom_flags(libc::O_DIRECT);
             }
+            #[cfg(windows)]
+            options.custom_flags(crate::sys::windows::FILE_FLAG_NO_BUFFERING);
             options.open(&o.path.join("meta"))?
         };
 
+        let mut meta = meta::Meta::read(&page_pool, &meta_fd)?;
+        meta.validate()?;
+        // Databases of older formats are kept as they are, so that older versions of NOMT can
+        // still open them, unless an upgrade is asked for.
+        if meta.version < meta::VERSION && o.upgrade_format {
+            if read_only {
+                anyhow::bail!("the database must be opened for writing to be upgraded");
+            }
+            // Stamp databases created before the lineage was recorded with an identifier.
+            if meta.version < 2 {
+                meta.lineage = lineage::Lineage::new();
+            }
+            meta.version = meta::VERSION;
+            Meta::write(&page_pool, &meta_fd, &meta)?;
+        }
+        // Older versions of NOMT can't read compressed values, and would fail on them rather than
+        // on the format version.
+        if meta.version < meta::COMPRESSION_VERSION
+            && o.compression != crate::Compression::None
+            && !read_only
+        {
+            anyhow::bail!(
+                "compressing values requires format version {}, but the database is of version \
+                 {}. see `Options::upgrade_format`",
+                meta::COMPRESSION_VERSION,
+                meta.version
+            );
+        }
+        // Older versions of NOMT would take the intermediate metas of a checkpointed sync for
+        // complete syncs.
+        if meta.version < meta::CHECKPOINT_VERSION
+            && o.sync_checkpoint_interval.is_some()
+            && !read_only
+        {
+            anyhow::bail!(
+                "checkpointed syncs require format version {}, but the database is of version \
+                 {}. see `Options::upgrade_format`",
+                meta::CHECKPOINT_VERSION,
+                meta.version
+            );
+        }
+        // Finish or discard a resize of the hash table interrupted by a crash, before the HT file
+        // is opened. The files of a resize in progress are left to the process resizing.
+        if !read_only {
+            bitbox::recover_resize(&o.path.join("ht"), meta.bitbox_num_pages)?;
+        }
+
         let ln_fd = {
             let mut options = OpenOptions::new();
-            options.read(true).write(true);
+            options.read(true).write(!read_only);
             #[cfg(target_os = "linux")]
-            if o_direct {
+            if o_direct("ln") && !mmap {
                 options.custom_flags(libc::O_DIRECT);
             }
+            #[cfg(windows)]
+            if !mmap {
+                options.custom_flags(crate::sys::windows::FILE_FLAG_NO_BUFFERING);
+            }
             Arc::new(options.open(&o.path.join("ln"))?)
         };
         let bbn_fd = {
             let mut options = OpenOptions::new();
-            options.read(true).write(true);
+            options.read(true).write(!read_only);
             #[cfg(target_os = "linux")]
-            if o_direct {
+            if o_direct("bbn") && !mmap {
                 options.custom_flags(libc::O_DIRECT);
             }
+            #[cfg(windows)]
+            if !mmap {
+                options.custom_flags(crate::sys::windows::FILE_FLAG_NO_BUFFERING);
+            }
             Arc::new(options.open(&o.path.join("bbn"))?)
         };
         let ht_fd = {
             let mut options = OpenOptions::new();
-            options.read(true).write(true);
+            options.read(true).write(!read_only);
             #[cfg(target_os = "linux")]
-            if o_direct {
+            if o_direct("ht") && !mmap {
                 options.custom_flags(libc::O_DIRECT);
             }
+            #[cfg(windows)]
+            if !mmap {
+                options.custom_flags(crate::sys::windows::FILE_FLAG_NO_BUFFERING);
+            }
             options.open(&o.path.join("ht"))?
         };
         let wal_fd = {
             let options = &mut OpenOptions::new();
-            options.read(true).write(true);
+            options.read(true).write(!read_only);
             #[cfg(target_os = "linux")]
-            if o_direct {
+            if o_direct("wal") {
                 options.custom_flags(libc::O_DIRECT);
             }
+            #[cfg(windows)]
+            options.custom_flags(crate::sys::windows::FILE_FLAG_NO_BUFFERING);
             options.open(&o.path.join("wal"))?
         };
 
@@ -138,15 +268,19 @@ impl Store {
             use std::os::fd::AsRawFd as _;
             unsafe {
                 libc::fcntl(meta_fd.as_raw_fd(), libc::F_NOCACHE, 1);
-                libc::fcntl(ln_fd.as_raw_fd(), libc::F_NOCACHE, 1);
-                libc::fcntl(bbn_fd.as_raw_fd(), libc::F_NOCACHE, 1);
-                libc::fcntl(ht_fd.as_raw_fd(), libc::F_NOCACHE, 1);
                 libc::fcntl(wal_fd.as_raw_fd(), libc::F_NOCACHE, 1);
+                if !mmap {
+                    libc::fcntl(ln_fd.as_raw_fd(), libc::F_NOCACHE, 1);
+                    libc::fcntl(bbn_fd.as_raw_fd(), libc::F_NOCACHE, 1);
+                    libc::fcntl(ht_fd.as_raw_fd(), libc::F_NOCACHE, 1);
+                }
             }
         }
 
-        let meta = meta::Meta::read(&page_pool, &meta_fd)?;
-        meta.validate()?;
+        if mmap {
+            io_pool.map_for_reads([&*ln_fd, &*bbn_fd, &ht_fd])?;
+        }
+
         let values = beatree::Tree::open(
             page_pool.clone(),
             &io_pool,
@@ -156,9 +290,26 @@ impl Store {
             meta.bbn_bump,
             bbn_fd,
             ln_fd,
-            o.commit_concurrency,
+            o.beatree_sync_workers
+                .unwrap_or(o.commit_concurrency)
+                .min(crate::MAX_COMMIT_CONCURRENCY),
             o.leaf_cache_size,
+            o.compression,
+            beatree::Dictionaries::open(&o.path, o.compression)?,
         )?;
+        // A read-only store opened in the middle of a checkpointed sync sees the values of the
+        // chunks already applied, but not the root they lead to.
+        let meta = if read_only {
+            meta
+        } else {
+            let sync_seqn = meta.sync_seqn;
+            let meta = checkpoint::recover(&o.path, &page_pool, &meta_fd, &values, meta)
+                .map_err(partial::sync_interrupted(sync_seqn, SyncPhase::Journal))?;
+            options_journal::record(&o.path, meta.sync_seqn, options_journal::snapshot(o, &meta))?;
+            meta
+        };
+        // A WAL left behind is replayed when opening the hash table.
+        let wal_pending = wal_fd.metadata()?.len() > 0;
         let pages = bitbox::DB::open(
             meta.sync_seqn,
             meta.bitbox_num_pages,
@@ -166,9 +317,18 @@ impl Store {
             page_pool.clone(),
             ht_fd,
             wal_fd,
-        )?;
-        let rollback = o
-            .rollback
+            o.wal_compression,
+            read_only,
+        )
+        .map_err(|e| {
+            if wal_pending {
+                partial::sync_interrupted(meta.sync_seqn, SyncPhase::Wal)(e)
+            } else {
+                e
+            }
+        })?;
+        // The rollback log is only read when writing.
+        let rollback = (o.rollback && !read_only)
             .then(|| {
                 Rollback::read(
                     o.max_rollback_log_len,
@@ -176,6 +336,11 @@ impl Store {
                     Arc::clone(&db_dir_fd),
                     meta.rollback_start_live,
                     meta.rollback_end_live,
+                    ArchiveRetention {
+                        max_segments: o.rollback_log_archive.0,
+                        max_bytes: o.rollback_log_archive.1,
+                    },
+                    o.rollback_disk_alert.clone(),
                 )
             })
             .transpose()?;
@@ -185,16 +350,30 @@ impl Store {
                 meta.bitbox_num_pages,
                 meta.bitbox_seed,
                 o.panic_on_sync,
+                o.simulate_out_of_space.clone(),
+                o.sync_checkpoint_interval,
+                meta.checkpoint,
+                meta.version,
+                meta.lineage,
             ))),
             shared: Arc::new(Shared {
                 rollback,
                 values,
-                pages,
+                pages: RwLock::new(pages),
+                ht_generation: AtomicU64::new(0),
                 io_pool,
                 _db_dir_fd: db_dir_fd,
                 meta_fd,
-                flock: Some(flock),
+                flock: Mutex::new(flock),
+                read_only,
+                write_gate: Arc::new(RwLock::new(())),
                 poisoned: false.into(),
+                db_dir_path: o.path.clone(),
+                write_stats: Mutex::new((None, WriteStats::default())),
+                prefix_writes: o.prefix_write_stats.map(|(prefix_len, window)| {
+                    Mutex::new(hot_prefixes::PrefixWriteTracker::new(prefix_len, window))
+                }),
+                stalled_commit: Mutex::new(None),
             }),
         })
     }
@@ -205,10 +384,230 @@ impl Store {
             .load(std::sync::atomic::Ordering::Relaxed)
     }
 
+    /// The phase of the sync which ran out of disk space, if a commit is waiting for it to be
+    /// resumed.
+    pub fn stalled_sync(&self) -> Option<SyncPhase> {
+        self.sync.lock().stalled()
+    }
+
+    /// Fail if commits are not accepted, because the store is poisoned or a commit is waiting
+    /// for disk space.
+    pub fn ensure_writable(&self) -> anyhow::Result<()> {
+        self.ensure_writable_inner(&self.sync.lock())
+    }
+
+    fn ensure_writable_inner(&self, sync: &sync::Sync) -> anyhow::Result<()> {
+        if self.shared.read_only {
+            anyhow::bail!("Store is opened read-only");
+        }
+        if self.is_poisoned() {
+            return Err(crate::error::Poisoned.into());
+        }
+        if let Some(phase) = sync.stalled() {
+            anyhow::bail!(
+                "A prior commit ran out of disk space while writing the {}. \
+                 Free some space and resume its sync",
+                phase
+            );
+        }
+        Ok(())
+    }
+
     pub fn sync_seqn(&self) -> u32 {
         self.sync.lock().sync_seqn
     }
 
+    /// Check that the writer of a store opened read-only has not synced past the view of the
+    /// store, failing with [`crate::StaleView`] if it has. Always succeeds for a writable store.
+    pub fn check_view(&self) -> anyhow::Result<()> {
+        if !self.shared.read_only {
+            return Ok(());
+        }
+        let (view_sync_seqn, view_checkpoint) = {
+            let sync = self.sync.lock();
+            (sync.sync_seqn, sync.checkpoint)
+        };
+        let meta = Meta::read(self.shared.io_pool.page_pool(), &self.shared.meta_fd)?;
+        if meta.sync_seqn != view_sync_seqn || meta.checkpoint != view_checkpoint {
+            return Err(crate::StaleView {
+                view_sync_seqn,
+                writer_sync_seqn: meta.sync_seqn,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Perform a read, checking the view of a store opened read-only before and after it. The
+    /// result is only returned if the writer did not sync past the view before the read completed.
+    pub fn read_view<R>(&self, read: impl FnOnce() -> anyhow::Result<R>) -> anyhow::Result<R> {
+        self.check_view()?;
+        let result = read()?;
+        self.check_view()?;
+        Ok(result)
+    }
+
+    /// The identity and history of the database.
+    pub fn lineage(&self) -> Lineage {
+        self.sync.lock().lineage
+    }
+
+    /// The effective options the database was opened with over time, oldest first.
+    pub fn options_journal(&self) -> anyhow::Result<Vec<OptionsJournalEntry>> {
+        options_journal::read(&self.shared.db_dir_path)
+    }
+
+    /// Record a rollback of the given number of commits in the lineage. It is persisted by the
+    /// next commit, which is expected to be the rollback itself.
+    pub fn record_rollback(&self, commits: usize) {
+        let mut sync = self.sync.lock();
+        sync.lineage.last_rollback = Some(LineageRollback {
+            from_sync_seqn: sync.sync_seqn,
+            commits: commits as u32,
+        });
+    }
+
+    /// Copy the database into a new directory, which must not exist or be empty.
+    ///
+    /// The copy gets a new identifier and records this database as its origin.
+    pub fn clone_to(&self, dest: &Path) -> anyhow::Result<()> {
+        // Hold the sync lock so that no files change while copying.
+        let sync = self.sync.lock();
+
+        if dest.exists() && !is_directory_empty(dest)? {
+            anyhow::bail!("clone destination {} is not empty", dest.display());
+        }
+        std::fs::create_dir_all(dest)?;
+
+        for entry in std::fs::read_dir(&self.shared.db_dir_path)? {
+            let entry = entry?;
+            // Files placed outside of the database directory are reached through their links.
+            if !std::fs::metadata(entry.path())?.is_file() || entry.file_name() == ".lock" {
+                continue;
+            }
+            let dest_path = dest.join(entry.file_name());
+            std::fs::copy(entry.path(), &dest_path)?;
+            OpenOptions::new()
+                .write(true)
+                .open(&dest_path)?
+                .sync_all()?;
+        }
+
+        let page_pool = self.shared.io_pool.page_pool();
+        let meta_fd = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(dest.join("meta"))?;
+        let mut meta = Meta::read(page_pool, &meta_fd)?;
+        meta.lineage = Lineage {
+            origin: Some(LineageOrigin {
+                parent: sync.lineage.id,
+                parent_sync_seqn: sync.sync_seqn,
+            }),
+            last_rollback: None,
+            ..Lineage::new()
+        };
+        Meta::write(page_pool, &meta_fd, &meta)?;
+
+        crate::sys::sync_dir_path(dest)?;
+        Ok(())
+    }
+
+    /// Copy the database into a new directory, which must not exist or be empty, holding up syncs
+    /// only to copy the pages written meanwhile. See the [`crate::backup`] module.
+    pub fn backup_to(&self, dest: &Path) -> anyhow::Result<BackupInfo> {
+        if dest.exists() && !is_directory_empty(dest)? {
+            anyhow::bail!("backup destination {} is not empty", dest.display());
+        }
+        std::fs::create_dir_all(dest)?;
+
+        let result = self.backup_to_inner(dest);
+        if result.is_err() {
+            remove_files(dest);
+        }
+        result
+    }
+
+    fn backup_to_inner(&self, dest: &Path) -> anyhow::Result<BackupInfo> {
+        let db_dir = &self.shared.db_dir_path;
+        let (ln_fd, bbn_fd) = self.shared.values.store_fds();
+        let fds = [ln_fd, bbn_fd, self.pages().ht_fd().as_raw_fd()];
+
+        // No writes are in flight between syncs, so none is missed.
+        let (tracked, ht_generation) = {
+            let _sync = self.sync.lock();
+            let Some(tracked) = self.io_pool().track_writes(&fds) else {
+                anyhow::bail!("another backup is in progress");
+            };
+            (tracked, self.ht_generation())
+        };
+
+        let mut bulk_bytes = 0;
+        for name in backup::BULK_FILES {
+            bulk_bytes += backup::copy_file(&db_dir.join(name), &dest.join(name))?;
+        }
+
+        let sync = self.sync.lock();
+        if self.ht_generation() != ht_generation {
+            anyhow::bail!("the hash table was resized during the backup");
+        }
+        let written = tracked.finish();
+        let mut recopied_pages = 0;
+        for (name, fd) in backup::BULK_FILES.into_iter().zip(fds) {
+            recopied_pages +=
+                backup::recopy_pages(&db_dir.join(name), &dest.join(name), &written[&fd])?;
+        }
+        backup::copy_remaining(db_dir, dest, &backup::BULK_FILES)?;
+
+        Ok(BackupInfo {
+            sync_seqn: sync.sync_seqn,
+            bulk_bytes,
+            recopied_pages,
+        })
+    }
+
+    /// Hold off freezing the store until the returned guard is dropped.
+    ///
+    /// Commits, rollbacks, resuming a sync and finishing a resize of the hash table take this
+    /// before the access lock, so that while frozen they wait without holding up reads. The
+    /// other writes to the files take it themselves.
+    pub fn write_gate(&self) -> parking_lot::RwLockReadGuard<'_, ()> {
+        self.shared.write_gate.read()
+    }
+
+    /// Like [`Store::write_gate`], but returns `None` instead of waiting for a freeze.
+    pub fn try_write_gate(&self) -> Option<parking_lot::RwLockReadGuard<'_, ()>> {
+        self.shared.write_gate.try_read()
+    }
+
+    /// Stop all writes to the files of the store and release the lock on its directory, until the
+    /// returned guard is passed to [`Store::thaw`].
+    ///
+    /// This waits for the sync or hash table resize step in progress, if any. Every sync is
+    /// durable once it completes, so the files are consistent while frozen.
+    pub fn freeze(&self) -> Frozen {
+        let gate = RwLock::write_arc(&self.shared.write_gate);
+        drop(self.shared.flock.lock().take());
+        Frozen { _gate: gate }
+    }
+
+    /// Take the lock on the directory again and let writes resume. On failure, the store stays
+    /// frozen and the guard is returned.
+    pub fn thaw(&self, frozen: Frozen) -> Result<(), (Frozen, anyhow::Error)> {
+        if self.shared.read_only {
+            drop(frozen);
+            return Ok(());
+        }
+        match Flock::lock(&self.shared.db_dir_path, ".lock") {
+            Ok(flock) => {
+                *self.shared.flock.lock() = Some(flock);
+                drop(frozen);
+                Ok(())
+            }
+            Err(e) => Err((frozen, e)),
+        }
+    }
+
     /// Returns a handle to the rollback object. `None` if the rollback feature is not enabled.
     pub fn rollback(&self) -> Option<&Rollback> {
         self.shared.rollback.as_ref()
@@ -216,11 +615,28 @@ impl Store {
 
     /// Loads the flat value stored under the given key.
     pub fn load_value(&self, key: KeyPath) -> anyhow::Result<Option<Vec<u8>>> {
-        Ok(self.shared.values.lookup(key))
+        Ok(self.shared.values.lookup(key)?)
+    }
+
+    /// Loads the hash of the value stored elsewhere under the given key. `None` unless the key was
+    /// written with [`ValueTransaction::write_value_hash`].
+    pub fn load_value_hash_only(&self, key: KeyPath) -> anyhow::Result<Option<ValueHash>> {
+        Ok(self.shared.values.lookup_hash_only(key)?)
+    }
+
+    /// Loads the bytes within the given range of the value of the given key, blocking the current
+    /// thread. The range is clipped to the size of the value.
+    pub fn load_value_slice(
+        &self,
+        key: KeyPath,
+        range: std::ops::Range<usize>,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.shared.values.lookup_slice(key, range)?)
     }
 
     /// Loads the given page, blocking the current thread.
     pub fn load_page(&self, page_id: PageId) -> anyhow::Result<Option<(FatPage, BucketIndex)>> {
+        let _timer = self.io_pool().metrics().record(Metric::PageFetchTime);
         let page_loader = self.page_loader();
         let io_handle = self.io_pool().make_handle();
         let mut page_load = page_loader.start_load(page_id);
@@ -247,20 +663,214 @@ impl Store {
         self.shared.values.read_transaction()
     }
 
+    /// Creates a new [`ValueIter`] over the half-open key range `[start, end)`. `sync` will be
+    /// blocked until this is dropped.
+    pub fn iter_values(&self, start: KeyPath, end: Option<KeyPath>) -> ValueIter {
+        ValueIter::new(
+            self.read_transaction(),
+            self.io_pool().make_handle(),
+            start,
+            end,
+        )
+    }
+
     /// Creates a new [`PageLoader`].
     pub fn page_loader(&self) -> PageLoader {
-        let page_loader = bitbox::PageLoader::new(&self.shared.pages);
+        let page_loader = bitbox::PageLoader::new(&self.pages());
         PageLoader { inner: page_loader }
     }
 
+    fn pages(&self) -> bitbox::DB {
+        self.shared.pages.read().clone()
+    }
+
     /// Access the underlying IoPool.
     pub fn io_pool(&self) -> &IoPool {
         &self.shared.io_pool
     }
 
+    /// The total time background writes have been delayed by the write throttle.
+    pub fn background_write_delay(&self) -> std::time::Duration {
+        self.io_pool().write_throttle().total_delay()
+    }
+
     /// Get the current hash-table bucket counts.
     pub fn hash_table_utilization(&self) -> HashTableUtilization {
-        self.shared.pages.utilization()
+        self.pages().utilization()
+    }
+
+    /// Get the generation of the hash table, which is incremented by every resize.
+    ///
+    /// Bucket indices of pages only apply to the generation they were read at.
+    pub fn ht_generation(&self) -> u64 {
+        self.shared.ht_generation.load(Ordering::Relaxed)
+    }
+
+    /// Start resizing the hash table to `num_pages` buckets. See [`bitbox::DB::begin_resize`].
+    pub fn begin_hash_table_resize(&self, num_pages: u32) -> anyhow::Result<()> {
+        let _gate = self.shared.write_gate.read();
+        let sync = self.sync.lock();
+        self.ensure_writable_inner(&sync)?;
+        let pages = self.pages();
+        // Mapped reads are served by file descriptor, which the resized table does not keep.
+        if self.io_pool().mapped_file(pages.ht_fd()).is_some() {
+            anyhow::bail!("resizing the hash table is not supported with the mmap read backend");
+        }
+        pages.begin_resize(&self.shared.db_dir_path.join("ht"), num_pages)
+    }
+
+    /// Copy the next `n` buckets into the new hash table, between commits. Returns the number of
+    /// buckets copied so far.
+    pub fn hash_table_resize_step(&self, n: u64) -> anyhow::Result<u64> {
+        let _gate = self.shared.write_gate.read();
+        let _sync = self.sync.lock();
+        let io_handle = self.io_pool().make_handle();
+        self.pages().resize_step(&io_handle, n)
+    }
+
+    /// Abandon the resize of the hash table in progress, if any.
+    ///
+    /// A poisoned store may have committed the switch, so the new hash table is left for the
+    /// next open to recover.
+    pub fn cancel_hash_table_resize(&self) -> anyhow::Result<()> {
+        let _gate = self.shared.write_gate.read();
+        let _sync = self.sync.lock();
+        if !self.is_poisoned() {
+            self.pages().cancel_resize()?;
+        }
+        Ok(())
+    }
+
+    /// Copy the remaining buckets into the new hash table and switch over to it.
+    ///
+    /// Pages read from the current hash table must be committed with the generation they were
+    /// read at afterwards, so that their buckets are looked up again. A failure after the switch
+    /// is committed poisons the store. The caller holds the [`Store::write_gate`].
+    pub fn finish_hash_table_resize(&self) -> anyhow::Result<()> {
+        let mut sync = self.sync.lock();
+        self.ensure_writable_inner(&sync)?;
+        let pages = self.pages();
+        let io_handle = self.io_pool().make_handle();
+        let num_pages = match pages.seal_resize(&io_handle) {
+            Ok(num_pages) => num_pages,
+            Err(e) => {
+                let _ = pages.cancel_resize();
+                return Err(e);
+            }
+        };
+
+        let result = (|| {
+            // Writing the meta commits the switch.
+            let page_pool = self.io_pool().page_pool();
+            let mut meta = Meta::read(page_pool, &self.shared.meta_fd)?;
+            meta.bitbox_num_pages = num_pages;
+            Meta::write(page_pool, &self.shared.meta_fd, &meta)?;
+            sync.bitbox_num_pages = num_pages;
+
+            let resized = pages.switch_resized(sync.sync_seqn)?;
+            *self.shared.pages.write() = resized;
+            self.shared.ht_generation.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        })();
+        if result.is_err() {
+            self.shared
+                .poisoned
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Rewrite the values needed to move the pages of the beatree scanned next which lie beyond
+    /// the number of pages in use toward the start of its files, and sync them like a commit.
+    ///
+    /// At least `max_leaves` leaves are scanned, from the branch with the separator `start` on.
+    /// With `start` of `None`, nothing is scanned. Either way, the sync compacts the free-lists of
+    /// the beatree, and the files are shrunk by the free pages at their ends on the next sync.
+    /// Returns the relocations made, without their changes.
+    ///
+    /// The caller holds the [`Store::write_gate`].
+    pub fn defragment_step(
+        &self,
+        page_cache: PageCache,
+        start: Option<beatree::Key>,
+        max_leaves: usize,
+    ) -> anyhow::Result<beatree::Relocations> {
+        let mut sync = self.sync.lock();
+        self.ensure_writable_inner(&sync)?;
+
+        let mut relocations = match start {
+            Some(start) => self.shared.values.relocations(start, max_leaves),
+            None => beatree::Relocations {
+                changes: Vec::new(),
+                pages: 0,
+                leaves: 0,
+                next: None,
+            },
+        };
+        let changes = std::mem::take(&mut relocations.changes);
+        self.shared.values.compact_next_sync();
+
+        let result = sync.sync(
+            &self.shared,
+            changes,
+            self.pages(),
+            self.shared.values.clone(),
+            self.shared.rollback.clone(),
+            page_cache,
+            Vec::<(PageId, DirtyPage)>::new(),
+        );
+        self.conclude_sync(result, 0, None)?;
+        Ok(relocations)
+    }
+
+    /// The number of leaves of the beatree.
+    pub fn beatree_leaf_count(&self) -> usize {
+        self.shared.values.leaf_count()
+    }
+
+    /// The total length of the files of the beatree, in bytes.
+    pub fn beatree_file_len(&self) -> std::io::Result<u64> {
+        self.shared.values.file_len()
+    }
+
+    /// Remove all leaves from the leaf cache.
+    pub fn clear_leaf_cache(&self) {
+        self.shared.values.clear_leaf_cache()
+    }
+
+    /// The number of leaves in the leaf cache.
+    pub fn leaf_cache_len(&self) -> usize {
+        self.shared.values.leaf_cache_len()
+    }
+
+    /// Remove the leaves holding the `cold` keys from the leaf cache, except for those which also
+    /// hold any of the `hot` keys. Returns the number of leaves removed.
+    pub fn uncache_leaves(&self, cold: &[KeyPath], hot: &[KeyPath]) -> usize {
+        self.shared.values.uncache_leaves(cold, hot)
+    }
+
+    /// List the leaves held in the leaf cache.
+    #[cfg(feature = "cache-debug")]
+    pub fn leaf_cache_snapshot(&self) -> Vec<crate::cache_debug::CachedLeaf> {
+        self.shared.values.leaf_cache_snapshot()
+    }
+
+    /// Copy at most `limit` of the cached leaves, from most to least recently used, along with
+    /// their page numbers.
+    pub fn hottest_leaves(&self, limit: usize) -> Vec<(u32, FatPage)> {
+        self.shared.values.hottest_leaves(limit)
+    }
+
+    /// Get at most `limit` of the keys in the cached leaves, those of the most recently used
+    /// leaves first.
+    pub fn hottest_keys(&self, limit: usize) -> Vec<KeyPath> {
+        self.shared.values.hottest_keys(limit)
+    }
+
+    /// Load leaves read from the cache file, ordered from hottest to coldest, into the leaf
+    /// cache.
+    pub fn restore_leaf_cache(&self, leaves: Vec<(u32, FatPage)>) {
+        self.shared.values.restore_leaf_cache(leaves)
     }
 
     /// Create a new raw value transaction to be applied against this database.
@@ -272,37 +882,190 @@ impl Store {
     ///
     /// After this function returns, accessor methods such as [`Self::load_page`] will return the
     /// updated values.
+    ///
+    /// `ht_generation` is the generation of the hash table the pages were read at. If the hash
+    /// table was resized since, their buckets are looked up again in the new one.
+    ///
+    /// An `urgent` commit skips the hot prefix accounting and its background writes are not
+    /// delayed by the write throttle.
+    ///
+    /// The caller holds the [`Store::write_gate`].
     pub fn commit(
         &self,
-        value_tx: impl IntoIterator<Item = (beatree::Key, beatree::ValueChange)> + Send + 'static,
+        value_tx: impl IntoIterator<Item = (beatree::Key, beatree::ValueChange)>,
         page_cache: PageCache,
-        updated_pages: impl IntoIterator<Item = (PageId, DirtyPage)> + Send + 'static,
+        updated_pages: impl IntoIterator<Item = (PageId, DirtyPage), IntoIter: Send + 'static>,
+        ht_generation: u64,
+        urgent: bool,
     ) -> anyhow::Result<()> {
         let mut sync = self.sync.lock();
+        self.ensure_writable_inner(&sync)?;
 
-        if self
+        let changes = value_tx.into_iter().collect::<Vec<_>>();
+        let logical_bytes = changes
+            .iter()
+            .map(|(_, change)| stats::logical_change_size(change.as_option()))
+            .sum();
+        let prefix_tally = self
             .shared
-            .poisoned
-            .load(std::sync::atomic::Ordering::Relaxed)
-        {
-            anyhow::bail!("Store is poisoned due to prior error");
-        }
+            .prefix_writes
+            .as_ref()
+            .filter(|_| !urgent)
+            .map(|tracker| tracker.lock().tally(&changes));
+        let _prioritized = urgent.then(|| self.io_pool().write_throttle().prioritize());
+
+        // The remapped pages come first, followed by the rest, which is nothing if remapped.
+        let mut updated_pages = updated_pages.into_iter();
+        let remapped = if ht_generation != self.ht_generation() {
+            match self.remap_buckets(updated_pages.by_ref()) {
+                Ok(remapped) => remapped,
+                Err(e) => return self.conclude_sync(Err(e), logical_bytes, prefix_tally),
+            }
+        } else {
+            Vec::new()
+        };
+        let updated_pages = remapped.into_iter().chain(updated_pages);
 
-        if let Err(e) = sync.sync(
+        let result = sync.sync(
             &self.shared,
-            value_tx,
-            self.shared.pages.clone(),
+            changes,
+            self.pages(),
             self.shared.values.clone(),
             self.shared.rollback.clone(),
             page_cache,
             updated_pages,
-        ) {
-            self.shared
-                .poisoned
-                .store(true, std::sync::atomic::Ordering::Relaxed);
-            return Err(e);
+        );
+        self.conclude_sync(result, logical_bytes, prefix_tally)
+    }
+
+    // Look up the buckets of pages read before a resize of the hash table in the new one.
+    fn remap_buckets(
+        &self,
+        updated_pages: impl Iterator<Item = (PageId, DirtyPage)>,
+    ) -> anyhow::Result<Vec<(PageId, DirtyPage)>> {
+        let mut remapped = Vec::new();
+        for (page_id, mut dirty_page) in updated_pages {
+            let stale = match dirty_page.bucket {
+                BucketInfo::Known(_) => true,
+                BucketInfo::FreshOrDependent(ref maybe_bucket) => maybe_bucket.get().is_some(),
+                BucketInfo::FreshWithNoDependents => false,
+            };
+            if stale {
+                let Some((_, bucket)) = self.load_page(page_id.clone())? else {
+                    anyhow::bail!("page {:?} is missing from the resized hash table", page_id);
+                };
+                match dirty_page.bucket {
+                    // Dependent overlays share the bucket, which is now the one in the new table.
+                    BucketInfo::FreshOrDependent(ref maybe_bucket) => maybe_bucket.set(bucket),
+                    _ => dirty_page.bucket = BucketInfo::Known(bucket),
+                }
+            }
+            remapped.push((page_id, dirty_page));
         }
-        Ok(())
+        Ok(remapped)
+    }
+
+    /// Resume the sync of the commit which ran out of disk space, if there is one.
+    ///
+    /// Returns `false` if there is no such commit. Fails with [`OutOfSpace`] again if the disk
+    /// is still full. The caller holds the [`Store::write_gate`].
+    pub fn resume_sync(&self) -> anyhow::Result<bool> {
+        let mut sync = self.sync.lock();
+        let Some(result) = sync.resume(&self.shared, self.shared.values.clone()) else {
+            return Ok(false);
+        };
+        // UNWRAP: a stalled sync always has a stalled commit.
+        let (logical_bytes, prefix_tally) = self.shared.stalled_commit.lock().take().unwrap();
+        self.conclude_sync(result, logical_bytes, prefix_tally)?;
+        Ok(true)
+    }
+
+    fn conclude_sync(
+        &self,
+        result: anyhow::Result<WriteStats>,
+        logical_bytes: u64,
+        prefix_tally: Option<hot_prefixes::Tally>,
+    ) -> anyhow::Result<()> {
+        match result {
+            Ok(mut write_stats) => {
+                write_stats.logical_bytes = logical_bytes;
+                let mut stats = self.shared.write_stats.lock();
+                stats.0 = Some(write_stats);
+                stats.1.accumulate(&write_stats);
+                if let (Some(tracker), Some(tally)) = (&self.shared.prefix_writes, prefix_tally) {
+                    tracker.lock().push(tally);
+                }
+                Ok(())
+            }
+            Err(e) if e.is::<OutOfSpace>() => {
+                *self.shared.stalled_commit.lock() = Some((logical_bytes, prefix_tally));
+                Err(e)
+            }
+            Err(e) => {
+                self.shared
+                    .poisoned
+                    .store(true, std::sync::atomic::Ordering::Relaxed);
+                Err(e)
+            }
+        }
+    }
+
+    /// The time spent in each stage of persisting the last commit.
+    pub fn last_sync_timings(&self) -> SyncTimings {
+        self.sync.lock().timings()
+    }
+
+    /// The bytes written by the last commit, if any.
+    pub fn last_commit_write_stats(&self) -> Option<WriteStats> {
+        self.shared.write_stats.lock().0
+    }
+
+    /// The bytes written by all commits since the store was opened.
+    pub fn total_write_stats(&self) -> WriteStats {
+        self.shared.write_stats.lock().1
+    }
+
+    /// The `n` most written key prefixes over the recent commits. Empty if not tracked.
+    pub fn hot_prefixes(&self, n: usize) -> Vec<HotPrefix> {
+        self.shared
+            .prefix_writes
+            .as_ref()
+            .map_or_else(Vec::new, |tracker| tracker.lock().top(n))
+    }
+
+    /// Check the hash table, the beatree and the WAL, recording the inconsistencies found in the
+    /// report. Returns the IDs of the pages held by the hash table.
+    ///
+    /// This reads every occupied bucket and every leaf node.
+    pub fn check_integrity(
+        &self,
+        report: &mut IntegrityReport,
+    ) -> anyhow::Result<std::collections::HashSet<PageId>> {
+        // Hold the sync lock so that no files change while checking.
+        let _sync = self.sync.lock();
+        let page_ids = self.pages().check_integrity(report)?;
+        self.shared.values.check_integrity(report);
+        Ok(page_ids)
+    }
+
+    /// Measure the disk space taken by the store against the values stored in it.
+    ///
+    /// This reads every value in the store.
+    pub fn space_stats(&self) -> anyhow::Result<SpaceStats> {
+        let mut space_stats = SpaceStats::default();
+        let mut values = self.iter_values([0; 32], None);
+        while let Some(item) = values.next_local() {
+            let (_, value) = item?;
+            // Only the hash of a value stored elsewhere is kept.
+            let value = match value {
+                crate::LocalValue::Stored(ref value) => &value[..],
+                crate::LocalValue::HashOnly(_) => &[],
+            };
+            space_stats.values += 1;
+            space_stats.logical_bytes += stats::logical_change_size(Some(value));
+        }
+        space_stats.measure_files(&self.shared.db_dir_path)?;
+        Ok(space_stats)
     }
 }
 
@@ -314,10 +1077,15 @@ impl Drop for Shared {
         // Otherwise, these IO workers might still be writing to the files while another process
         // acquired the flock.
         self.io_pool.shutdown();
-        drop(self.flock.take());
+        drop(self.flock.get_mut().take());
     }
 }
 
+/// Keeps a store frozen. See [`Store::freeze`].
+pub struct Frozen {
+    _gate: parking_lot::ArcRwLockWriteGuard<parking_lot::RawRwLock, ()>,
+}
+
 /// An atomic transaction on raw key/value pairs to be applied against the store
 /// with [`Store::commit`].
 pub struct ValueTransaction {
@@ -331,6 +1099,13 @@ impl ValueTransaction {
             .push((path, beatree::ValueChange::from_option::<T>(value)))
     }
 
+    /// Write the hash of a value stored elsewhere to flat storage. See
+    /// [`beatree::ValueChange::hash_only`].
+    pub fn write_value_hash(&mut self, path: beatree::Key, value_hash: ValueHash) {
+        self.batch
+            .push((path, beatree::ValueChange::hash_only(value_hash)))
+    }
+
     /// Iterate all the changed values.
     pub fn into_iter(self) -> impl Iterator<Item = (beatree::Key, beatree::ValueChange)> {
         self.batch.into_iter()
@@ -386,26 +1161,204 @@ pub struct DirtyPage {
 ///
 /// The database directory must not exist when calling this function.
 fn create(page_pool: &PagePool, o: &crate::Options) -> anyhow::Result<(File, Flock)> {
-    // Create the directory and its parent directories.
-    std::fs::create_dir_all(&o.path)?;
-    let db_dir_fd = std::fs::File::open(&o.path)?;
+    // The database is created in a temporary sibling directory and renamed into place once it is
+    // complete. A crash during creation thus never leaves a partially created database behind.
+    // A rename replaces an empty directory, so this also works for an empty `o.path`.
+    let path = if o.path.exists() {
+        o.path.canonicalize()?
+    } else {
+        std::path::absolute(&o.path)?
+    };
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        anyhow::bail!("cannot create a database at {}", path.display());
+    };
+    let creation_prefix = format!(".{}.creating-", name.to_string_lossy());
+
+    // Create the parent directories.
+    std::fs::create_dir_all(parent)?;
+
+    match create_and_rename(page_pool, o, &path, parent, &creation_prefix) {
+        // An existing directory can't be replaced if it is a mount point, or if its parent is not
+        // writable. The database is then created in the directory itself.
+        Err(e) if path.is_dir() && must_create_in_place(&e) => create_in_place(page_pool, o, &path),
+        result => result,
+    }
+}
 
-    // It's important that the lock is taken before creating modifying the directory contents.
-    // Because otherwise different instances could fight for changes.
-    let flock = Flock::lock(&o.path, ".lock")?;
+fn create_and_rename(
+    page_pool: &PagePool,
+    o: &crate::Options,
+    path: &Path,
+    parent: &Path,
+    creation_prefix: &str,
+) -> anyhow::Result<(File, Flock)> {
+    remove_abandoned_creations(parent, creation_prefix)?;
 
-    let meta_fd = std::fs::File::create(o.path.join("meta"))?;
+    let tmp_path = parent.join(format!("{creation_prefix}{:016x}", rand::random::<u64>()));
+    std::fs::create_dir(&tmp_path)?;
+
+    // It's important that the lock is taken before modifying the directory contents, so that
+    // the directory is not mistaken for an abandoned creation. The lock is on the lock file
+    // itself, so it is still held once the directory is renamed.
+    let flock = match Flock::lock(&tmp_path, ".lock") {
+        Ok(flock) => flock,
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(&tmp_path);
+            return Err(e);
+        }
+    };
+
+    let populated = populate(page_pool, o, &tmp_path).and_then(|()| {
+        std::fs::rename(&tmp_path, path).with_context(|| {
+            format!(
+                "failed to move the new database into place at {}",
+                path.display()
+            )
+        })
+    });
+    if let Err(e) = populated {
+        let _ = layout::remove_linked(&tmp_path);
+        let _ = std::fs::remove_dir_all(&tmp_path);
+        return Err(e);
+    }
+
+    // Sync the parent directory to persist the rename.
+    crate::sys::sync_dir_path(parent)?;
+    let db_dir_fd = crate::sys::open_dir(path)?;
+    Ok((db_dir_fd, flock))
+}
+
+/// Create the database in the empty directory at `path`.
+///
+/// The directory holds the [`partial::CREATING`] marker until the database is complete, so that
+/// opening a database whose creation was interrupted fails with
+/// [`partial::PartialDatabase::CreationIncomplete`].
+fn create_in_place(
+    page_pool: &PagePool,
+    o: &crate::Options,
+    path: &Path,
+) -> anyhow::Result<(File, Flock)> {
+    let flock = Flock::lock(path, ".lock")?;
+
+    let marker = path.join(partial::CREATING);
+    let created = (|| -> anyhow::Result<()> {
+        File::create(&marker)?;
+        crate::sys::sync_dir_path(path)?;
+        populate(page_pool, o, path)?;
+        std::fs::remove_file(&marker)?;
+        crate::sys::sync_dir_path(path)?;
+        Ok(())
+    })();
+    if let Err(e) = created {
+        // The directory was empty, so this leaves it as it was.
+        remove_files(path);
+        return Err(e);
+    }
+
+    let db_dir_fd = crate::sys::open_dir(path)?;
+    Ok((db_dir_fd, flock))
+}
+
+/// Whether creating the database in a sibling directory failed because the parent directory is
+/// not writable, or because the target directory can't be replaced by a rename.
+fn must_create_in_place(e: &anyhow::Error) -> bool {
+    use std::io::ErrorKind;
+    e.chain()
+        .filter_map(|e| e.downcast_ref::<std::io::Error>())
+        .any(|e| {
+            matches!(
+                e.kind(),
+                ErrorKind::PermissionDenied
+                    | ErrorKind::ReadOnlyFilesystem
+                    | ErrorKind::ResourceBusy
+                    | ErrorKind::CrossesDevices
+            )
+        })
+}
+
+/// Create the files of a new database in the given directory.
+fn populate(page_pool: &PagePool, o: &crate::Options, db_dir: &Path) -> anyhow::Result<()> {
+    let meta_fd = std::fs::File::create(db_dir.join("meta"))?;
     let meta = Meta::create_new(o.bitbox_seed, o.bitbox_num_pages);
     Meta::write(page_pool, &meta_fd, &meta)?;
     drop(meta_fd);
 
-    bitbox::create(o.path.clone(), o.bitbox_num_pages, o.preallocate_ht)?;
-    beatree::create(&o.path)?;
+    let paths = layout::FilePaths::new(db_dir, &o.layout)?;
+    let created = bitbox::create(&paths.ht, &paths.wal, o.bitbox_num_pages, o.preallocate_ht)
+        .map_err(anyhow::Error::from)
+        .and_then(|()| beatree::create(&paths.ln, &paths.bbn))
+        .and_then(|()| paths.link(db_dir));
+    if let Err(e) = created {
+        paths.remove(db_dir);
+        return Err(e);
+    }
 
     // As the last step, sync the directory. This makes sure that the directory is properly
     // written to disk.
-    db_dir_fd.sync_all()?;
-    Ok((db_dir_fd, flock))
+    crate::sys::sync_dir_path(db_dir)?;
+    Ok(())
+}
+
+/// Restore a backup made with [`Store::backup_to`] into the directory of the options, which must
+/// not exist or be empty. The files are placed according to the storage layout of the options.
+pub fn restore_backup(backup: &Path, o: &crate::Options) -> anyhow::Result<()> {
+    if !backup.join("meta").is_file() {
+        anyhow::bail!("{} is not a complete backup", backup.display());
+    }
+    let dest = o.path.as_path();
+    if dest.exists() && !is_directory_empty(dest)? {
+        anyhow::bail!("restore destination {} is not empty", dest.display());
+    }
+    std::fs::create_dir_all(dest)?;
+
+    let paths = layout::FilePaths::new(dest, &o.layout)?;
+    let restored = (|| {
+        for (name, path) in [
+            ("wal", &paths.wal),
+            ("ht", &paths.ht),
+            ("ln", &paths.ln),
+            ("bbn", &paths.bbn),
+        ] {
+            backup::copy_file(&backup.join(name), path)?;
+        }
+        paths.link(dest)?;
+        backup::copy_remaining(backup, dest, &["wal", "ht", "ln", "bbn"])
+    })();
+    if restored.is_err() {
+        paths.remove(dest);
+        remove_files(dest);
+    }
+    restored
+}
+
+// Remove the entries of a directory left by a failed copy, keeping the directory.
+fn remove_files(dir: &Path) {
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Remove the temporary directories of creations which were interrupted by a crash. Creations
+/// still in progress hold the lock of their directory and are left alone.
+fn remove_abandoned_creations(parent: &Path, creation_prefix: &str) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(parent)? {
+        let entry = entry?;
+        let is_creation = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with(creation_prefix));
+        if !is_creation || !entry.file_type()?.is_dir() {
+            continue;
+        }
+        if let Ok(flock) = Flock::lock(&entry.path(), ".lock") {
+            layout::remove_linked(&entry.path())?;
+            std::fs::remove_dir_all(entry.path())?;
+            drop(flock);
+        }
+    }
+    Ok(())
 }
 
 fn is_directory_empty(path: &std::path::Path) -> std::io::Result<bool> {
@@ -415,7 +1368,7 @@ fn is_directory_empty(path: &std::path::Path) -> std::io::Result<bool> {
 
 #[cfg(test)]
 mod tests {
-    use super::{PagePool, Store};
+    use super::{Chaos, Flock, Metrics, PagePool, Store};
 
     #[test]
     fn can_crate_in_empty_dir() {
@@ -425,7 +1378,134 @@ mod tests {
         options.path(tempdir.path());
 
         let page_pool = PagePool::new();
-        let store = Store::open(&options, page_pool.clone()).unwrap();
+        let store = Store::open(
+            &options,
+            page_pool.clone(),
+            Chaos::default(),
+            Metrics::new(false),
+        )
+        .unwrap();
         assert!(!store.is_poisoned());
     }
+
+    #[test]
+    fn abandoned_creations_are_removed() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let creation = |n: u64| tempdir.path().join(format!(".db.creating-{n:016x}"));
+
+        // A creation interrupted by a crash, and one still in progress.
+        std::fs::create_dir(creation(1)).unwrap();
+        std::fs::write(creation(1).join("meta"), b"partial").unwrap();
+        std::fs::create_dir(creation(2)).unwrap();
+        let in_progress = Flock::lock(&creation(2), ".lock").unwrap();
+
+        let mut options = crate::Options::new();
+        options.path(tempdir.path().join("db"));
+        let store = Store::open(
+            &options,
+            PagePool::new(),
+            Chaos::default(),
+            Metrics::new(false),
+        )
+        .unwrap();
+        assert!(!store.is_poisoned());
+
+        assert!(!creation(1).exists());
+        assert!(creation(2).exists());
+        let entries = std::fs::read_dir(tempdir.path()).unwrap().count();
+        assert_eq!(entries, 2);
+
+        // The new database is locked, and can be reopened once the store is dropped.
+        assert!(Store::open(
+            &options,
+            PagePool::new(),
+            Chaos::default(),
+            Metrics::new(false)
+        )
+        .is_err());
+        drop(store);
+        drop(in_progress);
+        Store::open(
+            &options,
+            PagePool::new(),
+            Chaos::default(),
+            Metrics::new(false),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn interrupted_creation_in_place_is_reported() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let db_dir = tempdir.path().join("db");
+        std::fs::create_dir(&db_dir).unwrap();
+
+        let mut options = crate::Options::new();
+        options.path(&db_dir);
+        let created = super::create_in_place(&PagePool::new(), &options, &db_dir).unwrap();
+        drop(created);
+        assert!(!db_dir.join(super::partial::CREATING).exists());
+        let store = Store::open(
+            &options,
+            PagePool::new(),
+            Chaos::default(),
+            Metrics::new(false),
+        )
+        .unwrap();
+        drop(store);
+
+        // A crash before the creation completed leaves the marker behind.
+        std::fs::write(db_dir.join(super::partial::CREATING), b"").unwrap();
+        let Err(e) = Store::open(
+            &options,
+            PagePool::new(),
+            Chaos::default(),
+            Metrics::new(false),
+        ) else {
+            panic!("opened a database whose creation did not complete");
+        };
+        let partial = e.downcast_ref::<crate::PartialDatabase>().unwrap();
+        assert!(partial.is_safe_to_delete());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn creates_in_place_when_parent_is_not_writable() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let db_dir = tempdir.path().join("db");
+        std::fs::create_dir(&db_dir).unwrap();
+        let set_mode = |mode| {
+            std::fs::set_permissions(tempdir.path(), std::fs::Permissions::from_mode(mode)).unwrap()
+        };
+        set_mode(0o555);
+        // Permissions are not enforced for privileged users.
+        if std::fs::File::create(tempdir.path().join("probe")).is_ok() {
+            set_mode(0o755);
+            return;
+        }
+
+        let mut options = crate::Options::new();
+        options.path(&db_dir);
+        let opened = Store::open(
+            &options,
+            PagePool::new(),
+            Chaos::default(),
+            Metrics::new(false),
+        );
+        set_mode(0o755);
+        let store = opened.unwrap();
+        assert!(!store.is_poisoned());
+        assert!(!db_dir.join(super::partial::CREATING).exists());
+
+        drop(store);
+        Store::open(
+            &options,
+            PagePool::new(),
+            Chaos::default(),
+            Metrics::new(false),
+        )
+        .unwrap();
+    }
 }
@@ -0,0 +1,270 @@
+//! A journal of the effective options the database was opened with.
+//!
+//! Whenever the database is opened with options differing from the last entry, a snapshot of the
+//! effective options is appended along with the current sync sequence number. Creation-time
+//! options are taken from the database itself rather than from the [`crate::Options`] given, as
+//! those given to an existing database are ignored.
+//!
+//! The journal is a text file of one entry per line. Each line is prefixed with a CRC32 of the
+//! rest of the line, so that an entry torn by a crash is detected and dropped. The entries before
+//! it are unaffected.
+
+use super::meta::Meta;
+use std::{
+    fs::OpenOptions,
+    io::{Seek as _, SeekFrom, Write as _},
+    path::Path,
+};
+
+const FILE_NAME: &str = "options_journal";
+
+/// The effective options of the database from a sync sequence number onwards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionsJournalEntry {
+    /// The sync sequence number when the database was opened with these options.
+    pub sync_seqn: u32,
+    /// The options, by name, in a fixed order.
+    pub options: Vec<(String, String)>,
+}
+
+impl OptionsJournalEntry {
+    /// Get the value of the option with the given name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn encode(&self) -> String {
+        let mut body = self.sync_seqn.to_string();
+        for (name, value) in &self.options {
+            body.push('\t');
+            body.push_str(name);
+            body.push('=');
+            body.push_str(value);
+        }
+        format!("{:08x}\t{}\n", crc32fast::hash(body.as_bytes()), body)
+    }
+
+    fn decode(line: &str) -> Option<Self> {
+        let (crc, body) = line.split_once('\t')?;
+        if u32::from_str_radix(crc, 16).ok()? != crc32fast::hash(body.as_bytes()) {
+            return None;
+        }
+        let mut fields = body.split('\t');
+        let sync_seqn = fields.next()?.parse().ok()?;
+        let options = fields
+            .map(|field| {
+                field
+                    .split_once('=')
+                    .map(|(n, v)| (n.to_string(), v.to_string()))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(OptionsJournalEntry { sync_seqn, options })
+    }
+}
+
+/// The effective options of a database opened with the given options and metadata.
+pub(super) fn snapshot(o: &crate::Options, meta: &Meta) -> Vec<(String, String)> {
+    let options: [(&str, String); 31] = [
+        // Creation-time options.
+        ("hashtable_buckets", meta.bitbox_num_pages.to_string()),
+        (
+            "bitbox_seed",
+            meta.bitbox_seed
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect(),
+        ),
+        // Runtime options.
+        (
+            "commit_concurrency",
+            o.commit_concurrency
+                .min(crate::MAX_COMMIT_CONCURRENCY)
+                .to_string(),
+        ),
+        (
+            "beatree_sync_workers",
+            o.beatree_sync_workers
+                .unwrap_or(o.commit_concurrency)
+                .min(crate::MAX_COMMIT_CONCURRENCY)
+                .to_string(),
+        ),
+        ("io_workers", o.io_workers.to_string()),
+        (
+            "hashtable_auto_resize",
+            format!("{:?}", o.hashtable_auto_resize),
+        ),
+        ("metrics", o.metrics.to_string()),
+        ("rollback", o.rollback.to_string()),
+        ("max_rollback_log_len", o.max_rollback_log_len.to_string()),
+        (
+            "rollback_log_archive",
+            format!("{:?}", o.rollback_log_archive),
+        ),
+        (
+            "rollback_cache_policy",
+            format!("{:?}", o.rollback_cache_policy),
+        ),
+        ("warm_up", o.warm_up.to_string()),
+        ("page_cache_size", o.page_cache_size.to_string()),
+        ("leaf_cache_size", o.leaf_cache_size.to_string()),
+        (
+            "prepopulate_page_cache",
+            o.prepopulate_page_cache.to_string(),
+        ),
+        (
+            "prepopulate_page_cache_rate",
+            format!("{:?}", o.prepopulate_page_cache_rate),
+        ),
+        (
+            "page_cache_upper_levels",
+            o.page_cache_upper_levels.to_string(),
+        ),
+        ("cache_file_size", o.cache_file_size.to_string()),
+        ("read_through", o.read_through.is_some().to_string()),
+        (
+            "read_repair_sample_rate",
+            format!("{:?}", o.read_repair.as_ref().map(|r| r.sample_rate)),
+        ),
+        (
+            "sync_checkpoint_interval",
+            format!("{:?}", o.sync_checkpoint_interval),
+        ),
+        ("wal_compression", o.wal_compression.to_string()),
+        ("compression", format!("{:?}", o.compression)),
+        ("max_trie_depth", format!("{:?}", o.max_trie_depth)),
+        (
+            "duplicate_write_policy",
+            format!("{:?}", o.duplicate_write_policy),
+        ),
+        (
+            "write_throttle_target",
+            format!("{:?}", o.write_throttle_target),
+        ),
+        ("read_backend", format!("{:?}", o.read_backend)),
+        (
+            "reserve_system_keyspace",
+            o.reserve_system_keyspace.to_string(),
+        ),
+        ("namespace_bits", format!("{:?}", o.namespace_bits)),
+        ("upgrade_format", o.upgrade_format.to_string()),
+        (
+            "yield_hook_every",
+            format!("{:?}", o.yield_hook.as_ref().map(|hook| hook.every)),
+        ),
+    ];
+    options
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+}
+
+/// Read the valid entries of the journal, along with the length of the file they take up.
+fn read_entries(db_dir: &Path) -> anyhow::Result<(Vec<OptionsJournalEntry>, u64)> {
+    let contents = match std::fs::read(db_dir.join(FILE_NAME)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut entries = Vec::new();
+    let mut valid_len = 0;
+    // Only complete lines are considered. Stop at the first entry torn by a crash.
+    for line in contents.split_inclusive(|b| *b == b'\n') {
+        let Some(line) = line.strip_suffix(b"\n") else {
+            break;
+        };
+        let Some(entry) = std::str::from_utf8(line)
+            .ok()
+            .and_then(OptionsJournalEntry::decode)
+        else {
+            break;
+        };
+        entries.push(entry);
+        valid_len += line.len() as u64 + 1;
+    }
+    Ok((entries, valid_len))
+}
+
+/// Read all entries of the journal, oldest first.
+pub(super) fn read(db_dir: &Path) -> anyhow::Result<Vec<OptionsJournalEntry>> {
+    read_entries(db_dir).map(|(entries, _)| entries)
+}
+
+/// Append an entry for the given options, unless they equal those of the last entry.
+pub(super) fn record(
+    db_dir: &Path,
+    sync_seqn: u32,
+    options: Vec<(String, String)>,
+) -> anyhow::Result<()> {
+    let (entries, valid_len) = read_entries(db_dir)?;
+    if entries.last().is_some_and(|last| last.options == options) {
+        return Ok(());
+    }
+
+    let path = db_dir.join(FILE_NAME);
+    let created = !path.exists();
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(&path)?;
+    // Drop a torn entry before appending.
+    file.set_len(valid_len)?;
+    file.seek(SeekFrom::Start(valid_len))?;
+    let entry = OptionsJournalEntry { sync_seqn, options };
+    file.write_all(entry.encode().as_bytes())?;
+    file.sync_data()?;
+    if created {
+        crate::sys::sync_dir_path(db_dir)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, record, FILE_NAME};
+    use std::io::Write as _;
+
+    fn options(page_cache_size: usize) -> Vec<(String, String)> {
+        vec![
+            ("io_workers".to_string(), "3".to_string()),
+            ("page_cache_size".to_string(), page_cache_size.to_string()),
+        ]
+    }
+
+    #[test]
+    fn torn_entries_are_dropped() {
+        let dir = std::env::temp_dir().join(format!("options_journal_{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        record(&dir, 0, options(256)).unwrap();
+        record(&dir, 3, options(256)).unwrap();
+        record(&dir, 5, options(512)).unwrap();
+        let entries = read(&dir).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].sync_seqn, 0);
+        assert_eq!(entries[1].sync_seqn, 5);
+        assert_eq!(entries[1].get("page_cache_size"), Some("512"));
+
+        // A crash in the middle of appending leaves a partial line, or a line of garbage.
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join(FILE_NAME))
+            .unwrap();
+        file.write_all(b"00000000\t7\tio_workers=3\n12345678\t8")
+            .unwrap();
+        drop(file);
+        assert_eq!(read(&dir).unwrap(), entries);
+
+        record(&dir, 9, options(1024)).unwrap();
+        let after = read(&dir).unwrap();
+        assert_eq!(after.len(), 3);
+        assert_eq!(after[..2], entries[..]);
+        assert_eq!(after[2].sync_seqn, 9);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+//! Recognizing databases which were only partially written.
+//!
+//! A database is created in full before it is moved into place, or, where the directory can't be
+//! replaced, marked as being created until it is complete. Interrupted syncs are finished on
+//! opening. Databases created by older versions, or with files removed after the fact,
+//! can still be found half-created, and finishing a sync can fail. Both are reported as a
+//! [`PartialDatabase`], which tells the two apart, so that it is clear whether the directory can
+//! be deleted.
+
+use super::{meta::Meta, SyncPhase};
+use crate::io::PAGE_SIZE;
+use std::path::Path;
+
+/// The files every database has, in the order they are created.
+const FILES: [&str; 5] = ["meta", "ht", "wal", "ln", "bbn"];
+
+/// The file marking a database which is being created in its own directory.
+pub(super) const CREATING: &str = "creating";
+
+/// The error of opening a database which was only partially written.
+///
+/// This is returned by [`crate::Nomt::open`] and can be found with
+/// [`anyhow::Error::downcast_ref`].
+#[derive(Debug)]
+pub enum PartialDatabase {
+    /// The creation of the database never completed.
+    ///
+    /// The database holds no data. It is safe to delete the directory, or its contents, and open it
+    /// again to create a new database.
+    CreationIncomplete {
+        /// The files of the database which are missing or were never written.
+        missing: Vec<&'static str>,
+    },
+    /// A sync was interrupted by a crash, and finishing it on opening failed.
+    ///
+    /// The database holds committed data and must not be deleted. Opening it again retries the
+    /// recovery, which should succeed once the cause of the failure, such as a full disk, is
+    /// fixed. Otherwise, it has to be restored from a backup.
+    SyncInterrupted {
+        /// The sequence number of the last sync which was completed.
+        sync_seqn: u32,
+        /// The part of the sync whose recovery failed.
+        phase: SyncPhase,
+        /// The error the recovery failed with.
+        source: anyhow::Error,
+    },
+}
+
+impl PartialDatabase {
+    /// Whether the database can be deleted without losing any data.
+    pub fn is_safe_to_delete(&self) -> bool {
+        matches!(self, PartialDatabase::CreationIncomplete { .. })
+    }
+}
+
+impl std::fmt::Display for PartialDatabase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PartialDatabase::CreationIncomplete { missing } => {
+                write!(f, "the creation of the database did not complete")?;
+                if !missing.is_empty() {
+                    write!(f, " (missing: {})", missing.join(", "))?;
+                }
+                write!(
+                    f,
+                    ". It holds no data: delete the directory, or its contents, and open it again to \
+                     create a new one"
+                )
+            }
+            PartialDatabase::SyncInterrupted {
+                sync_seqn,
+                phase,
+                source,
+            } => write!(
+                f,
+                "recovering the sync interrupted after sync {} failed in the {}: {}. \
+                 The database holds committed data and must not be deleted: fix the cause and \
+                 open it again, or restore it from a backup",
+                sync_seqn, phase, source
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PartialDatabase {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PartialDatabase::CreationIncomplete { .. } => None,
+            PartialDatabase::SyncInterrupted { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
+
+/// Fail with [`PartialDatabase::CreationIncomplete`] if the database in the directory was never
+/// fully created.
+///
+/// This is the case if the directory is still marked as being created, if the meta was never
+/// written, or if it records no syncs and some of the other files are missing. Files missing from
+/// a database which has been synced are damage, which is left to fail opening as usual.
+pub(super) fn check_created(db_dir: &Path) -> anyhow::Result<()> {
+    let mut missing = FILES
+        .into_iter()
+        .filter(|name| !db_dir.join(name).exists())
+        .collect::<Vec<_>>();
+    if db_dir.join(CREATING).exists() {
+        return Err(PartialDatabase::CreationIncomplete { missing }.into());
+    }
+
+    let meta = match std::fs::read(db_dir.join("meta")) {
+        Ok(buf) if buf.len() >= PAGE_SIZE => Some(Meta::decode(&buf)),
+        Ok(_) => None,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    // A zeroed meta page was allocated but never written.
+    match meta.filter(|meta| meta.magic != [0; 4]) {
+        Some(meta) if meta.sync_seqn > 0 || missing.is_empty() => return Ok(()),
+        Some(_) => {}
+        None if missing.contains(&"meta") => {}
+        None => missing.insert(0, "meta"),
+    }
+    Err(PartialDatabase::CreationIncomplete { missing }.into())
+}
+
+/// Report a failure to recover an interrupted sync as [`PartialDatabase::SyncInterrupted`].
+pub(super) fn sync_interrupted(
+    sync_seqn: u32,
+    phase: SyncPhase,
+) -> impl FnOnce(anyhow::Error) -> anyhow::Error {
+    move |source| {
+        PartialDatabase::SyncInterrupted {
+            sync_seqn,
+            phase,
+            source,
+        }
+        .into()
+    }
+}
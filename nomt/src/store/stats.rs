@@ -0,0 +1,116 @@
+//! Write and space amplification accounting.
+
+use std::path::Path;
+
+/// The bytes written to disk by commits.
+///
+/// Logical bytes are the key and value bytes handed to the database. Physical bytes are
+/// everything written to storage to persist them: the bitbox WAL and hash-table pages, beatree
+/// pages, the rollback log and the meta page.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WriteStats {
+    /// The number of commits covered.
+    pub commits: u64,
+    /// Key and value bytes of all changed values. A deletion counts only its key.
+    pub logical_bytes: u64,
+    /// Bytes written to the bitbox write-ahead log.
+    pub wal_bytes: u64,
+    /// Bytes written to the journal of checkpointed syncs, which holds their value changes a
+    /// second time. See [`crate::Options::sync_checkpoint_interval`].
+    pub journal_bytes: u64,
+    /// Bytes of hash-table pages written.
+    pub ht_bytes: u64,
+    /// Bytes of beatree leaf, branch, overflow and free-list pages written.
+    pub beatree_bytes: u64,
+    /// Bytes appended to the rollback log.
+    pub rollback_bytes: u64,
+    /// Bytes of the meta page written.
+    pub meta_bytes: u64,
+}
+
+impl WriteStats {
+    /// The total number of bytes written to disk.
+    pub fn physical_bytes(&self) -> u64 {
+        self.wal_bytes
+            + self.journal_bytes
+            + self.ht_bytes
+            + self.beatree_bytes
+            + self.rollback_bytes
+            + self.meta_bytes
+    }
+
+    /// Physical bytes written per logical byte. `None` if nothing logical was written.
+    pub fn write_amplification(&self) -> Option<f64> {
+        (self.logical_bytes != 0).then(|| self.physical_bytes() as f64 / self.logical_bytes as f64)
+    }
+
+    pub(super) fn accumulate(&mut self, other: &WriteStats) {
+        self.commits += other.commits;
+        self.logical_bytes += other.logical_bytes;
+        self.wal_bytes += other.wal_bytes;
+        self.journal_bytes += other.journal_bytes;
+        self.ht_bytes += other.ht_bytes;
+        self.beatree_bytes += other.beatree_bytes;
+        self.rollback_bytes += other.rollback_bytes;
+        self.meta_bytes += other.meta_bytes;
+    }
+}
+
+/// The space taken by the database on disk relative to the data stored in it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SpaceStats {
+    /// The number of values stored.
+    pub values: u64,
+    /// Key and value bytes of all stored values.
+    pub logical_bytes: u64,
+    /// Disk space allocated to the hash-table file and its WAL.
+    pub ht_bytes: u64,
+    /// Disk space allocated to the beatree leaf and branch files.
+    pub beatree_bytes: u64,
+    /// Disk space allocated to the rollback log.
+    pub rollback_bytes: u64,
+    /// Disk space allocated to anything else in the database directory.
+    pub other_bytes: u64,
+}
+
+impl SpaceStats {
+    /// The total disk space allocated to the database.
+    pub fn physical_bytes(&self) -> u64 {
+        self.ht_bytes + self.beatree_bytes + self.rollback_bytes + self.other_bytes
+    }
+
+    /// Allocated disk space per logical byte stored. `None` if the database is empty.
+    ///
+    /// Note that the hash-table file is usually preallocated, so this is high for small
+    /// databases.
+    pub fn space_amplification(&self) -> Option<f64> {
+        (self.logical_bytes != 0).then(|| self.physical_bytes() as f64 / self.logical_bytes as f64)
+    }
+
+    /// Fill in the physical side from the files in the database directory.
+    pub(super) fn measure_files(&mut self, db_dir: &Path) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(db_dir)? {
+            let entry = entry?;
+            // Follow the links to files placed outside of the database directory.
+            let metadata = std::fs::metadata(entry.path())?;
+            if !metadata.is_file() {
+                continue;
+            }
+
+            let allocated = crate::sys::allocated_bytes(&metadata);
+            let name = entry.file_name();
+            match name.to_str().unwrap_or("") {
+                "ht" | "wal" => self.ht_bytes += allocated,
+                "ln" | "bbn" => self.beatree_bytes += allocated,
+                name if name.starts_with("rollback") => self.rollback_bytes += allocated,
+                _ => self.other_bytes += allocated,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The number of bytes a value change counts for in [`WriteStats::logical_bytes`].
+pub(super) fn logical_change_size(value: Option<&[u8]>) -> u64 {
+    32 + value.map_or(0, |v| v.len() as u64)
+}
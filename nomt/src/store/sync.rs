@@ -1,16 +1,200 @@
 use nomt_core::page_id::PageId;
 
 use super::{
+    checkpoint::Journal,
+    lineage::Lineage,
     meta::{self, Meta},
+    stats::WriteStats,
     DirtyPage, Shared,
 };
-use crate::{beatree, bitbox, options::PanicOnSyncMode, page_cache::PageCache, rollback};
+use crate::{
+    beatree, bitbox,
+    io::PAGE_SIZE,
+    metrics::{Metric, Metrics, SyncStage},
+    options::PanicOnSyncMode,
+    page_cache::PageCache,
+    rollback,
+    trace::Trace,
+};
+use std::{
+    cell::Cell,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// A phase of persisting a commit which may run out of disk space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPhase {
+    /// Writing the updated pages to the write-ahead log.
+    Wal,
+    /// Writing the journal of a checkpointed sync. See [`crate::Options::sync_checkpoint_interval`].
+    Journal,
+    /// Writing the values, or a chunk of them in a checkpointed sync.
+    Values,
+}
+
+impl std::fmt::Display for SyncPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SyncPhase::Wal => f.write_str("WAL"),
+            SyncPhase::Journal => f.write_str("checkpoint journal"),
+            SyncPhase::Values => f.write_str("values"),
+        }
+    }
+}
+
+/// The error of a commit which could not be persisted because the disk is full.
+///
+/// The commit is not lost. It remains pending, visible to reads, until
+/// [`crate::Nomt::resume_sync`] completes it, starting over from the phase which failed. Until
+/// then, further commits are refused.
+#[derive(Debug)]
+pub struct OutOfSpace {
+    /// The phase which ran out of space.
+    pub phase: SyncPhase,
+    source: std::io::Error,
+}
+
+impl std::fmt::Display for OutOfSpace {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "out of disk space while writing the {}", self.phase)
+    }
+}
+
+impl std::error::Error for OutOfSpace {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
 
 pub struct Sync {
     pub(crate) sync_seqn: u32,
     pub(crate) bitbox_num_pages: u32,
     pub(crate) bitbox_seed: [u8; 16],
     pub(crate) panic_on_sync: Option<PanicOnSyncMode>,
+    pub(crate) simulate_out_of_space: Option<(SyncPhase, Arc<AtomicBool>)>,
+    pub(crate) checkpoint_interval: Option<usize>,
+    /// The number of chunks of the next sync already applied to the beatree and checkpointed.
+    /// 0 unless a checkpointed sync is underway.
+    pub(crate) checkpoint: u32,
+    /// The format version written to every meta.
+    pub(crate) version: u32,
+    /// Written to every meta.
+    pub(crate) lineage: Lineage,
+    /// The sync which ran out of disk space, if any.
+    stalled: Option<Box<Pending>>,
+    /// The time spent in each stage by the last sync, including its resumptions.
+    timings: StageTimings,
+}
+
+/// The time spent in each stage of persisting a commit.
+///
+/// The WAL is written while the values are, so their times overlap.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SyncTimings {
+    /// Writing the updated pages to the write-ahead log.
+    pub wal: Duration,
+    /// Writing the journal of a checkpointed sync. Zero for syncs which aren't split. See
+    /// [`crate::Options::sync_checkpoint_interval`].
+    pub journal: Duration,
+    /// Writing the values to the beatree.
+    pub values: Duration,
+    /// Writing the meta, which makes the commit durable.
+    pub meta: Duration,
+    /// Writing the updated pages to the hash table and releasing the freed pages.
+    pub post_meta: Duration,
+}
+
+#[derive(Default)]
+struct StageTimings([Cell<Duration>; 5]);
+
+impl StageTimings {
+    fn get(&self) -> SyncTimings {
+        SyncTimings {
+            wal: self.stage(SyncStage::Wal).get(),
+            journal: self.stage(SyncStage::Journal).get(),
+            values: self.stage(SyncStage::Values).get(),
+            meta: self.stage(SyncStage::Meta).get(),
+            post_meta: self.stage(SyncStage::PostMeta).get(),
+        }
+    }
+
+    fn stage(&self, stage: SyncStage) -> &Cell<Duration> {
+        match stage {
+            SyncStage::Wal => &self.0[0],
+            SyncStage::Journal => &self.0[1],
+            SyncStage::Values => &self.0[2],
+            SyncStage::Meta => &self.0[3],
+            SyncStage::PostMeta => &self.0[4],
+        }
+    }
+}
+
+// Adds the time until dropped to a stage.
+struct StageTimer<'a> {
+    timing: &'a Cell<Duration>,
+    start: Instant,
+}
+
+impl Drop for StageTimer<'_> {
+    fn drop(&mut self) {
+        self.timing.set(self.timing.get() + self.start.elapsed());
+    }
+}
+
+// A sync in progress.
+struct Pending {
+    /// The phase being attempted.
+    phase: SyncPhase,
+    /// The sequence number of the final meta, one past that of the last sync.
+    sync_seqn: u32,
+    num_chunks: u32,
+    chunk_size: usize,
+    rollback_live: (u64, u64),
+    bitbox_sync: bitbox::SyncController,
+    rollback_sync: Option<rollback::SyncController>,
+    /// The journal of a checkpointed sync, until it is written.
+    journal: Option<Journal>,
+    /// The value changes not yet handed to the beatree.
+    changes: std::vec::IntoIter<(beatree::Key, beatree::ValueChange)>,
+    /// Whether the beatree staging holds changes to sync, returned by an aborted beatree sync or
+    /// staged when the sync stalled.
+    staged: bool,
+    /// Whether the remaining changes are synced at once instead of in chunks.
+    unchunked: bool,
+    /// Whether a write of the WAL failed.
+    wal_failed: bool,
+    wal_bytes: Option<usize>,
+    journal_bytes: usize,
+    /// The final beatree sync, once it is ready for the meta.
+    values: Option<(beatree::SyncController, beatree::SyncData)>,
+    beatree_pages: usize,
+    metas_written: usize,
+}
+
+enum SyncError {
+    /// Ran out of disk space. The sync may be resumed.
+    Stalled(std::io::Error),
+    Fatal(anyhow::Error),
+}
+
+impl From<anyhow::Error> for SyncError {
+    fn from(e: anyhow::Error) -> Self {
+        SyncError::Fatal(e)
+    }
+}
+
+impl From<std::io::Error> for SyncError {
+    fn from(e: std::io::Error) -> Self {
+        SyncError::Fatal(e.into())
+    }
+}
+
+fn is_out_of_space(e: &std::io::Error) -> bool {
+    e.kind() == std::io::ErrorKind::StorageFull
 }
 
 impl Sync {
@@ -19,48 +203,345 @@ impl Sync {
         bitbox_num_pages: u32,
         bitbox_seed: [u8; 16],
         panic_on_sync: Option<PanicOnSyncMode>,
+        simulate_out_of_space: Option<(SyncPhase, Arc<AtomicBool>)>,
+        checkpoint_interval: Option<usize>,
+        checkpoint: u32,
+        version: u32,
+        lineage: Lineage,
     ) -> Self {
         Self {
             sync_seqn,
             bitbox_num_pages,
             bitbox_seed,
             panic_on_sync,
+            simulate_out_of_space,
+            checkpoint_interval,
+            checkpoint,
+            version,
+            lineage,
+            stalled: None,
+            timings: StageTimings::default(),
         }
     }
 
+    /// The time spent in each stage by the last sync, including its resumptions.
+    pub fn timings(&self) -> SyncTimings {
+        self.timings.get()
+    }
+
+    /// The phase of the sync which ran out of disk space, if there is one.
+    pub fn stalled(&self) -> Option<SyncPhase> {
+        self.stalled.as_ref().map(|pending| pending.phase)
+    }
+
+    /// Persist a commit.
+    ///
+    /// The root is not stored separately: it is derived from the root page, which is written
+    /// through the bitbox WAL together with every other updated page. The WAL only becomes
+    /// current with the final meta, so all of the pages a commit touches, the root page among
+    /// them, are published in one step. Anything that carries additional roots must pass them
+    /// through `updated_pages` to keep that guarantee.
+    ///
+    /// If the disk fills up before the final meta is written, this fails with [`OutOfSpace`] and
+    /// the sync is kept to be resumed with [`Self::resume`].
     pub fn sync(
         &mut self,
         shared: &Shared,
-        value_tx: impl IntoIterator<Item = (beatree::Key, beatree::ValueChange)> + Send + 'static,
+        changes: Vec<(beatree::Key, beatree::ValueChange)>,
         bitbox: bitbox::DB,
         beatree: beatree::Tree,
         rollback: Option<rollback::Rollback>,
         page_cache: PageCache,
         updated_pages: impl IntoIterator<Item = (PageId, DirtyPage)> + Send + 'static,
-    ) -> anyhow::Result<()> {
-        let sync_seqn = self.sync_seqn + 1;
+    ) -> anyhow::Result<WriteStats> {
+        assert!(self.stalled.is_none());
+        self.timings = StageTimings::default();
+
+        // Large changesets are split into chunks, each but the last concluded by an intermediate
+        // meta. Only the final meta advances the sequence number. See the `checkpoint` module.
+        let chunk_size = match self.checkpoint_interval {
+            Some(interval) if changes.len() > interval => interval,
+            _ => changes.len().max(1),
+        };
+        let num_chunks = changes.len().div_ceil(chunk_size).max(1) as u32;
+        let base_seqn = self.sync_seqn;
+        let sync_seqn = base_seqn + 1;
 
         let mut bitbox_sync = bitbox.sync();
-        let mut beatree_sync = beatree.sync();
         let mut rollback_sync = rollback.map(|rollback| rollback.sync());
 
         bitbox_sync.begin_sync(sync_seqn, page_cache, updated_pages);
-        beatree_sync.begin_sync(value_tx);
-        let (rollback_start_live, rollback_end_live) = match rollback_sync {
+        let rollback_live = match rollback_sync {
             Some(ref mut rollback) => rollback.begin_sync(),
             None => (0, 0),
         };
 
-        bitbox_sync.wait_pre_meta()?;
-        let beatree_meta_wd = beatree_sync.wait_pre_meta()?;
+        let (journal, changes) = if num_chunks > 1 {
+            let journal = Journal {
+                base_seqn,
+                rollback_start_live: rollback_live.0,
+                rollback_end_live: rollback_live.1,
+                chunk_size,
+                changes,
+            };
+            (Some(journal), Vec::new())
+        } else {
+            (None, changes)
+        };
+
+        let pending = Pending {
+            phase: SyncPhase::Wal,
+            sync_seqn,
+            num_chunks,
+            chunk_size,
+            rollback_live,
+            bitbox_sync,
+            rollback_sync,
+            journal,
+            changes: changes.into_iter(),
+            staged: false,
+            unchunked: false,
+            wal_failed: false,
+            wal_bytes: None,
+            journal_bytes: 0,
+            values: None,
+            beatree_pages: 0,
+            metas_written: 0,
+        };
+        self.drive(shared, &beatree, pending)
+    }
+
+    /// Resume the sync which ran out of disk space, from the phase which failed.
+    ///
+    /// Returns `None` if there is no such sync.
+    pub fn resume(
+        &mut self,
+        shared: &Shared,
+        beatree: beatree::Tree,
+    ) -> Option<anyhow::Result<WriteStats>> {
+        let pending = self.stalled.take()?;
+        Some(self.drive(shared, &beatree, *pending))
+    }
+
+    fn drive(
+        &mut self,
+        shared: &Shared,
+        beatree: &beatree::Tree,
+        mut pending: Pending,
+    ) -> anyhow::Result<WriteStats> {
+        match self.advance(shared, beatree, &mut pending) {
+            Ok(write_stats) => Ok(write_stats),
+            Err(SyncError::Stalled(source)) => {
+                // Make the changes of a checkpointed sync visible while it waits. They are then
+                // synced at once on resuming. A crash in the meantime is still recovered from the
+                // journal, if one was written.
+                if pending.journal.is_some() || !pending.changes.as_slice().is_empty() {
+                    if let Some(journal) = pending.journal.take() {
+                        beatree.stage(journal.changes);
+                    }
+                    beatree.stage(pending.changes.by_ref());
+                    pending.staged = true;
+                    pending.unchunked = true;
+                }
+                let phase = pending.phase;
+                self.stalled = Some(Box::new(pending));
+                Err(OutOfSpace { phase, source }.into())
+            }
+            Err(SyncError::Fatal(e)) => Err(e),
+        }
+    }
+
+    fn advance(
+        &mut self,
+        shared: &Shared,
+        beatree: &beatree::Tree,
+        pending: &mut Pending,
+    ) -> Result<WriteStats, SyncError> {
+        let metrics = shared.io_pool.metrics();
+        if pending.num_chunks > 1 {
+            // The journal relies on the WAL, so the WAL must be durable first.
+            if pending.wal_bytes.is_none() {
+                pending.phase = SyncPhase::Wal;
+                let _timer = record_stage(metrics, &self.timings, SyncStage::Wal);
+                pending.wal_bytes = Some(write_wal(pending, self.simulated(SyncPhase::Wal))?);
+            }
+
+            if let Some(journal) = pending.journal.take() {
+                pending.phase = SyncPhase::Journal;
+                let journal_timer = record_stage(metrics, &self.timings, SyncStage::Journal);
+                let written = match self.simulated(SyncPhase::Journal) {
+                    Some(e) => Err(e),
+                    None => journal.write(&shared.db_dir_path),
+                };
+                drop(journal_timer);
+                match written {
+                    Ok(bytes) => pending.journal_bytes = bytes,
+                    Err(e) => {
+                        pending.journal = Some(journal);
+                        return Err(stall_if_out_of_space(e));
+                    }
+                }
+                pending.changes = journal.changes.into_iter();
+            }
+
+            let page_pool = shared.io_pool.page_pool();
+            let last_checkpoint = if pending.unchunked {
+                self.checkpoint
+            } else {
+                pending.num_chunks - 1
+            };
+            for checkpoint in self.checkpoint + 1..=last_checkpoint {
+                pending.phase = SyncPhase::Values;
+                let values_timer = record_stage(metrics, &self.timings, SyncStage::Values);
+                let beatree_sync = begin_values(pending, beatree, pending.chunk_size);
+                let (mut beatree_sync, sync_data) =
+                    wait_values(pending, beatree_sync, self.simulated(SyncPhase::Values))?;
+                drop(values_timer);
+
+                let meta = self.meta(
+                    self.sync_seqn,
+                    checkpoint,
+                    &sync_data,
+                    pending.rollback_live,
+                );
+                {
+                    let _timer = record_stage(metrics, &self.timings, SyncStage::Meta);
+                    Meta::write(page_pool, &shared.meta_fd, &meta)?;
+                }
+                pending.metas_written += 1;
+                beatree_sync.post_meta();
+                pending.beatree_pages += sync_data.pages_written;
+                self.checkpoint = checkpoint;
+
+                if let Some(PanicOnSyncMode::PostCheckpoint) = self.panic_on_sync {
+                    panic!("panic_on_sync is true (post-checkpoint)");
+                }
+            }
+        }
+
+        if pending.values.is_none() || pending.wal_bytes.is_none() {
+            // The final chunk is written to the beatree while the WAL is written.
+            let values_timer = pending
+                .values
+                .is_none()
+                .then(|| record_stage(metrics, &self.timings, SyncStage::Values));
+            let beatree_sync = pending
+                .values
+                .is_none()
+                .then(|| begin_values(pending, beatree, usize::MAX));
+            let wal = match pending.wal_bytes {
+                Some(wal_bytes) => Ok(wal_bytes),
+                None => {
+                    let _timer = record_stage(metrics, &self.timings, SyncStage::Wal);
+                    write_wal(pending, self.simulated(SyncPhase::Wal))
+                }
+            };
+            let simulated = self.simulated(SyncPhase::Values);
+            let values = match beatree_sync.map(|sync| wait_values(pending, sync, simulated)) {
+                Some(Ok(values)) => {
+                    pending.values = Some(values);
+                    Ok(())
+                }
+                Some(Err(e)) => Err(e),
+                None => Ok(()),
+            };
+            drop(values_timer);
+            if let Ok(wal_bytes) = wal {
+                pending.wal_bytes = Some(wal_bytes);
+            }
+
+            match (wal, values) {
+                (Err(e @ SyncError::Fatal(_)), _) | (_, Err(e @ SyncError::Fatal(_))) => {
+                    return Err(e)
+                }
+                (Err(e), _) => {
+                    pending.phase = SyncPhase::Wal;
+                    return Err(e);
+                }
+                (_, Err(e)) => {
+                    pending.phase = SyncPhase::Values;
+                    return Err(e);
+                }
+                _ => {}
+            }
+        }
+
+        // UNWRAP: both are set above.
+        let wal_bytes = pending.wal_bytes.unwrap();
+        let (mut beatree_sync, beatree_meta_wd) = pending.values.take().unwrap();
+        pending.beatree_pages += beatree_meta_wd.pages_written;
 
         if let Some(PanicOnSyncMode::PostWal) = self.panic_on_sync {
             panic!("panic_on_sync is true (post-wal)")
         }
 
-        let new_meta = Meta {
+        let sync_seqn = pending.sync_seqn;
+        let new_meta = self.meta(sync_seqn, 0, &beatree_meta_wd, pending.rollback_live);
+        {
+            let _timer = record_stage(metrics, &self.timings, SyncStage::Meta);
+            Meta::write(shared.io_pool.page_pool(), &shared.meta_fd, &new_meta)?;
+        }
+        self.sync_seqn = sync_seqn;
+        self.checkpoint = 0;
+
+        if let Some(PanicOnSyncMode::PostMeta) = self.panic_on_sync {
+            panic!("panic_on_sync is true (post-meta)");
+        }
+
+        let post_meta_timer = record_stage(metrics, &self.timings, SyncStage::PostMeta);
+        if let Some(ref mut rollback) = pending.rollback_sync {
+            rollback.post_meta();
+        }
+
+        let ht_pages = pending
+            .bitbox_sync
+            .post_meta(shared.io_pool.make_background_handle())?;
+        beatree_sync.post_meta();
+
+        if let Some(ref rollback) = pending.rollback_sync {
+            rollback.wait_post_meta()?;
+        }
+        drop(post_meta_timer);
+
+        if pending.num_chunks > 1 {
+            Journal::remove(&shared.db_dir_path)?;
+        }
+
+        Ok(WriteStats {
+            commits: 1,
+            logical_bytes: 0,
+            wal_bytes: wal_bytes as u64,
+            journal_bytes: pending.journal_bytes as u64,
+            ht_bytes: (ht_pages * PAGE_SIZE) as u64,
+            beatree_bytes: (pending.beatree_pages * PAGE_SIZE) as u64,
+            rollback_bytes: pending
+                .rollback_sync
+                .as_ref()
+                .map_or(0, |r| r.appended_bytes()),
+            meta_bytes: ((pending.metas_written + 1) * PAGE_SIZE) as u64,
+        })
+    }
+
+    // The error to fail the given phase with, if running out of space is being simulated.
+    fn simulated(&self, phase: SyncPhase) -> Option<std::io::Error> {
+        match self.simulate_out_of_space {
+            Some((p, ref trigger)) if p == phase && trigger.load(Ordering::Relaxed) => {
+                Some(std::io::Error::from_raw_os_error(libc::ENOSPC))
+            }
+            _ => None,
+        }
+    }
+
+    fn meta(
+        &self,
+        sync_seqn: u32,
+        checkpoint: u32,
+        beatree_meta_wd: &beatree::SyncData,
+        (rollback_start_live, rollback_end_live): (u64, u64),
+    ) -> Meta {
+        Meta {
             magic: meta::MAGIC,
-            version: meta::VERSION,
+            version: self.version,
             ln_freelist_pn: beatree_meta_wd.ln_freelist_pn,
             ln_bump: beatree_meta_wd.ln_bump,
             bbn_freelist_pn: beatree_meta_wd.bbn_freelist_pn,
@@ -70,24 +551,97 @@ impl Sync {
             bitbox_seed: self.bitbox_seed,
             rollback_start_live,
             rollback_end_live,
-        };
-        Meta::write(&shared.io_pool.page_pool(), &shared.meta_fd, &new_meta)?;
-        self.sync_seqn += 1;
-
-        if let Some(PanicOnSyncMode::PostMeta) = self.panic_on_sync {
-            panic!("panic_on_sync is true (post-meta)");
+            lineage: self.lineage,
+            checkpoint,
         }
+    }
+}
 
-        if let Some(ref mut rollback) = rollback_sync {
-            rollback.post_meta();
+// Time a stage of the sync in the metrics and the timings, and record it as a span, until
+// dropped.
+fn record_stage<'a>(
+    metrics: &'a Metrics,
+    timings: &'a StageTimings,
+    stage: SyncStage,
+) -> (Option<impl Drop + 'a>, Trace, StageTimer<'a>) {
+    (
+        metrics.record(Metric::SyncTime(stage)),
+        Trace::sync_stage(stage),
+        StageTimer {
+            timing: timings.stage(stage),
+            start: Instant::now(),
+        },
+    )
+}
+
+fn stall_if_out_of_space(e: std::io::Error) -> SyncError {
+    if is_out_of_space(&e) {
+        SyncError::Stalled(e)
+    } else {
+        SyncError::Fatal(e.into())
+    }
+}
+
+// Wait for the WAL to be written, or write it again if it failed before. A `simulated` error
+// replaces the outcome.
+fn write_wal(pending: &mut Pending, simulated: Option<std::io::Error>) -> Result<usize, SyncError> {
+    let result = if pending.wal_failed {
+        pending
+            .bitbox_sync
+            .rewrite_wal()
+            .map_err(anyhow::Error::from)
+    } else {
+        pending.bitbox_sync.wait_pre_meta()
+    };
+    match (result, simulated) {
+        (Ok(wal_bytes), None) => Ok(wal_bytes),
+        (Ok(_), Some(e)) => {
+            pending.wal_failed = true;
+            Err(stall_if_out_of_space(e))
+        }
+        (Err(e), _) => {
+            pending.wal_failed = true;
+            match e.downcast::<std::io::Error>() {
+                Ok(e) => Err(stall_if_out_of_space(e)),
+                Err(e) => Err(SyncError::Fatal(e)),
+            }
         }
+    }
+}
 
-        bitbox_sync.post_meta(shared.io_pool.make_handle())?;
-        beatree_sync.post_meta();
+// Begin a beatree sync of the next `n` value changes, or of those returned to the staging by an
+// aborted sync.
+fn begin_values(
+    pending: &mut Pending,
+    beatree: &beatree::Tree,
+    n: usize,
+) -> beatree::SyncController {
+    let changes = if std::mem::take(&mut pending.staged) {
+        Vec::new()
+    } else {
+        pending.changes.by_ref().take(n).collect()
+    };
+    let mut beatree_sync = beatree.sync();
+    beatree_sync.begin_sync(changes);
+    beatree_sync
+}
 
-        if let Some(ref rollback) = rollback_sync {
-            rollback.wait_post_meta()?;
+// Wait for a beatree sync to be ready for the meta. If it runs out of space, it is aborted so
+// that it can be attempted again. A `simulated` error replaces the outcome.
+fn wait_values(
+    pending: &mut Pending,
+    mut beatree_sync: beatree::SyncController,
+    simulated: Option<std::io::Error>,
+) -> Result<(beatree::SyncController, beatree::SyncData), SyncError> {
+    let result = beatree_sync
+        .wait_pre_meta()
+        .and_then(|sync_data| simulated.map_or(Ok(sync_data), Err));
+    match result {
+        Ok(sync_data) => Ok((beatree_sync, sync_data)),
+        Err(e) if is_out_of_space(&e) && beatree_sync.abort().is_ok() => {
+            pending.staged = true;
+            Err(SyncError::Stalled(e))
         }
-        Ok(())
+        Err(e) => Err(e.into()),
     }
 }
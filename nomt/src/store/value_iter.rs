@@ -0,0 +1,146 @@
+//! A blocking iterator over the key-value pairs stored in the beatree.
+
+use crate::{
+    beatree::{self, iterator::IterOutput},
+    io::IoHandle,
+    LocalValue, ValueNotStored,
+};
+use nomt_core::trie::{KeyPath, ValueHash};
+
+/// A value as stored in a leaf node.
+pub enum StoredValue {
+    /// A value stored in the leaf node itself.
+    Inline(Vec<u8>),
+    /// A value stored in overflow pages, of which the leaf node holds the hash.
+    Overflow(ValueHash),
+    /// A value stored elsewhere, of which only the hash is kept. See
+    /// [`crate::Session::write_value_hash`].
+    HashOnly(ValueHash),
+}
+
+/// An iterator over all key-value pairs within a range, in ascending key order.
+///
+/// Keys are compared as byte strings, which is the same as comparing them as bit paths from the
+/// most significant bit. The order is therefore the order of the leaves in the trie.
+///
+/// All necessary leaf and overflow page fetches are performed with blocking I/O.
+///
+/// Values of which only the hash is kept are yielded as [`ValueNotStored`] errors, after which
+/// iteration continues with the next key. [`ValueIter::next_local`] yields them as hashes instead.
+///
+/// The iterator holds a read transaction, so it blocks the next sync from starting until it is
+/// dropped. Keep it short-lived.
+pub struct ValueIter {
+    read_tx: beatree::ReadTransaction,
+    inner: beatree::BeatreeIterator,
+    io_handle: IoHandle,
+}
+
+impl ValueIter {
+    pub(super) fn new(
+        read_tx: beatree::ReadTransaction,
+        io_handle: IoHandle,
+        start: KeyPath,
+        end: Option<KeyPath>,
+    ) -> Self {
+        let inner = read_tx.iterator(start, end);
+        ValueIter {
+            read_tx,
+            inner,
+            io_handle,
+        }
+    }
+
+    /// Like [`Iterator::next`], but without loading the values stored in overflow pages.
+    pub fn next_stored(&mut self) -> Option<anyhow::Result<(KeyPath, StoredValue)>> {
+        self.next_stored_with_pages()
+            .map(|item| item.map(|(key, value, _)| (key, value)))
+    }
+
+    /// Like [`Iterator::next`], but yielding the hashes of values stored elsewhere instead of
+    /// failing.
+    pub fn next_local(&mut self) -> Option<anyhow::Result<(KeyPath, LocalValue)>> {
+        loop {
+            match self.next_stored()? {
+                Ok((key, StoredValue::Inline(value))) => {
+                    return Some(Ok((key, LocalValue::Stored(value))))
+                }
+                Ok((key, StoredValue::HashOnly(value_hash))) => {
+                    return Some(Ok((key, LocalValue::HashOnly(value_hash))))
+                }
+                Ok((key, StoredValue::Overflow(_))) => {
+                    // The overflow cell only carries metadata, so load the whole value.
+                    match self.read_tx.lookup_blocking(key) {
+                        Ok(Some(value)) => return Some(Ok((key, LocalValue::Stored(value)))),
+                        Ok(None) => continue,
+                        Err(e) => return Some(Err(e.into())),
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    /// Like [`ValueIter::next_stored`], along with the number of overflow pages holding the
+    /// value. Zero for values stored inline or elsewhere.
+    pub(crate) fn next_stored_with_pages(
+        &mut self,
+    ) -> Option<anyhow::Result<(KeyPath, StoredValue, usize)>> {
+        loop {
+            match self.inner.next() {
+                None => return None,
+                Some(IterOutput::Blocked) => {
+                    // UNWRAP: when blocked, needed leaf always exists.
+                    let page_number = self.inner.needed_leaves().next().unwrap();
+                    let leaf = match self
+                        .read_tx
+                        .load_leaf_async(page_number, &self.io_handle, 0)
+                    {
+                        Ok(leaf) => leaf,
+                        Err(leaf_load) => {
+                            let complete_io = match self.io_handle.recv() {
+                                Ok(complete_io) => complete_io,
+                                Err(e) => return Some(Err(e.into())),
+                            };
+                            if let Err(e) = complete_io.result {
+                                return Some(Err(e.into()));
+                            }
+
+                            // UNWRAP: the I/O command submitted by `load_leaf_async` is always a
+                            // `Read`.
+                            leaf_load.finish(complete_io.command.kind.unwrap_buf())
+                        }
+                    };
+
+                    self.inner.provide_leaf(leaf);
+                }
+                Some(IterOutput::Item(key, value)) => {
+                    return Some(Ok((key, StoredValue::Inline(value.to_vec()), 0)))
+                }
+                Some(IterOutput::OverflowItem(key, value_hash, cell)) => {
+                    // Only values stored elsewhere have no pages.
+                    let pages = beatree::overflow_cell_pages(cell);
+                    let value = match pages {
+                        0 => StoredValue::HashOnly(value_hash),
+                        _ => StoredValue::Overflow(value_hash),
+                    };
+                    return Some(Ok((key, value, pages)));
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for ValueIter {
+    type Item = anyhow::Result<(KeyPath, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(match self.next_local()? {
+            Ok((key, LocalValue::Stored(value))) => Ok((key, value)),
+            Ok((key, LocalValue::HashOnly(value_hash))) => {
+                Err(ValueNotStored { key, value_hash }.into())
+            }
+            Err(e) => Err(e),
+        })
+    }
+}
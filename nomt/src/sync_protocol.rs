@@ -0,0 +1,268 @@
+//! Incremental state sync: transferring the whole state in verifiable chunks.
+//!
+//! A node serving the state splits it into [`Chunk`]s with a [`ChunkExporter`]. A chunk holds the
+//! values in a range of keys and a [`RangeProof`] that they are all the values in the range, so
+//! it can be checked against the root alone. Chunks chain onto one another: the first starts at
+//! the smallest key, every other starts where the previous one ends, and the last reaches the end
+//! of the key space.
+//!
+//! A syncing node obtains the root from a source it trusts, e.g. a finalized block header, and
+//! feeds the chunks in order to a [`ChunkImporter`], which verifies each one against the root and
+//! writes its values to an empty database. Once the last chunk is imported, the database has the
+//! target root. Chunks may come from different peers: a chunk failing verification is rejected
+//! without changing anything, and can be fetched again from elsewhere.
+//!
+//! Chunks can be serialized with borsh when the `borsh` feature is enabled.
+
+use crate::{HashAlgorithm, KeyReadWrite, LocalValue, Nomt, Root, SessionParams};
+use nomt_core::{
+    proof::{self, RangeProof, RangeProofVerificationError},
+    trie::KeyPath,
+};
+
+const FIRST_KEY: KeyPath = [0; 32];
+
+/// The values within a range of keys and a proof that they are all of them, as transferred by the
+/// sync protocol.
+///
+/// Produced by a [`ChunkExporter`] and consumed by a [`ChunkImporter`].
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct Chunk {
+    /// The root the chunk is proven against.
+    pub root: Root,
+    /// The inclusive start of the range.
+    pub start: KeyPath,
+    /// The exclusive end of the range, where the next chunk starts. `None` for the last chunk.
+    pub end: Option<KeyPath>,
+    /// All key-value pairs in the range, in ascending key order. Values of which only the hash is
+    /// kept are given by their hash, and imported as such.
+    pub values: Vec<(KeyPath, LocalValue)>,
+    /// The proof of the range.
+    pub proof: RangeProof,
+}
+
+/// The reason a chunk failed verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkVerificationError {
+    /// The chunk is of a different root than expected.
+    RootMismatch,
+    /// The chunk does not start where the previous one ended, or ends where it starts.
+    OutOfOrder,
+    /// The proof is not a valid proof of the range against the root.
+    InvalidProof(RangeProofVerificationError),
+    /// The values do not match the leaves of the proof.
+    ValueMismatch,
+}
+
+impl std::fmt::Display for ChunkVerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RootMismatch => write!(f, "chunk is of an unexpected root"),
+            Self::OutOfOrder => write!(f, "chunk is out of order"),
+            Self::InvalidProof(e) => write!(f, "chunk proof is invalid: {:?}", e),
+            Self::ValueMismatch => write!(f, "chunk values do not match the proof"),
+        }
+    }
+}
+
+impl std::error::Error for ChunkVerificationError {}
+
+impl Chunk {
+    /// Verify the chunk against `expected_root`, which must come from a source trusted
+    /// independently of the chunk, and check that it starts at `expected_start`.
+    pub fn verify<H: HashAlgorithm>(
+        &self,
+        expected_root: Root,
+        expected_start: KeyPath,
+    ) -> Result<(), ChunkVerificationError> {
+        if self.root != expected_root {
+            return Err(ChunkVerificationError::RootMismatch);
+        }
+        if self.start != expected_start || self.end.is_some_and(|end| end <= self.start) {
+            return Err(ChunkVerificationError::OutOfOrder);
+        }
+        let leaves = proof::verify_range::<H>(
+            &self.proof,
+            &self.start,
+            self.end.as_ref(),
+            self.root.into_inner(),
+        )
+        .map_err(ChunkVerificationError::InvalidProof)?;
+
+        if leaves.len() != self.values.len() {
+            return Err(ChunkVerificationError::ValueMismatch);
+        }
+        for (leaf, (key, value)) in leaves.iter().zip(&self.values) {
+            if leaf.key_path != *key || leaf.value_hash != value.value_hash::<H>() {
+                return Err(ChunkVerificationError::ValueMismatch);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits the state as of a root into chunks, holding at most a given number of values each.
+///
+/// This is an iterator of chunks in order. Every chunk is proven against the root the export
+/// began with, so the export fails if the database is committed to in the meantime.
+pub struct ChunkExporter<'a, T: HashAlgorithm> {
+    nomt: &'a Nomt<T>,
+    root: Root,
+    next: Option<KeyPath>,
+    max_values: usize,
+}
+
+impl<'a, T: HashAlgorithm> ChunkExporter<'a, T> {
+    /// Export the current state of the database, starting with the first chunk.
+    ///
+    /// Panics if `max_values` is zero.
+    pub fn new(nomt: &'a Nomt<T>, max_values: usize) -> Self {
+        Self::resume(nomt, nomt.root(), FIRST_KEY, max_values)
+    }
+
+    /// Continue an export of the state as of `root`, starting with the chunk at `start`.
+    ///
+    /// This lets a node serve the chunk requested by a peer without keeping an exporter around
+    /// for every sync. Panics if `max_values` is zero.
+    pub fn resume(nomt: &'a Nomt<T>, root: Root, start: KeyPath, max_values: usize) -> Self {
+        assert!(max_values > 0, "chunks must hold at least one value");
+        ChunkExporter {
+            nomt,
+            root,
+            next: Some(start),
+            max_values,
+        }
+    }
+
+    /// The root the chunks are proven against.
+    pub fn root(&self) -> Root {
+        self.root
+    }
+}
+
+impl<T: HashAlgorithm> Iterator for ChunkExporter<'_, T> {
+    type Item = anyhow::Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.next.take()?;
+        let range = match self.nomt.prove_range(start, None, self.max_values) {
+            Ok(range) => range,
+            Err(e) => return Some(Err(e)),
+        };
+        if range.root != self.root {
+            return Some(Err(anyhow::anyhow!(
+                "sync protocol: the database no longer has root {}",
+                self.root
+            )));
+        }
+        self.next = range.end;
+        Some(Ok(Chunk {
+            root: range.root,
+            start,
+            end: range.end,
+            values: range.values,
+            proof: range.proof,
+        }))
+    }
+}
+
+/// Rebuilds the state as of a target root in an empty database from chunks.
+///
+/// Each chunk is committed on its own, so the database holds a prefix of the target state while
+/// the import is under way. Nothing else may commit to the database until the import is
+/// finished.
+pub struct ChunkImporter<'a, T: HashAlgorithm> {
+    nomt: &'a Nomt<T>,
+    target: Root,
+    next: Option<KeyPath>,
+    imported_values: u64,
+}
+
+impl<'a, T: HashAlgorithm> ChunkImporter<'a, T> {
+    /// Begin importing the state as of `target`, which must come from a trusted source.
+    ///
+    /// Fails if the database is not empty.
+    pub fn new(nomt: &'a Nomt<T>, target: Root) -> anyhow::Result<Self> {
+        if !nomt.root().is_empty() {
+            anyhow::bail!("sync protocol: chunks can only be imported into an empty database");
+        }
+        Ok(ChunkImporter {
+            nomt,
+            target,
+            next: Some(FIRST_KEY),
+            imported_values: 0,
+        })
+    }
+
+    /// The root being imported.
+    pub fn target(&self) -> Root {
+        self.target
+    }
+
+    /// Where the next chunk to import starts. `None` once the last chunk has been imported.
+    pub fn next_start(&self) -> Option<KeyPath> {
+        self.next
+    }
+
+    /// The number of values imported so far.
+    pub fn imported_values(&self) -> u64 {
+        self.imported_values
+    }
+
+    /// Verify the chunk and write its values to the database.
+    ///
+    /// The chunk must start where the previously imported one ended. Fails with a
+    /// [`ChunkVerificationError`] if the chunk doesn't verify, in which case nothing is written.
+    pub fn import(&mut self, chunk: &Chunk) -> anyhow::Result<()> {
+        let Some(start) = self.next else {
+            anyhow::bail!("sync protocol: the import is already complete");
+        };
+        chunk.verify::<T>(self.target, start)?;
+
+        if !chunk.values.is_empty() {
+            let mut session = self.nomt.begin_session(SessionParams::default());
+            let mut actuals = Vec::with_capacity(chunk.values.len());
+            for (key, value) in &chunk.values {
+                match value {
+                    LocalValue::Stored(value) => {
+                        actuals.push((*key, KeyReadWrite::Write(Some(value.clone()))))
+                    }
+                    LocalValue::HashOnly(value_hash) => session.write_value_hash(*key, *value_hash),
+                }
+            }
+            session
+                .finish(actuals)?
+                .commit(self.nomt)
+                .map_err(crate::Error::into_anyhow)?;
+        }
+
+        self.next = chunk.end;
+        self.imported_values += chunk.values.len() as u64;
+        Ok(())
+    }
+
+    /// Finish the import, checking that the database has the target root.
+    ///
+    /// Fails if chunks remain to be imported.
+    pub fn finish(self) -> anyhow::Result<()> {
+        if let Some(next) = self.next {
+            anyhow::bail!(
+                "sync protocol: the import is incomplete, next chunk at {:?}",
+                next
+            );
+        }
+        let root = self.nomt.root();
+        if root != self.target {
+            anyhow::bail!(
+                "sync protocol: imported root {} does not match target {}",
+                root,
+                self.target
+            );
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,10 @@
 //! Platform-specific code.
 //!
-//! At the moment we only target Linux and macOS.
+//! Linux is the primary target. Other Unix-based OSs, macOS among them, and Windows are supported
+//! for development, through the portable I/O backend.
+//!
+//! The rest of the crate goes through the definitions re-exported here for file handles,
+//! positional I/O, locking and directory syncs, instead of the ones of `std::os`.
 
 cfg_if::cfg_if! {
     if #[cfg(target_os = "linux")] {
@@ -9,5 +13,33 @@ cfg_if::cfg_if! {
     } else if #[cfg(target_os = "macos")] {
         pub mod macos;
         pub mod unix;
+    } else if #[cfg(unix)] {
+        pub mod unix;
+    } else if #[cfg(windows)] {
+        pub mod windows;
     }
 }
+
+#[cfg(unix)]
+pub use self::unix::{
+    allocated_bytes, fs_capacity, open_dir, symlink, sync_dir, try_lock_exclusive, unlock,
+};
+#[cfg(all(unix, not(all(target_os = "linux", feature = "io-uring"))))]
+pub use self::unix::{pread, pwrite};
+#[cfg(unix)]
+pub use std::os::{
+    fd::{AsRawFd, RawFd},
+    unix::fs::FileExt,
+};
+
+#[cfg(windows)]
+pub use self::windows::{
+    allocated_bytes, fs_capacity, open_dir, pread, pwrite, symlink, sync_dir, try_lock_exclusive,
+    unlock, AsRawFd, FileExt, RawFd,
+};
+
+/// Sync the directory at the given path, persisting the creation, removal and renaming of the
+/// files in it.
+pub fn sync_dir_path(path: &std::path::Path) -> std::io::Result<()> {
+    sync_dir(&open_dir(path)?)
+}
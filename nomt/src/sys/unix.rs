@@ -1,6 +1,10 @@
 //! Common Unix definitions.
 
-use std::{fs::File, os::fd::AsRawFd as _};
+use std::{
+    fs::{File, Metadata},
+    os::{fd::AsRawFd as _, unix::fs::MetadataExt as _},
+    path::Path,
+};
 
 pub fn try_lock_exclusive(file: &File) -> std::io::Result<()> {
     cvt_r(|| unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) }).map(drop)
@@ -10,6 +14,74 @@ pub fn unlock(file: &File) -> std::io::Result<()> {
     unsafe { cvt_r(|| libc::flock(file.as_raw_fd(), libc::LOCK_UN)).map(drop) }
 }
 
+/// Get the total size of the file system holding `file`, in bytes.
+pub fn fs_capacity(file: &File) -> std::io::Result<u64> {
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    cvt_r(|| unsafe { libc::fstatvfs(file.as_raw_fd(), stat.as_mut_ptr()) })?;
+    // SAFETY: `fstatvfs` succeeded and initialized `stat`.
+    let stat = unsafe { stat.assume_init() };
+    // The widths of the fields differ between platforms.
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_blocks as u64 * stat.f_frsize as u64)
+}
+
+/// Get the space allocated on disk to a file, in bytes.
+pub fn allocated_bytes(metadata: &Metadata) -> u64 {
+    // `st_blocks` is always in units of 512 bytes, regardless of the block size.
+    metadata.blocks() * 512
+}
+
+/// Open a directory, to sync it.
+pub fn open_dir(path: &Path) -> std::io::Result<File> {
+    File::open(path)
+}
+
+/// Sync a directory opened with [`open_dir`].
+pub fn sync_dir(dir: &File) -> std::io::Result<()> {
+    dir.sync_all()
+}
+
+/// Create a symbolic link at `link` pointing to the file at `original`.
+pub fn symlink(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+/// Read into `buf` from the file at `offset`, returning the number of bytes read.
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+pub fn pread(fd: std::os::fd::RawFd, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    let res = unsafe {
+        libc::pread(
+            fd,
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len() as libc::size_t,
+            offset as libc::off_t,
+        )
+    };
+    if res == -1 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(res as usize)
+    }
+}
+
+/// Write `buf` to the file at `offset`, returning the number of bytes written.
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+pub fn pwrite(fd: std::os::fd::RawFd, buf: &[u8], offset: u64) -> std::io::Result<usize> {
+    let res = unsafe {
+        libc::pwrite(
+            fd,
+            buf.as_ptr() as *const libc::c_void,
+            buf.len() as libc::size_t,
+            offset as libc::off_t,
+        )
+    };
+    if res == -1 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(res as usize)
+    }
+}
+
 pub(super) fn cvt_r<F>(mut f: F) -> std::io::Result<i32>
 where
     F: FnMut() -> i32,
@@ -0,0 +1,148 @@
+//! Windows-specific code.
+//!
+//! Windows is supported for development only. File handles stand in for file descriptors, and
+//! positional I/O is done with `seek_read` and `seek_write`, which move the cursor of the file.
+//! The crate never relies on the cursor of a file it does positional I/O on.
+
+use std::{
+    fs::{File, Metadata, OpenOptions},
+    io,
+    mem::ManuallyDrop,
+    os::windows::{
+        fs::{FileExt as _, OpenOptionsExt as _},
+        io::{AsRawHandle as _, FromRawHandle as _, RawHandle},
+    },
+    path::Path,
+};
+
+/// Required to open a handle to a directory.
+const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+
+/// Bypasses the cache of the operating system, like `O_DIRECT`. Reads and writes must be aligned
+/// to the sector size, in memory and in the file.
+pub const FILE_FLAG_NO_BUFFERING: u32 = 0x2000_0000;
+
+/// A file handle, as an integer so that it is `Send` and `Sync` like a Unix file descriptor.
+pub type RawFd = usize;
+
+/// The counterpart of `std::os::fd::AsRawFd`.
+pub trait AsRawFd {
+    fn as_raw_fd(&self) -> RawFd;
+}
+
+impl AsRawFd for File {
+    fn as_raw_fd(&self) -> RawFd {
+        self.as_raw_handle() as RawFd
+    }
+}
+
+/// The subset of `std::os::unix::fs::FileExt` used by the crate.
+pub trait FileExt {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()>;
+}
+
+impl FileExt for File {
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.seek_read(buf, offset) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "failed to fill whole buffer",
+                    ))
+                }
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn write_all_at(&self, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.seek_write(buf, offset) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ))
+                }
+                Ok(n) => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn try_lock_exclusive(file: &File) -> io::Result<()> {
+    file.try_lock().map_err(io::Error::from)
+}
+
+pub fn unlock(file: &File) -> io::Result<()> {
+    file.unlock()
+}
+
+/// Get the total size of the file system holding `file`, in bytes.
+///
+/// Not supported on Windows.
+pub fn fs_capacity(_file: &File) -> io::Result<u64> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Get the space allocated on disk to a file, in bytes.
+///
+/// Windows doesn't report it through the metadata, so this is the length of the file.
+pub fn allocated_bytes(metadata: &Metadata) -> u64 {
+    metadata.len()
+}
+
+/// Open a directory, to sync it.
+pub fn open_dir(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)
+}
+
+/// Sync a directory opened with [`open_dir`].
+///
+/// This does nothing: directories can't be flushed on Windows, and NTFS journals changes to them.
+pub fn sync_dir(_dir: &File) -> io::Result<()> {
+    Ok(())
+}
+
+/// Create a symbolic link at `link` pointing to the file at `original`.
+///
+/// This requires the privilege to create symbolic links, e.g. through the developer mode.
+pub fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(original, link)
+}
+
+/// Read into `buf` from the file at `offset`, returning the number of bytes read.
+pub fn pread(fd: RawFd, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    borrow_file(fd).seek_read(buf, offset)
+}
+
+/// Write `buf` to the file at `offset`, returning the number of bytes written.
+pub fn pwrite(fd: RawFd, buf: &[u8], offset: u64) -> io::Result<usize> {
+    borrow_file(fd).seek_write(buf, offset)
+}
+
+// The file of a handle, which is not closed when dropped.
+//
+// The handle must stay open while the file is used. As with a file descriptor on Unix, the owner
+// of the file must outlive the I/O done on the handle.
+fn borrow_file(fd: RawFd) -> ManuallyDrop<File> {
+    // SAFETY: the handle was obtained from an open file, see above.
+    ManuallyDrop::new(unsafe { File::from_raw_handle(fd as RawHandle) })
+}
@@ -0,0 +1,77 @@
+//! A reserved keyspace for metadata managed by NOMT.
+//!
+//! Metadata stored in the files of the database cannot be proven. With
+//! [`Options::reserve_system_keyspace`](crate::Options::reserve_system_keyspace), NOMT instead
+//! stores its metadata under keys starting with [`SYSTEM_KEY_PREFIX`], as values of the trie like
+//! any other, so that it can be proven to light clients under the same root.
+//!
+//! Users may read and prove system keys, but not write them. Use
+//! [`Nomt::read_system`](crate::Nomt::read_system) to read a system value, and
+//! [`Session::prove`](crate::Session::prove) with [`SystemKey::key_path`] to prove it.
+
+use crate::{KeyReadWrite, Value};
+use nomt_core::trie::KeyPath;
+
+/// The prefix of all keys in the system keyspace.
+pub const SYSTEM_KEY_PREFIX: [u8; 8] = *b"\xffNOMTSYS";
+
+/// The version of the format of the system values written by this version of NOMT.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// A key in the system keyspace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SystemKey {
+    /// The [`FORMAT_VERSION`] of the system values, as a little-endian `u32`.
+    ///
+    /// Written with the first commit of a database with a reserved system keyspace.
+    FormatVersion,
+    /// The root of the database a migration copied the values from. See
+    /// [`migrate`](crate::migration::migrate).
+    ///
+    /// Written with every batch of a migration into a database with a reserved system keyspace.
+    MigrationSource,
+}
+
+impl SystemKey {
+    /// The key path under which the value is stored.
+    pub fn key_path(self) -> KeyPath {
+        let mut key_path = [0; 32];
+        key_path[..8].copy_from_slice(&SYSTEM_KEY_PREFIX);
+        key_path[8] = match self {
+            SystemKey::FormatVersion => 0,
+            SystemKey::MigrationSource => 1,
+        };
+        key_path
+    }
+}
+
+/// Whether the key path is in the system keyspace.
+pub fn is_system_key(key_path: &KeyPath) -> bool {
+    key_path.starts_with(&SYSTEM_KEY_PREFIX)
+}
+
+// Add writes of system values to the sorted actuals of a session. A user read of a system key is
+// turned into a read followed by the write.
+pub(crate) fn add_system_writes(
+    actuals: &mut Vec<(KeyPath, KeyReadWrite)>,
+    writes: impl IntoIterator<Item = (SystemKey, Value)>,
+) {
+    for (key, value) in writes {
+        let key_path = key.key_path();
+        match actuals.binary_search_by_key(&key_path, |(k, _)| *k) {
+            Ok(i) => {
+                let read = match &actuals[i].1 {
+                    KeyReadWrite::Read(read) | KeyReadWrite::ReadThenWrite(read, _) => {
+                        Some(read.clone())
+                    }
+                    KeyReadWrite::Write(_) => None,
+                };
+                actuals[i].1 = match read {
+                    Some(read) => KeyReadWrite::ReadThenWrite(read, Some(value)),
+                    None => KeyReadWrite::Write(Some(value)),
+                };
+            }
+            Err(i) => actuals.insert(i, (key_path, KeyReadWrite::Write(Some(value)))),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Export of OpenTelemetry spans, with the `opentelemetry` feature. See
+//! `SessionParams::trace_context` for the spans recorded.
+//!
+//! Without the feature, traces are always empty and nothing is recorded.
+
+use crate::metrics::SyncStage;
+
+#[cfg(feature = "opentelemetry")]
+use opentelemetry::{
+    global,
+    trace::{Status, TraceContextExt as _, Tracer as _},
+    Context,
+};
+
+#[cfg(feature = "opentelemetry")]
+const TRACER: &str = "nomt";
+
+/// A trace context, usually with a span of ours active in it. Empty if nothing is traced.
+///
+/// The span ends once all clones of the trace are dropped.
+#[derive(Clone, Default)]
+pub(crate) struct Trace {
+    #[cfg(feature = "opentelemetry")]
+    cx: Option<Context>,
+}
+
+impl Trace {
+    /// Wrap a context provided by the user, to start spans in.
+    #[cfg(feature = "opentelemetry")]
+    pub(crate) fn new(cx: Context) -> Self {
+        Trace { cx: Some(cx) }
+    }
+
+    /// Start a span as a child of the span active in the current context, if any.
+    pub(crate) fn current(name: &'static str) -> Self {
+        #[cfg(feature = "opentelemetry")]
+        {
+            let cx = Context::current();
+            if cx.has_active_span() {
+                return Trace::new(cx).child(name);
+            }
+        }
+        let _ = name;
+        Trace::default()
+    }
+
+    /// Start the span of a sync stage. See [`Trace::current`].
+    pub(crate) fn sync_stage(stage: SyncStage) -> Self {
+        Self::current(match stage {
+            SyncStage::Wal => "nomt.sync.wal",
+            SyncStage::Journal => "nomt.sync.journal",
+            SyncStage::Values => "nomt.sync.values",
+            SyncStage::Meta => "nomt.sync.meta",
+            SyncStage::PostMeta => "nomt.sync.post_meta",
+        })
+    }
+
+    /// Start a span as a child of this trace. Empty if this trace is.
+    pub(crate) fn child(&self, name: &'static str) -> Self {
+        #[cfg(feature = "opentelemetry")]
+        if let Some(cx) = &self.cx {
+            let span = global::tracer(TRACER).start_with_context(name, cx);
+            return Trace {
+                cx: Some(cx.with_span(span)),
+            };
+        }
+        let _ = name;
+        Trace::default()
+    }
+
+    /// Make this the current context of the calling thread until the guard is dropped.
+    pub(crate) fn attach(&self) -> TraceGuard {
+        TraceGuard {
+            #[cfg(feature = "opentelemetry")]
+            _guard: self.cx.clone().map(Context::attach),
+        }
+    }
+
+    /// Set the status of the span to an error if the result is one.
+    pub(crate) fn record_result<T, E: std::fmt::Display>(&self, result: &Result<T, E>) {
+        #[cfg(feature = "opentelemetry")]
+        if let (Some(cx), Err(e)) = (&self.cx, result) {
+            cx.span().set_status(Status::error(e.to_string()));
+        }
+        let _ = result;
+    }
+}
+
+/// Keeps a trace the current context of the thread. See [`Trace::attach`].
+pub(crate) struct TraceGuard {
+    #[cfg(feature = "opentelemetry")]
+    _guard: Option<opentelemetry::ContextGuard>,
+}
@@ -0,0 +1,190 @@
+//! Statistics of the shape of the trie and of how it is stored, for sizing the hash table and the
+//! caches.
+//!
+//! [`Nomt::stats`](crate::Nomt::stats) walks the leaves in the beatree, from which the depth of
+//! every leaf follows, and the merkle pages from the root page.
+
+use crate::{
+    io::{page_pool::FatPage, PAGE_SIZE},
+    merkle::ElidedChildren,
+    store::{HashTableUtilization, Store},
+    HashAlgorithm,
+};
+use nomt_core::{
+    page::{DEPTH, NODES_PER_PAGE},
+    page_id::{ChildPageIndex, ROOT_PAGE_ID},
+    trie::{self, KeyPath, Node, TERMINATOR},
+};
+
+/// Statistics of the trie and of the pages storing it.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TrieStats {
+    /// The number of leaves, i.e. of values.
+    pub leaves: u64,
+    /// The number of leaves at each depth, indexed by depth.
+    pub depth_histogram: Vec<u64>,
+    /// The number of merkle pages stored in the hash table.
+    pub pages: u64,
+    /// The number of nodes of the trie held in the merkle pages.
+    pub page_nodes: u64,
+    /// The number of child pages not stored, because their subtrees are small enough to be
+    /// rebuilt from the leaves.
+    pub elided_pages: u64,
+    /// The number of values stored in overflow pages.
+    pub overflow_values: u64,
+    /// The number of overflow pages holding them.
+    pub overflow_pages: u64,
+    /// The space utilization of the hash table.
+    pub hash_table: HashTableUtilization,
+}
+
+impl TrieStats {
+    /// The average depth of the leaves. `None` if the trie is empty.
+    pub fn average_depth(&self) -> Option<f64> {
+        let sum = self
+            .depth_histogram
+            .iter()
+            .enumerate()
+            .map(|(depth, n)| depth as u64 * n)
+            .sum::<u64>();
+        (self.leaves != 0).then(|| sum as f64 / self.leaves as f64)
+    }
+
+    /// The depth which the given fraction of leaves do not exceed, e.g. `0.99` for the 99th
+    /// percentile. `None` if the trie is empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fraction` is not within `0.0..=1.0`.
+    pub fn depth_percentile(&self, fraction: f64) -> Option<usize> {
+        assert!((0.0..=1.0).contains(&fraction), "fraction out of range");
+        let rank = ((fraction * self.leaves as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        self.depth_histogram.iter().position(|n| {
+            seen += n;
+            seen >= rank
+        })
+    }
+
+    /// The greatest depth of a leaf. `None` if the trie is empty.
+    pub fn max_depth(&self) -> Option<usize> {
+        self.depth_histogram.iter().rposition(|n| *n != 0)
+    }
+
+    /// The fraction of the node slots of the stored merkle pages holding nodes of the trie,
+    /// between 0.0 and 1.0. `None` if no pages are stored.
+    ///
+    /// Pages at the bottom of the trie are sparse, so this is low for tries of few leaves per
+    /// bottom page.
+    pub fn page_fill_factor(&self) -> Option<f64> {
+        (self.pages != 0)
+            .then(|| self.page_nodes as f64 / (self.pages * NODES_PER_PAGE as u64) as f64)
+    }
+}
+
+/// Collect the statistics of the database. The caller keeps commits out.
+pub(crate) fn collect<H: HashAlgorithm>(store: &Store, root: Node) -> anyhow::Result<TrieStats> {
+    let mut stats = TrieStats {
+        hash_table: store.hash_table_utilization(),
+        ..TrieStats::default()
+    };
+    collect_leaves(store, &mut stats)?;
+    if trie::is_internal::<H>(&root) {
+        collect_pages::<H>(store, &mut stats)?;
+    }
+    Ok(stats)
+}
+
+/// Walk the leaves in key order. A leaf sits one below the deepest node it shares with either
+/// neighbour, or at the root if it is the only one.
+fn collect_leaves(store: &Store, stats: &mut TrieStats) -> anyhow::Result<()> {
+    let mut values = store.iter_values([0; 32], None);
+    // The previous key, and the bits it shares with the one before it.
+    let mut prev: Option<(KeyPath, usize)> = None;
+    while let Some(item) = values.next_stored_with_pages() {
+        let (key, _, pages) = item?;
+        if pages != 0 {
+            stats.overflow_values += 1;
+            stats.overflow_pages += pages as u64;
+        }
+        let shared = match prev {
+            None => 0,
+            Some((prev_key, prev_shared)) => {
+                let shared = shared_bits(&prev_key, &key);
+                record_depth(stats, prev_shared.max(shared) + 1);
+                shared
+            }
+        };
+        prev = Some((key, shared));
+    }
+    if let Some((_, shared)) = prev {
+        let depth = match stats.leaves {
+            0 => 0,
+            _ => shared + 1,
+        };
+        record_depth(stats, depth);
+    }
+    Ok(())
+}
+
+/// Walk the merkle pages from the root page, counting the nodes of the trie in them.
+fn collect_pages<H: HashAlgorithm>(store: &Store, stats: &mut TrieStats) -> anyhow::Result<()> {
+    let mut pending = vec![ROOT_PAGE_ID];
+    while let Some(page_id) = pending.pop() {
+        let Some((page, _)) = store.load_page(page_id.clone())? else {
+            continue;
+        };
+        stats.pages += 1;
+
+        let elided = ElidedChildren::from_bytes(
+            // UNWRAP: the slice is 8 bytes long.
+            page[PAGE_SIZE - 32 - 8..PAGE_SIZE - 32].try_into().unwrap(),
+        );
+        // Only the nodes below internal nodes are part of the trie.
+        let bottom_layer = NODES_PER_PAGE - (1 << DEPTH);
+        let mut nodes = vec![0, 1];
+        while let Some(node_index) = nodes.pop() {
+            let node = node(&page, node_index);
+            if node == TERMINATOR {
+                continue;
+            }
+            stats.page_nodes += 1;
+            if !trie::is_internal::<H>(&node) {
+                continue;
+            }
+            if node_index < bottom_layer {
+                nodes.extend([node_index * 2 + 2, node_index * 2 + 3]);
+                continue;
+            }
+
+            // UNWRAP: the bottom layer has 64 nodes.
+            let child_index = ChildPageIndex::new((node_index - bottom_layer) as u8).unwrap();
+            if elided.is_elided(child_index.clone()) {
+                stats.elided_pages += 1;
+            } else if let Ok(child_id) = page_id.child_page_id(child_index) {
+                pending.push(child_id);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn record_depth(stats: &mut TrieStats, depth: usize) {
+    if stats.depth_histogram.len() <= depth {
+        stats.depth_histogram.resize(depth + 1, 0);
+    }
+    stats.depth_histogram[depth] += 1;
+    stats.leaves += 1;
+}
+
+fn shared_bits(a: &KeyPath, b: &KeyPath) -> usize {
+    a.iter()
+        .zip(b)
+        .position(|(a, b)| a != b)
+        .map_or(256, |i| i * 8 + (a[i] ^ b[i]).leading_zeros() as usize)
+}
+
+fn node(page: &FatPage, index: usize) -> Node {
+    // UNWRAP: the slice is 32 bytes long.
+    page[index * 32..(index + 1) * 32].try_into().unwrap()
+}
@@ -0,0 +1,70 @@
+//! Accounting of the witness of a session. See [`crate::FinishedSession::witness_stats`].
+
+use std::collections::BTreeSet;
+
+use nomt_core::{
+    page_id::ROOT_PAGE_ID,
+    witness::{Witness, WitnessedPath},
+};
+
+/// The cost of the witness of a session, for metering gas and benchmarking block execution.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WitnessStats {
+    /// The number of merkle pages loaded from disk to update the trie. Pages found in memory are
+    /// not counted.
+    pub pages_fetched: usize,
+    /// The number of distinct merkle pages on the paths to the terminals of all keys accessed,
+    /// the root page included.
+    pub pages_touched: usize,
+    /// The number of sibling nodes in the path proofs of the witness.
+    pub siblings: usize,
+    /// The length of the witness in its stable encoding, in bytes. See [`Witness::encode_to`].
+    pub bytes: usize,
+}
+
+impl WitnessStats {
+    /// Account for the final `witness` of a session, given the pages fetched and touched by the
+    /// update of the trie.
+    pub(crate) fn new(witness: &Witness, pages_fetched: usize, pages_touched: usize) -> Self {
+        let mut counter = ByteCounter(0);
+        // UNWRAP: the counter never fails to write.
+        witness.encode_to(&mut counter).unwrap();
+        WitnessStats {
+            pages_fetched,
+            pages_touched,
+            siblings: witness
+                .path_proofs
+                .iter()
+                .map(|path| path.inner.siblings.len())
+                .sum(),
+            bytes: counter.0,
+        }
+    }
+}
+
+/// Count the distinct pages on the given paths, from the root page down to the pages of their
+/// terminals.
+pub(crate) fn pages_touched(paths: &[WitnessedPath]) -> usize {
+    let mut pages = BTreeSet::new();
+    for path in paths {
+        let mut page_id = path.path.page_id().unwrap_or(ROOT_PAGE_ID);
+        // pages shared with an earlier path have all their ancestors counted already.
+        while pages.insert(page_id.clone()) && page_id != ROOT_PAGE_ID {
+            page_id = page_id.parent_page_id();
+        }
+    }
+    pages.len()
+}
+
+struct ByteCounter(usize);
+
+impl std::io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
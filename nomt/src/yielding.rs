@@ -0,0 +1,86 @@
+//! Cooperative yielding from long CPU-bound sections.
+//!
+//! Finishing a large session keeps threads busy for a long time without blocking on I/O, which
+//! starves the other tasks of an async executor driving it, and offers no point to give up early.
+//! A hook set with [`crate::Options::yield_hook`] is called after every so many units of such
+//! work, so that embedders can yield, record progress, or cancel the session by returning
+//! [`Cancelled`].
+
+use crate::Cancelled;
+use std::sync::Arc;
+
+/// The kind of work a yield hook is called from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YieldPoint {
+    /// Updating the merkle trie, in units of keys. Called on the commit worker threads.
+    MerkleUpdate,
+    /// Sorting intermediate results, in units of items sorted.
+    Sort,
+    /// Building the witness of a session, in units of paths.
+    WitnessBuild,
+}
+
+type HookFn = dyn Fn(YieldPoint) -> Result<(), Cancelled> + Send + Sync;
+
+/// The hook given in [`crate::Options::yield_hook`].
+#[derive(Clone)]
+pub(crate) struct YieldHook {
+    pub every: usize,
+    hook: Arc<HookFn>,
+}
+
+impl std::fmt::Debug for YieldHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("YieldHook")
+            .field("every", &self.every)
+            .finish()
+    }
+}
+
+impl YieldHook {
+    pub fn new(every: usize, hook: Arc<HookFn>) -> Self {
+        YieldHook {
+            every: every.max(1),
+            hook,
+        }
+    }
+}
+
+/// Counts units of work done on one thread, calling the hook whenever enough have accumulated.
+pub(crate) struct Yielder {
+    hook: Option<YieldHook>,
+    point: YieldPoint,
+    units: usize,
+}
+
+impl Yielder {
+    pub fn new(hook: Option<YieldHook>, point: YieldPoint) -> Self {
+        Yielder {
+            hook,
+            point,
+            units: 0,
+        }
+    }
+
+    /// Record `units` of work done.
+    pub fn tick(&mut self, units: usize) -> Result<(), Cancelled> {
+        let Some(hook) = &self.hook else {
+            return Ok(());
+        };
+        self.units += units;
+        if self.units < hook.every {
+            return Ok(());
+        }
+        self.units %= hook.every;
+        (hook.hook)(self.point)
+    }
+}
+
+/// Convert an I/O error into an error which downcasts to [`Cancelled`] if it carries one.
+pub(crate) fn io_error_into_anyhow(e: std::io::Error) -> anyhow::Error {
+    if e.get_ref().is_some_and(|inner| inner.is::<Cancelled>()) {
+        anyhow::Error::new(Cancelled)
+    } else {
+        e.into()
+    }
+}
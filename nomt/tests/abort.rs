@@ -0,0 +1,107 @@
+mod common;
+
+use common::account_path;
+use nomt::{hasher::Blake3Hasher, KeyReadWrite, Nomt, SessionParams};
+use std::{collections::BTreeMap, path::PathBuf};
+
+fn open(name: &str) -> (Nomt<Blake3Hasher>, PathBuf) {
+    let nomt = common::setup_nomt_with(name, |o| {
+        o.bitbox_seed([0; 16]);
+        o.hashtable_buckets(64_000);
+        o.rollback(true);
+        o.io_workers(1);
+    });
+    (nomt, common::test_path(name))
+}
+
+fn file_sizes(path: &PathBuf) -> BTreeMap<String, u64> {
+    std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.file_type().unwrap().is_file())
+        .map(|entry| {
+            let name = entry.file_name().into_string().unwrap();
+            (name, entry.metadata().unwrap().len())
+        })
+        .collect()
+}
+
+fn commit_accounts(nomt: &Nomt<Blake3Hasher>, ids: std::ops::Range<u64>, balance: u64) {
+    let session = nomt.begin_session(SessionParams::default());
+    let mut actuals = ids
+        .map(|id| {
+            let value = balance.to_le_bytes().to_vec();
+            (account_path(id), KeyReadWrite::Write(Some(value)))
+        })
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    for (key, _) in &actuals {
+        session.warm_up(*key);
+        session.preserve_prior_value(*key);
+    }
+    session.finish(actuals).unwrap().commit(nomt).unwrap();
+}
+
+// Start a session touching many keys, then abandon it either explicitly or by dropping it.
+fn huge_session(nomt: &Nomt<Blake3Hasher>, explicit_abort: bool) {
+    let session = nomt.begin_session(SessionParams::default());
+    for id in 0..50_000 {
+        let key = account_path(id);
+        session.warm_up(key);
+        session.preserve_prior_value(key);
+        if id % 1000 == 0 {
+            let _ = session.read(key).unwrap();
+        }
+    }
+
+    if explicit_abort {
+        session.abort();
+    } else {
+        drop(session);
+    }
+}
+
+fn check_abort_leaves_no_trace(name: &str, explicit_abort: bool) {
+    let (nomt, path) = open(name);
+    commit_accounts(&nomt, 0..10_000, 1);
+
+    let root = nomt.root();
+    let sync_seqn = nomt.sync_seqn();
+    let sizes = file_sizes(&path);
+
+    for _ in 0..5 {
+        huge_session(&nomt, explicit_abort);
+    }
+
+    assert_eq!(nomt.root(), root);
+    assert_eq!(nomt.sync_seqn(), sync_seqn);
+    assert_eq!(file_sizes(&path), sizes);
+
+    // All resources of the aborted sessions are released, so a commit can proceed right away
+    // and sees nothing of them.
+    commit_accounts(&nomt, 0..10, 2);
+    assert_eq!(nomt.sync_seqn(), sync_seqn + 1);
+    assert_eq!(
+        nomt.read(account_path(5)).unwrap(),
+        Some(2u64.to_le_bytes().to_vec())
+    );
+    assert_eq!(
+        nomt.read(account_path(20)).unwrap(),
+        Some(1u64.to_le_bytes().to_vec())
+    );
+    assert_eq!(nomt.read(account_path(20_000)).unwrap(), None);
+
+    // Rolling back the last commit goes back to the root before the aborted sessions.
+    nomt.rollback(1).unwrap();
+    assert_eq!(nomt.root(), root);
+}
+
+#[test]
+fn abort_leaves_no_trace() {
+    check_abort_leaves_no_trace("abort_explicit", true);
+}
+
+#[test]
+fn drop_leaves_no_trace() {
+    check_abort_leaves_no_trace("abort_drop", false);
+}
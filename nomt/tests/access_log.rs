@@ -0,0 +1,112 @@
+mod common;
+
+use common::{clean_test_path, open_nomt, setup_nomt, setup_nomt_with, test_path};
+use nomt::{
+    access_log::{self, Pacing},
+    hasher::Blake3Hasher,
+    KeyReadWrite, Nomt, SessionParams,
+};
+use std::io::BufReader;
+
+fn key(i: u32) -> [u8; 32] {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, writes: impl IntoIterator<Item = (u32, Option<Vec<u8>>)>) {
+    let mut actuals = writes
+        .into_iter()
+        .map(|(i, value)| (key(i), KeyReadWrite::Write(value)))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+}
+
+#[test]
+fn capture_and_replay() {
+    let path = test_path("access_log_source");
+    let copy = clean_test_path("access_log_copy");
+    let log = test_path("access_log.log");
+
+    let nomt = setup_nomt("access_log_source");
+    commit(&nomt, (0..100).map(|i| (i, Some(vec![1; 100]))));
+    drop(nomt);
+    std::fs::create_dir_all(&copy).unwrap();
+    for entry in std::fs::read_dir(&path).unwrap() {
+        let entry = entry.unwrap();
+        std::fs::copy(entry.path(), copy.join(entry.file_name())).unwrap();
+    }
+
+    // Capture a workload.
+    let nomt = open_nomt("access_log_source", |o| o.access_log(&log, 1.0));
+    assert!(nomt.get(key(1)).unwrap().is_some());
+    let session = nomt.begin_session(SessionParams::default());
+    session.read(key(2)).unwrap();
+    session.prove(key(3)).unwrap();
+    let mut actuals = vec![
+        (key(4), KeyReadWrite::Write(None)),
+        (key(200), KeyReadWrite::Write(Some(vec![7; 3000]))),
+    ];
+    actuals.sort_by_key(|(k, _)| *k);
+    session.finish(actuals).unwrap().commit(&nomt).unwrap();
+    drop(nomt);
+
+    let text = std::fs::read_to_string(&log).unwrap();
+    let lines = text.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "nomt-access-log 1");
+    let kinds = lines[1..]
+        .iter()
+        .map(|line| line.split(' ').nth(1).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        ["read", "begin", "read", "prove", "write", "write", "commit"]
+    );
+    let write_200 = format!(
+        "write 1 {} 3000",
+        key(200)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>()
+    );
+    assert!(lines.iter().any(|line| line.ends_with(&write_200)));
+    assert!(!text.contains(&"07".repeat(32)));
+
+    // Replay it against the copy.
+    let replica = open_nomt("access_log_copy", |_| {});
+    let file = std::fs::File::open(&log).unwrap();
+    let stats = access_log::replay(&replica, BufReader::new(file), Pacing::Recorded).unwrap();
+    assert_eq!(stats.reads, 2);
+    assert_eq!(stats.proofs, 1);
+    assert_eq!(stats.commits, 1);
+    assert!(stats.max_read_time <= stats.read_time);
+    assert_eq!(replica.get(key(4)).unwrap(), None);
+    assert_eq!(replica.get(key(200)).unwrap().unwrap().len(), 3000);
+    assert_eq!(replica.get(key(5)).unwrap(), Some(vec![1; 100]));
+}
+
+#[test]
+fn sampling_and_malformed_logs() {
+    let log = test_path("access_log_sampled.log");
+    let nomt = setup_nomt_with("access_log_sampled", |o| o.access_log(&log, 0.0));
+    commit(&nomt, (0..10).map(|i| (i, Some(vec![1; 10]))));
+    nomt.get(key(1)).unwrap();
+    drop(nomt);
+    assert_eq!(
+        std::fs::read_to_string(&log).unwrap(),
+        "nomt-access-log 1\n"
+    );
+
+    let nomt = open_nomt("access_log_sampled", |_| {});
+    for bad in [
+        "not a log\n",
+        "nomt-access-log 1\n5 read\n",
+        "nomt-access-log 1\n5 read - 00\n",
+        "nomt-access-log 1\n5 fetch 1\n",
+    ] {
+        assert!(access_log::replay(&nomt, bad.as_bytes(), Pacing::Unpaced).is_err());
+    }
+}
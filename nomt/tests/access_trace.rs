@@ -0,0 +1,90 @@
+mod common;
+
+use common::setup_nomt_with;
+use nomt::{trie::KeyPath, AccessTraceMode, KeyReadWrite, SessionParams};
+
+fn key(i: u32) -> KeyPath {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+#[test]
+fn access_list_without_witness() {
+    let nomt = setup_nomt_with("access_list_without_witness", |o| o.commit_concurrency(2));
+    let mut actuals = (0..1000)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(vec![1; 8]))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    let mut finished = nomt
+        .begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap();
+    assert!(finished.take_access_list().is_none());
+    finished.commit(&nomt).unwrap();
+
+    let session =
+        nomt.begin_session(SessionParams::default().access_trace(AccessTraceMode::keys()));
+    // Reads of keys which do not end up in the actuals are traced too.
+    session.read(key(1)).unwrap();
+    session.read(key(5000)).unwrap();
+    session.prove(key(2)).unwrap();
+    let mut actuals = vec![
+        (key(3), KeyReadWrite::Read(Some(vec![1; 8]))),
+        (
+            key(4),
+            KeyReadWrite::ReadThenWrite(Some(vec![1; 8]), Some(vec![2; 8])),
+        ),
+        (key(6000), KeyReadWrite::Write(Some(vec![2; 8]))),
+    ];
+    actuals.sort_by_key(|(k, _)| *k);
+    let mut finished = session.finish(actuals).unwrap();
+    assert!(finished.take_witness().is_none());
+
+    let access_list = finished.take_access_list().unwrap();
+    let mut reads = vec![key(1), key(2), key(3), key(4), key(5000)];
+    reads.sort();
+    let mut writes = vec![key(4), key(6000)];
+    writes.sort();
+    assert_eq!(access_list.reads, reads);
+    assert_eq!(access_list.writes, writes);
+    assert!(access_list.pages.is_none());
+    assert!(finished.take_access_list().is_none());
+}
+
+#[test]
+fn access_list_with_pages() {
+    let nomt = setup_nomt_with("access_list_with_pages", |o| o.commit_concurrency(2));
+    let mut actuals = (0..10_000)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(vec![1; 8]))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    let session = nomt.begin_session(SessionParams::default());
+    session.finish(actuals).unwrap().commit(&nomt).unwrap();
+
+    let session = nomt
+        .begin_session(SessionParams::default().access_trace(AccessTraceMode::keys_and_pages()));
+    let mut actuals = (0..10)
+        .map(|i| (key(i), KeyReadWrite::Read(Some(vec![1; 8]))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    let access_list = session.finish(actuals).unwrap().take_access_list().unwrap();
+
+    assert_eq!(access_list.reads.len(), 10);
+    assert!(access_list.writes.is_empty());
+    let pages = access_list.pages.unwrap();
+    // With 10,000 keys, all terminals are below the first level of pages. Each key is on a path
+    // through the root page and the page of the first level given by its first 6 bits.
+    let first_level = (0..10)
+        .map(|i| key(i)[0] >> 2)
+        .collect::<std::collections::BTreeSet<_>>();
+    assert!(pages.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(pages.iter().filter(|p| p.depth() == 0).count(), 1);
+    assert_eq!(
+        pages.iter().filter(|p| p.depth() == 1).count(),
+        first_level.len()
+    );
+    for page in &pages {
+        if page.depth() > 0 {
+            assert!(pages.contains(&page.parent_page_id()));
+        }
+    }
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use common::{account_path, setup_nomt_with};
+use nomt::{hasher::Blake3Hasher, KeyReadWrite, Nomt, SessionParams};
+
+fn open(name: &str) -> Nomt<Blake3Hasher> {
+    common::setup_nomt_with(name, |o| {
+        o.bitbox_seed([0; 16]);
+        o.hashtable_buckets(64_000);
+        o.rollback(true);
+        o.io_workers(1);
+    })
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, ids: std::ops::Range<u64>, value: Option<Vec<u8>>) {
+    let session = nomt.begin_session(SessionParams::default());
+    let mut actuals = ids
+        .map(|id| (account_path(id), KeyReadWrite::Write(value.clone())))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    session.finish(actuals).unwrap().commit(nomt).unwrap();
+}
+
+#[test]
+fn write_amplification_per_commit() {
+    let nomt = open("amplification_write");
+    assert_eq!(nomt.last_commit_write_stats(), None);
+
+    commit(&nomt, 0..1000, Some(vec![7; 8]));
+    let first = nomt.last_commit_write_stats().unwrap();
+    assert_eq!(first.commits, 1);
+    assert_eq!(first.logical_bytes, 1000 * 40);
+    assert!(first.wal_bytes > 0);
+    assert!(first.ht_bytes > 0);
+    assert!(first.beatree_bytes > 0);
+    assert!(first.rollback_bytes > 0);
+    assert_eq!(first.rollback_bytes % 4096, 0);
+    assert_eq!(first.meta_bytes, 4096);
+    assert!(first.write_amplification().unwrap() > 1.0);
+
+    // Deletions only count their keys.
+    commit(&nomt, 0..10, None);
+    let second = nomt.last_commit_write_stats().unwrap();
+    assert_eq!(second.logical_bytes, 10 * 32);
+
+    let total = nomt.total_write_stats();
+    assert_eq!(total.commits, 2);
+    assert_eq!(
+        total.logical_bytes,
+        first.logical_bytes + second.logical_bytes
+    );
+    assert_eq!(
+        total.physical_bytes(),
+        first.physical_bytes() + second.physical_bytes()
+    );
+}
+
+#[test]
+fn space_amplification() {
+    let nomt = open("amplification_space");
+    let empty = nomt.space_stats().unwrap();
+    assert_eq!(empty.values, 0);
+    assert_eq!(empty.space_amplification(), None);
+
+    commit(&nomt, 0..1000, Some(vec![7; 100]));
+    let stats = nomt.space_stats().unwrap();
+    assert_eq!(stats.values, 1000);
+    assert_eq!(stats.logical_bytes, 1000 * 132);
+    assert!(stats.beatree_bytes > 0);
+    assert!(stats.rollback_bytes > 0);
+    assert!(stats.space_amplification().unwrap() > 1.0);
+}
+
+#[test]
+fn hot_prefixes() {
+    let nomt = setup_nomt_with("amplification_hot_prefixes", |o| {
+        o.io_workers(1);
+        o.prefix_write_stats(2, 2);
+    });
+    assert!(nomt.hot_prefixes(10).is_empty());
+
+    let commit_prefixed = |prefixes: &[[u8; 2]]| {
+        let mut actuals = prefixes
+            .iter()
+            .enumerate()
+            .map(|(i, prefix)| {
+                let mut key = [i as u8; 32];
+                key[..2].copy_from_slice(prefix);
+                (key, KeyReadWrite::Write(Some(vec![1; 8])))
+            })
+            .collect::<Vec<_>>();
+        actuals.sort_by_key(|(k, _)| *k);
+        let session = nomt.begin_session(SessionParams::default());
+        session.finish(actuals).unwrap().commit(&nomt).unwrap();
+    };
+
+    commit_prefixed(&[[0xAA, 0], [0xAA, 0], [0xAA, 0], [0xBB, 0]]);
+    commit_prefixed(&[[0xBB, 0], [0xAA, 1]]);
+    let hot = nomt.hot_prefixes(2);
+    assert_eq!(hot.len(), 2);
+    assert_eq!(
+        (hot[0].prefix.as_slice(), hot[0].writes),
+        (&[0xAA, 0][..], 3)
+    );
+    assert_eq!(hot[0].logical_bytes, 3 * 40);
+    assert_eq!(
+        (hot[1].prefix.as_slice(), hot[1].writes),
+        (&[0xBB, 0][..], 2)
+    );
+
+    // Only the last two commits are counted.
+    commit_prefixed(&[[0xAA, 1]]);
+    let hot = nomt.hot_prefixes(10);
+    assert_eq!(hot.len(), 2);
+    assert_eq!(
+        (hot[0].prefix.as_slice(), hot[0].writes),
+        (&[0xAA, 1][..], 2)
+    );
+    assert_eq!(
+        (hot[1].prefix.as_slice(), hot[1].writes),
+        (&[0xBB, 0][..], 1)
+    );
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use common::{clean_test_path, setup_nomt, test_options};
+use nomt::{hasher::Blake3Hasher, KeyReadWrite, Nomt, Root, SessionParams};
+use std::sync::Mutex;
+
+const COUNTER: [u8; 32] = [0xff; 32];
+
+fn key(i: u32) -> [u8; 32] {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+// Write 100 values in the given round, and the round to the counter.
+fn commit_round(nomt: &Nomt<Blake3Hasher>, round: u32) {
+    let mut actuals = (round * 100..(round + 1) * 100)
+        .map(|i| {
+            (
+                key(i),
+                KeyReadWrite::Write(Some(vec![i as u8; 1 + i as usize % 3000])),
+            )
+        })
+        .collect::<Vec<_>>();
+    actuals.push((
+        COUNTER,
+        KeyReadWrite::Write(Some(round.to_le_bytes().to_vec())),
+    ));
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+}
+
+#[test]
+fn backup_while_committing() {
+    let backup = clean_test_path("backup_copy");
+    clean_test_path("backup_restored");
+
+    let nomt = setup_nomt("backup_source");
+    commit_round(&nomt, 0);
+    let roots = Mutex::new(vec![nomt.root()]);
+
+    let info = std::thread::scope(|s| {
+        s.spawn(|| {
+            for round in 1..40 {
+                commit_round(&nomt, round);
+                roots.lock().unwrap().push(nomt.root());
+            }
+        });
+        nomt.create_backup(&backup).unwrap()
+    });
+    assert!(info.bulk_bytes > 0);
+    drop(nomt);
+
+    let nomt = Nomt::<Blake3Hasher>::open_backup(&backup, test_options("backup_restored")).unwrap();
+    let round = u32::from_le_bytes(nomt.get(COUNTER).unwrap().unwrap().try_into().unwrap());
+    let roots: Vec<Root> = roots.into_inner().unwrap();
+    assert_eq!(nomt.root(), roots[round as usize]);
+    assert_eq!(nomt.sync_seqn(), info.sync_seqn);
+    for i in 0..(round + 1) * 100 {
+        let value = nomt.get(key(i)).unwrap();
+        assert_eq!(value, Some(vec![i as u8; 1 + i as usize % 3000]));
+    }
+    assert_eq!(nomt.get(key((round + 1) * 100)).unwrap(), None);
+}
+
+#[test]
+fn backup_needs_empty_destination() {
+    let backup = clean_test_path("backup_nonempty_copy");
+    std::fs::create_dir_all(&backup).unwrap();
+    std::fs::write(backup.join("other"), b"data").unwrap();
+
+    let nomt = setup_nomt("backup_nonempty_source");
+    assert!(nomt.create_backup(&backup).is_err());
+    assert_eq!(std::fs::read(backup.join("other")).unwrap(), b"data");
+}
+
+#[test]
+fn incomplete_backup_is_not_restored() {
+    let backup = clean_test_path("backup_incomplete_copy");
+    let restored = clean_test_path("backup_incomplete_restored");
+
+    let nomt = setup_nomt("backup_incomplete_source");
+    commit_round(&nomt, 0);
+    nomt.create_backup(&backup).unwrap();
+    std::fs::remove_file(backup.join("meta")).unwrap();
+
+    assert!(
+        Nomt::<Blake3Hasher>::open_backup(&backup, test_options("backup_incomplete_restored"))
+            .is_err()
+    );
+    assert!(!restored.exists());
+}
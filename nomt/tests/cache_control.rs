@@ -0,0 +1,158 @@
+mod common;
+
+use common::{open_nomt, test_path};
+use nomt::{
+    hasher::Blake3Hasher, trie::KeyPath, KeyReadWrite, Nomt, PrepopulateStatus, SessionParams,
+};
+
+fn setup_nomt(name: &str) -> Nomt<Blake3Hasher> {
+    common::setup_nomt_with(name, |o| o.metrics(true))
+}
+
+// Commit many keys, returning them in order.
+fn populate(nomt: &Nomt<Blake3Hasher>) -> Vec<KeyPath> {
+    let keys = (0..20_000u32)
+        .map(|i| *blake3::hash(&i.to_le_bytes()).as_bytes())
+        .collect::<std::collections::BTreeSet<_>>();
+    let actuals = keys
+        .iter()
+        .map(|key| (*key, KeyReadWrite::Write(Some(key.to_vec()))))
+        .collect();
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+    keys.into_iter().collect()
+}
+
+// The number of page cache misses while proving `key`.
+fn proof_misses(nomt: &Nomt<Blake3Hasher>, key: KeyPath) -> u64 {
+    let before = nomt.metrics().get_page_cache_misses();
+    nomt.begin_session(SessionParams::default())
+        .prove(key)
+        .unwrap();
+    nomt.metrics().get_page_cache_misses() - before
+}
+
+#[test]
+fn clear_and_prepopulate_caches() {
+    let nomt = setup_nomt("clear_and_prepopulate_caches");
+    let key = populate(&nomt)[0];
+
+    nomt.clear_caches(0);
+    let cold = proof_misses(&nomt, key);
+    assert!(cold > 0);
+    assert_eq!(proof_misses(&nomt, key), 0);
+
+    // Only the pages below the kept level are loaded again.
+    nomt.clear_caches(1);
+    let upper_levels = proof_misses(&nomt, key);
+    assert!(upper_levels < cold);
+
+    nomt.clear_caches(0);
+    nomt.prepopulate_page_cache(1).unwrap();
+    assert_eq!(proof_misses(&nomt, key), upper_levels);
+
+    // Reads are unaffected by the state of the caches.
+    nomt.clear_caches(0);
+    assert_eq!(nomt.read(key).unwrap(), Some(key.to_vec()));
+    assert_eq!(proof_misses(&nomt, key), cold);
+}
+
+#[test]
+fn prepopulate_in_background() {
+    let nomt = setup_nomt("prepopulate_in_background");
+    let key = populate(&nomt)[0];
+    assert!(nomt.page_cache_prepopulation().is_none());
+
+    nomt.clear_caches(0);
+    nomt.prepopulate_page_cache(1).unwrap();
+    let upper_levels = proof_misses(&nomt, key);
+
+    nomt.clear_caches(0);
+    let progress = nomt.prepopulate_page_cache_in_background(1).wait();
+    assert_eq!(progress.status, PrepopulateStatus::Finished);
+    // All 64 pages of the first level exist with this many keys.
+    assert_eq!(progress.pages_loaded, 64);
+    assert_eq!(proof_misses(&nomt, key), upper_levels);
+    assert_eq!(
+        nomt.page_cache_prepopulation().unwrap().progress(),
+        progress
+    );
+}
+
+#[test]
+fn prepopulate_on_open_is_cancellable() {
+    let name = "prepopulate_on_open_is_cancellable";
+    populate(&setup_nomt(name));
+
+    let nomt = open_nomt(name, |o| {
+        o.metrics(true);
+        o.prepopulate_page_cache(true);
+        o.prepopulate_page_cache_rate(1);
+    });
+
+    // The first batch is loaded right away, and the next is held back by the rate limit.
+    let prepopulation = nomt.page_cache_prepopulation().unwrap();
+    assert_eq!(prepopulation.progress().status, PrepopulateStatus::Running);
+    prepopulation.cancel();
+    let progress = prepopulation.wait();
+    assert_eq!(progress.status, PrepopulateStatus::Cancelled);
+    assert!(progress.pages_loaded <= 64);
+    drop(nomt);
+
+    // Without a rate limit, all pages are loaded.
+    let nomt = open_nomt(name, |o| {
+        o.metrics(true);
+        o.prepopulate_page_cache(true);
+    });
+    let progress = nomt.page_cache_prepopulation().unwrap().wait();
+    assert_eq!(progress.status, PrepopulateStatus::Finished);
+    assert_eq!(progress.pages_loaded, 64);
+}
+
+#[test]
+fn cache_file_restores_caches() {
+    let name = "cache_file_restores_caches";
+    let key = populate(&setup_nomt(name))[0];
+    let cache_file = test_path(name).join("cache");
+
+    let open = |cache_file_size| {
+        open_nomt(name, |o| {
+            o.metrics(true);
+            o.cache_file_size(cache_file_size);
+        })
+    };
+
+    let nomt = open(64);
+    let cold = proof_misses(&nomt, key);
+    assert!(cold > 0);
+    drop(nomt);
+    assert!(cache_file.exists());
+
+    // The cache file is consumed on open and the caches start warm.
+    let nomt = open(64);
+    assert!(!cache_file.exists());
+    assert_eq!(proof_misses(&nomt, key), 0);
+    assert_eq!(nomt.read(key).unwrap(), Some(key.to_vec()));
+    drop(nomt);
+
+    // A cache file written before the last commit is ignored.
+    std::fs::copy(&cache_file, test_path(name).join("stale")).unwrap();
+    let nomt = open(0);
+    nomt.begin_session(SessionParams::default())
+        .finish(vec![([0; 32], KeyReadWrite::Write(Some(vec![1])))])
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+    drop(nomt);
+    assert!(!cache_file.exists());
+    std::fs::rename(test_path(name).join("stale"), &cache_file).unwrap();
+
+    let nomt = open(64);
+    assert!(!cache_file.exists());
+    assert_eq!(proof_misses(&nomt, key), cold);
+    assert_eq!(nomt.read(key).unwrap(), Some(key.to_vec()));
+    assert_eq!(nomt.read([0; 32]).unwrap(), Some(vec![1]));
+}
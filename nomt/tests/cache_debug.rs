@@ -0,0 +1,59 @@
+#![cfg(feature = "cache-debug")]
+
+mod common;
+
+use common::setup_nomt_with;
+use nomt::{KeyReadWrite, SessionParams};
+
+#[test]
+fn snapshot_lists_committed_pages_and_leaves() {
+    let nomt = setup_nomt_with("cache_snapshot", |o| o.commit_concurrency(2));
+
+    let mut keys = (0..1000u32)
+        .map(|i| {
+            let mut key = [0; 32];
+            key[..4].copy_from_slice(&i.wrapping_mul(0x9E3779B9).to_be_bytes());
+            key
+        })
+        .collect::<Vec<_>>();
+    keys.sort();
+    let actuals = keys
+        .iter()
+        .map(|key| (*key, KeyReadWrite::Write(Some(vec![7; 100]))))
+        .collect();
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+
+    let snapshot = nomt.cache_snapshot();
+
+    let root = &snapshot.pages[0];
+    assert_eq!(root.depth, 0);
+    assert!(root.pinned);
+    assert!(snapshot.pages.len() > 1);
+    assert!(snapshot.pages.windows(2).all(|w| w[0].depth <= w[1].depth));
+
+    // Every key written is in exactly one cached leaf.
+    assert!(!snapshot.leaves.is_empty());
+    let mut entries = 0;
+    for leaf in &snapshot.leaves {
+        let (first, last) = leaf.key_range.unwrap();
+        assert!(first <= last);
+        assert!(leaf.size > leaf.entries * 100);
+        entries += leaf.entries;
+    }
+    assert_eq!(entries, keys.len());
+    for key in &keys {
+        let holding = snapshot
+            .leaves
+            .iter()
+            .filter(|leaf| {
+                let (first, last) = leaf.key_range.unwrap();
+                first <= *key && *key <= last
+            })
+            .count();
+        assert_eq!(holding, 1);
+    }
+}
@@ -0,0 +1,73 @@
+#![cfg(feature = "chaos")]
+
+mod common;
+
+use nomt::{hasher::Blake3Hasher, ChaosConfig, KeyReadWrite, Nomt, Options, SessionParams};
+use std::time::Duration;
+
+fn open_nomt(name: &str, reset: bool) -> Nomt<Blake3Hasher> {
+    let configure = |o: &mut Options| {
+        o.commit_concurrency(2);
+        o.metrics(true);
+        o.chaos(ChaosConfig {
+            io_delay_probability: 1.0,
+            max_io_delay: Duration::from_micros(50),
+            eviction_probability: 1.0,
+        });
+    };
+    if reset {
+        common::setup_nomt_with(name, configure)
+    } else {
+        common::open_nomt(name, configure)
+    }
+}
+
+fn key(i: u32) -> [u8; 32] {
+    let mut key = [0; 32];
+    key[..4].copy_from_slice(&i.wrapping_mul(0x9E3779B9).to_be_bytes());
+    key
+}
+
+#[test]
+fn chaos_mode_injects_faults_without_changing_results() {
+    let nomt = open_nomt("chaos", true);
+
+    for round in 0..5u32 {
+        let mut actuals = (0..500)
+            .map(|i| {
+                (
+                    key(round * 500 + i),
+                    KeyReadWrite::Write(Some(round.to_le_bytes().to_vec())),
+                )
+            })
+            .collect::<Vec<_>>();
+        actuals.sort_by_key(|(k, _)| *k);
+        nomt.begin_session(SessionParams::default())
+            .finish(actuals)
+            .unwrap()
+            .commit(&nomt)
+            .unwrap();
+    }
+
+    let metrics = nomt.metrics();
+    assert!(metrics.get_chaos_io_delays() > 0);
+    assert!(metrics.get_chaos_evictions() > 0);
+
+    for round in 0..5u32 {
+        for i in 0..500 {
+            assert_eq!(
+                nomt.read(key(round * 500 + i)).unwrap(),
+                Some(round.to_le_bytes().to_vec())
+            );
+        }
+    }
+
+    let root = nomt.root();
+    drop(nomt);
+    let nomt = open_nomt("chaos", false);
+    assert_eq!(nomt.root(), root);
+    assert_eq!(
+        nomt.read(key(1234)).unwrap(),
+        Some(2u32.to_le_bytes().to_vec())
+    );
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use nomt::{hasher::Blake3Hasher, KeyReadWrite, Nomt, SessionParams};
+use std::time::{Duration, Instant};
+
+fn open_nomt(name: &str, throttle_target: Option<Duration>) -> Nomt<Blake3Hasher> {
+    common::setup_nomt_with(name, |o| {
+        o.rollback(true);
+        if let Some(target) = throttle_target {
+            o.write_throttle_target(target);
+        }
+    })
+}
+
+fn key(i: u32) -> [u8; 32] {
+    let mut key = [0; 32];
+    key[..4].copy_from_slice(&i.wrapping_mul(0x9E3779B9).to_be_bytes());
+    key
+}
+
+fn finish_batch(nomt: &Nomt<Blake3Hasher>, range: std::ops::Range<u32>) -> nomt::FinishedSession {
+    let mut actuals = range
+        .map(|i| (key(i), KeyReadWrite::Write(Some(i.to_le_bytes().to_vec()))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+}
+
+#[test]
+fn reports_met_deadline() {
+    let nomt = open_nomt("commit_deadline_met", None);
+    let finished = finish_batch(&nomt, 0..1000);
+    let root = finished.root();
+
+    let deadline = Instant::now() + Duration::from_secs(600);
+    let report = finished.commit_by(&nomt, deadline).unwrap();
+    assert!(report.deadline_met());
+    assert_eq!(report.overrun(), Duration::ZERO);
+    assert_eq!(report.deadline, deadline);
+    assert!(report.sync.meta > Duration::ZERO);
+    assert!(report.waiting + report.rollback + report.sync.meta <= report.total);
+    assert!(!report.skipped_optional_work);
+    assert_eq!(nomt.commit_stats().unwrap().root, root);
+
+    assert_eq!(nomt.root(), root);
+    assert_eq!(
+        nomt.read(key(999)).unwrap(),
+        Some(999u32.to_le_bytes().to_vec())
+    );
+}
+
+#[test]
+fn commits_past_deadline() {
+    let nomt = open_nomt("commit_deadline_missed", None);
+    let finished = finish_batch(&nomt, 0..1000);
+    let root = finished.root();
+
+    let deadline = Instant::now() - Duration::from_millis(1);
+    let report = finished.commit_by(&nomt, deadline).unwrap();
+    assert!(!report.deadline_met());
+    assert!(report.overrun() > Duration::ZERO);
+    assert_eq!(nomt.root(), root);
+
+    // The changes are synced, but the commit statistics are left for a later commit.
+    assert!(report.skipped_optional_work);
+    assert!(nomt.commit_stats().is_none());
+    finish_batch(&nomt, 1000..1100).commit(&nomt).unwrap();
+    assert_eq!(nomt.commit_stats().unwrap().root, nomt.root());
+}
+
+#[test]
+fn background_writes_not_throttled() {
+    // No device completes I/O within a nanosecond, so the throttle is always engaged.
+    let nomt = open_nomt("commit_deadline_throttle", Some(Duration::from_nanos(1)));
+    let deadline = Instant::now() + Duration::from_secs(600);
+    finish_batch(&nomt, 0..2000)
+        .commit_by(&nomt, deadline)
+        .unwrap();
+    finish_batch(&nomt, 2000..4000)
+        .commit_by(&nomt, deadline)
+        .unwrap();
+    assert_eq!(nomt.background_write_delay(), Duration::ZERO);
+}
@@ -15,8 +15,10 @@ use std::{
 
 static NEXT_TEST_ID: AtomicUsize = AtomicUsize::new(0);
 
+#[allow(unused_imports)]
 pub use nomt_test_utils::{account_path, key_diverging_at};
 
+#[allow(dead_code)]
 pub fn expected_root(accounts: u64) -> Node {
     let mut ops = (0..accounts)
         .map(account_path)
@@ -54,6 +56,7 @@ fn fill_missing_keys(g: &mut Gen, excluded: &mut BTreeSet<KeyPath>, target: usiz
     out
 }
 
+#[allow(dead_code)]
 pub fn apply_accesses(t: &mut Test, accesses: &[(KeyPath, KeyReadWrite)]) {
     for (key, access) in accesses {
         match access {
@@ -283,6 +286,56 @@ fn opts(path: PathBuf) -> Options {
     opts
 }
 
+/// The directory of the database of the test `name`.
+#[allow(dead_code)]
+pub fn test_path(name: &str) -> PathBuf {
+    let mut path = PathBuf::from("test");
+    path.push(name);
+    path
+}
+
+/// Like [`test_path`], but first removes the directory left by an earlier run.
+#[allow(dead_code)]
+pub fn clean_test_path(name: &str) -> PathBuf {
+    let path = test_path(name);
+    let _ = std::fs::remove_dir_all(&path);
+    path
+}
+
+/// The options of the database of the test `name`, committed to by a single worker.
+#[allow(dead_code)]
+pub fn test_options(name: &str) -> Options {
+    opts(test_path(name))
+}
+
+/// Open a fresh database for the test `name`, removing the one left by an earlier run.
+#[allow(dead_code)]
+pub fn setup_nomt(name: &str) -> Nomt<nomt::hasher::Blake3Hasher> {
+    setup_nomt_with(name, |_| {})
+}
+
+/// Like [`setup_nomt`], with the [`test_options`] adjusted by `configure`.
+#[allow(dead_code)]
+pub fn setup_nomt_with(
+    name: &str,
+    configure: impl FnOnce(&mut Options),
+) -> Nomt<nomt::hasher::Blake3Hasher> {
+    clean_test_path(name);
+    open_nomt(name, configure)
+}
+
+/// Open the existing database of the test `name`, with the [`test_options`] adjusted by
+/// `configure`.
+#[allow(dead_code)]
+pub fn open_nomt(
+    name: &str,
+    configure: impl FnOnce(&mut Options),
+) -> Nomt<nomt::hasher::Blake3Hasher> {
+    let mut o = test_options(name);
+    configure(&mut o);
+    Nomt::open(o).unwrap()
+}
+
 pub struct Test {
     nomt: Nomt<nomt::hasher::Blake3Hasher>,
     session: Option<Session<nomt::hasher::Blake3Hasher>>,
@@ -424,6 +477,10 @@ impl Test {
     pub fn root(&self) -> Root {
         self.nomt.root()
     }
+
+    pub fn nomt(&self) -> &Nomt<nomt::hasher::Blake3Hasher> {
+        &self.nomt
+    }
 }
 
 pub fn read_balance(t: &mut Test, id: u64) -> Option<u64> {
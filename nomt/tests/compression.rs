@@ -0,0 +1,198 @@
+#![cfg(feature = "lz4")]
+
+mod common;
+
+use common::clean_test_path;
+use nomt::{
+    hasher::Blake3Hasher, Compression, IntegrityLevel, KeyReadWrite, Nomt, ReadValue, SessionParams,
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+};
+
+fn open(name: &str, compression: Compression) -> Nomt<Blake3Hasher> {
+    common::open_nomt(name, |o| o.compression(compression))
+}
+
+fn key(i: u8) -> [u8; 32] {
+    [i; 32]
+}
+
+// Values which compress well, from inline values to values spanning many overflow pages.
+fn values() -> Vec<([u8; 32], Vec<u8>)> {
+    [100, 5_000, 200_000]
+        .into_iter()
+        .enumerate()
+        .map(|(i, len)| {
+            let mut value = format!("contract {i} ").repeat(len / 10 + 1).into_bytes();
+            value.truncate(len);
+            (key(i as u8), value)
+        })
+        .collect()
+}
+
+struct ThreadWaker(std::thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn block_on(mut read: ReadValue) -> Option<Vec<u8>> {
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(value) = Pin::new(&mut read).poll(&mut cx) {
+            return value.unwrap();
+        }
+        std::thread::park();
+    }
+}
+
+fn check_values(nomt: &Nomt<Blake3Hasher>, values: &[([u8; 32], Vec<u8>)]) {
+    let session = nomt.begin_session(SessionParams::default());
+    for (k, v) in values {
+        assert_eq!(nomt.read(*k).unwrap().as_ref(), Some(v));
+        assert_eq!(session.read(*k).unwrap().as_ref(), Some(v));
+        assert_eq!(block_on(session.read_async(*k)).as_ref(), Some(v));
+        assert_eq!(
+            session.read_slice(*k, 4_090, 20).unwrap(),
+            Some(v[v.len().min(4_090)..v.len().min(4_110)].to_vec()),
+        );
+    }
+    let iterated = session
+        .iter_range(..)
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(iterated, values);
+}
+
+fn compressed_values_read_back(name: &str, compression: Compression) {
+    let path = clean_test_path(name);
+    let nomt = open(name, compression);
+    let values = values();
+    let actuals = values
+        .iter()
+        .map(|(k, v)| (*k, KeyReadWrite::Write(Some(v.clone()))))
+        .collect();
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+    check_values(&nomt, &values);
+    drop(nomt);
+
+    // The repetitions of the largest value are gone from the leaf store.
+    let ln = std::fs::read(path.join("ln")).unwrap();
+    let needle = b"contract 2 ";
+    let repetitions = ln.windows(needle.len()).filter(|w| w == needle).count();
+    assert!(repetitions < 100, "{repetitions} repetitions");
+
+    // The codec is stored along with every value, so the values remain readable without
+    // compression.
+    let nomt = open(name, Compression::None);
+    check_values(&nomt, &values);
+    let report = nomt.check_integrity(IntegrityLevel::Full).unwrap();
+    assert!(report.is_consistent(), "{:?}", report.inconsistencies);
+}
+
+#[test]
+fn lz4_values_read_back() {
+    compressed_values_read_back("compression_lz4", Compression::Lz4);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_values_read_back() {
+    compressed_values_read_back(
+        "compression_zstd",
+        Compression::Zstd {
+            level: 3,
+            dictionary_size: 0,
+        },
+    );
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_dictionary_values_read_back() {
+    let name = "compression_zstd_dictionary";
+    let path = clean_test_path(name);
+    let compression = Compression::Zstd {
+        level: 3,
+        dictionary_size: 1024,
+    };
+    let nomt = open(name, compression);
+
+    // Records which are alike but too short to compress well on their own.
+    let values = (0..200u32)
+        .map(|i| {
+            let mut k = [0; 32];
+            k[..4].copy_from_slice(&i.to_be_bytes());
+            let record = format!("{{\"owner\":\"account-{i}\",\"nonce\":{}}}", i * 7);
+            let mut value = record.repeat(3_000 / record.len() + 1).into_bytes();
+            value.truncate(3_000);
+            (k, value)
+        })
+        .collect::<Vec<_>>();
+    for chunk in values.chunks(50) {
+        let actuals = chunk
+            .iter()
+            .map(|(k, v)| (*k, KeyReadWrite::Write(Some(v.clone()))))
+            .collect();
+        nomt.begin_session(SessionParams::default())
+            .finish(actuals)
+            .unwrap()
+            .commit(&nomt)
+            .unwrap();
+    }
+    check_values(&nomt, &values);
+    drop(nomt);
+    assert!(path.join("zstd_dict_1").is_file());
+
+    // The dictionary is kept for the values compressed with it.
+    let nomt = open(name, Compression::None);
+    check_values(&nomt, &values);
+    let report = nomt.check_integrity(IntegrityLevel::Full).unwrap();
+    assert!(report.is_consistent(), "{:?}", report.inconsistencies);
+}
+
+#[test]
+fn compression_requires_an_upgraded_format() {
+    let name = "compression_upgrade";
+    let path = clean_test_path(name);
+    drop(open(name, Compression::None));
+
+    // Rewrite the meta as one of format version 2, before compressed values.
+    let meta_path = path.join("meta");
+    let mut meta = std::fs::read(&meta_path).unwrap();
+    meta[4..8].copy_from_slice(&2u32.to_le_bytes());
+    std::fs::write(&meta_path, meta).unwrap();
+
+    let options = |upgrade| {
+        let mut o = common::test_options(name);
+        o.compression(Compression::Lz4);
+        o.upgrade_format(upgrade);
+        o
+    };
+    assert!(Nomt::<Blake3Hasher>::open(options(false)).is_err());
+    drop(open(name, Compression::None));
+
+    let nomt = Nomt::<Blake3Hasher>::open(options(true)).unwrap();
+    let values = values();
+    let actuals = values
+        .iter()
+        .map(|(k, v)| (*k, KeyReadWrite::Write(Some(v.clone()))))
+        .collect();
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+    check_values(&nomt, &values);
+}
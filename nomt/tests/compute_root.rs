@@ -97,6 +97,9 @@ fn test_root_match_with_inputs(
     let nomt::Witness {
         path_proofs,
         operations: nomt::WitnessedOperations { .. },
+        excluded: _,
+        binding: _,
+        values: _,
     } = witness;
     let mut inner = path_proofs.into_iter().map(|p| p.inner).collect::<Vec<_>>();
     inner.sort_by(|a, b| a.terminal.path().cmp(b.terminal.path()));
@@ -218,6 +221,13 @@ fn delete_nonexistent_key() {
     test_root_match_with_inputs("delete_nonexistent_key", prev, &accesses);
 }
 
+#[test]
+fn delete_nonexistent_key_from_empty() {
+    let k = key_diverging_at(8, true);
+    let accesses = vec![(k, KeyReadWrite::Write(None))];
+    test_root_match_with_inputs("delete_nonexistent_key_from_empty", vec![], &accesses);
+}
+
 #[test]
 fn read_then_write_mixed() {
     let k0 = key_diverging_at(0, true);
@@ -0,0 +1,145 @@
+mod common;
+
+use common::{open_nomt, setup_nomt};
+use nomt::{
+    hasher::Blake3Hasher, DefragParams, DefragStatus, IntegrityLevel, KeyReadWrite, Nomt,
+    SessionParams,
+};
+use std::time::Duration;
+
+fn commit(
+    nomt: &Nomt<Blake3Hasher>,
+    writes: impl IntoIterator<Item = ([u8; 32], Option<Vec<u8>>)>,
+) {
+    let mut actuals = writes
+        .into_iter()
+        .map(|(key, value)| (key, KeyReadWrite::Write(value)))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(key, _)| *key);
+    let session = nomt.begin_session(SessionParams::default());
+    session.finish(actuals).unwrap().commit(nomt).unwrap();
+}
+
+fn key(i: u32) -> [u8; 32] {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+fn value(i: u32) -> Vec<u8> {
+    let len = if i.is_multiple_of(100) { 10_000 } else { 100 };
+    vec![i as u8; len]
+}
+
+const KEYS: u32 = 20_000;
+
+// Keeps every fourth key.
+fn kept(i: u32) -> bool {
+    i.is_multiple_of(4)
+}
+
+#[test]
+fn shrinks_pruned_store() {
+    let nomt = setup_nomt("defrag_shrink");
+    commit(&nomt, (0..KEYS).map(|i| (key(i), Some(value(i)))));
+    let full = nomt
+        .defragment(DefragParams::default().pause(Duration::ZERO))
+        .unwrap()
+        .wait();
+    assert_eq!(full.status, DefragStatus::Finished);
+
+    commit(
+        &nomt,
+        (0..KEYS).filter(|&i| !kept(i)).map(|i| (key(i), None)),
+    );
+    let root = nomt.root();
+    let progress = nomt
+        .defragment(DefragParams::default().pause(Duration::ZERO))
+        .unwrap()
+        .wait();
+    assert_eq!(progress.status, DefragStatus::Finished);
+    assert!(progress.pages_moved > 0);
+    assert_eq!(progress.leaves_scanned, progress.leaves_total);
+    assert!(
+        progress.bytes < full.bytes * 2 / 3,
+        "{} bytes, {} before pruning",
+        progress.bytes,
+        full.bytes,
+    );
+    assert_eq!(nomt.defragmentation().unwrap().progress(), progress);
+
+    assert_eq!(nomt.root(), root);
+    for i in 0..KEYS {
+        let expected = kept(i).then(|| value(i));
+        assert_eq!(nomt.read(key(i)).unwrap(), expected);
+    }
+
+    let report = nomt.check_integrity(IntegrityLevel::Full).unwrap();
+    assert!(report.is_consistent(), "{:?}", report.inconsistencies);
+
+    drop(nomt);
+    let nomt = open_nomt("defrag_shrink", |_| {});
+    assert_eq!(nomt.root(), root);
+    let report = nomt.check_integrity(IntegrityLevel::Structure).unwrap();
+    assert!(report.is_consistent(), "{:?}", report.inconsistencies);
+    for i in (0..KEYS).filter(|&i| kept(i)) {
+        assert_eq!(nomt.read(key(i)).unwrap(), Some(value(i)));
+    }
+
+    // the freed pages are used again after a reopen.
+    commit(&nomt, (0..KEYS).map(|i| (key(i), Some(value(i)))));
+    for i in 0..KEYS {
+        assert_eq!(nomt.read(key(i)).unwrap(), Some(value(i)));
+    }
+}
+
+#[test]
+fn commits_during_defragmentation() {
+    let nomt = setup_nomt("defrag_commits");
+    commit(&nomt, (0..KEYS).map(|i| (key(i), Some(value(i)))));
+    commit(
+        &nomt,
+        (0..KEYS).filter(|&i| !kept(i)).map(|i| (key(i), None)),
+    );
+
+    let handle = nomt
+        .defragment(DefragParams::default().leaves_per_step(16))
+        .unwrap();
+    for round in 0..10u32 {
+        commit(
+            &nomt,
+            (0..KEYS)
+                .filter(|&i| kept(i) && i % 40 == round * 4)
+                .map(|i| (key(i), Some(vec![round as u8; 200]))),
+        );
+    }
+    assert_eq!(handle.wait().status, DefragStatus::Finished);
+
+    for i in 0..KEYS {
+        let expected = kept(i).then(|| vec![(i % 40 / 4) as u8; 200]);
+        assert_eq!(nomt.read(key(i)).unwrap(), expected);
+    }
+}
+
+#[test]
+fn cancel() {
+    let nomt = setup_nomt("defrag_cancel");
+    commit(&nomt, (0..KEYS).map(|i| (key(i), Some(value(i)))));
+    let root = nomt.root();
+
+    let handle = nomt
+        .defragment(
+            DefragParams::default()
+                .leaves_per_step(1)
+                .pause(Duration::from_secs(1)),
+        )
+        .unwrap();
+    assert!(nomt.defragment(DefragParams::default()).is_err());
+    handle.cancel();
+    let progress = handle.wait();
+    assert_eq!(progress.status, DefragStatus::Cancelled);
+    assert!(progress.leaves_scanned < progress.leaves_total);
+
+    assert_eq!(nomt.root(), root);
+    for i in 0..KEYS {
+        assert_eq!(nomt.read(key(i)).unwrap(), Some(value(i)));
+    }
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use nomt::{
+    hasher::Blake3Hasher, DuplicateWritePolicy, DuplicateWrites, KeyReadWrite, Nomt, SessionParams,
+};
+
+fn setup_nomt(name: &str, policy: DuplicateWritePolicy) -> Nomt<Blake3Hasher> {
+    common::setup_nomt_with(name, |o| {
+        o.metrics(true);
+        o.duplicate_write_policy(policy);
+    })
+}
+
+fn actuals() -> Vec<([u8; 32], KeyReadWrite)> {
+    vec![
+        ([1; 32], KeyReadWrite::Read(None)),
+        ([1; 32], KeyReadWrite::Write(Some(vec![1]))),
+        ([2; 32], KeyReadWrite::Write(Some(vec![1]))),
+        ([2; 32], KeyReadWrite::Read(Some(vec![1]))),
+        ([2; 32], KeyReadWrite::Write(Some(vec![2]))),
+        ([2; 32], KeyReadWrite::Write(Some(vec![3]))),
+        ([3; 32], KeyReadWrite::Write(Some(vec![1]))),
+    ]
+}
+
+#[test]
+fn last_write_wins() {
+    let nomt = setup_nomt(
+        "duplicate_writes_last_write_wins",
+        DuplicateWritePolicy::LastWriteWins,
+    );
+    let finished = nomt
+        .begin_session(SessionParams::default())
+        .finish(actuals())
+        .unwrap();
+
+    // A read followed by a write is not a duplicate.
+    assert_eq!(finished.duplicate_writes(), &[[2; 32]]);
+    assert_eq!(nomt.metrics().get_duplicate_writes(), 1);
+    let root = finished.root();
+    finished.commit(&nomt).unwrap();
+
+    assert_eq!(nomt.read([1; 32]).unwrap(), Some(vec![1]));
+    assert_eq!(nomt.read([2; 32]).unwrap(), Some(vec![3]));
+    assert_eq!(nomt.read([3; 32]).unwrap(), Some(vec![1]));
+
+    // The root is the same as with the duplicates removed.
+    let other = setup_nomt(
+        "duplicate_writes_last_write_wins_other",
+        DuplicateWritePolicy::Reject,
+    );
+    let finished = other
+        .begin_session(SessionParams::default())
+        .finish(vec![
+            ([1; 32], KeyReadWrite::Write(Some(vec![1]))),
+            ([2; 32], KeyReadWrite::Write(Some(vec![3]))),
+            ([3; 32], KeyReadWrite::Write(Some(vec![1]))),
+        ])
+        .unwrap();
+    assert!(finished.duplicate_writes().is_empty());
+    assert_eq!(finished.root(), root);
+}
+
+#[test]
+fn reject() {
+    let nomt = setup_nomt("duplicate_writes_reject", DuplicateWritePolicy::Reject);
+    let err = match nomt
+        .begin_session(SessionParams::default())
+        .finish(actuals())
+    {
+        Ok(_) => panic!("session with duplicate writes finished"),
+        Err(err) => err,
+    };
+    assert_eq!(
+        err.downcast::<DuplicateWrites>().unwrap(),
+        DuplicateWrites {
+            keys: vec![[2; 32]]
+        }
+    );
+    assert_eq!(nomt.metrics().get_duplicate_writes(), 1);
+    assert_eq!(nomt.read([2; 32]).unwrap(), None);
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use bitvec::prelude::*;
+use common::{open_nomt, setup_nomt_with, Test};
+use nomt::{
+    hasher::{Blake3Hasher, ValueHasher},
+    trie::{KeyPath, LeafData},
+    KeyReadWrite, Nomt, Root, SessionParams,
+};
+
+const EMPTY: KeyPath = [1; 32];
+const DELETED: KeyPath = [2; 32];
+const OTHER: KeyPath = [3; 32];
+
+fn commit(nomt: &Nomt<Blake3Hasher>, changes: Vec<(KeyPath, Option<Vec<u8>>)>) -> Root {
+    let session = nomt.begin_session(SessionParams::default());
+    let actuals = changes
+        .into_iter()
+        .map(|(key, value)| (key, KeyReadWrite::Write(value)))
+        .collect();
+    session.finish(actuals).unwrap().commit(nomt).unwrap();
+    nomt.root()
+}
+
+#[test]
+fn empty_value_is_not_deletion() {
+    let mut t = Test::new("empty_value_is_not_deletion");
+    t.write(EMPTY, Some(vec![]));
+    t.write(DELETED, Some(vec![2]));
+    t.write(OTHER, Some(vec![3]));
+    let (_, inserted) = t.commit();
+
+    t.write(DELETED, None);
+    let (root, deleted) = t.commit();
+
+    // Sessions and the database read the empty value back as such.
+    assert_eq!(t.read(EMPTY), Some(vec![]));
+    assert_eq!(t.read(DELETED), None);
+    assert_eq!(t.nomt().read(EMPTY).unwrap(), Some(vec![]));
+    assert_eq!(t.nomt().read(DELETED).unwrap(), None);
+
+    // The empty value is witnessed as a write of the hash of the empty value, unlike the
+    // deletion.
+    let write = &inserted.operations.writes[0];
+    assert_eq!(write.key, EMPTY);
+    assert_eq!(write.value, Some(Blake3Hasher::hash_value(&[])));
+    assert_eq!(deleted.operations.writes.len(), 1);
+    assert_eq!(deleted.operations.writes[0].value, None);
+
+    // The empty value has a leaf of its own in the trie.
+    let proof = t.prove(EMPTY);
+    let leaf = LeafData {
+        key_path: EMPTY,
+        value_hash: Blake3Hasher::hash_value(&[]),
+    };
+    assert_eq!(proof.terminal.as_leaf_option(), Some(leaf.clone()));
+    let verified = proof
+        .verify::<Blake3Hasher>(EMPTY.view_bits::<Msb0>(), root.into_inner())
+        .unwrap();
+    assert!(verified.confirm_value(&leaf).unwrap());
+    assert!(!verified.confirm_nonexistence(&EMPTY).unwrap());
+
+    let proof = t.prove(DELETED);
+    let verified = proof
+        .verify::<Blake3Hasher>(DELETED.view_bits::<Msb0>(), root.into_inner())
+        .unwrap();
+    assert!(verified.confirm_nonexistence(&DELETED).unwrap());
+
+    // Iteration yields the empty value and skips the deleted one.
+    let values = t
+        .nomt()
+        .iter_values([0; 32], None)
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(values, vec![(EMPTY, vec![]), (OTHER, vec![3])]);
+
+    // Writes within an overlay.
+    t.write(EMPTY, None);
+    t.write(DELETED, Some(vec![]));
+    let (overlay, _) = t.update();
+    t.start_overlay_session([&overlay]);
+    assert_eq!(t.read(EMPTY), None);
+    assert_eq!(t.read(DELETED), Some(vec![]));
+}
+
+#[test]
+fn empty_value_changes_root() {
+    let name = "empty_value_changes_root";
+    let nomt = setup_nomt_with(name, |o| o.rollback(true));
+    let base = commit(&nomt, vec![(OTHER, Some(vec![3]))]);
+    let with_empty = commit(&nomt, vec![(EMPTY, Some(vec![]))]);
+    assert_ne!(with_empty, base);
+    let with_zero = commit(&nomt, vec![(EMPTY, Some(vec![0]))]);
+    assert_ne!(with_zero, with_empty);
+    assert_eq!(commit(&nomt, vec![(EMPTY, Some(vec![]))]), with_empty);
+
+    // The empty value survives reopening, i.e. it is persisted in the beatree.
+    drop(nomt);
+    let nomt = open_nomt(name, |o| o.rollback(true));
+    assert_eq!(nomt.root(), with_empty);
+    assert_eq!(nomt.read(EMPTY).unwrap(), Some(vec![]));
+
+    // Rollback restores the empty value rather than deleting the key, and vice versa.
+    assert_eq!(commit(&nomt, vec![(EMPTY, None)]), base);
+    nomt.rollback(1).unwrap();
+    assert_eq!(nomt.root(), with_empty);
+    assert_eq!(nomt.read(EMPTY).unwrap(), Some(vec![]));
+    nomt.rollback(3).unwrap();
+    assert_eq!(nomt.root(), base);
+    assert_eq!(nomt.read(EMPTY).unwrap(), None);
+}
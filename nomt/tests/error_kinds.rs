@@ -0,0 +1,71 @@
+mod common;
+
+use common::{clean_test_path, test_options};
+use nomt::{hasher::Blake3Hasher, Error, KeyReadWrite, Nomt, SessionMisuse, SessionParams};
+
+fn open(name: &str, rollback: bool) -> Result<Nomt<Blake3Hasher>, Error> {
+    let mut o = test_options(name);
+    o.rollback(rollback);
+    Nomt::open(o)
+}
+
+fn write(value: u8) -> Vec<([u8; 32], KeyReadWrite)> {
+    vec![([1; 32], KeyReadWrite::Write(Some(vec![value])))]
+}
+
+#[test]
+fn stale_commit_is_misuse() {
+    clean_test_path("error_kinds_misuse");
+    let nomt = open("error_kinds_misuse", false).unwrap();
+    let first = nomt.begin_session(SessionParams::default());
+    let second = nomt.begin_session(SessionParams::default());
+    let first = first.finish(write(1)).unwrap();
+    let second = second.finish(write(2)).unwrap();
+    first.commit(&nomt).unwrap();
+
+    let err = second.commit(&nomt).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Misuse(SessionMisuse::StaleChangeset { .. })
+    ));
+    // The typed error is still found by downcasting.
+    assert!(err.downcast_ref::<SessionMisuse>().is_some());
+    assert!(err.into_anyhow().downcast_ref::<SessionMisuse>().is_some());
+}
+
+#[test]
+fn rollback_failure() {
+    clean_test_path("error_kinds_rollback");
+    let nomt = open("error_kinds_rollback", false).unwrap();
+    assert!(matches!(nomt.rollback(1), Err(Error::Rollback(_))));
+    drop(nomt);
+
+    let nomt = open("error_kinds_rollback", true).unwrap();
+    nomt.begin_session(SessionParams::default())
+        .finish(write(1))
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+    assert!(matches!(nomt.rollback(2), Err(Error::Rollback(_))));
+    nomt.rollback(1).unwrap();
+}
+
+#[test]
+fn truncated_hash_table_is_corruption() {
+    let path = clean_test_path("error_kinds_corruption");
+    drop(open("error_kinds_corruption", false).unwrap());
+
+    let ht = std::fs::OpenOptions::new()
+        .write(true)
+        .open(path.join("ht"))
+        .unwrap();
+    let len = ht.metadata().unwrap().len();
+    ht.set_len(len - 4096).unwrap();
+    drop(ht);
+
+    match open("error_kinds_corruption", false) {
+        Err(Error::Corruption(_)) => {}
+        Err(err) => panic!("not a corruption: {err:?}"),
+        Ok(_) => panic!("opened a truncated hash table"),
+    }
+}
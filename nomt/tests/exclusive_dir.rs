@@ -16,7 +16,7 @@ fn setup_nomt(path: &str, should_clean_up: bool) -> anyhow::Result<Nomt<Blake3Ha
     let mut o = Options::new();
     o.path(path);
     o.bitbox_seed([0; 16]);
-    Nomt::open(o)
+    Ok(Nomt::open(o)?)
 }
 
 #[test]
@@ -0,0 +1,94 @@
+mod common;
+
+use common::{clean_test_path, open_nomt, setup_nomt, test_path};
+use nomt::{hasher::Blake3Hasher, KeyReadWrite, Nomt, SessionParams};
+use std::{path::Path, sync::mpsc, time::Duration};
+
+fn key(i: u32) -> [u8; 32] {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+fn commit_round(nomt: &Nomt<Blake3Hasher>, round: u32) {
+    let mut actuals = (round * 100..(round + 1) * 100)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(vec![round as u8; 500]))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+}
+
+// Copy the directory like an external tool would, file by file.
+fn copy_dir(src: &Path, dest: &Path) {
+    std::fs::create_dir_all(dest).unwrap();
+    for entry in std::fs::read_dir(src).unwrap() {
+        let entry = entry.unwrap();
+        if entry.file_type().unwrap().is_file() {
+            std::fs::copy(entry.path(), dest.join(entry.file_name())).unwrap();
+        }
+    }
+}
+
+#[test]
+fn copy_while_frozen() {
+    let path = test_path("freeze_source");
+    let copy = clean_test_path("freeze_copy");
+
+    let nomt = setup_nomt("freeze_source");
+    commit_round(&nomt, 0);
+    let frozen_root = nomt.root();
+
+    nomt.freeze().unwrap();
+    assert!(nomt.is_frozen());
+    assert!(nomt.freeze().is_err());
+
+    let committer = &nomt;
+    std::thread::scope(|s| {
+        // A commit waits for the thaw.
+        let (tx, rx) = mpsc::channel();
+        s.spawn(move || {
+            commit_round(committer, 1);
+            tx.send(()).unwrap();
+        });
+        assert!(rx.recv_timeout(Duration::from_millis(300)).is_err());
+
+        // Reads and sessions continue meanwhile.
+        assert_eq!(nomt.read(key(5)).unwrap(), Some(vec![0; 500]));
+        let session = nomt.begin_session(SessionParams::default());
+        assert_eq!(session.read(key(7)).unwrap(), Some(vec![0; 500]));
+        drop(session);
+
+        copy_dir(&path, &copy);
+        assert_eq!(nomt.root(), frozen_root);
+
+        nomt.thaw().unwrap();
+        assert!(!nomt.is_frozen());
+        rx.recv_timeout(Duration::from_secs(30)).unwrap();
+    });
+    assert_ne!(nomt.root(), frozen_root);
+    assert_eq!(nomt.read(key(105)).unwrap(), Some(vec![1; 500]));
+
+    // Thawing again does nothing.
+    nomt.thaw().unwrap();
+    drop(nomt);
+
+    let copied = open_nomt("freeze_copy", |_| {});
+    assert_eq!(copied.root(), frozen_root);
+    assert_eq!(copied.read(key(5)).unwrap(), Some(vec![0; 500]));
+    assert_eq!(copied.read(key(105)).unwrap(), None);
+}
+
+#[test]
+fn drop_while_frozen() {
+    let nomt = setup_nomt("freeze_drop");
+    commit_round(&nomt, 0);
+    let root = nomt.root();
+    nomt.freeze().unwrap();
+    drop(nomt);
+
+    let nomt = open_nomt("freeze_drop", |_| {});
+    assert_eq!(nomt.root(), root);
+    commit_round(&nomt, 1);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use common::setup_nomt;
+use nomt::{hasher::Blake3Hasher, KeyReadWrite, Nomt, SessionParams};
+
+fn key(i: u8) -> [u8; 32] {
+    [i; 32]
+}
+
+fn write(nomt: &Nomt<Blake3Hasher>, changes: Vec<([u8; 32], Option<Vec<u8>>)>) {
+    nomt.begin_session(SessionParams::default())
+        .finish(
+            changes
+                .into_iter()
+                .map(|(k, v)| (k, KeyReadWrite::Write(v)))
+                .collect(),
+        )
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+}
+
+#[test]
+fn get_reads_committed_values() {
+    let nomt = setup_nomt("get_committed");
+    assert_eq!(nomt.get(key(1)).unwrap(), None);
+
+    write(
+        &nomt,
+        vec![(key(1), Some(vec![1; 4])), (key(2), Some(vec![2; 5000]))],
+    );
+    assert_eq!(nomt.get(key(1)).unwrap(), Some(vec![1; 4]));
+    assert_eq!(nomt.get(key(2)).unwrap(), Some(vec![2; 5000]));
+    assert_eq!(nomt.get(key(3)).unwrap(), None);
+
+    write(&nomt, vec![(key(1), None)]);
+    assert_eq!(nomt.get(key(1)).unwrap(), None);
+}
+
+#[test]
+fn get_many_keeps_key_order() {
+    let nomt = setup_nomt("get_many");
+    write(
+        &nomt,
+        vec![(key(1), Some(vec![1])), (key(3), Some(vec![3]))],
+    );
+
+    let values = nomt.get_many([key(3), key(2), key(1), key(3)]).unwrap();
+    assert_eq!(
+        values,
+        vec![Some(vec![3]), None, Some(vec![1]), Some(vec![3])]
+    );
+    assert!(nomt.get_many([]).unwrap().is_empty());
+}
+
+#[test]
+fn get_ignores_uncommitted_overlays() {
+    let nomt = setup_nomt("get_overlay");
+    write(&nomt, vec![(key(1), Some(vec![1]))]);
+
+    let _overlay = nomt
+        .begin_session(SessionParams::default())
+        .finish(vec![(key(1), KeyReadWrite::Write(Some(vec![2])))])
+        .unwrap()
+        .into_overlay();
+    assert_eq!(nomt.get(key(1)).unwrap(), Some(vec![1]));
+}
@@ -0,0 +1,270 @@
+mod common;
+
+use common::{clean_test_path, test_path};
+use nomt::{
+    hasher::Blake3Hasher, HashTableResizeStatus, KeyReadWrite, Nomt, ReadBackend, SessionParams,
+};
+use std::collections::BTreeMap;
+
+fn open(name: &str, buckets: u32) -> Nomt<Blake3Hasher> {
+    common::open_nomt(name, |o| {
+        o.hashtable_buckets(buckets);
+        o.preallocate_ht(false);
+    })
+}
+
+fn setup_nomt(name: &str, buckets: u32) -> Nomt<Blake3Hasher> {
+    clean_test_path(name);
+    open(name, buckets)
+}
+
+fn key(i: u32) -> [u8; 32] {
+    let mut key = [0; 32];
+    key[..4].copy_from_slice(&i.wrapping_mul(0x9E3779B9).to_be_bytes());
+    key
+}
+
+fn commit(
+    nomt: &Nomt<Blake3Hasher>,
+    model: &mut BTreeMap<[u8; 32], Vec<u8>>,
+    writes: impl IntoIterator<Item = ([u8; 32], Option<Vec<u8>>)>,
+) {
+    let mut actuals = writes
+        .into_iter()
+        .map(|(key, value)| {
+            match value {
+                Some(ref value) => model.insert(key, value.clone()),
+                None => model.remove(&key),
+            };
+            (key, KeyReadWrite::Write(value))
+        })
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(key, _)| *key);
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+}
+
+fn check(nomt: &Nomt<Blake3Hasher>, model: &BTreeMap<[u8; 32], Vec<u8>>, keys: u32) {
+    for i in 0..keys {
+        assert_eq!(nomt.read(key(i)).unwrap().as_ref(), model.get(&key(i)));
+    }
+}
+
+fn shadow_exists(name: &str) -> bool {
+    test_path(name).join("ht.resize").exists()
+}
+
+#[test]
+fn resize_while_committing() {
+    let nomt = setup_nomt("ht_resize_commit", 100_000);
+    let mut model = BTreeMap::new();
+    commit(
+        &nomt,
+        &mut model,
+        (0..2000).map(|i| (key(i), Some(vec![0]))),
+    );
+
+    let handle = nomt.resize_hash_table(20_000).unwrap();
+
+    // Overwrite, delete and insert keys while the buckets are being copied.
+    let mut round = 1;
+    while handle.progress().status == HashTableResizeStatus::Running && round < 200 {
+        let writes = (0..50)
+            .map(|i| (key(round * 50 + i), Some(vec![round as u8])))
+            .chain((0..50).map(|i| (key((round - 1) * 50 + i), None)))
+            .collect::<Vec<_>>();
+        commit(&nomt, &mut model, writes);
+        round += 1;
+    }
+
+    let progress = handle.wait();
+    assert_eq!(progress.status, HashTableResizeStatus::Finished);
+    assert_eq!(progress.buckets_copied, 100_000);
+    assert_eq!(progress.buckets_total, 100_000);
+    assert_eq!(nomt.hash_table_utilization().capacity, 20_000);
+    assert!(!shadow_exists("ht_resize_commit"));
+    check(&nomt, &model, 2000 + round * 50);
+
+    // The resized hash table takes further commits.
+    commit(
+        &nomt,
+        &mut model,
+        (0..100).map(|i| (key(i), Some(vec![0xff]))),
+    );
+    check(&nomt, &model, 2000 + round * 50);
+
+    let root = nomt.root();
+    drop(nomt);
+    let nomt = open("ht_resize_commit", 100_000);
+    assert_eq!(nomt.root(), root);
+    assert_eq!(nomt.hash_table_utilization().capacity, 20_000);
+    check(&nomt, &model, 2000 + round * 50);
+}
+
+#[test]
+fn changesets_from_before_the_switch_are_committed() {
+    let nomt = setup_nomt("ht_resize_stale", 10_000);
+    let mut model = BTreeMap::new();
+    commit(
+        &nomt,
+        &mut model,
+        (0..1000).map(|i| (key(i), Some(vec![1]))),
+    );
+
+    // An overlay and a child of it, both touching existing and fresh pages.
+    let writes = |value: u8, keys: std::ops::Range<u32>| {
+        let mut writes = keys
+            .map(|i| (key(i), KeyReadWrite::Write(Some(vec![value]))))
+            .collect::<Vec<_>>();
+        writes.sort_by_key(|(key, _)| *key);
+        writes
+    };
+    let parent = nomt
+        .begin_session(SessionParams::default())
+        .finish(writes(2, 0..1500))
+        .unwrap()
+        .into_overlay();
+    let child = nomt
+        .begin_session(SessionParams::default().overlay([&parent]).unwrap())
+        .finish(writes(3, 500..2000))
+        .unwrap()
+        .into_overlay();
+
+    let handle = nomt.resize_hash_table(20_000).unwrap();
+    assert_eq!(handle.wait().status, HashTableResizeStatus::Finished);
+
+    parent.commit(&nomt).unwrap();
+    child.commit(&nomt).unwrap();
+    for i in 0..2000 {
+        model.insert(key(i), vec![if i < 500 { 2 } else { 3 }]);
+    }
+    check(&nomt, &model, 2000);
+
+    // A session finished before the switch, but committed after it.
+    let finished = nomt
+        .begin_session(SessionParams::default())
+        .finish(writes(4, 1000..3000))
+        .unwrap();
+    let handle = nomt.resize_hash_table(30_000).unwrap();
+    assert_eq!(handle.wait().status, HashTableResizeStatus::Finished);
+    finished.commit(&nomt).unwrap();
+    for i in 1000..3000 {
+        model.insert(key(i), vec![4]);
+    }
+    check(&nomt, &model, 3000);
+
+    let root = nomt.root();
+    drop(nomt);
+    let nomt = open("ht_resize_stale", 10_000);
+    assert_eq!(nomt.root(), root);
+    check(&nomt, &model, 3000);
+}
+
+#[test]
+fn cancel_removes_new_hash_table() {
+    let nomt = setup_nomt("ht_resize_cancel", 10_000);
+    let mut model = BTreeMap::new();
+    commit(&nomt, &mut model, (0..100).map(|i| (key(i), Some(vec![1]))));
+
+    // A live session holds off the switch.
+    let session = nomt.begin_session(SessionParams::default());
+    let handle = nomt.resize_hash_table(20_000).unwrap();
+    assert!(shadow_exists("ht_resize_cancel"));
+    assert!(nomt.resize_hash_table(30_000).is_err());
+    handle.cancel();
+    assert_eq!(handle.wait().status, HashTableResizeStatus::Cancelled);
+    drop(session);
+
+    assert!(!shadow_exists("ht_resize_cancel"));
+    assert_eq!(nomt.hash_table_utilization().capacity, 10_000);
+    check(&nomt, &model, 100);
+
+    assert!(nomt.resize_hash_table(10_000).is_err());
+    assert!(nomt.resize_hash_table(1).is_err());
+}
+
+#[test]
+fn interrupted_resize_is_discarded_on_open() {
+    let nomt = setup_nomt("ht_resize_interrupted", 10_000);
+    let mut model = BTreeMap::new();
+    commit(&nomt, &mut model, (0..100).map(|i| (key(i), Some(vec![1]))));
+    drop(nomt);
+
+    std::fs::write(
+        test_path("ht_resize_interrupted").join("ht.resize"),
+        vec![0; 4096],
+    )
+    .unwrap();
+    let nomt = open("ht_resize_interrupted", 10_000);
+    assert!(!shadow_exists("ht_resize_interrupted"));
+    check(&nomt, &model, 100);
+}
+
+#[test]
+fn resizes_past_load_factor() {
+    let nomt = common::setup_nomt_with("ht_resize_auto", |o| {
+        o.hashtable_buckets(1000);
+        o.preallocate_ht(false);
+        o.hashtable_auto_resize(0.5);
+        o.metrics(true);
+    });
+
+    let mut model = BTreeMap::new();
+    let mut keys = 0;
+    while nomt.hash_table_resize().is_none() {
+        assert!(nomt.hash_table_utilization().occupancy_rate() <= 0.5);
+        commit(
+            &nomt,
+            &mut model,
+            (keys..keys + 500).map(|i| (key(i), Some(vec![1]))),
+        );
+        keys += 500;
+    }
+
+    let progress = nomt.hash_table_resize().unwrap().wait();
+    assert_eq!(progress.status, HashTableResizeStatus::Finished);
+    assert_eq!(nomt.hash_table_utilization().capacity, 2000);
+    assert_eq!(nomt.metrics().get_hashtable_resize_failures(), 0);
+    check(&nomt, &model, keys);
+}
+
+#[test]
+fn nonblocking_commits_resize_past_load_factor() {
+    let nomt = common::setup_nomt_with("ht_resize_auto_nonblocking", |o| {
+        o.hashtable_buckets(1000);
+        o.preallocate_ht(false);
+        o.hashtable_auto_resize(0.5);
+    });
+
+    let mut keys = 0;
+    while nomt.hash_table_resize().is_none() {
+        assert!(nomt.hash_table_utilization().occupancy_rate() <= 0.5);
+        let actuals = (keys..keys + 500)
+            .map(|i| (key(i), KeyReadWrite::Write(Some(vec![1]))))
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let finished = nomt
+            .begin_session(SessionParams::default())
+            .finish(actuals)
+            .unwrap();
+        assert!(finished.try_commit_nonblocking(&nomt).unwrap().is_none());
+        keys += 500;
+    }
+
+    let progress = nomt.hash_table_resize().unwrap().wait();
+    assert_eq!(progress.status, HashTableResizeStatus::Finished);
+    assert_eq!(nomt.hash_table_utilization().capacity, 2000);
+}
+
+#[test]
+fn auto_resize_rejected_with_mmap() {
+    clean_test_path("ht_resize_auto_mmap");
+    let mut o = common::test_options("ht_resize_auto_mmap");
+    o.hashtable_auto_resize(0.5);
+    o.read_backend(ReadBackend::Mmap);
+    assert!(Nomt::<Blake3Hasher>::open(o).is_err());
+}
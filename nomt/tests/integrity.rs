@@ -0,0 +1,146 @@
+mod common;
+
+use common::{account_path, clean_test_path};
+use nomt::{
+    hasher::Blake3Hasher, integrity::Inconsistency, IntegrityLevel, KeyReadWrite, Nomt,
+    SessionParams,
+};
+use std::os::unix::fs::FileExt as _;
+
+const BUCKETS: u32 = 4096;
+const PAGE_SIZE: usize = 4096;
+
+fn open(name: &str) -> Nomt<Blake3Hasher> {
+    common::open_nomt(name, |o| o.hashtable_buckets(BUCKETS))
+}
+
+fn value(id: u64, round: u64) -> Vec<u8> {
+    // Every tenth value is large enough to be stored in overflow pages.
+    let len = if id.is_multiple_of(10) { 5000 } else { 40 };
+    let mut value = format!("value-{id}-{round}-").into_bytes();
+    value.resize(len, id as u8);
+    value
+}
+
+// Write values over a few commits, deleting some of those written before.
+fn populate(nomt: &Nomt<Blake3Hasher>) {
+    for round in 0..4 {
+        let mut actuals = (0..1000)
+            .map(|id| {
+                let write = if round > 0 && id % 7 == round {
+                    None
+                } else {
+                    Some(value(id, round))
+                };
+                (account_path(id), KeyReadWrite::Write(write))
+            })
+            .collect::<Vec<_>>();
+        actuals.sort_by_key(|(k, _)| *k);
+        nomt.begin_session(SessionParams::default())
+            .finish(actuals)
+            .unwrap()
+            .commit(nomt)
+            .unwrap();
+    }
+}
+
+#[test]
+fn healthy_database_is_consistent() {
+    let name = "integrity_healthy";
+    let _path = clean_test_path(name);
+    let nomt = open(name);
+    let report = nomt.check_integrity(IntegrityLevel::Full).unwrap();
+    assert!(report.is_consistent(), "{:?}", report.inconsistencies);
+    assert_eq!(report.values, 0);
+
+    populate(&nomt);
+    let report = nomt.check_integrity(IntegrityLevel::Structure).unwrap();
+    assert!(report.is_consistent(), "{:?}", report.inconsistencies);
+    assert!(report.buckets > 0);
+    assert!(report.branch_nodes > 0);
+    assert!(report.leaf_nodes > 0);
+    assert_eq!(report.wal_entries, 0);
+    assert_eq!(report.pages, 0);
+
+    let report = nomt.check_integrity(IntegrityLevel::Full).unwrap();
+    assert!(report.is_consistent(), "{:?}", report.inconsistencies);
+    assert_eq!(report.pages, report.buckets);
+    // The last round deletes the values with IDs congruent to 3 modulo 7.
+    assert_eq!(report.values, 1000 - 143);
+}
+
+#[test]
+fn corrupted_page_is_reported() {
+    let name = "integrity_corrupted_page";
+    let path = clean_test_path(name);
+    populate(&open(name));
+
+    // Flip a bit of the top node of the first page held in the hash table, other than the root
+    // page, whose ID is all zeroes.
+    let ht = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path.join("ht"))
+        .unwrap();
+    let meta_pages = (BUCKETS as usize).div_ceil(PAGE_SIZE);
+    let mut page = vec![0; PAGE_SIZE];
+    let offset = (meta_pages..meta_pages + BUCKETS as usize)
+        .map(|pn| (pn * PAGE_SIZE) as u64)
+        .find(|&offset| {
+            ht.read_exact_at(&mut page, offset).unwrap();
+            page[PAGE_SIZE - 32..].iter().any(|&b| b != 0)
+        })
+        .unwrap();
+    page[5] ^= 1;
+    ht.write_all_at(&page, offset).unwrap();
+    drop(ht);
+
+    let nomt = open(name);
+    let report = nomt.check_integrity(IntegrityLevel::Structure).unwrap();
+    assert!(report.is_consistent(), "{:?}", report.inconsistencies);
+    let report = nomt.check_integrity(IntegrityLevel::Full).unwrap();
+    assert!(report
+        .inconsistencies
+        .iter()
+        .any(|i| matches!(i, Inconsistency::PageHashMismatch { .. })));
+}
+
+#[test]
+fn corrupted_value_is_reported() {
+    let name = "integrity_corrupted_value";
+    let path = clean_test_path(name);
+    populate(&open(name));
+
+    // Change a value in place in the leaf store, one stored in a leaf and one in overflow pages.
+    let ln = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path.join("ln"))
+        .unwrap();
+    let contents = std::fs::read(path.join("ln")).unwrap();
+    for id in [2, 20] {
+        let needle = format!("value-{id}-3-").into_bytes();
+        let at = contents
+            .windows(needle.len())
+            .position(|w| w == &needle[..])
+            .unwrap();
+        ln.write_all_at(b"V", at as u64).unwrap();
+    }
+    drop(ln);
+
+    let nomt = open(name);
+    let report = nomt.check_integrity(IntegrityLevel::Structure).unwrap();
+    assert!(report.is_consistent(), "{:?}", report.inconsistencies);
+    let report = nomt.check_integrity(IntegrityLevel::Full).unwrap();
+    assert_eq!(
+        report.inconsistencies[0],
+        Inconsistency::ValueHashMismatch {
+            key: account_path(20)
+        }
+    );
+    assert!(matches!(
+        report.inconsistencies[1],
+        Inconsistency::RootMismatch { .. }
+    ));
+    assert_eq!(report.inconsistencies.len(), 2);
+}
@@ -0,0 +1,131 @@
+mod common;
+
+use common::setup_nomt;
+use nomt::{hasher::Blake3Hasher, KeyReadWrite, Nomt, SessionParams};
+use std::{collections::BTreeMap, ops::Bound};
+
+fn key(i: u32) -> [u8; 32] {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+// Every tenth value spills into overflow pages.
+fn value(i: u32) -> Vec<u8> {
+    let len = if i.is_multiple_of(10) { 20_000 } else { 16 };
+    (0..len).map(|j| (i as usize + j) as u8).collect()
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, changes: &BTreeMap<[u8; 32], Option<Vec<u8>>>) {
+    let actuals = changes
+        .iter()
+        .map(|(k, v)| (*k, KeyReadWrite::Write(v.clone())))
+        .collect();
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+}
+
+fn assert_range(
+    session: &nomt::Session<Blake3Hasher>,
+    expected: &BTreeMap<[u8; 32], Vec<u8>>,
+    range: (Bound<[u8; 32]>, Bound<[u8; 32]>),
+) {
+    let actual = session
+        .iter_range(range)
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+    let expected = expected
+        .range(range)
+        .map(|(k, v)| (*k, v.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(actual, expected);
+}
+
+type KeyRange = (Bound<[u8; 32]>, Bound<[u8; 32]>);
+
+fn ranges(keys: &[[u8; 32]]) -> Vec<KeyRange> {
+    let mut ranges = vec![(Bound::Unbounded, Bound::Unbounded)];
+    for pair in keys.windows(2) {
+        let (a, b) = (pair[0].min(pair[1]), pair[0].max(pair[1]));
+        ranges.push((Bound::Included(a), Bound::Excluded(b)));
+        ranges.push((Bound::Excluded(a), Bound::Included(b)));
+        ranges.push((Bound::Unbounded, Bound::Included(a)));
+        ranges.push((Bound::Excluded(b), Bound::Unbounded));
+        ranges.push((Bound::Included(a), Bound::Excluded(a)));
+    }
+    ranges
+}
+
+#[test]
+fn matches_committed_values() {
+    let nomt = setup_nomt("iter_range_committed");
+    let changes = (0..1000)
+        .map(|i| (key(i), Some(value(i))))
+        .collect::<BTreeMap<_, _>>();
+    commit(&nomt, &changes);
+    let expected = changes
+        .into_iter()
+        .map(|(k, v)| (k, v.unwrap()))
+        .collect::<BTreeMap<_, _>>();
+
+    let session = nomt.begin_session(SessionParams::default());
+    let keys = (0..1010).step_by(101).map(key).collect::<Vec<_>>();
+    for range in ranges(&keys) {
+        assert_range(&session, &expected, range);
+    }
+}
+
+#[test]
+fn merges_overlays() {
+    let nomt = setup_nomt("iter_range_overlay");
+    let committed = (0..500)
+        .map(|i| (key(i), Some(value(i))))
+        .collect::<BTreeMap<_, _>>();
+    commit(&nomt, &committed);
+
+    // Delete some values, overwrite others, and insert new ones, over two overlays.
+    let mut expected = committed
+        .into_iter()
+        .map(|(k, v)| (k, v.unwrap()))
+        .collect::<BTreeMap<_, _>>();
+    let mut overlays = Vec::new();
+    for round in 0..2u32 {
+        let changes = (0..600)
+            .filter(|i| i % 7 == round)
+            .map(|i| {
+                let value = (i % 3 != 0).then(|| value(i + 1000 * (round + 1)));
+                (key(i), value)
+            })
+            .collect::<BTreeMap<_, _>>();
+        for (k, v) in &changes {
+            match v {
+                Some(v) => expected.insert(*k, v.clone()),
+                None => expected.remove(k),
+            };
+        }
+        let actuals = changes
+            .into_iter()
+            .map(|(k, v)| (k, KeyReadWrite::Write(v)))
+            .collect();
+        let params = SessionParams::default()
+            .overlay(overlays.iter().rev())
+            .unwrap();
+        let overlay = nomt
+            .begin_session(params)
+            .finish(actuals)
+            .unwrap()
+            .into_overlay();
+        overlays.push(overlay);
+    }
+
+    let session = nomt.begin_session(
+        SessionParams::default()
+            .overlay(overlays.iter().rev())
+            .unwrap(),
+    );
+    let keys = (0..600).step_by(61).map(key).collect::<Vec<_>>();
+    for range in ranges(&keys) {
+        assert_range(&session, &expected, range);
+    }
+}
@@ -0,0 +1,53 @@
+#![cfg(feature = "keccak-hasher")]
+
+mod common;
+
+use common::{clean_test_path, test_options};
+use nomt::{
+    hasher::{Keccak256Hasher, ValueHasher},
+    trie::LeafData,
+    KeyReadWrite, Nomt, SessionParams, WitnessMode,
+};
+
+#[test]
+fn witness_verifies_with_keccak() {
+    clean_test_path("keccak_witness");
+    let nomt = Nomt::<Keccak256Hasher>::open(test_options("keccak_witness")).unwrap();
+
+    let key = |i: u8| [i; 32];
+    let session = nomt.begin_session(SessionParams::default());
+    let actuals = (0..10)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(vec![i; 100]))))
+        .collect();
+    session.finish(actuals).unwrap().commit(&nomt).unwrap();
+    let prev_root = nomt.root();
+
+    let session =
+        nomt.begin_session(SessionParams::default().witness_mode(WitnessMode::read_write()));
+    let actuals = vec![
+        (key(3), KeyReadWrite::Read(Some(vec![3; 100]))),
+        (key(20), KeyReadWrite::Read(None)),
+    ];
+    let mut finished = session.finish(actuals).unwrap();
+    let witness = finished.take_witness().unwrap();
+
+    for (i, witnessed_path) in witness.path_proofs.iter().enumerate() {
+        let verified = witnessed_path
+            .inner
+            .verify::<Keccak256Hasher>(witnessed_path.path.path(), prev_root.into_inner())
+            .unwrap();
+        for read in witness.reads_for_path(i) {
+            match read.value {
+                None => assert!(verified.confirm_nonexistence(&read.key).unwrap()),
+                Some(ref value_hash) => {
+                    assert_eq!(*value_hash, Keccak256Hasher::hash_value(&[3; 100]));
+                    let leaf = LeafData {
+                        key_path: read.key,
+                        value_hash: *value_hash,
+                    };
+                    assert!(verified.confirm_value(&leaf).unwrap());
+                }
+            }
+        }
+    }
+}
@@ -19,3 +19,31 @@ fn large_values() {
     assert_eq!(&*t.read_id(0).unwrap(), &large1);
     assert!(t.read_id(1).is_none());
 }
+
+#[test]
+fn large_values_across_sync_workers() {
+    // Overflow values spread over the key space are written out by several beatree workers.
+    let mut t = Test::new_with_params("large_values_across_sync_workers", 4, 64_000, None, true);
+    let value = |id: u64| vec![id as u8; 2000 + (id as usize % 7) * 1000];
+
+    // Workers are only split at existing leaves.
+    for id in 0..500 {
+        t.write_id(id, Some(vec![1; 100]));
+    }
+    let _ = t.commit();
+    for id in 0..500 {
+        t.write_id(id, Some(value(id)));
+    }
+    let _ = t.commit();
+    for id in (0..500).step_by(2) {
+        t.write_id(id, None);
+    }
+    let _ = t.commit();
+    drop(t);
+
+    let mut t = Test::new_with_params("large_values_across_sync_workers", 4, 64_000, None, false);
+    for id in 0..500 {
+        let expected = (id % 2 == 1).then(|| value(id));
+        assert_eq!(t.read_id(id), expected);
+    }
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use common::setup_nomt;
+use nomt::{
+    hasher::Blake3Hasher, light_state::LightState, light_state::LightStateVerificationError,
+    KeyReadWrite, Nomt, Root, SessionParams,
+};
+
+fn key(i: u32) -> [u8; 32] {
+    let mut key = [0; 32];
+    key[..4].copy_from_slice(&i.wrapping_mul(0x9E3779B9).to_be_bytes());
+    key
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, keys: impl Iterator<Item = u32>) {
+    let mut actuals = keys
+        .map(|i| (key(i), KeyReadWrite::Write(Some(i.to_le_bytes().to_vec()))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(key, _)| *key);
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+}
+
+fn roundtrip(light_state: &LightState) -> LightState {
+    let mut encoded = Vec::new();
+    light_state.encode(&mut encoded).unwrap();
+    LightState::decode(&encoded[..]).unwrap()
+}
+
+#[test]
+fn export_and_verify() {
+    let nomt = setup_nomt("light_state_export");
+    commit(&nomt, 0..1000);
+
+    // Present and absent keys, in no particular order and with a duplicate.
+    let keys = [5, 999, 1000, 17, 5, 2000].map(key);
+    let light_state = roundtrip(&nomt.export_light_state(keys).unwrap());
+    assert_eq!(light_state.root, nomt.root());
+    assert_eq!(light_state.entries.len(), 5);
+    light_state.verify::<Blake3Hasher>(nomt.root()).unwrap();
+
+    assert_eq!(
+        light_state.get(&key(17)),
+        Some(Some(&17u32.to_le_bytes()[..]))
+    );
+    assert_eq!(light_state.get(&key(1000)), Some(None));
+    assert_eq!(light_state.get(&key(18)), None);
+
+    // The bundle stays valid for its own root only.
+    commit(&nomt, 1000..1001);
+    assert_eq!(
+        light_state.verify::<Blake3Hasher>(nomt.root()),
+        Err(LightStateVerificationError::RootMismatch)
+    );
+}
+
+#[test]
+fn tampering_detected() {
+    let nomt = setup_nomt("light_state_tampering");
+    commit(&nomt, 0..1000);
+    let light_state = nomt.export_light_state([1, 2, 3, 2000].map(key)).unwrap();
+    let root = nomt.root();
+
+    let mut changed_value = light_state.clone();
+    let index = changed_value
+        .entries
+        .iter()
+        .position(|(k, _)| *k == key(2))
+        .unwrap();
+    changed_value.entries[index].1 = Some(vec![0xFF]);
+    assert_eq!(
+        changed_value.verify::<Blake3Hasher>(root),
+        Err(LightStateVerificationError::ValueMismatch(key(2)))
+    );
+
+    let mut value_of_absent_key = light_state.clone();
+    let index = value_of_absent_key
+        .entries
+        .iter()
+        .position(|(k, _)| *k == key(2000))
+        .unwrap();
+    value_of_absent_key.entries[index].1 = Some(vec![0xFF]);
+    assert_eq!(
+        value_of_absent_key.verify::<Blake3Hasher>(root),
+        Err(LightStateVerificationError::ValueMismatch(key(2000)))
+    );
+
+    let mut unproven_key = light_state.clone();
+    unproven_key.entries.push(([0xFF; 32], None));
+    assert_eq!(
+        unproven_key.verify::<Blake3Hasher>(root),
+        Err(LightStateVerificationError::KeyNotProven([0xFF; 32]))
+    );
+
+    let mut changed_sibling = light_state.clone();
+    changed_sibling.proof.siblings[0][0] ^= 1;
+    assert!(matches!(
+        changed_sibling.verify::<Blake3Hasher>(root),
+        Err(LightStateVerificationError::InvalidProof(_))
+    ));
+
+    let mut forged_root = light_state;
+    forged_root.root = Root::from([0xAA; 32]);
+    assert!(forged_root
+        .verify::<Blake3Hasher>(Root::from([0xAA; 32]))
+        .is_err());
+}
+
+#[test]
+fn hot_keys_are_exported() {
+    let nomt = setup_nomt("light_state_hot_keys");
+    commit(&nomt, 0..1000);
+    assert!(nomt.read(key(42)).unwrap().is_some());
+
+    let hot_keys = nomt.hot_keys(100);
+    assert!(!hot_keys.is_empty() && hot_keys.len() <= 100);
+    let light_state = roundtrip(&nomt.export_light_state(hot_keys.clone()).unwrap());
+    light_state.verify::<Blake3Hasher>(nomt.root()).unwrap();
+    for key in hot_keys {
+        assert!(light_state.get(&key).unwrap().is_some());
+    }
+}
+
+#[test]
+fn empty_database() {
+    let nomt = setup_nomt("light_state_empty");
+    let light_state = roundtrip(&nomt.export_light_state([key(1), key(2)]).unwrap());
+    light_state.verify::<Blake3Hasher>(nomt.root()).unwrap();
+    assert_eq!(light_state.get(&key(1)), Some(None));
+}
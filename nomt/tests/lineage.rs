@@ -0,0 +1,133 @@
+mod common;
+
+use common::{clean_test_path, test_options, test_path};
+use nomt::{
+    hasher::Blake3Hasher, KeyReadWrite, LineageOrigin, LineageRollback, Nomt, SessionParams,
+};
+use std::path::Path;
+
+fn open(name: &str, rollback: bool) -> Nomt<Blake3Hasher> {
+    common::open_nomt(name, |o| o.rollback(rollback))
+}
+
+fn setup_nomt(name: &str, rollback: bool) -> Nomt<Blake3Hasher> {
+    common::setup_nomt_with(name, |o| o.rollback(rollback))
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, key: u8, value: Vec<u8>) {
+    nomt.begin_session(SessionParams::default())
+        .finish(vec![([key; 32], KeyReadWrite::Write(Some(value)))])
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+}
+
+#[test]
+fn new_database_has_stable_id() {
+    let nomt = setup_nomt("lineage_new", false);
+    let lineage = nomt.lineage();
+    assert!(!lineage.id.is_nil());
+    assert_eq!(lineage.origin, None);
+    assert_eq!(lineage.last_rollback, None);
+
+    commit(&nomt, 1, vec![1]);
+    drop(nomt);
+
+    let nomt = open("lineage_new", false);
+    assert_eq!(nomt.lineage(), lineage);
+}
+
+#[test]
+fn clone_records_origin() {
+    let nomt = setup_nomt("lineage_clone_src", false);
+    commit(&nomt, 1, vec![1, 2, 3]);
+    commit(&nomt, 2, vec![4, 5, 6]);
+
+    let dest = clean_test_path("lineage_clone_dst");
+    nomt.clone_to(&dest).unwrap();
+    // The destination is no longer empty.
+    assert!(nomt.clone_to(&dest).is_err());
+
+    let clone = open("lineage_clone_dst", false);
+    let lineage = clone.lineage();
+    assert!(!lineage.id.is_nil());
+    assert_ne!(lineage.id, nomt.lineage().id);
+    assert_eq!(
+        lineage.origin,
+        Some(LineageOrigin {
+            parent: nomt.lineage().id,
+            parent_sync_seqn: nomt.sync_seqn(),
+        })
+    );
+    assert_eq!(clone.root(), nomt.root());
+    assert_eq!(clone.read([1; 32]).unwrap(), Some(vec![1, 2, 3]));
+    assert_eq!(clone.read([2; 32]).unwrap(), Some(vec![4, 5, 6]));
+
+    // The clone is independent of the original.
+    commit(&clone, 3, vec![7]);
+    assert_ne!(clone.root(), nomt.root());
+    assert_eq!(nomt.read([3; 32]).unwrap(), None);
+}
+
+#[test]
+fn rollback_is_recorded() {
+    let nomt = setup_nomt("lineage_rollback", true);
+    for i in 1..=3 {
+        commit(&nomt, i, vec![i]);
+    }
+    let from_sync_seqn = nomt.sync_seqn();
+    nomt.rollback(2).unwrap();
+
+    let expected = Some(LineageRollback {
+        from_sync_seqn,
+        commits: 2,
+    });
+    assert_eq!(nomt.lineage().last_rollback, expected);
+    drop(nomt);
+
+    let nomt = open("lineage_rollback", true);
+    assert_eq!(nomt.lineage().last_rollback, expected);
+}
+
+// Rewrite the meta of a closed database as one of format version 1, before the lineage.
+fn downgrade_to_v1(path: &Path) {
+    let meta_path = path.join("meta");
+    let mut meta = std::fs::read(&meta_path).unwrap();
+    meta[4..8].copy_from_slice(&1u32.to_le_bytes());
+    std::fs::write(&meta_path, meta).unwrap();
+}
+
+fn meta_version(path: &Path) -> u32 {
+    let meta = std::fs::read(path.join("meta")).unwrap();
+    u32::from_le_bytes(meta[4..8].try_into().unwrap())
+}
+
+#[test]
+fn old_format_is_upgraded_only_on_request() {
+    let path = test_path("lineage_upgrade");
+    let nomt = setup_nomt("lineage_upgrade", false);
+    commit(&nomt, 1, vec![1]);
+    drop(nomt);
+    downgrade_to_v1(&path);
+
+    // Opened as usual, the database keeps its format through commits.
+    let nomt = open("lineage_upgrade", false);
+    assert!(nomt.lineage().id.is_nil());
+    commit(&nomt, 2, vec![2]);
+    drop(nomt);
+    assert_eq!(meta_version(&path), 1);
+
+    let upgrade = || {
+        let mut o = test_options("lineage_upgrade");
+        o.upgrade_format(true);
+        o
+    };
+    assert!(Nomt::<Blake3Hasher>::open_read_only(upgrade()).is_err());
+    let nomt = Nomt::<Blake3Hasher>::open(upgrade()).unwrap();
+    let lineage = nomt.lineage();
+    assert!(!lineage.id.is_nil());
+    assert_eq!(nomt.read([2; 32]).unwrap(), Some(vec![2]));
+    drop(nomt);
+    assert_eq!(meta_version(&path), 4);
+    assert_eq!(open("lineage_upgrade", false).lineage(), lineage);
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use nomt::{
+    hasher::Blake3Hasher,
+    metrics::{Histogram, SyncStage},
+    KeyReadWrite, Nomt, SessionParams,
+};
+
+fn setup_nomt(name: &str, metrics: bool) -> Nomt<Blake3Hasher> {
+    common::setup_nomt_with(name, |o| o.metrics(metrics))
+}
+
+// Commit many keys and read some of them back.
+fn populate(nomt: &Nomt<Blake3Hasher>) {
+    let actuals = (0..5_000u32)
+        .map(|i| *blake3::hash(&i.to_le_bytes()).as_bytes())
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .map(|key| (key, KeyReadWrite::Write(Some(vec![1; 64]))))
+        .collect();
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+
+    let session = nomt.begin_session(SessionParams::default());
+    for i in 0..100u32 {
+        let key = *blake3::hash(&i.to_le_bytes()).as_bytes();
+        assert_eq!(session.read(key).unwrap(), Some(vec![1; 64]));
+        session.prove(key).unwrap();
+    }
+}
+
+fn assert_consistent(histogram: &Histogram) {
+    let bucketed: u64 = histogram.buckets.iter().map(|(_, n)| n).sum();
+    assert_eq!(bucketed, histogram.count);
+    assert!(histogram.buckets.windows(2).all(|w| w[0].0 < w[1].0));
+}
+
+#[test]
+fn metrics_are_collected() {
+    let nomt = setup_nomt("metrics_collected", true);
+    let metrics = nomt.metrics();
+    assert!(metrics.is_enabled());
+
+    populate(&nomt);
+
+    let hit_rate = metrics.get_page_cache_hit_rate().unwrap();
+    assert!((0.0..=1.0).contains(&hit_rate));
+
+    for stage in [
+        SyncStage::Wal,
+        SyncStage::Values,
+        SyncStage::Meta,
+        SyncStage::PostMeta,
+    ] {
+        let sync_time = metrics.get_sync_time(stage);
+        assert_consistent(&sync_time);
+        assert_eq!(sync_time.count, 1, "{:?}", stage);
+    }
+    // Syncs which aren't checkpointed write no journal.
+    assert_eq!(metrics.get_sync_time(SyncStage::Journal).count, 0);
+
+    let fanout = metrics.get_leaf_fanout();
+    assert_consistent(&fanout);
+    assert!(fanout.count > 0);
+    assert_eq!(fanout.sum, 5_000);
+
+    let value_fetch = metrics.get_value_fetch_times();
+    assert_consistent(&value_fetch);
+    assert_eq!(value_fetch.count, 100);
+    assert!(value_fetch.quantile(0.5) <= value_fetch.quantile(0.99));
+
+    assert_consistent(&metrics.get_io_queue_depth());
+}
+
+#[test]
+fn disabled_metrics() {
+    let nomt = setup_nomt("metrics_disabled", false);
+    populate(&nomt);
+    assert!(!nomt.metrics().is_enabled());
+    #[cfg(feature = "prometheus")]
+    assert!(nomt.metrics().render_prometheus().is_empty());
+}
+
+#[cfg(feature = "prometheus")]
+#[test]
+fn render_prometheus() {
+    let nomt = setup_nomt("metrics_prometheus", true);
+    populate(&nomt);
+
+    let rendered = nomt.metrics().render_prometheus();
+    let value = |line: &str| {
+        rendered
+            .lines()
+            .find_map(|l| l.strip_prefix(line)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("missing {line}"))
+            .to_string()
+    };
+
+    assert!(rendered.contains("# TYPE nomt_page_requests_total counter\n"));
+    assert!(rendered.contains("# TYPE nomt_sync_phase_seconds histogram\n"));
+    assert_eq!(value("nomt_beatree_leaf_fanout_sum"), "5000");
+    assert_eq!(value("nomt_sync_phase_seconds_count{phase=\"wal\"}"), "1");
+    assert_eq!(
+        value("nomt_sync_phase_seconds_bucket{phase=\"meta\",le=\"+Inf\"}"),
+        "1"
+    );
+    assert_eq!(value("nomt_value_fetch_seconds_count"), "100");
+    let hit_ratio: f64 = value("nomt_page_cache_hit_ratio").parse().unwrap();
+    assert!((0.0..=1.0).contains(&hit_ratio));
+}
@@ -0,0 +1,166 @@
+mod common;
+
+use common::{clean_test_path, test_options, test_path, Test};
+use nomt::{
+    hasher::{Blake3Hasher, Sha2Hasher},
+    migration::{self, MigrationOptions},
+    trie::KeyPath,
+    CancellationToken, Cancelled, Nomt,
+};
+
+fn open_dest(name: &str) -> Nomt<Sha2Hasher> {
+    clean_test_path(name);
+    let mut o = test_options(name);
+    o.bitbox_seed([0; 16]);
+    o.io_workers(1);
+    Nomt::open(o).unwrap()
+}
+
+fn remap(key: &KeyPath, _value: &[u8]) -> KeyPath {
+    *blake3::hash(key).as_bytes()
+}
+
+fn populate(name: &str, n: u64) -> Test {
+    let mut t = Test::new(name);
+    for i in 0..n {
+        common::set_balance(&mut t, i, i);
+    }
+    t.commit();
+    t
+}
+
+fn expected_root(n: u64) -> [u8; 32] {
+    let mut ops = (0..n)
+        .map(|i| {
+            let key = remap(&common::account_path(i), &[]);
+            let value = <Sha2Hasher as nomt::hasher::ValueHasher>::hash_value(&i.to_le_bytes());
+            (key, value)
+        })
+        .collect::<Vec<_>>();
+    ops.sort_unstable_by_key(|(k, _)| *k);
+    nomt_core::update::build_trie::<Sha2Hasher>(0, ops, |_| {})
+}
+
+#[test]
+fn migrate_to_new_hasher() {
+    let source = populate("migration_source", 1000);
+    let dest = open_dest("migration_dest");
+
+    let mut options = MigrationOptions::new();
+    options.batch_size(128);
+    let report =
+        migration::migrate::<Blake3Hasher, Sha2Hasher>(source.nomt(), &dest, &options, remap)
+            .unwrap();
+
+    assert_eq!(report.migrated, 1000);
+    assert_eq!(report.batches, 8);
+    assert!(!report.resumed);
+    assert_eq!(report.root.into_inner(), expected_root(1000));
+    assert_eq!(dest.root(), report.root);
+
+    let key = remap(&common::account_path(7), &[]);
+    assert_eq!(dest.read(key).unwrap(), Some(7u64.to_le_bytes().to_vec()));
+}
+
+#[test]
+fn migration_resumes_from_checkpoint() {
+    let source = populate("migration_resume_source", 1000);
+    let dest = open_dest("migration_resume_dest");
+    let checkpoint = test_path("migration_resume_checkpoint");
+    let _ = std::fs::remove_file(&checkpoint);
+
+    let mut options = MigrationOptions::new();
+    options.batch_size(100);
+    options.checkpoint_path(&checkpoint);
+
+    // Interrupt the migration in the middle of the fourth batch.
+    let mut calls = 0;
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        migration::migrate::<Blake3Hasher, Sha2Hasher>(
+            source.nomt(),
+            &dest,
+            &options,
+            |key, value| {
+                calls += 1;
+                if calls == 350 {
+                    panic!("interrupted");
+                }
+                remap(key, value)
+            },
+        )
+    }));
+    assert!(res.is_err());
+    assert!(!dest.is_empty());
+
+    let report =
+        migration::migrate::<Blake3Hasher, Sha2Hasher>(source.nomt(), &dest, &options, remap)
+            .unwrap();
+
+    assert!(report.resumed);
+    assert_eq!(report.migrated, 1000);
+    assert_eq!(report.batches, 7);
+    assert_eq!(report.root.into_inner(), expected_root(1000));
+
+    // A completed migration is a no-op when run again.
+    let report =
+        migration::migrate::<Blake3Hasher, Sha2Hasher>(source.nomt(), &dest, &options, remap)
+            .unwrap();
+    assert_eq!(report.batches, 0);
+    assert_eq!(report.migrated, 1000);
+}
+
+#[test]
+fn migration_rejects_collisions() {
+    let source = populate("migration_collision_source", 10);
+    let dest = open_dest("migration_collision_dest");
+
+    let res = migration::migrate::<Blake3Hasher, Sha2Hasher>(
+        source.nomt(),
+        &dest,
+        &MigrationOptions::new(),
+        |_, _| [1; 32],
+    );
+    assert!(res.unwrap_err().to_string().contains("collision"));
+}
+
+#[test]
+fn cancelled_migration_resumes() {
+    let source = populate("migration_cancel_source", 1000);
+    let dest = open_dest("migration_cancel_dest");
+    let checkpoint = test_path("migration_cancel_checkpoint");
+    let _ = std::fs::remove_file(&checkpoint);
+
+    let cancel = CancellationToken::new();
+    let mut options = MigrationOptions::new();
+    options.batch_size(100);
+    options.checkpoint_path(&checkpoint);
+    options.cancellation_token(cancel.clone());
+
+    // Cancel in the middle of the fourth batch. It is not committed.
+    let mut calls = 0;
+    let err = migration::migrate::<Blake3Hasher, Sha2Hasher>(
+        source.nomt(),
+        &dest,
+        &options,
+        |key, value| {
+            calls += 1;
+            if calls == 350 {
+                cancel.cancel();
+            }
+            remap(key, value)
+        },
+    )
+    .unwrap_err();
+    assert!(err.downcast_ref::<Cancelled>().is_some());
+    assert_eq!(calls, 400);
+
+    let mut options = MigrationOptions::new();
+    options.batch_size(100);
+    options.checkpoint_path(&checkpoint);
+    let report =
+        migration::migrate::<Blake3Hasher, Sha2Hasher>(source.nomt(), &dest, &options, remap)
+            .unwrap();
+    assert!(report.resumed);
+    assert_eq!(report.batches, 7);
+    assert_eq!(report.root.into_inner(), expected_root(1000));
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use nomt::{
+    hasher::{Blake3Hasher, NodeHasher},
+    namespace::{KeyOutsideNamespace, Namespace},
+    proof::verify_prefix,
+    trie::{KeyPath, LeafData, TERMINATOR},
+    KeyReadWrite, Nomt, SessionParams, WitnessMode,
+};
+
+fn setup_nomt(name: &str, namespace_bits: Option<usize>) -> Nomt<Blake3Hasher> {
+    common::setup_nomt_with(name, |o| {
+        if let Some(bits) = namespace_bits {
+            o.namespace_bits(bits);
+        }
+    })
+}
+
+fn key(namespace: Namespace, i: u32) -> KeyPath {
+    namespace.key_path(*blake3::hash(&i.to_le_bytes()).as_bytes())
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, namespace: Namespace, keys: std::ops::Range<u32>, value: u8) {
+    let mut actuals = keys
+        .map(|i| (key(namespace, i), KeyReadWrite::Write(Some(vec![value; 8]))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.begin_session(SessionParams::default().namespace(namespace))
+        .finish(actuals)
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+}
+
+#[test]
+fn namespace_roots_are_independent() {
+    let nomt = setup_nomt("namespace_roots_independent", None);
+    let state = Namespace::new(0, 2);
+    let receipts = Namespace::new(1, 2);
+    let storage = Namespace::new(2, 2);
+
+    commit(&nomt, state, 0..100, 1);
+    commit(&nomt, receipts, 0..100, 1);
+    let state_root = nomt.namespace_root(state).unwrap();
+    let receipts_root = nomt.namespace_root(receipts).unwrap();
+    assert_ne!(state_root, receipts_root);
+    assert_eq!(nomt.namespace_root(storage).unwrap(), TERMINATOR);
+
+    // Writing one namespace leaves the roots of the others alone.
+    commit(&nomt, receipts, 50..150, 2);
+    assert_eq!(nomt.namespace_root(state).unwrap(), state_root);
+    assert_ne!(nomt.namespace_root(receipts).unwrap(), receipts_root);
+
+    // The root of a namespace is proven against the root of the database.
+    for namespace in [state, receipts, storage] {
+        let proven = nomt.prove_prefix(&namespace.prefix(), 0).unwrap();
+        let verified = verify_prefix::<Blake3Hasher>(
+            &proven.proof,
+            &namespace.prefix(),
+            nomt.root().into_inner(),
+        )
+        .unwrap();
+        assert_eq!(
+            verified.subtree_root(),
+            nomt.namespace_root(namespace).unwrap()
+        );
+    }
+    assert!(nomt.commit_stats().unwrap().namespace_roots.is_empty());
+}
+
+#[test]
+fn commits_record_namespace_roots() {
+    let nomt = setup_nomt("namespace_commit_stats", Some(2));
+    let state = Namespace::new(0, 2);
+    let receipts = Namespace::new(1, 2);
+    let namespace_roots = |nomt: &Nomt<Blake3Hasher>| {
+        (0..4)
+            .map(|i| nomt.namespace_root(Namespace::new(i, 2)).unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(nomt.commit_stats(), None);
+
+    commit(&nomt, state, 0..100, 1);
+    commit(&nomt, receipts, 0..10, 1);
+    let stats = nomt.commit_stats().unwrap();
+    assert_eq!(stats.root, nomt.root());
+    assert_eq!(stats.namespace_roots, namespace_roots(&nomt));
+    assert_eq!(stats.namespace_roots[2], TERMINATOR);
+
+    // Overlays record them as well.
+    let mut actuals = (0..10)
+        .map(|i| (key(receipts, i), KeyReadWrite::Write(Some(vec![2; 8]))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .into_overlay()
+        .commit(&nomt)
+        .unwrap();
+    let overlay_stats = nomt.commit_stats().unwrap();
+    assert_eq!(overlay_stats.root, nomt.root());
+    assert_eq!(overlay_stats.namespace_roots, namespace_roots(&nomt));
+    assert_eq!(overlay_stats.namespace_roots[0], stats.namespace_roots[0]);
+    assert_ne!(overlay_stats.namespace_roots[1], stats.namespace_roots[1]);
+}
+
+#[test]
+fn namespace_with_a_single_key() {
+    let nomt = setup_nomt("namespace_single_key", None);
+    let a = Namespace::new(0, 1);
+    let b = Namespace::new(1, 1);
+    commit(&nomt, a, 0..10, 1);
+    commit(&nomt, b, 0..1, 1);
+
+    let leaf = LeafData {
+        key_path: key(b, 0),
+        value_hash: *blake3::hash(&[1; 8]).as_bytes(),
+    };
+    assert_eq!(
+        nomt.namespace_root(b).unwrap(),
+        Blake3Hasher::hash_leaf(&leaf)
+    );
+}
+
+#[test]
+fn session_confined_to_namespace() {
+    let nomt = setup_nomt("namespace_session_confined", None);
+    let state = Namespace::new(0, 2);
+    let receipts = Namespace::new(1, 2);
+    commit(&nomt, receipts, 0..10, 1);
+
+    let outside = key(receipts, 0);
+    let err = nomt
+        .begin_session(SessionParams::default().namespace(state))
+        .finish(vec![(outside, KeyReadWrite::Read(Some(vec![1; 8])))])
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.downcast_ref::<KeyOutsideNamespace>(),
+        Some(&KeyOutsideNamespace {
+            namespace: state,
+            key: outside,
+        })
+    );
+
+    let session = nomt.begin_session(
+        SessionParams::default()
+            .namespace(state)
+            .witness_mode(WitnessMode::read_write()),
+    );
+    let mut actuals = (0..10)
+        .map(|i| (key(state, i), KeyReadWrite::Write(Some(vec![2; 8]))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    let mut finished = session.finish(actuals).unwrap();
+    let witness = finished.take_witness().unwrap();
+    assert!(witness
+        .path_proofs
+        .iter()
+        .all(|p| p.path.path().starts_with(&state.prefix())));
+}
+
+#[test]
+fn key_paths_carry_the_prefix() {
+    let namespace = Namespace::new(5, 3);
+    let key = namespace.key_path([0xFF; 32]);
+    assert_eq!(key[0], 0b1011_1111);
+    assert_eq!(&key[1..], &[0xFF; 31]);
+    assert!(namespace.contains(&key));
+    assert!(!Namespace::new(4, 3).contains(&key));
+    assert_eq!(namespace.prefix().len(), 3);
+
+    let namespace = Namespace::new(200, 8);
+    let key = namespace.key_path([0xFF; 32]);
+    assert_eq!(key[0], 200);
+    assert!(namespace.contains(&key));
+}
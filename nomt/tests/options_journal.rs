@@ -0,0 +1,47 @@
+mod common;
+
+use common::clean_test_path;
+use nomt::{hasher::Blake3Hasher, KeyReadWrite, Nomt, SessionParams};
+
+fn open(name: &str, page_cache_size: usize) -> Nomt<Blake3Hasher> {
+    common::open_nomt(name, |o| {
+        o.hashtable_buckets(10_000);
+        o.page_cache_size(page_cache_size);
+    })
+}
+
+#[test]
+fn options_changes_are_journaled() {
+    let name = "options_journal";
+    clean_test_path(name);
+
+    let nomt = open(name, 64);
+    let session = nomt.begin_session(SessionParams::default());
+    session
+        .finish(vec![([1; 32], KeyReadWrite::Write(Some(vec![1])))])
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+    let journal = nomt.options_journal().unwrap();
+    assert_eq!(journal.len(), 1);
+    assert_eq!(journal[0].sync_seqn, 0);
+    assert_eq!(journal[0].get("page_cache_size"), Some("64"));
+    assert_eq!(journal[0].get("hashtable_buckets"), Some("10000"));
+    drop(nomt);
+
+    // Reopening with the same options records nothing.
+    let nomt = open(name, 64);
+    assert_eq!(nomt.options_journal().unwrap(), journal);
+    drop(nomt);
+
+    // Creation-time options given to an existing database are ignored, and not recorded.
+    let nomt = common::open_nomt(name, |o| {
+        o.hashtable_buckets(20_000);
+        o.page_cache_size(128);
+    });
+    let journal = nomt.options_journal().unwrap();
+    assert_eq!(journal.len(), 2);
+    assert_eq!(journal[1].sync_seqn, 1);
+    assert_eq!(journal[1].get("page_cache_size"), Some("128"));
+    assert_eq!(journal[1].get("hashtable_buckets"), Some("10000"));
+}
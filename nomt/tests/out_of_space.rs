@@ -0,0 +1,165 @@
+mod common;
+
+use common::account_path;
+use nomt::{
+    hasher::Blake3Hasher, KeyReadWrite, Nomt, Options, OutOfSpace, Root, SessionParams, SyncPhase,
+};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+fn open(
+    name: &str,
+    clean: bool,
+    checkpoint_interval: Option<usize>,
+    out_of_space: Option<(SyncPhase, Arc<AtomicBool>)>,
+) -> Nomt<Blake3Hasher> {
+    let configure = |o: &mut Options| {
+        o.hashtable_buckets(64_000);
+        o.rollback(true);
+        if let Some(interval) = checkpoint_interval {
+            o.sync_checkpoint_interval(interval);
+        }
+        if let Some((phase, trigger)) = out_of_space {
+            o.simulate_out_of_space(phase, trigger);
+        }
+    };
+    if clean {
+        common::setup_nomt_with(name, configure)
+    } else {
+        common::open_nomt(name, configure)
+    }
+}
+
+fn value(id: u64, version: u8) -> Vec<u8> {
+    let mut value = id.to_le_bytes().to_vec();
+    value.push(version);
+    value
+}
+
+// Commit the accounts in the range, returning the new root and the result of the commit.
+fn commit(
+    nomt: &Nomt<Blake3Hasher>,
+    ids: std::ops::Range<u64>,
+    version: u8,
+) -> (Root, Result<(), nomt::Error>) {
+    let session = nomt.begin_session(SessionParams::default());
+    let mut actuals = ids
+        .map(|id| {
+            (
+                account_path(id),
+                KeyReadWrite::Write(Some(value(id, version))),
+            )
+        })
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    let finished = session.finish(actuals).unwrap();
+    let root = finished.root();
+    (root, finished.commit(nomt))
+}
+
+fn out_of_space_phase(err: &nomt::Error) -> Option<SyncPhase> {
+    err.downcast_ref::<OutOfSpace>().map(|e| e.phase)
+}
+
+fn stall_and_resume(name: &str, phase: SyncPhase, checkpoint_interval: Option<usize>) {
+    let trigger = Arc::new(AtomicBool::new(false));
+    let nomt = open(
+        name,
+        true,
+        checkpoint_interval,
+        Some((phase, trigger.clone())),
+    );
+    let (root_1, result) = commit(&nomt, 0..500, 1);
+    result.unwrap();
+
+    trigger.store(true, Ordering::Relaxed);
+    let (root_2, result) = commit(&nomt, 0..1000, 2);
+    let err = result.unwrap_err();
+    assert!(matches!(err, nomt::Error::OutOfSpace(_)));
+    assert_eq!(out_of_space_phase(&err), Some(phase));
+    assert!(!nomt.is_poisoned());
+    assert_eq!(nomt.stalled_sync(), Some(phase));
+
+    // The pending commit is visible, but no further commits are accepted.
+    assert_eq!(nomt.root(), root_2);
+    assert_eq!(nomt.read(account_path(700)).unwrap(), Some(value(700, 2)));
+    let (_, result) = commit(&nomt, 1000..1100, 3);
+    assert_eq!(out_of_space_phase(&result.unwrap_err()), None);
+
+    // Resuming fails for as long as the disk is full. A checkpointed sync which stalled is
+    // completed at once, so it has no further use for a journal.
+    if phase != SyncPhase::Journal {
+        assert_eq!(
+            out_of_space_phase(&nomt.resume_sync().unwrap_err()),
+            Some(phase)
+        );
+    }
+    trigger.store(false, Ordering::Relaxed);
+    assert!(nomt.resume_sync().unwrap());
+    assert!(!nomt.resume_sync().unwrap());
+    assert_eq!(nomt.stalled_sync(), None);
+
+    let (root_3, result) = commit(&nomt, 1000..1100, 3);
+    result.unwrap();
+    drop(nomt);
+
+    let nomt = open(name, false, checkpoint_interval, None);
+    assert_eq!(nomt.root(), root_3);
+    for id in (0..1100).step_by(7) {
+        let version = if id < 1000 { 2 } else { 3 };
+        assert_eq!(
+            nomt.read(account_path(id)).unwrap(),
+            Some(value(id, version))
+        );
+    }
+
+    // The rollback log recorded the resumed commit.
+    nomt.rollback(2).unwrap();
+    assert_eq!(nomt.root(), root_1);
+    assert_eq!(nomt.read(account_path(700)).unwrap(), None);
+}
+
+#[test]
+fn resume_after_wal_out_of_space() {
+    stall_and_resume("out_of_space_wal", SyncPhase::Wal, None);
+}
+
+#[test]
+fn resume_after_values_out_of_space() {
+    stall_and_resume("out_of_space_values", SyncPhase::Values, None);
+}
+
+#[test]
+fn resume_after_journal_out_of_space() {
+    stall_and_resume("out_of_space_journal", SyncPhase::Journal, Some(100));
+}
+
+#[test]
+fn resume_after_checkpoint_out_of_space() {
+    stall_and_resume("out_of_space_checkpoint", SyncPhase::Values, Some(100));
+}
+
+#[test]
+fn stalled_commit_is_lost_on_reopen() {
+    let trigger = Arc::new(AtomicBool::new(false));
+    let nomt = open(
+        "out_of_space_reopen",
+        true,
+        None,
+        Some((SyncPhase::Values, trigger.clone())),
+    );
+    let (root_1, result) = commit(&nomt, 0..500, 1);
+    result.unwrap();
+
+    trigger.store(true, Ordering::Relaxed);
+    let (_, result) = commit(&nomt, 0..1000, 2);
+    assert!(result.is_err());
+    drop(nomt);
+
+    let nomt = open("out_of_space_reopen", false, None, None);
+    assert_eq!(nomt.root(), root_1);
+    assert_eq!(nomt.read(account_path(100)).unwrap(), Some(value(100, 1)));
+    assert_eq!(nomt.read(account_path(700)).unwrap(), None);
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use nomt::{hasher::Blake3Hasher, trie::KeyPath, KeyReadWrite, Nomt, Overlay, SessionParams};
+
+fn setup_nomt(name: &str, budget: Option<usize>) -> Nomt<Blake3Hasher> {
+    common::setup_nomt_with(name, |o| {
+        if let Some(budget) = budget {
+            o.overlay_memory_budget(budget);
+        }
+    })
+}
+
+fn key(i: u32) -> KeyPath {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+fn value(i: u32, round: u8) -> Vec<u8> {
+    vec![round; 500 + i as usize % 3000]
+}
+
+// Build a chain of overlays, each writing its own round of values to the same keys and deleting
+// a few of the previous round.
+fn build_chain(nomt: &Nomt<Blake3Hasher>) -> Vec<Overlay> {
+    let mut overlays: Vec<Overlay> = Vec::new();
+    for round in 0..4u8 {
+        let mut actuals = (0..100)
+            .map(|i| {
+                let write = if round > 0 && i % 10 == 0 {
+                    None
+                } else {
+                    Some(value(i, round))
+                };
+                (key(i), KeyReadWrite::Write(write))
+            })
+            .collect::<Vec<_>>();
+        actuals.sort_by_key(|(k, _)| *k);
+
+        let params = SessionParams::default()
+            .overlay(overlays.iter().rev())
+            .unwrap();
+        let overlay = nomt
+            .begin_session(params)
+            .finish(actuals)
+            .unwrap()
+            .into_overlay();
+        overlays.push(overlay);
+    }
+    overlays
+}
+
+#[test]
+fn spilled_overlays_read_back() {
+    let nomt = setup_nomt("overlay_spill_read_back", Some(100_000));
+    let overlays = build_chain(&nomt);
+
+    // The oldest overlays were spilled, the newest fit the budget.
+    assert_eq!(overlays[0].resident_bytes(), 0);
+    assert!(overlays.iter().map(|o| o.resident_bytes()).sum::<usize>() <= 100_000);
+    assert!(overlays[3].resident_bytes() > 0);
+    // The segment is unlinked.
+    assert!(!common::test_path("overlay_spill_read_back")
+        .join("overlay_spill")
+        .exists());
+
+    let session = nomt.begin_session(
+        SessionParams::default()
+            .overlay(overlays.iter().rev())
+            .unwrap(),
+    );
+    for i in 0..100 {
+        let expected = (i % 10 != 0).then(|| value(i, 3));
+        assert_eq!(session.read(key(i)).unwrap(), expected);
+    }
+    let ranged = session
+        .iter_range(..)
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(ranged.len(), 90);
+    drop(session);
+
+    // Only the first overlay is in the chain.
+    let session = nomt.begin_session(SessionParams::default().overlay([&overlays[0]]).unwrap());
+    assert_eq!(session.read(key(10)).unwrap(), Some(value(10, 0)));
+    drop(session);
+
+    // The roots match the ones computed without a budget.
+    let unbounded = setup_nomt("overlay_spill_unbounded", None);
+    let expected = build_chain(&unbounded);
+    for (overlay, expected) in overlays.iter().zip(&expected) {
+        assert_eq!(overlay.root(), expected.root());
+    }
+    assert!(expected[0].resident_bytes() > 0);
+
+    // Committing spilled overlays writes the values read back.
+    for overlay in overlays {
+        overlay.commit(&nomt).unwrap();
+    }
+    for i in 0..100 {
+        let expected = (i % 10 != 0).then(|| value(i, 3));
+        assert_eq!(nomt.get(key(i)).unwrap(), expected);
+    }
+    assert_eq!(nomt.root(), expected[3].root());
+}
+
+#[test]
+fn dropped_overlays_free_the_budget() {
+    let nomt = setup_nomt("overlay_spill_dropped", Some(100_000));
+    drop(build_chain(&nomt));
+
+    // With the old overlays gone, a new one within the budget stays in memory.
+    let actuals = (0..10)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(value(i, 7)))))
+        .collect::<std::collections::BTreeMap<_, _>>()
+        .into_iter()
+        .collect();
+    let overlay = nomt
+        .begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .into_overlay();
+    assert_eq!(
+        overlay.resident_bytes(),
+        (0..10).map(|i| value(i, 7).len()).sum::<usize>()
+    );
+    let session = nomt.begin_session(SessionParams::default().overlay([&overlay]).unwrap());
+    assert_eq!(session.read(key(1)).unwrap(), Some(value(1, 7)));
+}
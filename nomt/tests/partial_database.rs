@@ -0,0 +1,87 @@
+mod common;
+
+use common::{clean_test_path, test_options};
+use nomt::{hasher::Blake3Hasher, KeyReadWrite, Nomt, PartialDatabase, SessionParams};
+
+fn open(name: &str) -> Result<Nomt<Blake3Hasher>, nomt::Error> {
+    Nomt::open(test_options(name))
+}
+
+fn partial(err: nomt::Error) -> PartialDatabase {
+    match err.into_anyhow().downcast::<PartialDatabase>() {
+        Ok(partial) => partial,
+        Err(err) => panic!("not a partial database: {err:?}"),
+    }
+}
+
+#[test]
+fn unwritten_meta_is_incomplete_creation() {
+    let name = "partial_unwritten_meta";
+    let path = clean_test_path(name);
+    std::fs::create_dir_all(&path).unwrap();
+    std::fs::write(path.join("meta"), vec![0; 4096]).unwrap();
+    std::fs::write(path.join("ht"), b"").unwrap();
+
+    let err = partial(open(name).err().unwrap());
+    assert!(err.is_safe_to_delete());
+    match err {
+        PartialDatabase::CreationIncomplete { missing } => {
+            assert_eq!(missing, vec!["meta", "wal", "ln", "bbn"])
+        }
+        err => panic!("unexpected error: {err}"),
+    }
+
+    // Following the advice recreates the database.
+    std::fs::remove_dir_all(&path).unwrap();
+    open(name).unwrap();
+}
+
+#[test]
+fn missing_files_before_first_sync_are_incomplete_creation() {
+    let name = "partial_missing_files";
+    let path = clean_test_path(name);
+    drop(open(name).unwrap());
+    std::fs::remove_file(path.join("bbn")).unwrap();
+
+    match partial(open(name).err().unwrap()) {
+        PartialDatabase::CreationIncomplete { missing } => assert_eq!(missing, vec!["bbn"]),
+        err => panic!("unexpected error: {err}"),
+    }
+}
+
+#[test]
+fn missing_files_after_sync_are_not_incomplete_creation() {
+    let name = "partial_synced";
+    let path = clean_test_path(name);
+    let nomt = open(name).unwrap();
+    nomt.begin_session(SessionParams::default())
+        .finish(vec![([1; 32], KeyReadWrite::Write(Some(vec![1])))])
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+    drop(nomt);
+    std::fs::remove_file(path.join("bbn")).unwrap();
+
+    let err = open(name).err().unwrap();
+    assert!(err.downcast_ref::<PartialDatabase>().is_none());
+}
+
+#[test]
+fn failed_journal_recovery_is_interrupted_sync() {
+    let name = "partial_journal";
+    let path = clean_test_path(name);
+    drop(open(name).unwrap());
+    std::fs::write(path.join("checkpoint"), b"torn").unwrap();
+
+    let err = partial(open(name).err().unwrap());
+    assert!(!err.is_safe_to_delete());
+    match err {
+        PartialDatabase::SyncInterrupted {
+            sync_seqn, phase, ..
+        } => {
+            assert_eq!(sync_seqn, 0);
+            assert_eq!(phase, nomt::SyncPhase::Journal);
+        }
+        err => panic!("unexpected error: {err}"),
+    }
+}
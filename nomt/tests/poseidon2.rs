@@ -0,0 +1,66 @@
+#![cfg(feature = "poseidon2-hasher")]
+
+mod common;
+
+use common::{clean_test_path, test_options};
+use nomt::{
+    hasher::{Poseidon2Hasher, ValueHasher},
+    trie::KeyPath,
+    KeyReadWrite, Nomt, SessionParams, WitnessMode,
+};
+
+fn key(i: u8) -> KeyPath {
+    let mut key = [i; 32];
+    key[31] = 0xff - i;
+    key
+}
+
+// Whether the bytes are the canonical encoding of a field element, ignoring the node kind tag.
+fn is_element(bytes: &[u8; 32]) -> bool {
+    let mut untagged = *bytes;
+    untagged[0] &= 0x7f;
+    Poseidon2Hasher::to_element(&untagged).is_some()
+}
+
+#[test]
+fn witness_verifies_with_poseidon2() {
+    clean_test_path("poseidon2_witness");
+    let nomt = Nomt::<Poseidon2Hasher>::open(test_options("poseidon2_witness")).unwrap();
+
+    let session = nomt.begin_session(SessionParams::default());
+    let actuals = (0..50)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(vec![i; 100]))))
+        .collect();
+    session.finish(actuals).unwrap().commit(&nomt).unwrap();
+    let prev_root = nomt.root().into_inner();
+    assert!(is_element(&prev_root));
+
+    let session =
+        nomt.begin_session(SessionParams::default().witness_mode(WitnessMode::read_write()));
+    let actuals = vec![
+        (key(3), KeyReadWrite::Read(Some(vec![3; 100]))),
+        (key(7), KeyReadWrite::Write(None)),
+        (key(60), KeyReadWrite::Write(Some(vec![60; 1000]))),
+        (key(70), KeyReadWrite::Read(None)),
+    ];
+    let mut finished = session.finish(actuals).unwrap();
+    let witness = finished.take_witness().unwrap();
+    finished.commit(&nomt).unwrap();
+    let new_root = nomt.root().into_inner();
+
+    let statements = witness.verify::<Poseidon2Hasher>(prev_root).unwrap();
+    assert_eq!(statements.new_root, new_root);
+    assert!(witness
+        .path_proofs
+        .iter()
+        .all(|witnessed_path| witnessed_path.inner.siblings.iter().all(is_element)));
+
+    let value_hash = Poseidon2Hasher::hash_value(&[60; 1000]);
+    assert!(is_element(&value_hash));
+    assert_eq!(
+        nomt.read(key(60))
+            .unwrap()
+            .map(|v| Poseidon2Hasher::hash_value(&v)),
+        Some(value_hash)
+    );
+}
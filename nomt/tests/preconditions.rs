@@ -0,0 +1,103 @@
+mod common;
+
+use common::setup_nomt;
+use nomt::{
+    hasher::{Blake3Hasher, ValueHasher},
+    KeyReadWrite, Nomt, Precondition, PreconditionsViolated, SessionParams, ViolatedPrecondition,
+};
+
+fn write(nomt: &Nomt<Blake3Hasher>, key: [u8; 32], value: Vec<u8>) {
+    let session = nomt.begin_session(SessionParams::default());
+    session
+        .finish(vec![(key, KeyReadWrite::Write(Some(value)))])
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+}
+
+#[test]
+fn preconditions_hold() {
+    let nomt = setup_nomt("preconditions_hold");
+    write(&nomt, [1; 32], vec![1]);
+
+    let mut session = nomt.begin_session(SessionParams::default());
+    session.require(
+        [1; 32],
+        Precondition::ValueHash(Blake3Hasher::hash_value(&[1])),
+    );
+    session.require([2; 32], Precondition::Absent);
+    session
+        .finish(vec![
+            (
+                [1; 32],
+                KeyReadWrite::ReadThenWrite(Some(vec![1]), Some(vec![2])),
+            ),
+            ([2; 32], KeyReadWrite::Write(Some(vec![2]))),
+        ])
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+
+    assert_eq!(nomt.read([1; 32]).unwrap(), Some(vec![2]));
+    assert_eq!(nomt.read([2; 32]).unwrap(), Some(vec![2]));
+}
+
+#[test]
+fn violated_preconditions_are_listed() {
+    let nomt = setup_nomt("preconditions_violated");
+    write(&nomt, [1; 32], vec![1]);
+    let root = nomt.root();
+
+    let mut session = nomt.begin_session(SessionParams::default());
+    session.require([3; 32], Precondition::Absent);
+    session.require([1; 32], Precondition::Absent);
+    session.require(
+        [2; 32],
+        Precondition::ValueHash(Blake3Hasher::hash_value(&[2])),
+    );
+    let err = match session.finish(vec![([1; 32], KeyReadWrite::Write(None))]) {
+        Ok(_) => panic!("session finished despite violated preconditions"),
+        Err(err) => err,
+    };
+
+    let violated = err.downcast::<PreconditionsViolated>().unwrap().violated;
+    assert_eq!(
+        violated,
+        vec![
+            ViolatedPrecondition {
+                key: [1; 32],
+                precondition: Precondition::Absent,
+                actual: Some(Blake3Hasher::hash_value(&[1])),
+            },
+            ViolatedPrecondition {
+                key: [2; 32],
+                precondition: Precondition::ValueHash(Blake3Hasher::hash_value(&[2])),
+                actual: None,
+            },
+        ]
+    );
+    assert_eq!(nomt.root(), root);
+}
+
+#[test]
+fn concurrent_change_fails_commit() {
+    let nomt = setup_nomt("preconditions_concurrent_change");
+    write(&nomt, [1; 32], vec![1]);
+
+    let mut session = nomt.begin_session(SessionParams::default());
+    session.require(
+        [1; 32],
+        Precondition::ValueHash(Blake3Hasher::hash_value(&[1])),
+    );
+    let finished = session
+        .finish(vec![(
+            [1; 32],
+            KeyReadWrite::ReadThenWrite(Some(vec![1]), Some(vec![3])),
+        )])
+        .unwrap();
+
+    // The key changes between checking the precondition and committing.
+    write(&nomt, [1; 32], vec![2]);
+    finished.commit(&nomt).unwrap_err();
+    assert_eq!(nomt.read([1; 32]).unwrap(), Some(vec![2]));
+}
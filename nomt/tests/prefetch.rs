@@ -0,0 +1,134 @@
+mod common;
+
+use nomt::{hasher::Blake3Hasher, trie::KeyPath, KeyReadWrite, Nomt, SessionParams};
+
+fn setup_nomt(name: &str, warm_up: bool) -> Nomt<Blake3Hasher> {
+    common::setup_nomt_with(name, |o| o.warm_up(warm_up))
+}
+
+fn key(i: u32) -> KeyPath {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+// Commit many keys, leaving all but the root page out of the caches.
+fn populate(nomt: &Nomt<Blake3Hasher>) {
+    let mut actuals = (0..5_000)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(i.to_le_bytes().to_vec()))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+    nomt.clear_caches(0);
+}
+
+#[test]
+fn prefetched_reads_and_commits_are_unaffected() {
+    for warm_up in [false, true] {
+        let nomt = setup_nomt("prefetch_unaffected", warm_up);
+        populate(&nomt);
+
+        let session = nomt.begin_session(SessionParams::default());
+        session.prefetch((0..100).map(key).chain([[0xff; 32]]));
+        for i in 0..100 {
+            assert_eq!(
+                session.read(key(i)).unwrap(),
+                Some(i.to_le_bytes().to_vec())
+            );
+        }
+        assert_eq!(session.read([0xff; 32]).unwrap(), None);
+
+        let mut actuals = (0..100)
+            .map(|i| {
+                (
+                    key(i),
+                    KeyReadWrite::ReadThenWrite(Some(i.to_le_bytes().to_vec()), None),
+                )
+            })
+            .collect::<Vec<_>>();
+        actuals.sort_by_key(|(k, _)| *k);
+        let finished = session.finish(actuals.clone()).unwrap();
+        let root = finished.root();
+        finished.commit(&nomt).unwrap();
+
+        // The same changes without prefetching lead to the same root.
+        let expected = setup_nomt("prefetch_expected", warm_up);
+        populate(&expected);
+        expected
+            .begin_session(SessionParams::default())
+            .finish(actuals)
+            .unwrap()
+            .commit(&expected)
+            .unwrap();
+        assert_eq!(root, expected.root());
+        assert_eq!(nomt.root(), expected.root());
+        assert_eq!(nomt.get(key(0)).unwrap(), None);
+        assert_eq!(
+            nomt.get(key(100)).unwrap(),
+            Some(100u32.to_le_bytes().to_vec())
+        );
+    }
+}
+
+#[test]
+fn prefetch_skips_overlays() {
+    let nomt = setup_nomt("prefetch_overlay", false);
+    populate(&nomt);
+
+    let overlay = nomt
+        .begin_session(SessionParams::default())
+        .finish(vec![(key(0), KeyReadWrite::Write(Some(vec![1])))])
+        .unwrap()
+        .into_overlay();
+    let session = nomt.begin_session(SessionParams::default().overlay([&overlay]).unwrap());
+    session.prefetch([key(0), key(1)]);
+    assert_eq!(session.read(key(0)).unwrap(), Some(vec![1]));
+    assert_eq!(
+        session.read(key(1)).unwrap(),
+        Some(1u32.to_le_bytes().to_vec())
+    );
+    drop(session);
+
+    // Dropping a session with prefetches in flight is fine.
+    let session = nomt.begin_session(SessionParams::default());
+    session.prefetch((0..1000).map(key));
+    drop(session);
+    nomt.begin_session(SessionParams::default())
+        .finish(vec![(key(2), KeyReadWrite::Write(None))])
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+}
+
+#[cfg(feature = "cache-debug")]
+#[test]
+fn prefetch_loads_leaves() {
+    let nomt = setup_nomt("prefetch_leaves", false);
+    populate(&nomt);
+    assert!(nomt.cache_snapshot().leaves.is_empty());
+
+    let session = nomt.begin_session(SessionParams::default());
+    let keys = (0..20).map(key).collect::<Vec<_>>();
+    session.prefetch(keys.iter().copied());
+
+    let cached = |leaves: &[nomt::cache_debug::CachedLeaf], key: &KeyPath| {
+        leaves.iter().any(|leaf| {
+            leaf.key_range
+                .is_some_and(|(first, last)| first <= *key && *key <= last)
+        })
+    };
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    loop {
+        let leaves = nomt.cache_snapshot().leaves;
+        if keys.iter().all(|key| cached(&leaves, key)) {
+            break;
+        }
+        assert!(
+            std::time::Instant::now() < deadline,
+            "leaves not prefetched"
+        );
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use bitvec::prelude::*;
+use common::setup_nomt;
+use nomt::{
+    hasher::Blake3Hasher,
+    proof::{verify_prefix, PrefixNode, PrefixProofVerificationError},
+    trie::{KeyPath, TERMINATOR},
+    KeyReadWrite, LocalValue, Nomt, ProvenPrefix, SessionParams, Value,
+};
+use std::collections::BTreeMap;
+
+fn populate(nomt: &Nomt<Blake3Hasher>, n: u32) -> BTreeMap<KeyPath, Value> {
+    let mut expected = BTreeMap::new();
+    let mut actuals = Vec::new();
+    for i in 0..n {
+        let key = *blake3::hash(&i.to_le_bytes()).as_bytes();
+        let value = i.to_le_bytes().to_vec();
+        expected.insert(key, value.clone());
+        actuals.push((key, KeyReadWrite::Write(Some(value))));
+    }
+    actuals.sort_by_key(|(key, _)| *key);
+    let session = nomt.begin_session(SessionParams::default());
+    session.finish(actuals).unwrap().commit(nomt).unwrap();
+    expected
+}
+
+fn with_prefix<'a>(
+    expected: &'a BTreeMap<KeyPath, Value>,
+    prefix: &'a BitSlice<u8, Msb0>,
+) -> impl Iterator<Item = (&'a KeyPath, &'a Value)> {
+    expected
+        .iter()
+        .filter(move |(key, _)| key.view_bits::<Msb0>().starts_with(prefix))
+}
+
+fn verify(
+    prefix: &BitSlice<u8, Msb0>,
+    proven: &ProvenPrefix,
+) -> Result<(), PrefixProofVerificationError> {
+    let verified = verify_prefix::<Blake3Hasher>(&proven.proof, prefix, proven.root.into_inner())?;
+    if let Some(values) = &proven.values {
+        let leaves = verified.leaves().unwrap();
+        assert_eq!(leaves.len(), values.len());
+        for (leaf, (key, value)) in leaves.iter().zip(values) {
+            assert_eq!(&leaf.key_path, key);
+            assert_eq!(leaf.value_hash, value.value_hash::<Blake3Hasher>());
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn prefixes_of_all_lengths() {
+    let nomt = setup_nomt("prefix_proof_all_lengths");
+    let expected = populate(&nomt, 1000);
+
+    let key = *expected.keys().nth(400).unwrap();
+    for len in 0..=256 {
+        let prefix = &key.view_bits::<Msb0>()[..len];
+        let proven = nomt.prove_prefix(prefix, 2000).unwrap();
+        verify(prefix, &proven).unwrap();
+
+        let values = proven.values.unwrap();
+        let want = with_prefix(&expected, prefix)
+            .map(|(k, v)| (*k, LocalValue::Stored(v.clone())))
+            .collect::<Vec<_>>();
+        assert_eq!(values, want, "prefix length {}", len);
+    }
+}
+
+#[test]
+fn too_many_leaves_to_list() {
+    let nomt = setup_nomt("prefix_proof_too_many");
+    let expected = populate(&nomt, 1000);
+
+    let prefix = bits![u8, Msb0; 1, 0];
+    let count = with_prefix(&expected, prefix).count();
+
+    let proven = nomt.prove_prefix(prefix, count - 1).unwrap();
+    assert!(proven.values.is_none());
+    assert!(proven.proof.leaves.is_none());
+    assert!(matches!(proven.proof.node, PrefixNode::Internal(_)));
+    verify(prefix, &proven).unwrap();
+
+    let proven = nomt.prove_prefix(prefix, count).unwrap();
+    assert_eq!(proven.values.as_ref().unwrap().len(), count);
+    verify(prefix, &proven).unwrap();
+
+    // A prefix of only ones reaches the end of the key space.
+    let all_ones = bits![u8, Msb0; 1; 5];
+    let proven = nomt.prove_prefix(all_ones, 0).unwrap();
+    verify(all_ones, &proven).unwrap();
+}
+
+#[test]
+fn empty_and_missing_prefixes() {
+    let nomt = setup_nomt("prefix_proof_empty");
+    let prefix = bits![u8, Msb0; 0, 1, 1];
+    let proven = nomt.prove_prefix(prefix, 10).unwrap();
+    assert_eq!(proven.values, Some(vec![]));
+    let verified =
+        verify_prefix::<Blake3Hasher>(&proven.proof, prefix, proven.root.into_inner()).unwrap();
+    assert_eq!(verified.subtree_root(), TERMINATOR);
+
+    let expected = populate(&nomt, 10);
+    // A long prefix of a present key diverging in the last bit has no keys under it.
+    let key = *expected.keys().next().unwrap();
+    let mut missing = key.view_bits::<Msb0>()[..40].to_bitvec();
+    let last = !missing[39];
+    missing.set(39, last);
+    let proven = nomt.prove_prefix(&missing, 10).unwrap();
+    assert_eq!(proven.values, Some(vec![]));
+    verify(&missing, &proven).unwrap();
+}
+
+#[test]
+fn tampered_values_are_rejected() {
+    let nomt = setup_nomt("prefix_proof_tampered");
+    populate(&nomt, 200);
+
+    let prefix = bits![u8, Msb0; 0, 0, 1];
+    let mut proven = nomt.prove_prefix(prefix, 200).unwrap();
+    proven.proof.leaves.as_mut().unwrap().pop();
+    assert_eq!(
+        verify(prefix, &proven).unwrap_err(),
+        PrefixProofVerificationError::LeavesMismatch
+    );
+
+    let proven = nomt.prove_prefix(prefix, 200).unwrap();
+    let other = bits![u8, Msb0; 0, 1, 1];
+    assert!(verify(other, &proven).is_err());
+}
+
+#[test]
+fn session_on_overlay() {
+    let nomt = setup_nomt("prefix_proof_overlay");
+    let mut expected = populate(&nomt, 500);
+
+    // Delete, overwrite and insert keys in an overlay without committing it.
+    let prefix = bits![u8, Msb0; 1, 1];
+    let under_prefix = with_prefix(&expected, prefix)
+        .map(|(k, _)| *k)
+        .collect::<Vec<_>>();
+    let mut actuals = Vec::new();
+    for (i, key) in under_prefix.iter().enumerate() {
+        let value = (i % 3 != 0).then(|| vec![i as u8; 4]);
+        match value {
+            Some(ref value) => expected.insert(*key, value.clone()),
+            None => expected.remove(key),
+        };
+        actuals.push((*key, KeyReadWrite::Write(value)));
+    }
+    for i in 500..600u32 {
+        let key = *blake3::hash(&i.to_le_bytes()).as_bytes();
+        let value = i.to_le_bytes().to_vec();
+        expected.insert(key, value.clone());
+        actuals.push((key, KeyReadWrite::Write(Some(value))));
+    }
+    actuals.sort_by_key(|(key, _)| *key);
+    let overlay = nomt
+        .begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .into_overlay();
+
+    let session = nomt.begin_session(SessionParams::default().overlay([&overlay]).unwrap());
+    for prefix in [prefix, bits![u8, Msb0; 1, 1, 0, 1], bits![u8, Msb0; 0]] {
+        let proven = session.prove_prefix(prefix, 1000).unwrap();
+        assert_eq!(proven.root, overlay.root());
+        verify(prefix, &proven).unwrap();
+        let want = with_prefix(&expected, prefix)
+            .map(|(k, v)| (*k, LocalValue::Stored(v.clone())))
+            .collect::<Vec<_>>();
+        assert_eq!(proven.values.unwrap(), want);
+    }
+
+    // The database itself is still at the committed root.
+    drop(session);
+    let proven = nomt.prove_prefix(prefix, 1000).unwrap();
+    assert_ne!(proven.root, overlay.root());
+    verify(prefix, &proven).unwrap();
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::{clean_test_path, test_options};
+use nomt::{
+    hasher::{Blake3Hasher, ValueHasher},
+    trie::KeyPath,
+    KeyReadWrite, Nomt, SessionParams,
+};
+
+fn key(i: u32) -> KeyPath {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+fn open<T: nomt::HashAlgorithm>(name: &str) -> Nomt<T> {
+    clean_test_path(name);
+    Nomt::open(test_options(name)).unwrap()
+}
+
+fn commit<T: nomt::HashAlgorithm>(nomt: &Nomt<T>, mut writes: Vec<(KeyPath, Option<Vec<u8>>)>) {
+    writes.sort_by_key(|(key, _)| *key);
+    let session = nomt.begin_session(SessionParams::default());
+    let actuals = writes
+        .into_iter()
+        .map(|(key, value)| (key, KeyReadWrite::Write(value)))
+        .collect();
+    session.finish(actuals).unwrap().commit(nomt).unwrap();
+}
+
+#[test]
+fn quad_trie_of_database_tracks_commits() {
+    let nomt = open::<Blake3Hasher>("quad_trie_tracks_commits");
+    commit(
+        &nomt,
+        (0..300).map(|i| (key(i), Some(vec![1; 40]))).collect(),
+    );
+
+    let trie = nomt.experimental_quad_trie().unwrap();
+    assert_eq!(trie.stats().leaves, 300);
+    let root = trie.root::<Blake3Hasher>();
+
+    // Update a witness of some keys in the 4-ary trie, then commit the same writes.
+    let writes = (0..20)
+        .map(|i| (key(i), (i % 2 == 0).then(|| vec![2; 40])))
+        .chain((500..510).map(|i| (key(i), Some(vec![3; 40]))))
+        .collect::<Vec<_>>();
+    let mut witness = trie.witness::<Blake3Hasher>(writes.iter().map(|(key, _)| *key));
+    assert_eq!(witness.root::<Blake3Hasher>(), root);
+    assert!(witness.stats().opaque_nodes > 0);
+    assert_eq!(
+        witness.get(&key(3)).unwrap(),
+        Some(Blake3Hasher::hash_value(&[1; 40]))
+    );
+    witness
+        .update::<Blake3Hasher>(
+            writes
+                .iter()
+                .map(|(key, value)| (*key, value.as_ref().map(|v| Blake3Hasher::hash_value(v)))),
+        )
+        .unwrap();
+
+    commit(&nomt, writes);
+    let trie = nomt.experimental_quad_trie().unwrap();
+    assert_eq!(trie.stats().leaves, 300 - 10 + 10);
+    assert_eq!(witness.root::<Blake3Hasher>(), trie.root::<Blake3Hasher>());
+}
+
+#[cfg(feature = "poseidon2-hasher")]
+#[test]
+fn quad_trie_has_shallower_paths() {
+    use nomt::{hasher::Poseidon2Hasher, quad::QuadTrie, trie::LeafData};
+
+    let nomt = open::<Poseidon2Hasher>("quad_trie_shallower");
+    commit(
+        &nomt,
+        (0..1000).map(|i| (key(i), Some(vec![1; 8]))).collect(),
+    );
+
+    let trie = nomt.experimental_quad_trie().unwrap();
+    let stats = trie.stats();
+    assert_eq!(stats.leaves, 1000);
+    // A random set of 1000 keys has a depth of about 20 bits.
+    assert!(stats.depth < 16, "{stats:?}");
+
+    // Roots differ by hasher but either is reproducible from the leaves.
+    let leaves = (0..1000).map(|i| LeafData {
+        key_path: key(i),
+        value_hash: Poseidon2Hasher::hash_value(&[1; 8]),
+    });
+    let rebuilt = QuadTrie::build(leaves);
+    assert_eq!(
+        rebuilt.root::<Poseidon2Hasher>(),
+        trie.root::<Poseidon2Hasher>()
+    );
+    assert_ne!(
+        rebuilt.root::<Poseidon2Hasher>(),
+        rebuilt.root::<Blake3Hasher>()
+    );
+}
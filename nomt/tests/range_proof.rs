@@ -0,0 +1,146 @@
+mod common;
+
+use common::setup_nomt;
+use nomt::{
+    hasher::Blake3Hasher,
+    proof::{verify_range, RangeProofVerificationError},
+    trie::KeyPath,
+    KeyReadWrite, LocalValue, Nomt, ProvenRange, SessionParams, Value,
+};
+use std::collections::BTreeMap;
+
+// Fill the database with values under pseudo-random keys, over a few commits.
+fn populate(nomt: &Nomt<Blake3Hasher>) -> BTreeMap<KeyPath, Value> {
+    let mut expected = BTreeMap::new();
+    for commit in 0..4u32 {
+        let mut actuals = Vec::new();
+        for i in 0..250u32 {
+            let key = *blake3::hash(&(commit * 1000 + i).to_le_bytes()).as_bytes();
+            let value = (commit * 1000 + i).to_le_bytes().to_vec();
+            expected.insert(key, value.clone());
+            actuals.push((key, KeyReadWrite::Write(Some(value))));
+        }
+        actuals.sort_by_key(|(key, _)| *key);
+        let session = nomt.begin_session(SessionParams::default());
+        session.finish(actuals).unwrap().commit(nomt).unwrap();
+    }
+    expected
+}
+
+fn verify(start: KeyPath, range: &ProvenRange) -> Result<(), RangeProofVerificationError> {
+    let leaves = verify_range::<Blake3Hasher>(
+        &range.proof,
+        &start,
+        range.end.as_ref(),
+        range.root.into_inner(),
+    )?;
+    assert_eq!(leaves.len(), range.values.len());
+    for (leaf, (key, value)) in leaves.iter().zip(&range.values) {
+        assert_eq!(&leaf.key_path, key);
+        assert_eq!(leaf.value_hash, value.value_hash::<Blake3Hasher>());
+    }
+    Ok(())
+}
+
+#[test]
+fn iteration_follows_trie_order() {
+    let nomt = setup_nomt("iteration_follows_trie_order");
+    let expected = populate(&nomt);
+
+    let keys = nomt
+        .iter_values([0; 32], None)
+        .map(|item| item.unwrap().0)
+        .collect::<Vec<_>>();
+    assert_eq!(keys, expected.keys().copied().collect::<Vec<_>>());
+
+    // Byte order is the same as the order of the bit paths through the trie.
+    for pair in keys.windows(2) {
+        let bits = |k: &KeyPath| {
+            k.iter()
+                .flat_map(|b| (0..8).rev().map(move |i| (b >> i) & 1))
+                .collect::<Vec<_>>()
+        };
+        assert!(bits(&pair[0]) < bits(&pair[1]));
+    }
+
+    let start = keys[100];
+    let end = keys[200];
+    let range = nomt
+        .iter_values(start, Some(end))
+        .map(|item| item.unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        range,
+        expected
+            .range(start..end)
+            .map(|(k, v)| (*k, v.clone()))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn chained_range_proofs_cover_database() {
+    let nomt = setup_nomt("chained_range_proofs");
+    let expected = populate(&nomt);
+
+    let mut dumped = BTreeMap::new();
+    let mut start = [0; 32];
+    loop {
+        let range = nomt.prove_range(start, None, 64).unwrap();
+        assert_eq!(range.root, nomt.root());
+        assert!(range.values.len() <= 64);
+        verify(start, &range).unwrap();
+        dumped.extend(range.values.into_iter().map(|(key, value)| match value {
+            LocalValue::Stored(value) => (key, value),
+            LocalValue::HashOnly(_) => panic!("only stored values were written"),
+        }));
+        match range.end {
+            Some(end) => start = end,
+            None => break,
+        }
+    }
+    assert_eq!(dumped, expected);
+}
+
+#[test]
+fn range_proof_detects_omissions() {
+    let nomt = setup_nomt("range_proof_omissions");
+    let expected = populate(&nomt);
+    let keys = expected.keys().copied().collect::<Vec<_>>();
+
+    // A bounded range which doesn't start on a key.
+    let mut start = keys[300];
+    start[31] = start[31].wrapping_add(1);
+    let end = keys[400];
+    let range = nomt.prove_range(start, Some(end), 1000).unwrap();
+    assert_eq!(range.end, Some(end));
+    assert_eq!(range.values.len(), 99);
+    verify(start, &range).unwrap();
+
+    // Leaving out a leaf in the middle.
+    let mut tampered = range.clone();
+    tampered.values.remove(50);
+    tampered.proof.leaves.remove(50);
+    assert_eq!(
+        verify(start, &tampered),
+        Err(RangeProofVerificationError::Gap)
+    );
+
+    // Leaving out the last leaf and claiming it as the leaf after the range.
+    let mut tampered = range.clone();
+    tampered.values.pop();
+    tampered.proof.upper = tampered.proof.leaves.pop();
+    assert_eq!(
+        verify(start, &tampered),
+        Err(RangeProofVerificationError::LeavesOutOfOrder)
+    );
+
+    // Leaving out the leaf after the range.
+    let mut tampered = range;
+    tampered.proof.upper = None;
+    tampered.end = None;
+    assert_eq!(
+        verify(start, &tampered),
+        Err(RangeProofVerificationError::Gap)
+    );
+}
@@ -0,0 +1,156 @@
+mod common;
+
+use common::{open_nomt, setup_nomt};
+use nomt::{hasher::Blake3Hasher, KeyReadWrite, Nomt, ReadValue, SessionParams, ValueNotStored};
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::Thread,
+};
+
+fn key(i: u32) -> [u8; 32] {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+// Every tenth value spills into overflow pages.
+fn value(i: u32) -> Vec<u8> {
+    let len = if i.is_multiple_of(10) { 20_000 } else { 16 };
+    (0..len).map(|j| (i as usize + j) as u8).collect()
+}
+
+fn populate(nomt: &Nomt<Blake3Hasher>, n: u32) -> BTreeMap<[u8; 32], Vec<u8>> {
+    let expected = (0..n)
+        .map(|i| (key(i), value(i)))
+        .collect::<BTreeMap<_, _>>();
+    let actuals = expected
+        .iter()
+        .map(|(k, v)| (*k, KeyReadWrite::Write(Some(v.clone()))))
+        .collect();
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+    expected
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// Poll all the reads until they are done, parking the thread in between.
+fn join_all(reads: Vec<ReadValue>) -> Vec<Option<Vec<u8>>> {
+    try_join_all(reads)
+        .into_iter()
+        .map(|value| value.unwrap())
+        .collect()
+}
+
+fn try_join_all(mut reads: Vec<ReadValue>) -> Vec<anyhow::Result<Option<Vec<u8>>>> {
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut results = reads.iter().map(|_| None).collect::<Vec<_>>();
+    while results.iter().any(Option::is_none) {
+        for (read, result) in reads.iter_mut().zip(results.iter_mut()) {
+            if result.is_none() {
+                if let Poll::Ready(value) = Pin::new(read).poll(&mut cx) {
+                    *result = Some(value);
+                }
+            }
+        }
+        if results.iter().any(Option::is_none) {
+            std::thread::park();
+        }
+    }
+    results.into_iter().map(Option::unwrap).collect()
+}
+
+#[test]
+fn reads_match_blocking_reads() {
+    let nomt = setup_nomt("read_async_cold");
+    let expected = populate(&nomt, 1000);
+    drop(nomt);
+
+    // Reopen so that the leaves have to be read from disk.
+    let nomt = open_nomt("read_async_cold", |_| {});
+    let session = nomt.begin_session(SessionParams::default());
+    let keys = (0..1100).map(key).collect::<Vec<_>>();
+    let reads = keys.iter().map(|k| session.read_async(*k)).collect();
+    let values = join_all(reads);
+    for (k, value) in keys.iter().zip(values) {
+        assert_eq!(value.as_ref(), expected.get(k));
+        assert_eq!(value, session.read(*k).unwrap());
+    }
+}
+
+#[test]
+fn reads_see_overlay_and_outlive_session() {
+    let nomt = setup_nomt("read_async_overlay");
+    let mut expected = populate(&nomt, 100);
+
+    let mut actuals = vec![
+        (key(1), KeyReadWrite::Write(None)),
+        (key(2), KeyReadWrite::Write(Some(vec![2; 3]))),
+    ];
+    actuals.sort_by_key(|(k, _)| *k);
+    let overlay = nomt
+        .begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .into_overlay();
+    let session = nomt.begin_session(SessionParams::default().overlay([&overlay]).unwrap());
+    let reads = (0..4).map(|i| session.read_async(key(i))).collect();
+    drop(session);
+
+    expected.remove(&key(1));
+    expected.insert(key(2), vec![2; 3]);
+    let values = join_all(reads);
+    for (i, value) in values.into_iter().enumerate() {
+        assert_eq!(value.as_ref(), expected.get(&key(i as u32)));
+    }
+}
+
+#[test]
+fn values_stored_elsewhere_are_not_read() {
+    let nomt = setup_nomt("read_async_hash_only");
+    populate(&nomt, 100);
+    let elsewhere = key(1000);
+    let empty = key(1001);
+    let mut session = nomt.begin_session(SessionParams::default());
+    session.write_value_hash(elsewhere, [7; 32]);
+    session
+        .finish(vec![(empty, KeyReadWrite::Write(Some(vec![])))])
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+
+    let check = |nomt: &Nomt<Blake3Hasher>| {
+        let session = nomt.begin_session(SessionParams::default());
+        let reads = vec![session.read_async(elsewhere), session.read_async(empty)];
+        let mut results = try_join_all(reads).into_iter();
+        let err = results.next().unwrap().unwrap_err();
+        let not_stored = err.downcast_ref::<ValueNotStored>().unwrap();
+        assert_eq!(not_stored.key, elsewhere);
+        assert_eq!(not_stored.value_hash, [7; 32]);
+        assert!(session.read(elsewhere).is_err());
+        assert_eq!(results.next().unwrap().unwrap(), Some(vec![]));
+    };
+
+    // Once with the leaves cached and once with the leaves read from disk.
+    check(&nomt);
+    drop(nomt);
+    check(&open_nomt("read_async_hash_only", |_| {}));
+}
+
+#[test]
+fn read_value_is_send() {
+    fn assert_send<T: Send + 'static>() {}
+    assert_send::<ReadValue>();
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use nomt::{
+    hasher::Blake3Hasher, trie::KeyPath, KeyReadWrite, Nomt, Options, ReadBackend, SessionParams,
+};
+
+fn open(name: &str, read_backend: ReadBackend, clean: bool) -> Nomt<Blake3Hasher> {
+    let configure = |o: &mut Options| {
+        o.commit_concurrency(2);
+        o.read_backend(read_backend);
+    };
+    if clean {
+        common::setup_nomt_with(name, configure)
+    } else {
+        common::open_nomt(name, configure)
+    }
+}
+
+fn key(i: u32) -> KeyPath {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, batch: u32) {
+    let session = nomt.begin_session(SessionParams::default());
+    let mut actuals = (batch * 5000..(batch + 1) * 5000)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(vec![batch as u8; 100]))))
+        .collect::<Vec<_>>();
+    // Overwrite some values written by the previous batch.
+    if batch > 0 {
+        actuals.extend(
+            ((batch - 1) * 5000..(batch - 1) * 5000 + 100)
+                .map(|i| (key(i), KeyReadWrite::Write(Some(vec![batch as u8; 50])))),
+        );
+    }
+    actuals.sort_by_key(|(key, _)| *key);
+    session.finish(actuals).unwrap().commit(nomt).unwrap();
+}
+
+#[test]
+fn mmap_reads_match_io_reads() {
+    let io = open("read_backend_io", ReadBackend::Io, true);
+    let mmap = open("read_backend_mmap", ReadBackend::Mmap, true);
+
+    // The stores grow with every batch, beyond the extent of the initial mappings.
+    for batch in 0..4 {
+        commit(&io, batch);
+        commit(&mmap, batch);
+        assert_eq!(io.root(), mmap.root());
+
+        for i in (0..(batch + 1) * 5000).step_by(97) {
+            assert_eq!(io.read(key(i)).unwrap(), mmap.read(key(i)).unwrap());
+        }
+        let session = mmap.begin_session(SessionParams::default());
+        for i in (0..(batch + 1) * 5000).step_by(997) {
+            let proof = session.prove(key(i)).unwrap();
+            assert_eq!(
+                proof.terminal.as_leaf_option().map(|leaf| leaf.key_path),
+                Some(key(i))
+            );
+        }
+    }
+
+    // Reads of the reopened database.
+    let root = mmap.root();
+    drop(mmap);
+    let mmap = open("read_backend_mmap", ReadBackend::Mmap, false);
+    assert_eq!(mmap.root(), root);
+    assert_eq!(mmap.read(key(0)).unwrap(), Some(vec![1; 50]));
+    assert_eq!(mmap.read(key(19_999)).unwrap(), Some(vec![3; 100]));
+    assert_eq!(mmap.read(key(20_000)).unwrap(), None);
+}
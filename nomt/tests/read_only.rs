@@ -0,0 +1,165 @@
+mod common;
+
+use common::{clean_test_path, test_options};
+use nomt::{hasher::Blake3Hasher, KeyReadWrite, Nomt, Options, SessionParams, StaleView};
+use std::{
+    future::Future,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::Thread,
+};
+
+fn options(name: &str) -> Options {
+    let mut o = test_options(name);
+    o.rollback(true);
+    o
+}
+
+fn key(i: u8) -> [u8; 32] {
+    [i; 32]
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, value: u8) {
+    let actuals = (0..4)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(vec![value; 100]))))
+        .collect();
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// Poll the future until it is done, parking the thread in between.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        std::thread::park();
+    }
+}
+
+#[test]
+fn reads_alongside_writer() {
+    let name = "read_only_alongside_writer";
+    let _path = clean_test_path(name);
+    let writer = Nomt::<Blake3Hasher>::open(options(name)).unwrap();
+    commit(&writer, 1);
+
+    // The writer holds the lock on the directory, which doesn't keep a reader out.
+    assert!(Nomt::<Blake3Hasher>::open(options(name)).is_err());
+    let reader = Nomt::<Blake3Hasher>::open_read_only(options(name)).unwrap();
+    assert_eq!(reader.root(), writer.root());
+    assert_eq!(reader.get(key(0)).unwrap(), Some(vec![1; 100]));
+    assert_eq!(reader.get(key(9)).unwrap(), None);
+
+    // Reopening catches up with the writer.
+    commit(&writer, 2);
+    drop(reader);
+    let reader = Nomt::<Blake3Hasher>::open_read_only(options(name)).unwrap();
+    assert_eq!(reader.root(), writer.root());
+    assert_eq!(reader.get(key(3)).unwrap(), Some(vec![2; 100]));
+}
+
+#[test]
+fn reads_fail_once_writer_syncs_past_view() {
+    let name = "read_only_stale_view";
+    let _path = clean_test_path(name);
+    let writer = Nomt::<Blake3Hasher>::open(options(name)).unwrap();
+    commit(&writer, 1);
+
+    let reader = Nomt::<Blake3Hasher>::open_read_only(options(name)).unwrap();
+    let view_sync_seqn = reader.sync_seqn();
+    let session = reader.begin_session(SessionParams::default());
+    assert_eq!(session.read(key(0)).unwrap(), Some(vec![1; 100]));
+
+    commit(&writer, 2);
+    let stale = StaleView {
+        view_sync_seqn,
+        writer_sync_seqn: writer.sync_seqn(),
+    };
+    let err = reader.get(key(0)).unwrap_err();
+    assert_eq!(err.downcast_ref::<StaleView>(), Some(&stale));
+    let err = session.read(key(0)).unwrap_err();
+    assert_eq!(err.downcast_ref::<StaleView>(), Some(&stale));
+    assert!(session.prove(key(0)).is_err());
+}
+
+#[test]
+fn async_reads_fail_once_writer_syncs_past_view() {
+    let name = "read_only_stale_view_async";
+    let _path = clean_test_path(name);
+    let writer = Nomt::<Blake3Hasher>::open(options(name)).unwrap();
+    commit(&writer, 1);
+
+    let reader = Nomt::<Blake3Hasher>::open_read_only(options(name)).unwrap();
+    let view_sync_seqn = reader.sync_seqn();
+    let session = reader.begin_session(SessionParams::default());
+
+    // A read started before the writer syncs fails when it resolves, as does one started after.
+    // Nothing is cached yet, so the first read waits for the leaf to be read.
+    let pending = session.read_async(key(1));
+    commit(&writer, 2);
+    let stale = StaleView {
+        view_sync_seqn,
+        writer_sync_seqn: writer.sync_seqn(),
+    };
+    let err = block_on(pending).unwrap_err();
+    assert_eq!(err.downcast_ref::<StaleView>(), Some(&stale));
+    let err = block_on(session.read_async(key(2))).unwrap_err();
+    assert_eq!(err.downcast_ref::<StaleView>(), Some(&stale));
+    drop(session);
+
+    let reader = Nomt::<Blake3Hasher>::open_read_only(options(name)).unwrap();
+    let session = reader.begin_session(SessionParams::default());
+    assert_eq!(
+        block_on(session.read_async(key(1))).unwrap(),
+        Some(vec![2; 100])
+    );
+}
+
+#[test]
+fn writes_are_refused() {
+    let name = "read_only_writes_refused";
+    let _path = clean_test_path(name);
+    commit(&Nomt::<Blake3Hasher>::open(options(name)).unwrap(), 1);
+
+    let reader = Nomt::<Blake3Hasher>::open_read_only(options(name)).unwrap();
+    let root = reader.root();
+    let err = reader
+        .begin_session(SessionParams::default())
+        .finish(vec![(key(0), KeyReadWrite::Write(None))])
+        .unwrap()
+        .commit(&reader)
+        .unwrap_err();
+    assert!(err.to_string().contains("read-only"));
+    assert!(reader.rollback(1).is_err());
+    assert_eq!(reader.root(), root);
+    drop(reader);
+
+    // Nothing was written.
+    let nomt = Nomt::<Blake3Hasher>::open(options(name)).unwrap();
+    assert_eq!(nomt.root(), root);
+    assert_eq!(nomt.get(key(0)).unwrap(), Some(vec![1; 100]));
+}
+
+#[test]
+fn missing_database_is_not_created() {
+    let name = "read_only_missing";
+    let path = clean_test_path(name);
+    assert!(Nomt::<Blake3Hasher>::open_read_only(options(name)).is_err());
+    assert!(!path.exists());
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::test_path;
+use nomt::{
+    hasher::Blake3Hasher,
+    read_through::{RemoteArchive, RemoteValue},
+    trie::KeyPath,
+    KeyReadWrite, Nomt, Root, SessionParams,
+};
+use std::sync::Arc;
+
+struct Archive {
+    remote: Nomt<Blake3Hasher>,
+}
+
+impl RemoteArchive for Archive {
+    fn fetch(&self, root: Root, key_path: KeyPath) -> anyhow::Result<RemoteValue> {
+        assert_eq!(self.remote.root(), root);
+        let session = self.remote.begin_session(SessionParams::default());
+        let value = session.read(key_path)?;
+        let proof = session.prove(key_path)?;
+        Ok(RemoteValue { value, proof })
+    }
+}
+
+fn open(name: &str, read_repair: Option<(Arc<Archive>, f64)>) -> Nomt<Blake3Hasher> {
+    common::open_nomt(name, |o| {
+        if let Some((archive, sample_rate)) = read_repair {
+            o.read_repair(archive, sample_rate);
+        }
+    })
+}
+
+fn key(i: u8) -> KeyPath {
+    [i; 32]
+}
+
+// A value which is easy to find in the files of the database.
+fn value(i: u8) -> Vec<u8> {
+    let mut value = b"read repair value".repeat(4);
+    value.push(i);
+    value
+}
+
+fn create(name: &str) -> Nomt<Blake3Hasher> {
+    let _ = std::fs::remove_dir_all(test_path(name));
+    let nomt = open(name, None);
+    let actuals = (1..=10)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(value(i)))))
+        .collect();
+    let session = nomt.begin_session(SessionParams::default());
+    session.finish(actuals).unwrap().commit(&nomt).unwrap();
+    nomt
+}
+
+#[test]
+fn damaged_values_are_repaired() {
+    let archive = Arc::new(Archive {
+        remote: create("read_repair_archive"),
+    });
+    let root = create("read_repair_local").root();
+    assert_eq!(root, archive.remote.root());
+
+    // Damage the fifth value in place.
+    let ln = test_path("read_repair_local").join("ln");
+    let mut data = std::fs::read(&ln).unwrap();
+    let needle = value(5);
+    let offset = data
+        .windows(needle.len())
+        .position(|w| w == &needle[..])
+        .unwrap();
+    data[offset] ^= 0xff;
+    std::fs::write(&ln, data).unwrap();
+
+    let nomt = open("read_repair_local", Some((archive, 1.0)));
+    let session = nomt.begin_session(SessionParams::default());
+    for i in 1..=10 {
+        assert_eq!(session.read(key(i)).unwrap(), Some(value(i)));
+    }
+    assert_eq!(session.read(key(11)).unwrap(), None);
+    drop(session);
+
+    let stats = nomt.read_repair_stats().unwrap();
+    assert_eq!(stats.checked, 11);
+    assert_eq!(stats.damaged, 1);
+    assert_eq!(stats.repaired, 1);
+    assert_eq!(stats.unrepaired, 0);
+    assert_eq!(stats.pending, 1);
+
+    assert_eq!(nomt.persist_repairs().unwrap(), 1);
+    assert_eq!(nomt.root(), root);
+    assert_eq!(nomt.read_repair_stats().unwrap().pending, 0);
+    drop(nomt);
+
+    let nomt = open("read_repair_local", None);
+    let session = nomt.begin_session(SessionParams::default());
+    assert_eq!(session.read(key(5)).unwrap(), Some(value(5)));
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::setup_nomt;
+use nomt::{KeyReadWrite, SessionParams};
+
+fn key(i: u8) -> [u8; 32] {
+    [i; 32]
+}
+
+fn value(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|j| (j / 7) as u8 ^ (j as u8) ^ seed).collect()
+}
+
+// Sizes from inline values to values whose overflow page numbers do not fit in the leaf.
+const SIZES: [usize; 6] = [0, 100, 4_000, 20_000, 200_000, 5_000_000];
+
+fn slices(len: usize) -> Vec<(usize, usize)> {
+    let mut slices = vec![
+        (0, 0),
+        (0, 8),
+        (0, len),
+        (0, usize::MAX),
+        (len, 1),
+        (len + 10, 5),
+    ];
+    for offset in [1, 4_091, 4_092, 4_096, 61_379, 61_380, 100_000, 4_000_000] {
+        for slice_len in [1, 64, 4_092, 10_000] {
+            slices.push((offset, slice_len));
+        }
+    }
+    slices.push((len.saturating_sub(3), 10));
+    slices
+}
+
+fn expected(value: &[u8], offset: usize, len: usize) -> Vec<u8> {
+    let start = offset.min(value.len());
+    let end = offset.saturating_add(len).clamp(start, value.len());
+    value[start..end].to_vec()
+}
+
+#[test]
+fn reads_slices_of_committed_values() {
+    let nomt = setup_nomt("read_slice_committed");
+    let values = SIZES
+        .iter()
+        .enumerate()
+        .map(|(i, size)| (key(i as u8), value(*size, i as u8)))
+        .collect::<Vec<_>>();
+    let actuals = values
+        .iter()
+        .map(|(k, v)| (*k, KeyReadWrite::Write(Some(v.clone()))))
+        .collect();
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+
+    let session = nomt.begin_session(SessionParams::default());
+    for (k, v) in &values {
+        for (offset, len) in slices(v.len()) {
+            assert_eq!(
+                session.read_slice(*k, offset, len).unwrap(),
+                Some(expected(v, offset, len)),
+                "size {} offset {} len {}",
+                v.len(),
+                offset,
+                len,
+            );
+        }
+    }
+    assert_eq!(session.read_slice(key(0xff), 0, 10).unwrap(), None);
+}
+
+#[test]
+fn reads_slices_of_overlay_values() {
+    let nomt = setup_nomt("read_slice_overlay");
+    let committed = value(200_000, 1);
+    nomt.begin_session(SessionParams::default())
+        .finish(vec![(key(1), KeyReadWrite::Write(Some(committed)))])
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+
+    let overwritten = value(50_000, 2);
+    let overlay = nomt
+        .begin_session(SessionParams::default())
+        .finish(vec![
+            (key(1), KeyReadWrite::Write(Some(overwritten.clone()))),
+            (key(2), KeyReadWrite::Write(None)),
+        ])
+        .unwrap()
+        .into_overlay();
+
+    let session = nomt.begin_session(SessionParams::default().overlay([&overlay]).unwrap());
+    assert_eq!(
+        session.read_slice(key(1), 40_000, 20_000).unwrap(),
+        Some(overwritten[40_000..].to_vec())
+    );
+    assert_eq!(session.read_slice(key(2), 0, 10).unwrap(), None);
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use common::Test;
+use nomt::{
+    hasher::Blake3Hasher,
+    read_through::{RemoteArchive, RemoteValue},
+    trie::KeyPath,
+    Error, KeyReadWrite, Nomt, Root, SessionMisuse, SessionParams,
+};
+use std::sync::Arc;
+
+struct Archive {
+    remote: Test,
+    tamper: bool,
+}
+
+impl RemoteArchive for Archive {
+    fn fetch(&self, root: Root, key_path: KeyPath) -> anyhow::Result<RemoteValue> {
+        assert_eq!(self.remote.root(), root);
+        let session = self.remote.nomt().begin_session(SessionParams::default());
+        let mut value = session.read(key_path)?;
+        if self.tamper {
+            value = value.map(|mut v| {
+                v.push(0);
+                v
+            });
+        }
+        let proof = session.prove(key_path)?;
+        Ok(RemoteValue { value, proof })
+    }
+}
+
+fn remote(name: &str, n: u64, tamper: bool) -> Arc<Archive> {
+    let mut t = Test::new(name);
+    for i in 0..n {
+        common::set_balance(&mut t, i, i + 1);
+    }
+    t.commit();
+    Arc::new(Archive { remote: t, tamper })
+}
+
+fn open_local(name: &str, archive: Arc<Archive>) -> Nomt<Blake3Hasher> {
+    common::setup_nomt_with(name, |o| {
+        o.bitbox_seed([0; 16]);
+        o.io_workers(1);
+        let target_root = archive.remote.root();
+        o.read_through(archive, target_root);
+    })
+}
+
+#[test]
+fn reads_fall_through_to_remote() {
+    let archive = remote("read_through_remote", 100, false);
+    let target_root = archive.remote.root();
+    let local = open_local("read_through_local", archive);
+
+    {
+        let session = local.begin_session(SessionParams::default());
+        for i in 0..100 {
+            let value = session.read(common::account_path(i)).unwrap();
+            assert_eq!(value, Some((i + 1).to_le_bytes().to_vec()));
+        }
+        assert_eq!(session.read(common::account_path(1000)).unwrap(), None);
+    }
+
+    let stats = local.read_through_stats().unwrap();
+    assert!(stats.active);
+    assert_eq!(stats.fetched, 101);
+    assert_eq!(stats.pending, 101);
+
+    assert_eq!(local.persist_fetched().unwrap(), 100);
+    assert_eq!(local.root(), target_root);
+
+    let stats = local.read_through_stats().unwrap();
+    assert!(!stats.active);
+    assert_eq!(stats.pending, 0);
+
+    // Values are now served locally.
+    let session = local.begin_session(SessionParams::default());
+    assert_eq!(
+        session.read(common::account_path(5)).unwrap(),
+        Some(6u64.to_le_bytes().to_vec())
+    );
+}
+
+#[test]
+fn invalid_remote_values_are_rejected() {
+    let archive = remote("read_through_tamper_remote", 10, true);
+    let local = open_local("read_through_tamper_local", archive);
+
+    let session = local.begin_session(SessionParams::default());
+    assert!(session.read(common::account_path(0)).is_err());
+    drop(session);
+
+    assert_eq!(local.read_through_stats().unwrap().fetched, 0);
+    assert_eq!(local.persist_fetched().unwrap(), 0);
+}
+
+#[test]
+fn sessions_reading_through_are_not_committed() {
+    let archive = remote("read_through_commit_remote", 10, false);
+    let local = open_local("read_through_commit_local", archive);
+    let read_through_misuse = |result: Result<(), Error>| {
+        matches!(result, Err(Error::Misuse(SessionMisuse::ReadThrough)))
+    };
+
+    let session = local.begin_session(SessionParams::default());
+    let value = session.read(common::account_path(0)).unwrap();
+    let finished = session
+        .finish(vec![(common::account_path(0), KeyReadWrite::Read(value))])
+        .unwrap();
+    assert!(read_through_misuse(finished.commit(&local)));
+
+    let session = local.begin_session(SessionParams::default());
+    let value = session.read(common::account_path(1)).unwrap();
+    let overlay = session
+        .finish(vec![(
+            common::account_path(1),
+            KeyReadWrite::ReadThenWrite(value, Some(vec![1])),
+        )])
+        .unwrap()
+        .into_overlay();
+    assert!(read_through_misuse(overlay.commit(&local)));
+
+    // sessions which only write are committed.
+    let session = local.begin_session(SessionParams::default());
+    let finished = session
+        .finish(vec![(
+            common::account_path(1000),
+            KeyReadWrite::Write(Some(vec![1])),
+        )])
+        .unwrap();
+    finished.commit(&local).unwrap();
+    assert_eq!(local.read_through_stats().unwrap().fetched, 2);
+
+    // once persisted, fetched values can be built on.
+    assert_eq!(local.persist_fetched().unwrap(), 2);
+    let session = local.begin_session(SessionParams::default());
+    let value = session.read(common::account_path(1)).unwrap();
+    assert_eq!(value, Some(2u64.to_le_bytes().to_vec()));
+    session
+        .finish(vec![(
+            common::account_path(1),
+            KeyReadWrite::ReadThenWrite(value, Some(vec![1])),
+        )])
+        .unwrap()
+        .commit(&local)
+        .unwrap();
+    assert_eq!(local.read_through_stats().unwrap().fetched, 2);
+}
@@ -1,10 +1,16 @@
+mod common;
+
 use hex_literal::hex;
 use nomt::{
-    hasher::Blake3Hasher, trie::KeyPath, KeyReadWrite, Nomt, Options, SessionParams, Value,
+    hasher::{Blake3Hasher, ValueHasher},
+    trie::KeyPath,
+    KeyReadWrite, Nomt, Options, RollbackCachePolicy, RollbackStats, RolledBackKey, SessionParams,
+    Value,
 };
 use std::{
     collections::{BTreeMap, BTreeSet},
     path::PathBuf,
+    sync::{Arc, Mutex},
 };
 
 /// Setup a NOMT with the given path, rollback enabled, and the given commit concurrency.
@@ -494,3 +500,223 @@ fn test_rollback_read_then_write() {
     nomt.rollback(1).unwrap();
     assert_eq!(nomt.read(key).unwrap(), None);
 }
+
+#[test]
+fn test_rollback_reports_changed_keys() {
+    let nomt = setup_nomt(
+        "rollback_reports_changed_keys",
+        /* rollback_enabled */ true,
+        /* commit_concurrency */ 1,
+        /* should_clean_up */ true,
+    );
+
+    let commit = |actuals: Vec<(KeyPath, KeyReadWrite)>| {
+        let session = nomt.begin_session(SessionParams::default());
+        session.finish(actuals).unwrap().commit(&nomt).unwrap();
+    };
+    let hash = |value: &[u8]| Some(Blake3Hasher::hash_value(value));
+
+    commit(vec![
+        ([1; 32], KeyReadWrite::Write(Some(vec![1]))),
+        ([2; 32], KeyReadWrite::Write(Some(vec![1]))),
+    ]);
+    // The key [2; 32] is rewritten with the same value, so the rollback doesn't change it.
+    commit(vec![
+        ([1; 32], KeyReadWrite::Write(Some(vec![2]))),
+        ([2; 32], KeyReadWrite::Write(Some(vec![1]))),
+        ([3; 32], KeyReadWrite::Write(Some(vec![1]))),
+    ]);
+
+    let changed = nomt.rollback_with_changes(1).unwrap();
+    assert_eq!(
+        changed,
+        vec![
+            RolledBackKey {
+                key: [1; 32],
+                prior: hash(&[2]),
+                restored: hash(&[1]),
+            },
+            RolledBackKey {
+                key: [3; 32],
+                prior: hash(&[1]),
+                restored: None,
+            },
+        ]
+    );
+
+    let changed = nomt.rollback_with_changes(1).unwrap();
+    assert_eq!(
+        changed,
+        vec![
+            RolledBackKey {
+                key: [1; 32],
+                prior: hash(&[1]),
+                restored: None,
+            },
+            RolledBackKey {
+                key: [2; 32],
+                prior: hash(&[1]),
+                restored: None,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_rollback_stats_and_disk_alert() {
+    let alerts = Arc::new(Mutex::new(Vec::new()));
+    let nomt = common::setup_nomt_with("test_rollback_stats_and_disk_alert", |o| {
+        o.rollback(true);
+        o.rollback_disk_alert(0.0, {
+            let alerts = alerts.clone();
+            move |usage| alerts.lock().unwrap().push(usage)
+        });
+    });
+    assert_eq!(
+        nomt.rollback_stats().unwrap(),
+        Some(RollbackStats {
+            delta_bytes: Vec::new(),
+            log_bytes: 0,
+        })
+    );
+
+    let commit = |key: u8, value_len: usize| {
+        let session = nomt.begin_session(SessionParams::default());
+        session
+            .finish(vec![(
+                [key; 32],
+                KeyReadWrite::Write(Some(vec![key; value_len])),
+            )])
+            .unwrap()
+            .commit(&nomt)
+            .unwrap();
+    };
+
+    // Each commit overwrites the previous value, which its delta holds. Deltas are stored in
+    // 4KiB-aligned records, so only a large prior value makes one larger.
+    let mut delta_bytes = Vec::new();
+    for value_len in [10, 5000, 10] {
+        commit(1, value_len);
+        delta_bytes.push(nomt.last_commit_write_stats().unwrap().rollback_bytes);
+        let stats = nomt.rollback_stats().unwrap().unwrap();
+        assert_eq!(stats.delta_bytes, delta_bytes);
+        assert!(stats.log_bytes >= delta_bytes.iter().sum::<u64>());
+    }
+    assert!(delta_bytes[2] > delta_bytes[1]);
+
+    // The alert is raised once when the threshold is crossed.
+    {
+        let alerts = alerts.lock().unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].log_bytes > 0);
+        assert!(alerts[0].disk_bytes > alerts[0].log_bytes);
+    }
+
+    // Emptying the log re-arms the alert.
+    nomt.rollback(3).unwrap();
+    assert_eq!(nomt.rollback_stats().unwrap().unwrap().log_bytes, 0);
+    commit(2, 10);
+    assert_eq!(alerts.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn test_rollback_cache_policy() {
+    for policy in [RollbackCachePolicy::Patch, RollbackCachePolicy::Clear] {
+        let name = format!("test_rollback_cache_policy_{policy:?}");
+        let nomt = common::setup_nomt_with(&name, |o| {
+            o.rollback(true);
+            o.rollback_cache_policy(policy);
+        });
+        assert_eq!(nomt.rollback_cache_stats(), None);
+
+        let key = |i: u32| {
+            let mut key = [0; 32];
+            key[..4].copy_from_slice(&i.wrapping_mul(0x9E3779B9).to_be_bytes());
+            key
+        };
+        let commit = |range: std::ops::Range<u32>, tag: u8| {
+            let mut actuals = range
+                .map(|i| (key(i), KeyReadWrite::Write(Some(vec![tag; 8]))))
+                .collect::<Vec<_>>();
+            actuals.sort_by_key(|(k, _)| *k);
+            let session = nomt.begin_session(SessionParams::default());
+            session.finish(actuals).unwrap().commit(&nomt).unwrap();
+        };
+        commit(0..2000, 1);
+        commit(0..10, 2);
+
+        nomt.rollback(1).unwrap();
+        let stats = nomt.rollback_cache_stats().unwrap();
+        match policy {
+            RollbackCachePolicy::Patch => {
+                assert!(stats.pages_after >= stats.pages_before);
+                assert!(stats.leaves_after >= stats.leaves_before);
+            }
+            RollbackCachePolicy::Clear => {
+                assert!(stats.pages_after <= stats.pages_before);
+                assert_eq!(stats.leaves_after, 0);
+            }
+        }
+        assert!(stats.leaves_before > 0);
+
+        for i in [0, 9, 10, 1999] {
+            assert_eq!(nomt.read(key(i)).unwrap(), Some(vec![1; 8]));
+        }
+    }
+}
+
+#[test]
+fn test_export_reverse_delta() {
+    let nomt = setup_nomt(
+        "rollback_export_reverse_delta",
+        /* rollback_enabled */ true,
+        /* commit_concurrency */ 1,
+        /* should_clean_up */ true,
+    );
+
+    let commit = |actuals: Vec<(KeyPath, KeyReadWrite)>| {
+        let session = nomt.begin_session(SessionParams::default());
+        session.finish(actuals).unwrap().commit(&nomt).unwrap();
+        nomt.root()
+    };
+    let hash = |value: &[u8]| Some(Blake3Hasher::hash_value(value));
+
+    let initial_root = nomt.root();
+    let root_1 = commit(vec![
+        ([1; 32], KeyReadWrite::Write(Some(vec![1]))),
+        ([2; 32], KeyReadWrite::Write(Some(vec![1]))),
+    ]);
+    let root_2 = commit(vec![
+        ([1; 32], KeyReadWrite::Write(Some(vec![2]))),
+        ([3; 32], KeyReadWrite::Write(Some(vec![2]))),
+    ]);
+    let root_3 = commit(vec![([2; 32], KeyReadWrite::Write(None))]);
+
+    let delta = nomt.export_reverse_delta(2).unwrap();
+    assert_eq!(delta.pre_root, root_1);
+    assert_eq!(delta.post_root, root_2);
+    assert_eq!(delta.priors, vec![([1; 32], hash(&[1])), ([3; 32], None)]);
+    assert!(delta.verify::<Blake3Hasher>());
+
+    let delta = nomt.export_reverse_delta(1).unwrap();
+    assert_eq!(delta.pre_root, root_2);
+    assert_eq!(delta.post_root, root_3);
+    assert_eq!(delta.priors, vec![([2; 32], hash(&[1]))]);
+    assert!(delta.verify::<Blake3Hasher>());
+
+    let delta = nomt.export_reverse_delta(3).unwrap();
+    assert_eq!(delta.pre_root, initial_root);
+    assert_eq!(delta.priors, vec![([1; 32], None), ([2; 32], None)]);
+    assert!(delta.verify::<Blake3Hasher>());
+
+    // A tampered prior doesn't verify.
+    let mut tampered = delta.clone();
+    tampered.priors[0].1 = hash(&[1]);
+    assert!(!tampered.verify::<Blake3Hasher>());
+
+    // Nothing was written and the log is intact.
+    assert_eq!(nomt.root(), root_3);
+    assert!(nomt.export_reverse_delta(4).is_err());
+    nomt.rollback(3).unwrap();
+    assert_eq!(nomt.root(), initial_root);
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use common::setup_nomt_with;
+use nomt::{KeyReadWrite, SessionMisuse, SessionParams};
+
+fn write(value: u8) -> Vec<([u8; 32], KeyReadWrite)> {
+    vec![([1; 32], KeyReadWrite::Write(Some(vec![value])))]
+}
+
+fn misuse(err: nomt::Error) -> SessionMisuse {
+    match err {
+        nomt::Error::Misuse(misuse) => misuse,
+        err => panic!("not a misuse: {err:?}"),
+    }
+}
+
+#[test]
+fn first_commit_wins() {
+    let nomt = setup_nomt_with("misuse_first_commit_wins", |o| o.rollback(true));
+    let prev_root = nomt.root();
+    let first = nomt.begin_session(SessionParams::default());
+    let second = nomt.begin_session(SessionParams::default());
+    let first = first.finish(write(1)).unwrap();
+    let second = second.finish(write(2)).unwrap();
+
+    first.commit(&nomt).unwrap();
+    let err = second.commit(&nomt).unwrap_err();
+    assert_eq!(
+        misuse(err),
+        SessionMisuse::StaleChangeset {
+            expected: prev_root,
+            actual: nomt.root(),
+        }
+    );
+}
+
+#[test]
+fn overlay_errors_are_typed() {
+    let nomt = setup_nomt_with("misuse_overlay_errors", |o| o.rollback(true));
+    let a = nomt
+        .begin_session(SessionParams::default())
+        .finish(write(1))
+        .unwrap()
+        .into_overlay();
+    let b = nomt
+        .begin_session(SessionParams::default().overlay([&a]).unwrap())
+        .finish(write(2))
+        .unwrap()
+        .into_overlay();
+
+    let err = b.commit(&nomt).unwrap_err();
+    assert_eq!(misuse(err), SessionMisuse::OverlayParentNotCommitted);
+
+    nomt.begin_session(SessionParams::default())
+        .finish(write(3))
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+    let err = a.commit(&nomt).unwrap_err();
+    assert!(matches!(misuse(err), SessionMisuse::StaleChangeset { .. }));
+}
+
+#[test]
+fn live_session_on_thread_is_refused() {
+    let nomt = setup_nomt_with("misuse_live_session", |o| o.rollback(true));
+    let finished = nomt
+        .begin_session(SessionParams::default())
+        .finish(write(1))
+        .unwrap();
+
+    let live = nomt.begin_session(SessionParams::default());
+    let err = finished.commit(&nomt).unwrap_err();
+    assert_eq!(misuse(err), SessionMisuse::LiveSessionOnThread);
+    let err = nomt.rollback(1).unwrap_err();
+    assert_eq!(misuse(err), SessionMisuse::LiveSessionOnThread);
+
+    // Once the session is gone, the database is usable again.
+    drop(live);
+    nomt.begin_session(SessionParams::default())
+        .finish(write(2))
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+}
+
+#[test]
+fn finished_session_outliving_rollback() {
+    let nomt = setup_nomt_with("misuse_rollback", |o| o.rollback(true));
+    nomt.begin_session(SessionParams::default())
+        .finish(write(1))
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+
+    let prev_root = nomt.root();
+    let finished = nomt
+        .begin_session(SessionParams::default())
+        .finish(write(2))
+        .unwrap();
+    nomt.rollback(1).unwrap();
+
+    let err = finished.commit(&nomt).unwrap_err();
+    assert_eq!(
+        misuse(err),
+        SessionMisuse::RolledBack {
+            expected: prev_root,
+            actual: nomt.root(),
+        }
+    );
+}
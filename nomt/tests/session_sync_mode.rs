@@ -0,0 +1,122 @@
+mod common;
+
+use common::setup_nomt;
+use nomt::{hasher::Blake3Hasher, KeyReadWrite, Nomt, Root, SessionParams, SessionSyncMode};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+// Wait until a commit started in another thread waits for the live sessions.
+fn wait_for_pending_commit(nomt: &Nomt<Blake3Hasher>) {
+    while !nomt.is_commit_pending() {
+        std::thread::yield_now();
+    }
+}
+
+// Begin a session with the given mode while a commit is waiting for another live session.
+// Returns the root the new session observes, along with the roots before and after the commit.
+fn begin_while_commit_pending(name: &str, sync_mode: SessionSyncMode) -> [Root; 3] {
+    let nomt = setup_nomt(name);
+    let pre_sync_root = nomt.root();
+
+    let finished = nomt
+        .begin_session(SessionParams::default())
+        .finish(vec![([1; 32], KeyReadWrite::Write(Some(vec![1, 2, 3])))])
+        .unwrap();
+    let post_sync_root = finished.root();
+
+    let nomt = &nomt;
+    std::thread::scope(|scope| {
+        let live = nomt.begin_session(SessionParams::default());
+        let commit = scope.spawn(|| finished.commit(nomt).unwrap());
+
+        wait_for_pending_commit(nomt);
+
+        let (observed_tx, observed_rx) = channel();
+        let session = scope.spawn(move || {
+            let session = nomt.begin_session(SessionParams::default().sync_mode(sync_mode));
+            observed_tx.send(session.prev_root()).unwrap();
+            drop(session);
+        });
+
+        let observed = match sync_mode {
+            SessionSyncMode::PreSync => {
+                // The session begins without waiting for the pending commit.
+                let observed = observed_rx.recv().unwrap();
+                assert!(!commit.is_finished());
+                drop(live);
+                observed
+            }
+            SessionSyncMode::WaitForSync => {
+                drop(live);
+                observed_rx.recv().unwrap()
+            }
+        };
+
+        session.join().unwrap();
+        commit.join().unwrap();
+        [observed, pre_sync_root, post_sync_root]
+    })
+}
+
+#[test]
+fn pre_sync_session_observes_pre_sync_root() {
+    let [observed, pre_sync_root, _] =
+        begin_while_commit_pending("session_sync_mode_pre", SessionSyncMode::PreSync);
+    assert_eq!(observed, pre_sync_root);
+}
+
+#[test]
+fn wait_for_sync_session_observes_post_sync_root() {
+    let [observed, _, post_sync_root] =
+        begin_while_commit_pending("session_sync_mode_wait", SessionSyncMode::WaitForSync);
+    assert_eq!(observed, post_sync_root);
+}
+
+// Begin a PreSync session in another thread, which sends `None` as it begins the session and then
+// the root it observes, and keeps the session until the returned sender is dropped.
+fn begin_pre_sync<'scope>(
+    scope: &'scope std::thread::Scope<'scope, '_>,
+    nomt: &'scope Nomt<Blake3Hasher>,
+) -> (Receiver<Option<Root>>, Sender<()>) {
+    let (observed_tx, observed_rx) = channel();
+    let (drop_tx, drop_rx) = channel::<()>();
+    scope.spawn(move || {
+        observed_tx.send(None).unwrap();
+        let session =
+            nomt.begin_session(SessionParams::default().sync_mode(SessionSyncMode::PreSync));
+        observed_tx.send(Some(session.prev_root())).unwrap();
+        let _ = drop_rx.recv();
+    });
+    (observed_rx, drop_tx)
+}
+
+#[test]
+fn pre_sync_sessions_do_not_starve_commits() {
+    let nomt = setup_nomt("session_sync_mode_starve");
+    let finished = nomt
+        .begin_session(SessionParams::default())
+        .finish(vec![([1; 32], KeyReadWrite::Write(Some(vec![1, 2, 3])))])
+        .unwrap();
+    let post_sync_root = finished.root();
+
+    let nomt = &nomt;
+    std::thread::scope(|scope| {
+        let live = nomt.begin_session(SessionParams::default());
+        let commit = scope.spawn(|| finished.commit(nomt).unwrap());
+        wait_for_pending_commit(nomt);
+
+        // A session begins ahead of the commit while the session it waits for is live...
+        let (first_observed, first_drop) = begin_pre_sync(scope, nomt);
+        assert_eq!(first_observed.recv().unwrap(), None);
+        let pre_sync_root = first_observed.recv().unwrap().unwrap();
+        drop(live);
+
+        // ...but not once only such sessions are left.
+        let (second_observed, second_drop) = begin_pre_sync(scope, nomt);
+        assert_eq!(second_observed.recv().unwrap(), None);
+        drop(first_drop);
+        commit.join().unwrap();
+        assert_ne!(pre_sync_root, post_sync_root);
+        assert_eq!(second_observed.recv().unwrap(), Some(post_sync_root));
+        drop(second_drop);
+    });
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use nomt::{
+    hasher::{Blake3Hasher, ValueHasher as _},
+    proof::{combine_shard_roots, ShardedPathProofVerificationError},
+    sharding::ShardedNomt,
+    trie::{KeyPath, LeafData},
+    KeyReadWrite, Nomt, Options, SessionParams,
+};
+use std::path::PathBuf;
+
+fn shard_path(name: &str, shard: usize) -> PathBuf {
+    common::test_path(name).join(format!("shard-{shard}"))
+}
+
+fn options(name: &str, shard: usize) -> Options {
+    let path = shard_path(name, shard);
+    if path.exists() {
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+    reopen_options(path)
+}
+
+fn reopen_options(path: PathBuf) -> Options {
+    let mut o = Options::new();
+    o.path(path);
+    o.commit_concurrency(1);
+    o
+}
+
+fn key(i: u32) -> KeyPath {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+fn writes(range: std::ops::Range<u32>) -> Vec<(KeyPath, KeyReadWrite)> {
+    let mut actuals = range
+        .map(|i| (key(i), KeyReadWrite::Write(Some(i.to_le_bytes().to_vec()))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    actuals
+}
+
+#[test]
+fn sharded_reads_and_proofs() {
+    let sharded =
+        ShardedNomt::<Blake3Hasher>::open((0..4).map(|i| options("sharding_proofs", i))).unwrap();
+    assert_eq!(sharded.shard_bits(), 2);
+    assert!(sharded.shard_roots().iter().all(|r| r.is_empty()));
+
+    let root = sharded.commit(writes(0..100)).unwrap();
+    assert_eq!(root, sharded.root());
+
+    // Every shard holds its part of the keys, and the root combines the shard roots.
+    for i in 0..100 {
+        let shard = sharded.shard_for(&key(i));
+        assert_eq!(shard, (key(i)[0] >> 6) as usize);
+        assert_eq!(
+            sharded.shards()[shard].read(key(i)).unwrap(),
+            Some(i.to_le_bytes().to_vec())
+        );
+    }
+    let shard_roots = sharded
+        .shard_roots()
+        .into_iter()
+        .map(|r| r.into_inner())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        root.into_inner(),
+        combine_shard_roots::<Blake3Hasher>(&shard_roots)
+    );
+
+    for i in [0, 17, 99] {
+        let value = sharded.read(key(i)).unwrap().unwrap();
+        let proof = sharded.prove(key(i)).unwrap();
+        let verified = proof
+            .verify::<Blake3Hasher>(&key(i), root.into_inner())
+            .unwrap();
+        assert!(verified
+            .confirm_value(&LeafData {
+                key_path: key(i),
+                value_hash: Blake3Hasher::hash_value(&value),
+            })
+            .unwrap());
+    }
+
+    // Absent keys are proven too.
+    let proof = sharded.prove(key(1000)).unwrap();
+    let verified = proof
+        .verify::<Blake3Hasher>(&key(1000), root.into_inner())
+        .unwrap();
+    assert!(verified.confirm_nonexistence(&key(1000)).unwrap());
+
+    // Proofs don't verify against a different root.
+    sharded.commit(writes(100..110)).unwrap();
+    assert_eq!(
+        proof
+            .verify::<Blake3Hasher>(&key(1000), sharded.root().into_inner())
+            .unwrap_err(),
+        ShardedPathProofVerificationError::ShardRootMismatch
+    );
+}
+
+#[test]
+fn invalid_commits_leave_shards_unchanged() {
+    let sharded =
+        ShardedNomt::<Blake3Hasher>::open((0..2).map(|i| options("sharding_invalid", i))).unwrap();
+    sharded.commit(writes(0..10)).unwrap();
+    let root = sharded.root();
+
+    let mut unsorted = writes(10..20);
+    unsorted.reverse();
+    assert!(sharded.commit(unsorted).is_err());
+    assert_eq!(sharded.root(), root);
+}
+
+#[test]
+fn misplaced_keys_are_rejected() {
+    let nomt = Nomt::<Blake3Hasher>::open(options("sharding_misplaced", 1)).unwrap();
+    // Shard 1 of 2 only holds keys starting with a 1 bit.
+    let misplaced = (0..)
+        .map(key)
+        .find(|k| k[0] < 0x80)
+        .map(|k| (k, KeyReadWrite::Write(Some(vec![1]))))
+        .unwrap();
+    let session = nomt.begin_session(SessionParams::default());
+    session
+        .finish(vec![misplaced])
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+    drop(nomt);
+
+    let shards = [
+        options("sharding_misplaced", 0),
+        reopen_options(shard_path("sharding_misplaced", 1)),
+    ];
+    assert!(ShardedNomt::<Blake3Hasher>::open(shards).is_err());
+    assert!(
+        ShardedNomt::<Blake3Hasher>::open((0..3).map(|i| options("sharding_count", i))).is_err()
+    );
+}
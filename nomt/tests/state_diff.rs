@@ -0,0 +1,109 @@
+mod common;
+
+use common::setup_nomt;
+use nomt::{
+    hasher::Blake3Hasher,
+    state_diff::{StateDiffHeader, StateDiffReader, StateDiffWriter},
+    trie::KeyPath,
+    CancellationToken, Cancelled, KeyReadWrite, Nomt, SessionParams, Value,
+};
+use std::collections::BTreeMap;
+
+fn commit(nomt: &Nomt<Blake3Hasher>, changes: &BTreeMap<KeyPath, Option<Value>>) {
+    let actuals = changes
+        .iter()
+        .map(|(key, value)| (*key, KeyReadWrite::Write(value.clone())))
+        .collect();
+    let session = nomt.begin_session(SessionParams::default());
+    session.finish(actuals).unwrap().commit(nomt).unwrap();
+}
+
+fn key(i: u8) -> KeyPath {
+    [i; 32]
+}
+
+#[test]
+fn follower_reaches_leader_state() {
+    let leader = setup_nomt("state_diff_leader");
+    let follower = setup_nomt("state_diff_follower");
+
+    let base = BTreeMap::from([(key(1), Some(vec![1])), (key(2), Some(vec![2]))]);
+    commit(&leader, &base);
+    commit(&follower, &base);
+
+    let header = StateDiffHeader {
+        from_seqn: leader.sync_seqn(),
+        from_root: leader.root(),
+        to_seqn: 0,
+        to_root: leader.root(),
+    };
+
+    // Accumulate the net changes of several commits: a key is changed twice, one is added and
+    // then deleted, and one is deleted.
+    let commits = [
+        BTreeMap::from([(key(1), Some(vec![10])), (key(3), Some(vec![3]))]),
+        BTreeMap::from([(key(1), Some(vec![11])), (key(4), Some(vec![4]))]),
+        BTreeMap::from([(key(2), None), (key(4), None)]),
+    ];
+    let mut net = BTreeMap::new();
+    for changes in &commits {
+        commit(&leader, changes);
+        net.extend(changes.clone());
+    }
+
+    let header = StateDiffHeader {
+        to_seqn: leader.sync_seqn(),
+        to_root: leader.root(),
+        ..header
+    };
+    let mut writer = StateDiffWriter::new(Vec::new(), &header).unwrap();
+    for (key, value) in &net {
+        writer.write(*key, value.as_deref()).unwrap();
+    }
+    let diff = writer.finish().unwrap();
+
+    let apply = |diff: &[u8], expected_root| {
+        follower.apply_diff(StateDiffReader::new(diff).unwrap(), expected_root, None)
+    };
+    let root_before = follower.root();
+
+    // A diff with different contents than its header claims is rejected without committing.
+    let mut wrong = StateDiffWriter::new(Vec::new(), &header).unwrap();
+    wrong.write(key(1), Some(&[12])).unwrap();
+    let wrong = wrong.finish().unwrap();
+    assert!(apply(&wrong, header.to_root).is_err());
+    assert_eq!(follower.root(), root_before);
+
+    // So is a correct diff which doesn't lead to the expected root.
+    assert!(apply(&diff, root_before).is_err());
+    assert_eq!(follower.root(), root_before);
+
+    // A corrupted diff is rejected as well.
+    let mut corrupted = diff.clone();
+    let last = corrupted.len() - 1;
+    corrupted[last] ^= 1;
+    assert!(apply(&corrupted, header.to_root).is_err());
+    assert_eq!(follower.root(), root_before);
+
+    // A cancelled application commits nothing.
+    let cancel = CancellationToken::with_timeout(std::time::Duration::ZERO);
+    let err = follower
+        .apply_diff(
+            StateDiffReader::new(&diff[..]).unwrap(),
+            header.to_root,
+            &cancel,
+        )
+        .unwrap_err();
+    assert!(err.downcast_ref::<Cancelled>().is_some());
+    assert_eq!(follower.root(), root_before);
+
+    apply(&diff, header.to_root).unwrap();
+    assert_eq!(follower.root(), leader.root());
+    assert_eq!(follower.read(key(1)).unwrap(), Some(vec![11]));
+    assert_eq!(follower.read(key(2)).unwrap(), None);
+    assert_eq!(follower.read(key(3)).unwrap(), Some(vec![3]));
+    assert_eq!(follower.read(key(4)).unwrap(), None);
+
+    // The follower is no longer at the state the diff applies to.
+    assert!(apply(&diff, header.to_root).is_err());
+}
@@ -0,0 +1,131 @@
+mod common;
+
+use nomt::{
+    hasher::Blake3Hasher, FinishedSession, KeyReadWrite, Nomt, SessionParams, StorageClass,
+};
+use std::collections::HashSet;
+
+fn open_nomt(name: &str) -> Nomt<Blake3Hasher> {
+    common::setup_nomt_with(name, |o| o.metrics(true))
+}
+
+fn key(first_byte: u8, i: u16) -> [u8; 32] {
+    let mut key = [0; 32];
+    key[0] = first_byte;
+    key[1..3].copy_from_slice(&i.to_be_bytes());
+    key
+}
+
+fn commit(
+    nomt: &Nomt<Blake3Hasher>,
+    keys: impl IntoIterator<Item = [u8; 32]>,
+    hints: impl IntoIterator<Item = ([u8; 32], StorageClass)>,
+) {
+    finish(nomt, keys, hints).commit(nomt).unwrap();
+}
+
+fn finish(
+    nomt: &Nomt<Blake3Hasher>,
+    keys: impl IntoIterator<Item = [u8; 32]>,
+    hints: impl IntoIterator<Item = ([u8; 32], StorageClass)>,
+) -> FinishedSession {
+    let mut session = nomt.begin_session(SessionParams::default());
+    for (key, class) in hints {
+        session.hint_storage_class(key, class);
+    }
+    let mut actuals = keys
+        .into_iter()
+        .map(|key| (key, KeyReadWrite::Write(Some(vec![1; 64]))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(key, _)| *key);
+    session.finish(actuals).unwrap()
+}
+
+#[test]
+fn cold_leaves_not_cached() {
+    let nomt = open_nomt("storage_class_cold");
+    let hot = (0..500).map(|i| key(0x00, i)).collect::<Vec<_>>();
+    let cold = (0..500).map(|i| key(0xFF, i)).collect::<Vec<_>>();
+    commit(
+        &nomt,
+        hot.iter().chain(&cold).copied(),
+        hot.iter()
+            .map(|key| (*key, StorageClass::Hot))
+            .chain(cold.iter().map(|key| (*key, StorageClass::Cold))),
+    );
+
+    // Only the cold keys sharing a leaf with the last hot keys remain cached.
+    let cached = nomt
+        .hot_keys(usize::MAX)
+        .into_iter()
+        .collect::<HashSet<_>>();
+    assert!(hot.iter().all(|key| cached.contains(key)));
+    let cached_cold = cold.iter().filter(|key| cached.contains(*key)).count();
+    assert!(cached_cold < 100, "{cached_cold} cold keys cached");
+    assert!(nomt.metrics().get_cold_leaves_uncached() > 0);
+
+    // Cold values are still there, and reading them caches their leaves.
+    assert_eq!(nomt.read(cold[0]).unwrap(), Some(vec![1; 64]));
+    assert!(nomt.hot_keys(usize::MAX).contains(&cold[0]));
+}
+
+#[test]
+fn hot_hint_wins_within_leaf() {
+    let nomt = open_nomt("storage_class_shared_leaf");
+    let (a, b) = (key(0x10, 0), key(0x10, 1));
+    commit(
+        &nomt,
+        [a, b],
+        [(a, StorageClass::Cold), (b, StorageClass::Hot)],
+    );
+    let cached = nomt.hot_keys(usize::MAX);
+    assert!(cached.contains(&a) && cached.contains(&b));
+    assert_eq!(nomt.metrics().get_cold_leaves_uncached(), 0);
+}
+
+#[test]
+fn hints_apply_to_written_keys_only() {
+    let nomt = open_nomt("storage_class_unwritten");
+    let (a, b) = (key(0x20, 0), key(0x80, 0));
+    let uncached = || nomt.metrics().get_cold_leaves_uncached();
+    commit(&nomt, [a], []);
+
+    // A cold hint for a key which is not written is ignored.
+    commit(&nomt, [b], [(a, StorageClass::Cold)]);
+    assert_eq!(uncached(), 0);
+
+    // The last hint for a key wins.
+    commit(
+        &nomt,
+        [a],
+        [(a, StorageClass::Cold), (a, StorageClass::Hot)],
+    );
+    assert_eq!(uncached(), 0);
+    commit(
+        &nomt,
+        [a],
+        [(a, StorageClass::Hot), (a, StorageClass::Cold)],
+    );
+    assert_eq!(uncached(), 1);
+}
+
+#[test]
+fn hints_apply_on_every_commit_path() {
+    let nomt = open_nomt("storage_class_commit_paths");
+    let uncached = || nomt.metrics().get_cold_leaves_uncached();
+    let cold = |first_byte| [(key(first_byte, 0), StorageClass::Cold)];
+    let commit_paths: [&dyn Fn(FinishedSession); 3] = [
+        &|finished| assert!(finished.try_commit_nonblocking(&nomt).unwrap().is_none()),
+        &|finished| finished.into_overlay().commit(&nomt).unwrap(),
+        &|finished| {
+            let overlay = finished.into_overlay();
+            assert!(overlay.try_commit_nonblocking(&nomt).unwrap().is_none());
+        },
+    ];
+    for (i, commit_path) in commit_paths.into_iter().enumerate() {
+        let first_byte = 0x40 + i as u8;
+        commit_path(finish(&nomt, [key(first_byte, 0)], cold(first_byte)));
+        assert_eq!(uncached(), i as u64 + 1);
+        assert!(!nomt.hot_keys(usize::MAX).contains(&key(first_byte, 0)));
+    }
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::clean_test_path;
+use nomt::{hasher::Blake3Hasher, KeyReadWrite, Nomt, Options, SessionParams, StorageLayout};
+use std::path::Path;
+
+fn options(path: &Path, layout: StorageLayout) -> Options {
+    let mut o = Options::new();
+    o.path(path);
+    o.commit_concurrency(1);
+    o.layout(layout);
+    o
+}
+
+#[test]
+fn files_placed_in_other_directories() {
+    let root = clean_test_path("storage_layout");
+    let db = root.join("db");
+    let fast = root.join("fast");
+    let bulk = root.join("bulk");
+    std::fs::create_dir_all(&fast).unwrap();
+    std::fs::create_dir_all(&bulk).unwrap();
+
+    let mut layout = StorageLayout::new();
+    layout.wal(&fast);
+    layout.beatree(&bulk);
+
+    // The directories must exist.
+    let mut missing = layout.clone();
+    missing.hash_table(root.join("missing"));
+    assert!(Nomt::<Blake3Hasher>::open(options(&db, missing)).is_err());
+    assert!(!db.exists());
+    assert_eq!(std::fs::read_dir(&fast).unwrap().count(), 0);
+
+    let nomt = Nomt::<Blake3Hasher>::open(options(&db, layout.clone())).unwrap();
+    nomt.begin_session(SessionParams::default())
+        .finish(vec![([1; 32], KeyReadWrite::Write(Some(vec![1; 1000])))])
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+    let root_node = nomt.root();
+    let stats = nomt.space_stats().unwrap();
+    assert!(stats.ht_bytes > 0);
+    assert!(stats.beatree_bytes > 0);
+    drop(nomt);
+
+    // The files live in the given directories and are linked into the database directory.
+    for (name, dir) in [("wal", &fast), ("ln", &bulk), ("bbn", &bulk)] {
+        let link = db.join(name);
+        assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+        let target = std::fs::canonicalize(&link).unwrap();
+        assert_eq!(target.parent(), Some(&*std::fs::canonicalize(dir).unwrap()));
+    }
+    assert!(db.join("ht").symlink_metadata().unwrap().is_file());
+
+    // Reopening finds the files with the same layout or without one, but not with another.
+    let nomt = Nomt::<Blake3Hasher>::open(options(&db, layout)).unwrap();
+    assert_eq!(nomt.root(), root_node);
+    drop(nomt);
+    let mut moved = StorageLayout::new();
+    moved.wal(&bulk);
+    assert!(Nomt::<Blake3Hasher>::open(options(&db, moved)).is_err());
+    let nomt = Nomt::<Blake3Hasher>::open(options(&db, StorageLayout::new())).unwrap();
+    assert_eq!(nomt.read([1; 32]).unwrap(), Some(vec![1; 1000]));
+
+    // A clone gathers all files in one directory.
+    let clone = root.join("clone");
+    nomt.clone_to(&clone).unwrap();
+    assert!(clone.join("wal").symlink_metadata().unwrap().is_file());
+    let clone = Nomt::<Blake3Hasher>::open(options(&clone, StorageLayout::new())).unwrap();
+    assert_eq!(clone.root(), root_node);
+}
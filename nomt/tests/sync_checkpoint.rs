@@ -0,0 +1,155 @@
+mod common;
+
+use common::{account_path, test_path};
+use nomt::{
+    hasher::Blake3Hasher, KeyReadWrite, Nomt, Options, PanicOnSyncMode, Root, SessionParams,
+};
+
+fn open(
+    name: &str,
+    checkpoint_interval: Option<usize>,
+    panic_on_sync: Option<PanicOnSyncMode>,
+    clean: bool,
+) -> Nomt<Blake3Hasher> {
+    let configure = |o: &mut Options| {
+        o.bitbox_seed([0; 16]);
+        o.hashtable_buckets(64_000);
+        o.rollback(true);
+        o.io_workers(1);
+        if let Some(interval) = checkpoint_interval {
+            o.sync_checkpoint_interval(interval);
+        }
+        if let Some(mode) = panic_on_sync {
+            o.panic_on_sync(mode);
+        }
+    };
+    if clean {
+        common::setup_nomt_with(name, configure)
+    } else {
+        common::open_nomt(name, configure)
+    }
+}
+
+fn commit_accounts(nomt: &Nomt<Blake3Hasher>, n: u64) -> Root {
+    let session = nomt.begin_session(SessionParams::default());
+    let mut actuals = (0..n)
+        .map(|id| {
+            let value = id.to_le_bytes().to_vec();
+            (account_path(id), KeyReadWrite::Write(Some(value)))
+        })
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    let finished = session.finish(actuals).unwrap();
+    let root = finished.root();
+    finished.commit(nomt).unwrap();
+    root
+}
+
+fn check_accounts(nomt: &Nomt<Blake3Hasher>, n: u64) {
+    for id in 0..n {
+        assert_eq!(
+            nomt.read(account_path(id)).unwrap(),
+            Some(id.to_le_bytes().to_vec())
+        );
+    }
+}
+
+fn expected_root(name: &str, n: u64) -> Root {
+    let reference = open(&format!("{name}_reference"), None, None, true);
+    let root = commit_accounts(&reference, n);
+    assert_eq!(reference.sync_seqn(), 1);
+    root
+}
+
+#[test]
+fn checkpointed_commit_is_one_root_switch() {
+    let expected = expected_root("sync_checkpoint_commit", 1000);
+
+    let nomt = open("sync_checkpoint_commit", Some(100), None, true);
+    assert_eq!(commit_accounts(&nomt, 1000), expected);
+    assert_eq!(nomt.root(), expected);
+    assert_eq!(nomt.sync_seqn(), 1);
+    check_accounts(&nomt, 1000);
+
+    // The values are written a second time, to the journal.
+    let journal_bytes = nomt.total_write_stats().journal_bytes;
+    assert!(journal_bytes > 1000 * 32, "{journal_bytes}");
+    assert!(!test_path("sync_checkpoint_commit")
+        .join("checkpoint")
+        .exists());
+
+    // Small commits are not split.
+    let session = nomt.begin_session(SessionParams::default());
+    let actuals = vec![(account_path(1), KeyReadWrite::Write(None))];
+    session.finish(actuals).unwrap().commit(&nomt).unwrap();
+    assert_eq!(nomt.sync_seqn(), 2);
+    assert_eq!(nomt.total_write_stats().journal_bytes, journal_bytes);
+
+    nomt.rollback(1).unwrap();
+    assert_eq!(nomt.root(), expected);
+    drop(nomt);
+
+    let nomt = open("sync_checkpoint_commit", Some(100), None, false);
+    assert_eq!(nomt.root(), expected);
+    check_accounts(&nomt, 1000);
+}
+
+fn check_resume_after_crash(name: &str, mode: PanicOnSyncMode) {
+    let expected = expected_root(name, 1000);
+
+    let nomt = open(name, Some(100), Some(mode), true);
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        commit_accounts(&nomt, 1000);
+    }));
+    assert!(res.is_err());
+    drop(nomt);
+    assert!(test_path(name).join("checkpoint").exists());
+
+    let nomt = open(name, Some(100), None, false);
+    assert_eq!(nomt.root(), expected);
+    assert_eq!(nomt.sync_seqn(), 1);
+    check_accounts(&nomt, 1000);
+    assert!(!test_path(name).join("checkpoint").exists());
+
+    // The rollback log survived the recovery.
+    nomt.rollback(1).unwrap();
+    assert!(nomt.is_empty());
+    assert_eq!(nomt.read(account_path(0)).unwrap(), None);
+}
+
+#[test]
+fn resume_after_first_checkpoint() {
+    check_resume_after_crash(
+        "sync_checkpoint_resume_first",
+        PanicOnSyncMode::PostCheckpoint,
+    );
+}
+
+#[test]
+fn resume_before_final_meta() {
+    check_resume_after_crash("sync_checkpoint_resume_final", PanicOnSyncMode::PostWal);
+}
+
+#[test]
+fn checkpoints_require_an_upgraded_format() {
+    let name = "sync_checkpoint_upgrade";
+    drop(open(name, None, None, true));
+
+    // Rewrite the meta as one of format version 3, before checkpoints were recorded.
+    let meta_path = test_path(name).join("meta");
+    let mut meta = std::fs::read(&meta_path).unwrap();
+    meta[4..8].copy_from_slice(&3u32.to_le_bytes());
+    std::fs::write(&meta_path, meta).unwrap();
+
+    let options = |upgrade| {
+        let mut o = common::test_options(name);
+        o.sync_checkpoint_interval(100);
+        o.upgrade_format(upgrade);
+        o
+    };
+    assert!(Nomt::<Blake3Hasher>::open(options(false)).is_err());
+    let nomt = Nomt::<Blake3Hasher>::open(options(true)).unwrap();
+    commit_accounts(&nomt, 1000);
+    assert_eq!(nomt.sync_seqn(), 1);
+    check_accounts(&nomt, 1000);
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use common::setup_nomt;
+use nomt::{
+    hasher::Blake3Hasher,
+    proof::RangeProofVerificationError,
+    sync_protocol::{Chunk, ChunkExporter, ChunkImporter, ChunkVerificationError},
+    KeyReadWrite, LocalValue, Nomt, SessionParams,
+};
+
+fn key(i: u32) -> [u8; 32] {
+    let mut key = [0; 32];
+    key[..4].copy_from_slice(&i.wrapping_mul(0x9E3779B9).to_be_bytes());
+    key
+}
+
+// Every hundredth value is large enough to be stored in overflow pages.
+fn value(i: u32) -> Vec<u8> {
+    let len = if i.is_multiple_of(100) { 5000 } else { 16 };
+    vec![i as u8; len]
+}
+
+fn populate(nomt: &Nomt<Blake3Hasher>, count: u32) {
+    let mut actuals = (0..count)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(value(i)))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+}
+
+fn export(nomt: &Nomt<Blake3Hasher>, max_values: usize) -> Vec<Chunk> {
+    ChunkExporter::new(nomt, max_values)
+        .collect::<anyhow::Result<_>>()
+        .unwrap()
+}
+
+fn verification_error(e: anyhow::Error) -> ChunkVerificationError {
+    *e.downcast_ref::<ChunkVerificationError>().unwrap()
+}
+
+#[test]
+fn export_and_import() {
+    let source = setup_nomt("sync_protocol_source");
+    populate(&source, 3000);
+    let chunks = export(&source, 256);
+    assert_eq!(chunks.len(), 12);
+    assert!(chunks.iter().all(|chunk| chunk.values.len() <= 256));
+    assert_eq!(chunks.last().unwrap().end, None);
+
+    let dest = setup_nomt("sync_protocol_dest");
+    let mut importer = ChunkImporter::new(&dest, source.root()).unwrap();
+    for chunk in &chunks {
+        assert_eq!(importer.next_start(), Some(chunk.start));
+        importer.import(chunk).unwrap();
+    }
+    assert_eq!(importer.next_start(), None);
+    assert_eq!(importer.imported_values(), 3000);
+    importer.finish().unwrap();
+
+    assert_eq!(dest.root(), source.root());
+    for i in [0, 1, 100, 2999] {
+        assert_eq!(dest.read(key(i)).unwrap(), Some(value(i)));
+    }
+}
+
+#[test]
+fn export_and_import_empty() {
+    let source = setup_nomt("sync_protocol_empty_source");
+    let chunks = export(&source, 16);
+    assert_eq!(chunks.len(), 1);
+    assert!(chunks[0].values.is_empty());
+
+    let dest = setup_nomt("sync_protocol_empty_dest");
+    let mut importer = ChunkImporter::new(&dest, source.root()).unwrap();
+    importer.import(&chunks[0]).unwrap();
+    importer.finish().unwrap();
+    assert!(dest.root().is_empty());
+}
+
+#[test]
+fn rejects_bad_chunks() {
+    let source = setup_nomt("sync_protocol_bad_source");
+    populate(&source, 1000);
+    let chunks = export(&source, 100);
+    let target = source.root();
+
+    let dest = setup_nomt("sync_protocol_bad_dest");
+    let mut importer = ChunkImporter::new(&dest, target).unwrap();
+
+    // Skipping a chunk.
+    let e = importer.import(&chunks[1]).unwrap_err();
+    assert_eq!(verification_error(e), ChunkVerificationError::OutOfOrder);
+
+    // A tampered value.
+    let mut tampered = chunks[0].clone();
+    let LocalValue::Stored(ref mut value) = tampered.values[3].1 else {
+        panic!("only stored values were written");
+    };
+    value.push(0);
+    let e = importer.import(&tampered).unwrap_err();
+    assert_eq!(verification_error(e), ChunkVerificationError::ValueMismatch);
+
+    // A value left out, along with its proof.
+    let mut truncated = chunks[0].clone();
+    truncated.values.remove(3);
+    truncated.proof.leaves.remove(3);
+    let e = importer.import(&truncated).unwrap_err();
+    assert_eq!(
+        verification_error(e),
+        ChunkVerificationError::InvalidProof(RangeProofVerificationError::Gap)
+    );
+
+    // A chunk of another root.
+    let other = setup_nomt("sync_protocol_bad_other");
+    populate(&other, 10);
+    let other_chunks = export(&other, 100);
+    let e = importer.import(&other_chunks[0]).unwrap_err();
+    assert_eq!(verification_error(e), ChunkVerificationError::RootMismatch);
+
+    // Nothing was written by the rejected chunks, and the import goes on.
+    assert!(dest.root().is_empty());
+    for chunk in &chunks {
+        importer.import(chunk).unwrap();
+    }
+    importer.finish().unwrap();
+    assert_eq!(dest.root(), target);
+}
+
+#[test]
+fn incomplete_import_fails() {
+    let source = setup_nomt("sync_protocol_incomplete_source");
+    populate(&source, 1000);
+    let chunks = export(&source, 100);
+
+    let dest = setup_nomt("sync_protocol_incomplete_dest");
+    let mut importer = ChunkImporter::new(&dest, source.root()).unwrap();
+    importer.import(&chunks[0]).unwrap();
+    assert!(importer.finish().is_err());
+
+    // The database isn't empty anymore.
+    assert!(ChunkImporter::new(&dest, source.root()).is_err());
+}
+
+#[test]
+fn export_fails_after_commit() {
+    let nomt = setup_nomt("sync_protocol_moving_root");
+    populate(&nomt, 1000);
+    let mut exporter = ChunkExporter::new(&nomt, 100);
+    let first = exporter.next().unwrap().unwrap();
+
+    populate(&nomt, 1001);
+    assert!(exporter.next().unwrap().is_err());
+
+    // A chunk of the old root can't be served anymore.
+    let mut resumed = ChunkExporter::resume(&nomt, first.root, first.end.unwrap(), 100);
+    assert!(resumed.next().unwrap().is_err());
+}
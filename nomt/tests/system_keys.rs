@@ -0,0 +1,111 @@
+mod common;
+
+use nomt::{
+    hasher::{Blake3Hasher, ValueHasher as _},
+    migration::{migrate, MigrationOptions},
+    proof::PathProofTerminal,
+    system_keys::{self, SystemKey},
+    trie::{KeyPath, LeafData},
+    KeyReadWrite, Nomt, SessionParams,
+};
+
+fn setup_nomt(name: &str, reserve: bool) -> Nomt<Blake3Hasher> {
+    common::setup_nomt_with(name, |o| o.reserve_system_keyspace(reserve))
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, actuals: Vec<(KeyPath, KeyReadWrite)>) -> anyhow::Result<()> {
+    let session = nomt.begin_session(SessionParams::default());
+    Ok(session.finish(actuals)?.commit(nomt)?)
+}
+
+fn write(i: u8) -> (KeyPath, KeyReadWrite) {
+    ([i; 32], KeyReadWrite::Write(Some(vec![i; 4])))
+}
+
+#[test]
+fn format_version_is_provable() {
+    let nomt = setup_nomt("system_keys_format_version", true);
+    assert_eq!(nomt.read_system(SystemKey::FormatVersion).unwrap(), None);
+
+    commit(&nomt, vec![write(1), write(2)]).unwrap();
+    let format_version = system_keys::FORMAT_VERSION.to_le_bytes().to_vec();
+    assert_eq!(
+        nomt.read_system(SystemKey::FormatVersion).unwrap(),
+        Some(format_version.clone())
+    );
+
+    // The value is proven under the root like any other.
+    let key_path = SystemKey::FormatVersion.key_path();
+    assert!(system_keys::is_system_key(&key_path));
+    let proof = nomt
+        .begin_session(SessionParams::default())
+        .prove(key_path)
+        .unwrap();
+    let verified = proof
+        .verify::<Blake3Hasher>(proof.terminal.path(), nomt.root().into_inner())
+        .unwrap();
+    assert!(matches!(proof.terminal, PathProofTerminal::Leaf(_)));
+    assert!(verified
+        .confirm_value(&LeafData {
+            key_path,
+            value_hash: Blake3Hasher::hash_value(&format_version),
+        })
+        .unwrap());
+
+    // Later commits leave the system values alone. They can be read, but not written.
+    let root = nomt.root();
+    let mut actuals = vec![
+        write(3),
+        (key_path, KeyReadWrite::Read(Some(format_version.clone()))),
+    ];
+    actuals.sort_by_key(|(k, _)| *k);
+    commit(&nomt, actuals).unwrap();
+    assert_ne!(nomt.root(), root);
+
+    let root = nomt.root();
+    let mut actuals = vec![write(4), (key_path, KeyReadWrite::Write(None))];
+    actuals.sort_by_key(|(k, _)| *k);
+    assert!(commit(&nomt, actuals).is_err());
+    assert_eq!(nomt.root(), root);
+    assert_eq!(
+        nomt.read_system(SystemKey::FormatVersion).unwrap(),
+        Some(format_version)
+    );
+}
+
+#[test]
+fn unreserved_keyspace_is_unmanaged() {
+    let nomt = setup_nomt("system_keys_unreserved", false);
+    let key_path = SystemKey::FormatVersion.key_path();
+    commit(
+        &nomt,
+        vec![write(1), (key_path, KeyReadWrite::Write(Some(vec![7])))],
+    )
+    .unwrap();
+    assert_eq!(
+        nomt.read_system(SystemKey::FormatVersion).unwrap(),
+        Some(vec![7])
+    );
+}
+
+#[test]
+fn migration_records_source() {
+    let source = setup_nomt("system_keys_migration_source", true);
+    commit(&source, (1..=100).map(write).collect()).unwrap();
+
+    let dest = setup_nomt("system_keys_migration_dest", true);
+    let mut options = MigrationOptions::new();
+    options.batch_size(30);
+    let report = migrate(&source, &dest, &options, |key, _| *key).unwrap();
+
+    // The system value of the source is not migrated.
+    assert_eq!(report.migrated, 100);
+    assert_eq!(
+        dest.read_system(SystemKey::MigrationSource).unwrap(),
+        Some(source.root().into_inner().to_vec())
+    );
+    assert_eq!(
+        dest.read_system(SystemKey::FormatVersion).unwrap(),
+        source.read_system(SystemKey::FormatVersion).unwrap()
+    );
+}
@@ -0,0 +1,141 @@
+#![cfg(feature = "opentelemetry")]
+
+mod common;
+
+use common::setup_nomt;
+use nomt::{KeyReadWrite, SessionParams};
+use opentelemetry::{
+    global,
+    trace::{SpanId, Status, TraceContextExt as _, TraceId, Tracer as _},
+    Context,
+};
+use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider, SpanData};
+use std::sync::OnceLock;
+
+// The global tracer provider is shared by all tests, so they tell their spans apart by trace ID.
+fn exporter() -> &'static InMemorySpanExporter {
+    static EXPORTER: OnceLock<InMemorySpanExporter> = OnceLock::new();
+    EXPORTER.get_or_init(|| {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        global::set_tracer_provider(provider);
+        exporter
+    })
+}
+
+fn root_context(name: &'static str) -> Context {
+    exporter();
+    Context::current_with_span(global::tracer("test").start(name))
+}
+
+fn finished_spans(trace_id: TraceId) -> Vec<SpanData> {
+    exporter()
+        .get_finished_spans()
+        .unwrap()
+        .into_iter()
+        .filter(|span| span.span_context.trace_id() == trace_id)
+        .collect()
+}
+
+fn find<'a>(spans: &'a [SpanData], name: &str) -> Vec<&'a SpanData> {
+    spans.iter().filter(|span| span.name == name).collect()
+}
+
+fn span_id(spans: &[SpanData], name: &str) -> SpanId {
+    let found = find(spans, name);
+    assert_eq!(found.len(), 1, "{name}");
+    found[0].span_context.span_id()
+}
+
+#[test]
+fn session_spans_extend_the_caller_trace() {
+    let nomt = setup_nomt("trace_session");
+    let cx = root_context("rpc");
+    let trace_id = cx.span().span_context().trace_id();
+    let rpc_id = cx.span().span_context().span_id();
+
+    let session = nomt.begin_session(SessionParams::default().trace_context(cx.clone()));
+    assert_eq!(session.read([1; 32]).unwrap(), None);
+    assert_eq!(session.read([2; 32]).unwrap(), None);
+    session
+        .finish(vec![([1; 32], KeyReadWrite::Write(Some(vec![1, 2, 3])))])
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+    cx.span().end();
+
+    let spans = finished_spans(trace_id);
+    let session_id = span_id(&spans, "nomt.session");
+    let commit_id = span_id(&spans, "nomt.commit");
+    assert_eq!(find(&spans, "nomt.session")[0].parent_span_id, rpc_id);
+    assert_eq!(find(&spans, "nomt.read").len(), 2);
+    for name in ["nomt.read", "nomt.finish", "nomt.commit"] {
+        for span in find(&spans, name) {
+            assert_eq!(span.parent_span_id, session_id, "{name}");
+        }
+    }
+    for name in [
+        "nomt.sync.wal",
+        "nomt.sync.values",
+        "nomt.sync.meta",
+        "nomt.sync.post_meta",
+    ] {
+        let found = find(&spans, name);
+        assert_eq!(found.len(), 1, "{name}");
+        assert_eq!(found[0].parent_span_id, commit_id, "{name}");
+    }
+    assert!(spans
+        .iter()
+        .all(|span| !matches!(span.status, Status::Error { .. })));
+}
+
+#[test]
+fn failed_commit_is_recorded() {
+    let nomt = setup_nomt("trace_failed_commit");
+    let cx = root_context("rpc");
+    let trace_id = cx.span().span_context().trace_id();
+
+    let stale = nomt
+        .begin_session(SessionParams::default().trace_context(cx.clone()))
+        .finish(vec![([1; 32], KeyReadWrite::Write(Some(vec![1])))])
+        .unwrap();
+    nomt.begin_session(SessionParams::default())
+        .finish(vec![([2; 32], KeyReadWrite::Write(Some(vec![2])))])
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+    assert!(stale.commit(&nomt).is_err());
+
+    let spans = finished_spans(trace_id);
+    let commit = find(&spans, "nomt.commit");
+    assert_eq!(commit.len(), 1);
+    assert!(matches!(commit[0].status, Status::Error { .. }));
+    assert!(find(&spans, "nomt.sync.wal").is_empty());
+}
+
+#[test]
+fn overlay_commit_uses_current_context() {
+    let nomt = setup_nomt("trace_overlay");
+    let cx = root_context("block");
+    let trace_id = cx.span().span_context().trace_id();
+    let block_id = cx.span().span_context().span_id();
+
+    // Untraced sessions record nothing.
+    let overlay = nomt
+        .begin_session(SessionParams::default())
+        .finish(vec![([1; 32], KeyReadWrite::Write(Some(vec![1])))])
+        .unwrap()
+        .into_overlay();
+    {
+        let _attached = cx.clone().attach();
+        overlay.commit(&nomt).unwrap();
+    }
+
+    let spans = finished_spans(trace_id);
+    assert!(find(&spans, "nomt.session").is_empty());
+    assert_eq!(find(&spans, "nomt.commit")[0].parent_span_id, block_id);
+    let commit_id = span_id(&spans, "nomt.commit");
+    assert_eq!(find(&spans, "nomt.sync.meta")[0].parent_span_id, commit_id);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use nomt::{
+    hasher::Blake3Hasher, trie::KeyPath, DeepPathPolicy, KeyReadWrite, Nomt, SessionParams,
+    TrieDepthExceeded,
+};
+
+fn setup_nomt(name: &str, policy: DeepPathPolicy) -> Nomt<Blake3Hasher> {
+    common::setup_nomt_with(name, |o| {
+        o.commit_concurrency(2);
+        o.metrics(true);
+        o.max_trie_depth(64, policy);
+    })
+}
+
+fn write(nomt: &Nomt<Blake3Hasher>, keys: &[KeyPath]) -> anyhow::Result<()> {
+    let actuals = keys
+        .iter()
+        .map(|key| (*key, KeyReadWrite::Write(Some(vec![1]))))
+        .collect();
+    Ok(nomt
+        .begin_session(SessionParams::default())
+        .finish(actuals)?
+        .commit(nomt)?)
+}
+
+// Two keys differing only in the last bit, which end up 256 nodes deep.
+fn colliding_keys() -> [KeyPath; 2] {
+    let mut b = [0xAA; 32];
+    b[31] ^= 1;
+    [[0xAA; 32], b]
+}
+
+#[test]
+fn deep_paths_rejected() {
+    let nomt = setup_nomt("trie_depth_reject", DeepPathPolicy::Reject);
+
+    // Hashed keys stay far from the limit.
+    let hashed = (0..1000u32)
+        .map(|i| *blake3::hash(&i.to_le_bytes()).as_bytes())
+        .collect::<std::collections::BTreeSet<_>>();
+    write(&nomt, &hashed.into_iter().collect::<Vec<_>>()).unwrap();
+    assert_eq!(nomt.metrics().get_deep_paths(), 0);
+
+    // Adding the second key pushes the first one down with it.
+    let [a, b] = colliding_keys();
+    write(&nomt, &[a]).unwrap();
+    let root = nomt.root();
+
+    let err = write(&nomt, &[b]).unwrap_err();
+    let err = err.downcast_ref::<TrieDepthExceeded>().unwrap();
+    assert_eq!(err.depth, 256);
+    assert_eq!(err.max_depth, 64);
+    assert!(err.key == a || err.key == b);
+    assert_eq!(nomt.root(), root);
+    assert_eq!(nomt.read(b).unwrap(), None);
+    assert_eq!(nomt.metrics().get_deep_paths(), 2);
+}
+
+#[test]
+fn deep_paths_allowed() {
+    let nomt = setup_nomt("trie_depth_allow", DeepPathPolicy::Allow);
+    let [a, b] = colliding_keys();
+    write(&nomt, &[a, b]).unwrap();
+    assert_eq!(nomt.read(b).unwrap(), Some(vec![1]));
+    assert_eq!(nomt.metrics().get_deep_paths(), 2);
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use common::setup_nomt;
+use nomt::{hasher::Blake3Hasher, KeyReadWrite, Nomt, SessionParams};
+
+fn commit(nomt: &Nomt<Blake3Hasher>, values: impl IntoIterator<Item = ([u8; 32], Vec<u8>)>) {
+    let mut actuals = values
+        .into_iter()
+        .map(|(key, value)| (key, KeyReadWrite::Write(Some(value))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(key, _)| *key);
+    let session = nomt.begin_session(SessionParams::default());
+    session.finish(actuals).unwrap().commit(nomt).unwrap();
+}
+
+fn key(i: u32) -> [u8; 32] {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+#[test]
+fn empty_trie() {
+    let nomt = setup_nomt("trie_stats_empty");
+    let stats = nomt.stats().unwrap();
+    assert_eq!(stats.leaves, 0);
+    assert_eq!(stats.pages, 0);
+    assert_eq!(stats.average_depth(), None);
+    assert_eq!(stats.depth_percentile(0.5), None);
+    assert_eq!(stats.page_fill_factor(), None);
+}
+
+#[test]
+fn single_leaf_is_the_root() {
+    let nomt = setup_nomt("trie_stats_single");
+    commit(&nomt, [(key(0), vec![1; 10])]);
+    let stats = nomt.stats().unwrap();
+    assert_eq!(stats.leaves, 1);
+    assert_eq!(stats.depth_histogram, vec![1]);
+    assert_eq!(stats.pages, 0);
+}
+
+#[test]
+fn depths_match_proofs() {
+    let nomt = setup_nomt("trie_stats_depths");
+    let n = 5000;
+    commit(&nomt, (0..n).map(|i| (key(i), vec![1; 32])));
+    let stats = nomt.stats().unwrap();
+
+    let session = nomt.begin_session(SessionParams::default());
+    let mut histogram = Vec::new();
+    for i in 0..n {
+        let depth = session.prove(key(i)).unwrap().siblings.len();
+        if histogram.len() <= depth {
+            histogram.resize(depth + 1, 0);
+        }
+        histogram[depth] += 1;
+    }
+    drop(session);
+
+    assert_eq!(stats.leaves, n as u64);
+    assert_eq!(stats.depth_histogram, histogram);
+    let average = stats.average_depth().unwrap();
+    assert!((12.0..16.0).contains(&average), "average depth {average}");
+    assert!(stats.depth_percentile(0.5).unwrap() <= stats.depth_percentile(0.99).unwrap());
+    assert_eq!(stats.depth_percentile(1.0), stats.max_depth());
+
+    assert!(stats.pages > 1);
+    let fill = stats.page_fill_factor().unwrap();
+    assert!(fill > 0.0 && fill <= 1.0, "fill factor {fill}");
+    assert_eq!(stats.hash_table, nomt.hash_table_utilization());
+    assert_eq!(stats.overflow_values, 0);
+}
+
+#[test]
+fn counts_overflow_pages() {
+    let nomt = setup_nomt("trie_stats_overflow");
+    commit(
+        &nomt,
+        [
+            (key(0), vec![1; 10]),
+            (key(1), vec![2; 10_000]),
+            (key(2), vec![3; 20_000]),
+        ],
+    );
+    let stats = nomt.stats().unwrap();
+    assert_eq!(stats.leaves, 3);
+    assert_eq!(stats.overflow_values, 2);
+    assert!(stats.overflow_pages >= 8, "{} pages", stats.overflow_pages);
+}
@@ -0,0 +1,242 @@
+mod common;
+
+use bitvec::prelude::*;
+use nomt::{
+    hasher::{Blake3Hasher, ValueHasher},
+    migration::{self, MigrationOptions},
+    proof::verify_range,
+    sync_protocol::{ChunkExporter, ChunkImporter},
+    trie::LeafData,
+    KeyReadWrite, LocalValue, Nomt, SessionParams, ValueNotStored,
+};
+
+fn setup_nomt(name: &str, rollback: bool) -> Nomt<Blake3Hasher> {
+    common::setup_nomt_with(name, |o| o.rollback(rollback))
+}
+
+fn not_stored(err: anyhow::Error) -> ValueNotStored {
+    *err.downcast_ref::<ValueNotStored>().unwrap()
+}
+
+const STORED: [u8; 32] = [1; 32];
+const ELSEWHERE: [u8; 32] = [2; 32];
+
+fn external_value() -> Vec<u8> {
+    vec![7; 10_000]
+}
+
+#[test]
+fn hash_only_values_are_proven_but_not_stored() {
+    let nomt = setup_nomt("value_hash_only_proven", false);
+    let value_hash = Blake3Hasher::hash_value(&external_value());
+
+    let mut session = nomt.begin_session(SessionParams::default());
+    session.write_value_hash(ELSEWHERE, value_hash);
+    session
+        .finish(vec![(STORED, KeyReadWrite::Write(Some(vec![1; 8])))])
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+
+    // The root is the same as if the value itself were stored.
+    let full = setup_nomt("value_hash_only_full", false);
+    full.begin_session(SessionParams::default())
+        .finish(vec![
+            (STORED, KeyReadWrite::Write(Some(vec![1; 8]))),
+            (ELSEWHERE, KeyReadWrite::Write(Some(external_value()))),
+        ])
+        .unwrap()
+        .commit(&full)
+        .unwrap();
+    assert_eq!(nomt.root(), full.root());
+
+    let err = nomt.get(ELSEWHERE).unwrap_err();
+    assert_eq!(
+        not_stored(err),
+        ValueNotStored {
+            key: ELSEWHERE,
+            value_hash,
+        }
+    );
+    assert_eq!(nomt.get(STORED).unwrap(), Some(vec![1; 8]));
+
+    let session = nomt.begin_session(SessionParams::default());
+    assert!(session.read(ELSEWHERE).is_err());
+    assert!(session.read_slice(ELSEWHERE, 0, 4).is_err());
+    assert_eq!(
+        session.read_local(ELSEWHERE).unwrap(),
+        Some(LocalValue::HashOnly(value_hash))
+    );
+    assert_eq!(
+        session.read_local(STORED).unwrap(),
+        Some(LocalValue::Stored(vec![1; 8]))
+    );
+
+    let proof = session.prove(ELSEWHERE).unwrap();
+    let verified = proof
+        .verify::<Blake3Hasher>(ELSEWHERE.view_bits::<Msb0>(), nomt.root().into_inner())
+        .unwrap();
+    assert!(verified
+        .confirm_value(&LeafData {
+            key_path: ELSEWHERE,
+            value_hash,
+        })
+        .unwrap());
+}
+
+#[test]
+fn hash_only_values_in_overlays() {
+    let nomt = setup_nomt("value_hash_only_overlay", false);
+    let value_hash = Blake3Hasher::hash_value(&external_value());
+
+    let mut session = nomt.begin_session(SessionParams::default());
+    session.write_value_hash(ELSEWHERE, [0; 32]);
+    session.write_value_hash(ELSEWHERE, value_hash);
+    let overlay = session.finish(Vec::new()).unwrap().into_overlay();
+
+    let session = nomt.begin_session(SessionParams::default().overlay([&overlay]).unwrap());
+    assert_eq!(
+        not_stored(session.read(ELSEWHERE).unwrap_err()).value_hash,
+        value_hash
+    );
+    assert_eq!(
+        session.read_local(ELSEWHERE).unwrap(),
+        Some(LocalValue::HashOnly(value_hash))
+    );
+    drop(session);
+
+    overlay.commit(&nomt).unwrap();
+    assert_eq!(
+        not_stored(nomt.get(ELSEWHERE).unwrap_err()).value_hash,
+        value_hash
+    );
+
+    // Overwriting the key stores the value again.
+    nomt.begin_session(SessionParams::default())
+        .finish(vec![(ELSEWHERE, KeyReadWrite::Write(Some(vec![3; 4])))])
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+    assert_eq!(nomt.get(ELSEWHERE).unwrap(), Some(vec![3; 4]));
+}
+
+#[test]
+fn conflicting_and_unsupported_hash_writes_are_refused() {
+    let nomt = setup_nomt("value_hash_only_conflict", false);
+    let mut session = nomt.begin_session(SessionParams::default());
+    session.write_value_hash(ELSEWHERE, [9; 32]);
+    assert!(session
+        .finish(vec![(ELSEWHERE, KeyReadWrite::Write(None))])
+        .is_err());
+
+    // A read of the key in the actuals is fine.
+    let mut session = nomt.begin_session(SessionParams::default());
+    session.write_value_hash(ELSEWHERE, [9; 32]);
+    session
+        .finish(vec![(ELSEWHERE, KeyReadWrite::Read(None))])
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+
+    let nomt = setup_nomt("value_hash_only_rollback", true);
+    let mut session = nomt.begin_session(SessionParams::default());
+    session.write_value_hash(ELSEWHERE, [9; 32]);
+    assert!(session.finish(Vec::new()).is_err());
+}
+
+#[test]
+fn hash_only_values_in_ranges_and_migration() {
+    const AFTER: [u8; 32] = [3; 32];
+    let value_hash = Blake3Hasher::hash_value(&external_value());
+    let nomt = setup_nomt("value_hash_only_ranges", false);
+    let mut session = nomt.begin_session(SessionParams::default());
+    session.write_value_hash(ELSEWHERE, value_hash);
+    session
+        .finish(vec![
+            (STORED, KeyReadWrite::Write(Some(vec![1; 8]))),
+            (AFTER, KeyReadWrite::Write(Some(vec![3; 8]))),
+        ])
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+
+    // Iterators fail on the key and carry on with the next one.
+    let mut values = nomt.iter_values([0; 32], None);
+    assert_eq!(values.next().unwrap().unwrap(), (STORED, vec![1; 8]));
+    assert_eq!(
+        not_stored(values.next().unwrap().unwrap_err()).key,
+        ELSEWHERE
+    );
+    assert_eq!(values.next().unwrap().unwrap(), (AFTER, vec![3; 8]));
+    assert!(values.next().is_none());
+    drop(values);
+    let session = nomt.begin_session(SessionParams::default());
+    let mut range = session.iter_range(..);
+    assert_eq!(range.next().unwrap().unwrap(), (STORED, vec![1; 8]));
+    assert_eq!(
+        not_stored(range.next().unwrap().unwrap_err()).key,
+        ELSEWHERE
+    );
+    assert_eq!(range.next().unwrap().unwrap(), (AFTER, vec![3; 8]));
+    drop(range);
+    drop(session);
+
+    // Range proofs list the hash.
+    let range = nomt.prove_range([0; 32], None, 10).unwrap();
+    assert_eq!(
+        range.values,
+        vec![
+            (STORED, LocalValue::Stored(vec![1; 8])),
+            (ELSEWHERE, LocalValue::HashOnly(value_hash)),
+            (AFTER, LocalValue::Stored(vec![3; 8])),
+        ]
+    );
+    let leaves =
+        verify_range::<Blake3Hasher>(&range.proof, &[0; 32], None, range.root.into_inner())
+            .unwrap();
+    for (leaf, (key, value)) in leaves.iter().zip(&range.values) {
+        assert_eq!(leaf.key_path, *key);
+        assert_eq!(leaf.value_hash, value.value_hash::<Blake3Hasher>());
+    }
+
+    // State sync carries the hash over.
+    let dest = setup_nomt("value_hash_only_ranges_sync", false);
+    let mut importer = ChunkImporter::new(&dest, nomt.root()).unwrap();
+    for chunk in ChunkExporter::new(&nomt, 2) {
+        importer.import(&chunk.unwrap()).unwrap();
+    }
+    importer.finish().unwrap();
+    assert_eq!(dest.root(), nomt.root());
+    let session = dest.begin_session(SessionParams::default());
+    assert_eq!(
+        session.read_local(ELSEWHERE).unwrap(),
+        Some(LocalValue::HashOnly(value_hash))
+    );
+    drop(session);
+
+    // The 4-ary trie commits to the hash too.
+    let full = setup_nomt("value_hash_only_ranges_full", false);
+    full.begin_session(SessionParams::default())
+        .finish(vec![
+            (STORED, KeyReadWrite::Write(Some(vec![1; 8]))),
+            (ELSEWHERE, KeyReadWrite::Write(Some(external_value()))),
+            (AFTER, KeyReadWrite::Write(Some(vec![3; 8]))),
+        ])
+        .unwrap()
+        .commit(&full)
+        .unwrap();
+    assert_eq!(
+        nomt.experimental_quad_trie()
+            .unwrap()
+            .root::<Blake3Hasher>(),
+        full.experimental_quad_trie()
+            .unwrap()
+            .root::<Blake3Hasher>()
+    );
+
+    // Migration re-hashes values, which it can't do without them.
+    let dest = setup_nomt("value_hash_only_ranges_migration", false);
+    let err =
+        migration::migrate(&nomt, &dest, &MigrationOptions::new(), |key, _| *key).unwrap_err();
+    assert_eq!(not_stored(err).key, ELSEWHERE);
+}
@@ -0,0 +1,146 @@
+mod common;
+
+use common::{fresh_test_name, key_diverging_at, test_path, Test};
+use nomt::{
+    hasher::Blake3Hasher,
+    proof,
+    trie::{KeyPath, Node, TERMINATOR},
+    Witness,
+};
+use nomt_test_utils::{key_with_prefix, reference_root, TrieUpdateCase};
+use quickcheck::QuickCheck;
+
+// The root `verify_update` computes from the witness of a commit.
+fn verified_root(prev_root: Node, witness: &Witness) -> Node {
+    let mut updates = Vec::new();
+    for (i, witnessed_path) in witness.path_proofs.iter().enumerate() {
+        let verified = witnessed_path
+            .inner
+            .verify::<Blake3Hasher>(witnessed_path.path.path(), prev_root)
+            .unwrap();
+        let ops = witness
+            .writes_for_path(i)
+            .iter()
+            .map(|write| (write.key, write.value))
+            .collect::<Vec<_>>();
+        if !ops.is_empty() {
+            updates.push(proof::PathUpdate {
+                inner: verified,
+                ops,
+            });
+        }
+    }
+    proof::verify_update::<Blake3Hasher>(prev_root, &updates).unwrap()
+}
+
+// Commit the case to a fresh database, checking that the roots of the database, of `verify_update`
+// and of the trie built in memory agree. Returns the root after the batch.
+fn check(name: &str, case: &TrieUpdateCase) -> Node {
+    let name = fresh_test_name(name);
+    let mut t = Test::new(&name);
+
+    for (key, value) in &case.prev_state {
+        t.write(*key, Some(value.clone()));
+    }
+    let (prev_root, _) = t.commit();
+    let prev_root = prev_root.into_inner();
+    assert_eq!(prev_root, reference_root::<Blake3Hasher>(&case.prev_state));
+
+    for (key, value) in &case.writes {
+        t.write(*key, value.clone());
+    }
+    let (new_root, witness) = t.commit();
+    let new_root = new_root.into_inner();
+    assert_eq!(
+        new_root,
+        reference_root::<Blake3Hasher>(&case.final_state())
+    );
+    assert_eq!(verified_root(prev_root, &witness), new_root);
+
+    drop(t);
+    std::fs::remove_dir_all(test_path(&name)).unwrap();
+    new_root
+}
+
+fn value(i: u8) -> Vec<u8> {
+    vec![i; 4]
+}
+
+#[test]
+fn sibling_deletion_at_every_depth() {
+    for depth in [0, 1, 7, 8, 63, 64, 127, 128, 254, 255] {
+        let left = key_diverging_at(depth, false);
+        let right = key_diverging_at(depth, true);
+        // A leaf on the other side of the root keeps it an internal node.
+        let other = [0xFF; 32];
+
+        for deleted in [left, right] {
+            let case = TrieUpdateCase::new(
+                [(left, value(1)), (right, value(2)), (other, value(3))],
+                [(deleted, None)],
+            );
+            check("verify_update_sibling", &case);
+        }
+    }
+}
+
+#[test]
+fn emptied_subtree_compacts_to_terminator() {
+    let prefix = [true, false, true, true, false];
+    let subtree = (0..4u8)
+        .map(|i| {
+            let mut key = key_with_prefix(prefix);
+            key[31] = i;
+            key
+        })
+        .collect::<Vec<KeyPath>>();
+    let outside = key_with_prefix([false]);
+    let mut prev_state = vec![(outside, value(0))];
+    prev_state.extend(subtree.iter().map(|key| (*key, value(1))));
+
+    // Everything below the prefix is deleted, leaving the leaf outside of it at the root.
+    let case = TrieUpdateCase::new(prev_state.clone(), subtree.iter().map(|key| (*key, None)));
+    let root = check("verify_update_emptied", &case);
+    assert_eq!(root, reference_root::<Blake3Hasher>(&[(outside, value(0))]));
+
+    // Deleting all leaves leaves the empty trie.
+    let case = TrieUpdateCase::new(
+        prev_state.clone(),
+        prev_state.iter().map(|(key, _)| (*key, None)),
+    );
+    assert_eq!(check("verify_update_emptied", &case), TERMINATOR);
+
+    // All but one of the subtree is deleted, which moves up next to the leaf outside.
+    let case = TrieUpdateCase::new(prev_state, subtree[1..].iter().map(|key| (*key, None)));
+    check("verify_update_emptied", &case);
+}
+
+#[test]
+fn leaf_pushed_down_by_diverging_key() {
+    for depth in [0, 8, 100, 255] {
+        let existing = key_diverging_at(depth, false);
+        let new = key_diverging_at(depth, true);
+
+        let case = TrieUpdateCase::new([(existing, value(1))], [(new, Some(value(2)))]);
+        check("verify_update_push_down", &case);
+
+        // The leaf is replaced by a neighbor in the same batch.
+        let case = TrieUpdateCase::new(
+            [(existing, value(1))],
+            [(existing, None), (new, Some(value(2)))],
+        );
+        check("verify_update_push_down", &case);
+    }
+}
+
+#[test]
+fn property_verify_update_matches_commit() {
+    fn property(case: TrieUpdateCase) -> bool {
+        check("verify_update_prop", &case);
+        true
+    }
+
+    QuickCheck::new()
+        .tests(64)
+        .quickcheck(property as fn(TrieUpdateCase) -> bool);
+}
@@ -73,3 +73,55 @@ fn wal_recovery_test_pre_meta_swap() {
         assert_eq!(common::read_balance(&mut t, i), None);
     }
 }
+
+#[test]
+fn wal_recovery_test_compressed() {
+    use nomt::{hasher::Blake3Hasher, KeyReadWrite, Nomt, Options, SessionParams};
+
+    fn open(panic_on_sync: Option<PanicOnSyncMode>, clean: bool) -> Nomt<Blake3Hasher> {
+        let configure = |o: &mut Options| {
+            o.bitbox_seed([0; 16]);
+            o.hashtable_buckets(1000000);
+            o.io_workers(1);
+            // Only the first instance writes a compressed WAL; recovery must handle it regardless.
+            o.wal_compression(clean);
+            if let Some(mode) = panic_on_sync {
+                o.panic_on_sync(mode);
+            }
+        };
+        if clean {
+            common::setup_nomt_with("wal_compressed", configure)
+        } else {
+            common::open_nomt("wal_compressed", configure)
+        }
+    }
+
+    let nomt = open(Some(PanicOnSyncMode::PostMeta), true);
+    let session = nomt.begin_session(SessionParams::default());
+    let actuals = (0..1000)
+        .map(|id| {
+            (
+                common::account_path(id),
+                KeyReadWrite::Write(Some(vec![1; 8])),
+            )
+        })
+        .collect::<std::collections::BTreeMap<_, _>>()
+        .into_iter()
+        .collect();
+    let finished = session.finish(actuals).unwrap();
+    let root = finished.root();
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        finished.commit(&nomt).unwrap();
+    }));
+    assert!(r.is_err());
+    drop(nomt);
+
+    let nomt = open(None, false);
+    assert_eq!(nomt.root(), root);
+    for id in 0..1000 {
+        assert_eq!(
+            nomt.read(common::account_path(id)).unwrap(),
+            Some(vec![1; 8])
+        );
+    }
+}
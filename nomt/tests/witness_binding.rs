@@ -0,0 +1,97 @@
+mod common;
+
+use common::setup_nomt;
+use nomt::{
+    hasher::Blake3Hasher, trie::KeyPath, KeyReadWrite, Nomt, SessionParams, Witness, WitnessMode,
+    WitnessVerificationError,
+};
+
+fn key(i: u8) -> KeyPath {
+    [i; 32]
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, round: u8, mode: WitnessMode) -> Witness {
+    let actuals = (0..10)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(vec![i, round]))))
+        .collect();
+    let session = nomt.begin_session(SessionParams::default().witness_mode(mode));
+    let mut finished = session.finish(actuals).unwrap();
+    let witness = finished.take_witness().unwrap();
+    finished.commit(nomt).unwrap();
+    witness
+}
+
+#[test]
+fn bound_witness_verifies_in_its_context() {
+    let nomt = setup_nomt("witness_binding_context");
+    let prev_root = nomt.root().into_inner();
+    let witness = commit(
+        &nomt,
+        0,
+        WitnessMode::read_write().bind_to_context(b"chain-1/block-7".to_vec()),
+    );
+    let new_root = nomt.root().into_inner();
+
+    let binding = witness.binding.as_ref().unwrap();
+    assert_eq!(binding.prev_root, prev_root);
+    assert_eq!(binding.new_root, new_root);
+
+    let statements = witness
+        .verify_in_context::<Blake3Hasher>(prev_root, b"chain-1/block-7")
+        .unwrap();
+    assert_eq!(statements.new_root, new_root);
+    assert!(witness.verify::<Blake3Hasher>(prev_root).is_ok());
+
+    assert!(matches!(
+        witness
+            .verify_in_context::<Blake3Hasher>(prev_root, b"chain-1/block-8")
+            .unwrap_err(),
+        WitnessVerificationError::BoundToOtherContext,
+    ));
+}
+
+#[test]
+fn bound_witness_rejects_other_roots() {
+    let nomt = setup_nomt("witness_binding_roots");
+    let context = b"chain-1".to_vec();
+    commit(&nomt, 0, WitnessMode::read_write());
+    let prev_root = nomt.root().into_inner();
+    let witness = commit(
+        &nomt,
+        1,
+        WitnessMode::read_write().bind_to_context(context.clone()),
+    );
+
+    // Replaying the witness after the commit it was produced for.
+    let replayed = nomt.root().into_inner();
+    assert!(matches!(
+        witness.verify::<Blake3Hasher>(replayed).unwrap_err(),
+        WitnessVerificationError::BoundToOtherRoots,
+    ));
+
+    // A binding altered to claim another new root.
+    let mut altered = witness.clone();
+    altered.binding.as_mut().unwrap().new_root = prev_root;
+    assert!(matches!(
+        altered
+            .verify_in_context::<Blake3Hasher>(prev_root, &context)
+            .unwrap_err(),
+        WitnessVerificationError::BoundToOtherRoots,
+    ));
+}
+
+#[test]
+fn unbound_witness_has_no_context() {
+    let nomt = setup_nomt("witness_binding_unbound");
+    let prev_root = nomt.root().into_inner();
+    let witness = commit(&nomt, 0, WitnessMode::read_write());
+
+    assert!(witness.binding.is_none());
+    assert!(witness.verify::<Blake3Hasher>(prev_root).is_ok());
+    assert!(matches!(
+        witness
+            .verify_in_context::<Blake3Hasher>(prev_root, b"chain-1")
+            .unwrap_err(),
+        WitnessVerificationError::Unbound,
+    ));
+}
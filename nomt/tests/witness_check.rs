@@ -1,19 +1,9 @@
 mod common;
 
 use common::{apply_accesses, fresh_test_name, SessionAccessCase, Test};
-use nomt::{hasher::Blake3Hasher, proof, trie::LeafData};
+use nomt::{hasher::Blake3Hasher, proof, trie::LeafData, Witness, WitnessEquivalenceError};
 use quickcheck::QuickCheck;
 
-fn sort_updates_by_path(updates: &mut [proof::PathUpdate]) {
-    // Witness path proofs may be emitted in worker order rather than trie path order.
-    updates.sort_by(|a, b| {
-        a.inner
-            .path()
-            .partial_cmp(b.inner.path())
-            .expect("verified witness paths should be comparable")
-    });
-}
-
 #[test]
 fn produced_witness_validity() {
     let mut accounts = 0;
@@ -53,6 +43,7 @@ fn produced_witness_validity() {
 
     assert_eq!(witness.operations.reads.len(), 15); // 10 existing + 5 nonexisting
     assert_eq!(witness.operations.writes.len(), 10); // 5 deletes + 5 inserts
+    assert!(witness.is_canonical());
 
     let mut updates = Vec::new();
     for (i, witnessed_path) in witness.path_proofs.iter().enumerate() {
@@ -60,13 +51,7 @@ fn produced_witness_validity() {
             .inner
             .verify::<Blake3Hasher>(&witnessed_path.path.path(), prev_root.into_inner())
             .unwrap();
-        for read in witness
-            .operations
-            .reads
-            .iter()
-            .skip_while(|r| r.path_index != i)
-            .take_while(|r| r.path_index == i)
-        {
+        for read in witness.reads_for_path(i) {
             match read.value {
                 None => assert!(verified.confirm_nonexistence(&read.key).unwrap()),
                 Some(ref v) => {
@@ -80,13 +65,7 @@ fn produced_witness_validity() {
         }
 
         let mut write_ops = Vec::new();
-        for write in witness
-            .operations
-            .writes
-            .iter()
-            .skip_while(|r| r.path_index != i)
-            .take_while(|r| r.path_index == i)
-        {
+        for write in witness.writes_for_path(i) {
             write_ops.push((write.key, write.value.clone()));
         }
 
@@ -97,7 +76,6 @@ fn produced_witness_validity() {
             });
         }
     }
-    sort_updates_by_path(&mut updates);
 
     assert_eq!(
         proof::verify_update::<Blake3Hasher>(prev_root.into_inner(), &updates).unwrap(),
@@ -105,6 +83,82 @@ fn produced_witness_validity() {
     );
 }
 
+// The same statements as `witness`, encoded with paths and operations in reverse order and a
+// duplicated read.
+fn reencode(witness: &Witness) -> Witness {
+    let mut reencoded = witness.clone();
+    let last = reencoded.path_proofs.len() - 1;
+    reencoded.path_proofs.reverse();
+    for read in &mut reencoded.operations.reads {
+        read.path_index = last - read.path_index;
+    }
+    for write in &mut reencoded.operations.writes {
+        write.path_index = last - write.path_index;
+    }
+    reencoded.operations.reads.reverse();
+    reencoded.operations.writes.reverse();
+    let duplicate = reencoded.operations.reads[0].clone();
+    reencoded.operations.reads.push(duplicate);
+    reencoded
+}
+
+#[test]
+fn witness_equivalence() {
+    let mut t = Test::new("witness_equivalence");
+    for id in 0..10 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    let (prev_root, _) = t.commit();
+
+    for id in 0..10 {
+        t.read_id(id);
+    }
+    t.read_id(100);
+    common::kill(&mut t, 0);
+    common::set_balance(&mut t, 1, 500);
+    common::set_balance(&mut t, 10, 500);
+    let (new_root, witness) = t.commit();
+
+    let reencoded = reencode(&witness);
+    assert!(!reencoded.is_canonical());
+    let statements = witness
+        .check_equivalence::<Blake3Hasher>(&reencoded, prev_root.into_inner())
+        .unwrap();
+    assert_eq!(statements.new_root, new_root.into_inner());
+    assert_eq!(statements.reads.len(), 11);
+    assert_eq!(statements.writes.len(), 3);
+
+    // A missing write.
+    let mut missing_write = reencoded.clone();
+    let dropped = missing_write.operations.writes.pop().unwrap();
+    assert!(matches!(
+        witness.check_equivalence::<Blake3Hasher>(&missing_write, prev_root.into_inner()),
+        Err(WitnessEquivalenceError::WritesDiffer(key)) if key == dropped.key
+    ));
+
+    // A missing read.
+    let mut missing_read = witness.clone();
+    let dropped = missing_read.operations.reads.remove(3);
+    assert!(matches!(
+        witness.check_equivalence::<Blake3Hasher>(&missing_read, prev_root.into_inner()),
+        Err(WitnessEquivalenceError::ReadsDiffer(key)) if key == dropped.key
+    ));
+
+    // A read of a value which is not the one proven.
+    let mut wrong_read = witness.clone();
+    wrong_read.operations.reads[0].value = Some([0xFF; 32]);
+    assert!(matches!(
+        witness.check_equivalence::<Blake3Hasher>(&wrong_read, prev_root.into_inner()),
+        Err(WitnessEquivalenceError::InvalidSecond(_))
+    ));
+
+    // A witness for another root.
+    assert!(matches!(
+        witness.check_equivalence::<Blake3Hasher>(&reencoded, new_root.into_inner()),
+        Err(WitnessEquivalenceError::InvalidFirst(_))
+    ));
+}
+
 #[test]
 fn empty_witness() {
     let mut accounts = 0;
@@ -198,6 +252,7 @@ fn property_generated_witnesses_verify() {
         apply_accesses(&mut t, &case.accesses);
 
         let (new_root, witness) = t.commit();
+        assert!(witness.is_canonical());
 
         let mut updates = Vec::new();
         for (i, witnessed_path) in witness.path_proofs.iter().enumerate() {
@@ -206,13 +261,7 @@ fn property_generated_witnesses_verify() {
                 .verify::<Blake3Hasher>(&witnessed_path.path.path(), prev_root.into_inner())
                 .unwrap();
 
-            for read in witness
-                .operations
-                .reads
-                .iter()
-                .skip_while(|r| r.path_index != i)
-                .take_while(|r| r.path_index == i)
-            {
+            for read in witness.reads_for_path(i) {
                 match read.value {
                     None => assert!(verified.confirm_nonexistence(&read.key).unwrap()),
                     Some(ref value_hash) => {
@@ -226,13 +275,7 @@ fn property_generated_witnesses_verify() {
             }
 
             let mut write_ops = Vec::new();
-            for write in witness
-                .operations
-                .writes
-                .iter()
-                .skip_while(|w| w.path_index != i)
-                .take_while(|w| w.path_index == i)
-            {
+            for write in witness.writes_for_path(i) {
                 write_ops.push((write.key, write.value.clone()));
             }
 
@@ -243,7 +286,6 @@ fn property_generated_witnesses_verify() {
                 });
             }
         }
-        sort_updates_by_path(&mut updates);
 
         assert_eq!(
             proof::verify_update::<Blake3Hasher>(prev_root.into_inner(), &updates).unwrap(),
@@ -0,0 +1,192 @@
+mod common;
+
+use bitvec::prelude::*;
+use common::setup_nomt;
+use nomt::{
+    hasher::Blake3Hasher,
+    proof::PrefixNode,
+    trie::{self, KeyPath, Node},
+    KeyReadWrite, Nomt, SessionParams, Witness, WitnessMode, WitnessVerificationError,
+};
+
+// Keys beginning with these bits are private.
+const PRIVATE_BYTE: [u8; 1] = [0xf0];
+
+fn private() -> &'static BitSlice<u8, Msb0> {
+    &PRIVATE_BYTE.view_bits::<Msb0>()[..4]
+}
+
+fn public_key(i: u8) -> KeyPath {
+    let mut key = [i; 32];
+    key[0] = i & 0x7f;
+    key
+}
+
+fn private_key(i: u8) -> KeyPath {
+    let mut key = [i; 32];
+    key[0] = 0xf0 | (i & 0x0f);
+    key
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, mut actuals: Vec<(KeyPath, KeyReadWrite)>) -> Witness {
+    actuals.sort_by_key(|(key, _)| *key);
+    let session = nomt.begin_session(
+        SessionParams::default().witness_mode(WitnessMode::read_write().exclude_prefix(private())),
+    );
+    let mut finished = session.finish(actuals).unwrap();
+    let witness = finished.take_witness().unwrap();
+    finished.commit(nomt).unwrap();
+    witness
+}
+
+// The root of the subtree at the prefix, as proven by the database itself.
+fn subtree_root(nomt: &Nomt<Blake3Hasher>, prefix: &BitSlice<u8, Msb0>) -> Node {
+    match nomt.prove_prefix(prefix, 0).unwrap().proof.node {
+        PrefixNode::Internal(node) => node,
+        PrefixNode::Terminal(_) => panic!("no subtree root"),
+    }
+}
+
+fn populate(nomt: &Nomt<Blake3Hasher>) {
+    let mut writes = (0..20)
+        .map(|i| (public_key(i), KeyReadWrite::Write(Some(vec![i]))))
+        .chain((0..8).map(|i| (private_key(i), KeyReadWrite::Write(Some(vec![i])))))
+        .collect::<Vec<_>>();
+    // The private keys are seeded without exclusion, as there is no subtree root before them.
+    writes.sort_by_key(|(key, _)| *key);
+    let session = nomt.begin_session(SessionParams::default());
+    session.finish(writes).unwrap().commit(nomt).unwrap();
+}
+
+#[test]
+fn private_operations_are_committed_by_subtree_roots() {
+    let nomt = setup_nomt("witness_exclusion_roots");
+    populate(&nomt);
+
+    let prev_root = nomt.root();
+    let prev_subtree_root = subtree_root(&nomt, private());
+    let witness = commit(
+        &nomt,
+        vec![
+            (public_key(1), KeyReadWrite::Read(Some(vec![1]))),
+            (public_key(2), KeyReadWrite::Write(Some(vec![0xff]))),
+            (public_key(50), KeyReadWrite::Write(Some(vec![50]))),
+            (private_key(3), KeyReadWrite::Read(Some(vec![3]))),
+            (private_key(4), KeyReadWrite::Write(None)),
+            (private_key(9), KeyReadWrite::Write(Some(vec![9]))),
+        ],
+    );
+
+    assert!(witness
+        .path_proofs
+        .iter()
+        .all(|path| !path.path.path().starts_with(private())));
+    assert_eq!(witness.excluded.len(), 1);
+
+    let statements = witness
+        .verify::<Blake3Hasher>(prev_root.into_inner())
+        .unwrap();
+    assert_eq!(statements.new_root, nomt.root().into_inner());
+    assert_eq!(statements.reads.len(), 1);
+    assert_eq!(statements.writes.len(), 2);
+    assert_eq!(statements.excluded.len(), 1);
+    let excluded = &statements.excluded[0];
+    assert_eq!(excluded.position.path(), private());
+    assert_eq!(excluded.prev_root, prev_subtree_root);
+    assert_eq!(excluded.new_root, subtree_root(&nomt, private()));
+
+    // Without knowledge of the private state, the new subtree root can't be forged.
+    let mut forged = witness.clone();
+    forged.excluded[0].new_root = prev_subtree_root;
+    let statements = forged
+        .verify::<Blake3Hasher>(prev_root.into_inner())
+        .unwrap();
+    assert_ne!(statements.new_root, nomt.root().into_inner());
+
+    let mut forged = witness.clone();
+    forged.excluded[0].prev_root = [0xaa; 32];
+    assert!(matches!(
+        forged.verify::<Blake3Hasher>(prev_root.into_inner()),
+        Err(WitnessVerificationError::InvalidSubtree(0))
+    ));
+
+    let mut forged = witness.clone();
+    let mut write = forged.operations.writes[0].clone();
+    write.key = private_key(5);
+    forged.operations.writes.push(write);
+    assert!(matches!(
+        forged.verify::<Blake3Hasher>(prev_root.into_inner()),
+        Err(WitnessVerificationError::OpInExcludedSubtree(_))
+    ));
+}
+
+#[test]
+fn private_subtree_collapses() {
+    let nomt = setup_nomt("witness_exclusion_collapse");
+    populate(&nomt);
+
+    // Delete all but one private key, which moves up the trie.
+    let prev_root = nomt.root();
+    let witness = commit(
+        &nomt,
+        (1..8)
+            .map(|i| (private_key(i), KeyReadWrite::Write(None)))
+            .chain([(public_key(3), KeyReadWrite::Write(None))])
+            .collect(),
+    );
+    let statements = witness
+        .verify::<Blake3Hasher>(prev_root.into_inner())
+        .unwrap();
+    assert_eq!(statements.new_root, nomt.root().into_inner());
+    assert!(trie::is_leaf::<Blake3Hasher>(
+        &statements.excluded[0].new_root
+    ));
+
+    // The single remaining private key has no subtree root to commit to any more.
+    let session = nomt.begin_session(
+        SessionParams::default().witness_mode(WitnessMode::read_write().exclude_prefix(private())),
+    );
+    let actuals = vec![(private_key(0), KeyReadWrite::Write(None))];
+    assert!(session.finish(actuals).is_err());
+
+    // Without exclusion, the same session succeeds.
+    let session =
+        nomt.begin_session(SessionParams::default().witness_mode(WitnessMode::read_write()));
+    let actuals = vec![(private_key(0), KeyReadWrite::Write(None))];
+    session.finish(actuals).unwrap().commit(&nomt).unwrap();
+}
+
+#[test]
+fn untouched_prefix_is_not_committed() {
+    let nomt = setup_nomt("witness_exclusion_untouched");
+    populate(&nomt);
+
+    let prev_root = nomt.root();
+    let witness = commit(
+        &nomt,
+        vec![(public_key(7), KeyReadWrite::Write(Some(vec![0])))],
+    );
+    assert!(witness.excluded.is_empty());
+    let statements = witness
+        .verify::<Blake3Hasher>(prev_root.into_inner())
+        .unwrap();
+    assert_eq!(statements.new_root, nomt.root().into_inner());
+
+    // Excluding everything leaves only the roots.
+    let prev_root = nomt.root();
+    let session = nomt.begin_session(
+        SessionParams::default()
+            .witness_mode(WitnessMode::read_write().exclude_prefix(BitSlice::empty())),
+    );
+    let mut finished = session
+        .finish(vec![(public_key(8), KeyReadWrite::Write(Some(vec![1])))])
+        .unwrap();
+    let witness = finished.take_witness().unwrap();
+    finished.commit(&nomt).unwrap();
+    assert!(witness.path_proofs.is_empty());
+    let statements = witness
+        .verify::<Blake3Hasher>(prev_root.into_inner())
+        .unwrap();
+    assert_eq!(statements.excluded[0].prev_root, prev_root.into_inner());
+    assert_eq!(statements.new_root, nomt.root().into_inner());
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use common::setup_nomt_with;
+use nomt::{
+    hasher::{Blake3Hasher, ValueHasher},
+    trie::{KeyPath, Node},
+    KeyReadWrite, Nomt, SessionParams, Witness, WitnessMode,
+};
+
+fn key(i: u32) -> KeyPath {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+fn populate(nomt: &Nomt<Blake3Hasher>) {
+    let mut actuals = (0..1000)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(vec![1; 16]))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+}
+
+// Reads and writes a batch of 500 keys, a fifth of which are new, and returns the witness along
+// with the roots before and after.
+fn commit_batch(nomt: &Nomt<Blake3Hasher>, mode: WitnessMode) -> (Witness, Node, Node) {
+    let prev_root = nomt.root().into_inner();
+    let mut actuals = (0..500)
+        .map(|i| {
+            let op = match i % 3 {
+                0 => KeyReadWrite::Read(Some(vec![1; 16])),
+                1 => KeyReadWrite::ReadThenWrite(Some(vec![1; 16]), Some(vec![2; 16])),
+                _ => KeyReadWrite::Write(None),
+            };
+            (key(i * 2), op)
+        })
+        .chain((0..100).map(|i| (key(5000 + i), KeyReadWrite::Write(Some(vec![3; 16])))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+
+    let session = nomt.begin_session(SessionParams::default().witness_mode(mode));
+    let mut finished = session.finish(actuals).unwrap();
+    let witness = finished.take_witness().unwrap();
+    finished.commit(nomt).unwrap();
+    (witness, prev_root, nomt.root().into_inner())
+}
+
+#[test]
+fn witness_of_few_keys_is_small() {
+    let nomt = setup_nomt_with("witness_filter_keys", |o| o.commit_concurrency(2));
+    populate(&nomt);
+    // A read, a write, a deletion and an insertion.
+    let proven = [key(0), key(2), key(4), key(5000)];
+    let (witness, prev_root, new_root) =
+        commit_batch(&nomt, WitnessMode::read_write().only_keys(proven));
+
+    assert!(witness.path_proofs.len() <= proven.len());
+    let statements = witness.verify::<Blake3Hasher>(prev_root).unwrap();
+    assert_eq!(statements.new_root, new_root);
+
+    // Keys sharing a path with a proven key are witnessed along with it.
+    let proven_only = |ops: Vec<(KeyPath, _)>| {
+        ops.into_iter()
+            .filter(|(k, _)| proven.contains(k))
+            .collect::<Vec<_>>()
+    };
+    let mut reads = proven[..2]
+        .iter()
+        .map(|k| (*k, Some(Blake3Hasher::hash_value(&[1; 16]))))
+        .collect::<Vec<_>>();
+    reads.sort();
+    assert_eq!(proven_only(statements.reads), reads);
+    let mut writes = vec![
+        (key(2), Some(Blake3Hasher::hash_value(&[2; 16]))),
+        (key(4), None),
+        (key(5000), Some(Blake3Hasher::hash_value(&[3; 16]))),
+    ];
+    writes.sort();
+    assert_eq!(proven_only(statements.writes), writes);
+}
+
+#[test]
+fn filtered_witness_matches_full_witness_root() {
+    let full_nomt = setup_nomt_with("witness_filter_full", |o| o.commit_concurrency(2));
+    populate(&full_nomt);
+    let (full, prev_root, new_root) = commit_batch(&full_nomt, WitnessMode::read_write());
+
+    let nomt = setup_nomt_with("witness_filter_predicate", |o| o.commit_concurrency(2));
+    populate(&nomt);
+    let (filtered, filtered_prev_root, filtered_new_root) = commit_batch(
+        &nomt,
+        WitnessMode::read_write().only_keys_matching(|key| key[0] < 0x10),
+    );
+    assert_eq!(
+        (prev_root, new_root),
+        (filtered_prev_root, filtered_new_root)
+    );
+    assert!(filtered.path_proofs.len() * 4 < full.path_proofs.len());
+
+    let statements = filtered.verify::<Blake3Hasher>(prev_root).unwrap();
+    assert_eq!(statements.new_root, new_root);
+    assert!(statements.reads.iter().all(|(key, _)| key[0] < 0x10));
+    assert!(statements.writes.iter().all(|(key, _)| key[0] < 0x10));
+    assert_eq!(
+        statements.writes.len(),
+        full.operations
+            .writes
+            .iter()
+            .filter(|w| w.key[0] < 0x10)
+            .count()
+    );
+}
+
+#[test]
+fn no_matching_keys_excludes_the_whole_trie() {
+    let nomt = setup_nomt_with("witness_filter_none", |o| o.commit_concurrency(2));
+    populate(&nomt);
+    let (witness, prev_root, new_root) =
+        commit_batch(&nomt, WitnessMode::read_write().only_keys([]));
+
+    assert!(witness.path_proofs.is_empty());
+    let statements = witness.verify::<Blake3Hasher>(prev_root).unwrap();
+    assert_eq!(statements.new_root, new_root);
+    assert_eq!(statements.excluded.len(), 1);
+}
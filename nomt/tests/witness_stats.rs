@@ -0,0 +1,95 @@
+mod common;
+
+use common::{open_nomt, setup_nomt};
+use nomt::{hasher::Blake3Hasher, AccessTraceMode, KeyReadWrite, Nomt, SessionParams, WitnessMode};
+
+fn key(i: u32) -> [u8; 32] {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+fn populate(nomt: &Nomt<Blake3Hasher>, n: u32) {
+    let mut actuals = (0..n)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(vec![1; 32]))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(key, _)| *key);
+    let session = nomt.begin_session(SessionParams::default());
+    session.finish(actuals).unwrap().commit(nomt).unwrap();
+}
+
+fn actuals() -> Vec<([u8; 32], KeyReadWrite)> {
+    let mut actuals = (0..50)
+        .map(|i| (key(i * 100), KeyReadWrite::Read(Some(vec![1; 32]))))
+        .chain((0..50).map(|i| (key(i * 100 + 1), KeyReadWrite::Write(Some(vec![2; 32])))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(key, _)| *key);
+    actuals
+}
+
+#[test]
+fn no_stats_without_witness() {
+    let nomt = setup_nomt("witness_stats_none");
+    populate(&nomt, 100);
+    let session = nomt.begin_session(SessionParams::default());
+    let finished = session.finish(actuals()).unwrap();
+    assert_eq!(finished.witness_stats(), None);
+}
+
+#[test]
+fn stats_match_witness() {
+    let nomt = setup_nomt("witness_stats_match");
+    populate(&nomt, 10_000);
+    // start from an empty page cache.
+    drop(nomt);
+    let nomt = open_nomt("witness_stats_match", |_| {});
+
+    let session = nomt.begin_session(
+        SessionParams::default()
+            .witness_mode(WitnessMode::read_write())
+            .access_trace(AccessTraceMode::keys_and_pages()),
+    );
+    let mut finished = session.finish(actuals()).unwrap();
+    let stats = finished.witness_stats().unwrap();
+    let witness = finished.take_witness().unwrap();
+    let access_list = finished.take_access_list().unwrap();
+    assert_eq!(finished.witness_stats(), Some(stats));
+
+    let mut encoded = Vec::new();
+    witness.encode_to(&mut encoded).unwrap();
+    assert_eq!(stats.bytes, encoded.len());
+    let siblings = witness
+        .path_proofs
+        .iter()
+        .map(|path| path.inner.siblings.len())
+        .sum::<usize>();
+    assert_eq!(stats.siblings, siblings);
+    assert!(siblings > 100 * 10);
+    assert_eq!(stats.pages_touched, access_list.pages.unwrap().len());
+    assert!(stats.pages_fetched > 0);
+    assert!(stats.pages_fetched <= stats.pages_touched);
+    finished.commit(&nomt).unwrap();
+
+    // the pages are in memory now.
+    let session =
+        nomt.begin_session(SessionParams::default().witness_mode(WitnessMode::read_write()));
+    let finished = session.finish(actuals()).unwrap();
+    let cached = finished.witness_stats().unwrap();
+    assert_eq!(cached.pages_fetched, 0);
+    assert_eq!(cached.pages_touched, stats.pages_touched);
+}
+
+#[test]
+fn filtered_witness_is_smaller() {
+    let nomt = setup_nomt("witness_stats_filtered");
+    populate(&nomt, 1000);
+
+    let stats = |mode: WitnessMode| {
+        let session = nomt.begin_session(SessionParams::default().witness_mode(mode));
+        session.finish(actuals()).unwrap().witness_stats().unwrap()
+    };
+    let full = stats(WitnessMode::read_write());
+    let filtered = stats(WitnessMode::read_write().only_keys([key(0)]));
+    assert!(filtered.bytes < full.bytes);
+    assert!(filtered.siblings < full.siblings);
+    // the pages are those of the update, whatever the witness retains.
+    assert_eq!(filtered.pages_touched, full.pages_touched);
+}
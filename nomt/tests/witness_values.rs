@@ -0,0 +1,115 @@
+mod common;
+
+use common::setup_nomt;
+use nomt::{
+    hasher::Blake3Hasher, trie::KeyPath, KeyReadWrite, Nomt, SessionParams, Witness, WitnessMode,
+};
+
+fn key(i: u8) -> KeyPath {
+    [i; 32]
+}
+
+fn commit(
+    nomt: &Nomt<Blake3Hasher>,
+    actuals: Vec<(KeyPath, KeyReadWrite)>,
+    mode: WitnessMode,
+) -> Witness {
+    let session = nomt.begin_session(SessionParams::default().witness_mode(mode));
+    let mut finished = session.finish(actuals).unwrap();
+    let witness = finished.take_witness().unwrap();
+    finished.commit(nomt).unwrap();
+    witness
+}
+
+fn populate(nomt: &Nomt<Blake3Hasher>) {
+    let actuals = (0..10)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(vec![i; 100]))))
+        .collect();
+    commit(nomt, actuals, WitnessMode::read_write());
+}
+
+#[test]
+fn witness_embeds_prior_values() {
+    let nomt = setup_nomt("witness_values_prior");
+    populate(&nomt);
+    let prev_root = nomt.root().into_inner();
+
+    let witness = commit(
+        &nomt,
+        vec![
+            (key(1), KeyReadWrite::Read(Some(vec![1; 100]))),
+            (
+                key(2),
+                KeyReadWrite::ReadThenWrite(Some(vec![2; 100]), None),
+            ),
+            (key(3), KeyReadWrite::Write(Some(vec![33; 5]))),
+            (key(20), KeyReadWrite::Read(None)),
+            (key(21), KeyReadWrite::Write(Some(vec![21]))),
+        ],
+        WitnessMode::read_write().include_values(),
+    );
+
+    assert!(witness.verify::<Blake3Hasher>(prev_root).is_ok());
+    assert_eq!(witness.verify_values::<Blake3Hasher>(), Ok(()));
+    assert_eq!(witness.values.len(), 5);
+    assert_eq!(witness.prior_value(&key(2)), Some(Some(&[2; 100][..])));
+    assert_eq!(witness.prior_value(&key(3)), Some(Some(&[3; 100][..])));
+    assert_eq!(witness.prior_value(&key(20)), Some(None));
+    assert_eq!(witness.prior_value(&key(21)), Some(None));
+    assert_eq!(witness.prior_value(&key(4)), None);
+    for read in &witness.operations.reads {
+        let value = witness.read_value(read).unwrap();
+        assert!(read.key == key(20) || value == Some(&[read.key[0]; 100][..]));
+    }
+
+    // A value altered after the fact no longer matches the witnessed hash.
+    let mut altered = witness.clone();
+    let i = altered.values.iter().position(|v| v.key == key(3)).unwrap();
+    altered.values[i].value = Some(vec![4; 100]);
+    assert_eq!(altered.verify_values::<Blake3Hasher>(), Err(key(3)));
+
+    // As does a value for a key which is not witnessed.
+    let mut altered = witness.clone();
+    altered.values.push(nomt::WitnessedValue {
+        key: key(30),
+        value: None,
+    });
+    assert_eq!(altered.verify_values::<Blake3Hasher>(), Err(key(30)));
+
+    let mut bytes = Vec::new();
+    witness.encode_to(&mut bytes).unwrap();
+    let decoded = Witness::decode_from(&mut &bytes[..]).unwrap();
+    assert_eq!(decoded.values, witness.values);
+}
+
+#[test]
+fn values_not_embedded_by_default() {
+    let nomt = setup_nomt("witness_values_default");
+    populate(&nomt);
+    let witness = commit(
+        &nomt,
+        vec![(key(1), KeyReadWrite::Read(Some(vec![1; 100])))],
+        WitnessMode::read_write(),
+    );
+    assert!(witness.values.is_empty());
+    assert_eq!(witness.prior_value(&key(1)), None);
+}
+
+#[test]
+fn excluded_keys_have_no_values() {
+    let nomt = setup_nomt("witness_values_excluded");
+    populate(&nomt);
+    let witness = commit(
+        &nomt,
+        vec![
+            (key(1), KeyReadWrite::Read(Some(vec![1; 100]))),
+            (key(9), KeyReadWrite::Write(None)),
+        ],
+        WitnessMode::read_write()
+            .only_keys([key(9)])
+            .include_values(),
+    );
+    assert_eq!(witness.prior_value(&key(1)), None);
+    assert_eq!(witness.prior_value(&key(9)), Some(Some(&[9; 100][..])));
+    assert_eq!(witness.verify_values::<Blake3Hasher>(), Ok(()));
+}
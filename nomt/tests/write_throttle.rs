@@ -0,0 +1,64 @@
+mod common;
+
+use nomt::{hasher::Blake3Hasher, KeyReadWrite, Nomt, Options, SessionParams};
+use std::time::Duration;
+
+fn open_nomt(name: &str, throttle_target: Option<Duration>, reset: bool) -> Nomt<Blake3Hasher> {
+    let configure = |o: &mut Options| {
+        if let Some(target) = throttle_target {
+            o.write_throttle_target(target);
+        }
+    };
+    if reset {
+        common::setup_nomt_with(name, configure)
+    } else {
+        common::open_nomt(name, configure)
+    }
+}
+
+fn key(i: u32) -> [u8; 32] {
+    let mut key = [0; 32];
+    key[..4].copy_from_slice(&i.wrapping_mul(0x9E3779B9).to_be_bytes());
+    key
+}
+
+fn commit_batch(nomt: &Nomt<Blake3Hasher>, range: std::ops::Range<u32>) {
+    let mut actuals = range
+        .map(|i| (key(i), KeyReadWrite::Write(Some(i.to_le_bytes().to_vec()))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals)
+        .unwrap()
+        .commit(nomt)
+        .unwrap();
+}
+
+#[test]
+fn unthrottled_by_default() {
+    let nomt = open_nomt("write_throttle_default", None, true);
+    commit_batch(&nomt, 0..2000);
+    assert_eq!(nomt.background_write_delay(), Duration::ZERO);
+}
+
+#[test]
+fn saturated_device_delays_background_writes() {
+    // No device completes I/O within a nanosecond, so the throttle is always engaged.
+    let nomt = open_nomt(
+        "write_throttle_saturated",
+        Some(Duration::from_nanos(1)),
+        true,
+    );
+    commit_batch(&nomt, 0..2000);
+    commit_batch(&nomt, 2000..4000);
+    assert!(nomt.background_write_delay() > Duration::ZERO);
+    let root = nomt.root();
+    drop(nomt);
+
+    // Throttled writes are written all the same.
+    let nomt = open_nomt("write_throttle_saturated", None, false);
+    assert_eq!(nomt.root(), root);
+    for i in [0, 1999, 2000, 3999] {
+        assert_eq!(nomt.read(key(i)).unwrap(), Some(i.to_le_bytes().to_vec()));
+    }
+}
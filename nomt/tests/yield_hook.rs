@@ -0,0 +1,85 @@
+mod common;
+
+use common::setup_nomt_with;
+use nomt::{Cancelled, KeyReadWrite, SessionParams, WitnessMode, YieldPoint};
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+fn actuals(range: std::ops::Range<u32>) -> Vec<([u8; 32], KeyReadWrite)> {
+    let mut actuals = range
+        .map(|i| {
+            let key = *blake3::hash(&i.to_le_bytes()).as_bytes();
+            (key, KeyReadWrite::Write(Some(i.to_le_bytes().to_vec())))
+        })
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    actuals
+}
+
+#[test]
+fn hook_is_called_and_can_cancel() {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let cancel = Arc::new(AtomicBool::new(false));
+    let cancelled_calls = Arc::new(AtomicUsize::new(0));
+    let nomt = setup_nomt_with("yield_hook", |o| {
+        o.commit_concurrency(2);
+        o.yield_hook(100, {
+            let calls = calls.clone();
+            let cancel = cancel.clone();
+            let cancelled_calls = cancelled_calls.clone();
+            move |point| {
+                if cancel.load(Ordering::Relaxed) {
+                    cancelled_calls.fetch_add(1, Ordering::Relaxed);
+                    return Err(Cancelled);
+                }
+                calls.lock().unwrap().push(point);
+                Ok(())
+            }
+        });
+    });
+
+    // Keys landing in an empty trie are inserted in one go.
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals(0..10_000))
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+    calls.lock().unwrap().clear();
+
+    let session =
+        nomt.begin_session(SessionParams::default().witness_mode(WitnessMode::read_write()));
+    session
+        .finish(actuals(10_000..20_000))
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+    let root = nomt.root();
+    {
+        let calls = calls.lock().unwrap();
+        let count = |point| calls.iter().filter(|p| **p == point).count();
+        // Every 100 keys on each of the two workers, and every 100 paths.
+        assert!(count(YieldPoint::MerkleUpdate) >= 10_000 / 100 - 2);
+        assert!(count(YieldPoint::WitnessBuild) > 0);
+    }
+
+    // A cancelled session fails and leaves the database as it was.
+    cancel.store(true, Ordering::Relaxed);
+    let err = nomt
+        .begin_session(SessionParams::default())
+        .finish(actuals(20_000..30_000))
+        .err()
+        .unwrap();
+    assert!(err.downcast_ref::<Cancelled>().is_some());
+    assert!(cancelled_calls.load(Ordering::Relaxed) > 0);
+    assert_eq!(nomt.root(), root);
+
+    cancel.store(false, Ordering::Relaxed);
+    nomt.begin_session(SessionParams::default())
+        .finish(actuals(20_000..30_000))
+        .unwrap()
+        .commit(&nomt)
+        .unwrap();
+    assert_ne!(nomt.root(), root);
+}
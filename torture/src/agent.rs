@@ -2,6 +2,7 @@ use anyhow::{anyhow, bail, Result};
 use futures::SinkExt as _;
 use nomt::Session;
 use nomt::{hasher::Blake3Hasher, Nomt, SessionParams};
+use std::collections::HashMap;
 use std::future::Future;
 use std::path::Path;
 use std::{path::PathBuf, sync::Arc, time::Duration};
@@ -22,8 +23,9 @@ use tracing::trace;
 use crate::message::Key;
 use crate::{
     message::{
-        self, CommitPayload, Envelope, InitOutcome, KeyValueChange, OpenOutcome, OpenPayload,
-        Outcome, RollbackPayload, ToAgent, ToSupervisor, MAX_ENVELOPE_SIZE,
+        self, CommitPayload, CommitPhases, Envelope, InitOutcome, KeyValueChange, OpenOutcome,
+        OpenPayload, OpenStats, Outcome, PinnedReadsPayload, RollbackPayload, ToAgent,
+        ToSupervisor, MAX_ENVELOPE_SIZE, PINNED_READ_VIOLATION_EXIT_CODE,
     },
     panic::panic_to_err,
 };
@@ -51,6 +53,7 @@ pub async fn run(input: UnixStream) -> Result<()> {
                 read_concurrency,
                 changeset,
                 should_crash: Some(crash_delay),
+                pinned_reads,
             }) => {
                 // Ack first.
                 stream
@@ -60,11 +63,15 @@ pub async fn run(input: UnixStream) -> Result<()> {
                     })
                     .await?;
 
+                if let Some(pinned_reads) = pinned_reads {
+                    agent.spawn_pinned_readers(pinned_reads, &changeset);
+                }
+
                 let task = async move {
                     let start = std::time::Instant::now();
                     agent.begin_session();
                     agent.read(reads, read_concurrency).await;
-                    let _ = agent.commit(changeset).await;
+                    let _ = agent.commit(changeset, &mut CommitPhases::default()).await;
                     let elapsed = start.elapsed();
                     tracing::info!("commit took {}ms", elapsed.as_millis());
                 };
@@ -77,17 +84,26 @@ pub async fn run(input: UnixStream) -> Result<()> {
                 read_concurrency,
                 changeset,
                 should_crash: None,
+                ..
             }) => {
                 let start = std::time::Instant::now();
                 agent.begin_session();
                 agent.read(reads, read_concurrency).await;
-                let outcome = agent.commit(changeset).await;
+                let mut phases = CommitPhases {
+                    read: start.elapsed(),
+                    ..CommitPhases::default()
+                };
+                let outcome = agent.commit(changeset, &mut phases).await;
                 let elapsed = start.elapsed();
                 tracing::info!("commit took {}ms", elapsed.as_millis());
                 stream
                     .send(Envelope {
                         reqno,
-                        message: ToSupervisor::CommitResponse { elapsed, outcome },
+                        message: ToSupervisor::CommitResponse {
+                            elapsed,
+                            phases,
+                            outcome,
+                        },
                     })
                     .await?;
             }
@@ -156,11 +172,11 @@ pub async fn run(input: UnixStream) -> Result<()> {
             }
             ToAgent::Open(open_params) => {
                 tracing::info!("opening the database");
-                let outcome = agent.perform_open(&workdir, open_params).await;
+                let (outcome, stats) = agent.perform_open(&workdir, open_params).await;
                 stream
                     .send(Envelope {
                         reqno,
-                        message: ToSupervisor::OpenResponse(outcome),
+                        message: ToSupervisor::OpenResponse { stats, outcome },
                     })
                     .await?;
             }
@@ -261,7 +277,7 @@ async fn initialize(stream: &mut Stream) -> Result<PathBuf> {
 }
 
 struct Agent {
-    nomt: Option<Nomt<Blake3Hasher>>,
+    nomt: Option<Arc<Nomt<Blake3Hasher>>>,
     session: Option<Session<Blake3Hasher>>,
 }
 
@@ -276,7 +292,11 @@ impl Agent {
     /// Open a NOMT instance at the specified path with the specified open parameters.
     ///
     /// Drops any pending session and instance.
-    async fn perform_open(&mut self, workdir: &Path, open_params: OpenPayload) -> OpenOutcome {
+    async fn perform_open(
+        &mut self,
+        workdir: &Path,
+        open_params: OpenPayload,
+    ) -> (OpenOutcome, OpenStats) {
         if let Some(nomt) = self.nomt.take() {
             // Drop any pending session.
             let _ = self.session.take();
@@ -284,8 +304,13 @@ impl Agent {
             drop(nomt);
         }
 
+        let db_path = workdir.join("nomt_db");
+        // The WAL is truncated after every successful sync, so whatever is left of it is replayed
+        // on open.
+        let wal_len = std::fs::metadata(db_path.join("wal")).map_or(0, |m| m.len());
+
         let mut o = nomt::Options::new();
-        o.path(workdir.join("nomt_db"));
+        o.path(db_path);
         o.bitbox_seed(open_params.bitbox_seed);
         o.hashtable_buckets(open_params.hashtable_buckets);
         o.warm_up(open_params.warm_up);
@@ -300,13 +325,19 @@ impl Agent {
         } else {
             o.rollback(false);
         }
-        let nomt = match block_in_place(|| Nomt::open(o), "Panic opening nomt") {
+        let start = std::time::Instant::now();
+        let result = block_in_place(|| Ok(Nomt::open(o)?), "Panic opening nomt");
+        let stats = OpenStats {
+            elapsed: start.elapsed(),
+            wal_len,
+        };
+        let nomt = match result {
             Ok(nomt) => nomt,
-            Err(ref err) if is_enospc(err) => return OpenOutcome::StorageFull,
-            Err(ref err) => return OpenOutcome::UnknownFailure(err.to_string()),
+            Err(ref err) if is_enospc(err) => return (OpenOutcome::StorageFull, stats),
+            Err(ref err) => return (OpenOutcome::UnknownFailure(err.to_string()), stats),
         };
-        self.nomt = Some(nomt);
-        OpenOutcome::Success
+        self.nomt = Some(Arc::new(nomt));
+        (OpenOutcome::Success, stats)
     }
 
     /// Begin a session, this must be called before `read` and `commit`,
@@ -352,11 +383,78 @@ impl Agent {
         self.session.replace(Arc::into_inner(session).unwrap());
     }
 
+    /// Spawn readers which repeatedly read the given keys, each through its own session, until the
+    /// process ends.
+    ///
+    /// A session opened at the current root must observe the expected values and a session
+    /// opened at any other root, which can only follow the commit of `changeset`, must observe
+    /// them with `changeset` applied. Otherwise, the process exits with
+    /// [`PINNED_READ_VIOLATION_EXIT_CODE`].
+    ///
+    /// Returns once every reader holds its first session, so that the commit has to wait for
+    /// them to release it.
+    fn spawn_pinned_readers(&self, payload: PinnedReadsPayload, changeset: &[KeyValueChange]) {
+        // UNWRAP: `nomt` is always `Some` except recreation.
+        let nomt = self.nomt.as_ref().unwrap();
+        let pinned_root = nomt.root();
+
+        let changes = changeset
+            .iter()
+            .map(|change| (*change.key(), change.value()))
+            .collect::<HashMap<_, _>>();
+        let after = payload
+            .expected
+            .iter()
+            .map(|(key, value)| (*key, changes.get(key).unwrap_or(value).clone()))
+            .collect::<Vec<_>>();
+        let expected = Arc::new((payload.expected, after));
+
+        let barrier = Arc::new(std::sync::Barrier::new(payload.readers + 1));
+        for _ in 0..payload.readers {
+            let nomt = nomt.clone();
+            let expected = expected.clone();
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                let mut first = true;
+                loop {
+                    let session = nomt.begin_session(SessionParams::default());
+                    if std::mem::take(&mut first) {
+                        barrier.wait();
+                    }
+                    let expected = if session.prev_root() == pinned_root {
+                        &expected.0
+                    } else {
+                        &expected.1
+                    };
+                    for (key, value) in expected {
+                        let found = session.read(*key).expect("read failed");
+                        if found != *value {
+                            tracing::error!(
+                                "pinned read of {} at {:?}: expected {:?}, found {:?}",
+                                hex::encode(key),
+                                session.prev_root(),
+                                value.as_ref().map(hex::encode),
+                                found.as_ref().map(hex::encode),
+                            );
+                            std::process::exit(PINNED_READ_VIOLATION_EXIT_CODE);
+                        }
+                    }
+                }
+            });
+        }
+        barrier.wait();
+    }
+
     /// Commit the specified changeset to an already opened session, it requires
     /// `begin_session` to have been called.
     ///
     /// It consumes the session, thus after the call the session is expected to be reopened .
-    async fn commit(&mut self, changeset: Vec<KeyValueChange>) -> Outcome {
+    /// The time spent finishing the session and syncing it is recorded in `phases`.
+    async fn commit(
+        &mut self,
+        changeset: Vec<KeyValueChange>,
+        phases: &mut CommitPhases,
+    ) -> Outcome {
         // UNWRAP: `nomt` is always `Some` except recreation.
         let nomt = self.nomt.as_ref().unwrap();
         // UNWRAP: `commit` is expected to be called after `begin_session`.
@@ -375,8 +473,17 @@ impl Agent {
         }
 
         // Perform the commit.
-        let commit_result =
-            block_in_place(|| session.finish(actuals)?.commit(&nomt), "Panic in commit");
+        let commit_result = block_in_place(
+            || {
+                let start = std::time::Instant::now();
+                let finished = session.finish(actuals)?;
+                phases.finish = start.elapsed();
+                let result = finished.commit(&nomt);
+                phases.sync = start.elapsed() - phases.finish;
+                Ok(result?)
+            },
+            "Panic in commit",
+        );
         let commit_outcome = classify_result(commit_result);
 
         // Log the outcome if it was not successful.
@@ -394,7 +501,7 @@ impl Agent {
 
         // Perform the rollback.
 
-        let rollback_result = block_in_place(|| nomt.rollback(n_commits), "Panic in rollback");
+        let rollback_result = block_in_place(|| Ok(nomt.rollback(n_commits)?), "Panic in rollback");
         let rollback_outcome = classify_result(rollback_result);
 
         // Log the outcome if it was not successful.
@@ -444,7 +551,12 @@ fn classify_result(operation_result: anyhow::Result<()>) -> Outcome {
 
 /// Examines the given error to determine if it is an `ENOSPC` IO error.
 fn is_enospc(err: &anyhow::Error) -> bool {
-    let Some(io_err) = err.downcast_ref::<std::io::Error>() else {
+    let io_err = match err.downcast_ref::<nomt::Error>() {
+        Some(nomt::Error::OutOfSpace(_)) => return true,
+        Some(err) => err.downcast_ref::<std::io::Error>(),
+        None => err.downcast_ref::<std::io::Error>(),
+    };
+    let Some(io_err) = io_err else {
         return false;
     };
     let Some(errno) = io_err.raw_os_error() else {
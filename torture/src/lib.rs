@@ -0,0 +1,37 @@
+//! Torture testing of NOMT.
+//!
+//! The supervisor generates workloads and runs each of them in an agent, a child process which
+//! opens a database and applies the commits, rollbacks and crashes the supervisor asks for. The
+//! supervisor checks the results against the state it expects and flags the workloads which
+//! diverge for investigation.
+//!
+//! Besides the `torture` binary, the supervisor can be embedded with [`run`], e.g. to exercise a
+//! database with the commits of a [`CommitSource`] in the CI of a downstream crate. Agents are
+//! spawned by re-executing the current binary, so a binary calling [`run`] must begin its `main`
+//! with [`run_agent_if_spawned`] and return if it yields `true`.
+
+use tokio::net::UnixStream;
+
+mod agent;
+mod logging;
+mod message;
+mod panic;
+mod spawn;
+mod supervisor;
+
+pub use message::{Key, KeyValueChange, Value};
+pub use supervisor::{
+    run, run_cli, CommitSource, Config, InvestigationFlag, Report, WorkloadTemplate,
+};
+
+/// Run as an agent until the supervisor is done with it, if this process was spawned as one.
+///
+/// Returns `false` right away otherwise. Panics if called more than once.
+pub async fn run_agent_if_spawned() -> anyhow::Result<bool> {
+    let Some(chan) = spawn::am_spawned() else {
+        return Ok(false);
+    };
+    let chan = UnixStream::from_std(chan)?;
+    agent::run(chan).await?;
+    Ok(true)
+}
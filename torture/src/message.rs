@@ -96,8 +96,32 @@ pub struct CommitPayload {
     /// If Some the supervisor expects the commit to crash,
     /// the crash should happen after the specified amount of time.
     pub should_crash: Option<Duration>,
+    /// If Some, readers holding their own sessions should run concurrently with the commit.
+    ///
+    /// Only used if the commit is expected to crash.
+    pub pinned_reads: Option<PinnedReadsPayload>,
+}
+
+/// The parameters of the readers running concurrently with a crashing commit.
+///
+/// Every reader repeatedly opens a session and reads all the keys through it. A session opened
+/// before the commit must observe the expected values, a session opened after it must observe
+/// them with the changeset applied. An agent whose readers observe anything else exits with
+/// [`PINNED_READ_VIOLATION_EXIT_CODE`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PinnedReadsPayload {
+    /// The number of concurrent readers.
+    ///
+    /// It must be greater than 0.
+    pub readers: usize,
+    /// The keys to read along with their values before the commit.
+    pub expected: Vec<(Key, Option<Value>)>,
 }
 
+/// The exit code of an agent whose pinned readers observed a value inconsistent with the root
+/// of their session.
+pub const PINNED_READ_VIOLATION_EXIT_CODE: i32 = 3;
+
 /// The parameters for the [`ToAgent::Rollback`] message.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RollbackPayload {
@@ -181,6 +205,26 @@ pub enum OpenOutcome {
     UnknownFailure(String),
 }
 
+/// Measurements of the agent opening the database, which includes recovering from a crash.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct OpenStats {
+    /// The time it took to open the database.
+    pub elapsed: Duration,
+    /// The length of the WAL file found before opening, i.e. the amount of WAL replayed.
+    pub wal_len: u64,
+}
+
+/// The time spent by the agent in each phase of a commit.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct CommitPhases {
+    /// Reading the keys of the commit.
+    pub read: Duration,
+    /// Computing the new root, i.e. finishing the session.
+    pub finish: Duration,
+    /// Committing the finished session to disk.
+    pub sync: Duration,
+}
+
 /// Messages sent from the agent to the supervisor.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ToSupervisor {
@@ -189,11 +233,17 @@ pub enum ToSupervisor {
     /// The response to the [`ToAgent::Init`] request.
     InitResponse(InitOutcome),
     /// The response to the [`ToAgent::Open`] request.
-    OpenResponse(OpenOutcome),
+    OpenResponse {
+        /// How long opening the database took and how much WAL was replayed.
+        stats: OpenStats,
+        outcome: OpenOutcome,
+    },
     /// The response to a completed commit request.
     CommitResponse {
         /// The time it took for the operation to complete.
         elapsed: Duration,
+        /// The time spent in each phase of the operation.
+        phases: CommitPhases,
         /// The outcome of the commit.
         outcome: Outcome,
     },
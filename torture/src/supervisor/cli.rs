@@ -1,5 +1,7 @@
 use clap::{Args, Parser, Subcommand};
 
+use super::template::WorkloadTemplate;
+
 #[derive(Parser, Debug)]
 pub struct Cli {
     #[command(subcommand)]
@@ -37,6 +39,17 @@ pub struct SwarmParams {
     #[clap(value_parser=clap::value_parser!(u8).range(1..=100))]
     #[arg(long, default_value_t = 70)]
     pub max_memory: u8,
+
+    /// The longest time the database may take to recover after a crash, as a multiple of the
+    /// average commit time of the workload.
+    ///
+    /// A workload whose recovery takes longer fails. 0 disables the check.
+    #[arg(long, default_value_t = 50)]
+    pub max_recovery_factor: u32,
+
+    /// Generate the commits of every workload following a template, instead of at random.
+    #[arg(long, value_enum)]
+    pub template: Option<WorkloadTemplate>,
 }
 
 #[derive(Clone, Debug, Args)]
@@ -62,4 +75,15 @@ pub struct RunParams {
     /// This applies after every rollback.
     #[arg(long = "ensure_snapshot", default_value = "false")]
     pub ensure_snapshot: bool,
+
+    /// The longest time the database may take to recover after a crash, as a multiple of the
+    /// average commit time of the workload.
+    ///
+    /// A workload whose recovery takes longer fails. 0 disables the check.
+    #[arg(long, default_value_t = 50)]
+    pub max_recovery_factor: u32,
+
+    /// Generate the commits of every workload following a template, instead of at random.
+    #[arg(long, value_enum)]
+    pub template: Option<WorkloadTemplate>,
 }
@@ -1,6 +1,7 @@
 use super::{
     resource::ResourceAllocator,
     swarm::{self, SwarmFeatures},
+    template::WorkloadTemplate,
     ResourceExhaustion,
 };
 use rand::prelude::*;
@@ -94,6 +95,10 @@ pub struct WorkloadConfiguration {
     pub sample_snapshot: bool,
     /// When executing a commit this is the probability of causing it to crash.
     pub commit_crash: f64,
+    /// The number of readers holding sessions concurrently with a crashing commit.
+    ///
+    /// If 0, no such readers are used.
+    pub pinned_readers: usize,
     /// When executing a workload iteration ,this is the probability of executing a rollback.
     pub rollback: f64,
     /// The max number of commits involved in a rollback.
@@ -113,6 +118,15 @@ pub struct WorkloadConfiguration {
     pub latency_on: f64,
     /// The probability of turning off the latency injector.
     pub latency_off: f64,
+    /// The longest recovery after a crash allowed, as a multiple of the average commit time.
+    ///
+    /// If 0, recovery time is not checked.
+    pub max_recovery_factor: u32,
+    /// The template generating the commits, instead of generating them at random.
+    ///
+    /// If set, the swarm features shaping the commits are not used, and the sizes of commits and
+    /// values are the ones of the template.
+    pub template: Option<WorkloadTemplate>,
     // Whether to ensure the correctness of the entire state after every crash or rollback.
     //
     // This is only used when repeating a failed workload.
@@ -122,6 +136,7 @@ pub struct WorkloadConfiguration {
 impl WorkloadConfiguration {
     fn new_inner(
         rng: &mut rand_pcg::Pcg64,
+        template: Option<WorkloadTemplate>,
         avail_bytes: impl Fn(bool) -> Result<u64, ResourceExhaustion>,
     ) -> Result<Self, ResourceExhaustion> {
         let swarm_features = swarm::new_features_set(rng);
@@ -149,12 +164,15 @@ impl WorkloadConfiguration {
             overflow: 0.0,
             rollback: 0.0,
             commit_crash: 0.0,
+            pinned_readers: 0,
             rollback_crash: 0.0,
             trickfs,
             enospc_on: 0.0,
             enospc_off: 0.0,
             latency_on: 0.0,
             latency_off: 0.0,
+            max_recovery_factor: 0,
+            template,
             ensure_changeset: false,
             ensure_snapshot: false,
             sample_snapshot: false,
@@ -178,6 +196,9 @@ impl WorkloadConfiguration {
             hashtable_buckets: 0,
             iterations: 0,
         };
+        if let Some(template) = template {
+            template.configure(&mut config, rng);
+        }
 
         // Use only portion of the assigned bytes for the hash table.
         let hashtable_ratio = if trickfs {
@@ -236,6 +257,9 @@ impl WorkloadConfiguration {
         config.iterations = n_items as usize / config.avg_commit_size;
 
         for swarm_feature in swarm_features {
+            if template.is_some() && swarm_feature.shapes_commits() {
+                continue;
+            }
             config.apply_swarm_feature(rng, swarm_feature);
         }
 
@@ -254,6 +278,7 @@ impl WorkloadConfiguration {
         rng: &mut rand_pcg::Pcg64,
         workload_id: u64,
         resource_alloc: Arc<Mutex<ResourceAllocator>>,
+        template: Option<WorkloadTemplate>,
     ) -> Result<Self, ResourceExhaustion> {
         let avail_bytes = |trickfs: bool| {
             // UNWRAP: The allocator is only used during the creation of the workload or
@@ -267,13 +292,14 @@ impl WorkloadConfiguration {
                 Ok(allocator.assigned_resources(workload_id).disk)
             }
         };
-        Self::new_inner(rng, avail_bytes)
+        Self::new_inner(rng, template, avail_bytes)
     }
 
     pub fn new_with_resources(
         rng: &mut rand_pcg::Pcg64,
         assigned_disk: u64,
         assigned_memory: u64,
+        template: Option<WorkloadTemplate>,
     ) -> Self {
         let avail_bytes = |trickfs: bool| {
             if trickfs {
@@ -282,7 +308,7 @@ impl WorkloadConfiguration {
                 Ok(assigned_disk)
             }
         };
-        Self::new_inner(rng, avail_bytes).unwrap()
+        Self::new_inner(rng, template, avail_bytes).unwrap()
     }
 
     pub fn enable_ensure_snapshot(&mut self) {
@@ -320,6 +346,7 @@ impl WorkloadConfiguration {
             }
             SwarmFeatures::RollbackCrash => self.rollback_crash = rng.random_range(0.01..1.00),
             SwarmFeatures::CommitCrash => self.commit_crash = rng.random_range(0.01..1.00),
+            SwarmFeatures::PinnedReadersCrash => self.pinned_readers = rng.random_range(1..=16),
             SwarmFeatures::PrepopulatePageCache => self.prepopulate_page_cache = true,
             SwarmFeatures::NewKeys => self.new_key = rng.random_range(0.01..=1.00),
             SwarmFeatures::DeleteKeys => self.delete_key = rng.random_range(0.01..=1.00),
@@ -1,9 +1,10 @@
-use crate::message::{InitOutcome, OpenOutcome};
+use crate::message::{InitOutcome, OpenOutcome, OpenStats};
 
 use super::{comms, config::WorkloadConfiguration};
 use anyhow::Result;
 use std::{
     path::PathBuf,
+    process::ExitStatus,
     sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 use tokio::{net::UnixStream, process::Child};
@@ -57,7 +58,7 @@ impl SpawnedAgentController {
         }
     }
 
-    pub async fn open(&self, config: &WorkloadConfiguration) -> Result<OpenOutcome> {
+    pub async fn open(&self, config: &WorkloadConfiguration) -> Result<(OpenOutcome, OpenStats)> {
         let rollback = if config.is_rollback_enable() {
             Some(config.max_rollback_commits)
         } else {
@@ -81,7 +82,9 @@ impl SpawnedAgentController {
             }))
             .await?;
         match response {
-            crate::message::ToSupervisor::OpenResponse(outcome) => return Ok(outcome),
+            crate::message::ToSupervisor::OpenResponse { stats, outcome } => {
+                return Ok((outcome, stats))
+            }
             _ => {
                 panic!("expected open, unexpected response: {:?}", response);
             }
@@ -99,9 +102,9 @@ impl SpawnedAgentController {
         let _ = self.child.kill().await;
     }
 
-    /// Resolves when the agent process exits.
-    pub async fn died(&mut self) {
-        let _ = self.child.wait().await;
+    /// Resolves when the agent process exits, with its exit status if it could be obtained.
+    pub async fn died(&mut self) -> Option<ExitStatus> {
+        self.child.wait().await.ok()
     }
 
     pub fn rr(&self) -> &comms::RequestResponse {
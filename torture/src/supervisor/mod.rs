@@ -3,13 +3,12 @@
 use std::{
     path::PathBuf,
     process::exit,
-    str::FromStr,
     sync::{Arc, Mutex},
 };
 
 use anyhow::Result;
 use clap::Parser;
-use cli::{Cli, RunParams, SwarmParams};
+use cli::{Cli, RunParams};
 use resource::{AssignedResources, ResourceAllocator, ResourceExhaustion};
 use tempfile::TempDir;
 use tokio::{
@@ -19,9 +18,14 @@ use tokio::{
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info, instrument::WithSubscriber, warn};
 
-use crate::logging;
+use crate::{
+    logging,
+    message::{Key, KeyValueChange},
+};
 use workload::Workload;
 
+pub use template::WorkloadTemplate;
+
 mod cli;
 mod comms;
 mod config;
@@ -29,12 +33,89 @@ mod controller;
 mod pbt;
 mod resource;
 mod swarm;
+mod template;
+mod timeline;
 mod workload;
 
-/// The entrypoint for the supervisor part of the program.
+/// The configuration of a run of the supervisor. See [`run`].
+pub struct Config {
+    /// The seed of the run. Workloads are generated from consecutive seeds starting here.
+    pub seed: u64,
+    /// The number of workloads to run. Several run at the same time, as resources allow.
+    pub workloads: u64,
+    /// The directory holding the directories of the workloads. The temporary directory of the
+    /// system if `None`.
+    pub workdir: Option<PathBuf>,
+    /// The maximum percentage of total disk space the workloads may occupy.
+    pub max_disk: u8,
+    /// The maximum percentage of total memory the workloads may occupy.
+    pub max_memory: u8,
+    /// The number of flagged workloads after which no more are started.
+    pub flag_limit: usize,
+    /// The longest time the database may take to recover after a crash, as a multiple of the
+    /// average commit time of the workload. 0 disables the check.
+    pub max_recovery_factor: u32,
+    /// Creates the source of the commits of a workload, given the seed of the workload. The
+    /// commits are generated at random if `None`.
+    pub commits: Option<Arc<dyn Fn(u64) -> Box<dyn CommitSource> + Send + Sync>>,
+    /// The template the commits of every workload follow, unless they are taken from
+    /// [`Config::commits`]. The commits are generated at random if `None`.
+    pub template: Option<WorkloadTemplate>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            seed: rand::random(),
+            workloads: 1,
+            workdir: None,
+            max_disk: 70,
+            max_memory: 70,
+            flag_limit: 1,
+            max_recovery_factor: 50,
+            commits: None,
+            template: None,
+        }
+    }
+}
+
+/// A source of the commits of a workload, in place of randomly generated ones.
+///
+/// Everything else about the workload, such as the options of the database, the crashes and the
+/// rollbacks, is still chosen at random.
+pub trait CommitSource: Send {
+    /// The keys to read and the changes to apply in the next commit, or `None` once the workload
+    /// is done.
+    ///
+    /// The values read are checked against the state the supervisor expects. A key may be changed
+    /// at most once in a commit.
+    fn next_commit(&mut self) -> Option<(Vec<Key>, Vec<KeyValueChange>)>;
+}
+
+/// The outcome of a run of the supervisor.
+#[derive(Debug)]
+pub struct Report {
+    /// The seed of the run.
+    pub seed: u64,
+    /// The number of workloads started.
+    pub workloads: u64,
+    /// The workloads flagged for investigation. The run found no issues if this is empty.
+    pub flags: Vec<InvestigationFlag>,
+}
+
+/// Run workloads as configured, returning once all of them have finished or the flag limit has
+/// been reached.
+///
+/// This must be called from a binary which begins with [`crate::run_agent_if_spawned`]. It fails
+/// only if the supervisor itself does. Failures of the workloads are reported as flags.
+pub async fn run(config: Config) -> Result<Report> {
+    control_loop(CancellationToken::new(), config).await
+}
+
+/// The entrypoint for the supervisor part of the `torture` binary, parsing the command line.
 ///
 /// This is not expected to return explicitly unless there was an error.
-pub async fn run() -> Result<()> {
+pub async fn run_cli() -> Result<()> {
     logging::init_supervisor();
 
     // Create a cancellation token and spawn the control loop task passing the token to it and
@@ -46,9 +127,21 @@ pub async fn run() -> Result<()> {
         cli::Commands::Swarm(swarm_params) => swarm_params,
         cli::Commands::Run(run_params) => return run_single_workload(ct, run_params).await,
     };
-    let seed = rand::random();
+    let config = Config {
+        workloads: u64::MAX,
+        workdir: swarm_params.workdir.map(PathBuf::from),
+        max_disk: swarm_params.max_disk,
+        max_memory: swarm_params.max_memory,
+        flag_limit: swarm_params.flag_limit,
+        max_recovery_factor: swarm_params.max_recovery_factor,
+        template: swarm_params.template,
+        ..Config::default()
+    };
 
-    let control_loop_jh = task::spawn(control_loop(ct.clone(), seed, swarm_params));
+    let control_loop_jh = task::spawn({
+        let ct = ct.clone();
+        async move { control_loop(ct, config).await.map(|_| ()) }
+    });
     match join_interruptable(control_loop_jh, ct).await {
         ExitReason::Finished => {
             exit(0);
@@ -150,19 +243,23 @@ async fn join_interruptable(
     }
 }
 
+/// A workload which failed, along with what is needed to investigate it.
 #[derive(Debug)]
 pub struct InvestigationFlag {
     /// Seed used to generate the workload.
-    seed: u64,
+    pub seed: u64,
     /// Amount of disk, in bytes, that was assigned to the workload.
-    assigned_disk: u64,
+    pub assigned_disk: u64,
     /// Amount of memory, in bytes, that was assigned to the workload.
-    assigned_memory: u64,
-    workload_id: u64,
-    /// The directory the agent was working in.
-    workdir: PathBuf,
+    pub assigned_memory: u64,
+    pub workload_id: u64,
+    /// The template the commits of the workload followed, if any.
+    pub template: Option<WorkloadTemplate>,
+    /// The directory the agent was working in. It is kept, along with the log and the timeline
+    /// of the workload.
+    pub workdir: PathBuf,
     /// The reason for flagging.
-    reason: anyhow::Error,
+    pub reason: anyhow::Error,
 }
 
 fn init_workload_dir(workdir_path: PathBuf, workload_id: u64) -> TempDir {
@@ -187,6 +284,7 @@ async fn run_workload(
 ) -> Result<Option<InvestigationFlag>> {
     let workload_dir_path = workload.workload_dir_path();
     let AssignedResources { disk, memory } = workload.assigned_resources();
+    let template = workload.template();
     let result = workload
         .run(cancel_token)
         .with_subscriber(logging::workload_subscriber(&workload_dir_path))
@@ -199,6 +297,7 @@ async fn run_workload(
             workload_id,
             assigned_disk: disk,
             assigned_memory: memory,
+            template,
             // `TempDir::into_path` persists the TempDir to disk.
             workdir: workload.into_workload_dir().into_path(),
             reason: err,
@@ -207,10 +306,13 @@ async fn run_workload(
 }
 
 fn print_flag(flag: &InvestigationFlag) {
+    let template = flag
+        .template
+        .map_or("none".to_string(), |template| template.to_string());
     warn!(
         "Flagged for investigation:\n  seed={seed}\n  assigned_disk={assigned_disk}\n  \
-         assigned_memory={assigned_memory}\n  workload_id={workload_id}\n  workdir={workdir}\n  \
-         reason={reason}",
+         assigned_memory={assigned_memory}\n  template={template}\n  workload_id={workload_id}\n  \
+         workdir={workdir}\n  reason={reason}",
         seed = flag.seed,
         assigned_disk = flag.assigned_disk,
         assigned_memory = flag.assigned_memory,
@@ -227,21 +329,18 @@ const NON_DETERMINISM_DISCLAIMER: &str = "torture is a non-deterministic fuzzer.
 /// Run the control loop creating and tearing down the agents.
 ///
 /// `cancel_token` is used to gracefully shutdown the supervisor.
-async fn control_loop(
-    cancel_token: CancellationToken,
-    seed: u64,
-    mut swarm_params: SwarmParams,
-) -> Result<()> {
+async fn control_loop(cancel_token: CancellationToken, config: Config) -> Result<Report> {
+    let seed = config.seed;
     info!("Starting control loop, seed={seed}.\n{NON_DETERMINISM_DISCLAIMER}");
     let mut flags = Vec::new();
     let mut workload_cnt = 0;
     let mut running_workloads = JoinSet::new();
 
-    let workdir_path = if let Some(workdir_path) = swarm_params.workdir.take() {
-        if !std::path::Path::new(&workdir_path).exists() {
+    let workdir_path = if let Some(workdir_path) = config.workdir {
+        if !workdir_path.exists() {
             anyhow::bail!("The workdir path does not exist");
         }
-        PathBuf::from_str(&workdir_path).unwrap()
+        workdir_path
     } else {
         std::env::temp_dir()
     };
@@ -255,8 +354,8 @@ async fn control_loop(
     let resource_alloc = Arc::new(Mutex::new(ResourceAllocator::new(
         workdir_path.clone(),
         seed,
-        swarm_params.max_disk,
-        swarm_params.max_memory,
+        config.max_disk,
+        config.max_memory,
     )?));
 
     loop {
@@ -268,19 +367,24 @@ async fn control_loop(
             }
         }
 
-        loop {
+        while workload_cnt < config.workloads {
             let workload_id = workload_cnt;
             let workload_seed = seed + workload_cnt;
             let workload_dir = init_workload_dir(workdir_path.clone(), workload_id);
 
-            let Ok(workload) = Workload::new(
+            let Ok(mut workload) = Workload::new(
                 workload_seed,
                 workload_dir,
                 workload_id,
                 resource_alloc.clone(),
+                config.max_recovery_factor,
+                config.template,
             ) else {
                 break;
             };
+            if let Some(ref commits) = config.commits {
+                workload = workload.with_commit_source(commits(workload_seed));
+            }
 
             workload_cnt += 1;
 
@@ -298,9 +402,12 @@ async fn control_loop(
         // ctrl-c is received, the cancel_token will stop the workload
         // and thus allow all workloads to conclude early.
         //
-        // UNWRAP: at least one workload execution was spawned.
-        // The execution is expected to properly reach completion.
-        let workload_result = running_workloads.join_next().await.unwrap()?;
+        // The execution is expected to properly reach completion. Nothing is running only once
+        // all workloads have been run.
+        let Some(workload_result) = running_workloads.join_next().await else {
+            break;
+        };
+        let workload_result = workload_result?;
         // The execution could have returned an error or an optional flag.
         let maybe_flag = workload_result?;
         if let Some(flag) = maybe_flag {
@@ -311,7 +418,7 @@ async fn control_loop(
         if cancel_token.is_cancelled() {
             break;
         }
-        if flags.len() >= swarm_params.flag_limit {
+        if flags.len() >= config.flag_limit {
             info!("Flag limit reached. Exiting.");
             break;
         }
@@ -324,10 +431,14 @@ async fn control_loop(
         }
     }
 
-    for flag in flags {
-        print_flag(&flag);
+    for flag in &flags {
+        print_flag(flag);
     }
-    Ok(())
+    Ok(Report {
+        seed,
+        workloads: workload_cnt,
+        flags,
+    })
 }
 
 async fn run_single_workload(
@@ -352,6 +463,8 @@ async fn run_single_workload(
         run_params.ensure_snapshot,
         run_params.assigned_disk,
         run_params.assigned_memory,
+        run_params.max_recovery_factor,
+        run_params.template,
     );
 
     let maybe_flag = run_workload(cancel_token.clone(), run_params.seed, 0, workload).await?;
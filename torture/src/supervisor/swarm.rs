@@ -28,6 +28,8 @@ pub enum SwarmFeatures {
     RollbackCrash,
     /// Whether commit crash should be exercised.
     CommitCrash,
+    /// Whether concurrent readers should hold sessions across commit crashes.
+    PinnedReadersCrash,
     /// Whether to prepopulate the upper levels of the page cache on startup.
     PrepopulatePageCache,
     /// Whether new keys should be inserted during commits.
@@ -40,6 +42,20 @@ pub enum SwarmFeatures {
     OverflowValues,
 }
 
+impl SwarmFeatures {
+    /// Whether the feature shapes the commits, which a workload template does instead.
+    pub fn shapes_commits(&self) -> bool {
+        matches!(
+            self,
+            SwarmFeatures::Read
+                | SwarmFeatures::NewKeys
+                | SwarmFeatures::DeleteKeys
+                | SwarmFeatures::UpdateKeys
+                | SwarmFeatures::OverflowValues
+        )
+    }
+}
+
 pub fn new_features_set(rng: &mut rand_pcg::Pcg64) -> Vec<SwarmFeatures> {
     let mut features = vec![
         SwarmFeatures::EnsureChangeset,
@@ -50,6 +66,7 @@ pub fn new_features_set(rng: &mut rand_pcg::Pcg64) -> Vec<SwarmFeatures> {
         SwarmFeatures::Rollback,
         SwarmFeatures::RollbackCrash,
         SwarmFeatures::CommitCrash,
+        SwarmFeatures::PinnedReadersCrash,
         SwarmFeatures::PrepopulatePageCache,
         SwarmFeatures::NewKeys,
         SwarmFeatures::DeleteKeys,
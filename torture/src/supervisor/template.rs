@@ -0,0 +1,364 @@
+//! Curated workloads, resembling the state accesses of common applications.
+//!
+//! Random workloads spread their changes uniformly over the key space, with uniformly distributed
+//! value sizes. Applications don't: a handful of keys are changed by almost every commit, new
+//! keys arrive in bursts and values come in a few typical sizes. A template generates the commits
+//! of a workload following such a pattern. Everything else about the workload, such as the
+//! options of the database, the crashes and the rollbacks, is still chosen at random.
+//!
+//! The generators keep track of the entities they created, e.g. accounts or tokens, but not of
+//! rollbacks. Entities created by reverted commits are still referred to afterwards, which is
+//! what happens to the addresses of reverted transactions on a chain as well.
+
+use nomt::hasher::{Blake3Hasher, ValueHasher as _};
+use rand::prelude::*;
+use rand_distr::Zipf;
+use std::collections::BTreeMap;
+
+use crate::message::{Key, KeyValueChange, Value, MAX_ENVELOPE_SIZE};
+
+use super::config::{WorkloadConfiguration, MAX_VALUE_LEN};
+
+/// The values of a commit are kept well below the maximum size of a message.
+const MAX_COMMIT_VALUE_BYTES: usize = MAX_ENVELOPE_SIZE / 4;
+
+/// A curated workload, selected with `--template`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum WorkloadTemplate {
+    /// Transfers of a token between accounts.
+    ///
+    /// The accounts involved follow a power law, with a few exchange-like accounts taking part in
+    /// most transfers. Balances are 32-byte values, some transfers create accounts and emptied
+    /// balances are deleted.
+    Erc20Transfer,
+    /// Bursts of NFT mints between quieter periods of trades.
+    ///
+    /// A burst inserts many keys at once, including token metadata of a few hundred bytes and,
+    /// now and then, on-chain art spilling into overflow pages. The supply counter of the
+    /// collection being minted is changed by every mint.
+    NftMintBurst,
+    /// Swaps against a few liquidity pools.
+    ///
+    /// Most swaps go through the same couple of pools, whose reserves and price accumulators are
+    /// changed by nearly every commit. Traders' balances follow a power law, and liquidity
+    /// positions are opened and closed now and then.
+    DexSwapHeavy,
+}
+
+impl std::fmt::Display for WorkloadTemplate {
+    /// The name of the template on the command line.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use clap::ValueEnum as _;
+        // UNWRAP: no variant is skipped.
+        f.write_str(self.to_possible_value().unwrap().get_name())
+    }
+}
+
+impl WorkloadTemplate {
+    /// Set the sizes of the commits and values of the workload to the ones of the template.
+    ///
+    /// These are used to estimate how many commits fit in the resources of the workload.
+    pub(super) fn configure(self, config: &mut WorkloadConfiguration, rng: &mut rand_pcg::Pcg64) {
+        match self {
+            WorkloadTemplate::Erc20Transfer => {
+                config.avg_commit_size = rng.random_range(100..=20_000);
+                config.avg_value_len = 32;
+                config.overflow = 0.0;
+            }
+            WorkloadTemplate::NftMintBurst => {
+                config.avg_commit_size = rng.random_range(100..=10_000);
+                config.avg_value_len = 160;
+                config.avg_overflow_value_len = 12 * 1024;
+                config.overflow = NFT_ONCHAIN_ART;
+            }
+            WorkloadTemplate::DexSwapHeavy => {
+                config.avg_commit_size = rng.random_range(100..=20_000);
+                config.avg_value_len = 64;
+                config.overflow = 0.0;
+            }
+        }
+    }
+}
+
+/// Generates the commits of a workload following a template.
+pub struct TemplateGenerator {
+    template: WorkloadTemplate,
+    /// The number of accounts created so far: holders, minters or traders.
+    accounts: u64,
+    /// The number of NFTs minted, or liquidity positions opened, so far.
+    items: u64,
+    /// The number of NFT collections, or liquidity pools.
+    groups: u64,
+    /// The number of commits left in the current burst of NFT mints.
+    burst: u32,
+}
+
+// The kinds of keys, hashed along with the identifiers of the entities they belong to.
+const BALANCE: u8 = 0;
+const NONCE: u8 = 1;
+const SUPPLY: u8 = 2;
+const ALLOWANCE: u8 = 3;
+const OWNER: u8 = 4;
+const TOKEN_URI: u8 = 5;
+const APPROVAL: u8 = 6;
+const RESERVES: u8 = 7;
+const PRICE: u8 = 8;
+const TICK: u8 = 9;
+const POSITION: u8 = 10;
+
+/// The probability of an NFT holding its art on-chain, in overflow pages.
+const NFT_ONCHAIN_ART: f64 = 0.05;
+
+impl TemplateGenerator {
+    pub fn new(template: WorkloadTemplate, rng: &mut rand_pcg::Pcg64) -> Self {
+        let groups = match template {
+            WorkloadTemplate::Erc20Transfer | WorkloadTemplate::NftMintBurst => 0,
+            WorkloadTemplate::DexSwapHeavy => rng.random_range(4..=64),
+        };
+        TemplateGenerator {
+            template,
+            accounts: 0,
+            items: 0,
+            groups,
+            burst: 0,
+        }
+    }
+
+    /// Generate the keys to read and the changes to apply in the next commit, of about
+    /// `avg_commit_size` reads and changes on average.
+    pub fn gen_commit(
+        &mut self,
+        rng: &mut rand_pcg::Pcg64,
+        avg_commit_size: usize,
+    ) -> (Vec<Key>, Vec<KeyValueChange>) {
+        let mut commit = CommitBuilder::default();
+        let size = rng.random_range(1..=avg_commit_size * 2);
+        match self.template {
+            WorkloadTemplate::Erc20Transfer => {
+                while !commit.is_full(size) {
+                    self.erc20_transfer(rng, &mut commit);
+                }
+            }
+            WorkloadTemplate::NftMintBurst => {
+                if self.burst == 0 && rng.random_bool(0.1) {
+                    self.burst = rng.random_range(1..=10);
+                    self.groups += 1;
+                }
+                if self.burst > 0 {
+                    self.burst -= 1;
+                    // Mints come in much larger commits than trades.
+                    while !commit.is_full(size * 3) {
+                        self.nft_mint(rng, &mut commit);
+                    }
+                } else {
+                    while !commit.is_full(size) {
+                        self.nft_trade(rng, &mut commit);
+                    }
+                }
+            }
+            WorkloadTemplate::DexSwapHeavy => {
+                while !commit.is_full(size) {
+                    self.dex_swap(rng, &mut commit);
+                }
+            }
+        }
+        commit.finish()
+    }
+
+    fn erc20_transfer(&mut self, rng: &mut rand_pcg::Pcg64, commit: &mut CommitBuilder) {
+        if self.accounts < 2 || rng.random_bool(0.02) {
+            // Mint to a new account.
+            let account = self.new_account();
+            commit.write(key(BALANCE, &[account]), value(rng, 32));
+            commit.write(key(SUPPLY, &[]), value(rng, 32));
+            return;
+        }
+
+        let sender = popular(rng, self.accounts, 1.1);
+        let receiver = if rng.random_bool(0.15) {
+            self.new_account()
+        } else {
+            popular(rng, self.accounts, 1.1)
+        };
+        commit.read(key(BALANCE, &[sender]));
+        commit.read(key(BALANCE, &[receiver]));
+        if rng.random_bool(0.05) {
+            // The whole balance is sent, and the storage of the emptied balance is freed.
+            commit.delete(key(BALANCE, &[sender]));
+        } else {
+            commit.write(key(BALANCE, &[sender]), value(rng, 32));
+        }
+        commit.write(key(BALANCE, &[receiver]), value(rng, 32));
+        commit.write(key(NONCE, &[sender]), value(rng, 8));
+        if rng.random_bool(0.05) {
+            commit.write(key(ALLOWANCE, &[sender, receiver]), value(rng, 32));
+        }
+    }
+
+    fn nft_mint(&mut self, rng: &mut rand_pcg::Pcg64, commit: &mut CommitBuilder) {
+        let collection = self.groups - 1;
+        let token = self.items;
+        self.items += 1;
+        let minter = if self.accounts == 0 || rng.random_bool(0.5) {
+            self.new_account()
+        } else {
+            popular(rng, self.accounts, 1.05)
+        };
+
+        commit.write(key(OWNER, &[token]), value(rng, 20));
+        let uri_len = if rng.random_bool(NFT_ONCHAIN_ART) {
+            rng.random_range(MAX_VALUE_LEN..=24 * 1024)
+        } else {
+            rng.random_range(64..=512)
+        };
+        commit.write(key(TOKEN_URI, &[token]), value(rng, uri_len));
+        commit.write(key(BALANCE, &[minter]), value(rng, 32));
+        commit.write(key(SUPPLY, &[collection]), value(rng, 32));
+    }
+
+    fn nft_trade(&mut self, rng: &mut rand_pcg::Pcg64, commit: &mut CommitBuilder) {
+        if self.items == 0 {
+            // Nothing was minted yet. Mint the first token of a collection.
+            self.groups = self.groups.max(1);
+            return self.nft_mint(rng, commit);
+        }
+
+        // Recently minted tokens are traded the most.
+        let token = self.items - 1 - popular(rng, self.items, 1.2);
+        let buyer = if rng.random_bool(0.2) {
+            self.new_account()
+        } else {
+            popular(rng, self.accounts, 1.05)
+        };
+        commit.read(key(OWNER, &[token]));
+        commit.read(key(TOKEN_URI, &[token]));
+        if rng.random_bool(0.01) {
+            // Burn.
+            commit.delete(key(OWNER, &[token]));
+            commit.delete(key(TOKEN_URI, &[token]));
+            commit.delete(key(APPROVAL, &[token]));
+            return;
+        }
+        commit.write(key(OWNER, &[token]), value(rng, 20));
+        commit.write(key(BALANCE, &[buyer]), value(rng, 32));
+        if rng.random_bool(0.1) {
+            commit.write(key(APPROVAL, &[token]), value(rng, 20));
+        } else {
+            // Approvals are cleared by transfers.
+            commit.delete(key(APPROVAL, &[token]));
+        }
+    }
+
+    fn dex_swap(&mut self, rng: &mut rand_pcg::Pcg64, commit: &mut CommitBuilder) {
+        let trader = if self.accounts == 0 || rng.random_bool(0.05) {
+            self.new_account()
+        } else {
+            popular(rng, self.accounts, 1.05)
+        };
+
+        if rng.random_bool(0.03) {
+            // Add liquidity, opening a position.
+            let pool = popular(rng, self.groups, 1.3);
+            let position = self.items;
+            self.items += 1;
+            commit.read(key(RESERVES, &[pool]));
+            commit.write(key(RESERVES, &[pool]), value(rng, 64));
+            commit.write(key(POSITION, &[position]), value(rng, 160));
+            commit.write(key(BALANCE, &[trader, pool]), value(rng, 32));
+            return;
+        }
+        if self.items > 0 && rng.random_bool(0.01) {
+            // Remove liquidity, closing a position.
+            let position = rng.random_range(0..self.items);
+            commit.delete(key(POSITION, &[position]));
+            return;
+        }
+
+        // The router quotes a few pools before routing the swap through one or more of them.
+        for _ in 0..rng.random_range(1..=4) {
+            commit.read(key(RESERVES, &[rng.random_range(0..self.groups)]));
+        }
+        for _ in 0..rng.random_range(1..=3) {
+            let pool = popular(rng, self.groups, 1.3);
+            commit.read(key(RESERVES, &[pool]));
+            commit.write(key(RESERVES, &[pool]), value(rng, 64));
+            commit.write(key(PRICE, &[pool]), value(rng, 96));
+            if rng.random_bool(0.3) {
+                // Crossing a tick of a concentrated liquidity pool.
+                let tick = rng.random_range(0..256);
+                commit.write(key(TICK, &[pool, tick]), value(rng, 64));
+            }
+            commit.write(key(BALANCE, &[trader, pool]), value(rng, 32));
+        }
+        commit.write(key(NONCE, &[trader]), value(rng, 8));
+    }
+
+    fn new_account(&mut self) -> u64 {
+        self.accounts += 1;
+        self.accounts - 1
+    }
+}
+
+/// The reads and changes of a commit, with at most one change per key.
+#[derive(Default)]
+struct CommitBuilder {
+    reads: Vec<Key>,
+    changes: BTreeMap<Key, Option<Value>>,
+    value_bytes: usize,
+}
+
+impl CommitBuilder {
+    fn read(&mut self, key: Key) {
+        self.reads.push(key);
+    }
+
+    /// Write a value, replacing any earlier change of the key in the commit.
+    fn write(&mut self, key: Key, value: Value) {
+        self.value_bytes += value.len();
+        self.changes.insert(key, Some(value));
+    }
+
+    fn delete(&mut self, key: Key) {
+        self.changes.insert(key, None);
+    }
+
+    fn is_full(&self, size: usize) -> bool {
+        self.reads.len() + self.changes.len() >= size || self.value_bytes >= MAX_COMMIT_VALUE_BYTES
+    }
+
+    fn finish(self) -> (Vec<Key>, Vec<KeyValueChange>) {
+        let changes = self
+            .changes
+            .into_iter()
+            .map(|(key, value)| match value {
+                Some(value) => KeyValueChange::Insert(key, value),
+                None => KeyValueChange::Delete(key),
+            })
+            .collect();
+        (self.reads, changes)
+    }
+}
+
+/// The key of the given kind belonging to the entities with the given identifiers.
+fn key(kind: u8, ids: &[u64]) -> Key {
+    let mut preimage = Vec::with_capacity(1 + ids.len() * 8);
+    preimage.push(kind);
+    for id in ids {
+        preimage.extend_from_slice(&id.to_le_bytes());
+    }
+    Blake3Hasher::hash_value(&preimage)
+}
+
+fn value(rng: &mut rand_pcg::Pcg64, len: usize) -> Value {
+    let mut value = vec![0; len];
+    rng.fill_bytes(&mut value);
+    value
+}
+
+/// Pick one of `n` entities following a power law with exponent `s`: the lower the identifier,
+/// the more popular the entity.
+fn popular(rng: &mut rand_pcg::Pcg64, n: u64, s: f64) -> u64 {
+    // UNWRAP: `n` is at least 1 and `s` is positive.
+    let zipf = Zipf::new(n as f64, s).unwrap();
+    zipf.sample(rng) as u64 - 1
+}
@@ -0,0 +1,180 @@
+//! A timeline of what happened during a workload, written as part of the failure artifact.
+//!
+//! The timeline is in the chrome trace event format and can be opened with `chrome://tracing` or
+//! Perfetto. It has a lane for the workload as driven by the supervisor, one for the phases of
+//! the commits as reported by the agent, and one for injected faults and agent restarts.
+
+use std::{
+    collections::VecDeque,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+/// The maximum number of events kept. Older events are dropped first.
+const MAX_EVENTS: usize = 100_000;
+
+/// The lane an event is shown in.
+#[derive(Clone, Copy)]
+pub enum Lane {
+    /// The steps of the workload, as driven by the supervisor.
+    Workload = 1,
+    /// The phases of commits, as reported by the agent.
+    Agent = 2,
+    /// Injected faults and agent restarts.
+    Faults = 3,
+}
+
+impl Lane {
+    fn name(self) -> &'static str {
+        match self {
+            Lane::Workload => "workload",
+            Lane::Agent => "agent",
+            Lane::Faults => "faults",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TraceEvent {
+    name: String,
+    ph: &'static str,
+    /// Microseconds since the start of the workload.
+    ts: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<u64>,
+    /// Instant events span the whole lane.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    s: Option<&'static str>,
+    pid: u64,
+    tid: u64,
+    args: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Trace<'a> {
+    trace_events: Vec<&'a TraceEvent>,
+    display_time_unit: &'static str,
+}
+
+/// Records the events of a workload.
+pub struct Timeline {
+    start: Instant,
+    workload_id: u64,
+    events: VecDeque<TraceEvent>,
+    dropped: usize,
+}
+
+impl Timeline {
+    pub fn new(workload_id: u64) -> Self {
+        Self {
+            start: Instant::now(),
+            workload_id,
+            events: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Record something which took from `start` until now.
+    pub fn span(&mut self, lane: Lane, name: impl Into<String>, start: Instant, args: &[Arg]) {
+        let end = Instant::now();
+        self.span_between(lane, name, start, end, args);
+    }
+
+    /// Record something which took from `start` until `end`.
+    pub fn span_between(
+        &mut self,
+        lane: Lane,
+        name: impl Into<String>,
+        start: Instant,
+        end: Instant,
+        args: &[Arg],
+    ) {
+        let dur = end.saturating_duration_since(start);
+        self.push(lane, name.into(), "X", start, Some(dur), args);
+    }
+
+    /// Record something which happened just now.
+    pub fn instant(&mut self, lane: Lane, name: impl Into<String>, args: &[Arg]) {
+        self.push(lane, name.into(), "i", Instant::now(), None, args);
+    }
+
+    fn push(
+        &mut self,
+        lane: Lane,
+        name: String,
+        ph: &'static str,
+        at: Instant,
+        dur: Option<Duration>,
+        args: &[Arg],
+    ) {
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(TraceEvent {
+            name,
+            ph,
+            ts: at.saturating_duration_since(self.start).as_micros() as u64,
+            dur: dur.map(|dur| dur.as_micros() as u64),
+            s: (ph == "i").then_some("t"),
+            pid: self.workload_id,
+            tid: lane as u64,
+            args: args
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+        });
+    }
+
+    /// Write the timeline as a chrome trace JSON file.
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let mut metadata = Vec::new();
+        for lane in [Lane::Workload, Lane::Agent, Lane::Faults] {
+            let mut args = serde_json::Map::new();
+            args.insert("name".to_string(), lane.name().into());
+            metadata.push(TraceEvent {
+                name: "thread_name".to_string(),
+                ph: "M",
+                ts: 0,
+                dur: None,
+                s: None,
+                pid: self.workload_id,
+                tid: lane as u64,
+                args,
+            });
+        }
+        let mut args = serde_json::Map::new();
+        args.insert(
+            "name".to_string(),
+            format!(
+                "workload {} ({} earlier events dropped)",
+                self.workload_id, self.dropped
+            )
+            .into(),
+        );
+        metadata.push(TraceEvent {
+            name: "process_name".to_string(),
+            ph: "M",
+            ts: 0,
+            dur: None,
+            s: None,
+            pid: self.workload_id,
+            tid: 0,
+            args,
+        });
+
+        let trace = Trace {
+            trace_events: metadata.iter().chain(self.events.iter()).collect(),
+            display_time_unit: "ms",
+        };
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer(file, &trace)?;
+        Ok(())
+    }
+}
+
+/// An argument attached to an event.
+pub type Arg = (&'static str, serde_json::Value);
@@ -5,7 +5,7 @@ use std::{
     collections::HashSet,
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tempfile::TempDir;
 use tokio::time::{error::Elapsed, timeout};
@@ -13,13 +13,19 @@ use tokio_util::sync::CancellationToken;
 use tracing::{info, trace, trace_span, Instrument as _};
 
 use crate::{
-    message::{InitOutcome, Key, KeyValueChange, OpenOutcome, ToSupervisor, MAX_ENVELOPE_SIZE},
+    message::{
+        InitOutcome, Key, KeyValueChange, OpenOutcome, OpenStats, PinnedReadsPayload, ToSupervisor,
+        MAX_ENVELOPE_SIZE, PINNED_READ_VIOLATION_EXIT_CODE,
+    },
     supervisor::{
         comms,
         config::{WorkloadConfiguration, MAX_VALUE_LEN},
         controller::{self, SpawnedAgentController},
         pbt,
         resource::{self, AssignedResources, ResourceAllocator, ResourceExhaustion},
+        template::{TemplateGenerator, WorkloadTemplate},
+        timeline::{Lane, Timeline},
+        CommitSource,
     },
 };
 
@@ -29,6 +35,14 @@ const TOLERANCE: Duration = Duration::from_secs(5);
 /// Max time after which the agent should crash a task.
 const MAX_CRASH_DELAY: Duration = TOLERANCE.checked_sub(Duration::from_secs(1)).unwrap();
 
+/// Recovery is allowed to take at least this long, however fast commits are.
+///
+/// Opening the database has a fixed cost which does not shrink along with tiny commits.
+const MIN_RECOVERY_BUDGET: Duration = Duration::from_secs(1);
+
+/// Max number of keys read by each pinned reader during a commit crash.
+const MAX_PINNED_READS: usize = 1000;
+
 /// Represents a snapshot of the state of the database.
 #[derive(Clone)]
 struct Snapshot {
@@ -46,6 +60,31 @@ impl Snapshot {
     }
 }
 
+/// Measurements of the recoveries after the crashes of a workload.
+#[derive(Default)]
+struct RecoveryStats {
+    /// Number of recoveries.
+    n: u32,
+    /// Total time spent opening the database after a crash.
+    tot_open_time: Duration,
+    /// The longest time spent opening the database after a crash.
+    max_open_time: Duration,
+    /// Total length of the WAL replayed.
+    tot_wal_len: u64,
+    /// The longest WAL replayed.
+    max_wal_len: u64,
+}
+
+impl RecoveryStats {
+    fn record(&mut self, stats: &OpenStats) {
+        self.n += 1;
+        self.tot_open_time += stats.elapsed;
+        self.max_open_time = self.max_open_time.max(stats.elapsed);
+        self.tot_wal_len += stats.wal_len;
+        self.max_wal_len = self.max_wal_len.max(stats.wal_len);
+    }
+}
+
 enum Resources {
     /// Resource allocator that is used to assign and check resource usage.
     Allocator(Arc<Mutex<ResourceAllocator>>),
@@ -144,6 +183,18 @@ pub struct Workload {
     /// Resources is used to make sure that the workload that is being executed
     /// does not exceed the assigned disk space and memory.
     resources: Resources,
+    /// The events of the workload, written out if it fails.
+    timeline: Timeline,
+    /// How long recovering from crashes took.
+    recovery: RecoveryStats,
+    /// The source of the commits, if they are not generated at random.
+    ///
+    /// Behind a mutex only to keep the workload `Sync`.
+    commit_source: Option<Mutex<Box<dyn CommitSource>>>,
+    /// The next commit taken from the commit source, which is yet to be performed.
+    next_commit: Option<(Vec<Key>, Vec<KeyValueChange>)>,
+    /// The generator of the commits, if the workload follows a template.
+    template: Option<TemplateGenerator>,
 }
 
 /// Contains the information required to apply a rollback.
@@ -162,10 +213,14 @@ impl Workload {
         workload_dir: TempDir,
         workload_id: u64,
         resource_alloc: Arc<Mutex<ResourceAllocator>>,
+        max_recovery_factor: u32,
+        template: Option<WorkloadTemplate>,
     ) -> Result<Self, ResourceExhaustion> {
         let mut rng = rand_pcg::Pcg64::seed_from_u64(seed);
 
-        let config = WorkloadConfiguration::new(&mut rng, workload_id, resource_alloc.clone())?;
+        let mut config =
+            WorkloadConfiguration::new(&mut rng, workload_id, resource_alloc.clone(), template)?;
+        config.max_recovery_factor = max_recovery_factor;
 
         Ok(Self::new_inner(
             rng,
@@ -184,14 +239,21 @@ impl Workload {
         ensure_snapshot: bool,
         assigned_disk: u64,
         assigned_memory: u64,
+        max_recovery_factor: u32,
+        template: Option<WorkloadTemplate>,
     ) -> Self {
         let mut rng = rand_pcg::Pcg64::seed_from_u64(seed);
 
-        let mut config =
-            WorkloadConfiguration::new_with_resources(&mut rng, assigned_disk, assigned_memory);
+        let mut config = WorkloadConfiguration::new_with_resources(
+            &mut rng,
+            assigned_disk,
+            assigned_memory,
+            template,
+        );
         if ensure_snapshot {
             config.enable_ensure_snapshot();
         }
+        config.max_recovery_factor = max_recovery_factor;
 
         Self::new_inner(
             rng,
@@ -208,7 +270,7 @@ impl Workload {
 
     #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
     fn new_inner(
-        rng: rand_pcg::Pcg64,
+        mut rng: rand_pcg::Pcg64,
         seed: u64,
         workload_dir: TempDir,
         workload_id: u64,
@@ -227,6 +289,10 @@ impl Workload {
         #[cfg(not(target_os = "linux"))]
         let trick_handle = None;
 
+        let template = config
+            .template
+            .map(|template| TemplateGenerator::new(template, &mut rng));
+
         Self {
             workload_dir,
             trick_handle,
@@ -242,9 +308,22 @@ impl Workload {
             rng,
             committed: Snapshot::empty(),
             config,
+            timeline: Timeline::new(workload_id),
+            recovery: RecoveryStats::default(),
+            commit_source: None,
+            next_commit: None,
+            template,
         }
     }
 
+    /// Take the commits from the given source instead of generating them, running until the
+    /// source is exhausted or the assigned resources are.
+    pub fn with_commit_source(mut self, commit_source: Box<dyn CommitSource>) -> Self {
+        self.commit_source = Some(Mutex::new(commit_source));
+        self.config.iterations = usize::MAX;
+        self
+    }
+
     /// Run the workload.
     ///
     /// Pass the cancellation token to the workload. The workload will run until the token is
@@ -255,6 +334,18 @@ impl Workload {
             None => Ok(()),
         };
 
+        if self.recovery.n > 0 {
+            let stats = &self.recovery;
+            info!(
+                "recovered from {} crashes: open avg={:?} max={:?}, replayed WAL avg={} max={} bytes",
+                stats.n,
+                stats.tot_open_time / stats.n,
+                stats.max_open_time,
+                stats.tot_wal_len / stats.n as u64,
+                stats.max_wal_len,
+            );
+        }
+
         // If the workload timed out, we assume it's deadlocked. In that case it would be useful
         // to collect the stack trace of the agent.
         if matches!(result, Err(ref e) if is_err_timeout_like(e)) {
@@ -262,6 +353,19 @@ impl Workload {
             self.collect_and_display_backtrace().await;
         }
 
+        if let Err(ref e) = result {
+            self.timeline.instant(
+                Lane::Workload,
+                "failure",
+                &[("reason", e.to_string().into())],
+            );
+            let filename = self.workload_dir.path().join("timeline.json");
+            match self.timeline.write(&filename) {
+                Ok(()) => info!("Timeline written to {}", filename.display()),
+                Err(err) => tracing::warn!("Failed to write timeline: {}", err),
+            }
+        }
+
         // Irregardless of the result or if the workload was cancelled, we need to release the
         // resources.
         self.teardown().await;
@@ -272,6 +376,17 @@ impl Workload {
     async fn run_inner(&mut self) -> Result<()> {
         self.spawn_new_agent().await?;
         for iterno in 0..self.config.iterations {
+            if let Some(ref mut commit_source) = self.commit_source {
+                if self.next_commit.is_none() {
+                    // UNWRAP: the mutex is never poisoned, as it is never locked.
+                    self.next_commit = commit_source.get_mut().unwrap().next_commit();
+                }
+                if self.next_commit.is_none() {
+                    tracing::info!("Commit source exhausted");
+                    break;
+                }
+            }
+
             self.run_iteration()
                 .instrument(trace_span!("iteration", iterno))
                 .await?;
@@ -308,6 +423,7 @@ impl Workload {
                 let should_turn_off = self.rng.random_bool(self.config.enospc_off);
                 if should_turn_off {
                     info!("unsetting ENOSPC");
+                    self.timeline.instant(Lane::Faults, "ENOSPC off", &[]);
                     self.enabled_enospc = false;
                     self.trick_handle
                         .as_ref()
@@ -318,6 +434,7 @@ impl Workload {
                 let should_turn_on = self.rng.random_bool(self.config.enospc_on);
                 if should_turn_on {
                     info!("setting ENOSPC");
+                    self.timeline.instant(Lane::Faults, "ENOSPC on", &[]);
                     self.enabled_enospc = true;
                     self.trick_handle.as_ref().unwrap().set_trigger_enospc(true);
                 }
@@ -327,6 +444,7 @@ impl Workload {
                 let should_turn_off = self.rng.random_bool(self.config.latency_off);
                 if should_turn_off {
                     info!("unsetting latency injector");
+                    self.timeline.instant(Lane::Faults, "latency off", &[]);
                     self.enabled_latency = false;
                     self.trick_handle
                         .as_ref()
@@ -337,6 +455,7 @@ impl Workload {
                 let should_turn_on = self.rng.random_bool(self.config.latency_on);
                 if should_turn_on {
                     info!("setting latency injector");
+                    self.timeline.instant(Lane::Faults, "latency on", &[]);
                     self.enabled_latency = true;
                     self.trick_handle
                         .as_ref()
@@ -371,6 +490,24 @@ impl Workload {
 
         // Generate a changeset and the associated snapshot
         let (snapshot, reads, changeset) = self.gen_commit();
+        let pinned_reads = if should_crash.is_some() && self.config.pinned_readers > 0 {
+            self.gen_pinned_reads(&reads, &changeset)
+        } else {
+            None
+        };
+        if let Some(crash_delay) = should_crash {
+            self.timeline.instant(
+                Lane::Faults,
+                "commit crash injected",
+                &[("delay_ms", (crash_delay.as_millis() as u64).into())],
+            );
+        }
+        let commit_args = [
+            ("sync_seqn", snapshot.sync_seqn.into()),
+            ("reads", reads.len().into()),
+            ("changes", changeset.len().into()),
+        ];
+        let commit_start = Instant::now();
         let commit_response = self
             .rr()
             .send_request(crate::message::ToAgent::Commit(
@@ -379,6 +516,7 @@ impl Workload {
                     read_concurrency: self.config.read_concurrency,
                     changeset: changeset.clone(),
                     should_crash,
+                    pinned_reads,
                 },
             ))
             .await?;
@@ -388,12 +526,20 @@ impl Workload {
                 return Err(anyhow::anyhow!("Commit crash did not execute successfully"));
             };
 
-            self.wait_for_crash().await?;
+            let crash_result = self.wait_for_crash().await;
+            self.timeline.span(
+                Lane::Workload,
+                "commit (crashed)",
+                commit_start,
+                &commit_args,
+            );
+            crash_result?;
 
             // During a commit crash, every type of error could happen.
             // However the agent will be respawned, so it will just
             // make sure the changeset was correctly applied or reverted.
-            self.spawn_new_agent().await?;
+            let open_stats = self.spawn_new_agent().await?;
+            self.check_recovery(&open_stats)?;
 
             // Sample the agent to make sure the changeset was correctly applied or reverted.
             let agent_sync_seqn = self.rr().send_query_sync_seqn().await?;
@@ -405,9 +551,40 @@ impl Workload {
                 return Err(anyhow::anyhow!("Unexpected sync_seqn after commit crash",));
             }
         } else {
-            let ToSupervisor::CommitResponse { elapsed, outcome } = commit_response else {
+            let ToSupervisor::CommitResponse {
+                elapsed,
+                phases,
+                outcome,
+            } = commit_response
+            else {
                 return Err(anyhow::anyhow!("Commit did not execute successfully"));
             };
+            self.timeline.span(
+                Lane::Workload,
+                "commit",
+                commit_start,
+                &[
+                    &commit_args[..],
+                    &[("outcome", format!("{:?}", outcome).into())],
+                ]
+                .concat(),
+            );
+            // The phases ended when the response was sent, which is about now.
+            let mut phase_start = Instant::now() - elapsed;
+            for (name, duration) in [
+                ("read", phases.read),
+                ("finish", phases.finish),
+                ("sync", phases.sync),
+            ] {
+                self.timeline.span_between(
+                    Lane::Agent,
+                    name,
+                    phase_start,
+                    phase_start + duration,
+                    &[("sync_seqn", snapshot.sync_seqn.into())],
+                );
+                phase_start += duration;
+            }
 
             // Keep track of ENOSPC because the flag could be erased during the agent's respawn
             let was_enospc_enabled = self.enabled_enospc;
@@ -429,12 +606,23 @@ impl Workload {
             }
         };
 
+        let verify_start = Instant::now();
         if is_applied {
             self.ensure_changeset_applied(&changeset).await?;
             self.commit(snapshot);
         } else {
             self.ensure_changeset_reverted(&changeset).await?;
         }
+        self.timeline.span(
+            Lane::Workload,
+            if is_applied {
+                "verify applied"
+            } else {
+                "verify reverted"
+            },
+            verify_start,
+            &[],
+        );
 
         Ok(())
     }
@@ -466,7 +654,8 @@ impl Workload {
                     return Err(anyhow::anyhow!("Operation should have succeeded"));
                 }
 
-                // At this point, we expect the agent will have its NOMT instance poisoned.
+                // At this point, we expect the agent will have its NOMT instance poisoned, or
+                // waiting for its sync to be resumed.
                 //
                 // But we still should be able to make the sync_seqn and the kv queries.
                 let agent_sync_seqn = self.rr().send_query_sync_seqn().await?;
@@ -547,6 +736,15 @@ impl Workload {
             n_commits_to_rollback
         );
 
+        if let Some(crash_delay) = should_crash {
+            self.timeline.instant(
+                Lane::Faults,
+                "rollback crash injected",
+                &[("delay_ms", (crash_delay.as_millis() as u64).into())],
+            );
+        }
+        let rollback_args = [("n_commits", n_commits_to_rollback.into())];
+        let rollback_start = Instant::now();
         let rollback_outcome = self
             .rr()
             .send_request(crate::message::ToAgent::Rollback(
@@ -564,12 +762,20 @@ impl Workload {
                 ));
             };
 
-            self.wait_for_crash().await?;
+            let crash_result = self.wait_for_crash().await;
+            self.timeline.span(
+                Lane::Workload,
+                "rollback (crashed)",
+                rollback_start,
+                &rollback_args,
+            );
+            crash_result?;
 
             // During a rollback crash, every type of error could happen.
             // However the agent will be respawned, so it will just
             // make sure the rollback was correctly applied or not.
-            self.spawn_new_agent().await?;
+            let open_stats = self.spawn_new_agent().await?;
+            self.check_recovery(&open_stats)?;
 
             let agent_sync_seqn = self.rr().send_query_sync_seqn().await?;
             let last_sync_seqn = self.committed.sync_seqn;
@@ -591,6 +797,16 @@ impl Workload {
                     "RollbackCommit did not execute successfully"
                 ));
             };
+            self.timeline.span(
+                Lane::Workload,
+                "rollback",
+                rollback_start,
+                &[
+                    &rollback_args[..],
+                    &[("outcome", format!("{:?}", outcome).into())],
+                ]
+                .concat(),
+            );
 
             let was_enospc_enabled = self.enabled_enospc;
             self.ensure_outcome_validity(&outcome).await?;
@@ -629,10 +845,54 @@ impl Workload {
         let agent_died_or_timeout = timeout(TOLERANCE, agent.died()).await;
         self.agent.take().unwrap().teardown().await;
         self.rr = None;
-        if let Err(Elapsed { .. }) = agent_died_or_timeout {
-            return Err(anyhow::anyhow!("agent did not die"));
+        let status = match agent_died_or_timeout {
+            Err(Elapsed { .. }) => "did not die".to_string(),
+            Ok(Some(status)) => status.to_string(),
+            Ok(None) => "unknown".to_string(),
+        };
+        self.timeline
+            .instant(Lane::Faults, "agent died", &[("status", status.into())]);
+        match agent_died_or_timeout {
+            Err(Elapsed { .. }) => Err(anyhow::anyhow!("agent did not die")),
+            Ok(Some(status)) if status.code() == Some(PINNED_READ_VIOLATION_EXIT_CODE) => Err(
+                anyhow::anyhow!("pinned reader observed a value inconsistent with its root"),
+            ),
+            Ok(_) => Ok(()),
         }
+    }
+
+    /// Record the recovery after a crash and make sure it did not take too long compared to the
+    /// commits of the workload.
+    fn check_recovery(&mut self, stats: &OpenStats) -> Result<()> {
+        self.recovery.record(stats);
+        self.timeline.instant(
+            Lane::Faults,
+            "recovered",
+            &[
+                ("open_ms", (stats.elapsed.as_millis() as u64).into()),
+                ("wal_len", stats.wal_len.into()),
+            ],
+        );
 
+        let factor = self.config.max_recovery_factor;
+        if factor == 0 {
+            return Ok(());
+        }
+        let avg_commit_time = u32::try_from(self.n_successfull_commit)
+            .ok()
+            .and_then(|n| self.tot_commit_time.checked_div(n))
+            .unwrap_or(Duration::ZERO);
+        let budget = std::cmp::max(avg_commit_time * factor, MIN_RECOVERY_BUDGET);
+        if stats.elapsed > budget {
+            return Err(anyhow::anyhow!(
+                "Recovery took {:?} replaying {} bytes of WAL, more than {} times the average \
+                 commit time of {:?}",
+                stats.elapsed,
+                stats.wal_len,
+                factor,
+                avg_commit_time,
+            ));
+        }
         Ok(())
     }
 
@@ -719,11 +979,15 @@ impl Workload {
             ));
         }
 
-        if self.config.should_ensure_snapshot() {
-            return self.check_entire_snapshot().await;
-        }
-
-        self.check_sampled_snapshot().await
+        let check_start = Instant::now();
+        let result = if self.config.should_ensure_snapshot() {
+            self.check_entire_snapshot().await
+        } else {
+            self.check_sampled_snapshot().await
+        };
+        self.timeline
+            .span(Lane::Workload, "check snapshot", check_start, &[]);
+        result
     }
 
     async fn check_entire_snapshot(&self) -> anyhow::Result<()> {
@@ -768,8 +1032,12 @@ impl Workload {
         Ok(())
     }
 
-    async fn spawn_new_agent(&mut self) -> anyhow::Result<()> {
+    /// Spawn a new agent and make it open the database.
+    ///
+    /// Returns how long the successful open took.
+    async fn spawn_new_agent(&mut self) -> anyhow::Result<OpenStats> {
         assert!(self.agent.is_none());
+        let spawn_start = Instant::now();
         let workload_dir_path = self.workload_dir_path();
         controller::spawn_agent_into(&mut self.agent, workload_dir_path).await?;
         self.rr = Some(self.agent.as_ref().unwrap().rr().clone());
@@ -790,19 +1058,28 @@ impl Workload {
         }
 
         // Finally, make the agent open the database.
-        self.ensure_agent_open_db().await?;
-
-        Ok(())
+        let open_stats = self.ensure_agent_open_db().await?;
+
+        let pid = self.agent.as_ref().unwrap().pid();
+        self.timeline.span(
+            Lane::Faults,
+            "agent spawned",
+            spawn_start,
+            &[("pid", pid.into())],
+        );
+        Ok(open_stats)
     }
 
     /// Ensure that the agent has opened the database.
     ///
     /// If the agent has run out of storage, we will turn off the `ENOSPC` error and try again.
-    async fn ensure_agent_open_db(&mut self) -> anyhow::Result<()> {
-        let outcome = self.agent.as_mut().unwrap().open(&self.config).await?;
+    ///
+    /// Returns the stats of the successful open.
+    async fn ensure_agent_open_db(&mut self) -> anyhow::Result<OpenStats> {
+        let (outcome, stats) = self.agent.as_mut().unwrap().open(&self.config).await?;
 
         match outcome {
-            OpenOutcome::Success => (),
+            OpenOutcome::Success => Ok(stats),
             OpenOutcome::StorageFull => {
                 // We got storage full and we know here that enospc is enabled.
                 //
@@ -815,15 +1092,14 @@ impl Workload {
                     .unwrap()
                     .set_trigger_enospc(false);
 
-                let outcome = self.agent.as_mut().unwrap().open(&self.config).await?;
+                let (outcome, stats) = self.agent.as_mut().unwrap().open(&self.config).await?;
                 assert!(matches!(outcome, OpenOutcome::Success));
+                Ok(stats)
             }
             OpenOutcome::UnknownFailure(err) => {
-                return Err(anyhow::anyhow!("unexpected open outcome: {:?}", err));
+                Err(anyhow::anyhow!("unexpected open outcome: {:?}", err))
             }
         }
-
-        Ok(())
     }
 
     fn rr(&self) -> &comms::RequestResponse {
@@ -871,6 +1147,11 @@ impl Workload {
     }
 
     /// Return the amount of assigned resources for the workload.
+    /// The template the commits of the workload follow, if any.
+    pub fn template(&self) -> Option<WorkloadTemplate> {
+        self.config.template
+    }
+
     pub fn assigned_resources(&self) -> AssignedResources {
         self.resources.assigned_resources(self.workload_id)
     }
@@ -879,6 +1160,19 @@ impl Workload {
         let mut snapshot = self.committed.clone();
         snapshot.sync_seqn += 1;
 
+        let next_commit = self.next_commit.take().or_else(|| {
+            self.template
+                .as_mut()
+                .map(|template| template.gen_commit(&mut self.rng, self.config.avg_commit_size))
+        });
+        if let Some((reads, mut changes)) = next_commit {
+            for change in &changes {
+                snapshot.state.insert(*change.key(), change.value());
+            }
+            changes.sort_by(|a, b| a.key().cmp(&b.key()));
+            return (snapshot, reads, changes);
+        }
+
         let size = self.rng.random_range(0..self.config.avg_commit_size * 2);
 
         let reads_size = (size as f64 * self.config.reads) as usize;
@@ -922,6 +1216,39 @@ impl Workload {
         (snapshot, reads, changes)
     }
 
+    /// Select the keys read by the pinned readers during a commit crash, along with their
+    /// committed values.
+    ///
+    /// The keys are taken from both the reads and the changeset, so that readers observe keys
+    /// which are about to change as well as ones which are not.
+    fn gen_pinned_reads(
+        &mut self,
+        reads: &[Key],
+        changeset: &[KeyValueChange],
+    ) -> Option<PinnedReadsPayload> {
+        let mut keys = reads
+            .iter()
+            .chain(changeset.iter().map(|change| change.key()))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        if keys.is_empty() {
+            return None;
+        }
+        keys.sort();
+        keys.shuffle(&mut self.rng);
+        keys.truncate(MAX_PINNED_READS);
+
+        let expected = keys
+            .into_iter()
+            .map(|key| (*key, self.committed.state.get(key).cloned().flatten()))
+            .collect();
+        Some(PinnedReadsPayload {
+            readers: self.config.pinned_readers,
+            expected,
+        })
+    }
+
     fn gen_reads(&mut self, size: usize) -> Vec<Key> {
         let mut reads = vec![];
         let mut key = [0; 32];